@@ -0,0 +1,221 @@
+//! Battery supervision for robots whose manifest names a `battery` getter:
+//! the last reading and its level for `/status`, level changes for client
+//! notifications, and with `--battery-lockout` the refusal of calls that
+//! would drain a critically low battery further.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::errors::AdapterError;
+use crate::manifest::{Battery, Function, Manifest};
+
+/// How often the battery is read when the manifest doesn't say.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How far above a threshold, as a fraction of it, a reading must be to
+/// leave its level, so a voltage sagging under load doesn't flap.
+const HYSTERESIS: f64 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Ok,
+    Low,
+    Critical,
+}
+
+impl Level {
+    /// The level of `voltage` against the thresholds in `battery`, coming
+    /// from `previous`.
+    pub fn of(battery: &Battery, voltage: f64, previous: Level) -> Self {
+        let below = |threshold: Option<f64>, level: Level| {
+            threshold.is_some_and(|threshold| match previous >= level {
+                true => voltage < threshold + threshold.abs() * HYSTERESIS,
+                false => voltage < threshold,
+            })
+        };
+        if below(battery.critical, Self::Critical) {
+            Self::Critical
+        } else if below(battery.low, Self::Low) {
+            Self::Low
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatteryStatus {
+    /// In the unit the getter returns, as are the thresholds
+    pub voltage: f64,
+    pub level: Level,
+    /// Milliseconds since the Unix epoch
+    pub at: u64,
+}
+
+pub struct BatteryMonitor {
+    /// Refuse calls that change the robot while the level is critical
+    lockout: bool,
+    last: Mutex<Option<BatteryStatus>>,
+    changes: broadcast::Sender<BatteryStatus>,
+}
+
+impl BatteryMonitor {
+    pub fn new(lockout: bool) -> Self {
+        Self {
+            lockout,
+            last: Mutex::new(None),
+            changes: broadcast::channel(16).0,
+        }
+    }
+
+    /// Keep a reading, announcing it if its level differs from the last
+    /// one's. The first reading is announced only if it isn't `Ok`.
+    pub fn record(&self, battery: &Battery, voltage: f64) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut last = self.last.lock().unwrap();
+        let previous = last.as_ref().map_or(Level::Ok, |last| last.level);
+        let status = BatteryStatus {
+            voltage,
+            level: Level::of(battery, voltage, previous),
+            at,
+        };
+        *last = Some(status.clone());
+        drop(last);
+        if status.level == previous {
+            return;
+        }
+        match status.level {
+            Level::Ok => info!("Battery back to {}", voltage),
+            Level::Low => warn!("Battery low: {}", voltage),
+            Level::Critical => warn!("Battery critical: {}", voltage),
+        }
+        let _ = self.changes.send(status);
+    }
+
+    /// Forget the last reading, as when the robot disconnects.
+    pub fn clear(&self) {
+        *self.last.lock().unwrap() = None;
+    }
+
+    pub fn status(&self) -> Option<BatteryStatus> {
+        self.last.lock().unwrap().clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BatteryStatus> {
+        self.changes.subscribe()
+    }
+
+    /// Whether [`Self::check`] may refuse anything right now; cheaper than
+    /// finding the manifest it needs.
+    pub fn locked_out(&self) -> bool {
+        self.lockout && self.status().is_some_and(|s| s.level == Level::Critical)
+    }
+
+    /// With the lockout on and the battery critical, refuse `func` if it
+    /// changes the robot. Functions that stop or hold the robot safe, and
+    /// the battery getter itself, are always allowed.
+    pub fn check(&self, func: &Function, manifest: &Manifest) -> Result<(), AdapterError> {
+        let getter = manifest
+            .battery
+            .as_ref()
+            .is_some_and(|b| b.function == func.name);
+        if !self.lockout || getter || func.is_read_only() || manifest.is_safety_function(&func.name)
+        {
+            return Ok(());
+        }
+        match self.status() {
+            Some(status) if self.locked_out() => Err(AdapterError::BatteryCritical(format!(
+                "Battery critical at {}, below {}; '{}' was refused until it is charged",
+                status.voltage,
+                manifest
+                    .battery
+                    .as_ref()
+                    .and_then(|b| b.critical)
+                    .unwrap_or_default(),
+                func.name
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, function};
+    use serde_json::json;
+
+    fn manifest() -> Manifest {
+        fixtures::manifest_with(
+            json!([
+                function(1, "readBattery", Some("i16"), &[]),
+                function(2, "drive", None, &[("mm", "i16")]),
+                function(3, "stop", None, &[])
+            ]),
+            json!({
+                "safe_state": "stop",
+                "battery": {"function": "readBattery", "low": 6800, "critical": 6200}
+            }),
+        )
+    }
+
+    #[test]
+    fn test_announces_level_changes() {
+        let manifest = manifest();
+        let battery = manifest.battery.as_ref().unwrap();
+        let monitor = BatteryMonitor::new(false);
+        let mut changes = monitor.subscribe();
+
+        monitor.record(battery, 7400.0);
+        monitor.record(battery, 6500.0);
+        monitor.record(battery, 6400.0);
+        monitor.record(battery, 6000.0);
+        // Just above critical isn't enough to leave it
+        monitor.record(battery, 6250.0);
+        monitor.record(battery, 7000.0);
+        monitor.record(battery, 6790.0);
+        let levels: Vec<Level> = std::iter::from_fn(|| changes.try_recv().ok())
+            .map(|status| status.level)
+            .collect();
+        assert_eq!(levels, [Level::Low, Level::Critical, Level::Ok, Level::Low]);
+        assert_eq!(monitor.status().unwrap().voltage, 6790.0);
+
+        monitor.clear();
+        assert!(monitor.status().is_none());
+    }
+
+    #[test]
+    fn test_lockout_refuses_motion_while_critical() {
+        let manifest = manifest();
+        let battery = manifest.battery.as_ref().unwrap();
+        let function = |name: &str| manifest.functions.iter().find(|f| f.name == name).unwrap();
+
+        let monitor = BatteryMonitor::new(true);
+        monitor.record(battery, 6500.0);
+        assert!(monitor.check(function("drive"), &manifest).is_ok());
+
+        monitor.record(battery, 6100.0);
+        let err = monitor.check(function("drive"), &manifest).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Battery critical at 6100, below 6200; 'drive' was refused until it is charged"
+        );
+        assert!(monitor.check(function("stop"), &manifest).is_ok());
+        assert!(monitor.check(function("readBattery"), &manifest).is_ok());
+        // The getter is still read even if marked as changing the robot
+        let mut getter = function("readBattery").clone();
+        getter.mutates = Some(true);
+        assert!(monitor.check(&getter, &manifest).is_ok());
+
+        let unlocked = BatteryMonitor::new(false);
+        unlocked.record(battery, 6100.0);
+        assert!(unlocked.check(function("drive"), &manifest).is_ok());
+    }
+}
@@ -0,0 +1,337 @@
+//! Robots reached over Bluetooth LE, such as ESP32 or nRF boards without a
+//! USB tether, given as a `ble://<device-name>` line. The usual SLIP frames
+//! travel over the Nordic UART Service (NUS). Needs the `ble` build
+//! feature.
+
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+use crate::transport::{Connector, Transport};
+
+const UNSUPPORTED: &str =
+    "This adapter was built without Bluetooth support; rebuild it with `--features ble`";
+
+/// Finds the device by its advertised name and connects to its NUS.
+pub struct BleConnector {
+    /// The line as configured, with its scheme
+    name: String,
+    device: String,
+}
+
+impl BleConnector {
+    pub fn new(device: &str) -> Result<Self> {
+        if cfg!(not(feature = "ble")) {
+            return Err(anyhow!(UNSUPPORTED));
+        }
+        Ok(Self {
+            name: format!("ble://{}", device),
+            device: device.to_string(),
+        })
+    }
+}
+
+impl Connector for BleConnector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Only known by scanning; a device that isn't advertising is retried
+    /// like a serial port that won't open.
+    fn is_present(&self) -> bool {
+        true
+    }
+
+    fn open(&self) -> Result<Box<dyn Transport>> {
+        link::open(&self.device)
+    }
+
+    /// Connecting over the air doesn't reset the board.
+    fn boot_timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(feature = "ble")]
+mod link {
+    use anyhow::{anyhow, Context, Result};
+    use btleplug::api::{
+        Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _, ScanFilter,
+        ValueNotification, WriteType,
+    };
+    use btleplug::platform::{Adapter, Manager, Peripheral};
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc, Condvar, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+    use tokio_stream::{Stream, StreamExt};
+    use tracing::{debug, info, warn};
+    use uuid::Uuid;
+
+    use crate::transport::Transport;
+
+    const NUS_SERVICE: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+    /// Written by the adapter
+    const NUS_RX: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+    /// Notified by the device
+    const NUS_TX: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+    /// Longest write that fits the default ATT MTU of 23 bytes.
+    const MAX_WRITE: usize = 20;
+    /// How long to look for the device before the attempt fails.
+    const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+    const SCAN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+    /// Same as the serial port's read timeout.
+    const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+    type Notifications = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+    /// Bytes notified by the device, shared with the link thread.
+    struct Shared {
+        incoming: Mutex<VecDeque<u8>>,
+        data_ready: Condvar,
+        /// Set once the link drops
+        closed: AtomicBool,
+    }
+
+    /// Connect to `device` on a thread of its own, which runs the async
+    /// Bluetooth stack until every handle to the transport is dropped.
+    pub fn open(device: &str) -> Result<Box<dyn Transport>> {
+        let shared = Arc::new(Shared {
+            incoming: Mutex::new(VecDeque::new()),
+            data_ready: Condvar::new(),
+            closed: AtomicBool::new(false),
+        });
+        let (outgoing, outgoing_rx) = unbounded_channel();
+        let (ready, ready_rx) = mpsc::channel();
+
+        let device = device.to_string();
+        let link_shared = Arc::clone(&shared);
+        std::thread::Builder::new()
+            .name("ble-link".to_string())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build();
+                match runtime {
+                    Ok(runtime) => {
+                        runtime.block_on(run(&device, link_shared, outgoing_rx, ready));
+                    }
+                    Err(e) => {
+                        let _ = ready.send(Err(e.into()));
+                    }
+                }
+            })?;
+        ready_rx
+            .recv()
+            .map_err(|_| anyhow!("Bluetooth link thread stopped"))??;
+        Ok(Box::new(BleTransport { shared, outgoing }))
+    }
+
+    async fn run(
+        device: &str,
+        shared: Arc<Shared>,
+        mut outgoing: UnboundedReceiver<Vec<u8>>,
+        ready: mpsc::Sender<Result<()>>,
+    ) {
+        let (peripheral, rx, mut notifications) = match connect(device).await {
+            Ok(link) => {
+                let _ = ready.send(Ok(()));
+                link
+            }
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return;
+            }
+        };
+        let write_type = if rx
+            .properties
+            .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        {
+            WriteType::WithoutResponse
+        } else {
+            WriteType::WithResponse
+        };
+        info!("Connected to {} over Bluetooth", device);
+
+        loop {
+            tokio::select! {
+                notification = notifications.next() => match notification {
+                    Some(notification) if notification.uuid == NUS_TX => {
+                        shared.incoming.lock().unwrap().extend(notification.value);
+                        shared.data_ready.notify_all();
+                    }
+                    Some(_) => {}
+                    None => {
+                        warn!("Bluetooth link to {} dropped", device);
+                        break;
+                    }
+                },
+                data = outgoing.recv() => {
+                    // Every transport handle was dropped
+                    let Some(data) = data else { break };
+                    let mut failed = false;
+                    for chunk in data.chunks(MAX_WRITE) {
+                        if let Err(e) = peripheral.write(&rx, chunk, write_type).await {
+                            warn!("Bluetooth write to {} failed: {}", device, e);
+                            failed = true;
+                            break;
+                        }
+                    }
+                    if failed {
+                        break;
+                    }
+                }
+            }
+        }
+
+        shared.closed.store(true, Ordering::Relaxed);
+        shared.data_ready.notify_all();
+        if let Err(e) = peripheral.disconnect().await {
+            debug!("Bluetooth disconnect from {} failed: {}", device, e);
+        }
+    }
+
+    /// Find, connect and subscribe; returns the characteristic to write.
+    async fn connect(device: &str) -> Result<(Peripheral, Characteristic, Notifications)> {
+        let manager = Manager::new().await.context("Bluetooth is not available")?;
+        let adapter = manager
+            .adapters()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No Bluetooth adapter found"))?;
+        // Not every firmware advertises the NUS UUID, so match on the name
+        adapter.start_scan(ScanFilter::default()).await?;
+        let found = find(&adapter, device).await;
+        let _ = adapter.stop_scan().await;
+        let peripheral = found?;
+
+        peripheral
+            .connect()
+            .await
+            .with_context(|| format!("Bluetooth connection to {} failed", device))?;
+        peripheral.discover_services().await?;
+        let characteristics = peripheral.characteristics();
+        let characteristic = |uuid: Uuid| {
+            characteristics
+                .iter()
+                .find(|c| c.service_uuid == NUS_SERVICE && c.uuid == uuid)
+                .cloned()
+                .ok_or_else(|| anyhow!("{} has no Nordic UART Service", device))
+        };
+        let rx = characteristic(NUS_RX)?;
+        let tx = characteristic(NUS_TX)?;
+        peripheral.subscribe(&tx).await?;
+        let notifications = peripheral.notifications().await?;
+        Ok((peripheral, rx, notifications))
+    }
+
+    async fn find(adapter: &Adapter, device: &str) -> Result<Peripheral> {
+        let deadline = Instant::now() + SCAN_TIMEOUT;
+        loop {
+            for peripheral in adapter.peripherals().await? {
+                let properties = peripheral.properties().await?;
+                if properties.and_then(|p| p.local_name).as_deref() == Some(device) {
+                    return Ok(peripheral);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "No Bluetooth device named '{}' found within {:?}",
+                    device,
+                    SCAN_TIMEOUT
+                ));
+            }
+            tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+        }
+    }
+
+    struct BleTransport {
+        shared: Arc<Shared>,
+        outgoing: UnboundedSender<Vec<u8>>,
+    }
+
+    impl Read for BleTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let incoming = self.shared.incoming.lock().unwrap();
+            let (mut incoming, _) = self
+                .shared
+                .data_ready
+                .wait_timeout_while(incoming, READ_TIMEOUT, |incoming| {
+                    incoming.is_empty() && !self.shared.closed.load(Ordering::Relaxed)
+                })
+                .unwrap();
+
+            if incoming.is_empty() {
+                return Err(match self.shared.closed.load(Ordering::Relaxed) {
+                    true => io::ErrorKind::ConnectionAborted.into(),
+                    false => io::ErrorKind::TimedOut.into(),
+                });
+            }
+            let count = buf.len().min(incoming.len());
+            for (slot, byte) in buf.iter_mut().zip(incoming.drain(..count)) {
+                *slot = byte;
+            }
+            Ok(count)
+        }
+    }
+
+    impl Write for BleTransport {
+        /// Queued for the link thread, which splits it into NUS writes.
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.shared.closed.load(Ordering::Relaxed)
+                || self.outgoing.send(buf.to_vec()).is_err()
+            {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for BleTransport {
+        fn try_clone_transport(&self) -> Result<Box<dyn Transport>> {
+            Ok(Box::new(BleTransport {
+                shared: Arc::clone(&self.shared),
+                outgoing: self.outgoing.clone(),
+            }))
+        }
+
+        fn pending_bytes(&self) -> usize {
+            self.shared.incoming.lock().unwrap().len()
+        }
+    }
+}
+
+#[cfg(not(feature = "ble"))]
+mod link {
+    use anyhow::{anyhow, Result};
+
+    use super::UNSUPPORTED;
+    use crate::transport::Transport;
+
+    pub fn open(_device: &str) -> Result<Box<dyn Transport>> {
+        Err(anyhow!(UNSUPPORTED))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ble_line_needs_feature() {
+        let connector = BleConnector::new("hackpack-arm");
+        if cfg!(feature = "ble") {
+            assert_eq!(connector.unwrap().name(), "ble://hackpack-arm");
+        } else {
+            assert_eq!(connector.err().unwrap().to_string(), UNSUPPORTED);
+        }
+    }
+}
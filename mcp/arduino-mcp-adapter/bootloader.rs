@@ -0,0 +1,126 @@
+//! Recognising a device that has reset into its bootloader, e.g. after a
+//! brownout. An AVR bootloader speaks STK500 for its first second or so and
+//! answers whatever it receives with sync bytes, never a SLIP frame.
+
+use std::fmt;
+
+const STK_INSYNC: u8 = 0x14;
+const STK_OK: u8 = 0x10;
+const STK_NOSYNC: u8 = 0x15;
+
+/// Bytes outside any SLIP frame remembered while waiting for a response.
+pub const NOISE_LIMIT: usize = 16;
+
+/// A response came from the bootloader instead of the firmware.
+#[derive(Debug)]
+pub struct BootloaderDetected;
+
+impl fmt::Display for BootloaderDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device answered from its bootloader - it has reset")
+    }
+}
+
+impl std::error::Error for BootloaderDetected {}
+
+/// Whether `noise`, bytes seen outside SLIP frames, holds an STK500 reply.
+pub fn is_handshake(noise: &[u8]) -> bool {
+    noise.contains(&STK_NOSYNC) || noise.windows(2).any(|pair| pair == [STK_INSYNC, STK_OK])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionManager;
+    use crate::fixtures;
+    use crate::loopback::LoopbackConnector;
+    use crate::manifest::Manifest;
+    use crate::robot_state::RobotState;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn manifest() -> Manifest {
+        fixtures::manifest(json!([fixtures::function(
+            1,
+            "getDistance",
+            Some("i16"),
+            &[]
+        )]))
+    }
+
+    #[test]
+    fn test_recognises_stk500_replies() {
+        assert!(is_handshake(&[b'x', STK_INSYNC, STK_OK]));
+        assert!(is_handshake(&[STK_NOSYNC]));
+        assert!(!is_handshake(b"debug: hello\r\n"));
+        assert!(!is_handshake(&[STK_INSYNC]));
+    }
+
+    #[tokio::test]
+    async fn test_polls_a_booting_board_until_it_answers() {
+        let device = fixtures::device(manifest()).booting(3).in_bootloader(1);
+        let connector = LoopbackConnector::new(device).with_boot_timeout(Duration::from_secs(5));
+        let connection_manager = ConnectionManager::with_connector(Box::new(connector.clone()));
+
+        let started = std::time::Instant::now();
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert!(connection_manager.get_state().is_ready());
+        // Well short of a fixed boot delay
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let silent = fixtures::device(manifest()).booting(usize::MAX);
+        let connector =
+            LoopbackConnector::new(silent).with_boot_timeout(Duration::from_millis(300));
+        let connection_manager = ConnectionManager::with_connector(Box::new(connector));
+        assert!(connection_manager
+            .check_and_update_connection()
+            .await
+            .is_err());
+        assert!(matches!(
+            connection_manager.get_state(),
+            RobotState::Error(message) if message.starts_with("Device did not start")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_waits_out_bootloader_and_reinitializes() {
+        let device = fixtures::device(manifest()).in_bootloader(2);
+        let connector = LoopbackConnector::new(device);
+        let connection_manager = ConnectionManager::with_connector(Box::new(connector.clone()));
+
+        // Comes up on the first connection check despite two bootloader replies
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert!(connection_manager.get_state().is_ready());
+
+        // A reset mid-run fails the call and asks for recovery at once
+        connector.reset_into_bootloader(1);
+        let manifest = manifest();
+        let err = connection_manager
+            .execute_function(&manifest.functions[0], &json!({}))
+            .await
+            .unwrap_err();
+        assert!(err.is::<BootloaderDetected>(), "{}", err);
+        assert!(matches!(
+            connection_manager.get_state(),
+            RobotState::Error(_)
+        ));
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            connection_manager.recovery_requested(),
+        )
+        .await
+        .unwrap();
+
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert!(connection_manager.get_state().is_ready());
+    }
+}
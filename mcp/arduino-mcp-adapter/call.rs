@@ -0,0 +1,164 @@
+//! `call` subcommand: run one device function without starting the server,
+//! or with `--url` through an adapter that is already running.
+
+use anyhow::{anyhow, Context, Result};
+use mcp_client::{McpClient, RpcError};
+use serde_json::Value;
+
+use crate::connection::ConnectionManager;
+use crate::errors::AdapterError;
+use crate::manifest::ManifestManager;
+
+/// Open the port and identify the device if not already done, returning the
+/// device ID.
+pub async fn connect(connection_manager: &ConnectionManager) -> Result<String> {
+    // A failed open leaves the reason in the state, which reads better than
+    // the bare error
+    let _ = connection_manager.check_and_update_connection().await;
+    let state = connection_manager.get_state();
+    state
+        .device_id()
+        .map(str::to_string)
+        .ok_or_else(|| AdapterError::NotReady(state.error_message()).into())
+}
+
+/// Connect, identify the device, execute `tool` with the JSON object `args`
+/// and return the decoded result.
+pub async fn run(
+    connection_manager: &ConnectionManager,
+    manifest_manager: &ManifestManager,
+    tool: &str,
+    args: &str,
+) -> Result<String> {
+    let arguments: Value =
+        serde_json::from_str(args).with_context(|| format!("Invalid --args JSON: {}", args))?;
+
+    let device_id = connect(connection_manager).await?;
+    let manifest = manifest_manager.get_manifest(&device_id)?;
+    let func = manifest
+        .functions
+        .iter()
+        .find(|f| f.name == tool)
+        .ok_or_else(|| {
+            let names: Vec<&str> = manifest.functions.iter().map(|f| f.name.as_str()).collect();
+            anyhow!(
+                "Function '{}' not found on {} (available: {})",
+                tool,
+                device_id,
+                names.join(", ")
+            )
+        })?;
+
+    manifest_manager
+        .validate_function_arguments(func, &arguments)
+        .map_err(|e| anyhow!("Invalid arguments: {}", e))?;
+
+    connection_manager.execute_function(func, &arguments).await
+}
+
+/// Call `tool` on the adapter serving `url` and return the text of its
+/// result.
+pub async fn run_remote(
+    url: &str,
+    token: Option<String>,
+    tool: &str,
+    args: &str,
+) -> Result<String> {
+    let arguments: Value =
+        serde_json::from_str(args).with_context(|| format!("Invalid --args JSON: {}", args))?;
+
+    let client = McpClient::new(url).with_token(token);
+    client
+        .initialize("arduino-mcp-adapter call")
+        .await
+        .with_context(|| format!("No adapter at {}", url))?;
+    let result =
+        client
+            .call_tool(tool, &arguments)
+            .await
+            .map_err(|e| match e.downcast::<RpcError>() {
+                Ok(error) => anyhow!("{}", error.message),
+                Err(e) => e,
+            })?;
+    Ok(result_text(&result))
+}
+
+/// The text items of a `tools/call` result, one per line.
+fn result_text(result: &Value) -> String {
+    let texts: Vec<&str> = result["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item["text"].as_str())
+        .collect();
+    texts.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::loopback::LoopbackConnector;
+    use serde_json::json;
+
+    fn functions() -> Value {
+        json!([fixtures::function(
+            1,
+            "setServo",
+            Some("i16"),
+            &[("angle", "i16")]
+        )])
+    }
+
+    /// Managers for `connector`; the tempdir holds the manifest.
+    fn managers(
+        connector: &LoopbackConnector,
+    ) -> (ConnectionManager, ManifestManager, tempfile::TempDir) {
+        let dir = fixtures::manifest_dir(&fixtures::manifest_json(functions(), json!({})));
+        (
+            ConnectionManager::with_connector(Box::new(connector.clone())),
+            ManifestManager::new(dir.path().to_path_buf()),
+            dir,
+        )
+    }
+
+    fn connector() -> LoopbackConnector {
+        let device = fixtures::device(fixtures::manifest(functions()))
+            .respond("setServo", 90i16.to_le_bytes().to_vec());
+        LoopbackConnector::new(device)
+    }
+
+    #[tokio::test]
+    async fn test_call_executes_function() {
+        let connector = connector();
+        let (connection_manager, manifest_manager, _dir) = managers(&connector);
+
+        let result = run(
+            &connection_manager,
+            &manifest_manager,
+            "setServo",
+            r#"{"angle": 90}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, "90");
+        assert_eq!(connector.calls()[1], ("setServo".to_string(), vec![90, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_call_reports_unknown_function_and_missing_device() {
+        let (connection_manager, manifest_manager, _dir) = managers(&connector());
+        let err = run(&connection_manager, &manifest_manager, "fly", "{}")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("available: setServo"));
+
+        let unplugged = connector();
+        unplugged.unplug();
+        let (connection_manager, manifest_manager, _dir) = managers(&unplugged);
+        let err = run(&connection_manager, &manifest_manager, "setServo", "{}")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Robot not ready"));
+    }
+}
@@ -0,0 +1,117 @@
+//! Reassembly of chunked responses. Firmware speaking protocol v3 sends every
+//! response as one or more `[index] [total] [payload...] [crc]` frames, so a
+//! result can be larger than its frame buffer.
+
+use anyhow::Result;
+
+use crate::errors::AdapterError;
+
+/// Collects the chunks of one response.
+#[derive(Default)]
+pub struct Reassembler {
+    total: usize,
+    received: usize,
+    data: Vec<u8>,
+    /// First problem seen; reported once every chunk has arrived, so the
+    /// rest of a damaged response isn't mistaken for the next one
+    error: Option<AdapterError>,
+}
+
+impl Reassembler {
+    /// Add one chunk, without its CRC. Returns the whole response once the
+    /// last chunk has arrived.
+    pub fn push(&mut self, chunk: &[u8], crc_ok: bool) -> Option<Result<Vec<u8>>> {
+        // Error frames are never chunked; 0xFF can't be an index as a
+        // response has at most 255 chunks
+        if crc_ok && matches!(chunk, [0xFF, _]) {
+            *self = Self::default();
+            return Some(Ok(chunk.to_vec()));
+        }
+        let &[index, total, ref payload @ ..] = chunk else {
+            *self = Self::default();
+            return Some(Err(AdapterError::Protocol(format!(
+                "Chunk too short ({} bytes)",
+                chunk.len()
+            ))
+            .into()));
+        };
+
+        let (index, total) = (usize::from(index), usize::from(total));
+        // A new response, or one whose first chunk went missing
+        if index == 0 || self.total == 0 {
+            *self = Self {
+                total,
+                ..Self::default()
+            };
+        }
+        if !crc_ok {
+            self.fail(AdapterError::CrcMismatch(format!(
+                "Chunk {} of {} failed its CRC check",
+                index + 1,
+                total
+            )));
+        } else if index != self.received || total != self.total || total == 0 {
+            self.fail(AdapterError::Protocol(format!(
+                "Expected chunk {} of {}, got chunk {} of {}",
+                self.received + 1,
+                self.total,
+                index + 1,
+                total
+            )));
+        } else {
+            self.data.extend_from_slice(payload);
+        }
+
+        self.received += 1;
+        // Done at the last chunk, even if one went missing on the way
+        if index + 1 < self.total {
+            return None;
+        }
+        let done = std::mem::take(self);
+        Some(match done.error {
+            Some(error) => Err(error.into()),
+            None => Ok(done.data),
+        })
+    }
+
+    fn fail(&mut self, error: AdapterError) {
+        self.error.get_or_insert(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reassembles_chunks() {
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(&[0, 3, 1, 2], true).is_none());
+        assert!(reassembler.push(&[1, 3, 3, 4], true).is_none());
+        let data = reassembler.push(&[2, 3, 5], true).unwrap().unwrap();
+        assert_eq!(data, vec![1, 2, 3, 4, 5]);
+
+        // Single-chunk and error responses
+        assert!(reassembler.push(&[0, 1], true).unwrap().unwrap().is_empty());
+        assert_eq!(
+            reassembler.push(&[0xFF, 0x02], true).unwrap().unwrap(),
+            vec![0xFF, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_damaged_chunk_fails_whole_response() {
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(&[0, 3, 1, 2], true).is_none());
+        assert!(reassembler.push(&[1, 3, 3, 4], false).is_none());
+        let err = reassembler.push(&[2, 3, 5], true).unwrap().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Chunk 2 of 3 failed its CRC check"));
+
+        // A lost chunk shows up as the next one arriving early
+        assert!(reassembler.push(&[0, 3, 1], true).is_none());
+        let err = reassembler.push(&[2, 3, 3], true).unwrap().unwrap_err();
+        assert!(err.to_string().contains("Expected chunk 2 of 3"));
+    }
+}
@@ -0,0 +1,370 @@
+//! Composite tools: tools a manifest defines as steps over its own
+//! functions, e.g. a `blink` that repeats `ledOn` and `ledOff`. A call is
+//! expanded into steps and run like a macro replay.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::manifest::{self, Ack, Function, Manifest, ManifestManager, Parameter};
+use crate::sequence::{self, Step, StepSpec};
+
+/// Steps one call may expand to.
+pub const MAX_STEPS: usize = 1000;
+
+/// Nesting allowed for `repeat`.
+const MAX_DEPTH: usize = 4;
+
+/// ```json
+/// {
+///   "name": "blink",
+///   "desc": "Blink the LED",
+///   "params": [{"name": "times", "type": "i16", "minimum": 1, "maximum": 50},
+///              {"name": "ms", "type": "i16", "minimum": 50}],
+///   "steps": [
+///     {"repeat": "$times", "steps": [
+///       {"call": "ledOn", "delay_ms": "$ms"},
+///       {"call": "ledOff", "delay_ms": "$ms"}
+///     ]}
+///   ]
+/// }
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Composite {
+    pub name: String,
+    pub desc: String,
+    #[serde(default)]
+    pub params: Vec<Parameter>,
+    pub steps: Vec<CompositeStep>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum CompositeStep {
+    Call(CallStep),
+    Repeat(RepeatStep),
+}
+
+/// A call of a manifest function. String arguments of the form `"$param"`
+/// are replaced by the composite's argument of that name.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CallStep {
+    pub call: String,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+    /// Pause after the call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<Count>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatStep {
+    pub repeat: Count,
+    pub steps: Vec<CompositeStep>,
+}
+
+/// A number given outright or as `"$param"`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Count {
+    Fixed(u64),
+    Param(String),
+}
+
+impl Composite {
+    /// The composite as a function, for its input schema and argument checks.
+    pub fn signature(&self) -> Function {
+        Function {
+            tag: 0,
+            name: self.name.clone(),
+            desc: self.desc.clone(),
+            return_type: None,
+            params: self.params.clone(),
+            ack: Ack::Complete,
+            mutates: None,
+            debounce_ms: None,
+        }
+    }
+
+    /// Validated steps for a call with `arguments`.
+    pub fn expand(
+        &self,
+        arguments: &Value,
+        manifest: &Manifest,
+        manifest_manager: &ManifestManager,
+    ) -> Result<Vec<Step>> {
+        let signature = self.signature();
+        manifest_manager.validate_function_arguments(&signature, arguments)?;
+        // Numbers given as strings or with units, if validation took them
+        let arguments = manifest::coerce_arguments(&signature, arguments);
+        let mut specs = Vec::new();
+        expand_steps(&self.steps, &arguments, &mut specs)?;
+        sequence::validate_steps(specs, manifest, manifest_manager)
+    }
+}
+
+fn expand_steps(steps: &[CompositeStep], arguments: &Value, out: &mut Vec<StepSpec>) -> Result<()> {
+    for step in steps {
+        match step {
+            CompositeStep::Call(call) => {
+                if out.len() == MAX_STEPS {
+                    return Err(anyhow!("Expands to more than {} steps", MAX_STEPS));
+                }
+                let substituted = call
+                    .arguments
+                    .iter()
+                    .map(|(name, value)| (name.clone(), substitute(value, arguments)))
+                    .collect();
+                out.push(StepSpec {
+                    name: call.call.clone(),
+                    arguments: Value::Object(substituted),
+                    delay_ms: match &call.delay_ms {
+                        Some(count) => count.resolve(arguments)?,
+                        None => 0,
+                    },
+                });
+            }
+            CompositeStep::Repeat(repeat) => {
+                let times = repeat.repeat.resolve(arguments)?;
+                if times > MAX_STEPS as u64 {
+                    return Err(anyhow!("Repeats more than {} times", MAX_STEPS));
+                }
+                for _ in 0..times {
+                    let before = out.len();
+                    expand_steps(&repeat.steps, arguments, out)?;
+                    // Every round expands the same, e.g. to nothing if an
+                    // inner repeat runs 0 times
+                    if out.len() == before {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn substitute(value: &Value, arguments: &Value) -> Value {
+    value
+        .as_str()
+        .and_then(|s| s.strip_prefix('$'))
+        .and_then(|param| arguments.get(param))
+        .unwrap_or(value)
+        .clone()
+}
+
+impl Count {
+    fn resolve(&self, arguments: &Value) -> Result<u64> {
+        match self {
+            Count::Fixed(n) => Ok(*n),
+            Count::Param(reference) => {
+                let param = reference.strip_prefix('$').unwrap_or(reference);
+                arguments
+                    .get(param)
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| anyhow!("'{}' must be a whole number of at least 0", param))
+            }
+        }
+    }
+}
+
+/// Check the composites of `manifest` when it is loaded, so a typo fails
+/// the manifest rather than the first call.
+pub fn check(manifest: &Manifest) -> Result<()> {
+    for composite in &manifest.composites {
+        if manifest.functions.iter().any(|f| f.name == composite.name) {
+            return Err(anyhow!(
+                "composite '{}' has the name of a function",
+                composite.name
+            ));
+        }
+        check_steps(composite, &composite.steps, manifest, 1)
+            .map_err(|e| anyhow!("composite '{}': {}", composite.name, e))?;
+    }
+    Ok(())
+}
+
+/// Whether `composite` only calls functions `manifest` has, such as one
+/// cut down to its read-only functions.
+pub fn fits(composite: &Composite, manifest: &Manifest) -> bool {
+    check_steps(composite, &composite.steps, manifest, 1).is_ok()
+}
+
+fn check_steps(
+    composite: &Composite,
+    steps: &[CompositeStep],
+    manifest: &Manifest,
+    depth: usize,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(anyhow!("repeats nested more than {} deep", MAX_DEPTH));
+    }
+    // Also keeps a huge repeat count from spinning without adding steps
+    if steps.is_empty() {
+        return Err(anyhow!("empty list of steps"));
+    }
+    for step in steps {
+        match step {
+            CompositeStep::Call(call) => {
+                if !manifest.functions.iter().any(|f| f.name == call.call) {
+                    return Err(anyhow!("function '{}' is not in the manifest", call.call));
+                }
+                let references = call
+                    .arguments
+                    .values()
+                    .filter_map(|value| value.as_str().and_then(|s| s.strip_prefix('$')));
+                for param in references {
+                    check_param(composite, param)?;
+                }
+                if let Some(Count::Param(reference)) = &call.delay_ms {
+                    check_count(composite, reference)?;
+                }
+            }
+            CompositeStep::Repeat(repeat) => {
+                match &repeat.repeat {
+                    Count::Fixed(times) if *times > MAX_STEPS as u64 => {
+                        return Err(anyhow!("repeat count {} is over {}", times, MAX_STEPS));
+                    }
+                    Count::Fixed(_) => {}
+                    Count::Param(reference) => check_count(composite, reference)?,
+                }
+                check_steps(composite, &repeat.steps, manifest, depth + 1)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_param<'a>(composite: &'a Composite, param: &str) -> Result<&'a Parameter> {
+    composite
+        .params
+        .iter()
+        .find(|p| p.name == param)
+        .ok_or_else(|| anyhow!("no parameter '{}'", param))
+}
+
+fn check_count(composite: &Composite, reference: &str) -> Result<()> {
+    let param = reference
+        .strip_prefix('$')
+        .ok_or_else(|| anyhow!("'{}' is neither a number nor a $parameter", reference))?;
+    let param = check_param(composite, param)?;
+    if !matches!(param.param_type.as_str(), "i16" | "i32") {
+        return Err(anyhow!("parameter '{}' is not an integer", param.name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, function};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn manifest(composite: Value) -> Manifest {
+        fixtures::manifest_with(
+            json!([
+                function(1, "ledOn", None, &[]),
+                function(2, "ledOff", None, &[]),
+                function(3, "setServo", None, &[("angle", "i16")])
+            ]),
+            json!({"composites": [composite]}),
+        )
+    }
+
+    fn blink() -> Value {
+        json!({
+            "name": "blink",
+            "desc": "Blink the LED",
+            "params": [{"name": "times", "type": "i16", "minimum": 1},
+                       {"name": "ms", "type": "i16"}],
+            "steps": [
+                {"call": "setServo", "arguments": {"angle": 90}},
+                {"repeat": "$times", "steps": [
+                    {"call": "ledOn", "delay_ms": "$ms"},
+                    {"call": "ledOff", "delay_ms": "$ms"}
+                ]}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_expands_repeats_and_parameters() {
+        let manifest = manifest(blink());
+        check(&manifest).unwrap();
+        let manager = ManifestManager::new(std::env::temp_dir());
+        let composite = &manifest.composites[0];
+
+        let steps = composite
+            .expand(&json!({"times": 2, "ms": 250}), &manifest, &manager)
+            .unwrap();
+        let names: Vec<&str> = steps
+            .iter()
+            .map(|s| s.func.as_ref().unwrap().name.as_str())
+            .collect();
+        assert_eq!(names, ["setServo", "ledOn", "ledOff", "ledOn", "ledOff"]);
+        assert_eq!(steps[0].arguments, json!({"angle": 90}));
+        assert_eq!(steps[2].delay, Duration::from_millis(250));
+
+        // The composite's own parameters are checked first
+        assert!(composite
+            .expand(&json!({"times": 0, "ms": 250}), &manifest, &manager)
+            .is_err());
+        let err = composite
+            .expand(&json!({"times": 600, "ms": 0}), &manifest, &manager)
+            .unwrap_err();
+        assert!(err.to_string().contains("more than 1000 steps"), "{}", err);
+
+        // Lenient numbers reach the repeat count as integers
+        let lenient = ManifestManager::new(std::env::temp_dir()).with_lenient_numbers(true);
+        let steps = composite
+            .expand(&json!({"times": "2", "ms": 250.0}), &manifest, &lenient)
+            .unwrap();
+        assert_eq!(steps.len(), 5);
+        assert_eq!(steps[2].delay, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_repeat_counts_are_capped() {
+        let mut composite = blink();
+        composite["steps"][1]["repeat"] = json!(1_000_000_000_000u64);
+        let err = check(&manifest(composite)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "composite 'blink': repeat count 1000000000000 is over 1000"
+        );
+
+        // An outer repeat over an inner one that runs 0 times ends at once
+        let mut composite = blink();
+        composite["steps"][1] = json!({"repeat": 1000, "steps": [
+            {"repeat": 1000, "steps": [{"repeat": "$times", "steps": [{"call": "ledOn"}]}]}
+        ]});
+        composite["params"][0] = json!({"name": "times", "type": "i16"});
+        let manifest = manifest(composite);
+        check(&manifest).unwrap();
+        let manager = ManifestManager::new(std::env::temp_dir());
+        let steps = manifest.composites[0]
+            .expand(&json!({"times": 0, "ms": 0}), &manifest, &manager)
+            .unwrap();
+        assert_eq!(steps.len(), 1);
+    }
+
+    #[test]
+    fn test_check_rejects_bad_references() {
+        let mut composite = blink();
+        composite["steps"][0]["arguments"]["angle"] = json!("$angle");
+        let err = check(&manifest(composite)).unwrap_err();
+        assert_eq!(err.to_string(), "composite 'blink': no parameter 'angle'");
+
+        let mut composite = blink();
+        composite["steps"][1]["steps"][0]["call"] = json!("ledToggle");
+        assert!(check(&manifest(composite)).is_err());
+
+        let mut composite = blink();
+        composite["name"] = json!("ledOn");
+        assert!(check(&manifest(composite)).is_err());
+    }
+}
@@ -0,0 +1,706 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors::Kind;
+use crate::geofence::Bounds;
+use crate::governor::LimitSpec;
+use crate::latency::DEFAULT_TIMEOUT_FACTOR;
+use crate::presets::{self, PresetGroups};
+use crate::scheduler::ScheduleSpec;
+
+/// Location checked when `--config` is not given.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/arduino-mcp-adapter/config.toml";
+
+/// Codes `[error_codes]` can't hand out: JSON-RPC's parse, invalid request
+/// and unknown method errors, and 0.
+const RESERVED_ERROR_CODES: [i32; 4] = [0, -32700, -32600, -32601];
+
+/// Adapter settings loaded from `config.toml`.
+///
+/// Every field is optional in the file; command-line flags are applied on top
+/// with [`Config::apply_cli`] so they always take precedence.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Serial line (e.g. /dev/ttyUSB0), or a URI such as `tcp://host:port`
+    pub line: Option<String>,
+    /// JSON manifest directory
+    pub manifest_dir: Option<PathBuf>,
+    /// HTTP port for MCP server
+    pub port: u16,
+    /// Baud rate
+    pub baud: u32,
+    /// Maximum commands outstanding on the wire; above 1 requires firmware
+    /// with sequence-number support
+    pub pipeline_depth: usize,
+    /// How long to keep resending a command the device answers busy to;
+    /// 0 fails it on the first busy answer
+    pub busy_wait_ms: u64,
+    /// Give up on a response after this multiple of the function's p99
+    /// round trip; 0 keeps the fixed 5 second timeout
+    pub timeout_factor: f64,
+    /// Refuse tool calls while this many commands are queued; 0 for no limit
+    pub max_queue_depth: usize,
+    /// Refuse tool calls while the oldest queued command has waited this
+    /// long; 0 for no limit
+    pub max_queue_age_ms: u64,
+    /// Write all serial traffic to this pcapng file
+    pub pcap: Option<PathBuf>,
+    /// Warm interpreters kept for `runPythonScript`; 0 starts `python3`
+    /// for every script
+    pub python_pool_size: usize,
+    /// Virtualenv for `runPythonScript`, created on startup if missing;
+    /// needed for per-call `requirements`
+    pub python_venv: Option<PathBuf>,
+    /// Where recorded macros are saved; defaults to `<manifest_dir>/macros`
+    pub macro_dir: Option<PathBuf>,
+    /// Write every robot function call clients make to this `.py` script
+    /// or `.json` macro
+    pub record_session: Option<PathBuf>,
+    /// Expose the `rawCommand` tool, which sends any tag and argument bytes
+    pub enable_raw: bool,
+    /// Refuse new MCP client sessions beyond this many
+    pub max_clients: Option<usize>,
+    /// Only the controlling session may call tools; others take over with
+    /// `takeControl`
+    pub exclusive_control: bool,
+    /// Only offer manifest functions that don't change the robot, for
+    /// clients trusted to observe it
+    pub read_only: bool,
+    /// Log function calls instead of sending them to the robot
+    pub dry_run: bool,
+    /// Refuse calls that change the robot while its battery reads below
+    /// the manifest's `critical` level
+    pub battery_lockout: bool,
+    /// Accept integer arguments sent as numeric strings (`"90"`) or whole
+    /// floats (`90.0`), converting them before the call
+    pub lenient_numbers: bool,
+    /// Language of the error messages clients see, e.g. `de`
+    pub lang: String,
+    /// Refuse manifests with unknown fields instead of logging them
+    pub strict_manifests: bool,
+    /// Gamepad mapping file; drives the robot from a gamepad
+    pub gamepad: Option<PathBuf>,
+    /// Directory of `.wasm` plugins adding tools
+    pub plugin_dir: Option<PathBuf>,
+    /// USB VID/PID/serial to device ID map; defaults to `devices.toml` in
+    /// the manifest directory
+    pub usb_devices: Option<PathBuf>,
+    pub auth: AuthConfig,
+    pub http: HttpConfig,
+    pub logging: LoggingConfig,
+    /// Per-device settings keyed by the ID returned from `deviceId()`
+    pub devices: HashMap<String, DeviceConfig>,
+    /// Recurring calls started with the server
+    pub schedules: Vec<ScheduleSpec>,
+    /// Safety limits on the arguments and pace of device calls
+    pub limits: Vec<LimitSpec>,
+    /// Named argument values clients can pass as `"@name"`, grouped in
+    /// `[presets.<group>]` tables
+    pub presets: PresetGroups,
+    /// JSON-RPC codes for tool call errors of some kinds, replacing the
+    /// defaults; keyed by `data.kind`
+    pub error_codes: BTreeMap<Kind, i32>,
+    /// Area drive commands must stay within, judged by the robot's odometry
+    pub geofence: Option<Bounds>,
+    /// Robots served together when no `line` is set
+    pub robots: Vec<RobotSpec>,
+    /// Other adapters whose tools a fleet re-exports
+    pub remotes: Vec<RemoteSpec>,
+    /// UDP port to hear Wi-Fi robots' discovery beacons on, in fleet mode
+    pub discovery_port: Option<u16>,
+    /// Robots paired from their beacons, served like `robots` over TCP
+    pub paired: Vec<PairedRobot>,
+    /// File this was loaded from, where pairings are saved
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// One robot of a fleet, from a `[[robots]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RobotSpec {
+    /// Names the robot's `/mcp/<name>` endpoint and prefixes its tools
+    pub name: String,
+    /// Serial line, or a URI such as `tcp://host:port`
+    pub line: String,
+    /// Defaults to the top-level `baud`
+    pub baud: Option<u32>,
+}
+
+/// A robot paired through discovery, from a `[[paired]]` entry.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PairedRobot {
+    /// Device ID from the beacon; also the robot's name in the fleet
+    pub id: String,
+    /// `host:port` of the robot's TCP socket
+    pub address: String,
+    /// Device ID whose manifest describes the robot
+    pub manifest: String,
+}
+
+/// Another adapter mounted into a fleet, from a `[[remotes]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteSpec {
+    /// Prefixes the remote's tools, like a robot name
+    pub name: String,
+    /// Base URL of the remote adapter, e.g. `http://pi-2.local:8080`
+    pub url: String,
+    /// The remote's `auth.token`, if it has one
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Bearer token required on every HTTP endpoint except `/health`
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Serve HTTP/2 over cleartext (h2c) alongside HTTP/1.1
+    pub http2: bool,
+    /// Keep HTTP/1.1 connections open between requests
+    pub keep_alive: bool,
+    /// Close HTTP/1.1 connections after this long without a request; 0
+    /// waits forever
+    pub idle_timeout_secs: u64,
+    /// Ping SSE streams and HTTP/2 connections this often so dead clients
+    /// are noticed; 0 disables
+    pub ping_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            http2: true,
+            keep_alive: true,
+            idle_timeout_secs: 60,
+            ping_secs: 15,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_secs > 0).then(|| Duration::from_secs(self.idle_timeout_secs))
+    }
+
+    pub fn ping_interval(&self) -> Option<Duration> {
+        (self.ping_secs > 0).then(|| Duration::from_secs(self.ping_secs))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Maximum log level: error, warn, info, debug or trace
+    pub level: String,
+    /// OTLP/HTTP endpoint spans are exported to
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Manifest file to use instead of `<manifest_dir>/<device_id>.json`
+    pub manifest: Option<PathBuf>,
+}
+
+/// Whether `name` can name a fleet robot: letters, digits and `-`.
+pub fn is_valid_robot_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Command-line values that override the configuration file.
+#[derive(Debug, Default)]
+pub struct CliOverrides {
+    pub line: Option<String>,
+    pub manifest_dir: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub baud: Option<u32>,
+    pub pipeline_depth: Option<usize>,
+    pub busy_wait_ms: Option<u64>,
+    pub timeout_factor: Option<f64>,
+    pub max_queue_depth: Option<usize>,
+    pub max_queue_age_ms: Option<u64>,
+    pub pcap: Option<PathBuf>,
+    pub python_pool_size: Option<usize>,
+    pub python_venv: Option<PathBuf>,
+    pub macro_dir: Option<PathBuf>,
+    pub record_session: Option<PathBuf>,
+    pub enable_raw: bool,
+    pub max_clients: Option<usize>,
+    pub exclusive_control: bool,
+    pub read_only: bool,
+    pub dry_run: bool,
+    pub battery_lockout: bool,
+    pub lenient_numbers: bool,
+    pub lang: Option<String>,
+    pub strict_manifests: bool,
+    pub gamepad: Option<PathBuf>,
+    pub plugin_dir: Option<PathBuf>,
+    pub usb_devices: Option<PathBuf>,
+    pub auth_token: Option<String>,
+    pub log_level: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub discovery_port: Option<u16>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            line: None,
+            manifest_dir: None,
+            port: 8080,
+            baud: 115200,
+            pipeline_depth: 1,
+            busy_wait_ms: 5000,
+            timeout_factor: DEFAULT_TIMEOUT_FACTOR,
+            max_queue_depth: 64,
+            max_queue_age_ms: 30_000,
+            pcap: None,
+            python_pool_size: 0,
+            python_venv: None,
+            macro_dir: None,
+            record_session: None,
+            enable_raw: false,
+            max_clients: None,
+            exclusive_control: false,
+            read_only: false,
+            dry_run: false,
+            battery_lockout: false,
+            lenient_numbers: false,
+            lang: "en".to_string(),
+            strict_manifests: false,
+            gamepad: None,
+            plugin_dir: None,
+            usb_devices: None,
+            auth: AuthConfig::default(),
+            http: HttpConfig::default(),
+            logging: LoggingConfig::default(),
+            devices: HashMap::new(),
+            schedules: Vec::new(),
+            limits: Vec::new(),
+            presets: PresetGroups::new(),
+            error_codes: BTreeMap::new(),
+            geofence: None,
+            robots: Vec::new(),
+            remotes: Vec::new(),
+            discovery_port: None,
+            paired: Vec::new(),
+            path: None,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration file at `path`, or the default location when no
+    /// path is given. A missing default file yields the built-in defaults; a
+    /// missing explicit file is an error.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None => {
+                let default_path = Path::new(DEFAULT_CONFIG_PATH);
+                if default_path.exists() {
+                    Self::from_file(default_path)
+                } else {
+                    Ok(Self::default())
+                }
+            }
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+        let mut config = Self::parse(&content)
+            .map_err(|e| anyhow!("Failed to parse config file {}: {}", path.display(), e))?;
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content)?;
+        presets::check(&config.presets)?;
+        // The manifest ID names a file in the manifest directory
+        if let Some(paired) = config.paired.iter().find(|paired| {
+            !is_valid_robot_name(&paired.id) || !is_valid_robot_name(&paired.manifest)
+        }) {
+            return Err(anyhow!(
+                "Invalid [[paired]] entry '{}' with manifest '{}' (use letters, digits and '-')",
+                paired.id,
+                paired.manifest
+            ));
+        }
+        // JSON-RPC's own codes would be taken for a malformed request, and
+        // 0 for no error at all
+        if let Some((kind, code)) = config
+            .error_codes
+            .iter()
+            .find(|(_, code)| RESERVED_ERROR_CODES.contains(code))
+        {
+            return Err(anyhow!(
+                "[error_codes] can't give {} the reserved code {}",
+                kind.name(),
+                code
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn apply_cli(&mut self, cli: CliOverrides) {
+        if let Some(line) = cli.line {
+            self.line = Some(line);
+        }
+        if let Some(manifest_dir) = cli.manifest_dir {
+            self.manifest_dir = Some(manifest_dir);
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+        if let Some(baud) = cli.baud {
+            self.baud = baud;
+        }
+        if let Some(depth) = cli.pipeline_depth {
+            self.pipeline_depth = depth;
+        }
+        if let Some(wait) = cli.busy_wait_ms {
+            self.busy_wait_ms = wait;
+        }
+        if let Some(factor) = cli.timeout_factor {
+            self.timeout_factor = factor;
+        }
+        if let Some(depth) = cli.max_queue_depth {
+            self.max_queue_depth = depth;
+        }
+        if let Some(age) = cli.max_queue_age_ms {
+            self.max_queue_age_ms = age;
+        }
+        if let Some(pcap) = cli.pcap {
+            self.pcap = Some(pcap);
+        }
+        if let Some(size) = cli.python_pool_size {
+            self.python_pool_size = size;
+        }
+        if let Some(venv) = cli.python_venv {
+            self.python_venv = Some(venv);
+        }
+        if let Some(dir) = cli.macro_dir {
+            self.macro_dir = Some(dir);
+        }
+        if let Some(path) = cli.record_session {
+            self.record_session = Some(path);
+        }
+        if cli.enable_raw {
+            self.enable_raw = true;
+        }
+        if let Some(max) = cli.max_clients {
+            self.max_clients = Some(max);
+        }
+        if cli.exclusive_control {
+            self.exclusive_control = true;
+        }
+        if cli.read_only {
+            self.read_only = true;
+        }
+        if cli.dry_run {
+            self.dry_run = true;
+        }
+        if cli.battery_lockout {
+            self.battery_lockout = true;
+        }
+        if cli.lenient_numbers {
+            self.lenient_numbers = true;
+        }
+        if let Some(lang) = cli.lang {
+            self.lang = lang;
+        }
+        if cli.strict_manifests {
+            self.strict_manifests = true;
+        }
+        if let Some(mapping) = cli.gamepad {
+            self.gamepad = Some(mapping);
+        }
+        if let Some(dir) = cli.plugin_dir {
+            self.plugin_dir = Some(dir);
+        }
+        if let Some(path) = cli.usb_devices {
+            self.usb_devices = Some(path);
+        }
+        if let Some(token) = cli.auth_token {
+            self.auth.token = Some(token);
+        }
+        if let Some(level) = cli.log_level {
+            self.logging.level = level;
+        }
+        if let Some(endpoint) = cli.otlp_endpoint {
+            self.logging.otlp_endpoint = Some(endpoint);
+        }
+        if let Some(port) = cli.discovery_port {
+            self.discovery_port = Some(port);
+        }
+    }
+
+    pub fn line(&self) -> Result<&str> {
+        self.line.as_deref().ok_or_else(|| {
+            anyhow!("No serial line configured. Pass --line or set `line` in the config file.")
+        })
+    }
+
+    /// Whether to serve `robots` and `remotes` as a fleet, after checking
+    /// them. A `line` always selects a single robot.
+    pub fn is_fleet(&self) -> Result<bool> {
+        if self.line.is_some() {
+            return Ok(false);
+        }
+        let mut names = std::collections::HashSet::new();
+        let robots = self.fleet_robots();
+        let robot_names = robots.iter().map(|robot| &robot.name);
+        for name in robot_names.chain(self.remotes.iter().map(|remote| &remote.name)) {
+            if !is_valid_robot_name(name) {
+                return Err(anyhow!(
+                    "Invalid robot name '{}' (use letters, digits and '-')",
+                    name
+                ));
+            }
+            if !names.insert(name.as_str()) {
+                return Err(anyhow!("Robot '{}' is listed twice", name));
+            }
+        }
+        if let Some(remote) = self.remotes.iter().find(|r| !r.url.starts_with("http://")) {
+            return Err(anyhow!(
+                "Remote '{}' needs an http:// URL, not '{}'",
+                remote.name,
+                remote.url
+            ));
+        }
+        Ok(!names.is_empty() || self.discovery_port.is_some())
+    }
+
+    /// `robots`, then a `tcp://` robot for each pairing, named by its
+    /// device ID.
+    pub fn fleet_robots(&self) -> Vec<RobotSpec> {
+        let paired = self.paired.iter().map(|paired| RobotSpec {
+            name: paired.id.clone(),
+            line: format!("tcp://{}", paired.address),
+            baud: None,
+        });
+        self.robots.iter().cloned().chain(paired).collect()
+    }
+
+    pub fn manifest_dir(&self) -> Result<&Path> {
+        self.manifest_dir.as_deref().ok_or_else(|| {
+            anyhow!("No manifest directory configured. Pass --manifest-dir or set `manifest_dir` in the config file.")
+        })
+    }
+
+    /// Macro directory, if one is configured or can be derived from the
+    /// manifest directory.
+    pub fn macro_dir(&self) -> Option<PathBuf> {
+        self.macro_dir
+            .clone()
+            .or_else(|| self.manifest_dir.as_ref().map(|dir| dir.join("macros")))
+    }
+
+    pub fn pipeline_depth(&self) -> Result<usize> {
+        match self.pipeline_depth {
+            1..=255 => Ok(self.pipeline_depth),
+            depth => Err(anyhow!(
+                "Invalid pipeline depth {} (must be between 1 and 255)",
+                depth
+            )),
+        }
+    }
+
+    pub fn timeout_factor(&self) -> Result<f64> {
+        match self.timeout_factor {
+            factor if factor == 0.0 || factor >= 1.0 => Ok(factor),
+            factor => Err(anyhow!(
+                "Invalid timeout factor {} (must be 0 or at least 1)",
+                factor
+            )),
+        }
+    }
+
+    pub fn log_level(&self) -> Result<tracing::Level> {
+        self.logging
+            .level
+            .parse()
+            .map_err(|_| anyhow!("Invalid log level '{}'", self.logging.level))
+    }
+
+    /// The USB device map to load: `usb_devices`, else `devices.toml` in the
+    /// manifest directory if there is one.
+    pub fn usb_devices_path(&self) -> Option<PathBuf> {
+        self.usb_devices.clone().or_else(|| {
+            let path = self.manifest_dir.as_ref()?.join("devices.toml");
+            path.exists().then_some(path)
+        })
+    }
+
+    /// Manifest paths configured per device, for [`crate::manifest::ManifestManager`].
+    /// Paired robots whose manifest has another device's ID use that
+    /// device's manifest file, unless `devices` says otherwise.
+    pub fn device_manifests(&self) -> HashMap<String, PathBuf> {
+        let paired = self
+            .paired
+            .iter()
+            .filter(|paired| paired.manifest != paired.id)
+            .filter_map(|paired| {
+                let path = self
+                    .manifest_dir
+                    .as_ref()?
+                    .join(format!("{}.json", paired.manifest));
+                Some((paired.id.clone(), path))
+            });
+        let configured = self
+            .devices
+            .iter()
+            .filter_map(|(id, device)| device.manifest.clone().map(|path| (id.clone(), path)));
+        paired.chain(configured).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_config() {
+        let config = Config::parse(
+            r#"
+            line = "/dev/ttyUSB0"
+            manifest_dir = "/home/pi/manifests"
+            port = 9000
+            baud = 57600
+
+            [auth]
+            token = "secret"
+
+            [logging]
+            level = "debug"
+
+            [devices.blinker]
+            manifest = "/opt/blinker.json"
+
+            [error_codes]
+            timeout = -32010
+
+            [[schedules]]
+            name = "battery"
+            function = "getBatteryVoltage"
+            every = "1m"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.line.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.baud, 57600);
+        assert_eq!(config.auth.token.as_deref(), Some("secret"));
+        assert_eq!(config.log_level().unwrap(), tracing::Level::DEBUG);
+        assert_eq!(
+            config.device_manifests().get("blinker"),
+            Some(&PathBuf::from("/opt/blinker.json"))
+        );
+        assert_eq!(config.error_codes[&Kind::Timeout], -32010);
+        assert_eq!(config.schedules[0].function, "getBatteryVoltage");
+        assert!(config.schedules[0]
+            .arguments
+            .as_object()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cli_overrides_config() {
+        let mut config = Config::parse("line = \"/dev/ttyUSB0\"\nport = 9000\n").unwrap();
+        config.apply_cli(CliOverrides {
+            line: Some("/dev/ttyACM0".to_string()),
+            baud: Some(9600),
+            ..Default::default()
+        });
+
+        assert_eq!(config.line().unwrap(), "/dev/ttyACM0");
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.baud, 9600);
+    }
+
+    #[test]
+    fn test_fleet_robots() {
+        let config = Config::parse(
+            "[[robots]]\nname = \"arm-1\"\nline = \"/dev/ttyUSB0\"\n\n\
+             [[robots]]\nname = \"rover\"\nline = \"tcp://10.0.0.5:3333\"\nbaud = 9600\n\n\
+             [[remotes]]\nname = \"pi-2\"\nurl = \"http://pi-2.local:8080\"\n",
+        )
+        .unwrap();
+        assert!(config.is_fleet().unwrap());
+        assert_eq!(config.robots[1].baud, Some(9600));
+        assert_eq!(config.remotes[0].token, None);
+
+        let mut config = Config {
+            robots: vec![config.robots[0].clone(), config.robots[0].clone()],
+            ..Config::default()
+        };
+        assert!(config.is_fleet().is_err());
+        config.robots[1].name = "arm__2".to_string();
+        assert!(config.is_fleet().is_err());
+        config.robots.pop();
+        assert!(config.is_fleet().unwrap());
+
+        // A line selects single-robot mode
+        config.line = Some("/dev/ttyACM0".to_string());
+        assert!(!config.is_fleet().unwrap());
+        assert!(!Config::default().is_fleet().unwrap());
+    }
+
+    #[test]
+    fn test_timeout_factor_below_one_rejected() {
+        let factor = |factor: &str| {
+            Config::parse(&format!("timeout_factor = {factor}\n"))
+                .unwrap()
+                .timeout_factor()
+        };
+        assert_eq!(factor("0.0").unwrap(), 0.0);
+        assert_eq!(factor("1.5").unwrap(), 1.5);
+        assert!(factor("0.5").is_err());
+        assert!(factor("-2.0").is_err());
+        assert!(factor("nan").is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        assert!(Config::parse("lines = \"/dev/ttyUSB0\"").is_err());
+        assert!(Config::parse("[error_codes]\ntimeot = -32010").is_err());
+        for code in ["0", "-32700", "-32600", "-32601"] {
+            assert!(Config::parse(&format!("[error_codes]\ntimeout = {code}")).is_err());
+        }
+    }
+
+    #[test]
+    fn test_paired_manifest_stays_in_manifest_dir() {
+        let paired = |manifest: &str| {
+            format!("[[paired]]\nid = \"arm\"\naddress = \"10.0.0.2:3333\"\nmanifest = \"{manifest}\"\n")
+        };
+        assert!(Config::parse(&paired("arm-v2")).is_ok());
+        assert!(Config::parse(&paired("../../etc/x")).is_err());
+    }
+}
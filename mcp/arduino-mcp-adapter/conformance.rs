@@ -0,0 +1,43 @@
+//! The golden frames in `testdata/protocol-vectors.json`, for the adapter's
+//! and the simulator's tests. Each crate checks its own CRC and SLIP code
+//! with [`check_framing`]; the firmware is checked against the same vectors
+//! by `make test-firmware`.
+
+use serde_json::Value;
+
+use crate::hex::parse_hex;
+
+pub fn vectors(section: &str) -> Vec<Value> {
+    let vectors: Value =
+        serde_json::from_str(include_str!("../testdata/protocol-vectors.json")).unwrap();
+    vectors[section].as_array().unwrap().clone()
+}
+
+pub fn hex(value: &Value) -> Vec<u8> {
+    parse_hex(value.as_str().unwrap()).unwrap()
+}
+
+/// Check a CRC-8 and a SLIP encoder and decoder against the `crc8`, `slip`
+/// and `slip_streams` vectors. `decode` returns every frame in the bytes.
+pub fn check_framing(
+    crc8: impl Fn(&[u8]) -> u8,
+    encode: impl Fn(&[u8]) -> Vec<u8>,
+    decode: impl Fn(&[u8]) -> Vec<Vec<u8>>,
+) {
+    for v in vectors("crc8") {
+        assert_eq!(vec![crc8(&hex(&v["data"]))], hex(&v["crc"]), "{}", v);
+    }
+    for v in vectors("slip") {
+        assert_eq!(encode(&hex(&v["frame"])), hex(&v["wire"]), "{}", v["name"]);
+        assert_eq!(
+            decode(&hex(&v["wire"])),
+            [hex(&v["frame"])],
+            "{}",
+            v["name"]
+        );
+    }
+    for v in vectors("slip_streams") {
+        let frames: Vec<Vec<u8>> = v["frames"].as_array().unwrap().iter().map(hex).collect();
+        assert_eq!(decode(&hex(&v["wire"])), frames, "{}", v["name"]);
+    }
+}
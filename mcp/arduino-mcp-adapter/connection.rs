@@ -1,107 +1,364 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use mcp_client::hex::to_hex;
 use serde_json::Value;
-use serialport::SerialPort;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tracing::{debug, error, info, warn};
-
-use crate::manifest::Function;
-use crate::protocol::{decode_response_by_type, CommandEncoder, ResponseDecoder};
-use crate::slip::{slip_encode, SlipDecoder};
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum RobotState {
-    Disconnected,  // No serial device found
-    Connecting,    // Found device, trying to connect
-    Connected,     // Connected but not identified
-    Initializing,  // Getting device ID
-    Ready(String), // Ready with device ID
-    Error(String), // Error state with description
-}
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Notify, RwLock};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-impl RobotState {
-    pub fn is_ready(&self) -> bool {
-        matches!(self, RobotState::Ready(_))
-    }
+use crate::battery::{self, BatteryMonitor, BatteryStatus};
+use crate::bootloader::BootloaderDetected;
+use crate::errors::{AdapterError, Kind};
+use crate::events::DeviceEvents;
+use crate::frame_log::{FrameLog, FRAME_LOG_CAPACITY};
+use crate::geofence::{Geofence, Pose};
+use crate::governor::Governor;
+use crate::latency::{LatencySnapshot, LatencyTracker, DEFAULT_TIMEOUT_FACTOR};
+use crate::manifest::{self, Ack, Function, Manifest, ManifestManager, ManifestNotFound};
+use crate::pcap::PcapWriter;
+use crate::pipeline::{Pipeline, RESPONSE_TIMEOUT};
+use crate::port_actor::{PortActor, Priority};
+use crate::protocol::{
+    crc8, decode_response_by_type, device_error, CommandEncoder, DeviceError, Fault, ProtocolInfo,
+    ResponseDecoder, DEVICE_ID_TAG, MANIFEST_VERSION_TAG, PROTOCOL_VERSION_TAG,
+};
+use crate::reliable::{LinkSnapshot, LinkStats};
+use crate::robot_state::{Event, RobotState};
+use crate::sequence::{self, Step, StepResult};
+use crate::stats::{ErrorKind, Stats, StatsSnapshot};
+use crate::transport::{connector_for, Connector, Transport};
 
-    pub fn device_id(&self) -> Option<&str> {
-        match self {
-            RobotState::Ready(id) => Some(id),
-            _ => None,
-        }
-    }
+/// How long a command answered with a busy error is retried by default.
+pub const DEFAULT_BUSY_WAIT: Duration = Duration::from_secs(5);
 
-    pub fn error_message(&self) -> String {
-        match self {
-            RobotState::Disconnected => "Robot not connected - check USB connection".to_string(),
-            RobotState::Connecting => "Robot is connecting - please wait".to_string(),
-            RobotState::Connected => "Robot connected but not initialized".to_string(),
-            RobotState::Initializing => "Robot is initializing - please wait".to_string(),
-            RobotState::Ready(_) => "Robot is ready".to_string(),
-            RobotState::Error(msg) => format!("Robot error: {}", msg),
-        }
-    }
-}
+/// First pause before resending a command the device was too busy for;
+/// doubled on each retry up to [`BUSY_RETRY_MAX_DELAY`].
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(50);
+const BUSY_RETRY_MAX_DELAY: Duration = Duration::from_millis(500);
+
+/// How often the manifest's `busy` function is called while waiting for a
+/// motion to end.
+const BUSY_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
+/// Handshakes answered by the bootloader before giving up on the device.
+const BOOTLOADER_RETRIES: u32 = 3;
+/// How long the handshake and `deviceId()` may take once the device has
+/// booted, bootloader retries included.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the first `deviceId()` sent while the device boots waits for
+/// its answer; later ones wait twice as long as the one before.
+const BOOT_POLL_TIMEOUT: Duration = Duration::from_millis(50);
+const MAX_BOOT_POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Owns the device connection. Port I/O happens on a [`PortActor`]; the
+/// std locks here guard plain data and are never held across an `.await`.
 pub struct ConnectionManager {
-    line_path: String,
-    baud_rate: u32,
-    state: Arc<Mutex<RobotState>>,
-    port: Arc<Mutex<Option<Box<dyn SerialPort>>>>,
+    connector: Arc<dyn Connector>,
+    state: Mutex<RobotState>,
+    state_events: broadcast::Sender<RobotState>,
+    port: Mutex<Option<Arc<PortActor>>>,
+    /// Events the device sends, such as the end of a motion
+    events: Arc<DeviceEvents>,
+    manifest_manager: Option<Arc<ManifestManager>>,
+    /// Device ID the port's USB descriptors map to, if any
+    expected_device: Option<String>,
+    pipeline: Arc<Pipeline>,
+    frame_log: Arc<FrameLog>,
+    capture: Option<Arc<PcapWriter>>,
+    last_response: Mutex<Option<Instant>>,
+    protocol: Mutex<Option<ProtocolInfo>>,
+    stats: Stats,
+    /// Round trips per function, which set their response deadlines
+    latency: LatencyTracker,
+    link: Arc<LinkStats>,
+    /// Total time spent retrying a command the device answers busy to
+    busy_wait: Duration,
+    governor: Governor,
+    geofence: Option<Geofence>,
+    battery: BatteryMonitor,
+    /// Log function calls instead of sending them
+    dry_run: bool,
+    /// Held shared by each call and exclusively by a sequence, so nothing
+    /// else reaches the device in the middle of one
+    turn: RwLock<()>,
+    /// Signalled when the device needs reconnecting before the next poll
+    recover: Notify,
+    /// Set when a response arrives garbled, so the connection monitor
+    /// checks the device is still the one it identified
+    garbled: AtomicBool,
+    /// Why the device was resynced, each time it restarts mid-session
+    resets: broadcast::Sender<String>,
+    /// Whether the firmware of a device with no manifest file reports the
+    /// manifest version it was built from, and which
+    firmware_manifest: Mutex<Option<String>>,
 }
 
 impl ConnectionManager {
-    pub fn new(line_path: String, baud_rate: u32) -> Self {
+    /// Connect over `line`: a serial device path, or a URI whose scheme
+    /// picks the transport, such as `tcp://host:port`.
+    pub fn new(line: &str, baud_rate: u32) -> Result<Self> {
+        Ok(Self::with_connector(connector_for(line, baud_rate)?))
+    }
+
+    /// Connect through a connector built by the caller.
+    pub fn with_connector(connector: Box<dyn Connector>) -> Self {
         Self {
-            line_path,
-            baud_rate,
-            state: Arc::new(Mutex::new(RobotState::Disconnected)),
-            port: Arc::new(Mutex::new(None)),
+            connector: Arc::from(connector),
+            state: Mutex::new(RobotState::Disconnected),
+            state_events: broadcast::channel(16).0,
+            port: Mutex::new(None),
+            events: Arc::new(DeviceEvents::new()),
+            manifest_manager: None,
+            expected_device: None,
+            pipeline: Arc::new(Pipeline::new(1)),
+            frame_log: Arc::new(FrameLog::new(FRAME_LOG_CAPACITY)),
+            capture: None,
+            last_response: Mutex::new(None),
+            protocol: Mutex::new(None),
+            stats: Stats::new(),
+            latency: LatencyTracker::new(DEFAULT_TIMEOUT_FACTOR),
+            link: Arc::new(LinkStats::default()),
+            busy_wait: DEFAULT_BUSY_WAIT,
+            governor: Governor::default(),
+            geofence: None,
+            battery: BatteryMonitor::new(false),
+            dry_run: false,
+            turn: RwLock::new(()),
+            recover: Notify::new(),
+            garbled: AtomicBool::new(false),
+            resets: broadcast::channel(16).0,
+            firmware_manifest: Mutex::new(None),
         }
     }
 
+    /// The device the port should hold, from the USB device map; a
+    /// different `deviceId()` answer is logged and wins.
+    pub fn with_expected_device(mut self, device_id: String) -> Self {
+        self.expected_device = Some(device_id);
+        self
+    }
+
+    /// Record all serial traffic to a pcapng capture.
+    pub fn with_capture(mut self, capture: PcapWriter) -> Self {
+        self.capture = Some(Arc::new(capture));
+        self
+    }
+
+    /// Allow up to `depth` commands on the wire at once. Any depth above 1
+    /// switches to sequence-numbered framing, which the firmware must support.
+    pub fn with_pipeline_depth(mut self, depth: usize) -> Self {
+        self.pipeline = Arc::new(Pipeline::new(depth));
+        self
+    }
+
+    /// Keep resending a command the device answers busy to for up to
+    /// `wait`; zero fails it on the first busy answer.
+    pub fn with_busy_wait(mut self, wait: Duration) -> Self {
+        self.busy_wait = wait;
+        self
+    }
+
+    /// Give up on a response after `factor` times the function's p99 round
+    /// trip; 0 keeps the fixed timeout.
+    pub fn with_timeout_factor(mut self, factor: f64) -> Self {
+        self.latency = LatencyTracker::new(factor);
+        self
+    }
+
+    /// Keep manifest function calls within the governor's limits.
+    pub fn with_governor(mut self, governor: Governor) -> Self {
+        self.governor = governor;
+        self
+    }
+
+    /// Refuse drive commands the manifest's odometry says would leave the
+    /// geofence.
+    pub fn with_geofence(mut self, geofence: Geofence) -> Self {
+        self.geofence = Some(geofence);
+        self
+    }
+
+    /// Refuse calls that change the robot while its battery is critical.
+    pub fn with_battery_lockout(mut self, lockout: bool) -> Self {
+        self.battery = BatteryMonitor::new(lockout);
+        self
+    }
+
+    /// Validate and encode function calls as usual, then log the frame and
+    /// answer with a made-up success instead of sending it.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Resolve manifest lifecycle hooks (`on_connect`, `on_disconnect`,
+    /// `watchdog`) for the identified device.
+    pub fn with_manifest_manager(mut self, manifest_manager: Arc<ManifestManager>) -> Self {
+        self.manifest_manager = Some(manifest_manager);
+        self
+    }
+
+    /// Receive every state transition from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<RobotState> {
+        self.state_events.subscribe()
+    }
+
+    /// Receive the reason for every mid-session device reset from now on.
+    pub fn subscribe_resets(&self) -> broadcast::Receiver<String> {
+        self.resets.subscribe()
+    }
+
+    /// Most recent frames sent and received, for `/debug/frames`.
+    pub fn frame_log(&self) -> &FrameLog {
+        &self.frame_log
+    }
+
+    /// Whether a serial port is currently open.
+    pub fn is_port_open(&self) -> bool {
+        self.port.lock().unwrap().is_some()
+    }
+
+    /// Time since the device last answered a command.
+    pub fn last_response_age(&self) -> Option<Duration> {
+        self.last_response.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Protocol the connected firmware speaks, once the handshake is done.
+    pub fn protocol(&self) -> Option<ProtocolInfo> {
+        self.protocol.lock().unwrap().clone()
+    }
+
+    /// Pose last read for the geofence.
+    pub fn pose(&self) -> Option<Pose> {
+        self.geofence.as_ref()?.pose()
+    }
+
+    /// Call and error counters for `/status`.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot(
+            self.frame_log.crc_errors(),
+            self.frame_log.slip().snapshot(),
+        )
+    }
+
+    /// Commands queued for the device, and how long the oldest has waited.
+    pub fn queue(&self) -> (usize, Option<Duration>) {
+        self.stats.queue()
+    }
+
+    /// Round trips and response deadlines by function name, for `/status`.
+    pub fn latency(&self) -> BTreeMap<String, LatencySnapshot> {
+        let manifest = self.current_manifest();
+        self.latency
+            .snapshot()
+            .into_iter()
+            .map(|(tag, snapshot)| {
+                let name = match tag {
+                    DEVICE_ID_TAG => Some("deviceId".to_string()),
+                    PROTOCOL_VERSION_TAG => Some("getProtocolVersion".to_string()),
+                    MANIFEST_VERSION_TAG => Some("getManifestVersion".to_string()),
+                    _ => manifest
+                        .iter()
+                        .flat_map(|m| &m.functions)
+                        .find(|f| f.tag == tag)
+                        .map(|f| f.name.clone()),
+                };
+                (name.unwrap_or_else(|| format!("tag {}", tag)), snapshot)
+            })
+            .collect()
+    }
+
+    /// Reliable link layer counters, for `/status`.
+    pub fn link(&self) -> LinkSnapshot {
+        self.link.snapshot()
+    }
+
     pub fn get_state(&self) -> RobotState {
         self.state.lock().unwrap().clone()
     }
 
-    pub fn check_and_update_connection(&self) -> Result<()> {
+    /// Wait until a call finds the device reset or garbled, or the device
+    /// reports a reset or a fault, so the connection monitor can act
+    /// without waiting for its next poll.
+    pub async fn recovery_requested(&self) {
+        tokio::select! {
+            _ = self.recover.notified() => {}
+            _ = self.events.reset_reported() => {}
+            _ = self.events.fault_reported() => {}
+        }
+    }
+
+    pub async fn check_and_update_connection(&self) -> Result<()> {
         let current_state = self.get_state();
 
         // Check if serial device exists
-        if !Path::new(&self.line_path).exists() {
+        if !self.connector.is_present() {
             if !matches!(current_state, RobotState::Disconnected) {
-                warn!("Serial device {} disappeared", self.line_path);
-                self.set_state(RobotState::Disconnected);
-                *self.port.lock().unwrap() = None;
+                warn!("Serial device {} disappeared", self.connector.name());
+                self.transition(Event::DeviceGone);
+                self.close_port();
             }
             return Ok(());
         }
 
+        // Taken whatever the state, so a stale report can't wake the
+        // monitor again
+        let reset = self.events.take_reset();
+        let identified = matches!(
+            current_state,
+            RobotState::Ready(_) | RobotState::VersionMismatch { .. } | RobotState::Fault { .. }
+        );
+        if reset && identified {
+            self.resync("Device reported a reset").await;
+            return Ok(());
+        }
+        self.latch_fault();
+        if self.garbled.swap(false, Ordering::Relaxed) {
+            if let Some(device_id) = current_state.device_id() {
+                self.verify_device(device_id).await;
+                return Ok(());
+            }
+        }
+
         match current_state {
             RobotState::Disconnected => {
                 info!(
                     "Serial device {} found, attempting connection",
-                    self.line_path
+                    self.connector.name()
                 );
-                self.set_state(RobotState::Connecting);
-                self.attempt_connection()?;
+                self.transition(Event::DeviceAppeared);
+                self.attempt_connection().await?;
             }
             RobotState::Error(_) => {
                 // Retry connection on error
                 info!("Retrying connection after error");
-                self.set_state(RobotState::Connecting);
-                self.attempt_connection()?;
+                self.transition(Event::DeviceAppeared);
+                self.attempt_connection().await?;
+            }
+            RobotState::VersionMismatch { device_id, .. }
+                if self
+                    .manifest_manager
+                    .as_ref()
+                    .is_some_and(|m| m.refresh_if_changed(&device_id)) =>
+            {
+                // The manifest was edited, so it may match the firmware now
+                self.identify(device_id).await;
             }
             _ => {
                 // For other states, verify connection is still valid
-                if let Some(port) = self.port.lock().unwrap().as_mut() {
+                let lost = match self.port() {
                     // Try a simple write to check if port is still valid
-                    if port.write(&[]).is_err() {
-                        warn!("Serial port connection lost");
-                        self.set_state(RobotState::Disconnected);
-                        *self.port.lock().unwrap() = None;
-                    }
+                    Some(port) => !port.probe().await,
+                    None => false,
+                };
+                if lost {
+                    warn!("Serial port connection lost");
+                    self.transition(Event::Closed);
+                    self.close_port();
                 }
             }
         }
@@ -109,36 +366,40 @@ impl ConnectionManager {
         Ok(())
     }
 
-    fn attempt_connection(&self) -> Result<()> {
-        match serialport::new(&self.line_path, self.baud_rate)
-            .timeout(Duration::from_millis(1000))
-            .open()
-        {
+    async fn attempt_connection(&self) -> Result<()> {
+        // Opening may block for seconds, e.g. while a Bluetooth scan runs
+        let connector = Arc::clone(&self.connector);
+        let opened = tokio::task::spawn_blocking(move || connector.open())
+            .await
+            .unwrap_or_else(|e| Err(anyhow!("Opening the port panicked: {}", e)));
+        match opened {
             Ok(port) => {
-                info!("Successfully opened serial port {}", self.line_path);
-                *self.port.lock().unwrap() = Some(port);
-                self.set_state(RobotState::Connected);
+                info!("Successfully opened serial port {}", self.connector.name());
+                self.stats.record_reconnect();
+                let reader_port = match self.pipeline.is_enabled() {
+                    true => Some(port.try_clone_transport()?),
+                    false => None,
+                };
+                let actor = PortActor::spawn(
+                    port,
+                    Arc::clone(&self.frame_log),
+                    self.capture.clone(),
+                    Arc::clone(&self.events),
+                    Arc::clone(&self.link),
+                );
+                self.events.motion_stopped();
+                if let Some(previous) = self.port.lock().unwrap().replace(Arc::new(actor)) {
+                    previous.close();
+                }
+                self.transition(Event::PortOpened);
 
                 // Start initialization process
-                self.initialize_device()?;
+                self.initialize_device(reader_port).await?;
             }
             Err(e) => {
-                let error_msg = match e.kind() {
-                    serialport::ErrorKind::NoDevice => "Device not found".to_string(),
-                    serialport::ErrorKind::InvalidInput => "Invalid device path".to_string(),
-                    serialport::ErrorKind::Unknown => {
-                        if e.to_string().contains("busy") || e.to_string().contains("in use") {
-                            "Serial port is busy - close other applications using this port"
-                                .to_string()
-                        } else {
-                            format!("Connection failed: {}", e)
-                        }
-                    }
-                    _ => format!("Serial port error: {}", e),
-                };
-
+                let error_msg = e.to_string();
                 error!("Failed to open serial port: {}", error_msg);
-                self.set_state(RobotState::Error(error_msg));
+                self.transition(Event::OpenFailed(error_msg));
                 return Err(anyhow!("Failed to connect"));
             }
         }
@@ -146,22 +407,165 @@ impl ConnectionManager {
         Ok(())
     }
 
-    fn initialize_device(&self) -> Result<()> {
-        self.set_state(RobotState::Initializing);
+    /// After a garbled response, ask the device for its ID again. One that
+    /// doesn't answer, or answers with another ID, has restarted or been
+    /// swapped since it was identified.
+    async fn verify_device(&self, device_id: &str) {
+        let answer = {
+            let _turn = self.turn.write().await;
+            if let Some(port) = self.port() {
+                port.discard_input().await;
+            }
+            self.get_device_id().await
+        };
+        match answer {
+            Ok(id) if id == device_id => debug!("Device still answers as '{}'", id),
+            Ok(id) => {
+                self.resync(&format!("Device '{}' now answers as '{}'", device_id, id))
+                    .await
+            }
+            Err(e) => {
+                self.resync(&format!(
+                    "Device '{}' didn't answer deviceId() after a garbled response: {}",
+                    device_id, e
+                ))
+                .await
+            }
+        }
+    }
+
+    /// Bring a device that restarted mid-session back to a known state:
+    /// tell clients, handshake and identify it again, and rerun
+    /// `on_connect`, rather than carry on as if nothing had happened.
+    async fn resync(&self, reason: &str) {
+        warn!("{}, resynchronizing", reason);
+        self.stats.record_reset();
+        // Nobody subscribed is fine
+        let _ = self.resets.send(reason.to_string());
+        self.events.motion_stopped();
+        // The firmware started over, so a fault it reported before is void
+        self.events.take_fault();
+
+        if self.pipeline.is_enabled() {
+            // The pipeline reader holds the port's read side, so start over
+            // on a fresh connection
+            self.close_port();
+            self.transition(Event::Reset { reopen: true });
+            if let Err(e) = self.attempt_connection().await {
+                warn!("Resynchronizing failed: {}", e);
+            }
+            return;
+        }
+
+        // New calls are refused from here; wait for those under way
+        self.transition(Event::Reset { reopen: false });
+        drop(self.turn.write().await);
+        if let Some(port) = self.port() {
+            port.discard_input().await;
+        }
+        if let Err(e) = self.initialize_device(None).await {
+            warn!("Resynchronizing failed: {}", e);
+        }
+    }
+
+    /// Handshake and identify the device. `reader_port` starts the pipeline
+    /// reader once the handshake, which is always unsequenced, is done.
+    async fn initialize_device(&self, reader_port: Option<Box<dyn Transport>>) -> Result<()> {
+        self.transition(Event::HandshakeStarted);
+
+        // The handshake is always sent plain
+        if let Some(port) = self.port() {
+            port.set_reliable(false);
+        }
 
-        // Wait for Arduino to initialize
-        info!("Waiting 3 seconds for Arduino initialization...");
-        std::thread::sleep(Duration::from_secs(3));
+        let boot_timeout = self.connector.boot_timeout();
+        if !boot_timeout.is_zero() {
+            if let Err(e) = self.await_boot(boot_timeout).await {
+                let error_msg = format!("Device did not start: {}", e);
+                error!("{}", error_msg);
+                self.transition(Event::InitFailed(error_msg));
+                return Err(e);
+            }
+        }
+        // Firmware that never answers would otherwise hold the connection
+        // in Initializing for good
+        let deadline = tokio::time::Instant::now() + HANDSHAKE_TIMEOUT;
+        let timed_out = || {
+            anyhow::Error::from(AdapterError::Timeout(format!(
+                "no answer within {:?}",
+                HANDSHAKE_TIMEOUT
+            )))
+        };
+        let mut attempts = 0;
+        let negotiate = async {
+            loop {
+                match self.negotiate_protocol().await {
+                    Err(e) if e.is::<BootloaderDetected>() && attempts < BOOTLOADER_RETRIES => {
+                        attempts += 1;
+                        let window = self.connector.bootloader_window();
+                        warn!("{}; waiting {:?} for the firmware to start", e, window);
+                        tokio::time::sleep(window).await;
+                    }
+                    result => break result,
+                }
+            }
+        };
+        let negotiated = tokio::time::timeout_at(deadline, negotiate)
+            .await
+            .unwrap_or_else(|_| Err(timed_out()));
+        match negotiated {
+            Ok(protocol) => {
+                info!(
+                    "Firmware protocol v{} (max frame {} bytes)",
+                    protocol.version, protocol.max_frame_size
+                );
+                if let Some(port) = self.port() {
+                    port.set_chunked(protocol.chunked);
+                    port.set_reliable(protocol.reliable);
+                    // The pipeline reader watches for them instead
+                    port.set_events(protocol.events && reader_port.is_none());
+                }
+                if let Some(reader_port) = reader_port {
+                    self.pipeline.start_reader(
+                        reader_port,
+                        Arc::clone(&self.frame_log),
+                        self.capture.clone(),
+                        protocol.chunked,
+                        protocol.events.then(|| Arc::clone(&self.events)),
+                    );
+                }
+                *self.protocol.lock().unwrap() = Some(protocol);
+            }
+            Err(e) => {
+                let error_msg = format!("Protocol handshake failed: {}", e);
+                error!("{}", error_msg);
+                self.transition(Event::InitFailed(error_msg));
+                return Err(e);
+            }
+        }
 
-        match self.get_device_id() {
+        let device_id = tokio::time::timeout_at(deadline, self.get_device_id())
+            .await
+            .unwrap_or_else(|_| Err(timed_out()));
+        match device_id {
             Ok(device_id) => {
                 info!("Device initialized with ID: {}", device_id);
-                self.set_state(RobotState::Ready(device_id));
+                if let Some(expected) = self
+                    .expected_device
+                    .as_ref()
+                    .filter(|expected| **expected != device_id)
+                {
+                    warn!(
+                        "USB device map names this port '{}' but the device reports '{}'",
+                        expected, device_id
+                    );
+                }
+                self.identify(device_id).await;
             }
             Err(e) => {
                 let error_msg = format!("Failed to get device ID: {}", e);
                 error!("{}", error_msg);
-                self.set_state(RobotState::Error(error_msg));
+                self.transition(Event::InitFailed(error_msg));
                 return Err(e);
             }
         }
@@ -169,76 +573,384 @@ impl ConnectionManager {
         Ok(())
     }
 
-    fn get_device_id(&self) -> Result<String> {
-        let mut port_guard = self.port.lock().unwrap();
-        let port = port_guard
-            .as_mut()
-            .ok_or_else(|| anyhow!("No serial port available"))?;
+    /// Ask for the device ID until the device answers, instead of sleeping
+    /// through the slowest boot. Each try waits twice as long as the last
+    /// for an answer, up to `timeout` after opening.
+    async fn await_boot(&self, timeout: Duration) -> Result<()> {
+        let port = self
+            .port()
+            .ok_or_else(|| AdapterError::SerialIo("No serial port available".to_string()))?;
+        info!("Waiting up to {:?} for the device to start", timeout);
+        let started = Instant::now();
+        let mut wait = BOOT_POLL_TIMEOUT;
+        loop {
+            match port.poll(DEVICE_ID_TAG, wait).await {
+                Ok(_) => break,
+                Err(e) if port.has_failed() => return Err(e),
+                Err(e) if e.is::<BootloaderDetected>() => {
+                    // Commands keep the bootloader waiting for an upload
+                    debug!("{}; waiting for the firmware to start", e);
+                    tokio::time::sleep(self.connector.bootloader_window()).await;
+                }
+                Err(e) => debug!("No answer yet: {}", e),
+            }
+            if started.elapsed() >= timeout {
+                return Err(AdapterError::Timeout(format!(
+                    "no answer within {:?} of opening",
+                    timeout
+                ))
+                .into());
+            }
+            wait = (wait * 2).min(MAX_BOOT_POLL_TIMEOUT);
+        }
+        info!(
+            "Device answered {:.1}s after opening",
+            started.elapsed().as_secs_f32()
+        );
+
+        // A late answer to an earlier try may have been taken for this one's
+        tokio::time::sleep(BOOT_POLL_TIMEOUT).await;
+        port.discard_input().await;
+        Ok(())
+    }
+
+    /// Ask the firmware which protocol version it speaks. Firmware that
+    /// answers with an error frame predates the command and is taken as v1.
+    async fn negotiate_protocol(&self) -> Result<ProtocolInfo> {
+        let port = self
+            .port()
+            .ok_or_else(|| AdapterError::SerialIo("No serial port available".to_string()))?;
+        // A two-byte frame can't be a sequenced command, so this is sent the
+        // same way whatever framing the firmware uses
+        let data = port
+            .transact(
+                None,
+                Priority::Normal,
+                PROTOCOL_VERSION_TAG,
+                Bytes::new(),
+                RESPONSE_TIMEOUT,
+            )
+            .await?;
+        if data.first() == Some(&0xFF) {
+            info!("Firmware has no getProtocolVersion, assuming protocol v1");
+            return Ok(ProtocolInfo::legacy(self.pipeline.is_enabled()));
+        }
+
+        let protocol = ProtocolInfo::decode(&data)?;
+        match (protocol.sequence_numbers, self.pipeline.is_enabled()) {
+            (false, true) => Err(anyhow!(
+                "firmware does not support sequence numbers - run with --pipeline-depth 1"
+            )),
+            (true, false) => Err(anyhow!(
+                "firmware expects sequence numbers - run with --pipeline-depth 2 or more"
+            )),
+            (_, true) if protocol.reliable => Err(anyhow!(
+                "firmware uses the reliable link layer - run with --pipeline-depth 1"
+            )),
+            _ => Ok(protocol),
+        }
+    }
+
+    /// Become ready as `device_id`, unless its firmware was built from a
+    /// different manifest version than the one loaded.
+    async fn identify(&self, device_id: String) {
+        let missing = self.manifest_manager.as_ref().is_some_and(|manager| {
+            matches!(manager.get_manifest(&device_id), Err(e) if e.is::<ManifestNotFound>())
+        });
+        let firmware_manifest = match missing {
+            true => {
+                warn!(
+                    "No manifest for device '{}', offering generic tools only",
+                    device_id
+                );
+                self.read_manifest_version().await
+            }
+            false => None,
+        };
+        *self.firmware_manifest.lock().unwrap() = firmware_manifest;
+
+        if let Some((firmware, manifest)) = self.manifest_version_mismatch(&device_id).await {
+            let event = Event::IdMismatch {
+                device_id,
+                firmware,
+                manifest,
+            };
+            if self.transition(event) {
+                warn!("{}", self.get_state().error_message());
+            }
+            return;
+        }
+
+        if !self.transition(Event::IdOk(device_id)) {
+            return;
+        }
+        if let Some(func) = self.lifecycle_function("on_connect", |m| m.on_connect.as_ref()) {
+            info!("Sending on_connect command '{}'", func.name);
+            if let Err(e) = self
+                .execute_function(&func, &Value::Object(Default::default()))
+                .await
+            {
+                warn!("on_connect command '{}' failed: {}", func.name, e);
+            }
+        }
+    }
+
+    /// Firmware and manifest versions, if the firmware reports one and it
+    /// differs from the manifest's.
+    async fn manifest_version_mismatch(&self, device_id: &str) -> Option<(String, String)> {
+        let manifest = self
+            .manifest_manager
+            .as_ref()?
+            .get_manifest(device_id)
+            .ok()?;
+        let firmware = self.read_manifest_version().await?;
+        (firmware != manifest.version).then_some((firmware, manifest.version))
+    }
 
-        // Send deviceId command (tag=0)
-        self.send_command(&mut **port, 0)?;
+    /// The manifest version the firmware was built from, if it reports one.
+    async fn read_manifest_version(&self) -> Option<String> {
+        let data = match self
+            .transact(MANIFEST_VERSION_TAG, Bytes::new(), Priority::Normal)
+            .await
+        {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to read firmware manifest version: {}", e);
+                return None;
+            }
+        };
+        if data.first() == Some(&0xFF) {
+            debug!("Firmware does not report its manifest version");
+            return None;
+        }
+        ResponseDecoder::new(&data).read_cstring().ok()
+    }
 
-        // Read device ID response
-        self.read_response(&mut **port)
+    /// The manifest version reported by the firmware of a device that has
+    /// no manifest file, if it reports one.
+    pub fn firmware_manifest_version(&self) -> Option<String> {
+        self.firmware_manifest.lock().unwrap().clone()
     }
 
-    pub fn execute_function(&self, func: &Function, arguments: &Value) -> Result<String> {
+    async fn get_device_id(&self) -> Result<String> {
+        let data = self
+            .transact(DEVICE_ID_TAG, Bytes::new(), Priority::Normal)
+            .await?;
+        ResponseDecoder::new(&data).read_cstring()
+    }
+
+    pub async fn execute_function(&self, func: &Function, arguments: &Value) -> Result<String> {
+        let _turn = self.turn.read().instrument(info_span!("queue_wait")).await;
+        self.execute_in_turn(func, arguments).await
+    }
+
+    /// Send `func` ahead of every queued command, without waiting for a
+    /// sequence to end. For safety commands only.
+    async fn execute_urgent(&self, func: &Function) -> Result<String> {
+        let span = info_span!("device_call", function = %func.name, urgent = true);
+        self.device_call(func, &Value::Object(Default::default()), Priority::Urgent)
+            .instrument(span)
+            .await
+    }
+
+    /// Send `args` to `tag` as they are and return the raw response data,
+    /// for trying out functions the manifest doesn't have yet. Error frames
+    /// are returned like any other response.
+    pub async fn execute_raw(&self, tag: u8, args: &[u8]) -> Result<Vec<u8>> {
+        if tag == PROTOCOL_VERSION_TAG {
+            return Err(anyhow!(
+                "Tag 0x{:02X} is reserved for the protocol handshake",
+                tag
+            ));
+        }
+        let _turn = self.turn.read().await;
+        self.latch_fault();
         let state = self.get_state();
+        if !state.accepts_calls() {
+            return Err(AdapterError::NotReady(state.error_message()).into());
+        }
+        if let RobotState::Fault { fault, .. } = state {
+            // There's no telling whether a raw command moves the robot
+            return Err(AdapterError::Fault(fault, format!("Tag {}", tag)).into());
+        }
 
-        if !state.is_ready() {
-            return Err(anyhow!("Robot not ready: {}", state.error_message()));
+        self.check_frame_size(format_args!("tag {}", tag), args.len())?;
+        if self.dry_run {
+            self.log_dry_run(&format!("tag {}", tag), tag, args);
+            return Ok(Vec::new());
         }
+        self.stats.record_call();
+        self.transact(tag, Bytes::copy_from_slice(args), Priority::Normal)
+            .await
+            .inspect_err(|e| self.record_transact_error(e))
+    }
 
-        let mut port_guard = self.port.lock().unwrap();
-        let port = port_guard
-            .as_mut()
-            .ok_or_else(|| anyhow!("No serial port available"))?;
+    /// Run `steps` in order with no other command sent in between, stopping
+    /// at the first one that fails.
+    pub async fn execute_sequence(&self, steps: &[Step]) -> Vec<StepResult> {
+        let _turn = self.turn.write().await;
+        self.run_steps(steps, true).await
+    }
 
-        // Encode and send command
-        if func.params.is_empty() {
-            self.send_command(&mut **port, func.tag)?;
-        } else {
-            let mut encoder = CommandEncoder::new();
+    /// Like [`Self::execute_sequence`], but other calls may run between
+    /// steps.
+    pub async fn execute_steps(&self, steps: &[Step]) -> Vec<StepResult> {
+        self.run_steps(steps, false).await
+    }
 
-            for param in &func.params {
-                let arg_value = &arguments[&param.name];
+    /// Wait up to `timeout` for the motion an immediately acknowledged call
+    /// started to end, as told by the device's motion-done event or, from
+    /// firmware without events, by polling the manifest's `busy` function.
+    /// Returns how long the wait took.
+    pub async fn await_motion(&self, timeout: Duration) -> Result<Duration> {
+        if self.dry_run {
+            // Nothing was sent, so nothing is moving
+            return Ok(Duration::ZERO);
+        }
+        let started = Instant::now();
+        if self.protocol().is_some_and(|p| p.events) {
+            if !self.events.wait_motion_done(timeout).await {
+                return Err(AdapterError::Timeout(format!(
+                    "Motion still running after {:?}",
+                    timeout
+                ))
+                .into());
+            }
+            return Ok(started.elapsed());
+        }
 
-                match param.param_type.as_str() {
-                    "i16" => {
-                        let value = arg_value.as_i64().unwrap() as i16;
-                        debug!("Encoding i16 parameter '{}': {}", param.name, value);
-                        encoder.write_i16(value);
-                    }
-                    "i32" => {
-                        let value = arg_value.as_i64().unwrap() as i32;
-                        debug!("Encoding i32 parameter '{}': {}", param.name, value);
-                        encoder.write_i32(value);
-                    }
-                    "CStr" => {
-                        let value = arg_value.as_str().unwrap();
-                        debug!("Encoding CStr parameter '{}': '{}'", param.name, value);
-                        encoder.write_cstring(value);
-                    }
-                    _ => {
-                        let value = arg_value.as_str().unwrap_or("");
-                        debug!(
-                            "Encoding unknown type '{}' as CStr: '{}'",
-                            param.param_type, value
-                        );
-                        encoder.write_cstring(value);
-                    }
-                }
+        let busy = self
+            .lifecycle_function("busy", |m| m.busy.as_ref())
+            .ok_or_else(|| {
+                anyhow!("The robot sends no motion events and its manifest names no busy function")
+            })?;
+        loop {
+            let reading = self
+                .execute_function(&busy, &Value::Object(Default::default()))
+                .await?;
+            if reading.trim() == "0" {
+                return Ok(started.elapsed());
+            }
+            if started.elapsed() + BUSY_POLL_INTERVAL > timeout {
+                return Err(AdapterError::Timeout(format!(
+                    "Motion still running after {:?}",
+                    timeout
+                ))
+                .into());
+            }
+            tokio::time::sleep(BUSY_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn run_steps(&self, steps: &[Step], in_turn: bool) -> Vec<StepResult> {
+        let mut results = Vec::new();
+        for (index, step) in steps.iter().enumerate() {
+            let Some(func) = &step.func else {
+                tokio::time::sleep(step.delay).await;
+                results.push(StepResult {
+                    name: sequence::WAIT.to_string(),
+                    result: Some(format!("Waited {} ms", step.delay.as_millis())),
+                    error: None,
+                });
+                continue;
+            };
+            let outcome = match in_turn {
+                true => self.execute_in_turn(func, &step.arguments).await,
+                false => self.execute_function(func, &step.arguments).await,
+            };
+            let (result, error) = match outcome {
+                Ok(result) => (Some(result), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let failed = error.is_some();
+            results.push(StepResult {
+                name: func.name.clone(),
+                result,
+                error,
+            });
+            if failed {
+                break;
+            }
+            if index + 1 < steps.len() {
+                tokio::time::sleep(step.delay).await;
+            }
+        }
+        results
+    }
+
+    async fn execute_in_turn(&self, func: &Function, arguments: &Value) -> Result<String> {
+        let span = info_span!("device_call", function = %func.name);
+        self.device_call(func, arguments, Priority::Normal)
+            .instrument(span)
+            .await
+    }
+
+    async fn device_call(
+        &self,
+        func: &Function,
+        arguments: &Value,
+        priority: Priority,
+    ) -> Result<String> {
+        self.latch_fault();
+        let state = self.get_state();
+
+        if !state.accepts_calls() {
+            return Err(AdapterError::NotReady(state.error_message()).into());
+        }
+
+        if let RobotState::Fault { fault, .. } = state {
+            let safe = func.is_read_only()
+                || self
+                    .current_manifest()
+                    .is_some_and(|m| m.is_safety_function(&func.name));
+            if !safe {
+                return Err(AdapterError::Fault(fault, format!("'{}'", func.name)).into());
             }
+        }
 
-            let args_data = encoder.finish();
-            self.send_command_with_args(&mut **port, func.tag, &args_data)?;
+        if self.battery.locked_out() {
+            if let Some(manifest) = self.current_manifest() {
+                self.battery.check(func, &manifest)?;
+            }
         }
 
-        // Read and decode response
-        let response_data = self.read_response_raw(&mut **port)?;
+        let arguments = manifest::coerce_arguments(func, arguments);
+        let arguments = self.governor.apply(&func.name, &arguments).await;
+        // Reading the pose would mean sending a command
+        if !self.dry_run {
+            self.check_geofence(func, &arguments).await?;
+        }
+
+        // Encode, send and wait for the response
+        let args_data = Self::encode_arguments(func, &arguments);
+        self.check_frame_size(format_args!("'{}'", func.name), args_data.len())?;
+        if self.dry_run {
+            let frame = self.log_dry_run(&format!("'{}'", func.name), func.tag, &args_data);
+            return Ok(format!("Dry run: not sent; the frame would be {}", frame));
+        }
+        self.stats.record_call();
+        let immediate = func.ack == Ack::Immediate;
+        if immediate {
+            // Before sending, as the motion may end before the response is in
+            self.events.motion_started();
+        }
+        let response_data = self
+            .send_retrying_busy(func, args_data, priority)
+            .await
+            .inspect_err(|_| {
+                if immediate {
+                    self.events.motion_stopped();
+                }
+            })?;
 
-        let response_text = if let Some(return_type) = &func.return_type {
-            decode_response_by_type(&response_data, return_type)?
+        let response_text = if func.ack == Ack::Immediate {
+            match self.current_manifest() {
+                Some(manifest) => format!("Action started; {}", manifest::running_hint(&manifest)),
+                None => "Action started".to_string(),
+            }
+        } else if let Some(return_type) = &func.return_type {
+            info_span!("decode")
+                .in_scope(|| decode_response_by_type(&response_data, return_type))?
         } else {
             "Command executed successfully".to_string()
         };
@@ -247,103 +959,442 @@ impl ConnectionManager {
         Ok(response_text)
     }
 
-    fn set_state(&self, new_state: RobotState) {
-        *self.state.lock().unwrap() = new_state;
+    /// Send a command, resending it while the device answers busy for up
+    /// to `busy_wait`, and return the response data.
+    async fn send_retrying_busy(
+        &self,
+        func: &Function,
+        args_data: Bytes,
+        priority: Priority,
+    ) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let mut delay = BUSY_RETRY_DELAY;
+        loop {
+            let data = self
+                .transact(func.tag, args_data.clone(), priority)
+                .await
+                .inspect_err(|e| self.record_transact_error(e))?;
+            match device_error(func.return_type.as_deref(), &data) {
+                None => return Ok(data),
+                // The firmware hasn't acted on it, so the command is safe to resend
+                Some(DeviceError::Busy) if started.elapsed() + delay <= self.busy_wait => {
+                    debug!("Device busy for '{}', retrying in {:?}", func.name, delay);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(BUSY_RETRY_MAX_DELAY);
+                }
+                Some(DeviceError::Busy) if !self.busy_wait.is_zero() => {
+                    warn!(
+                        "Device still busy for '{}' after {:?}",
+                        func.name,
+                        started.elapsed()
+                    );
+                    self.stats.record_error(ErrorKind::Dispatch);
+                    return Err(anyhow::Error::new(DeviceError::Busy).context(format!(
+                        "Robot still busy after retrying for {:.1}s",
+                        started.elapsed().as_secs_f32()
+                    )));
+                }
+                Some(error) => {
+                    warn!("Device answered '{}': {}", func.name, error);
+                    self.stats.record_error(ErrorKind::Dispatch);
+                    return Err(error.into());
+                }
+            }
+        }
     }
 
-    fn send_command(&self, port: &mut dyn SerialPort, tag: u8) -> Result<()> {
-        self.send_command_with_args(port, tag, &[])
+    /// Read the pose before a drive command the manifest's odometry names,
+    /// and refuse it if it would end outside the geofence.
+    async fn check_geofence(&self, func: &Function, arguments: &Value) -> Result<()> {
+        let Some(geofence) = &self.geofence else {
+            return Ok(());
+        };
+        let Some(manifest) = self.current_manifest() else {
+            return Ok(());
+        };
+        let Some(odometry) = &manifest.odometry else {
+            return Ok(());
+        };
+        let Some(param) = odometry.drive.get(&func.name) else {
+            return Ok(());
+        };
+        let distance = arguments[param].as_f64().ok_or_else(|| {
+            anyhow!(
+                "'{}' has no numeric '{}' parameter to check against the geofence",
+                func.name,
+                param
+            )
+        })?;
+        let pose_func = manifest
+            .functions
+            .iter()
+            .find(|f| f.name == odometry.pose)
+            .ok_or_else(|| anyhow!("Odometry function '{}' not found", odometry.pose))?;
+
+        let reading = Box::pin(self.execute_in_turn(pose_func, &Value::Object(Default::default())))
+            .await
+            .context("Couldn't read the pose for the geofence")?;
+        let pose = Pose::parse(&reading)?;
+        if let Err(violation) = geofence.check(&func.name, pose, distance) {
+            warn!("Refused: {}", violation);
+            return Err(violation.into());
+        }
+        Ok(())
     }
 
-    fn send_command_with_args(
-        &self,
-        port: &mut dyn SerialPort,
-        tag: u8,
-        args_data: &[u8],
-    ) -> Result<()> {
-        debug!(
-            "Sending SLIP command with tag: {} and {} arg bytes",
-            tag,
-            args_data.len()
-        );
+    /// Refuse a command whose frame wouldn't fit the device's buffer.
+    fn check_frame_size(&self, command: fmt::Arguments, args_len: usize) -> Result<()> {
+        let Some(protocol) = self.protocol() else {
+            return Ok(());
+        };
+        // Tag, CRC and the sequence byte if any
+        let frame_size = args_len + 2 + usize::from(protocol.sequence_numbers);
+        if frame_size > protocol.max_frame_size {
+            return Err(AdapterError::Validation(format!(
+                "Command {} needs a {}-byte frame, over the device's {}-byte limit",
+                command, frame_size, protocol.max_frame_size
+            ))
+            .into());
+        }
+        Ok(())
+    }
 
-        let mut command_data = vec![tag];
-        command_data.extend_from_slice(args_data);
+    /// Log the frame a dry run leaves unsent, and return it as hex.
+    fn log_dry_run(&self, command: &str, tag: u8, args_data: &[u8]) -> String {
+        let mut frame = vec![tag];
+        frame.extend_from_slice(args_data);
+        frame.push(crc8(&frame));
+        let hex = to_hex(&frame);
+        info!("Dry run: command {} not sent, frame {}", command, hex);
+        hex
+    }
 
-        let crc = self.crc8(&command_data);
-        command_data.push(crc);
+    /// Timeouts are counted by `transact` itself.
+    fn record_transact_error(&self, e: &anyhow::Error) {
+        if Kind::of(e) != Kind::Timeout {
+            self.stats.record_error(ErrorKind::Other);
+        }
+    }
 
-        let slip_frame = slip_encode(&command_data);
-        port.write_all(&slip_frame)?;
-        port.flush()?;
-        debug!("SLIP command sent and flushed ({} bytes)", slip_frame.len());
-        Ok(())
+    /// Run the manifest's `on_disconnect` function (falling back to
+    /// `safe_state`), then close the port.
+    pub async fn shutdown(&self) {
+        let func = self
+            .lifecycle_function("on_disconnect", |m| m.on_disconnect.as_ref())
+            .or_else(|| self.lifecycle_function("safe_state", |m| m.safe_state.as_ref()));
+        if let Some(func) = func {
+            info!("Sending safe-state command '{}'", func.name);
+            if let Err(e) = self
+                .execute_function(&func, &Value::Object(Default::default()))
+                .await
+            {
+                warn!("Safe-state command '{}' failed: {}", func.name, e);
+            }
+        }
+
+        if self.close_port() {
+            info!("Closed serial port {}", self.connector.name());
+        }
+        self.transition(Event::Closed);
     }
 
-    fn read_response(&self, port: &mut dyn SerialPort) -> Result<String> {
-        let data = self.read_response_raw(port)?;
-        let mut decoder = ResponseDecoder::new(&data);
-        decoder.read_cstring()
+    /// Send the manifest's `safe_state` function ahead of everything
+    /// queued, to stop the robot now.
+    pub async fn emergency_stop(&self) -> Result<String> {
+        let func = self
+            .lifecycle_function("safe_state", |m| m.safe_state.as_ref())
+            .ok_or_else(|| anyhow!("The robot's manifest names no safe_state function"))?;
+        warn!("Emergency stop: sending '{}'", func.name);
+        self.execute_urgent(&func).await
     }
 
-    fn read_response_raw(&self, port: &mut dyn SerialPort) -> Result<Vec<u8>> {
-        debug!("Beginning to read SLIP response from serial port");
-        let mut buffer = [0; 256];
-        let mut decoder = SlipDecoder::new();
+    /// Send the manifest watchdog keep-alive while the robot is ready, so
+    /// firmware that stops hearing from the adapter can stop its motors.
+    pub async fn run_watchdog(self: Arc<Self>) {
+        loop {
+            let watchdog = self
+                .current_manifest()
+                .and_then(|m| m.watchdog)
+                .filter(|w| w.interval_ms > 0);
+
+            let Some(watchdog) = watchdog else {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            };
+
+            tokio::time::sleep(Duration::from_millis(watchdog.interval_ms)).await;
 
-        // Read until we get a complete SLIP frame
+            if let Some(func) =
+                self.lifecycle_function("watchdog", |m| m.watchdog.as_ref().map(|w| &w.function))
+            {
+                debug!("Sending watchdog keep-alive '{}'", func.name);
+                // Ahead of queued calls, so a long queue doesn't starve it
+                if let Err(e) = self.execute_urgent(&func).await {
+                    warn!("Watchdog keep-alive '{}' failed: {}", func.name, e);
+                }
+            }
+        }
+    }
+
+    /// Read the manifest's battery getter every `interval_ms` while the
+    /// robot is ready, for `/status`, low-battery notifications and the
+    /// lockout.
+    pub async fn run_battery_monitor(self: Arc<Self>) {
+        if self.dry_run {
+            // Nothing would be read
+            return;
+        }
         loop {
-            match port.read(&mut buffer) {
-                Ok(bytes_read) if bytes_read > 0 => {
-                    debug!("Read {} bytes from serial", bytes_read);
-
-                    // Process each byte through SLIP decoder
-                    for &byte in &buffer[..bytes_read] {
-                        if let Some(frame) = decoder.process_byte(byte)? {
-                            debug!("Received SLIP frame: {} bytes", frame.len());
-
-                            if frame.len() < 1 {
-                                return Err(anyhow!("Frame too short"));
-                            }
-
-                            if frame.len() == 1 {
-                                // Void function - just CRC, no data
-                                debug!("Void function response (CRC only)");
-                                return Ok(vec![]);
-                            }
-
-                            // Strip CRC (last byte) and return raw data
-                            let data = frame[..frame.len() - 1].to_vec();
-                            return Ok(data);
-                        }
-                    }
+            let Some(config) = self.current_manifest().and_then(|m| m.battery) else {
+                self.battery.clear();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            };
+
+            if let Some(func) =
+                self.lifecycle_function("battery", |m| m.battery.as_ref().map(|b| &b.function))
+            {
+                match self
+                    .execute_function(&func, &Value::Object(Default::default()))
+                    .await
+                {
+                    Ok(reading) => match reading.trim().parse() {
+                        Ok(voltage) => self.battery.record(&config, voltage),
+                        Err(_) => warn!(
+                            "Battery function '{}' returned '{}', not a number",
+                            func.name, reading
+                        ),
+                    },
+                    Err(e) => warn!("Battery reading '{}' failed: {}", func.name, e),
+                }
+            }
+
+            let interval = config
+                .interval_ms
+                .filter(|&ms| ms > 0)
+                .map_or(battery::DEFAULT_INTERVAL, Duration::from_millis);
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// The last battery reading, while the robot is connected.
+    pub fn battery(&self) -> Option<BatteryStatus> {
+        self.battery.status()
+    }
+
+    /// Battery readings whose level differs from the one before.
+    pub fn subscribe_battery(&self) -> broadcast::Receiver<BatteryStatus> {
+        self.battery.subscribe()
+    }
+
+    fn current_manifest(&self) -> Option<Manifest> {
+        let manifest_manager = self.manifest_manager.as_ref()?;
+        let state = self.get_state();
+        manifest_manager.get_manifest(state.device_id()?).ok()
+    }
+
+    /// Look up a zero-argument function named by a manifest lifecycle field.
+    fn lifecycle_function(
+        &self,
+        hook: &str,
+        select: impl Fn(&Manifest) -> Option<&String>,
+    ) -> Option<Function> {
+        let manifest = self.current_manifest()?;
+        let name = select(&manifest)?.clone();
+
+        match manifest.functions.into_iter().find(|f| f.name == name) {
+            Some(func) if func.params.is_empty() => Some(func),
+            Some(_) => {
+                warn!("{} function '{}' takes parameters, skipping", hook, name);
+                None
+            }
+            None => {
+                warn!("{} function '{}' not found in manifest", hook, name);
+                None
+            }
+        }
+    }
+
+    /// Latch a fault the device reported, so calls that would move the
+    /// robot are refused from now on. One reported while the device is
+    /// still being brought up is kept until it is ready.
+    fn latch_fault(&self) {
+        let Some(fault) = self.events.take_fault() else {
+            return;
+        };
+        if self.transition(Event::Fault(fault)) {
+            warn!("{}", self.get_state().error_message());
+        } else if matches!(
+            self.get_state(),
+            RobotState::Connected | RobotState::Initializing
+        ) {
+            self.events.keep_fault(fault);
+        }
+    }
+
+    /// Let calls move the robot again after a fault, returning the fault
+    /// that was cleared.
+    pub fn clear_fault(&self) -> Result<Fault> {
+        self.latch_fault();
+        let RobotState::Fault { fault, .. } = self.get_state() else {
+            return Err(anyhow!("The robot has no fault to clear"));
+        };
+        self.transition(Event::FaultCleared);
+        info!("{} fault cleared", fault);
+        Ok(fault)
+    }
+
+    /// Move to the state `event` leads to, returning whether it applied.
+    fn transition(&self, event: Event) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(new_state) = state.on(&event) else {
+            debug!("Ignoring {:?} while {:?}", event, *state);
+            return false;
+        };
+        if *state != new_state {
+            debug!("Robot state {:?} -> {:?}", *state, new_state);
+            *state = new_state.clone();
+            // Nobody subscribed is fine
+            let _ = self.state_events.send(new_state);
+        }
+        true
+    }
+
+    fn encode_arguments(func: &Function, arguments: &Value) -> Bytes {
+        let mut encoder = CommandEncoder::new();
+
+        for param in &func.params {
+            let arg_value = &arguments[&param.name];
+
+            match param.param_type.as_str() {
+                "i16" => {
+                    let value = arg_value.as_i64().unwrap() as i16;
+                    debug!("Encoding i16 parameter '{}': {}", param.name, value);
+                    encoder.write_i16(value);
                 }
-                Ok(_) => continue,
-                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                    debug!("Serial read timeout");
-                    continue;
+                "i32" => {
+                    let value = arg_value.as_i64().unwrap() as i32;
+                    debug!("Encoding i32 parameter '{}': {}", param.name, value);
+                    encoder.write_i32(value);
                 }
-                Err(e) => {
-                    let error_msg = format!("Serial read error: {}", e);
-                    self.set_state(RobotState::Error(error_msg.clone()));
-                    return Err(anyhow!(error_msg));
+                "CStr" => {
+                    let value = arg_value.as_str().unwrap();
+                    debug!("Encoding CStr parameter '{}': '{}'", param.name, value);
+                    encoder.write_cstring(value);
+                }
+                _ => {
+                    let value = arg_value.as_str().unwrap_or("");
+                    debug!(
+                        "Encoding unknown type '{}' as CStr: '{}'",
+                        param.param_type, value
+                    );
+                    encoder.write_cstring(value);
                 }
             }
         }
+
+        encoder.finish()
     }
 
-    fn crc8(&self, data: &[u8]) -> u8 {
-        let mut crc: u8 = 0;
-        for &byte in data {
-            crc ^= byte;
-            for _ in 0..8 {
-                if crc & 0x80 != 0 {
-                    crc = (crc << 1) ^ 0x07;
-                } else {
-                    crc <<= 1;
+    fn port(&self) -> Option<Arc<PortActor>> {
+        self.port.lock().unwrap().clone()
+    }
+
+    /// Send one command and return its raw response data.
+    async fn transact(&self, tag: u8, args_data: Bytes, priority: Priority) -> Result<Vec<u8>> {
+        let port = self
+            .port()
+            .ok_or_else(|| AdapterError::SerialIo("No serial port available".to_string()))?;
+        let _queued = self.stats.enqueue();
+        let crc_errors = self.frame_log.crc_errors();
+        let deadline = self.latency.timeout(tag);
+
+        let started = Instant::now();
+        let result = if self.pipeline.is_enabled() {
+            // The pipeline reader delivers the response, so other commands
+            // can go out while this one waits
+            let ticket = self.pipeline.begin().await?;
+            match port
+                .transact(Some(ticket.seq), priority, tag, args_data, RESPONSE_TIMEOUT)
+                .await
+            {
+                Ok(_) => {
+                    ticket
+                        .wait(deadline.unwrap_or(RESPONSE_TIMEOUT))
+                        .instrument(info_span!("pipeline_wait"))
+                        .await
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            // The port's thread stops reading at the same deadline, so a
+            // device that never answers doesn't hold up the commands behind
+            let timeout = deadline.unwrap_or(RESPONSE_TIMEOUT);
+            tokio::time::timeout(
+                timeout,
+                port.transact(None, priority, tag, args_data, timeout),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                Err(
+                    AdapterError::Timeout(format!("Timed out waiting for response to tag {}", tag))
+                        .into(),
+                )
+            })
+        };
+        let timed_out = matches!(&result, Err(e) if Kind::of(e) == Kind::Timeout);
+        if result.is_ok() {
+            self.latency.record(tag, started.elapsed());
+        } else if timed_out {
+            // Counted here, so the identity checks' timeouts are too
+            self.latency.record_timeout(tag);
+            self.stats.record_error(ErrorKind::Timeout);
+        }
+        let garbled = self.frame_log.crc_errors() > crc_errors;
+        // Realign frame boundaries at both ends; the reliable link layer
+        // does so itself as it resends
+        if (timed_out || garbled) && !self.protocol().is_some_and(|p| p.reliable) {
+            debug!("Sending the SLIP clear sequence after a failed response");
+            port.clear();
+        }
+
+        match &result {
+            Ok(_) => {
+                *self.last_response.lock().unwrap() = Some(Instant::now());
+            }
+            Err(e) if port.has_failed() => {
+                self.transition(Event::IoError(e.to_string()));
+            }
+            Err(e) if e.is::<BootloaderDetected>() => {
+                if self.transition(Event::BootloaderReply(e.to_string())) {
+                    warn!("{}, reconnecting", e);
+                    self.recover.notify_one();
                 }
             }
+            Err(_) => {}
+        }
+        // Noise a restarting board sends can end up read as a reply; the
+        // reliable link layer asks again for damaged replies
+        if garbled
+            && self.get_state().accepts_calls()
+            && !self.protocol().is_some_and(|p| p.reliable)
+        {
+            warn!("Garbled response from the device, checking its ID");
+            self.garbled.store(true, Ordering::Relaxed);
+            self.recover.notify_one();
+        }
+        result
+    }
+
+    fn close_port(&self) -> bool {
+        self.pipeline.stop_reader();
+        *self.protocol.lock().unwrap() = None;
+        match self.port.lock().unwrap().take() {
+            Some(port) => {
+                port.close();
+                true
+            }
+            None => false,
         }
-        crc
     }
 }
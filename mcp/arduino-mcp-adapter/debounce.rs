@@ -0,0 +1,113 @@
+//! Coalescing of repeated tool calls. A call to a function with
+//! `"debounce_ms"` in the manifest that arrives within that many
+//! milliseconds of an identical one shares its execution and result rather
+//! than going to the robot again, for clients that submit a call twice.
+
+use serde_json::Value;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+/// A call whose repeats are coalesced until `opened + length`.
+struct Window<T> {
+    function: String,
+    arguments: Value,
+    opened: Instant,
+    length: Duration,
+    result: Arc<OnceCell<T>>,
+}
+
+pub struct Debouncer<T> {
+    windows: Mutex<Vec<Window<T>>>,
+}
+
+impl<T: Clone> Debouncer<T> {
+    pub fn new() -> Self {
+        Self {
+            windows: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run `call`, or wait for the result of an identical call to `function`
+    /// that arrived less than `window` ago. Should that one be cancelled
+    /// before it ends, the next caller waiting runs its own.
+    pub async fn run<F, Fut>(
+        &self,
+        function: &str,
+        arguments: &Value,
+        window: Duration,
+        call: F,
+    ) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let result = {
+            let mut windows = self.windows.lock().unwrap();
+            let now = Instant::now();
+            windows.retain(|w| now.duration_since(w.opened) < w.length);
+            let earlier = windows
+                .iter()
+                .find(|w| w.function == function && w.arguments == *arguments);
+            match earlier {
+                Some(earlier) => {
+                    debug!(
+                        "Coalescing a repeated call to '{}' made {:?} after the first",
+                        function,
+                        now.duration_since(earlier.opened)
+                    );
+                    Arc::clone(&earlier.result)
+                }
+                None => {
+                    let result = Arc::new(OnceCell::new());
+                    windows.push(Window {
+                        function: function.to_string(),
+                        arguments: arguments.clone(),
+                        opened: now,
+                        length: window,
+                        result: Arc::clone(&result),
+                    });
+                    result
+                }
+            }
+        };
+        result.get_or_init(call).await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_identical_calls_within_the_window_share_one_run() {
+        let debouncer = Debouncer::new();
+        let runs = AtomicUsize::new(0);
+        let window = Duration::from_millis(200);
+        let call = |arguments: Value| {
+            let (debouncer, runs) = (&debouncer, &runs);
+            async move {
+                debouncer
+                    .run("drive", &arguments, window, || async {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        runs.fetch_add(1, Ordering::SeqCst)
+                    })
+                    .await
+            }
+        };
+
+        // The second arrives while the first runs, the third after it ended
+        let (first, second) = tokio::join!(call(json!({"mm": 5})), call(json!({"mm": 5})));
+        assert_eq!((first, second), (0, 0));
+        assert_eq!(call(json!({"mm": 5})).await, 0);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        assert_eq!(call(json!({"mm": 6})).await, 1);
+        tokio::time::sleep(window).await;
+        assert_eq!(call(json!({"mm": 5})).await, 2);
+    }
+}
@@ -0,0 +1,260 @@
+//! Wi-Fi robots announcing themselves with UDP beacons, listed by the
+//! fleet's `discoverRobots` tool and paired with `pairRobot`, which saves
+//! the robot's address and manifest ID to the config file.
+//!
+//! A beacon is one JSON datagram, `{"id": "arm", "port": 3333}`, with an
+//! optional `"manifest"` naming another device ID whose manifest the robot
+//! uses. The robot's TCP socket is at the beacon's source address and
+//! `port`.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use toml_edit::{DocumentMut, Item, Table};
+use tracing::{debug, info, warn};
+
+use crate::config::{is_valid_robot_name, Config, PairedRobot};
+
+/// Robots not heard from for this long drop off the list.
+const BEACON_EXPIRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct Beacon {
+    id: String,
+    port: u16,
+    manifest: Option<String>,
+}
+
+struct Sighting {
+    address: SocketAddr,
+    manifest: String,
+    last_seen: Instant,
+}
+
+pub struct Discovery {
+    port: u16,
+    heard: Mutex<BTreeMap<String, Sighting>>,
+    paired: Mutex<Vec<PairedRobot>>,
+    /// Names of `[[robots]]` and `[[remotes]]`, which pairings can't reuse
+    taken: Vec<String>,
+    /// Where pairings are saved
+    config_path: Option<PathBuf>,
+}
+
+impl Discovery {
+    pub fn new(port: u16, config: &Config) -> Self {
+        let robots = config.robots.iter().map(|robot| robot.name.clone());
+        let remotes = config.remotes.iter().map(|remote| remote.name.clone());
+        Self {
+            port,
+            heard: Mutex::new(BTreeMap::new()),
+            paired: Mutex::new(config.paired.clone()),
+            taken: robots.chain(remotes).collect(),
+            config_path: config.path.clone(),
+        }
+    }
+
+    /// Bind the beacon port and record beacons in the background.
+    pub async fn listen(self: Arc<Self>) -> Result<()> {
+        let socket = UdpSocket::bind(("0.0.0.0", self.port))
+            .await
+            .with_context(|| format!("Failed to listen for beacons on UDP port {}", self.port))?;
+        info!("Listening for robot beacons on UDP port {}", self.port);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                match socket.recv_from(&mut buf).await {
+                    Ok((len, from)) => self.hear(&buf[..len], from),
+                    Err(e) => {
+                        warn!("Beacon socket failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn hear(&self, datagram: &[u8], from: SocketAddr) {
+        let beacon: Beacon = match serde_json::from_slice(datagram) {
+            Ok(beacon) => beacon,
+            Err(e) => {
+                debug!("Ignoring datagram from {}: {}", from, e);
+                return;
+            }
+        };
+        let address = SocketAddr::new(from.ip(), beacon.port);
+        let manifest = beacon.manifest.unwrap_or_else(|| beacon.id.clone());
+        let mut heard = self.heard.lock().unwrap();
+        if heard.get(&beacon.id).map(|s| s.address) != Some(address) {
+            info!("Heard robot '{}' at {}", beacon.id, address);
+        }
+        heard.insert(
+            beacon.id,
+            Sighting {
+                address,
+                manifest,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    /// Robots heard recently, with whether each is paired.
+    pub fn robots(&self) -> Value {
+        let paired = self.paired.lock().unwrap();
+        let heard = self.heard.lock().unwrap();
+        heard
+            .iter()
+            .filter(|(_, sighting)| sighting.last_seen.elapsed() < BEACON_EXPIRY)
+            .map(|(id, sighting)| {
+                json!({
+                    "id": id,
+                    "address": sighting.address.to_string(),
+                    "manifest": sighting.manifest,
+                    "last_seen_secs": sighting.last_seen.elapsed().as_secs(),
+                    "paired": paired.iter().any(|p| p.id == *id),
+                })
+            })
+            .collect()
+    }
+
+    /// Pair the robot heard as `id`, saving its address and manifest ID
+    /// (the beacon's unless `manifest` is given) to the config file.
+    pub fn pair(&self, id: &str, manifest: Option<&str>) -> Result<PairedRobot> {
+        let path = self.config_path.as_deref().ok_or_else(|| {
+            anyhow!("No config file to save the pairing in; start the adapter with --config")
+        })?;
+        if !is_valid_robot_name(id) {
+            return Err(anyhow!(
+                "Can't pair '{}': robot names use letters, digits and '-'",
+                id
+            ));
+        }
+        if self.taken.iter().any(|name| name == id) {
+            return Err(anyhow!(
+                "Can't pair '{}': the config file already has a robot or remote by that name",
+                id
+            ));
+        }
+        let robot = {
+            let heard = self.heard.lock().unwrap();
+            let sighting = heard
+                .get(id)
+                .filter(|sighting| sighting.last_seen.elapsed() < BEACON_EXPIRY)
+                .ok_or_else(|| anyhow!("No beacon from '{}' lately; see discoverRobots", id))?;
+            PairedRobot {
+                id: id.to_string(),
+                address: sighting.address.to_string(),
+                manifest: manifest.unwrap_or(&sighting.manifest).to_string(),
+            }
+        };
+        // It names a file in the manifest directory, and may come from a beacon
+        if !is_valid_robot_name(&robot.manifest) {
+            return Err(anyhow!(
+                "Can't pair '{}' with manifest '{}': manifest IDs use letters, digits and '-'",
+                id,
+                robot.manifest
+            ));
+        }
+
+        save_pairing(path, &robot)?;
+        let mut paired = self.paired.lock().unwrap();
+        paired.retain(|p| p.id != robot.id);
+        paired.push(robot.clone());
+        info!("Paired '{}' at {}", robot.id, robot.address);
+        Ok(robot)
+    }
+}
+
+/// Add or update `robot`'s `[[paired]]` entry, keeping the rest of the
+/// file as written.
+fn save_pairing(path: &Path, robot: &PairedRobot) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let mut document: DocumentMut = content
+        .parse()
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+    let entries = document
+        .entry("paired")
+        .or_insert(Item::ArrayOfTables(Default::default()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| anyhow!("`paired` in {} is not a list of tables", path.display()))?;
+
+    let existing = entries
+        .iter_mut()
+        .find(|entry| entry.get("id").and_then(Item::as_str) == Some(robot.id.as_str()));
+    let entry = match existing {
+        Some(entry) => entry,
+        None => {
+            let mut entry = Table::new();
+            entry["id"] = toml_edit::value(&robot.id);
+            entries.push(entry);
+            entries.iter_mut().last().unwrap()
+        }
+    };
+    entry["address"] = toml_edit::value(&robot.address);
+    entry["manifest"] = toml_edit::value(&robot.manifest);
+
+    std::fs::write(path, document.to_string())
+        .with_context(|| format!("Failed to save pairing to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heard_robot_paired_into_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "# Workshop robots\nport = 9000\n\n[[robots]]\nname = \"rover\"\nline = \"/dev/ttyACM0\"\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&path).unwrap();
+        let discovery = Discovery::new(3334, &config);
+
+        let from: SocketAddr = "192.168.1.42:50000".parse().unwrap();
+        discovery.hear(b"not json", from);
+        discovery.hear(br#"{"id": "arm", "port": 3333}"#, from);
+        discovery.hear(br#"{"id": "rover", "port": 3333}"#, from);
+        let robots = discovery.robots();
+        assert_eq!(robots.as_array().unwrap().len(), 2);
+        assert_eq!(robots[0]["address"], "192.168.1.42:3333");
+        assert_eq!(robots[0]["paired"], false);
+
+        assert!(discovery.pair("ghost", None).is_err());
+        assert!(discovery.pair("rover", None).is_err());
+        assert!(discovery.pair("arm", Some("../../etc/x")).is_err());
+        discovery.hear(br#"{"id": "spy", "port": 3333, "manifest": "../x"}"#, from);
+        assert!(discovery.pair("spy", None).is_err());
+        discovery.pair("arm", None).unwrap();
+        // The robot moved; pairing again updates its entry
+        let moved: SocketAddr = "192.168.1.77:50000".parse().unwrap();
+        discovery.hear(br#"{"id": "arm", "port": 3333}"#, moved);
+        discovery.pair("arm", Some("arm-v2")).unwrap();
+        assert_eq!(discovery.robots()[0]["paired"], true);
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# Workshop robots\n"));
+        let config = Config::parse(&saved).unwrap();
+        assert_eq!(
+            config.paired,
+            vec![PairedRobot {
+                id: "arm".to_string(),
+                address: "192.168.1.77:3333".to_string(),
+                manifest: "arm-v2".to_string(),
+            }]
+        );
+        let robots = config.fleet_robots();
+        assert_eq!(robots[1].name, "arm");
+        assert_eq!(robots[1].line, "tcp://192.168.1.77:3333");
+    }
+}
@@ -0,0 +1,229 @@
+//! What kind of failure a tool call ran into. Every tool call error carries
+//! its [`Kind`] as `data.kind`, and the kind decides the JSON-RPC code, so
+//! clients can branch on it rather than on the message text.
+
+use serde::Deserialize;
+
+use crate::bootloader::BootloaderDetected;
+use crate::geofence::GeofenceViolation;
+use crate::manifest::ArgumentError;
+use crate::protocol::{DeviceError, Fault};
+
+/// Failures the adapter raises itself while talking to the device.
+#[derive(Debug, thiserror::Error)]
+pub enum AdapterError {
+    /// A frame failed its CRC check
+    #[error("{0}")]
+    CrcMismatch(String),
+    /// The device didn't answer in time
+    #[error("{0}")]
+    Timeout(String),
+    /// A frame that doesn't follow the protocol, such as a chunk out of
+    /// order
+    #[error("{0}")]
+    Protocol(String),
+    /// The port failed, closed or isn't open
+    #[error("{0}")]
+    SerialIo(String),
+    /// Refused before anything was sent
+    #[error("{0}")]
+    Validation(String),
+    #[error("Robot not ready: {0}")]
+    NotReady(String),
+    /// Refused by `--battery-lockout`
+    #[error("{0}")]
+    BatteryCritical(String),
+    /// A call that would move the robot while a fault is latched; holds
+    /// the fault and the function refused
+    #[error("{1} refused: the robot stopped on a {0} fault; call clearFault once it is safe to move again")]
+    Fault(Fault, String),
+}
+
+/// The kind of a tool call error, sent as `data.kind`. Also the keys of the
+/// config's `[error_codes]` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    CrcMismatch,
+    Timeout,
+    Protocol,
+    SerialIo,
+    Validation,
+    NotReady,
+    /// The firmware doesn't know the function or its arguments
+    Dispatch,
+    Busy,
+    HardwareFault,
+    /// An error code the adapter doesn't know
+    #[serde(rename = "device_error")]
+    Device,
+    Geofence,
+    QueueFull,
+    /// Another session has control of the robot
+    Control,
+    BatteryCritical,
+    /// The robot stopped on a fault event that hasn't been cleared
+    Fault,
+    Internal,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::CrcMismatch => "crc_mismatch",
+            Self::Timeout => "timeout",
+            Self::Protocol => "protocol",
+            Self::SerialIo => "serial_io",
+            Self::Validation => "validation",
+            Self::NotReady => "not_ready",
+            Self::Dispatch => "dispatch",
+            Self::Busy => "busy",
+            Self::HardwareFault => "hardware_fault",
+            Self::Device => "device_error",
+            Self::Geofence => "geofence",
+            Self::QueueFull => "queue_full",
+            Self::Control => "control",
+            Self::BatteryCritical => "battery_critical",
+            Self::Fault => "fault",
+            Self::Internal => "internal",
+        }
+    }
+
+    /// Whether the same call may succeed if sent again unchanged, perhaps
+    /// after a pause.
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            Self::CrcMismatch
+                | Self::Timeout
+                | Self::Protocol
+                | Self::SerialIo
+                | Self::NotReady
+                | Self::Busy
+                | Self::QueueFull
+        )
+    }
+
+    /// The JSON-RPC error code for this kind, unless `[error_codes]` sets
+    /// another.
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Validation => -32602,
+            Self::Control => -32001,
+            Self::Geofence => -32002,
+            Self::QueueFull => -32003,
+            _ => -32603,
+        }
+    }
+
+    /// The kind of an error built from a code alone.
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            -32602 => Self::Validation,
+            -32001 => Self::Control,
+            -32002 => Self::Geofence,
+            -32003 => Self::QueueFull,
+            _ => Self::Internal,
+        }
+    }
+
+    /// The kind of `error`, from the first typed error in its chain.
+    pub fn of(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| {
+                if let Some(error) = cause.downcast_ref::<AdapterError>() {
+                    return Some(error.kind());
+                }
+                if let Some(error) = cause.downcast_ref::<DeviceError>() {
+                    return Some(Self::from(*error));
+                }
+                if cause.is::<GeofenceViolation>() {
+                    return Some(Self::Geofence);
+                }
+                if cause.is::<ArgumentError>() {
+                    return Some(Self::Validation);
+                }
+                if cause.is::<BootloaderDetected>() {
+                    return Some(Self::NotReady);
+                }
+                None
+            })
+            .unwrap_or(Self::Internal)
+    }
+}
+
+impl AdapterError {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::CrcMismatch(_) => Kind::CrcMismatch,
+            Self::Timeout(_) => Kind::Timeout,
+            Self::Protocol(_) => Kind::Protocol,
+            Self::SerialIo(_) => Kind::SerialIo,
+            Self::Validation(_) => Kind::Validation,
+            Self::NotReady(_) => Kind::NotReady,
+            Self::BatteryCritical(_) => Kind::BatteryCritical,
+            Self::Fault(..) => Kind::Fault,
+        }
+    }
+}
+
+impl From<DeviceError> for Kind {
+    fn from(error: DeviceError) -> Self {
+        match error {
+            DeviceError::Crc => Self::CrcMismatch,
+            DeviceError::Dispatch => Self::Dispatch,
+            DeviceError::Busy => Self::Busy,
+            DeviceError::HardwareFault => Self::HardwareFault,
+            DeviceError::Unknown(_) => Self::Device,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_follows_the_error_chain() {
+        let timeout: anyhow::Error = AdapterError::Timeout("No response".to_string()).into();
+        let timeout = timeout.context("Couldn't read the pose");
+        assert_eq!(Kind::of(&timeout), Kind::Timeout);
+        assert_eq!(timeout.root_cause().to_string(), "No response");
+
+        let busy = anyhow::Error::new(DeviceError::Busy).context("Robot still busy");
+        assert_eq!(Kind::of(&busy), Kind::Busy);
+        assert_eq!(Kind::of(&DeviceError::Crc.into()), Kind::CrcMismatch);
+        assert_eq!(Kind::of(&anyhow::anyhow!("plain")), Kind::Internal);
+
+        let not_ready: anyhow::Error = AdapterError::NotReady("unplugged".to_string()).into();
+        assert_eq!(not_ready.to_string(), "Robot not ready: unplugged");
+    }
+
+    #[test]
+    fn test_codes_round_trip() {
+        for kind in [
+            Kind::Validation,
+            Kind::Control,
+            Kind::Geofence,
+            Kind::QueueFull,
+        ] {
+            assert_eq!(Kind::from_code(kind.code()), kind);
+        }
+        assert_eq!(Kind::Timeout.code(), -32603);
+        assert_eq!(Kind::from_code(-32603), Kind::Internal);
+
+        // `[error_codes]` is keyed by the names sent as `data.kind`
+        for kind in [
+            Kind::CrcMismatch,
+            Kind::NotReady,
+            Kind::Device,
+            Kind::QueueFull,
+        ] {
+            let parsed: Kind = serde_json::from_value(kind.name().into()).unwrap();
+            assert_eq!(parsed, kind);
+        }
+        assert!(Kind::Timeout.retryable());
+        assert!(!Kind::Validation.retryable());
+    }
+}
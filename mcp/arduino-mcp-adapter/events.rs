@@ -0,0 +1,159 @@
+//! Events the firmware sends between responses, whether a motion it
+//! acknowledged before finishing is still running, and whether it reported
+//! a reset or a fault the connection hasn't caught up with yet.
+
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::protocol::{DeviceEvent, Fault};
+
+/// Shared by the threads reading the port and the calls waiting on it.
+pub struct DeviceEvents {
+    /// Set when an immediately acknowledged call goes out, cleared by
+    /// [`DeviceEvent::MotionDone`]
+    moving: watch::Sender<bool>,
+    /// Set by [`DeviceEvent::Reset`], cleared once the connection resyncs
+    reset: watch::Sender<bool>,
+    /// Set by [`DeviceEvent::Fault`], taken when the connection latches it
+    fault: watch::Sender<Option<Fault>>,
+}
+
+impl DeviceEvents {
+    pub fn new() -> Self {
+        Self {
+            moving: watch::Sender::new(false),
+            reset: watch::Sender::new(false),
+            fault: watch::Sender::new(None),
+        }
+    }
+
+    /// Handle an event frame read from the port.
+    pub fn publish(&self, event: DeviceEvent) {
+        match event {
+            DeviceEvent::MotionDone => {
+                debug!("Device reported motion done");
+                self.moving.send_replace(false);
+            }
+            DeviceEvent::Reset => {
+                warn!("Device reported a reset");
+                // Whatever was moving stopped with it
+                self.moving.send_replace(false);
+                self.reset.send_replace(true);
+            }
+            DeviceEvent::Fault(fault) => {
+                warn!("Device reported a {} fault", fault);
+                // The firmware cut the motors
+                self.moving.send_replace(false);
+                // The first fault is the one that stopped the robot
+                self.fault.send_if_modified(|pending| {
+                    let first = pending.is_none();
+                    if first {
+                        *pending = Some(fault);
+                    }
+                    first
+                });
+            }
+            DeviceEvent::Unknown(_) => info!("Ignoring unknown device {}", event),
+        }
+    }
+
+    /// Called before sending a command whose motion outlasts its response.
+    pub fn motion_started(&self) {
+        self.moving.send_replace(true);
+    }
+
+    /// Forget a motion, when its command failed or the device reconnected.
+    pub fn motion_stopped(&self) {
+        self.moving.send_replace(false);
+    }
+
+    /// Wait until the device reports a reset not yet taken.
+    pub async fn reset_reported(&self) {
+        let mut reset = self.reset.subscribe();
+        // The sender lives as long as `self`
+        let _ = reset.wait_for(|reset| *reset).await;
+    }
+
+    /// Whether the device reported a reset since the last call.
+    pub fn take_reset(&self) -> bool {
+        self.reset.send_replace(false)
+    }
+
+    /// Wait until the device reports a fault not yet taken.
+    pub async fn fault_reported(&self) {
+        let mut fault = self.fault.subscribe();
+        let _ = fault.wait_for(Option::is_some).await;
+    }
+
+    /// The fault the device reported since the last call, if any.
+    pub fn take_fault(&self) -> Option<Fault> {
+        self.fault.send_replace(None)
+    }
+
+    /// Put back a taken fault that can't be latched yet.
+    pub fn keep_fault(&self, fault: Fault) {
+        self.fault.send_replace(Some(fault));
+    }
+
+    /// Wait up to `timeout` for the current motion, if any, to end; returns
+    /// `false` if it is still running.
+    pub async fn wait_motion_done(&self, timeout: Duration) -> bool {
+        let mut moving = self.moving.subscribe();
+        let done = tokio::time::timeout(timeout, moving.wait_for(|moving| !moving)).await;
+        done.is_ok()
+    }
+}
+
+impl Default for DeviceEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_motion_done_event_ends_wait() {
+        let events = DeviceEvents::new();
+        assert!(events.wait_motion_done(Duration::ZERO).await);
+
+        events.motion_started();
+        assert!(!events.wait_motion_done(Duration::from_millis(10)).await);
+        events.publish(DeviceEvent::Unknown(0x42));
+        assert!(!events.wait_motion_done(Duration::from_millis(10)).await);
+
+        let wait = events.wait_motion_done(Duration::from_secs(5));
+        events.publish(DeviceEvent::MotionDone);
+        assert!(wait.await);
+    }
+
+    #[tokio::test]
+    async fn test_reset_event_ends_motion_and_is_taken_once() {
+        let events = DeviceEvents::new();
+        assert!(!events.take_reset());
+
+        events.motion_started();
+        let reported = events.reset_reported();
+        events.publish(DeviceEvent::Reset);
+        tokio::time::timeout(Duration::from_secs(5), reported)
+            .await
+            .unwrap();
+        assert!(events.wait_motion_done(Duration::ZERO).await);
+        assert!(events.take_reset());
+        assert!(!events.take_reset());
+    }
+
+    #[test]
+    fn test_first_fault_is_kept_until_taken() {
+        let events = DeviceEvents::new();
+        events.motion_started();
+        events.publish(DeviceEvent::Fault(Fault::Stall));
+        events.publish(DeviceEvent::Fault(Fault::Thermal));
+        assert!(!*events.moving.borrow());
+        assert_eq!(events.take_fault(), Some(Fault::Stall));
+        assert_eq!(events.take_fault(), None);
+    }
+}
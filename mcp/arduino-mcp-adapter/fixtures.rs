@@ -0,0 +1,59 @@
+//! The `test-robot` that tests across the adapter talk to: its manifest,
+//! built from the functions each test needs, a manifest directory holding
+//! it and a loopback device answering for it.
+
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+use crate::loopback::LoopbackDevice;
+use crate::manifest::Manifest;
+
+/// Device id of the test robot, and the name of its manifest.
+pub const DEVICE_ID: &str = "test-robot";
+
+/// A function taking `params`, each a `(name, type)` pair.
+pub fn function(tag: u8, name: &str, ret: Option<&str>, params: &[(&str, &str)]) -> Value {
+    let params: Vec<Value> = params
+        .iter()
+        .map(|(name, kind)| json!({"name": name, "type": kind}))
+        .collect();
+    json!({"tag": tag, "name": name, "desc": name, "return": ret, "params": params})
+}
+
+/// The test robot's manifest with `functions` and the top-level fields of
+/// `extra`, as JSON.
+pub fn manifest_json(functions: Value, extra: Value) -> Value {
+    let mut manifest = json!({
+        "name": DEVICE_ID,
+        "description": "Test robot",
+        "version": "v1",
+        "functions": functions,
+    });
+    if let Value::Object(extra) = extra {
+        manifest.as_object_mut().unwrap().extend(extra);
+    }
+    manifest
+}
+
+pub fn manifest_with(functions: Value, extra: Value) -> Manifest {
+    serde_json::from_value(manifest_json(functions, extra)).unwrap()
+}
+
+pub fn manifest(functions: Value) -> Manifest {
+    manifest_with(functions, json!({}))
+}
+
+/// A manifest directory holding `manifest` as the test robot's.
+pub fn manifest_dir(manifest: &Value) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(format!("{}.json", DEVICE_ID)),
+        serde_json::to_string_pretty(manifest).unwrap(),
+    )
+    .unwrap();
+    dir
+}
+
+pub fn device(manifest: Manifest) -> LoopbackDevice {
+    LoopbackDevice::new(DEVICE_ID, manifest)
+}
@@ -0,0 +1,195 @@
+//! `flash` subcommand: upload new firmware over the robot's serial line with
+//! the tool its manifest's `board` section names, then wait for the robot to
+//! identify itself again.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::connection::ConnectionManager;
+use crate::manifest::{Board, FlashTool, ManifestManager};
+use crate::robot_state::RobotState;
+
+/// How long the new firmware gets to boot and answer `deviceId`.
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(15);
+const REBOOT_POLL: Duration = Duration::from_millis(500);
+
+/// Flash `firmware` to the robot on `line` and return its device ID once it
+/// is ready again. `device` picks the manifest when the robot can't say who
+/// it is, e.g. because its current firmware is broken.
+pub async fn run(
+    connection_manager: &ConnectionManager,
+    manifest_manager: &ManifestManager,
+    line: &str,
+    firmware: &Path,
+    device: Option<&str>,
+) -> Result<String> {
+    if !firmware.is_file() {
+        return Err(anyhow!("Firmware file not found: {}", firmware.display()));
+    }
+    let device_id = match device {
+        Some(id) => id.to_string(),
+        None => identify(connection_manager).await?,
+    };
+    let manifest = manifest_manager.get_manifest(&device_id)?;
+    let board = manifest.board.as_ref().ok_or_else(|| {
+        anyhow!(
+            "Manifest for {} has no `board` section saying how to flash it",
+            device_id
+        )
+    })?;
+    let command = command(board, line, firmware);
+
+    // The upload tool needs the port to itself
+    connection_manager.shutdown().await;
+    info!("Flashing {}: {}", device_id, command.join(" "));
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .status()
+        .await
+        .with_context(|| format!("Failed to run {}", command[0]))?;
+    if !status.success() {
+        return Err(anyhow!("{} failed ({})", command[0], status));
+    }
+
+    wait_for_device(connection_manager, &device_id).await?;
+    Ok(device_id)
+}
+
+/// The upload command line for `board`.
+fn command(board: &Board, line: &str, firmware: &Path) -> Vec<String> {
+    let firmware = firmware.display().to_string();
+    match board.tool {
+        FlashTool::Avrdude => vec![
+            "avrdude".to_string(),
+            format!("-p{}", board.mcu),
+            format!("-c{}", board.programmer.as_deref().unwrap_or("arduino")),
+            format!("-P{}", line),
+            format!("-b{}", board.baud.unwrap_or(115200)),
+            "-D".to_string(),
+            format!("-Uflash:w:{}:a", firmware),
+        ],
+        FlashTool::Esptool => vec![
+            "esptool.py".to_string(),
+            "--chip".to_string(),
+            board.mcu.clone(),
+            "--port".to_string(),
+            line.to_string(),
+            "--baud".to_string(),
+            board.baud.unwrap_or(460800).to_string(),
+            "write_flash".to_string(),
+            board.address.as_deref().unwrap_or("0x10000").to_string(),
+            firmware,
+        ],
+    }
+}
+
+/// The device ID of the connected robot, also when its firmware no longer
+/// matches its manifest (which is usually why it is being flashed).
+async fn identify(connection_manager: &ConnectionManager) -> Result<String> {
+    let _ = connection_manager.check_and_update_connection().await;
+    match connection_manager.get_state() {
+        RobotState::Ready(id) | RobotState::VersionMismatch { device_id: id, .. } => Ok(id),
+        state => Err(anyhow!(
+            "{} (pass --device to flash anyway)",
+            state.error_message()
+        )),
+    }
+}
+
+async fn wait_for_device(connection_manager: &ConnectionManager, expected: &str) -> Result<()> {
+    let deadline = Instant::now() + REBOOT_TIMEOUT;
+    loop {
+        tokio::time::sleep(REBOOT_POLL).await;
+        let _ = connection_manager.check_and_update_connection().await;
+        match connection_manager.get_state() {
+            RobotState::Ready(id) if id == expected => {
+                info!("{} is running the new firmware", id);
+                return Ok(());
+            }
+            RobotState::Ready(id) => {
+                return Err(anyhow!(
+                    "Flashed firmware identifies as '{}', expected '{}'",
+                    id,
+                    expected
+                ))
+            }
+            state @ RobotState::VersionMismatch { .. } => {
+                return Err(anyhow!(state.error_message()))
+            }
+            state if Instant::now() >= deadline => {
+                return Err(anyhow!(
+                    "Robot did not come back after flashing: {}",
+                    state.error_message()
+                ))
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::loopback::LoopbackConnector;
+    use serde_json::json;
+
+    fn board(json: &str) -> Board {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_upload_commands() {
+        let uno = board(r#"{"tool": "avrdude", "mcu": "atmega328p"}"#);
+        assert_eq!(
+            command(&uno, "/dev/ttyACM0", Path::new("robot.hex")).join(" "),
+            "avrdude -patmega328p -carduino -P/dev/ttyACM0 -b115200 -D -Uflash:w:robot.hex:a"
+        );
+
+        let esp = board(r#"{"tool": "esptool", "mcu": "esp32", "baud": 921600}"#);
+        assert_eq!(
+            command(&esp, "/dev/ttyUSB0", Path::new("robot.bin")).join(" "),
+            "esptool.py --chip esp32 --port /dev/ttyUSB0 --baud 921600 write_flash 0x10000 robot.bin"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refuses_manifest_without_board() {
+        let dir = fixtures::manifest_dir(&fixtures::manifest_json(json!([]), json!({})));
+        let firmware = dir.path().join("robot.hex");
+        std::fs::write(&firmware, ":00000001FF\n").unwrap();
+        let device = fixtures::device(fixtures::manifest(json!([])));
+        let connection_manager =
+            ConnectionManager::with_connector(Box::new(LoopbackConnector::new(device)));
+        let manifest_manager = ManifestManager::new(dir.path().to_path_buf());
+
+        let err = run(
+            &connection_manager,
+            &manifest_manager,
+            "loop",
+            &firmware,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("no `board` section"), "{}", err);
+        // Nothing was flashed, so the robot is still connected
+        assert!(connection_manager.get_state().is_ready());
+
+        let missing = dir.path().join("missing.hex");
+        let err = run(
+            &connection_manager,
+            &manifest_manager,
+            "loop",
+            &missing,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("not found"));
+    }
+}
@@ -0,0 +1,687 @@
+//! Fleet mode: one adapter serving several robots. `/mcp` offers every
+//! robot's tools under a `<robot>__` prefix plus `listRobots`, while
+//! `/mcp/<robot>` and `/robots/<robot>/...` reach one robot's own endpoints.
+//! Tools of remote adapters are mounted under their name the same way.
+//! With `discovery_port` set, `discoverRobots` and `pairRobot` find and pair
+//! Wi-Fi robots.
+
+use anyhow::Result;
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use serde_json::Value;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+use crate::config::{Config, RobotSpec};
+use crate::connection::ConnectionManager;
+use crate::discovery::Discovery;
+use crate::geofence::Geofence;
+use crate::governor::Governor;
+use crate::http_server;
+use crate::manifest::{ManifestManager, Tool};
+use crate::messages::Messages;
+use crate::notifications::Notifier;
+use crate::remote::RemoteAdapter;
+use crate::server::{McpError, McpRequest, McpResponse, McpServer, ToolCallParams};
+
+/// Between the robot name and the tool name in the unified toolset.
+pub const TOOL_SEPARATOR: &str = "__";
+
+/// One robot of the fleet, with its own connection and MCP server.
+pub struct Robot {
+    pub name: String,
+    pub line: String,
+    pub server: Arc<McpServer>,
+}
+
+impl Robot {
+    pub fn new(
+        spec: &RobotSpec,
+        config: &Arc<Config>,
+        manifest_manager: &Arc<ManifestManager>,
+    ) -> Result<Self> {
+        let mut connection_manager =
+            ConnectionManager::new(&spec.line, spec.baud.unwrap_or(config.baud))?
+                .with_pipeline_depth(config.pipeline_depth()?)
+                .with_busy_wait(Duration::from_millis(config.busy_wait_ms))
+                .with_timeout_factor(config.timeout_factor()?)
+                .with_dry_run(config.dry_run)
+                .with_governor(Governor::new(&config.limits)?)
+                .with_battery_lockout(config.battery_lockout)
+                .with_manifest_manager(Arc::clone(manifest_manager));
+        if let Some(bounds) = config.geofence {
+            connection_manager = connection_manager.with_geofence(Geofence::new(bounds)?);
+        }
+        let server = McpServer::new(
+            Arc::new(connection_manager),
+            Arc::clone(manifest_manager),
+            Arc::clone(config),
+        )
+        .with_messages(Messages::new(&config.lang)?);
+        Ok(Self {
+            name: spec.name.clone(),
+            line: spec.line.clone(),
+            server: Arc::new(server),
+        })
+    }
+}
+
+pub struct FleetServer {
+    robots: Vec<Robot>,
+    remotes: Vec<Arc<RemoteAdapter>>,
+    config: Arc<Config>,
+    /// Every robot's notifications, for clients of the unified `/mcp`
+    notifier: Arc<Notifier>,
+    discovery: Option<Arc<Discovery>>,
+}
+
+impl FleetServer {
+    pub fn new(robots: Vec<Robot>, remotes: Vec<RemoteAdapter>, config: Arc<Config>) -> Self {
+        Self {
+            robots,
+            remotes: remotes.into_iter().map(Arc::new).collect(),
+            config,
+            notifier: Arc::new(Notifier::new()),
+            discovery: None,
+        }
+    }
+
+    /// Listen for robot beacons and offer `discoverRobots` and `pairRobot`.
+    pub fn with_discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(Arc::new(discovery));
+        self
+    }
+
+    /// Serve HTTP until `shutdown` completes, then shut every robot down as
+    /// [`McpServer::start`] does for one.
+    pub async fn start(self: Arc<Self>, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let addr = format!("0.0.0.0:{}", self.config.port);
+        let listener = TcpListener::bind(&addr).await?;
+        info!(
+            "MCP HTTP server for {} robots and {} remote adapters listening on {}",
+            self.robots.len(),
+            self.remotes.len(),
+            addr
+        );
+
+        if let Some(discovery) = &self.discovery {
+            Arc::clone(discovery).listen().await?;
+        }
+        for robot in &self.robots {
+            robot.server.start_background().await?;
+            self.forward_notifications(robot);
+        }
+        for remote in &self.remotes {
+            remote.spawn_poller(Arc::clone(&self.notifier));
+        }
+
+        tokio::pin!(shutdown);
+
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => break,
+            };
+            let fleet = Arc::clone(&self);
+
+            tokio::spawn(async move {
+                let io = hyper_util::rt::TokioIo::new(stream);
+                let service_fleet = Arc::clone(&fleet);
+                if let Err(err) = http_server::builder(&fleet.config.http)
+                    .serve_connection(
+                        io,
+                        service_fn(move |req| {
+                            let fleet = Arc::clone(&service_fleet);
+                            async move { fleet.handle_request(req, peer).await }
+                        }),
+                    )
+                    .await
+                {
+                    error!("Connection error: {}", err);
+                }
+                for robot in &fleet.robots {
+                    robot.server.connection_closed(peer);
+                }
+            });
+        }
+
+        drop(listener);
+        // Put every robot into its safe state at once
+        let shutdowns: Vec<_> = self
+            .robots
+            .iter()
+            .map(|robot| {
+                let server = Arc::clone(&robot.server);
+                tokio::spawn(async move { server.shutdown().await })
+            })
+            .collect();
+        for shutdown in shutdowns {
+            let _ = shutdown.await;
+        }
+        Ok(())
+    }
+
+    fn forward_notifications(&self, robot: &Robot) {
+        let mut notifications = robot.server.notifier().subscribe();
+        let notifier = Arc::clone(&self.notifier);
+        let name = robot.name.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = match notifications.recv().await {
+                    Ok(message) => message,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Fleet missed {} robot notifications", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let method = message["method"].as_str().unwrap_or_default();
+                let mut params = message.get("params").cloned();
+                if method == "notifications/robot_state_changed" {
+                    // Say which robot it was on the unified endpoint
+                    if let Some(params) = &mut params {
+                        params["robot"] = name.clone().into();
+                    }
+                }
+                notifier.notify(method, params);
+            }
+        });
+    }
+
+    async fn handle_request(
+        &self,
+        mut req: Request<hyper::body::Incoming>,
+        peer: SocketAddr,
+    ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
+        let authorized = match &self.config.auth.token {
+            Some(token) => McpServer::is_authorized(&req, token),
+            None => true,
+        };
+        let exempt = req.method() == Method::OPTIONS || req.uri().path() == "/health";
+        if !exempt && !authorized {
+            return Ok(McpServer::unauthorized_response());
+        }
+
+        if let Some((robot, path)) = self.route(req.uri().path()) {
+            let query = req
+                .uri()
+                .query()
+                .map(|query| format!("?{}", query))
+                .unwrap_or_default();
+            *req.uri_mut() = format!("{}{}", path, query).parse().unwrap();
+            return robot.server.handle_request(req, peer).await;
+        }
+
+        let path = req.uri().path().to_string();
+        match (req.method().clone(), path.as_str()) {
+            (Method::POST, "/mcp") => self.handle_mcp_post(req, peer).await,
+            (Method::GET, "/mcp") => Ok(McpServer::sse_response(
+                &self.notifier,
+                StatusCode::OK,
+                self.config.http.ping_interval(),
+            )),
+            (Method::GET | Method::POST, "/status") => {
+                Ok(McpServer::json_response(self.status().to_string()))
+            }
+            (Method::GET, "/health") => Ok(self.handle_health(authorized)),
+            (Method::GET, "/discovery") => match &self.discovery {
+                Some(discovery) => Ok(McpServer::json_response(discovery.robots().to_string())),
+                None => Ok(McpServer::not_found_response()),
+            },
+            (Method::OPTIONS, _) => Ok(McpServer::cors_response()),
+            _ => Ok(McpServer::not_found_response()),
+        }
+    }
+
+    /// The robot a per-robot path is for, and the path it has on that robot:
+    /// `/mcp/<robot>` is its `/mcp`, `/robots/<robot>/<path>` its `/<path>`.
+    fn route(&self, path: &str) -> Option<(&Robot, String)> {
+        if let Some(name) = path.strip_prefix("/mcp/") {
+            return Some((self.robot(name)?, "/mcp".to_string()));
+        }
+        let rest = path.strip_prefix("/robots/")?;
+        let (name, path) = rest.split_once('/').unwrap_or((rest, "status"));
+        Some((self.robot(name)?, format!("/{}", path)))
+    }
+
+    /// `robot://<name>/stats`, a robot's `robot://stats` in the fleet.
+    fn stats_uri(name: &str) -> String {
+        format!("robot://{}/stats", name)
+    }
+
+    fn robot(&self, name: &str) -> Option<&Robot> {
+        self.robots.iter().find(|robot| robot.name == name)
+    }
+
+    fn remote(&self, name: &str) -> Option<&RemoteAdapter> {
+        self.remotes
+            .iter()
+            .find(|remote| remote.name == name)
+            .map(|remote| remote.as_ref())
+    }
+
+    async fn handle_mcp_post(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        peer: SocketAddr,
+    ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
+        let body_bytes = req.collect().await?.to_bytes();
+        let request: McpRequest = match serde_json::from_slice(&body_bytes) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed to parse MCP request: {}", e);
+                let message = format!("JSON parse error: {}", e);
+                return Ok(McpServer::error_response(-32700, &message));
+            }
+        };
+
+        let response = match request.method.as_str() {
+            "initialize" => McpServer::handle_initialize(&request).await,
+            "notifications/initialized" => {
+                info!("Received initialized notification from fleet client");
+                return Ok(McpServer::sse_response(
+                    &self.notifier,
+                    StatusCode::ACCEPTED,
+                    self.config.http.ping_interval(),
+                ));
+            }
+            "tools/list" => McpResponse::from_result(
+                request.id.clone(),
+                Ok(serde_json::json!({ "tools": self.tools() })),
+            ),
+            "tools/call" => {
+                let result = self.call_tool(request.params.as_ref(), peer).await;
+                McpResponse::from_result(request.id.clone(), result)
+            }
+            "resources/list" => {
+                let resources: Vec<Value> = self
+                    .robots
+                    .iter()
+                    .map(|robot| McpServer::stats_resource(&Self::stats_uri(&robot.name)))
+                    .collect();
+                McpResponse::from_result(
+                    request.id.clone(),
+                    Ok(serde_json::json!({ "resources": resources })),
+                )
+            }
+            "resources/read" => {
+                let uri = request
+                    .params
+                    .as_ref()
+                    .and_then(|params| params["uri"].as_str())
+                    .unwrap_or_default();
+                let result = self
+                    .robots
+                    .iter()
+                    .find(|robot| Self::stats_uri(&robot.name) == uri)
+                    .map(|robot| McpServer::resource_contents(uri, &robot.server.tool_stats()))
+                    .ok_or_else(|| McpError::new(-32602, "Unknown resource"));
+                McpResponse::from_result(request.id.clone(), result)
+            }
+            _ => McpResponse::from_result(
+                request.id.clone(),
+                Err(McpError::new(-32601, "Method not found")),
+            ),
+        };
+
+        Ok(McpServer::json_response(
+            serde_json::to_string(&response).unwrap(),
+        ))
+    }
+
+    /// `listRobots`, the discovery tools if enabled, and the tools of every
+    /// identified robot and reachable remote, prefixed with its name.
+    fn tools(&self) -> Vec<Tool> {
+        let mut tools = vec![Self::list_robots_tool()];
+        if self.discovery.is_some() {
+            tools.extend(discovery_tools().iter().cloned());
+        }
+        let local = self
+            .robots
+            .iter()
+            .map(|robot| (&robot.name, robot.server.current_tools().1));
+        let remote = self
+            .remotes
+            .iter()
+            .map(|remote| (&remote.name, remote.tools()));
+        for (prefix, prefixed) in local.chain(remote) {
+            tools.extend(prefixed.into_iter().map(|tool| Tool {
+                name: format!("{}{}{}", prefix, TOOL_SEPARATOR, tool.name),
+                description: format!("[{}] {}", prefix, tool.description),
+                input_schema: tool.input_schema,
+            }));
+        }
+        tools
+    }
+
+    async fn call_tool(&self, params: Option<&Value>, peer: SocketAddr) -> Result<Value, McpError> {
+        let ToolCallParams { name, arguments } = ToolCallParams::parse(params)?;
+        let name = name.as_str();
+        let arguments = &Value::Object(arguments);
+
+        if name == "listRobots" {
+            return Ok(McpServer::text_content(self.list_robots().to_string()));
+        }
+        if let Some(discovery) = &self.discovery {
+            match name {
+                "discoverRobots" => {
+                    return Ok(McpServer::text_content(discovery.robots().to_string()))
+                }
+                "pairRobot" => return Self::pair_robot(discovery, arguments),
+                _ => {}
+            }
+        }
+
+        let not_found = || {
+            McpError::new(
+                -32602,
+                format!(
+                    "Function not found: {} (fleet tools are named <robot>{}<tool>, see listRobots)",
+                    name, TOOL_SEPARATOR
+                ),
+            )
+        };
+        let (prefix, tool) = name.split_once(TOOL_SEPARATOR).ok_or_else(not_found)?;
+        if let Some(robot) = self.robot(prefix) {
+            return robot.server.call_tool_from(peer, tool, arguments).await;
+        }
+        match self.remote(prefix) {
+            Some(remote) => remote.call_tool(tool, arguments).await,
+            None => Err(not_found()),
+        }
+    }
+
+    fn pair_robot(discovery: &Discovery, arguments: &Value) -> Result<Value, McpError> {
+        let id = arguments["id"]
+            .as_str()
+            .ok_or_else(|| McpError::new(-32602, "Missing required parameter 'id'"))?;
+        let manifest = arguments.get("manifest").and_then(Value::as_str);
+        let robot = discovery
+            .pair(id, manifest)
+            .map_err(|e| McpError::new(-32603, format!("Pairing failed: {}", e)))?;
+        Ok(McpServer::text_content(format!(
+            "Paired '{}' at {} with manifest '{}'. Restart the adapter to serve it as robot '{}'.",
+            robot.id, robot.address, robot.manifest, robot.id
+        )))
+    }
+
+    fn list_robots(&self) -> Value {
+        let local = self.robots.iter().map(|robot| {
+            let status = robot.server.status();
+            serde_json::json!({
+                "name": robot.name,
+                "line": robot.line,
+                "ready": status["ready"],
+                "state": status["state"],
+                "device_id": status["device_id"],
+                "tool_prefix": format!("{}{}", robot.name, TOOL_SEPARATOR),
+                "endpoint": format!("/mcp/{}", robot.name)
+            })
+        });
+        let remote = self.remotes.iter().map(|remote| {
+            serde_json::json!({
+                "name": remote.name,
+                "remote": true,
+                "ready": remote.is_reachable(),
+                "tool_prefix": format!("{}{}", remote.name, TOOL_SEPARATOR),
+                "endpoint": format!("{}/mcp", remote.url)
+            })
+        });
+        local.chain(remote).collect()
+    }
+
+    fn status(&self) -> Value {
+        let robots: Vec<Value> = self
+            .robots
+            .iter()
+            .map(|robot| {
+                let mut status = robot.server.status();
+                status["name"] = robot.name.clone().into();
+                status["line"] = robot.line.clone().into();
+                status
+            })
+            .collect();
+        let remotes: Vec<Value> = self.remotes.iter().map(|r| r.status()).collect();
+        serde_json::json!({
+            "adapter_version": env!("CARGO_PKG_VERSION"),
+            "robots": robots,
+            "remotes": remotes
+        })
+    }
+
+    /// 200 while any robot or remote is ready; `status` tells whether all
+    /// of them are. Callers without the auth token only get the status.
+    fn handle_health(
+        &self,
+        authorized: bool,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let statuses: Vec<Value> = self.robots.iter().map(|r| r.server.status()).collect();
+        let reachable = self.remotes.iter().filter(|r| r.is_reachable()).count();
+        let ready = statuses.iter().filter(|s| s["ready"] == true).count() + reachable;
+        let status = match ready {
+            0 => "unavailable",
+            n if n == self.robots.len() + self.remotes.len() => "ok",
+            _ => "degraded",
+        };
+        let health = match authorized {
+            true => self.health_checks(&statuses, status),
+            false => serde_json::json!({ "status": status }),
+        };
+
+        let mut response = McpServer::json_response(health.to_string());
+        if ready == 0 {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        response
+    }
+
+    fn health_checks(&self, statuses: &[Value], status: &str) -> Value {
+        let robots: serde_json::Map<String, Value> = self
+            .robots
+            .iter()
+            .zip(statuses)
+            .map(|(robot, status)| {
+                let check = serde_json::json!({
+                    "ready": status["ready"],
+                    "state": status["state"]
+                });
+                (robot.name.clone(), check)
+            })
+            .collect();
+        let remotes: serde_json::Map<String, Value> = self
+            .remotes
+            .iter()
+            .map(|remote| {
+                let check = serde_json::json!({ "reachable": remote.is_reachable() });
+                (remote.name.clone(), check)
+            })
+            .collect();
+
+        serde_json::json!({
+            "status": status,
+            "service": "arduino-mcp-adapter",
+            "version": env!("CARGO_PKG_VERSION"),
+            "checks": { "robots": robots, "remotes": remotes }
+        })
+    }
+
+    fn list_robots_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/listRobots.json"))
+                    .expect("listRobots.json must deserialize to Tool")
+            })
+            .clone()
+    }
+}
+
+/// `discoverRobots` and `pairRobot`.
+fn discovery_tools() -> &'static [Tool] {
+    static TOOLS: OnceLock<Vec<Tool>> = OnceLock::new();
+    TOOLS.get_or_init(|| {
+        [
+            include_str!("resources/discoverRobots.json"),
+            include_str!("resources/pairRobot.json"),
+        ]
+        .iter()
+        .map(|json| serde_json::from_str(json).expect("discovery tools must deserialize to Tool"))
+        .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::Kind;
+    use crate::fixtures::{self, function};
+    use crate::loopback::LoopbackConnector;
+    use serde_json::json;
+
+    fn functions() -> Value {
+        json!([function(1, "getDistance", Some("i16"), &[])])
+    }
+
+    async fn fleet() -> (FleetServer, Vec<LoopbackConnector>, tempfile::TempDir) {
+        fleet_with(Config::default()).await
+    }
+
+    async fn fleet_with(
+        config: Config,
+    ) -> (FleetServer, Vec<LoopbackConnector>, tempfile::TempDir) {
+        let dir = fixtures::manifest_dir(&fixtures::manifest_json(functions(), json!({})));
+        let manifest_manager = Arc::new(ManifestManager::new(dir.path().to_path_buf()));
+        let config = Arc::new(config);
+
+        let mut robots = Vec::new();
+        let mut connectors = Vec::new();
+        for (name, distance) in [("red", 10i16), ("blue", 20)] {
+            let device = fixtures::device(fixtures::manifest(functions()))
+                .respond("getDistance", distance.to_le_bytes().to_vec());
+            let connector = LoopbackConnector::new(device);
+            let connection_manager = Arc::new(
+                ConnectionManager::with_connector(Box::new(connector.clone()))
+                    .with_manifest_manager(Arc::clone(&manifest_manager)),
+            );
+            connection_manager
+                .check_and_update_connection()
+                .await
+                .unwrap();
+            let server = McpServer::new(
+                connection_manager,
+                Arc::clone(&manifest_manager),
+                Arc::clone(&config),
+            );
+            robots.push(Robot {
+                name: name.to_string(),
+                line: format!("/dev/{}", name),
+                server: Arc::new(server),
+            });
+            connectors.push(connector);
+        }
+        (
+            FleetServer::new(robots, Vec::new(), config),
+            connectors,
+            dir,
+        )
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn text(result: &Value) -> &str {
+        result["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_tools_route_to_their_robot() {
+        let (fleet, connectors, _dir) = fleet().await;
+
+        let names: Vec<String> = fleet.tools().into_iter().map(|t| t.name).collect();
+        assert!(names.contains(&"listRobots".to_string()));
+        assert!(names.contains(&"red__getDistance".to_string()));
+        assert!(names.contains(&"blue__getDistance".to_string()));
+
+        let params = serde_json::json!({"name": "blue__getDistance"});
+        let result = fleet.call_tool(Some(&params), peer(1)).await.unwrap();
+        assert_eq!(text(&result), "20");
+        let calls = |c: &LoopbackConnector| {
+            c.calls()
+                .iter()
+                .filter(|(name, _)| name == "getDistance")
+                .count()
+        };
+        assert_eq!(calls(&connectors[0]), 0);
+        assert_eq!(calls(&connectors[1]), 1);
+
+        let params = serde_json::json!({"name": "green__getDistance"});
+        assert_eq!(
+            fleet
+                .call_tool(Some(&params), peer(1))
+                .await
+                .unwrap_err()
+                .code,
+            -32602
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unified_calls_respect_exclusive_control() {
+        let config = Config {
+            exclusive_control: true,
+            ..Config::default()
+        };
+        let (fleet, _connectors, _dir) = fleet_with(config).await;
+        let params = serde_json::json!({"name": "red__getDistance"});
+
+        fleet.call_tool(Some(&params), peer(1)).await.unwrap();
+        let err = fleet.call_tool(Some(&params), peer(2)).await.unwrap_err();
+        assert_eq!(err.code, Kind::Control.code());
+        // Control is per robot
+        let params = serde_json::json!({"name": "blue__getDistance"});
+        fleet.call_tool(Some(&params), peer(2)).await.unwrap();
+
+        fleet.robots[0].server.connection_closed(peer(1));
+        let params = serde_json::json!({"name": "red__getDistance"});
+        fleet.call_tool(Some(&params), peer(2)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_robots_and_routes() {
+        let (fleet, _connectors, _dir) = fleet().await;
+
+        let params = serde_json::json!({"name": "listRobots"});
+        let result = fleet.call_tool(Some(&params), peer(1)).await.unwrap();
+        let robots: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(robots[1]["name"], "blue");
+        assert_eq!(robots[1]["ready"], true);
+        assert_eq!(robots[1]["endpoint"], "/mcp/blue");
+
+        let route = |path: &str| {
+            fleet
+                .route(path)
+                .map(|(robot, path)| (robot.name.clone(), path))
+        };
+        assert_eq!(
+            route("/mcp/red"),
+            Some(("red".to_string(), "/mcp".to_string()))
+        );
+        assert_eq!(
+            route("/robots/blue/api/tools"),
+            Some(("blue".to_string(), "/api/tools".to_string()))
+        );
+        assert_eq!(
+            route("/robots/blue"),
+            Some(("blue".to_string(), "/status".to_string()))
+        );
+        assert_eq!(route("/mcp/green"), None);
+        assert_eq!(route("/status"), None);
+    }
+}
@@ -0,0 +1,203 @@
+use mcp_client::hex::to_hex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::protocol::crc8;
+use crate::slip::{slip_encode, SlipCounters};
+
+/// Number of frames kept for `/debug/frames`.
+pub const FRAME_LOG_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Host → Arduino command
+    Tx,
+    /// Arduino → Host response
+    Rx,
+}
+
+/// One SLIP frame as seen on the serial line, with its protocol fields split out.
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameRecord {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub direction: Direction,
+    /// SLIP-encoded bytes, hex
+    pub raw: String,
+    /// Frame contents after SLIP decoding, hex
+    pub frame: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u8>,
+    /// Function tag (commands only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<u8>,
+    /// Arguments for commands, return data for responses, hex
+    pub data: String,
+    pub crc: Option<u8>,
+    pub crc_ok: bool,
+    /// Error code if the response looks like an `[0xFF] [code]` error frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u8>,
+}
+
+impl FrameRecord {
+    /// Split a decoded frame into its fields. `sequenced` frames carry a
+    /// leading sequence byte (see `--pipeline-depth`).
+    pub fn parse(direction: Direction, frame: &[u8], sequenced: bool) -> Self {
+        let (body, crc) = match frame.split_last() {
+            Some((&crc, body)) => (body, Some(crc)),
+            None => (frame, None),
+        };
+        let crc_ok = crc == Some(crc8(body));
+
+        let (seq, body) = match body.split_first() {
+            Some((&seq, rest)) if sequenced => (Some(seq), rest),
+            _ => (None, body),
+        };
+
+        let (tag, data) = match direction {
+            Direction::Tx => match body.split_first() {
+                Some((&tag, args)) => (Some(tag), args),
+                None => (None, body),
+            },
+            Direction::Rx => (None, body),
+        };
+
+        let error_code = match (direction, data) {
+            (Direction::Rx, [0xFF, code]) => Some(*code),
+            _ => None,
+        };
+
+        Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            direction,
+            raw: to_hex(&slip_encode(frame)),
+            frame: to_hex(frame),
+            seq,
+            tag,
+            data: to_hex(data),
+            crc,
+            crc_ok,
+            error_code,
+        }
+    }
+}
+
+/// Ring buffer of the most recent frames in both directions.
+pub struct FrameLog {
+    frames: Mutex<VecDeque<FrameRecord>>,
+    capacity: usize,
+    /// Received frames whose CRC didn't match, since startup
+    crc_errors: AtomicU64,
+    /// Shared by the decoders reading the device's frames
+    slip: Arc<SlipCounters>,
+    /// Every frame as it is recorded, for `/transcript`
+    taps: broadcast::Sender<FrameRecord>,
+}
+
+impl FrameLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            crc_errors: AtomicU64::new(0),
+            slip: Arc::default(),
+            taps: broadcast::channel(256).0,
+        }
+    }
+
+    pub fn record(&self, direction: Direction, frame: &[u8], sequenced: bool) {
+        let record = FrameRecord::parse(direction, frame, sequenced);
+        if direction == Direction::Rx && !record.crc_ok {
+            self.crc_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.taps.receiver_count() > 0 {
+            let _ = self.taps.send(record.clone());
+        }
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() == self.capacity {
+            frames.pop_front();
+        }
+        frames.push_back(record);
+    }
+
+    /// The newest `limit` frames (all of them when `None`), oldest first.
+    pub fn recent(&self, limit: Option<usize>) -> Vec<FrameRecord> {
+        let frames = self.frames.lock().unwrap();
+        let skip = limit.map_or(0, |limit| frames.len().saturating_sub(limit));
+        frames.iter().skip(skip).cloned().collect()
+    }
+
+    /// Frames recorded from now on, however many the log keeps.
+    pub fn subscribe(&self) -> broadcast::Receiver<FrameRecord> {
+        self.taps.subscribe()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn crc_errors(&self) -> u64 {
+        self.crc_errors.load(Ordering::Relaxed)
+    }
+
+    /// For decoders of received bytes to count their trouble in.
+    pub fn slip(&self) -> Arc<SlipCounters> {
+        Arc::clone(&self.slip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command_frame() {
+        let body = [0x05, 0x64, 0x00];
+        let mut frame = body.to_vec();
+        frame.push(crc8(&body));
+
+        let record = FrameRecord::parse(Direction::Tx, &frame, false);
+        assert_eq!(record.tag, Some(5));
+        assert_eq!(record.data, "64 00");
+        assert!(record.crc_ok);
+        assert_eq!(record.seq, None);
+    }
+
+    #[test]
+    fn test_parse_sequenced_error_response_with_bad_crc() {
+        let record = FrameRecord::parse(Direction::Rx, &[0x07, 0xFF, 0x02, 0x00], true);
+        assert_eq!(record.seq, Some(7));
+        assert_eq!(record.error_code, Some(2));
+        assert!(!record.crc_ok);
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_newest() {
+        let log = FrameLog::new(2);
+        for tag in 1..=3u8 {
+            log.record(Direction::Tx, &[tag, crc8(&[tag])], false);
+        }
+
+        let tags: Vec<_> = log.recent(None).iter().map(|r| r.tag).collect();
+        assert_eq!(tags, vec![Some(2), Some(3)]);
+        assert_eq!(log.recent(Some(1))[0].tag, Some(3));
+    }
+
+    #[test]
+    fn test_counts_received_crc_errors() {
+        let log = FrameLog::new(2);
+        log.record(Direction::Rx, &[0x2A, 0x00], false);
+        log.record(Direction::Rx, &[0x2A, crc8(&[0x2A])], false);
+        log.record(Direction::Tx, &[0x01, 0x00], false);
+        assert_eq!(log.crc_errors(), 1);
+    }
+}
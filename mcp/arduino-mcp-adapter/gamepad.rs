@@ -0,0 +1,585 @@
+//! Gamepad teleoperation: joystick axes and buttons mapped to manifest
+//! functions by the TOML file given with `--gamepad`. Calls go through the
+//! same path as client tool calls. Reading gamepads needs the `gamepad`
+//! build feature.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+use crate::server::McpServer;
+
+/// Axis names usable in a mapping file.
+pub const AXES: &[&str] = &[
+    "left_stick_x",
+    "left_stick_y",
+    "left_z",
+    "right_stick_x",
+    "right_stick_y",
+    "right_z",
+    "dpad_x",
+    "dpad_y",
+];
+
+/// Button names usable in a mapping file.
+pub const BUTTONS: &[&str] = &[
+    "south",
+    "east",
+    "north",
+    "west",
+    "c",
+    "z",
+    "left_trigger",
+    "left_trigger2",
+    "right_trigger",
+    "right_trigger2",
+    "select",
+    "start",
+    "mode",
+    "left_thumb",
+    "right_thumb",
+    "dpad_up",
+    "dpad_down",
+    "dpad_left",
+    "dpad_right",
+];
+
+/// Shortest `interval_ms` allowed, so axes can't flood the robot.
+const MIN_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A mapping file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GamepadMapping {
+    /// How often axis-driven functions are called while the sticks move
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// Presses of a button this soon after its last one are ignored, so a
+    /// bouncing or mashed button doesn't flood the robot
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    #[serde(default)]
+    pub axes: Vec<AxisBinding>,
+    #[serde(default)]
+    pub buttons: Vec<ButtonBinding>,
+}
+
+fn default_interval_ms() -> u64 {
+    100
+}
+
+fn default_debounce_ms() -> u64 {
+    50
+}
+
+/// One axis feeding one argument. Axes naming the same function are sent
+/// together in one call.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisBinding {
+    pub axis: String,
+    pub function: String,
+    pub argument: String,
+    /// The axis goes from -1 to 1 and is multiplied by this
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Positions closer to the centre than this count as centred
+    #[serde(default = "default_deadzone")]
+    pub deadzone: f64,
+    /// Send whole numbers, for integer parameters
+    #[serde(default = "default_round")]
+    pub round: bool,
+    /// Fixed arguments sent along
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_deadzone() -> f64 {
+    0.1
+}
+
+fn default_round() -> bool {
+    true
+}
+
+impl AxisBinding {
+    fn value(&self, position: f64) -> Value {
+        let position = if position.abs() < self.deadzone {
+            0.0
+        } else {
+            position
+        };
+        let value = position * self.scale;
+        if self.round {
+            Value::from(value.round() as i64)
+        } else {
+            Value::from(value)
+        }
+    }
+}
+
+/// A button calling `function` when pressed, and optionally another
+/// function when released.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonBinding {
+    pub button: String,
+    pub function: String,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+    pub release: Option<ButtonAction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonAction {
+    pub function: String,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+impl GamepadMapping {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read gamepad mapping {}: {}", path.display(), e))?;
+        Self::parse(&content)
+            .map_err(|e| anyhow!("Invalid gamepad mapping {}: {}", path.display(), e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mapping: Self = toml::from_str(content)?;
+        if Duration::from_millis(mapping.interval_ms) < MIN_INTERVAL {
+            return Err(anyhow!(
+                "interval_ms must be at least {}",
+                MIN_INTERVAL.as_millis()
+            ));
+        }
+        for binding in &mapping.axes {
+            if !AXES.contains(&binding.axis.as_str()) {
+                return Err(anyhow!(
+                    "Unknown axis '{}' (use one of: {})",
+                    binding.axis,
+                    AXES.join(", ")
+                ));
+            }
+            if !(0.0..1.0).contains(&binding.deadzone) {
+                return Err(anyhow!(
+                    "Deadzone of axis '{}' must be at least 0 and below 1",
+                    binding.axis
+                ));
+            }
+        }
+        for binding in &mapping.buttons {
+            if !BUTTONS.contains(&binding.button.as_str()) {
+                return Err(anyhow!(
+                    "Unknown button '{}' (use one of: {})",
+                    binding.button,
+                    BUTTONS.join(", ")
+                ));
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+/// What the gamepad reader reports.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub enum Input {
+    Axis(&'static str, f64),
+    Button(&'static str, bool),
+    /// The gamepad went away; its axes count as centred
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Call {
+    pub function: String,
+    pub arguments: Value,
+}
+
+/// Turns gamepad input into tool calls.
+pub struct Teleop {
+    mapping: GamepadMapping,
+    /// Latest position of each moved axis
+    positions: HashMap<&'static str, f64>,
+    /// Arguments last sent to each axis-driven function
+    sent: HashMap<String, Value>,
+    /// When each button's last press that counted came
+    pressed_at: HashMap<&'static str, Instant>,
+    /// Buttons whose press counted and hasn't been released yet
+    held: HashSet<&'static str>,
+}
+
+impl Teleop {
+    pub fn new(mapping: GamepadMapping) -> Self {
+        let mut teleop = Self {
+            mapping,
+            positions: HashMap::new(),
+            sent: HashMap::new(),
+            pressed_at: HashMap::new(),
+            held: HashSet::new(),
+        };
+        // Centred sticks send nothing until they first move
+        teleop.tick();
+        teleop
+    }
+
+    /// Calls to make right away for `input`, arriving at `now`. Axis moves
+    /// are only recorded and sent by [`Teleop::tick`]. A press within
+    /// `debounce_ms` of the last one is dropped, and so is its release.
+    pub fn input(&mut self, input: Input, now: Instant) -> Vec<Call> {
+        match input {
+            Input::Axis(axis, position) => {
+                self.positions.insert(axis, position);
+                Vec::new()
+            }
+            Input::Button(button, true) => {
+                let debounce = Duration::from_millis(self.mapping.debounce_ms);
+                let bounced = self
+                    .pressed_at
+                    .get(button)
+                    .is_some_and(|at| now.saturating_duration_since(*at) < debounce);
+                if bounced || !self.held.insert(button) {
+                    debug!("Gamepad: ignored press of {}", button);
+                    return Vec::new();
+                }
+                self.pressed_at.insert(button, now);
+                self.button_calls(button, true)
+            }
+            Input::Button(button, false) => match self.held.remove(button) {
+                true => self.button_calls(button, false),
+                false => Vec::new(),
+            },
+            Input::Disconnected => {
+                self.positions.clear();
+                self.held.clear();
+                Vec::new()
+            }
+        }
+    }
+
+    fn button_calls(&self, button: &str, pressed: bool) -> Vec<Call> {
+        self.mapping
+            .buttons
+            .iter()
+            .filter(|binding| binding.button == button)
+            .filter_map(|binding| {
+                let (function, arguments) = if pressed {
+                    (&binding.function, &binding.arguments)
+                } else {
+                    let release = binding.release.as_ref()?;
+                    (&release.function, &release.arguments)
+                };
+                Some(Call {
+                    function: function.clone(),
+                    arguments: Value::Object(arguments.clone()),
+                })
+            })
+            .collect()
+    }
+
+    /// Calls for the axis-driven functions whose arguments changed since
+    /// they were last sent.
+    pub fn tick(&mut self) -> Vec<Call> {
+        let mut calls: Vec<(String, Map<String, Value>)> = Vec::new();
+        for binding in &self.mapping.axes {
+            let position = self.positions.get(binding.axis.as_str()).copied();
+            let value = binding.value(position.unwrap_or(0.0));
+            let index = match calls.iter().position(|(f, _)| *f == binding.function) {
+                Some(index) => index,
+                None => {
+                    calls.push((binding.function.clone(), Map::new()));
+                    calls.len() - 1
+                }
+            };
+            let arguments = &mut calls[index].1;
+            arguments.extend(binding.arguments.clone());
+            arguments.insert(binding.argument.clone(), value);
+        }
+
+        calls
+            .into_iter()
+            .map(|(function, arguments)| Call {
+                function,
+                arguments: Value::Object(arguments),
+            })
+            .filter(|call| {
+                if self.sent.get(&call.function) == Some(&call.arguments) {
+                    return false;
+                }
+                self.sent
+                    .insert(call.function.clone(), call.arguments.clone());
+                true
+            })
+            .collect()
+    }
+}
+
+/// Read the gamepad and drive `server`'s tools with it until shutdown.
+pub fn spawn(server: Arc<McpServer>, mapping: GamepadMapping) -> Result<()> {
+    let (inputs, receiver) = mpsc::unbounded_channel();
+    reader::start(inputs)?;
+    let interval = Duration::from_millis(mapping.interval_ms);
+    tokio::spawn(run(server, Teleop::new(mapping), receiver, interval));
+    Ok(())
+}
+
+async fn run(
+    server: Arc<McpServer>,
+    mut teleop: Teleop,
+    mut inputs: mpsc::UnboundedReceiver<Input>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // Calls are awaited one by one, so a slow robot slows the ticks
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        let calls = tokio::select! {
+            input = inputs.recv() => match input {
+                Some(input) => teleop.input(input, Instant::now()),
+                None => break,
+            },
+            _ = ticker.tick() => teleop.tick(),
+        };
+        for call in calls {
+            debug!("Gamepad: {}({})", call.function, call.arguments);
+            if let Err(e) = server.call_tool(&call.function, &call.arguments).await {
+                warn!("Gamepad call to {} failed: {}", call.function, e.message);
+            }
+        }
+    }
+    info!("Gamepad input ended");
+}
+
+#[cfg(feature = "gamepad")]
+mod reader {
+    use anyhow::{anyhow, Result};
+    use gilrs::{Axis, Button, EventType, Gilrs};
+    use std::sync::mpsc::sync_channel;
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc::UnboundedSender;
+    use tracing::{info, warn};
+
+    use super::Input;
+
+    /// Poll gamepads on their own thread, sending their input to `inputs`.
+    pub fn start(inputs: UnboundedSender<Input>) -> Result<()> {
+        let (opened, result) = sync_channel(1);
+        std::thread::spawn(move || {
+            let mut gilrs = match Gilrs::new() {
+                Ok(gilrs) => gilrs,
+                Err(e) => {
+                    let _ = opened.send(Err(anyhow!("Could not read gamepads: {}", e)));
+                    return;
+                }
+            };
+            let _ = opened.send(Ok(()));
+            for (_, gamepad) in gilrs.gamepads() {
+                info!("Gamepad: {}", gamepad.name());
+            }
+
+            while !inputs.is_closed() {
+                let Some(event) = gilrs.next_event_blocking(Some(Duration::from_secs(1))) else {
+                    continue;
+                };
+                let input = match event.event {
+                    EventType::AxisChanged(axis, position, _) => match axis_name(axis) {
+                        Some(name) => Input::Axis(name, position.into()),
+                        None => continue,
+                    },
+                    EventType::ButtonPressed(button, _) => match button_name(button) {
+                        Some(name) => Input::Button(name, true),
+                        None => continue,
+                    },
+                    EventType::ButtonReleased(button, _) => match button_name(button) {
+                        Some(name) => Input::Button(name, false),
+                        None => continue,
+                    },
+                    EventType::Connected => {
+                        info!("Gamepad connected: {}", gilrs.gamepad(event.id).name());
+                        continue;
+                    }
+                    EventType::Disconnected => {
+                        warn!("Gamepad disconnected, centring its axes");
+                        Input::Disconnected
+                    }
+                    _ => continue,
+                };
+                if inputs.send(input).is_err() {
+                    break;
+                }
+            }
+        });
+        result
+            .recv()
+            .map_err(|_| anyhow!("Gamepad thread exited"))?
+    }
+
+    fn axis_name(axis: Axis) -> Option<&'static str> {
+        Some(match axis {
+            Axis::LeftStickX => "left_stick_x",
+            Axis::LeftStickY => "left_stick_y",
+            Axis::LeftZ => "left_z",
+            Axis::RightStickX => "right_stick_x",
+            Axis::RightStickY => "right_stick_y",
+            Axis::RightZ => "right_z",
+            Axis::DPadX => "dpad_x",
+            Axis::DPadY => "dpad_y",
+            Axis::Unknown => return None,
+        })
+    }
+
+    fn button_name(button: Button) -> Option<&'static str> {
+        Some(match button {
+            Button::South => "south",
+            Button::East => "east",
+            Button::North => "north",
+            Button::West => "west",
+            Button::C => "c",
+            Button::Z => "z",
+            Button::LeftTrigger => "left_trigger",
+            Button::LeftTrigger2 => "left_trigger2",
+            Button::RightTrigger => "right_trigger",
+            Button::RightTrigger2 => "right_trigger2",
+            Button::Select => "select",
+            Button::Start => "start",
+            Button::Mode => "mode",
+            Button::LeftThumb => "left_thumb",
+            Button::RightThumb => "right_thumb",
+            Button::DPadUp => "dpad_up",
+            Button::DPadDown => "dpad_down",
+            Button::DPadLeft => "dpad_left",
+            Button::DPadRight => "dpad_right",
+            Button::Unknown => return None,
+        })
+    }
+}
+
+#[cfg(not(feature = "gamepad"))]
+mod reader {
+    use anyhow::{anyhow, Result};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::Input;
+
+    pub fn start(_inputs: UnboundedSender<Input>) -> Result<()> {
+        Err(anyhow!(
+            "This adapter was built without gamepad support; rebuild it with `--features gamepad`"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MAPPING: &str = r#"
+        interval_ms = 50
+
+        [[axes]]
+        axis = "left_stick_y"
+        function = "drive"
+        argument = "speed"
+        scale = 255
+
+        [[axes]]
+        axis = "left_stick_x"
+        function = "drive"
+        argument = "turn"
+        scale = 100
+
+        [[buttons]]
+        button = "south"
+        function = "setServo"
+        arguments = { angle = 90 }
+        release = { function = "setServo", arguments = { angle = 0 } }
+
+        [[buttons]]
+        button = "east"
+        function = "stopMotors"
+    "#;
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = GamepadMapping::parse(MAPPING).unwrap();
+        assert_eq!(mapping.interval_ms, 50);
+        assert_eq!(mapping.axes[0].deadzone, 0.1);
+        assert!(mapping.axes[0].round);
+
+        let unknown = MAPPING.replace("\"east\"", "\"triangle\"");
+        let err = GamepadMapping::parse(&unknown).unwrap_err();
+        assert!(err.to_string().contains("Unknown button 'triangle'"));
+        assert!(GamepadMapping::parse("interval_ms = 5").is_err());
+    }
+
+    #[test]
+    fn test_axes_are_combined_and_only_sent_on_change() {
+        let mut teleop = Teleop::new(GamepadMapping::parse(MAPPING).unwrap());
+        // Centred at start
+        assert!(teleop.tick().is_empty());
+
+        let now = Instant::now();
+        teleop.input(Input::Axis("left_stick_y", 0.5), now);
+        teleop.input(Input::Axis("left_stick_y", 1.0), now);
+        teleop.input(Input::Axis("left_stick_x", 0.05), now);
+        let calls = teleop.tick();
+        assert_eq!(
+            calls,
+            vec![Call {
+                function: "drive".to_string(),
+                arguments: json!({"speed": 255, "turn": 0}),
+            }]
+        );
+        assert!(teleop.tick().is_empty());
+
+        // Losing the gamepad stops the robot
+        teleop.input(Input::Disconnected, now);
+        assert_eq!(teleop.tick()[0].arguments, json!({"speed": 0, "turn": 0}));
+    }
+
+    #[test]
+    fn test_buttons_call_on_press_and_release() {
+        let mut teleop = Teleop::new(GamepadMapping::parse(MAPPING).unwrap());
+        let now = Instant::now();
+        let pressed = teleop.input(Input::Button("south", true), now);
+        assert_eq!(pressed[0].function, "setServo");
+        assert_eq!(pressed[0].arguments, json!({"angle": 90}));
+        let released = teleop.input(Input::Button("south", false), now);
+        assert_eq!(released[0].arguments, json!({"angle": 0}));
+
+        assert_eq!(teleop.input(Input::Button("east", true), now).len(), 1);
+        assert!(teleop.input(Input::Button("east", false), now).is_empty());
+        assert!(teleop.input(Input::Button("north", true), now).is_empty());
+    }
+
+    #[test]
+    fn test_button_presses_are_debounced() {
+        let mut teleop = Teleop::new(GamepadMapping::parse(MAPPING).unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let south = |pressed| Input::Button("south", pressed);
+        assert_eq!(teleop.input(south(true), at(0)).len(), 1);
+        assert_eq!(teleop.input(south(false), at(5)).len(), 1);
+        // A bounce: the press and its release are both dropped
+        assert!(teleop.input(south(true), at(10)).is_empty());
+        assert!(teleop.input(south(false), at(15)).is_empty());
+
+        assert_eq!(teleop.input(south(true), at(60)).len(), 1);
+        // A repeated press while held counts once
+        assert!(teleop.input(south(true), at(200)).is_empty());
+        assert_eq!(teleop.input(south(false), at(300)).len(), 1);
+    }
+}
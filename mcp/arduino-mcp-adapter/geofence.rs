@@ -0,0 +1,203 @@
+//! Geofence: refuse drive commands that would take the robot out of a
+//! configured rectangle, judged from the pose its odometry reports and the
+//! distance the command asks for.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+/// The allowed area, from the `[geofence]` config section, in the units of
+/// the robot's odometry.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    /// `[min, max]`
+    pub x: [f64; 2],
+    pub y: [f64; 2],
+}
+
+impl Bounds {
+    fn contains(&self, x: f64, y: f64) -> bool {
+        (self.x[0]..=self.x[1]).contains(&x) && (self.y[0]..=self.y[1]).contains(&y)
+    }
+}
+
+/// Where the robot is, as returned by the manifest's odometry getter.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct Pose {
+    pub x: f64,
+    pub y: f64,
+    /// Degrees counter-clockwise from the x axis
+    pub heading: f64,
+}
+
+impl Pose {
+    /// Parse `x,y,heading`; commas and whitespace both separate.
+    pub fn parse(reading: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid pose '{}' (expected x,y,heading)", reading);
+        let values = reading
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>>>()?;
+        match values[..] {
+            [x, y, heading] => Ok(Self { x, y, heading }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Where driving `distance` straight ahead ends up.
+    fn ahead(&self, distance: f64) -> (f64, f64) {
+        let heading = self.heading.to_radians();
+        (
+            self.x + distance * heading.cos(),
+            self.y + distance * heading.sin(),
+        )
+    }
+}
+
+/// A drive command refused by the geofence.
+#[derive(Debug, Clone, Serialize)]
+pub struct GeofenceViolation {
+    pub function: String,
+    pub pose: Pose,
+    /// `[x, y]` the command would have ended at
+    pub target: [f64; 2],
+    pub bounds: Bounds,
+}
+
+impl fmt::Display for GeofenceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' would drive from ({:.0}, {:.0}) to ({:.0}, {:.0}), outside the geofence x {:?}, y {:?}",
+            self.function,
+            self.pose.x,
+            self.pose.y,
+            self.target[0],
+            self.target[1],
+            self.bounds.x,
+            self.bounds.y
+        )
+    }
+}
+
+impl std::error::Error for GeofenceViolation {}
+
+pub struct Geofence {
+    bounds: Bounds,
+    /// Last pose read from the robot
+    pose: Mutex<Option<Pose>>,
+}
+
+impl Geofence {
+    pub fn new(bounds: Bounds) -> Result<Self> {
+        if bounds.x[0] >= bounds.x[1] || bounds.y[0] >= bounds.y[1] {
+            return Err(anyhow!(
+                "Geofence bounds must be [min, max] with min below max"
+            ));
+        }
+        Ok(Self {
+            bounds,
+            pose: Mutex::new(None),
+        })
+    }
+
+    /// Allow `function` to drive `distance` from `pose` only if it ends up
+    /// inside the bounds. Driving back in from outside is allowed.
+    pub fn check(
+        &self,
+        function: &str,
+        pose: Pose,
+        distance: f64,
+    ) -> std::result::Result<(), GeofenceViolation> {
+        *self.pose.lock().unwrap() = Some(pose);
+        let (x, y) = pose.ahead(distance);
+        if self.bounds.contains(x, y) {
+            return Ok(());
+        }
+        Err(GeofenceViolation {
+            function: function.to_string(),
+            pose,
+            target: [x, y],
+            bounds: self.bounds,
+        })
+    }
+
+    pub fn pose(&self) -> Option<Pose> {
+        *self.pose.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionManager;
+    use crate::fixtures::{self, function, DEVICE_ID};
+    use crate::loopback::LoopbackConnector;
+    use crate::manifest::ManifestManager;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_pose() {
+        let pose = Pose::parse("100, -20.5 90").unwrap();
+        assert_eq!((pose.x, pose.y, pose.heading), (100.0, -20.5, 90.0));
+        assert!(Pose::parse("1,2").is_err());
+        assert!(Pose::parse("1,2,north").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refuses_drives_leaving_bounds() {
+        let manifest = fixtures::manifest_json(
+            json!([
+                function(1, "getPose", Some("CStr"), &[]),
+                function(2, "driveDistance", None, &[("mm", "i16")])
+            ]),
+            json!({"odometry": {"pose": "getPose", "drive": {"driveDistance": "mm"}}}),
+        );
+        let dir = fixtures::manifest_dir(&manifest);
+        let manifest_manager = Arc::new(ManifestManager::new(dir.path().to_path_buf()));
+        let device = fixtures::device(serde_json::from_value(manifest).unwrap())
+            .respond("getPose", b"300,0,0\0".to_vec());
+        let connector = LoopbackConnector::new(device);
+        let bounds = Bounds {
+            x: [-400.0, 400.0],
+            y: [-300.0, 300.0],
+        };
+        let connection_manager = ConnectionManager::with_connector(Box::new(connector.clone()))
+            .with_geofence(Geofence::new(bounds).unwrap())
+            .with_manifest_manager(Arc::clone(&manifest_manager));
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        let manifest = manifest_manager.get_manifest(DEVICE_ID).unwrap();
+        let drive = &manifest.functions[1];
+
+        connection_manager
+            .execute_function(drive, &serde_json::json!({"mm": 50}))
+            .await
+            .unwrap();
+        // Reversing away from the edge is fine, going over it is not
+        connection_manager
+            .execute_function(drive, &serde_json::json!({"mm": -500}))
+            .await
+            .unwrap();
+        let err = connection_manager
+            .execute_function(drive, &serde_json::json!({"mm": 200}))
+            .await
+            .unwrap_err();
+        let violation = err.downcast_ref::<GeofenceViolation>().unwrap();
+        assert_eq!(violation.target, [500.0, 0.0]);
+        assert_eq!(connection_manager.pose().unwrap().x, 300.0);
+
+        let drives = connector
+            .calls()
+            .iter()
+            .filter(|(name, _)| name == "driveDistance")
+            .count();
+        assert_eq!(drives, 2);
+    }
+}
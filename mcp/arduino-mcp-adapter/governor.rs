@@ -0,0 +1,230 @@
+//! Safety limits on device calls: clamp numeric arguments, cap how fast they
+//! may change and space out calls, whatever the client asked for.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Limits for a group of functions, from a `[[limits]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitSpec {
+    /// Manifest functions these limits apply to
+    pub functions: Vec<String>,
+    /// Allowed `[min, max]` range of each parameter
+    #[serde(default)]
+    pub clamp: HashMap<String, [i64; 2]>,
+    /// Largest change per second of each parameter
+    #[serde(default)]
+    pub max_rate: HashMap<String, f64>,
+    /// Calls to any of the functions are held back to be this far apart
+    pub min_interval_ms: Option<u64>,
+}
+
+struct Rule {
+    spec: LimitSpec,
+    state: Mutex<RuleState>,
+}
+
+#[derive(Default)]
+struct RuleState {
+    /// Earliest time the next call may go out
+    next_slot: Option<Instant>,
+    /// Last value sent for each rate-limited parameter
+    last: HashMap<String, (i64, Instant)>,
+}
+
+/// Applies the configured [`LimitSpec`]s in front of the device.
+#[derive(Default)]
+pub struct Governor {
+    rules: Vec<Rule>,
+}
+
+impl Governor {
+    pub fn new(specs: &[LimitSpec]) -> Result<Self> {
+        for spec in specs {
+            if spec.functions.is_empty() {
+                return Err(anyhow!("Limits need at least one function"));
+            }
+            for (param, [min, max]) in &spec.clamp {
+                if min > max {
+                    return Err(anyhow!(
+                        "Clamp range of '{}' is empty: {} > {}",
+                        param,
+                        min,
+                        max
+                    ));
+                }
+            }
+            if let Some((param, _)) = spec.max_rate.iter().find(|(_, rate)| **rate <= 0.0) {
+                return Err(anyhow!("max_rate of '{}' must be positive", param));
+            }
+        }
+        let rules = specs
+            .iter()
+            .map(|spec| Rule {
+                spec: spec.clone(),
+                state: Mutex::new(RuleState::default()),
+            })
+            .collect();
+        Ok(Self { rules })
+    }
+
+    /// `arguments` for `function` brought within the limits of every rule
+    /// that names it, once their minimum intervals have passed.
+    pub async fn apply(&self, function: &str, arguments: &Value) -> Value {
+        let mut arguments = arguments.clone();
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.spec.functions.iter().any(|f| f == function));
+        for rule in rules {
+            rule.wait_turn(function).await;
+            rule.limit(function, &mut arguments);
+        }
+        arguments
+    }
+}
+
+impl Rule {
+    async fn wait_turn(&self, function: &str) {
+        let Some(interval) = self.spec.min_interval_ms.map(Duration::from_millis) else {
+            return;
+        };
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let slot = state.next_slot.map_or(now, |slot| slot.max(now));
+            state.next_slot = Some(slot + interval);
+            slot - now
+        };
+        if !wait.is_zero() {
+            debug!("Holding '{}' back for {:?}", function, wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn limit(&self, function: &str, arguments: &mut Value) {
+        let Some(params) = arguments.as_object_mut() else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        for (param, value) in params.iter_mut() {
+            let Some(requested) = value.as_i64() else {
+                continue;
+            };
+            let mut limited = requested;
+            if let Some([min, max]) = self.spec.clamp.get(param) {
+                limited = limited.clamp(*min, *max);
+            }
+            if let Some(rate) = self.spec.max_rate.get(param) {
+                if let Some((last, at)) = state.last.get(param) {
+                    let step = (rate * now.duration_since(*at).as_secs_f64()) as i64;
+                    limited = limited.clamp(last.saturating_sub(step), last.saturating_add(step));
+                }
+                state.last.insert(param.clone(), (limited, now));
+            }
+            if limited != requested {
+                warn!(
+                    "Limited {} of '{}' from {} to {}",
+                    param, function, requested, limited
+                );
+                *value = Value::from(limited);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::json;
+
+    fn spec(toml: &str) -> Vec<LimitSpec> {
+        Config::parse(toml).unwrap().limits
+    }
+
+    #[tokio::test]
+    async fn test_clamp_and_rate() {
+        let governor = Governor::new(&spec(
+            r#"
+            [[limits]]
+            functions = ["drive"]
+            clamp = { speed = [-150, 150] }
+
+            [[limits]]
+            functions = ["setServo"]
+            clamp = { angle = [0, 180] }
+            max_rate = { angle = 100 }
+            "#,
+        ))
+        .unwrap();
+
+        let drive = governor.apply("drive", &json!({"speed": 255})).await;
+        assert_eq!(drive, json!({"speed": 150}));
+        let other = governor.apply("stopMotors", &json!({"speed": 255})).await;
+        assert_eq!(other, json!({"speed": 255}));
+
+        // The first position is only clamped; the next may move 100°/s
+        let first = governor.apply("setServo", &json!({"angle": 200})).await;
+        assert_eq!(first, json!({"angle": 180}));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let next = governor.apply("setServo", &json!({"angle": 0})).await;
+        let angle = next["angle"].as_i64().unwrap();
+        assert!((1..=170).contains(&angle), "moved to {}", angle);
+    }
+
+    #[tokio::test]
+    async fn test_min_interval_spaces_calls() {
+        let governor = Governor::new(&spec(
+            r#"
+            [[limits]]
+            functions = ["drive", "turn"]
+            min_interval_ms = 40
+            "#,
+        ))
+        .unwrap();
+
+        let started = Instant::now();
+        for function in ["drive", "turn", "drive"] {
+            governor.apply(function, &json!({})).await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(80));
+
+        // A function in several groups gets the limits of each
+        let governor = Governor::new(&spec(
+            r#"
+            [[limits]]
+            functions = ["drive", "turn"]
+            clamp = { speed = [-150, 150] }
+
+            [[limits]]
+            functions = ["drive"]
+            clamp = { speed = [-100, 200] }
+            min_interval_ms = 40
+            "#,
+        ))
+        .unwrap();
+        let started = Instant::now();
+        for _ in 0..2 {
+            let drive = governor.apply("drive", &json!({"speed": 255})).await;
+            assert_eq!(drive, json!({"speed": 150}));
+        }
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        assert!(Governor::new(&spec(
+            r#"
+            [[limits]]
+            functions = ["drive"]
+            clamp = { speed = [10, -10] }
+            "#,
+        ))
+        .is_err());
+    }
+}
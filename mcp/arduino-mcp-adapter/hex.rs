@@ -0,0 +1,48 @@
+//! Bytes as hex text, for frame logs and raw frames typed by users.
+
+use anyhow::{anyhow, Result};
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse hex such as `"0A FF"` or `"0aff"`; whitespace is ignored.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.is_ascii() {
+        return Err(anyhow!("Invalid hex '{}'", text));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(anyhow!("Hex '{}' has an odd number of digits", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex '{}'", text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("0A ff 10").unwrap(), vec![0x0A, 0xFF, 0x10]);
+        assert_eq!(parse_hex("").unwrap(), Vec::<u8>::new());
+        assert!(parse_hex("ABC").is_err());
+        assert!(parse_hex("zz").is_err());
+        assert!(parse_hex("é1").is_err());
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x0A, 0xFF]), "0A FF");
+        assert_eq!(parse_hex(&to_hex(&[1, 2, 3])).unwrap(), vec![1, 2, 3]);
+    }
+}
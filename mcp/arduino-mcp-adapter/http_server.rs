@@ -0,0 +1,123 @@
+//! How HTTP connections are served: HTTP/1.1, and HTTP/2 over cleartext
+//! when a client opens with the HTTP/2 preface, with the timeouts from the
+//! `[http]` config section.
+
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto;
+
+use crate::config::HttpConfig;
+
+/// How long an HTTP/2 client gets to answer a ping before its connection
+/// is closed.
+const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
+pub fn builder(config: &HttpConfig) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive)
+        // Also runs while a kept-alive connection waits for its next request
+        .header_read_timeout(config.idle_timeout());
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .keep_alive_interval(config.ping_interval())
+        .keep_alive_timeout(PING_TIMEOUT);
+    if config.http2 {
+        builder
+    } else {
+        builder.http1_only()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::notifications::Notifier;
+    use crate::server::McpServer;
+
+    /// Serve "ok" on a local port with `config`.
+    async fn serve(config: HttpConfig) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder(&config);
+                tokio::spawn(async move {
+                    let service = service_fn(|_req| async {
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("ok"))))
+                    });
+                    let _ = builder
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_serves_h2c_and_closes_idle_connections() {
+        let addr = serve(HttpConfig {
+            idle_timeout_secs: 1,
+            ..Default::default()
+        })
+        .await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+        let response = client
+            .send_request(
+                Request::get(format!("http://{}/", addr))
+                    .body(Empty::<Bytes>::new())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), hyper::Version::HTTP_2);
+
+        // An HTTP/1.1 client that goes quiet after its request is dropped
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+            .await
+            .expect("idle connection was not closed")
+            .unwrap();
+        assert!(received.starts_with(b"HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_pings() {
+        let notifier = Notifier::new();
+        let response =
+            McpServer::sse_response(&notifier, StatusCode::OK, Some(Duration::from_millis(20)));
+        let mut body = response.into_body();
+        let frame = tokio::time::timeout(Duration::from_secs(1), body.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.into_data().unwrap(), Bytes::from(": ping\n\n"));
+    }
+}
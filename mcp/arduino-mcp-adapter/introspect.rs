@@ -0,0 +1,265 @@
+//! `dump-manifest` subcommand: read the function signatures of firmware
+//! built with `MCP_INTROSPECTION` and write them out as a starter manifest,
+//! rather than copying them from the sketch by hand.
+//!
+//! `[0xFC] [index]` is answered with `[count] [tag] [return] [param count]
+//! name\0 desc\0` and `[type] name\0` per parameter while `index` is below
+//! the function count, and with `[count] name\0 description\0` from there
+//! on. Types are sent as codes into [`TYPES`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::Path;
+
+use crate::call;
+use crate::connection::ConnectionManager;
+use crate::manifest::{Ack, Function, Manifest, ManifestManager, Parameter};
+use crate::protocol::{device_error, ResponseDecoder, DESCRIBE_FUNCTION_TAG, MANIFEST_VERSION_TAG};
+
+/// Return and parameter types by code; 0 is a function returning nothing.
+const TYPES: [Option<&str>; 6] = [
+    None,
+    Some("i16"),
+    Some("i32"),
+    Some("CStr"),
+    Some("blob"),
+    Some("image"),
+];
+
+fn type_code(name: Option<&str>) -> u8 {
+    let name = name.filter(|name| *name != "void");
+    TYPES.iter().position(|t| *t == name).unwrap_or(0) as u8
+}
+
+/// What firmware with introspection answers `[0xFC] [index]` with, for the
+/// loopback device.
+pub fn describe(manifest: &Manifest, index: u8) -> Vec<u8> {
+    let mut data = vec![manifest.functions.len() as u8];
+    let cstring = |data: &mut Vec<u8>, text: &str| {
+        data.extend_from_slice(text.as_bytes());
+        data.push(0);
+    };
+    match manifest.functions.get(usize::from(index)) {
+        Some(func) => {
+            data.extend([
+                func.tag,
+                type_code(func.return_type.as_deref()),
+                func.params.len() as u8,
+            ]);
+            cstring(&mut data, &func.name);
+            cstring(&mut data, &func.desc);
+            for param in &func.params {
+                data.push(type_code(Some(&param.param_type)));
+                cstring(&mut data, &param.name);
+            }
+        }
+        None => {
+            cstring(&mut data, &manifest.name);
+            cstring(&mut data, &manifest.description);
+        }
+    }
+    data
+}
+
+/// Ask for the description at `index`, returning the function count and
+/// the rest of the answer.
+async fn query(connection_manager: &ConnectionManager, index: u8) -> Result<(u8, Vec<u8>)> {
+    let data = connection_manager
+        .execute_raw(DESCRIBE_FUNCTION_TAG, &[index])
+        .await?;
+    if let Some(error) = device_error(None, &data) {
+        bail!(
+            "Firmware doesn't describe its functions ({}); rebuild it with \
+             `#define MCP_INTROSPECTION` before including mcp.hpp",
+            error
+        );
+    }
+    let (&count, rest) = data
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty answer to function description {}", index))?;
+    Ok((count, rest.to_vec()))
+}
+
+fn decode_function(data: &[u8]) -> Result<Function> {
+    let mut decoder = ResponseDecoder::new(data);
+    let tag = decoder.read_u8()?;
+    let return_code = decoder.read_u8()?;
+    let param_count = decoder.read_u8()?;
+    let name = decoder.read_cstring()?;
+    let desc = decoder.read_cstring()?;
+    let return_type = *TYPES
+        .get(usize::from(return_code))
+        .ok_or_else(|| anyhow!("'{}' has unknown return type code {}", name, return_code))?;
+    let params = (0..param_count)
+        .map(|_| {
+            let code = decoder.read_u8()?;
+            let param_name = decoder.read_cstring()?;
+            let param_type = match code {
+                1..=3 => TYPES[usize::from(code)].unwrap(),
+                _ => bail!(
+                    "Parameter '{}' of '{}' has unknown type code {}",
+                    param_name,
+                    name,
+                    code
+                ),
+            };
+            Ok(Parameter {
+                name: param_name,
+                param_type: param_type.to_string(),
+                minimum: None,
+                maximum: None,
+                unit: None,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Function {
+        tag,
+        name,
+        desc,
+        return_type: return_type.map(str::to_string),
+        params,
+        ack: Ack::Complete,
+        mutates: None,
+        debounce_ms: None,
+    })
+}
+
+/// Build a manifest from what the connected firmware reports about itself.
+pub async fn dump(connection_manager: &ConnectionManager) -> Result<Manifest> {
+    let (count, _) = query(connection_manager, 0).await?;
+    let mut functions = Vec::with_capacity(usize::from(count));
+    for index in 0..count {
+        let (_, data) = query(connection_manager, index).await?;
+        let func = decode_function(&data)
+            .with_context(|| format!("Malformed description of function {}", index))?;
+        functions.push(func);
+    }
+    let (_, data) = query(connection_manager, count).await?;
+    let mut decoder = ResponseDecoder::new(&data);
+    let name = decoder.read_cstring()?;
+    let description = decoder.read_cstring()?;
+
+    // Firmware from before getManifestVersion leaves the version to fill in
+    let data = connection_manager
+        .execute_raw(MANIFEST_VERSION_TAG, &[])
+        .await?;
+    let version = match device_error(Some("CStr"), &data) {
+        Some(_) => String::new(),
+        None => ResponseDecoder::new(&data).read_cstring()?,
+    };
+
+    Ok(Manifest {
+        name,
+        description,
+        version,
+        functions,
+        safe_state: None,
+        on_connect: None,
+        on_disconnect: None,
+        busy: None,
+        watchdog: None,
+        battery: None,
+        odometry: None,
+        board: None,
+        composites: Vec::new(),
+        self_test: Vec::new(),
+        fallback: false,
+    })
+}
+
+/// Connect, read the firmware's functions and write them as a manifest to
+/// `output` ("-" for stdout), by default where the adapter would look for
+/// the device's manifest. An existing file is kept unless `force` is set.
+pub async fn run(
+    connection_manager: &ConnectionManager,
+    manifest_manager: &ManifestManager,
+    output: Option<&Path>,
+    force: bool,
+) -> Result<()> {
+    let device_id = call::connect(connection_manager).await?;
+    let manifest = dump(connection_manager).await?;
+    let json = serde_json::to_string_pretty(&manifest)? + "\n";
+
+    if output == Some(Path::new("-")) {
+        print!("{}", json);
+        return Ok(());
+    }
+    let path = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| manifest_manager.manifest_path(&device_id));
+    if path.exists() && !force {
+        bail!(
+            "{} already exists; pass --force to overwrite it or --output to write elsewhere",
+            path.display()
+        );
+    }
+    std::fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+    println!(
+        "Wrote {} functions of {} to {}",
+        manifest.functions.len(),
+        device_id,
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, function};
+    use crate::loopback::{LoopbackConnector, LoopbackDevice};
+    use crate::manifest_schema;
+    use serde_json::json;
+
+    fn manifest() -> Manifest {
+        let mut drive = function(1, "drive", None, &[("mm", "i32"), ("label", "CStr")]);
+        drive["params"][0]["unit"] = json!("mm");
+        let mut snapshot = function(2, "snapshot", Some("image"), &[]);
+        snapshot["mutates"] = json!(false);
+        fixtures::manifest(json!([
+            function(0, "deviceId", Some("CStr"), &[]),
+            drive,
+            snapshot
+        ]))
+    }
+
+    fn connection_manager(device: LoopbackDevice) -> ConnectionManager {
+        ConnectionManager::with_connector(Box::new(LoopbackConnector::new(device)))
+    }
+
+    #[tokio::test]
+    async fn test_dump_reads_back_the_firmware_signatures() {
+        let device = fixtures::device(manifest()).chunked().introspection();
+        let connection_manager = connection_manager(device);
+        call::connect(&connection_manager).await.unwrap();
+
+        let manifest = dump(&connection_manager).await.unwrap();
+        let json = serde_json::to_string_pretty(&manifest).unwrap();
+        // What the firmware can't know is left out, and the result is valid
+        let reparsed: Manifest = manifest_schema::parse(&json, true, "dump").unwrap();
+        assert_eq!(
+            (reparsed.name.as_str(), reparsed.version.as_str()),
+            ("test-robot", "v1")
+        );
+        let drive = &reparsed.functions[1];
+        assert_eq!((drive.tag, drive.return_type.as_deref()), (1, None));
+        let params: Vec<(&str, &str)> = drive
+            .params
+            .iter()
+            .map(|p| (p.name.as_str(), p.param_type.as_str()))
+            .collect();
+        assert_eq!(params, [("mm", "i32"), ("label", "CStr")]);
+        assert!(drive.params[0].unit.is_none());
+        assert_eq!(reparsed.functions[2].return_type.as_deref(), Some("image"));
+        assert_eq!(reparsed.functions[2].mutates, None);
+    }
+
+    #[tokio::test]
+    async fn test_dump_explains_firmware_without_introspection() {
+        let device = fixtures::device(manifest());
+        let connection_manager = connection_manager(device);
+        call::connect(&connection_manager).await.unwrap();
+
+        let err = dump(&connection_manager).await.unwrap_err();
+        assert!(err.to_string().contains("MCP_INTROSPECTION"), "{}", err);
+    }
+}
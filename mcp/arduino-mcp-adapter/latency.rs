@@ -0,0 +1,170 @@
+//! Round-trip time of each device function, and the response deadline it
+//! earns: its recent p99 times a factor. A slow function isn't cut off by a
+//! deadline meant for quick ones, and a dead link fails quick calls in a
+//! fraction of a fixed timeout.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::pipeline::RESPONSE_TIMEOUT;
+
+/// Default multiple of a function's p99 round trip it may take.
+pub const DEFAULT_TIMEOUT_FACTOR: f64 = 3.0;
+
+/// Round trips kept per function for the p99.
+const WINDOW: usize = 64;
+/// Round trips a function needs before it gets its own deadline.
+const MIN_SAMPLES: usize = 8;
+/// Shortest deadline, so scheduling jitter on a fast link isn't a timeout.
+const MIN_TIMEOUT: Duration = Duration::from_millis(250);
+/// Longest deadline: no function waits longer than one without its own.
+const MAX_TIMEOUT: Duration = RESPONSE_TIMEOUT;
+/// Weight of the newest round trip in the moving average.
+const EMA_WEIGHT: f64 = 0.2;
+
+#[derive(Default)]
+struct Record {
+    ema_ms: f64,
+    recent: VecDeque<Duration>,
+    timeouts: u64,
+    /// Timeouts since the last response, each doubling the deadline
+    missed: u32,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LatencySnapshot {
+    pub samples: usize,
+    /// Exponential moving average
+    pub ema_ms: f64,
+    pub p99_ms: f64,
+    pub timeouts: u64,
+    /// Deadline for the next call; `None` until there are enough samples
+    pub timeout_ms: Option<f64>,
+}
+
+/// Round trips by command tag.
+pub struct LatencyTracker {
+    /// 0 turns adaptive deadlines off
+    factor: f64,
+    tags: Mutex<HashMap<u8, Record>>,
+}
+
+impl LatencyTracker {
+    pub fn new(factor: f64) -> Self {
+        Self {
+            factor,
+            tags: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a round trip to `tag`.
+    pub fn record(&self, tag: u8, elapsed: Duration) {
+        let mut tags = self.tags.lock().unwrap();
+        let record = tags.entry(tag).or_default();
+        record.missed = 0;
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        record.ema_ms = match record.recent.is_empty() {
+            true => ms,
+            false => record.ema_ms + EMA_WEIGHT * (ms - record.ema_ms),
+        };
+        if record.recent.len() == WINDOW {
+            record.recent.pop_front();
+        }
+        record.recent.push_back(elapsed);
+    }
+
+    /// Record a call to `tag` that timed out. It isn't a round trip, so
+    /// it leaves the p99 alone, but the next deadline doubles until a
+    /// response arrives, so a function that got slower isn't cut off
+    /// every time.
+    pub fn record_timeout(&self, tag: u8) {
+        let mut tags = self.tags.lock().unwrap();
+        let record = tags.entry(tag).or_default();
+        record.timeouts += 1;
+        record.missed = record.missed.saturating_add(1);
+    }
+
+    /// How long to wait for `tag`'s response, once it has enough round
+    /// trips to go by.
+    pub fn timeout(&self, tag: u8) -> Option<Duration> {
+        let tags = self.tags.lock().unwrap();
+        timeout(self.factor, tags.get(&tag)?)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<u8, LatencySnapshot> {
+        let tags = self.tags.lock().unwrap();
+        tags.iter()
+            .map(|(&tag, record)| {
+                let snapshot = LatencySnapshot {
+                    samples: record.recent.len(),
+                    ema_ms: record.ema_ms,
+                    p99_ms: p99(record).as_secs_f64() * 1000.0,
+                    timeouts: record.timeouts,
+                    timeout_ms: timeout(self.factor, record).map(|t| t.as_secs_f64() * 1000.0),
+                };
+                (tag, snapshot)
+            })
+            .collect()
+    }
+}
+
+/// Nearest-rank 99th percentile.
+fn p99(record: &Record) -> Duration {
+    let mut recent: Vec<Duration> = record.recent.iter().copied().collect();
+    recent.sort();
+    let rank = (recent.len() * 99).div_ceil(100);
+    recent
+        .get(rank.saturating_sub(1))
+        .copied()
+        .unwrap_or_default()
+}
+
+fn timeout(factor: f64, record: &Record) -> Option<Duration> {
+    if factor <= 0.0 || record.recent.len() < MIN_SAMPLES {
+        return None;
+    }
+    let backoff = 2f64.powi(record.missed.min(16) as i32);
+    let nanos = p99(record).as_nanos() as f64 * factor * backoff;
+    Some(Duration::from_nanos(nanos as u64).clamp(MIN_TIMEOUT, MAX_TIMEOUT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_follows_p99_and_backs_off_after_a_timeout() {
+        let tracker = LatencyTracker::new(3.0);
+        for _ in 0..MIN_SAMPLES - 1 {
+            tracker.record(1, Duration::from_millis(100));
+        }
+        assert_eq!(tracker.timeout(1), None);
+        tracker.record(1, Duration::from_millis(200));
+        // 200 ms p99 × 3
+        assert_eq!(tracker.timeout(1), Some(Duration::from_millis(600)));
+        assert_eq!(tracker.snapshot()[&1].ema_ms, 120.0);
+
+        // A quick function still gets the floor
+        for _ in 0..MIN_SAMPLES {
+            tracker.record(2, Duration::from_millis(1));
+        }
+        assert_eq!(tracker.timeout(2), Some(MIN_TIMEOUT));
+
+        // A timeout isn't a sample, but the next deadline is longer
+        tracker.record_timeout(1);
+        assert_eq!(tracker.timeout(1), Some(Duration::from_millis(1200)));
+        assert_eq!(tracker.snapshot()[&1].samples, MIN_SAMPLES);
+        assert_eq!(tracker.snapshot()[&1].timeouts, 1);
+        // Never longer than a function without its own deadline waits
+        for _ in 0..4 {
+            tracker.record_timeout(1);
+        }
+        assert_eq!(tracker.timeout(1), Some(RESPONSE_TIMEOUT));
+        tracker.record(1, Duration::from_millis(200));
+        assert_eq!(tracker.timeout(1), Some(Duration::from_millis(600)));
+
+        assert_eq!(LatencyTracker::new(0.0).timeout(1), None);
+    }
+}
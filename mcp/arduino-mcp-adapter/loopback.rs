@@ -0,0 +1,589 @@
+//! In-process device: a [`Connector`] whose transport feeds command frames
+//! straight into a simulated device built from a manifest, with no PTY or
+//! serial port involved. Used by the tests, and by `loop://` lines for
+//! trying a manifest without hardware.
+
+use anyhow::{anyhow, Context, Result};
+use mcp_client::responses::{encode_response, error_frame, package, seal, ResponseData};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::introspect;
+use crate::manifest::{Ack, Function, Manifest, DEVICE_ID_FUNCTION};
+use crate::manifest_schema;
+use crate::protocol::{
+    crc8, DESCRIBE_FUNCTION_TAG, MANIFEST_VERSION_TAG, PROTOCOL_VERSION, PROTOCOL_VERSION_TAG,
+};
+use crate::reliable;
+use crate::slip::{slip_encode, SlipDecoder, SLIP_CLEAR_SEQUENCE};
+use crate::transport::{Connector, Transport};
+
+/// How long a read waits for a response before reporting `TimedOut`.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Simulated firmware: answers `deviceId()` and every manifest function.
+pub struct LoopbackDevice {
+    device_id: String,
+    manifest: Manifest,
+    /// Sequence byte before each tag, as with `--pipeline-depth` > 1
+    sequenced: bool,
+    /// Firmware from before `getProtocolVersion`
+    legacy: bool,
+    /// Reported by `getProtocolVersion`
+    max_frame_size: u16,
+    /// Responses sent as protocol v3 chunks
+    chunked: bool,
+    /// Sends a motion-done event right after acknowledging an
+    /// `"ack": "immediate"` function
+    events: bool,
+    /// Acknowledges sequenced commands and answers a repeated one from the
+    /// last response, as with the reliable link layer
+    reliable: bool,
+    /// Answers `describeFunction` from the manifest, as firmware built with
+    /// `MCP_INTROSPECTION`
+    introspection: bool,
+    /// Sequence number and response frames of the last reliable command
+    last: Option<(u8, Vec<Vec<u8>>)>,
+    /// Responses still to be damaged on the way, as on a noisy link
+    noise: usize,
+    /// Raw return data per function name; defaults to zeroes / empty string
+    responses: HashMap<String, Vec<u8>>,
+    /// Calls still to be answered with a busy error, by function name
+    busy: HashMap<String, usize>,
+    /// Functions never answered, like firmware stuck in a long loop
+    silent: HashSet<String>,
+    /// Commands still to be answered with STK500 sync bytes, as by a
+    /// bootloader after a reset
+    bootloader: usize,
+    /// Commands still to be lost, as by a board that is still booting
+    asleep: usize,
+    /// Function name and raw argument bytes of every call, in order
+    pub calls: Vec<(String, Vec<u8>)>,
+}
+
+impl LoopbackDevice {
+    pub fn new(device_id: &str, manifest: Manifest) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            manifest,
+            sequenced: false,
+            legacy: false,
+            max_frame_size: 256,
+            chunked: false,
+            events: false,
+            reliable: false,
+            introspection: false,
+            last: None,
+            noise: 0,
+            responses: HashMap::new(),
+            busy: HashMap::new(),
+            silent: HashSet::new(),
+            bootloader: 0,
+            asleep: 0,
+            calls: Vec::new(),
+        }
+    }
+
+    pub fn sequenced(mut self) -> Self {
+        self.sequenced = true;
+        self
+    }
+
+    /// Report this from `getManifestVersion` instead of the manifest's own
+    /// version.
+    #[cfg(test)]
+    pub fn firmware_version(mut self, version: &str) -> Self {
+        self.manifest.version = version.to_string();
+        self
+    }
+
+    /// Answer `getProtocolVersion` and `getManifestVersion` with an
+    /// unknown-tag error, like firmware that predates them.
+    pub fn legacy(mut self) -> Self {
+        self.legacy = true;
+        self
+    }
+
+    /// Split responses into chunks that fit `max_frame_size`.
+    pub fn chunked(mut self) -> Self {
+        self.chunked = true;
+        self
+    }
+
+    /// Report events in the handshake and finish every immediately
+    /// acknowledged motion at once.
+    pub fn events(mut self) -> Self {
+        self.events = true;
+        self
+    }
+
+    /// Speak the reliable link layer.
+    pub fn reliable(mut self) -> Self {
+        self.reliable = true;
+        self
+    }
+
+    /// Describe the manifest's functions to `dump-manifest`.
+    pub fn introspection(mut self) -> Self {
+        self.introspection = true;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn max_frame_size(mut self, size: u16) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Make `function` return this raw (already encoded) data.
+    #[cfg(test)]
+    pub fn respond(mut self, function: &str, data: Vec<u8>) -> Self {
+        self.responses.insert(function.to_string(), data);
+        self
+    }
+
+    /// Answer the next `count` calls of `function` with a busy error, like
+    /// firmware in the middle of a long move.
+    #[cfg(test)]
+    pub fn busy(mut self, function: &str, count: usize) -> Self {
+        self.busy.insert(function.to_string(), count);
+        self
+    }
+
+    /// Never answer calls of `function`.
+    #[cfg(test)]
+    pub fn silent(mut self, function: &str) -> Self {
+        self.silent.insert(function.to_string());
+        self
+    }
+
+    /// Start in the bootloader, answering the next `frames` commands with
+    /// sync bytes instead of responses.
+    #[cfg(test)]
+    pub fn in_bootloader(mut self, frames: usize) -> Self {
+        self.bootloader = frames;
+        self
+    }
+
+    /// Ignore the first `frames` commands, like a board that takes a while
+    /// to boot.
+    #[cfg(test)]
+    pub fn booting(mut self, frames: usize) -> Self {
+        self.asleep = frames;
+        self
+    }
+
+    /// Handle one decoded command frame and build the response frames,
+    /// damaging the first if the link is noisy.
+    fn handle(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = self.answer(frame);
+        let response = frames
+            .iter_mut()
+            .find(|f| reliable::acknowledged(f).is_none());
+        if let Some(last) = response
+            .filter(|_| self.noise > 0)
+            .and_then(|f| f.last_mut())
+        {
+            self.noise -= 1;
+            *last ^= 0xFF;
+        }
+        frames
+    }
+
+    fn answer(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        let Some((&crc, body)) = frame.split_last() else {
+            return vec![error_frame(None, 0x01)];
+        };
+        let (seq, body) = match body.split_first() {
+            // The handshake is never sequenced
+            Some((&seq, rest)) if (self.sequenced || self.reliable) && frame.len() > 2 => {
+                (Some(seq), rest)
+            }
+            _ => (None, body),
+        };
+        if crc8(&frame[..frame.len() - 1]) != crc || body.is_empty() {
+            // Sent unsequenced as a NAK, as the sequence byte may be the
+            // damaged one
+            let seq = seq.filter(|_| !self.reliable);
+            return vec![error_frame(seq, 0x01)];
+        }
+
+        match seq.filter(|_| self.reliable) {
+            Some(seq) => {
+                let mut frames = vec![seal(None, &[0xFF, 0xAC, seq])];
+                match &self.last {
+                    Some((last, responses)) if *last == seq => {
+                        frames.extend(responses.iter().cloned())
+                    }
+                    _ => {
+                        let responses = self.run(Some(seq), body);
+                        frames.extend(responses.iter().cloned());
+                        self.last = Some((seq, responses));
+                    }
+                }
+                frames
+            }
+            None => {
+                // A handshake starts a new session
+                self.last = None;
+                self.run(seq, body)
+            }
+        }
+    }
+
+    /// Run the command in `body` and build its response frames.
+    fn run(&mut self, seq: Option<u8>, body: &[u8]) -> Vec<Vec<u8>> {
+        let (tag, args) = (body[0], &body[1..]);
+        if tag == 0 {
+            self.calls
+                .push((DEVICE_ID_FUNCTION.to_string(), Vec::new()));
+            if self.silent.contains(DEVICE_ID_FUNCTION) {
+                return Vec::new();
+            }
+            return self.reply(seq, &self.device_id());
+        }
+        if tag == MANIFEST_VERSION_TAG && !self.legacy {
+            return self.reply(seq, &ResponseData::CStr(self.manifest.version.clone()));
+        }
+        if let ([index], true) = (args, tag == DESCRIBE_FUNCTION_TAG && self.introspection) {
+            let data = introspect::describe(&self.manifest, *index);
+            return self.reply(seq, &ResponseData::Raw(data));
+        }
+        if tag == PROTOCOL_VERSION_TAG && !self.legacy {
+            // Chunked responses came with protocol v3
+            let (version, chunked) = match self.chunked {
+                true => (PROTOCOL_VERSION, 0x04),
+                false => (2, 0),
+            };
+            let flags = u8::from(self.sequenced)
+                | (u8::from(self.events) << 1)
+                | chunked
+                | (u8::from(self.reliable) << 3);
+            let [low, high] = self.max_frame_size.to_le_bytes();
+            return vec![seal(seq, &[version, flags, low, high])];
+        }
+
+        let Some(func) = self.manifest.functions.iter().find(|f| f.tag == tag) else {
+            return vec![error_frame(seq, 0x02)];
+        };
+        self.calls.push((func.name.clone(), args.to_vec()));
+        if let Some(remaining) = self.busy.get_mut(&func.name).filter(|n| **n > 0) {
+            *remaining -= 1;
+            return vec![error_frame(seq, 0x03)];
+        }
+        if self.silent.contains(&func.name) {
+            return Vec::new();
+        }
+
+        let response = self.response(func);
+        let mut frames = self.reply(seq, &response);
+        if self.events && func.ack == Ack::Immediate {
+            // Motion done
+            frames.push(seal(None, &[0xFF, 0xFE, 0x01]));
+        }
+        frames
+    }
+
+    fn device_id(&self) -> ResponseData {
+        ResponseData::CStr(self.device_id.clone())
+    }
+
+    fn response(&self, func: &Function) -> ResponseData {
+        match self.responses.get(&func.name) {
+            Some(data) => ResponseData::Raw(data.clone()),
+            None => match func.return_type.as_deref() {
+                None => ResponseData::Void,
+                Some("i16") => ResponseData::I16(0),
+                Some("i32") => ResponseData::I32(0),
+                Some(_) => ResponseData::CStr(String::new()),
+            },
+        }
+    }
+
+    fn reply(&self, seq: Option<u8>, response: &ResponseData) -> Vec<Vec<u8>> {
+        match encode_response(response) {
+            Ok(response) => package(
+                seq,
+                &response,
+                self.chunked,
+                usize::from(self.max_frame_size),
+            ),
+            Err(_) => vec![error_frame(seq, 0x02)],
+        }
+    }
+}
+
+struct Shared {
+    device: Mutex<LoopbackDevice>,
+    decoder: Mutex<SlipDecoder>,
+    incoming: Mutex<VecDeque<u8>>,
+    data_ready: Condvar,
+    /// ESC CLEAR sequences received
+    clears: AtomicUsize,
+    /// Of those, the ones sent on their own rather than before a frame
+    lone_clears: AtomicUsize,
+}
+
+/// Connector for a [`LoopbackDevice`]; the device stays attached until
+/// [`LoopbackConnector::unplug`].
+#[derive(Clone)]
+pub struct LoopbackConnector {
+    shared: Arc<Shared>,
+    present: Arc<AtomicBool>,
+    boot_timeout: Duration,
+}
+
+impl LoopbackConnector {
+    /// A device for a `loop://` line: the manifest's path, then options
+    /// after `?` separated by `&`, e.g. `robots/arm.json?events&chunked`.
+    /// The device ID is the file name without `.json`.
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        let (path, options) = spec.split_once('?').unwrap_or((spec, ""));
+        let path = Path::new(path);
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest file {}", path.display()))?;
+        let manifest: Manifest = manifest_schema::parse(
+            &content,
+            false,
+            &format!("manifest file {}", path.display()),
+        )?;
+        let device_id = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow!("Invalid manifest filename: {}", path.display()))?;
+
+        let mut device = LoopbackDevice::new(device_id, manifest);
+        for option in options.split('&').filter(|o| !o.is_empty()) {
+            device = match option {
+                "sequenced" => device.sequenced(),
+                "legacy" => device.legacy(),
+                "chunked" => device.chunked(),
+                "events" => device.events(),
+                "reliable" => device.reliable(),
+                "introspection" => device.introspection(),
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown loop:// option '{}' (expected sequenced, legacy, chunked, \
+                         events, reliable or introspection)",
+                        option
+                    ))
+                }
+            };
+        }
+        Ok(Self::new(device))
+    }
+
+    pub fn new(device: LoopbackDevice) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                device: Mutex::new(device),
+                decoder: Mutex::new(SlipDecoder::new()),
+                incoming: Mutex::new(VecDeque::new()),
+                data_ready: Condvar::new(),
+                clears: AtomicUsize::new(0),
+                lone_clears: AtomicUsize::new(0),
+            }),
+            present: Arc::new(AtomicBool::new(true)),
+            boot_timeout: Duration::ZERO,
+        }
+    }
+
+    /// Poll the device until it answers on connecting, as for a serial
+    /// board.
+    #[cfg(test)]
+    pub fn with_boot_timeout(mut self, timeout: Duration) -> Self {
+        self.boot_timeout = timeout;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn unplug(&self) {
+        self.present.store(false, Ordering::Relaxed);
+    }
+
+    /// Reset the device into its bootloader for the next `frames` commands.
+    #[cfg(test)]
+    pub fn reset_into_bootloader(&self, frames: usize) {
+        self.shared.device.lock().unwrap().bootloader = frames;
+    }
+
+    /// Damage the CRC of the next `responses` responses, as on a noisy
+    /// link.
+    #[cfg(test)]
+    pub fn add_noise(&self, responses: usize) {
+        self.shared.device.lock().unwrap().noise += responses;
+    }
+
+    /// Start ignoring calls of `function`, as firmware stuck in a loop would.
+    #[cfg(test)]
+    pub fn silence(&self, function: &str) {
+        let mut device = self.shared.device.lock().unwrap();
+        device.silent.insert(function.to_string());
+    }
+
+    /// Stop ignoring `function` and answer the unsequenced call of it left
+    /// waiting, as firmware finishing a long loop would.
+    #[cfg(test)]
+    pub fn wake(&self, function: &str) {
+        let mut device = self.shared.device.lock().unwrap();
+        device.silent.remove(function);
+        let response = match function {
+            DEVICE_ID_FUNCTION => device.device_id(),
+            _ => {
+                let func = device
+                    .manifest
+                    .functions
+                    .iter()
+                    .find(|f| f.name == function)
+                    .unwrap();
+                device.response(func)
+            }
+        };
+        let frames = device.reply(None, &response);
+        let mut incoming = self.shared.incoming.lock().unwrap();
+        for frame in frames {
+            incoming.extend(slip_encode(&frame));
+        }
+        self.shared.data_ready.notify_all();
+    }
+
+    /// Restart the device, which announces it with a reset event.
+    #[cfg(test)]
+    pub fn restart(&self) {
+        let mut incoming = self.shared.incoming.lock().unwrap();
+        incoming.extend(slip_encode(&seal(None, &[0xFF, 0xFE, 0x02])));
+        self.shared.data_ready.notify_all();
+    }
+
+    /// Report a fault event with `code`, as firmware cutting its motors
+    /// does.
+    #[cfg(test)]
+    pub fn report_fault(&self, code: u8) {
+        let mut incoming = self.shared.incoming.lock().unwrap();
+        incoming.extend(slip_encode(&seal(None, &[0xFF, 0xFE, code])));
+        self.shared.data_ready.notify_all();
+    }
+
+    /// ESC CLEAR sequences the device has received so far.
+    #[cfg(test)]
+    pub fn clears(&self) -> usize {
+        self.shared.clears.load(Ordering::Relaxed)
+    }
+
+    /// ESC CLEAR sequences received on their own, not before a command.
+    #[cfg(test)]
+    pub fn lone_clears(&self) -> usize {
+        self.shared.lone_clears.load(Ordering::Relaxed)
+    }
+
+    /// Calls the device has received so far.
+    #[cfg(test)]
+    pub fn calls(&self) -> Vec<(String, Vec<u8>)> {
+        self.shared.device.lock().unwrap().calls.clone()
+    }
+}
+
+impl Connector for LoopbackConnector {
+    fn name(&self) -> &str {
+        "loopback"
+    }
+
+    fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    fn open(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(LoopbackTransport {
+            shared: Arc::clone(&self.shared),
+        }))
+    }
+
+    fn boot_timeout(&self) -> Duration {
+        self.boot_timeout
+    }
+
+    fn bootloader_window(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+struct LoopbackTransport {
+    shared: Arc<Shared>,
+}
+
+impl Transport for LoopbackTransport {
+    fn try_clone_transport(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(LoopbackTransport {
+            shared: Arc::clone(&self.shared),
+        }))
+    }
+
+    fn pending_bytes(&self) -> usize {
+        self.shared.incoming.lock().unwrap().len()
+    }
+}
+
+impl Write for LoopbackTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Can't occur inside a frame, where ESC is only ever followed by
+        // ESC_END or ESC_ESC
+        let clears = buf.windows(2).filter(|w| *w == SLIP_CLEAR_SEQUENCE).count();
+        self.shared.clears.fetch_add(clears, Ordering::Relaxed);
+        if buf == SLIP_CLEAR_SEQUENCE {
+            self.shared.lone_clears.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut decoder = self.shared.decoder.lock().unwrap();
+        for &byte in buf {
+            let frame = decoder
+                .process_byte(byte)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+            if let Some(frame) = frame {
+                let mut device = self.shared.device.lock().unwrap();
+                let mut incoming = self.shared.incoming.lock().unwrap();
+                if device.asleep > 0 {
+                    device.asleep -= 1;
+                } else if device.bootloader > 0 {
+                    device.bootloader -= 1;
+                    // STK_INSYNC, STK_OK
+                    incoming.extend([0x14, 0x10]);
+                } else {
+                    for response in device.handle(&frame) {
+                        incoming.extend(slip_encode(&response));
+                    }
+                }
+                self.shared.data_ready.notify_all();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for LoopbackTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let incoming = self.shared.incoming.lock().unwrap();
+        let (mut incoming, _) = self
+            .shared
+            .data_ready
+            .wait_timeout_while(incoming, READ_TIMEOUT, |incoming| incoming.is_empty())
+            .unwrap();
+
+        if incoming.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        let count = buf.len().min(incoming.len());
+        for (slot, byte) in buf.iter_mut().zip(incoming.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,271 @@
+//! Recorded tool-call macros: capture the next N device calls under a name,
+//! keep them as JSON files and replay them with the `runMacro` tool.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::sequence::{StepSpec, MAX_STEPS};
+
+/// Longest pause kept between two recorded calls; anything longer was the
+/// client thinking, not part of the move.
+pub const MAX_RECORDED_GAP: Duration = Duration::from_secs(5);
+
+/// A saved macro, stored as `<macro_dir>/<name>.json`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Macro {
+    pub name: String,
+    /// Manifest `name` of the robot it was recorded on
+    pub robot: String,
+    pub steps: Vec<StepSpec>,
+}
+
+/// Recording in progress, as reported by `GET /macros`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStatus {
+    pub name: String,
+    pub recorded: usize,
+    pub count: usize,
+}
+
+struct Recording {
+    name: String,
+    count: usize,
+    robot: Option<String>,
+    steps: Vec<StepSpec>,
+    last_call: Option<Instant>,
+}
+
+pub struct MacroStore {
+    dir: Option<PathBuf>,
+    recording: Mutex<Option<Recording>>,
+}
+
+impl MacroStore {
+    /// Macros live in `dir`; without one, recording is unavailable.
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            recording: Mutex::new(None),
+        }
+    }
+
+    /// Record the next `count` device calls as macro `name`.
+    pub fn start_recording(&self, name: &str, count: usize) -> Result<RecordingStatus> {
+        self.path(name)?;
+        if count == 0 || count > MAX_STEPS {
+            return Err(anyhow!("'count' must be between 1 and {}", MAX_STEPS));
+        }
+
+        let mut recording = self.recording.lock().unwrap();
+        if let Some(current) = recording.as_ref() {
+            return Err(anyhow!("Already recording macro '{}'", current.name));
+        }
+        info!("Recording the next {} call(s) as macro '{}'", count, name);
+        *recording = Some(Recording {
+            name: name.to_string(),
+            count,
+            robot: None,
+            steps: Vec::new(),
+            last_call: None,
+        });
+        Ok(RecordingStatus {
+            name: name.to_string(),
+            recorded: 0,
+            count,
+        })
+    }
+
+    /// Stop recording without saving; returns the macro's name.
+    pub fn cancel_recording(&self) -> Option<String> {
+        let recording = self.recording.lock().unwrap().take()?;
+        info!("Cancelled recording of macro '{}'", recording.name);
+        Some(recording.name)
+    }
+
+    pub fn recording(&self) -> Option<RecordingStatus> {
+        self.recording
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| RecordingStatus {
+                name: r.name.clone(),
+                recorded: r.steps.len(),
+                count: r.count,
+            })
+    }
+
+    /// Add a successful call on `robot` to the recording, if one is running.
+    /// Returns the macro once its last call is recorded and it is saved.
+    pub fn record(&self, robot: &str, function: &str, arguments: &Value) -> Result<Option<Macro>> {
+        let mut guard = self.recording.lock().unwrap();
+        let Some(recording) = guard.as_mut() else {
+            return Ok(None);
+        };
+        if recording.robot.get_or_insert_with(|| robot.to_string()) != robot {
+            // Another robot was plugged in mid-recording
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        if let (Some(previous), Some(last_call)) = (recording.steps.last_mut(), recording.last_call)
+        {
+            previous.delay_ms = (now - last_call).min(MAX_RECORDED_GAP).as_millis() as u64;
+        }
+        recording.last_call = Some(now);
+        recording.steps.push(StepSpec {
+            name: function.to_string(),
+            arguments: arguments.clone(),
+            delay_ms: 0,
+        });
+        if recording.steps.len() < recording.count {
+            return Ok(None);
+        }
+
+        let recording = guard.take().unwrap();
+        let saved = Macro {
+            name: recording.name,
+            robot: robot.to_string(),
+            steps: recording.steps,
+        };
+        self.save(&saved)?;
+        Ok(Some(saved))
+    }
+
+    /// Every saved macro, sorted by name.
+    pub fn list(&self) -> Result<Vec<Macro>> {
+        let Some(dir) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read macro directory"),
+        };
+
+        let mut macros = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            // One broken file shouldn't hide the other macros
+            match Self::read(&path) {
+                Ok(saved) => macros.push(saved),
+                Err(e) => warn!("Skipping macro: {:#}", e),
+            }
+        }
+        macros.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(macros)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Option<Macro>> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::read(&path).map(Some)
+    }
+
+    /// Returns whether the macro existed.
+    pub fn delete(&self, name: &str) -> Result<bool> {
+        match std::fs::remove_file(self.path(name)?) {
+            Ok(()) => {
+                info!("Deleted macro '{}'", name);
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).context("Failed to delete macro"),
+        }
+    }
+
+    fn save(&self, saved: &Macro) -> Result<()> {
+        let path = self.path(&saved.name)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create macro directory")?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(saved)?)
+            .with_context(|| format!("Failed to save macro {}", path.display()))?;
+        info!(
+            "Saved macro '{}' ({} steps) to {}",
+            saved.name,
+            saved.steps.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    fn read(path: &Path) -> Result<Macro> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read macro {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse macro {}", path.display()))
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let dir = self
+            .dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("No macro directory configured"))?;
+        check_name("macro", name)?;
+        Ok(dir.join(format!("{}.json", name)))
+    }
+}
+
+/// Names become file names and keys, so keep them to a safe character set.
+/// `kind` says what is named, such as "macro" or "schedule".
+pub fn check_name(kind: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(anyhow!(
+            "Invalid {} name '{}' (use up to 64 letters, digits, '-' or '_')",
+            kind,
+            name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_saves_after_count_calls() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MacroStore::new(Some(dir.path().join("macros")));
+
+        store.start_recording("wave", 2).unwrap();
+        assert!(store.start_recording("other", 1).is_err());
+        let args = serde_json::json!({"angle": 10});
+        assert!(store.record("arm", "setServo", &args).unwrap().is_none());
+        // Calls from another robot are not part of the macro
+        assert!(store.record("rover", "drive", &args).unwrap().is_none());
+        let saved = store.record("arm", "home", &Value::Null).unwrap().unwrap();
+
+        assert_eq!(saved.steps.len(), 2);
+        assert!(saved.steps[0].delay_ms < MAX_RECORDED_GAP.as_millis() as u64);
+        assert!(store.recording().is_none());
+        let loaded = store.load("wave").unwrap().unwrap();
+        assert_eq!(
+            (loaded.robot.as_str(), loaded.steps[1].name.as_str()),
+            ("arm", "home")
+        );
+        assert_eq!(store.list().unwrap().len(), 1);
+        std::fs::write(dir.path().join("macros/broken.json"), "{").unwrap();
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        assert!(store.delete("wave").unwrap());
+        assert!(!store.delete("wave").unwrap());
+        assert!(store.load("../etc/passwd").is_err());
+        assert!(MacroStore::new(None).start_recording("wave", 1).is_err());
+    }
+}
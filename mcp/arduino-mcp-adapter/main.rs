@@ -1,55 +1,531 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::info;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
 
+mod battery;
+mod ble;
+mod bootloader;
+mod call;
+mod chunks;
+mod composite;
+mod config;
 mod connection;
+mod debounce;
+mod discovery;
+mod errors;
+mod events;
+#[cfg(test)]
+mod fixtures;
+mod flash;
+mod fleet;
+mod frame_log;
+mod gamepad;
+mod geofence;
+mod governor;
+mod http_server;
+mod introspect;
+mod latency;
+mod loopback;
+mod macros;
 mod manifest;
+mod manifest_schema;
+mod mdns;
+mod messages;
+mod middleware;
+mod notifications;
+mod otel;
+mod pcap;
+mod pipeline;
+mod plugins;
+mod port_actor;
+mod ports;
+mod presets;
 mod protocol;
+mod python_env;
+mod python_pool;
 mod python_runner;
+mod recorder;
+mod reliable;
+mod remote;
+mod repl;
+mod rest;
+mod robot_state;
+mod scheduler;
+mod self_test;
+mod sequence;
 mod server;
+mod sessions;
 mod slip;
+mod stats;
+mod tcp;
+mod telemetry;
+mod tool_bridge;
+mod transcript;
+mod transport;
+mod units;
+mod usb_devices;
 
+use config::{CliOverrides, Config};
 use connection::ConnectionManager;
+use discovery::Discovery;
+use fleet::{FleetServer, Robot};
+use geofence::Geofence;
+use governor::Governor;
 use manifest::ManifestManager;
+use messages::Messages;
+use pcap::PcapWriter;
+use recorder::SessionRecorder;
+use remote::RemoteAdapter;
 use server::McpServer;
+use usb_devices::UsbDeviceMap;
 
 #[derive(Parser)]
 #[command(name = "arduino-mcp-adapter")]
 #[command(about = "MCP adapter for serial Arduino devices")]
 struct Cli {
-    /// Serial line (e.g. /dev/ttyUSB0)
-    #[arg(short, long)]
-    line: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Run the self-test in the robot's manifest, print each step's result and exit; fails if any step does
+    #[arg(long)]
+    self_test: bool,
+
+    /// Configuration file (default: /etc/arduino-mcp-adapter/config.toml if present)
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Serial line (e.g. /dev/ttyUSB0), or tcp://, mdns://, ble://, pty:// or loop:// URI
+    #[arg(short, long, global = true)]
+    line: Option<String>,
 
     /// JSON manifest directory
-    #[arg(short, long)]
-    manifest_dir: PathBuf,
+    #[arg(short, long, global = true)]
+    manifest_dir: Option<PathBuf>,
+
+    /// HTTP port for MCP server [default: 8080]
+    #[arg(short, long, global = true)]
+    port: Option<u16>,
+
+    /// Baud rate [default: 115200]
+    #[arg(short, long, global = true)]
+    baud: Option<u32>,
+
+    /// Maximum commands in flight; above 1 needs firmware with sequence numbers [default: 1]
+    #[arg(long, global = true)]
+    pipeline_depth: Option<usize>,
+
+    /// Milliseconds to keep retrying a command the robot answers busy to; 0 disables retries [default: 5000]
+    #[arg(long, global = true)]
+    busy_wait_ms: Option<u64>,
+
+    /// Give up on a response after this multiple of the function's p99 round trip; 0 keeps the fixed 5 second timeout [default: 3]
+    #[arg(long, global = true)]
+    timeout_factor: Option<f64>,
+
+    /// Refuse tool calls while this many commands are queued for the robot; 0 for no limit [default: 64]
+    #[arg(long, global = true)]
+    max_queue_depth: Option<usize>,
+
+    /// Refuse tool calls while the oldest queued command has waited this many milliseconds; 0 for no limit [default: 30000]
+    #[arg(long, global = true)]
+    max_queue_age_ms: Option<u64>,
+
+    /// Write all serial traffic to a pcapng file (user DLT 147)
+    #[arg(long, global = true)]
+    pcap: Option<PathBuf>,
+
+    /// Warm Python interpreters kept for runPythonScript; 0 spawns one per script [default: 0]
+    #[arg(long, global = true)]
+    python_pool_size: Option<usize>,
+
+    /// Virtualenv for runPythonScript, created if missing; enables per-call requirements
+    #[arg(long, global = true)]
+    python_venv: Option<PathBuf>,
+
+    /// Directory for recorded macros [default: <manifest-dir>/macros]
+    #[arg(long, global = true)]
+    macro_dir: Option<PathBuf>,
+
+    /// Record every robot function call clients make to a Python script (.py) or macro (.json)
+    #[arg(long, global = true)]
+    record_session: Option<PathBuf>,
+
+    /// Expose the rawCommand tool for sending any tag and argument bytes
+    #[arg(long, global = true)]
+    enable_raw: bool,
+
+    /// Maximum MCP client sessions at once [default: unlimited]
+    #[arg(long, global = true)]
+    max_clients: Option<usize>,
+
+    /// Let only one client session call tools at a time; others must call takeControl
+    #[arg(long, global = true)]
+    exclusive_control: bool,
+
+    /// Only offer manifest functions marked "mutates": false, or taking no
+    /// parameters and returning a value
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Validate and log function calls as hex frames instead of sending them
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Refuse calls that change the robot while its battery is below the manifest's critical level
+    #[arg(long, global = true)]
+    battery_lockout: bool,
+
+    /// Accept integer arguments sent as numeric strings ("90") or whole floats (90.0)
+    #[arg(long, global = true)]
+    lenient_numbers: bool,
+
+    /// Language of the error messages clients see: en, de or es [default: en]
+    #[arg(long, global = true)]
+    lang: Option<String>,
+
+    /// Refuse manifests with fields manifest.schema.json doesn't describe, instead of warning
+    #[arg(long, global = true)]
+    strict_manifests: bool,
+
+    /// Drive the robot from a gamepad using this mapping file (needs the gamepad build feature)
+    #[arg(long, global = true)]
+    gamepad: Option<PathBuf>,
 
-    /// HTTP port for MCP server
-    #[arg(short, long, default_value = "8080")]
-    port: u16,
+    /// Load extra tools from the .wasm plugins in this directory (needs the plugins build feature)
+    #[arg(long, global = true)]
+    plugin_dir: Option<PathBuf>,
 
-    /// Baud rate
-    #[arg(short, long, default_value = "115200")]
-    baud: u32,
+    /// Map of USB VID/PID/serial numbers to device IDs [default: <manifest-dir>/devices.toml]
+    #[arg(long, global = true)]
+    usb_devices: Option<PathBuf>,
+
+    /// Bearer token required for HTTP requests
+    #[arg(long, global = true)]
+    auth_token: Option<String>,
+
+    /// Log level (error, warn, info, debug, trace) [default: info]
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Export tracing spans to this OTLP/HTTP endpoint, e.g. http://localhost:4318/v1/traces (needs the otel build feature)
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+
+    /// Listen for Wi-Fi robots' discovery beacons on this UDP port (fleet mode)
+    #[arg(long, global = true)]
+    discovery_port: Option<u16>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execute one device function, print its result and exit
+    Call {
+        /// Function name from the device manifest
+        #[arg(long)]
+        tool: String,
+
+        /// Arguments as a JSON object
+        #[arg(long, default_value = "{}")]
+        args: String,
+
+        /// Call through the adapter running at this URL instead of opening the port
+        #[arg(long, value_name = "URL")]
+        url: Option<String>,
+    },
+    /// Interactive prompt for calling device functions
+    Repl,
+    /// Write a starter manifest from the functions the firmware describes
+    DumpManifest {
+        /// Where to write it, `-` for stdout; defaults to the device's manifest path
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Overwrite an existing manifest file
+        #[arg(long)]
+        force: bool,
+    },
+    /// List serial ports with their USB details
+    ListPorts,
+    /// Identify the device on a port and show its matching manifest
+    Probe {
+        /// Serial port to probe (e.g. /dev/ttyACM0)
+        #[arg(value_name = "PORT")]
+        device: String,
+    },
+    /// Upload firmware with the tool named in the robot's manifest, then serve
+    Flash {
+        /// Firmware image (.hex for avrdude, .bin for esptool)
+        #[arg(value_name = "FIRMWARE")]
+        firmware: PathBuf,
+
+        /// Device ID whose manifest to use, for robots that can't identify themselves
+        #[arg(long)]
+        device: Option<String>,
+
+        /// Exit after flashing instead of serving
+        #[arg(long)]
+        exit: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-
     let cli = Cli::parse();
+    if cli.self_test && cli.command.is_some() {
+        anyhow::bail!("--self-test can't be combined with a subcommand");
+    }
+    // Flashing logs its progress and goes on to serve
+    let one_off = cli.self_test
+        || cli
+            .command
+            .as_ref()
+            .is_some_and(|command| !matches!(command, Command::Flash { .. }));
+
+    let mut config = Config::load(cli.config.as_deref())?;
+    config.apply_cli(CliOverrides {
+        line: cli.line,
+        manifest_dir: cli.manifest_dir,
+        port: cli.port,
+        baud: cli.baud,
+        pipeline_depth: cli.pipeline_depth,
+        busy_wait_ms: cli.busy_wait_ms,
+        timeout_factor: cli.timeout_factor,
+        max_queue_depth: cli.max_queue_depth,
+        max_queue_age_ms: cli.max_queue_age_ms,
+        pcap: cli.pcap,
+        python_pool_size: cli.python_pool_size,
+        python_venv: cli.python_venv,
+        macro_dir: cli.macro_dir,
+        record_session: cli.record_session,
+        enable_raw: cli.enable_raw,
+        max_clients: cli.max_clients,
+        exclusive_control: cli.exclusive_control,
+        read_only: cli.read_only,
+        dry_run: cli.dry_run,
+        battery_lockout: cli.battery_lockout,
+        lenient_numbers: cli.lenient_numbers,
+        lang: cli.lang,
+        strict_manifests: cli.strict_manifests,
+        gamepad: cli.gamepad,
+        plugin_dir: cli.plugin_dir,
+        usb_devices: cli.usb_devices,
+        auth_token: cli.auth_token,
+        // One-off commands only log warnings unless asked
+        log_level: cli
+            .log_level
+            .or_else(|| one_off.then(|| "warn".to_string())),
+        otlp_endpoint: cli.otlp_endpoint,
+        discovery_port: cli.discovery_port,
+    });
+
+    // Keep stdout for the result of one-off commands
+    let writer = match one_off {
+        true => BoxMakeWriter::new(std::io::stderr),
+        false => BoxMakeWriter::new(std::io::stdout),
+    };
+    let (span_export, _span_export_guard) = match &config.logging.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, guard) = otel::layer(endpoint)?;
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(span_export)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_filter(LevelFilter::from_level(config.log_level()?)),
+        )
+        .init();
+
+    let usb_devices = match config.usb_devices_path() {
+        Some(path) => {
+            info!("USB device map: {}", path.display());
+            Some(UsbDeviceMap::load(&path)?)
+        }
+        None => None,
+    };
+
+    // These need neither a configured line nor a manifest directory
+    match &cli.command {
+        Some(Command::ListPorts) => return ports::list(usb_devices.as_ref()),
+        Some(Command::Probe { device }) => return ports::probe(device, &config).await,
+        Some(Command::Call {
+            tool,
+            args,
+            url: Some(url),
+        }) => {
+            let result = call::run_remote(url, config.auth.token.clone(), tool, args).await?;
+            println!("{}", result);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Without a line or fleet, look for the one attached robot on the map
+    if let Some(map) = &usb_devices {
+        if config.line.is_none()
+            && config.fleet_robots().is_empty()
+            && config.remotes.is_empty()
+            && config.discovery_port.is_none()
+        {
+            if let Some((port, device_id)) = map.find_port()? {
+                info!("Found '{}' on {} by its USB descriptors", device_id, port);
+                config.line = Some(port);
+            }
+        }
+    }
+
+    if config.is_fleet()? && cli.command.is_none() && !cli.self_test {
+        return run_fleet(config).await;
+    }
+
+    let line = config.line()?.to_string();
+    let manifest_dir = config.manifest_dir()?.to_path_buf();
+    let pipeline_depth = config.pipeline_depth()?;
+    let messages = Messages::new(&config.lang)?;
 
     info!("Starting Arduino MCP Adapter");
-    info!("Serial line: {}", cli.line);
-    info!("Manifest directory: {}", cli.manifest_dir.display());
-    info!("HTTP port: {}", cli.port);
+    if config.discovery_port.is_some() && cli.command.is_none() {
+        warn!("Robot discovery only runs in fleet mode, without a line, and was ignored");
+    }
+    if let Some(path) = &cli.config {
+        info!("Config file: {}", path.display());
+    }
+    info!("Serial line: {}", line);
+    info!("Manifest directory: {}", manifest_dir.display());
+    info!("HTTP port: {}", config.port);
+    if pipeline_depth > 1 {
+        info!("Command pipeline depth: {}", pipeline_depth);
+    }
+    if config.auth.token.is_some() {
+        info!("HTTP bearer token authentication enabled");
+    }
+    if let Some(max) = config.max_clients {
+        info!("Client sessions limited to {}", max);
+    }
+    if config.exclusive_control {
+        info!("Exclusive control: one client session calls tools at a time");
+    }
+    if config.read_only {
+        info!("Read-only: only functions that don't change the robot are offered");
+    }
+    if config.dry_run {
+        warn!("Dry run: function calls are logged, not sent to the robot");
+    }
+    let usb_device = usb_devices
+        .as_ref()
+        .zip(transport::serial_path(&line))
+        .and_then(|(map, path)| map.identify(path))
+        .map(str::to_string);
+    if config.battery_lockout {
+        info!("Battery lockout: calls that change the robot are refused while its battery is critical");
+    }
+    if config.lenient_numbers {
+        info!("Lenient numbers: integer arguments may be sent as strings or whole floats");
+    }
+    if messages.lang() != "en" {
+        info!("Error messages for clients in '{}'", messages.lang());
+    }
+    if config.enable_raw {
+        warn!("rawCommand tool enabled: clients can send any command to the device");
+    }
 
     // Create managers
-    let connection_manager = Arc::new(ConnectionManager::new(cli.line, cli.baud));
-    let manifest_manager = Arc::new(ManifestManager::new(cli.manifest_dir));
+    let manifest_manager = Arc::new(
+        ManifestManager::new(manifest_dir)
+            .with_device_manifests(config.device_manifests())
+            .with_lenient_numbers(config.lenient_numbers)
+            .with_strict_manifests(config.strict_manifests),
+    );
+    let mut connection_manager = ConnectionManager::new(&line, config.baud)?
+        .with_pipeline_depth(pipeline_depth)
+        .with_busy_wait(Duration::from_millis(config.busy_wait_ms))
+        .with_timeout_factor(config.timeout_factor()?)
+        .with_dry_run(config.dry_run)
+        .with_governor(Governor::new(&config.limits)?)
+        .with_battery_lockout(config.battery_lockout)
+        .with_manifest_manager(Arc::clone(&manifest_manager));
+    if let Some(device_id) = &usb_device {
+        // Have the manifest ready before the device finishes booting
+        info!("USB descriptors identify {} as '{}'", line, device_id);
+        if let Err(e) = manifest_manager.get_manifest(device_id) {
+            warn!("{}", e);
+        }
+        connection_manager = connection_manager.with_expected_device(device_id.clone());
+    }
+    if let Some(bounds) = config.geofence {
+        info!("Geofence: x {:?}, y {:?}", bounds.x, bounds.y);
+        connection_manager = connection_manager.with_geofence(Geofence::new(bounds)?);
+    }
+    if let Some(path) = &config.pcap {
+        info!("Capturing serial traffic to {}", path.display());
+        connection_manager = connection_manager.with_capture(PcapWriter::create(path, &line)?);
+    }
+
+    if cli.self_test {
+        let device_id = call::connect(&connection_manager).await?;
+        let manifest = manifest_manager.get_manifest(&device_id)?;
+        if manifest.self_test.is_empty() {
+            anyhow::bail!("The manifest for {} has no self_test steps", device_id);
+        }
+        let report = self_test::run(&connection_manager, &manifest, &manifest_manager).await;
+        println!("{}", report);
+        if !report.ok() {
+            anyhow::bail!(
+                "Self-test failed: {} of {} steps failed",
+                report.failed,
+                report.steps.len()
+            );
+        }
+        return Ok(());
+    }
+
+    match &cli.command {
+        Some(Command::Call { tool, args, .. }) => {
+            let result = call::run(&connection_manager, &manifest_manager, tool, args).await?;
+            println!("{}", result);
+            return Ok(());
+        }
+        Some(Command::Repl) => return repl::run(&connection_manager, &manifest_manager).await,
+        Some(Command::DumpManifest { output, force }) => {
+            return introspect::run(
+                &connection_manager,
+                &manifest_manager,
+                output.as_deref(),
+                *force,
+            )
+            .await
+        }
+        Some(Command::Flash {
+            firmware,
+            device,
+            exit,
+        }) => {
+            flash::run(
+                &connection_manager,
+                &manifest_manager,
+                &line,
+                firmware,
+                device.as_deref().or(usb_device.as_deref()),
+            )
+            .await?;
+            if *exit {
+                return Ok(());
+            }
+        }
+        _ => {}
+    }
+
+    let connection_manager = Arc::new(connection_manager);
 
     // List available manifests
     match manifest_manager.list_available_manifests() {
@@ -66,8 +542,101 @@ async fn main() -> Result<()> {
     }
 
     // Create and start MCP server
-    let server = McpServer::new(connection_manager, manifest_manager);
-    server.start(cli.port).await?;
+    let session_recorder = config
+        .record_session
+        .as_deref()
+        .map(SessionRecorder::create)
+        .transpose()?;
+    let mut server = McpServer::new(connection_manager, manifest_manager, Arc::new(config))
+        .with_messages(messages);
+    if let Some(recorder) = session_recorder {
+        server = server.with_session_recorder(recorder);
+    }
+    let server = Arc::new(server);
+    server.start(shutdown_signal()).await?;
 
     Ok(())
 }
+
+/// Serve every `[[robots]]` and `[[remotes]]` entry from one HTTP server.
+async fn run_fleet(mut config: Config) -> Result<()> {
+    let manifest_dir = config.manifest_dir()?.to_path_buf();
+
+    let robot_specs = config.fleet_robots();
+    info!(
+        "Starting Arduino MCP Adapter for {} robots and {} remote adapters",
+        robot_specs.len(),
+        config.remotes.len()
+    );
+    info!("Manifest directory: {}", manifest_dir.display());
+    info!("HTTP port: {}", config.port);
+    if !config.schedules.is_empty() {
+        warn!("Schedules are not supported in fleet mode and were ignored");
+        config.schedules.clear();
+    }
+    if config.pcap.take().is_some() {
+        warn!("Packet capture is not supported in fleet mode and was ignored");
+    }
+    if config.record_session.take().is_some() {
+        warn!("Session recording is not supported in fleet mode and was ignored");
+    }
+    if config.gamepad.take().is_some() {
+        warn!("Gamepad teleoperation is not supported in fleet mode and was ignored");
+    }
+    if config.plugin_dir.take().is_some() {
+        warn!("Plugins are not supported in fleet mode and were ignored");
+    }
+    if config.enable_raw {
+        warn!("rawCommand tool enabled: clients can send any command to the robots");
+    }
+    if config.dry_run {
+        warn!("Dry run: function calls are logged, not sent to the robots");
+    }
+
+    let config = Arc::new(config);
+    let manifest_manager = Arc::new(
+        ManifestManager::new(manifest_dir)
+            .with_device_manifests(config.device_manifests())
+            .with_lenient_numbers(config.lenient_numbers)
+            .with_strict_manifests(config.strict_manifests),
+    );
+    let robots = robot_specs
+        .iter()
+        .map(|spec| {
+            info!("Robot '{}' on {}", spec.name, spec.line);
+            Robot::new(spec, &config, &manifest_manager)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let remotes = config
+        .remotes
+        .iter()
+        .map(|spec| {
+            info!("Remote '{}' at {}", spec.name, spec.url);
+            RemoteAdapter::new(spec)
+        })
+        .collect();
+
+    let mut server = FleetServer::new(robots, remotes, Arc::clone(&config));
+    if let Some(port) = config.discovery_port {
+        server = server.with_discovery(Discovery::new(port, &config));
+    }
+    let server = Arc::new(server);
+    server.start(shutdown_signal()).await
+}
+
+/// Resolve on the first SIGINT (^C) or SIGTERM (systemd stop).
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("Could not install SIGTERM handler: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        _ = terminate.recv() => info!("Received SIGTERM"),
+    }
+}
@@ -2,16 +2,131 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tracing::{debug, info, warn};
 
+use crate::composite::{self, Composite};
+use crate::manifest_schema;
+use crate::protocol::{DEVICE_ID_TAG, MANIFEST_VERSION_TAG, RESERVED_TAGS};
+use crate::self_test::{self, SelfTestStep};
+use crate::sequence;
+use crate::units::{self, Unit, UNIT_ARGUMENT};
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Manifest {
     pub name: String,
     pub description: String,
     pub version: String,
     pub functions: Vec<Function>,
+    /// Zero-argument function that stops all motion, sent on shutdown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safe_state: Option<String>,
+    /// Zero-argument function called each time the device is identified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_connect: Option<String>,
+    /// Zero-argument function called before the adapter closes the port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_disconnect: Option<String>,
+    /// Zero-argument function answering nonzero while an action started by
+    /// an `"ack": "immediate"` function is still running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy: Option<String>,
+    /// Keep-alive the firmware expects periodically while connected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchdog: Option<Watchdog>,
+    /// Battery getter and the levels clients are warned at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<Battery>,
+    /// Pose getter and distance-driving functions, used by the geofence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub odometry: Option<Odometry>,
+    /// How to upload new firmware, used by the `flash` subcommand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board: Option<Board>,
+    /// Tools made of steps over the functions above
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub composites: Vec<Composite>,
+    /// Diagnostic calls run by the `selfTest` tool and `--self-test`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub self_test: Vec<SelfTestStep>,
+    /// Made up by the adapter for a device with no manifest file, see
+    /// [`fallback`]
+    #[serde(skip)]
+    pub fallback: bool,
+}
+
+impl Manifest {
+    /// Whether `name` stops the robot or holds it safe, so that neither a
+    /// battery lockout nor a fault ever refuses it.
+    pub fn is_safety_function(&self, name: &str) -> bool {
+        [
+            self.safe_state.as_ref(),
+            self.on_disconnect.as_ref(),
+            self.watchdog.as_ref().map(|w| &w.function),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|safety| safety == name)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Watchdog {
+    /// Zero-argument keep-alive function
+    pub function: String,
+    /// Send interval; keep well below the firmware's own timeout
+    pub interval_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Battery {
+    /// Zero-argument function returning the voltage, e.g. in millivolts
+    pub function: String,
+    /// Read interval [default: 30000]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_ms: Option<u64>,
+    /// Warn clients below this, in the function's unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low: Option<f64>,
+    /// Below this, `--battery-lockout` refuses calls that change the robot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub critical: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Odometry {
+    /// Zero-argument function returning the pose as `x,y,heading`
+    pub pose: String,
+    /// Functions that drive straight, keyed by name, each with the
+    /// parameter giving the distance
+    #[serde(default)]
+    pub drive: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Board {
+    pub tool: FlashTool,
+    /// Chip as the tool names it, e.g. `atmega328p` or `esp32`
+    pub mcu: String,
+    /// avrdude programmer [default: arduino]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub programmer: Option<String>,
+    /// Upload baud rate [default: 115200 for avrdude, 460800 for esptool]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baud: Option<u32>,
+    /// esptool flash offset of the image [default: 0x10000]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashTool {
+    Avrdude,
+    Esptool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -22,6 +137,44 @@ pub struct Function {
     #[serde(rename = "return")]
     pub return_type: Option<String>,
     pub params: Vec<Parameter>,
+    /// When the firmware answers: once the action is done, or as soon as
+    /// it has started
+    #[serde(default, skip_serializing_if = "Ack::is_complete")]
+    pub ack: Ack,
+    /// Whether a call changes anything on the robot; unset, a function
+    /// with no parameters and a return value is taken to only read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutates: Option<bool>,
+    /// Identical calls arriving within this many milliseconds of each other
+    /// are sent once and share the result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce_ms: Option<u64>,
+}
+
+impl Function {
+    /// Whether the function may be offered with `--read-only`.
+    pub fn is_read_only(&self) -> bool {
+        match self.mutates {
+            Some(mutates) => !mutates,
+            None => self.params.is_empty() && self.return_type.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Ack {
+    #[default]
+    Complete,
+    /// The firmware answers before a slow action such as a motion ends, so
+    /// the serial link is free while it runs
+    Immediate,
+}
+
+impl Ack {
+    fn is_complete(&self) -> bool {
+        *self == Ack::Complete
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -29,6 +182,77 @@ pub struct Parameter {
     pub name: String,
     #[serde(rename = "type")]
     pub param_type: String,
+    /// Smallest accepted value for integer parameters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<i64>,
+    /// Largest accepted value for integer parameters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maximum: Option<i64>,
+    /// What an integer parameter measures in; values in a compatible unit
+    /// are converted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<Unit>,
+}
+
+impl Function {
+    /// Whether calls may pass a `unit` argument for their plain numbers.
+    pub fn takes_unit_argument(&self) -> bool {
+        self.params.iter().any(|p| p.unit.is_some())
+            && !self.params.iter().any(|p| p.name == UNIT_ARGUMENT)
+    }
+
+    /// Units the `unit` argument may name.
+    pub fn accepted_units(&self) -> Vec<Unit> {
+        let mut accepted = Vec::new();
+        for unit in self.params.iter().filter_map(|p| p.unit) {
+            for unit in unit.compatible() {
+                if !accepted.contains(&unit) {
+                    accepted.push(unit);
+                }
+            }
+        }
+        accepted
+    }
+}
+
+/// Local changes merged over a device's manifest, read from
+/// `<manifest_dir>/overrides/<device_id>.json`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestOverride {
+    pub description: Option<String>,
+    /// Keyed by function name
+    #[serde(default)]
+    pub functions: HashMap<String, FunctionOverride>,
+    /// Added to the manifest's, replacing any of the same name
+    #[serde(default)]
+    pub composites: Vec<Composite>,
+    /// Replaces the manifest's self-test
+    pub self_test: Option<Vec<SelfTestStep>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionOverride {
+    pub desc: Option<String>,
+    /// Leave the function out of the manifest entirely
+    #[serde(default)]
+    pub hidden: bool,
+    pub ack: Option<Ack>,
+    pub mutates: Option<bool>,
+    /// Keyed by parameter name
+    #[serde(default)]
+    pub params: HashMap<String, ParamOverride>,
+}
+
+/// A range that can only narrow the one in the base manifest, and the
+/// parameter's unit.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamOverride {
+    pub minimum: Option<i64>,
+    pub maximum: Option<i64>,
+    pub unit: Option<Unit>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -41,17 +265,57 @@ pub struct Tool {
 
 pub struct ManifestManager {
     manifest_dir: PathBuf,
+    device_manifests: HashMap<String, PathBuf>,
     loaded_manifests: Arc<Mutex<HashMap<String, Manifest>>>,
+    loaded_mtimes: Arc<Mutex<HashMap<String, LoadedMtimes>>>,
+    lenient_numbers: bool,
+    /// Reject manifests with fields the schema doesn't describe
+    strict: bool,
 }
 
+/// Files a cached manifest was read from, each with its modification time
+/// then; `None` for an override that didn't exist.
+type LoadedMtimes = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// Name of the function on [`DEVICE_ID_TAG`].
+pub const DEVICE_ID_FUNCTION: &str = "deviceId";
+
+/// Separates capability modules in a device ID, e.g. `drivebase+armv2`.
+pub const MODULE_SEPARATOR: char = '+';
+
 impl ManifestManager {
     pub fn new(manifest_dir: PathBuf) -> Self {
         Self {
             manifest_dir,
+            device_manifests: HashMap::new(),
             loaded_manifests: Arc::new(Mutex::new(HashMap::new())),
+            loaded_mtimes: Arc::new(Mutex::new(HashMap::new())),
+            lenient_numbers: false,
+            strict: false,
         }
     }
 
+    /// Use explicit manifest files for some device IDs instead of looking them
+    /// up in the manifest directory.
+    pub fn with_device_manifests(mut self, device_manifests: HashMap<String, PathBuf>) -> Self {
+        self.device_manifests = device_manifests;
+        self
+    }
+
+    /// Let integer parameters accept numeric strings and whole floats, which
+    /// [`coerce_arguments`] converts before the call is encoded.
+    pub fn with_lenient_numbers(mut self, lenient_numbers: bool) -> Self {
+        self.lenient_numbers = lenient_numbers;
+        self
+    }
+
+    /// Fail to load manifests with unknown fields, which are otherwise only
+    /// logged.
+    pub fn with_strict_manifests(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
     pub fn get_manifest(&self, device_id: &str) -> Result<Manifest> {
         // Check if already loaded
         {
@@ -62,25 +326,31 @@ impl ManifestManager {
             }
         }
 
-        // Load from disk
-        let manifest_path = self.manifest_dir.join(format!("{}.json", device_id));
-        info!("Loading manifest from: {}", manifest_path.display());
-
-        if !manifest_path.exists() {
-            return Err(anyhow!(
-                "Manifest not found for device '{}'. Expected file: {}. Make sure the manifest file exists and the device ID is correct.",
-                device_id,
-                manifest_path.display()
-            ));
-        }
-
-        let manifest = self.load_manifest_from_file(&manifest_path)?;
+        let (manifest, sources) = match self.module_ids(device_id) {
+            Some(modules) => self.load_modules(device_id, &modules)?,
+            None => self.load_file(device_id)?,
+        };
+        let source = match &sources[..] {
+            [(path, _), _] => path.display().to_string(),
+            _ => format!("for modules of '{}'", device_id),
+        };
+        check_tags(&manifest)
+            .and_then(|()| check_names(&manifest))
+            .and_then(|()| check_units(&manifest))
+            .and_then(|()| check_ack(&manifest))
+            .and_then(|()| composite::check(&manifest))
+            .and_then(|()| self_test::check(&manifest, self))
+            .map_err(|e| anyhow!("Invalid manifest {}: {}", source, e))?;
 
         // Cache the loaded manifest
         {
             let mut manifests = self.loaded_manifests.lock().unwrap();
             manifests.insert(device_id.to_string(), manifest.clone());
         }
+        self.loaded_mtimes
+            .lock()
+            .unwrap()
+            .insert(device_id.to_string(), sources);
 
         info!(
             "Loaded manifest for {}: {} (version: {})",
@@ -114,6 +384,131 @@ impl ManifestManager {
         Ok(manifest)
     }
 
+    /// Load the manifest file for `id` with its override, returning the
+    /// files read.
+    fn load_file(&self, id: &str) -> Result<(Manifest, LoadedMtimes)> {
+        let manifest_path = self.manifest_path(id);
+        info!("Loading manifest from: {}", manifest_path.display());
+
+        if !manifest_path.exists() {
+            return Err(ManifestNotFound {
+                device_id: id.to_string(),
+                path: manifest_path,
+            }
+            .into());
+        }
+
+        let modified = Self::modified_time(&manifest_path);
+        let override_path = self.override_path(id);
+        let override_modified = Self::modified_time(&override_path);
+        let mut manifest = self.load_manifest_from_file(&manifest_path)?;
+        if override_modified.is_some() {
+            info!("Applying manifest override {}", override_path.display());
+            let overlay = Self::load_override_from_file(&override_path)?;
+            apply_override(&mut manifest, overlay).map_err(|e| {
+                anyhow!(
+                    "Invalid manifest override {}: {}",
+                    override_path.display(),
+                    e
+                )
+            })?;
+        }
+        let sources = vec![
+            (manifest_path, modified),
+            (override_path, override_modified),
+        ];
+        Ok((manifest, sources))
+    }
+
+    /// The capability modules of a device reporting an ID like
+    /// `drivebase+armv2`, unless a manifest for the whole ID exists.
+    fn module_ids(&self, device_id: &str) -> Option<Vec<String>> {
+        if !device_id.contains(MODULE_SEPARATOR) || self.manifest_path(device_id).exists() {
+            return None;
+        }
+        Some(
+            device_id
+                .split(MODULE_SEPARATOR)
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    /// Merge the manifests of `modules` into one for `device_id`.
+    fn load_modules(
+        &self,
+        device_id: &str,
+        modules: &[String],
+    ) -> Result<(Manifest, LoadedMtimes)> {
+        let mut loaded = Vec::new();
+        let mut sources = Vec::new();
+        for module in modules {
+            let (manifest, files) = self.load_file(module)?;
+            loaded.push((module.as_str(), manifest));
+            sources.extend(files);
+        }
+        let manifest = merge_modules(device_id, loaded)
+            .map_err(|e| anyhow!("Cannot combine modules of '{}': {}", device_id, e))?;
+        Ok((manifest, sources))
+    }
+
+    /// Check whether the manifest file for a cached device was modified since it
+    /// was loaded, and drop the stale copy if so. Returns `true` when the cached
+    /// manifest was invalidated.
+    pub fn refresh_if_changed(&self, device_id: &str) -> bool {
+        let loaded = match self.loaded_mtimes.lock().unwrap().get(device_id) {
+            Some(loaded) => loaded.clone(),
+            None => return false,
+        };
+
+        // A deleted file counts as a change too, and so does adding or
+        // removing an override
+        let changed = loaded
+            .iter()
+            .any(|(path, modified)| Self::modified_time(path) != *modified);
+        if changed {
+            info!(
+                "Manifest or override for device '{}' changed on disk",
+                device_id
+            );
+            self.invalidate(device_id);
+        }
+        changed
+    }
+
+    /// File the manifest for `device_id` is (or would be) loaded from.
+    pub fn manifest_path(&self, device_id: &str) -> PathBuf {
+        self.device_manifests
+            .get(device_id)
+            .cloned()
+            .unwrap_or_else(|| self.manifest_dir.join(format!("{}.json", device_id)))
+    }
+
+    /// Override merged over the manifest for `device_id`, if the file exists.
+    pub fn override_path(&self, device_id: &str) -> PathBuf {
+        self.manifest_dir
+            .join("overrides")
+            .join(format!("{}.json", device_id))
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Drop the cached manifest so the next lookup re-reads it from disk.
+    pub fn invalidate(&self, device_id: &str) {
+        self.loaded_mtimes.lock().unwrap().remove(device_id);
+        if self
+            .loaded_manifests
+            .lock()
+            .unwrap()
+            .remove(device_id)
+            .is_some()
+        {
+            debug!("Dropped cached manifest for device: {}", device_id);
+        }
+    }
+
     pub fn list_available_manifests(&self) -> Result<Vec<String>> {
         let mut device_ids = Vec::new();
 
@@ -131,7 +526,7 @@ impl ManifestManager {
             let entry = entry?;
             let path = entry.path();
 
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
                 if let Some(stem) = path.file_stem() {
                     if let Some(device_id) = stem.to_str() {
                         device_ids.push(device_id.to_string());
@@ -146,19 +541,26 @@ impl ManifestManager {
         Ok(device_ids)
     }
 
+    /// Check `arguments` against the parameters of `func`. Errors are
+    /// [`ArgumentError`]s naming the offending argument.
     pub fn validate_function_arguments(&self, func: &Function, arguments: &Value) -> Result<()> {
-        let args_obj = arguments
-            .as_object()
-            .ok_or_else(|| anyhow!("Arguments must be an object"))?;
+        let args_obj = arguments.as_object().ok_or_else(|| {
+            ArgumentError::new(None, format!("expected object, got {}", arguments))
+        })?;
+        let given_unit = unit_argument(func, args_obj)?;
 
         // Check if function expects no parameters but arguments were provided
         if func.params.is_empty() && !args_obj.is_empty() {
             let provided_params: Vec<String> = args_obj.keys().cloned().collect();
-            return Err(anyhow!(
-                "Function '{}' takes no parameters, but you provided: [{}]. Remove all arguments.",
-                func.name,
-                provided_params.join(", ")
-            ));
+            return Err(ArgumentError::new(
+                args_obj.keys().next(),
+                format!(
+                    "Function '{}' takes no parameters, but you provided: [{}]. Remove all arguments.",
+                    func.name,
+                    provided_params.join(", ")
+                ),
+            )
+            .into());
         }
 
         // Check if function expects parameters but none were provided
@@ -168,86 +570,109 @@ impl ManifestManager {
                 .iter()
                 .map(|p| format!("{}: {}", p.name, type_to_json_type(&p.param_type)))
                 .collect();
-            return Err(anyhow!(
-                "Function '{}' requires {} parameters: [{}]. Please provide all required arguments.",
-                func.name,
-                func.params.len(),
-                param_specs.join(", ")
-            ));
+            return Err(ArgumentError::new(
+                None,
+                format!(
+                    "Function '{}' requires {} parameters: [{}]. Please provide all required arguments.",
+                    func.name,
+                    func.params.len(),
+                    param_specs.join(", ")
+                ),
+            )
+            .into());
         }
 
         // Check for unexpected parameters first (more actionable error)
         for arg_name in args_obj.keys() {
+            if given_unit.is_some() && arg_name == UNIT_ARGUMENT {
+                continue;
+            }
             if !func.params.iter().any(|p| &p.name == arg_name) {
                 let param_specs: Vec<String> = func
                     .params
                     .iter()
                     .map(|p| format!("{}: {}", p.name, type_to_json_type(&p.param_type)))
                     .collect();
-                return Err(anyhow!(
-                    "Invalid parameter '{}' for function '{}'. Valid parameters are: [{}]. Please correct the parameter name.",
-                    arg_name,
-                    func.name,
-                    param_specs.join(", ")
-                ));
+                return Err(ArgumentError::new(
+                    Some(arg_name),
+                    format!(
+                        "Invalid parameter '{}' for function '{}'. Valid parameters are: [{}]. Please correct the parameter name.",
+                        arg_name,
+                        func.name,
+                        param_specs.join(", ")
+                    ),
+                )
+                .into());
             }
         }
 
         // Check each required parameter
         for param in &func.params {
-            if !args_obj.contains_key(&param.name) {
-                return Err(anyhow!(
+            let invalid = |message: String| ArgumentError::new(Some(&param.name), message).into();
+
+            let Some(arg_value) = args_obj.get(&param.name) else {
+                return Err(invalid(format!(
                     "Missing required parameter '{}' (type: {}) for function '{}'. Please add this parameter to your arguments.",
                     param.name,
                     type_to_json_type(&param.param_type),
                     func.name
-                ));
-            }
-
-            let arg_value = &arguments[&param.name];
+                )));
+            };
 
             // Validate parameter type
             match param.param_type.as_str() {
                 "i16" | "i32" => {
-                    if !arg_value.is_number() {
-                        return Err(anyhow!(
-                            "Parameter '{}' must be a number (type: {}), but got {}. Please provide a numeric value.",
-                            param.name,
-                            type_to_json_type(&param.param_type),
+                    let converted = match param.unit {
+                        Some(unit) => units::resolve(unit, arg_value, given_unit)
+                            .map_err(|e| invalid(e.to_string()))?,
+                        None => None,
+                    };
+                    let value = match converted {
+                        Some(value) => Some(value),
+                        None if self.lenient_numbers => coerce_integer(arg_value),
+                        None => arg_value.as_i64(),
+                    };
+                    let Some(value) = value else {
+                        return Err(invalid(format!(
+                            "expected integer, got {}. Please provide a whole number.",
                             arg_value
-                        ));
+                        )));
+                    };
+
+                    let below = param.minimum.is_some_and(|min| value < min);
+                    let above = param.maximum.is_some_and(|max| value > max);
+                    if below || above {
+                        return Err(invalid(format!(
+                            "Parameter '{}' value {} is out of the allowed range ({} to {}). Please use a value within this range.",
+                            param.name,
+                            value,
+                            param.minimum.map_or("-".to_string(), |min| min.to_string()),
+                            param.maximum.map_or("-".to_string(), |max| max.to_string())
+                        )));
                     }
 
-                    if param.param_type == "i16" {
-                        let value = arg_value.as_i64().unwrap_or(0);
-                        if value < i16::MIN as i64 || value > i16::MAX as i64 {
-                            return Err(anyhow!(
-                                "Parameter '{}' value {} is out of range for i16 ({} to {}). Please use a value within this range.",
-                                param.name,
-                                value,
-                                i16::MIN,
-                                i16::MAX
-                            ));
-                        }
+                    let (min, max) = match param.param_type.as_str() {
+                        "i16" => (i16::MIN as i64, i16::MAX as i64),
+                        _ => (i32::MIN as i64, i32::MAX as i64),
+                    };
+                    if value < min || value > max {
+                        return Err(invalid(format!(
+                            "Parameter '{}' value {} is out of range for {} ({} to {}). Please use a value within this range.",
+                            param.name, value, param.param_type, min, max
+                        )));
                     }
                 }
-                "CStr" => {
-                    if !arg_value.is_string() {
-                        return Err(anyhow!(
-                            "Parameter '{}' must be a string, but got {}. Please provide a string value in quotes.",
-                            param.name,
-                            arg_value
-                        ));
-                    }
+                "CStr" if !arg_value.is_string() => {
+                    return Err(invalid(format!(
+                        "expected string, got {}. Please provide a string value in quotes.",
+                        arg_value
+                    )));
                 }
-                "bool" => {
-                    if !arg_value.is_boolean() {
-                        return Err(anyhow!(
-                            "Parameter '{}' must be a boolean (true/false), but got {}. Please use true or false.",
-                            param.name,
-                            arg_value
-                        ));
-                    }
+                "bool" if !arg_value.is_boolean() => {
+                    return Err(invalid(format!(
+                        "expected boolean, got {}. Please use true or false.",
+                        arg_value
+                    )));
                 }
                 _ => {
                     // Unknown types - accept any value and try to convert to string
@@ -259,13 +684,23 @@ impl ManifestManager {
     }
 
     pub fn create_tools_list(&self, manifest: &Manifest) -> Vec<Tool> {
+        let composites = manifest.composites.iter().map(Composite::signature);
         manifest
             .functions
             .iter()
+            .cloned()
+            .chain(composites)
             .map(|func| Tool {
-                name: func.name.clone(),
-                description: func.desc.clone(),
-                input_schema: self.create_input_schema(func),
+                input_schema: self.create_input_schema(&func),
+                description: match func.ack {
+                    Ack::Immediate => format!(
+                        "{} (returns once the action has started; {})",
+                        func.desc,
+                        running_hint(manifest)
+                    ),
+                    Ack::Complete => func.desc,
+                },
+                name: func.name,
             })
             .collect()
     }
@@ -275,16 +710,41 @@ impl ManifestManager {
         let mut required = Vec::new();
 
         for param in &func.params {
-            let param_schema = match param.param_type.as_str() {
+            let mut param_schema = match param.param_type.as_str() {
                 "i16" | "i32" | "i64" => serde_json::json!({"type": "integer"}),
                 "f32" | "f64" => serde_json::json!({"type": "number"}),
                 "CStr" => serde_json::json!({"type": "string"}),
                 "bool" => serde_json::json!({"type": "boolean"}),
                 _ => serde_json::json!({"type": "string"}),
             };
+            if let Some(min) = param.minimum {
+                param_schema["minimum"] = min.into();
+            }
+            if let Some(max) = param.maximum {
+                param_schema["maximum"] = max.into();
+            }
+            if let Some(unit) = param.unit {
+                param_schema["description"] = format!(
+                    "In {}; also accepts a string with another unit, e.g. \"{}\"",
+                    unit,
+                    unit.example()
+                )
+                .into();
+            }
             properties.insert(param.name.clone(), param_schema);
             required.push(param.name.clone());
         }
+        if func.takes_unit_argument() {
+            let accepted: Vec<&str> = func.accepted_units().into_iter().map(Unit::name).collect();
+            properties.insert(
+                UNIT_ARGUMENT.to_string(),
+                serde_json::json!({
+                    "type": "string",
+                    "enum": accepted,
+                    "description": "Unit of the plain numbers given for parameters that have one, instead of their own"
+                }),
+            );
+        }
 
         serde_json::json!({
             "type": "object",
@@ -297,13 +757,555 @@ impl ManifestManager {
         let content = std::fs::read_to_string(path)
             .map_err(|e| anyhow!("Failed to read manifest file {}: {}", path.display(), e))?;
 
-        let manifest: Manifest = serde_json::from_str(&content)
-            .map_err(|e| anyhow!("Failed to parse manifest file {}: {}", path.display(), e))?;
+        manifest_schema::parse(
+            &content,
+            self.strict,
+            &format!("manifest file {}", path.display()),
+        )
+    }
 
-        Ok(manifest)
+    fn load_override_from_file(path: &Path) -> Result<ManifestOverride> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read manifest override {}: {}", path.display(), e))?;
+
+        serde_json::from_str(&content).map_err(|e| {
+            anyhow!(
+                "Failed to parse manifest override {}: {}",
+                path.display(),
+                e
+            )
+        })
     }
 }
 
+/// Merge `overlay` into `manifest`. Names the overlay refers to must exist,
+/// so a renamed function doesn't silently lose its override.
+fn apply_override(manifest: &mut Manifest, overlay: ManifestOverride) -> Result<()> {
+    if let Some(description) = overlay.description {
+        manifest.description = description;
+    }
+
+    let mut hidden = Vec::new();
+    for (name, changes) in overlay.functions {
+        let func = manifest
+            .functions
+            .iter_mut()
+            .find(|f| f.name == name)
+            .ok_or_else(|| anyhow!("function '{}' is not in the manifest", name))?;
+        if let Some(desc) = changes.desc {
+            func.desc = desc;
+        }
+        if let Some(ack) = changes.ack {
+            func.ack = ack;
+        }
+        if changes.mutates.is_some() {
+            func.mutates = changes.mutates;
+        }
+        for (param_name, param_changes) in changes.params {
+            let param = func
+                .params
+                .iter_mut()
+                .find(|p| p.name == param_name)
+                .ok_or_else(|| anyhow!("function '{}' has no parameter '{}'", name, param_name))?;
+            if !matches!(param.param_type.as_str(), "i16" | "i32") {
+                return Err(anyhow!(
+                    "parameter '{}' of '{}' is not an integer, so it has no range or unit",
+                    param_name,
+                    name
+                ));
+            }
+            // Only ever narrow what the firmware accepts
+            if let Some(min) = param_changes.minimum {
+                param.minimum = Some(param.minimum.map_or(min, |base| base.max(min)));
+            }
+            if let Some(max) = param_changes.maximum {
+                param.maximum = Some(param.maximum.map_or(max, |base| base.min(max)));
+            }
+            if let (Some(min), Some(max)) = (param.minimum, param.maximum) {
+                if min > max {
+                    return Err(anyhow!(
+                        "range {} to {} for '{}' of '{}' is empty",
+                        min,
+                        max,
+                        param_name,
+                        name
+                    ));
+                }
+            }
+            if param_changes.unit.is_some() {
+                param.unit = param_changes.unit;
+            }
+        }
+        if changes.hidden {
+            hidden.push(name);
+        }
+    }
+
+    for name in &hidden {
+        let hooks = [
+            ("safe_state", manifest.safe_state.as_ref()),
+            ("on_connect", manifest.on_connect.as_ref()),
+            ("on_disconnect", manifest.on_disconnect.as_ref()),
+            ("busy", manifest.busy.as_ref()),
+            ("watchdog", manifest.watchdog.as_ref().map(|w| &w.function)),
+            ("battery", manifest.battery.as_ref().map(|b| &b.function)),
+            ("odometry pose", manifest.odometry.as_ref().map(|o| &o.pose)),
+        ];
+        if let Some((hook, _)) = hooks.iter().find(|(_, f)| *f == Some(name)) {
+            return Err(anyhow!(
+                "function '{}' is the manifest's {} and can't be hidden",
+                name,
+                hook
+            ));
+        }
+    }
+    manifest.functions.retain(|f| !hidden.contains(&f.name));
+
+    for composite in overlay.composites {
+        manifest.composites.retain(|c| c.name != composite.name);
+        manifest.composites.push(composite);
+    }
+    if let Some(steps) = overlay.self_test {
+        manifest.self_test = steps;
+    }
+    Ok(())
+}
+
+/// No manifest file exists for a device.
+#[derive(Debug)]
+pub struct ManifestNotFound {
+    pub device_id: String,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for ManifestNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Manifest not found for device '{}'. Expected file: {}. Make sure the manifest file exists and the device ID is correct.",
+            self.device_id,
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for ManifestNotFound {}
+
+/// Stand-in for the missing manifest of `device_id`, so the board can
+/// still be interrogated: `deviceId`, and `getManifestVersion` if its
+/// firmware answers the reserved tag.
+pub fn fallback(device_id: &str, reports_manifest_version: bool) -> Manifest {
+    let getter = |tag, name: &str, desc: &str| Function {
+        tag,
+        name: name.to_string(),
+        desc: desc.to_string(),
+        return_type: Some("CStr".to_string()),
+        params: Vec::new(),
+        ack: Ack::Complete,
+        mutates: Some(false),
+        debounce_ms: None,
+    };
+    let mut functions = vec![getter(
+        DEVICE_ID_TAG,
+        "deviceId",
+        "Get the device's ID, the name its manifest file should have",
+    )];
+    if reports_manifest_version {
+        functions.push(getter(
+            MANIFEST_VERSION_TAG,
+            "getManifestVersion",
+            "Get the version of the manifest the firmware was built from",
+        ));
+    }
+    Manifest {
+        name: device_id.to_string(),
+        description: format!("{} (no manifest, generic tools only)", device_id),
+        version: String::new(),
+        functions,
+        safe_state: None,
+        on_connect: None,
+        on_disconnect: None,
+        busy: None,
+        watchdog: None,
+        battery: None,
+        odometry: None,
+        board: None,
+        composites: Vec::new(),
+        self_test: Vec::new(),
+        fallback: true,
+    }
+}
+
+/// `manifest` cut down to its read-only functions and the composites made
+/// of them, for `--read-only`. A self-test calling anything else goes too.
+pub fn read_only(mut manifest: Manifest) -> Manifest {
+    manifest.functions.retain(Function::is_read_only);
+    let composites = std::mem::take(&mut manifest.composites);
+    manifest.composites = composites
+        .into_iter()
+        .filter(|c| composite::fits(c, &manifest))
+        .collect();
+    if !self_test::fits(&manifest.self_test, &manifest) {
+        manifest.self_test.clear();
+    }
+    manifest
+}
+
+/// For the tool description and result of an `"ack": "immediate"`
+/// function: how to tell when its action has ended.
+pub fn running_hint(manifest: &Manifest) -> String {
+    match &manifest.busy {
+        Some(busy) => format!("call {} until it returns 0 to know it has finished", busy),
+        None => "it may still be running".to_string(),
+    }
+}
+
+/// `value` as an integer, also accepting numeric strings such as `"90"` and
+/// whole floats such as `90.0`.
+pub fn coerce_integer(value: &Value) -> Option<i64> {
+    if let Some(value) = value.as_i64() {
+        return Some(value);
+    }
+    let float = match value {
+        Value::String(s) => {
+            let s = s.trim();
+            if let Ok(value) = s.parse::<i64>() {
+                return Some(value);
+            }
+            s.parse::<f64>().ok()?
+        }
+        _ => value.as_f64()?,
+    };
+    let whole = float.fract() == 0.0 && float >= i64::MIN as f64 && float < i64::MAX as f64;
+    whole.then_some(float as i64)
+}
+
+/// `arguments` with every integer parameter given as a string or whole
+/// float converted to an integer. Validation has already decided whether
+/// such values are accepted, so this only turns them into what the
+/// encoder expects.
+pub fn coerce_arguments(func: &Function, arguments: &Value) -> Value {
+    let mut arguments = arguments.clone();
+    let Some(args) = arguments.as_object_mut() else {
+        return arguments;
+    };
+    let given_unit = match func.takes_unit_argument() {
+        true => args
+            .remove(UNIT_ARGUMENT)
+            .and_then(|unit| unit.as_str().and_then(Unit::parse)),
+        false => None,
+    };
+    for param in &func.params {
+        if !matches!(param.param_type.as_str(), "i16" | "i32") {
+            continue;
+        }
+        let Some(value) = args.get_mut(&param.name) else {
+            continue;
+        };
+        let converted = param
+            .unit
+            .and_then(|unit| units::resolve(unit, value, given_unit).ok().flatten());
+        if let Some(integer) = converted {
+            debug!(
+                "Converted '{}' argument {} from {} to {}{}",
+                func.name,
+                param.name,
+                value,
+                integer,
+                param.unit.unwrap()
+            );
+            *value = integer.into();
+            continue;
+        }
+        if value.is_i64() {
+            continue;
+        }
+        if let Some(integer) = coerce_integer(value) {
+            info!(
+                "Coerced '{}' argument {} from {} to {}",
+                func.name, param.name, value, integer
+            );
+            *value = integer.into();
+        }
+    }
+    arguments
+}
+
+/// The call's `unit` argument, if the function takes one and it was given.
+fn unit_argument(func: &Function, args: &serde_json::Map<String, Value>) -> Result<Option<Unit>> {
+    let Some(value) = args
+        .get(UNIT_ARGUMENT)
+        .filter(|_| func.takes_unit_argument())
+    else {
+        return Ok(None);
+    };
+    let accepted = func.accepted_units();
+    match value.as_str().and_then(Unit::parse) {
+        Some(unit) if accepted.contains(&unit) => Ok(Some(unit)),
+        _ => {
+            let names: Vec<&str> = accepted.into_iter().map(Unit::name).collect();
+            let message = format!("expected one of {}, got {}", names.join(", "), value);
+            Err(ArgumentError::new(Some(&UNIT_ARGUMENT.to_string()), message).into())
+        }
+    }
+}
+
+/// Each function needs a tag of its own; tag 0 belongs to `deviceId` and the
+/// reserved range to the adapter. Otherwise a call could run the wrong
+/// function on the robot.
+fn check_tags(manifest: &Manifest) -> Result<()> {
+    let mut seen: HashMap<u8, &str> = HashMap::new();
+    for func in &manifest.functions {
+        if (func.tag == DEVICE_ID_TAG) != (func.name == DEVICE_ID_FUNCTION) {
+            return Err(anyhow!(
+                "tag {} belongs to {}, but '{}' has tag {}",
+                DEVICE_ID_TAG,
+                DEVICE_ID_FUNCTION,
+                func.name,
+                func.tag
+            ));
+        }
+        if RESERVED_TAGS.contains(&func.tag) {
+            return Err(anyhow!(
+                "'{}' has tag {:#04x}, but {:#04x}-{:#04x} are reserved for the adapter",
+                func.name,
+                func.tag,
+                RESERVED_TAGS.start(),
+                RESERVED_TAGS.end()
+            ));
+        }
+        if let Some(other) = seen.insert(func.tag, &func.name) {
+            return Err(anyhow!(
+                "tag {} is used by both '{}' and '{}'",
+                func.tag,
+                other,
+                func.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Names of built-in tools the adapter answers before looking at the
+/// manifest, so a function or composite with one could never be called.
+const RESERVED_NAMES: [&str; 2] = [sequence::WAIT, "clearFault"];
+
+fn check_names(manifest: &Manifest) -> Result<()> {
+    let names = manifest
+        .functions
+        .iter()
+        .map(|f| &f.name)
+        .chain(manifest.composites.iter().map(|c| &c.name));
+    for name in names {
+        if RESERVED_NAMES.contains(&name.as_str()) {
+            return Err(anyhow!(
+                "'{}' is the name of a built-in tool; rename it in the manifest and the firmware",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Units only make sense for integer parameters.
+fn check_units(manifest: &Manifest) -> Result<()> {
+    for func in &manifest.functions {
+        for param in &func.params {
+            if param.unit.is_some() && !matches!(param.param_type.as_str(), "i16" | "i32") {
+                return Err(anyhow!(
+                    "parameter '{}' of '{}' has a unit but is not an integer",
+                    param.name,
+                    func.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An immediately acknowledged function has nothing to return, and the
+/// `busy` poller must answer at once with a number.
+fn check_ack(manifest: &Manifest) -> Result<()> {
+    for func in manifest
+        .functions
+        .iter()
+        .filter(|f| f.ack == Ack::Immediate)
+    {
+        if !matches!(func.return_type.as_deref(), None | Some("void")) {
+            return Err(anyhow!(
+                "'{}' is acknowledged immediately, so it can't return {}",
+                func.name,
+                func.return_type.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+    let Some(name) = &manifest.busy else {
+        return Ok(());
+    };
+    let func = manifest
+        .functions
+        .iter()
+        .find(|f| f.name == *name)
+        .ok_or_else(|| anyhow!("busy function '{}' not found", name))?;
+    let returns_integer = matches!(func.return_type.as_deref(), Some("i16" | "i32"));
+    if !func.params.is_empty() || !returns_integer || func.ack == Ack::Immediate {
+        return Err(anyhow!(
+            "busy function '{}' must take no parameters, return an integer and be acknowledged on completion",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// One manifest for a modular device: the modules' functions and composites
+/// side by side. A tag or name may be used by only one module, and so may
+/// each of the single settings such as `safe_state` or `board`.
+fn merge_modules(device_id: &str, modules: Vec<(&str, Manifest)>) -> Result<Manifest> {
+    let mut merged = Manifest {
+        name: device_id.to_string(),
+        description: String::new(),
+        version: String::new(),
+        functions: Vec::new(),
+        safe_state: None,
+        on_connect: None,
+        on_disconnect: None,
+        busy: None,
+        watchdog: None,
+        battery: None,
+        odometry: None,
+        board: None,
+        composites: Vec::new(),
+        self_test: Vec::new(),
+        fallback: false,
+    };
+    let mut descriptions = Vec::new();
+    let mut versions = Vec::new();
+    // Module each function and composite came from, by name
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    let mut tags: HashMap<u8, (&str, String)> = HashMap::new();
+
+    for (module, manifest) in modules {
+        descriptions.push(manifest.description);
+        versions.push(manifest.version);
+        for func in manifest.functions {
+            // Every module's bindings include deviceId
+            if func.tag == DEVICE_ID_TAG && owners.contains_key(DEVICE_ID_FUNCTION) {
+                continue;
+            }
+            if let Some((other, name)) = tags.get(&func.tag) {
+                return Err(anyhow!(
+                    "tag {} is used by both {}.{} and {}.{}",
+                    func.tag,
+                    other,
+                    name,
+                    module,
+                    func.name
+                ));
+            }
+            tags.insert(func.tag, (module, func.name.clone()));
+            claim(&mut owners, &func.name, module)?;
+            merged.functions.push(func);
+        }
+        for composite in manifest.composites {
+            claim(&mut owners, &composite.name, module)?;
+            merged.composites.push(composite);
+        }
+        // Each module tests its own hardware
+        merged.self_test.extend(manifest.self_test);
+        take_single(
+            &mut merged.safe_state,
+            manifest.safe_state,
+            "safe_state",
+            module,
+        )?;
+        take_single(
+            &mut merged.on_connect,
+            manifest.on_connect,
+            "on_connect",
+            module,
+        )?;
+        take_single(
+            &mut merged.on_disconnect,
+            manifest.on_disconnect,
+            "on_disconnect",
+            module,
+        )?;
+        take_single(&mut merged.busy, manifest.busy, "busy", module)?;
+        take_single(&mut merged.watchdog, manifest.watchdog, "watchdog", module)?;
+        take_single(&mut merged.battery, manifest.battery, "battery", module)?;
+        take_single(&mut merged.odometry, manifest.odometry, "odometry", module)?;
+        take_single(&mut merged.board, manifest.board, "board", module)?;
+    }
+
+    merged.description = descriptions.join("; ");
+    merged.version = versions.join(&MODULE_SEPARATOR.to_string());
+    Ok(merged)
+}
+
+fn claim<'a>(owners: &mut HashMap<String, &'a str>, name: &str, module: &'a str) -> Result<()> {
+    match owners.insert(name.to_string(), module) {
+        Some(other) => Err(anyhow!(
+            "'{}' is defined by both {} and {}",
+            name,
+            other,
+            module
+        )),
+        None => Ok(()),
+    }
+}
+
+fn take_single<T>(
+    merged: &mut Option<T>,
+    value: Option<T>,
+    field: &str,
+    module: &str,
+) -> Result<()> {
+    if value.is_some() {
+        if merged.is_some() {
+            return Err(anyhow!(
+                "more than one module sets `{}`, including {}",
+                field,
+                module
+            ));
+        }
+        *merged = value;
+    }
+    Ok(())
+}
+
+/// An argument that doesn't match its parameter. `pointer` is a JSON
+/// pointer into the arguments object, e.g. `/angle`, or empty when the
+/// object as a whole is wrong.
+#[derive(Debug)]
+pub struct ArgumentError {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl ArgumentError {
+    fn new(name: Option<&String>, message: String) -> Self {
+        let pointer = name.map_or_else(String::new, |name| format!("/{}", pointer_token(name)));
+        Self { pointer, message }
+    }
+}
+
+/// `key` escaped as a JSON pointer token (RFC 6901): `~` as `~0`, `/` as `~1`.
+pub fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.pointer.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.pointer, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ArgumentError {}
+
 pub fn type_to_json_type(rust_type: &str) -> &'static str {
     match rust_type {
         "i16" | "i32" | "i64" => "integer",
@@ -313,3 +1315,293 @@ pub fn type_to_json_type(rust_type: &str) -> &'static str {
         _ => "string", // Default fallback
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, function};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn blink() -> serde_json::Value {
+        function(1, "blinkLED", None, &[("n", "i16")])
+    }
+
+    #[test]
+    fn test_refresh_if_changed_detects_modified_file() {
+        let mut manifest = fixtures::manifest_json(json!([blink()]), json!({}));
+        let dir = fixtures::manifest_dir(&manifest);
+        let path = dir.path().join("test-robot.json");
+
+        let manager = ManifestManager::new(dir.path().to_path_buf());
+        assert_eq!(manager.get_manifest("test-robot").unwrap().version, "v1");
+        assert!(!manager.refresh_if_changed("test-robot"));
+
+        manifest["version"] = json!("v2");
+        std::fs::write(&path, manifest.to_string()).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+
+        assert!(manager.refresh_if_changed("test-robot"));
+        assert_eq!(manager.get_manifest("test-robot").unwrap().version, "v2");
+    }
+
+    #[test]
+    fn test_override_merged_over_manifest() {
+        let dir = fixtures::manifest_dir(&fixtures::manifest_json(
+            json!([function(2, "stop", None, &[]), blink()]),
+            json!({"safe_state": "stop"}),
+        ));
+        let manager = ManifestManager::new(dir.path().to_path_buf());
+        std::fs::create_dir(dir.path().join("overrides")).unwrap();
+        let write_override = |content: &str| {
+            std::fs::write(manager.override_path("test-robot"), content).unwrap();
+            manager.invalidate("test-robot");
+        };
+
+        write_override(
+            r#"{"functions": {"blinkLED": {"desc": "Blink slowly",
+                "params": {"n": {"minimum": 1, "maximum": 5}}}}}"#,
+        );
+        let manifest = manager.get_manifest("test-robot").unwrap();
+        let blink = &manifest.functions[1];
+        assert_eq!(blink.desc, "Blink slowly");
+        let schema = manager.create_input_schema(blink);
+        assert_eq!(schema["properties"]["n"]["maximum"], 5);
+        assert!(manager
+            .validate_function_arguments(blink, &serde_json::json!({"n": 6}))
+            .is_err());
+        assert!(manager
+            .validate_function_arguments(blink, &serde_json::json!({"n": 5}))
+            .is_ok());
+
+        write_override(r#"{"functions": {"blinkLED": {"hidden": true}}}"#);
+        let manifest = manager.get_manifest("test-robot").unwrap();
+        assert_eq!(manifest.functions.len(), 1);
+
+        for invalid in [
+            r#"{"functions": {"stop": {"hidden": true}}}"#,
+            r#"{"functions": {"fly": {"desc": "Fly"}}}"#,
+            r#"{"functions": {"blinkLED": {"params": {"n": {"minimum": 9, "maximum": 3}}}}}"#,
+            r#"{"functoins": {}}"#,
+        ] {
+            write_override(invalid);
+            assert!(manager.get_manifest("test-robot").is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_lenient_numbers_coerced_only_when_enabled() {
+        let manifest = fixtures::manifest(json!([blink()]));
+        let blink = &manifest.functions[0];
+        let strict = ManifestManager::new(std::env::temp_dir());
+        let lenient = ManifestManager::new(std::env::temp_dir()).with_lenient_numbers(true);
+
+        for value in [serde_json::json!("90"), serde_json::json!(90.0)] {
+            let arguments = serde_json::json!({ "n": value });
+            assert!(strict
+                .validate_function_arguments(blink, &arguments)
+                .is_err());
+            lenient
+                .validate_function_arguments(blink, &arguments)
+                .unwrap();
+            assert_eq!(
+                coerce_arguments(blink, &arguments),
+                serde_json::json!({"n": 90})
+            );
+        }
+        for value in [serde_json::json!("ninety"), serde_json::json!(90.5)] {
+            let arguments = serde_json::json!({ "n": value });
+            assert!(lenient
+                .validate_function_arguments(blink, &arguments)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_unit_arguments_converted() {
+        let func: Function = serde_json::from_value(serde_json::json!({
+            "tag": 1, "name": "turn", "desc": "Turn", "return": null,
+            "params": [{"name": "angle", "type": "i16", "unit": "deg", "maximum": 180},
+                       {"name": "duration", "type": "i16", "unit": "ms"}]
+        }))
+        .unwrap();
+        let manager = ManifestManager::new(std::env::temp_dir());
+
+        let arguments = serde_json::json!({"angle": 1.57, "duration": "1.5s", "unit": "rad"});
+        manager
+            .validate_function_arguments(&func, &arguments)
+            .unwrap();
+        assert_eq!(
+            coerce_arguments(&func, &arguments),
+            serde_json::json!({"angle": 90, "duration": 1500})
+        );
+
+        // Ranges apply to the converted value
+        let arguments = serde_json::json!({"angle": "4rad", "duration": 0});
+        let err = manager
+            .validate_function_arguments(&func, &arguments)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("/angle: "), "{}", err);
+        let arguments = serde_json::json!({"angle": 90, "duration": 0, "unit": "cm"});
+        let err = manager
+            .validate_function_arguments(&func, &arguments)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "/unit: expected one of deg, rad, ms, s, got \"cm\""
+        );
+
+        let schema = manager.create_input_schema(&func);
+        assert_eq!(
+            schema["properties"]["unit"]["enum"],
+            serde_json::json!(["deg", "rad", "ms", "s"])
+        );
+        assert_eq!(schema["required"], serde_json::json!(["angle", "duration"]));
+    }
+
+    #[test]
+    fn test_modules_merged_and_collisions_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let module = |name: &str, tag: u8, function: &str| {
+            let manifest = serde_json::json!({
+                "name": name, "description": name, "version": "v1",
+                "functions": [
+                    {"tag": 0, "name": "deviceId", "desc": "", "return": "CStr", "params": []},
+                    {"tag": tag, "name": function, "desc": "", "return": null, "params": []}
+                ]
+            });
+            std::fs::write(
+                dir.path().join(format!("{}.json", name)),
+                manifest.to_string(),
+            )
+            .unwrap();
+        };
+        module("drivebase", 1, "drive");
+        module("armv2", 2, "grip");
+        module("gripper", 2, "clamp");
+        let manager = ManifestManager::new(dir.path().to_path_buf());
+
+        let manifest = manager.get_manifest("drivebase+armv2").unwrap();
+        assert_eq!(manifest.name, "drivebase+armv2");
+        assert_eq!(manifest.version, "v1+v1");
+        let names: Vec<&str> = manifest.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["deviceId", "drive", "grip"]);
+
+        let err = manager.get_manifest("armv2+gripper").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot combine modules of 'armv2+gripper': tag 2 is used by both armv2.grip and gripper.clamp"
+        );
+        assert!(manager.get_manifest("drivebase+lidar").is_err());
+    }
+
+    #[test]
+    fn test_immediate_ack_checked_and_described() {
+        let manifest = |busy: &str, move_return: &str| -> Manifest {
+            serde_json::from_str(&format!(
+                r#"{{"name": "arm", "description": "Arm", "version": "v1", "busy": "{}",
+                "functions": [
+                    {{"tag": 1, "name": "moveTo", "desc": "Move", "return": {}, "ack": "immediate",
+                     "params": [{{"name": "angle", "type": "i16"}}]}},
+                    {{"tag": 2, "name": "isBusy", "desc": "Moving?", "return": "i16", "params": []}}
+                ]}}"#,
+                busy, move_return
+            ))
+            .unwrap()
+        };
+
+        let good = manifest("isBusy", "null");
+        check_ack(&good).unwrap();
+        let tools = ManifestManager::new(PathBuf::new()).create_tools_list(&good);
+        assert_eq!(
+            tools[0].description,
+            "Move (returns once the action has started; call isBusy until it returns 0 to know it has finished)"
+        );
+        assert_eq!(tools[1].description, "Moving?");
+
+        assert_eq!(
+            check_ack(&manifest("isBusy", "\"i16\""))
+                .unwrap_err()
+                .to_string(),
+            "'moveTo' is acknowledged immediately, so it can't return i16"
+        );
+        assert!(check_ack(&manifest("moveTo", "null")).is_err());
+        assert!(check_ack(&manifest("isMoving", "null")).is_err());
+    }
+
+    #[test]
+    fn test_read_only_keeps_functions_that_only_read() {
+        let manifest: Manifest = serde_json::from_value(serde_json::json!({
+            "name": "arm", "description": "Arm", "version": "v1",
+            "functions": [
+                {"tag": 1, "name": "getAngle", "desc": "Angle", "return": "i16", "params": []},
+                {"tag": 2, "name": "getSensor", "desc": "Sensor", "return": "i16",
+                 "params": [{"name": "id", "type": "i16"}], "mutates": false},
+                {"tag": 3, "name": "moveTo", "desc": "Move", "return": null,
+                 "params": [{"name": "angle", "type": "i16"}]},
+                {"tag": 4, "name": "nextFrame", "desc": "Advance", "return": "i16", "params": [],
+                 "mutates": true}
+            ],
+            "composites": [
+                {"name": "survey", "desc": "Read both", "steps": [
+                    {"call": "getAngle"}, {"call": "getSensor", "arguments": {"id": 1}}]},
+                {"name": "wave", "desc": "Wave", "steps": [
+                    {"call": "moveTo", "arguments": {"angle": 0}}, {"call": "getAngle"}]}
+            ]
+        }))
+        .unwrap();
+
+        let manifest = read_only(manifest);
+        let names: Vec<&str> = manifest.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["getAngle", "getSensor"]);
+        assert_eq!(manifest.composites.len(), 1);
+        assert_eq!(manifest.composites[0].name, "survey");
+    }
+
+    #[test]
+    fn test_bad_tags_rejected() {
+        let manifest = fixtures::manifest;
+        let func = |tag: u8, name: &str| function(tag, name, None, &[]);
+
+        check_tags(&manifest(serde_json::json!([
+            func(0, "deviceId"),
+            func(1, "home")
+        ])))
+        .unwrap();
+        for (functions, error) in [
+            (
+                serde_json::json!([func(0, "home")]),
+                "tag 0 belongs to deviceId, but 'home' has tag 0",
+            ),
+            (
+                serde_json::json!([func(1, "home"), func(1, "grip")]),
+                "tag 1 is used by both 'home' and 'grip'",
+            ),
+            (
+                serde_json::json!([func(0xF2, "ping")]),
+                "'ping' has tag 0xf2, but 0xf0-0xff are reserved for the adapter",
+            ),
+        ] {
+            assert_eq!(
+                check_tags(&manifest(functions)).unwrap_err().to_string(),
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_reserved_names_rejected() {
+        for name in ["wait", "clearFault"] {
+            let manifest = fixtures::manifest(json!([function(1, name, None, &[])]));
+            assert_eq!(
+                check_names(&manifest).unwrap_err().to_string(),
+                format!(
+                    "'{}' is the name of a built-in tool; rename it in the manifest and the firmware",
+                    name
+                )
+            );
+        }
+    }
+}
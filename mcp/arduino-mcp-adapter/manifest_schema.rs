@@ -0,0 +1,311 @@
+//! `manifest.schema.json`, the authoritative description of a manifest, and
+//! parsing that catches fields it doesn't describe, such as `"paramss"`,
+//! which serde would otherwise skip without a word.
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+/// JSON Schema for manifest files, for editors and CI checks.
+pub const SCHEMA: &str = include_str!("../manifest.schema.json");
+
+/// One step of the path to a value.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A field the target type has no place for.
+struct UnknownField {
+    /// Path to the field itself, its name last
+    path: Vec<Segment>,
+}
+
+impl UnknownField {
+    /// E.g. "unknown field `paramss` in /functions/2 at line 9 column 14".
+    fn describe(&self, content: &str) -> String {
+        let (parent, name) = self.path.split_at(self.path.len().saturating_sub(1));
+        let name = match name {
+            [Segment::Key(name)] => name.as_str(),
+            _ => "?",
+        };
+        let mut message = format!("unknown field `{}`", name);
+        if !parent.is_empty() {
+            message.push_str(&format!(" in {}", pointer(parent)));
+        }
+        if let Some((line, column)) = locate(content, &self.path) {
+            message.push_str(&format!(" at line {} column {}", line, column));
+        }
+        message
+    }
+}
+
+/// Parse `content` as `T`. Fields `T` doesn't know are an error when
+/// `strict`, and are logged otherwise. `what` names the file in messages.
+pub fn parse<T: DeserializeOwned>(content: &str, strict: bool, what: &str) -> Result<T> {
+    let mut unknown = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_str(content);
+    let value: T = serde_ignored::deserialize(&mut deserializer, |path| {
+        unknown.push(UnknownField {
+            path: segments(&path),
+        })
+    })
+    .and_then(|value| deserializer.end().map(|()| value))
+    .map_err(|e| anyhow!("Failed to parse {}: {}", what, e))?;
+
+    if let Some(first) = unknown.first().filter(|_| strict) {
+        return Err(anyhow!(
+            "Failed to parse {}: {}",
+            what,
+            first.describe(content)
+        ));
+    }
+    for field in &unknown {
+        warn!("Ignoring {} in {}", field.describe(content), what);
+    }
+    Ok(value)
+}
+
+fn segments(path: &serde_ignored::Path) -> Vec<Segment> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => Vec::new(),
+        Path::Seq { parent, index } => {
+            let mut segments = segments(parent);
+            segments.push(Segment::Index(*index));
+            segments
+        }
+        Path::Map { parent, key } => {
+            let mut segments = segments(parent);
+            segments.push(Segment::Key(key.clone()));
+            segments
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => segments(parent),
+    }
+}
+
+/// RFC 6901 JSON pointer for `path`.
+fn pointer(path: &[Segment]) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Key(key) => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+            Segment::Index(index) => format!("/{}", index),
+        })
+        .collect()
+}
+
+/// Line and column (both from 1) where the value at `path` starts, or its
+/// key when the path ends in one.
+fn locate(content: &str, path: &[Segment]) -> Option<(usize, usize)> {
+    let mut scanner = Scanner {
+        bytes: content.as_bytes(),
+        pos: 0,
+    };
+    let offset = scanner.find(path)?;
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    Some((line, column))
+}
+
+/// Just enough of a JSON reader to walk to a path in well-formed input.
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn find(&mut self, path: &[Segment]) -> Option<usize> {
+        self.skip_whitespace();
+        let Some((segment, rest)) = path.split_first() else {
+            return Some(self.pos);
+        };
+        match segment {
+            Segment::Key(wanted) => {
+                self.expect(b'{')?;
+                loop {
+                    self.skip_whitespace();
+                    let key_start = self.pos;
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(b':')?;
+                    if key == *wanted {
+                        return match rest {
+                            [] => Some(key_start),
+                            _ => self.find(rest),
+                        };
+                    }
+                    self.skip_value()?;
+                    self.skip_whitespace();
+                    self.expect(b',')?;
+                }
+            }
+            Segment::Index(index) => {
+                self.expect(b'[')?;
+                for _ in 0..*index {
+                    self.skip_whitespace();
+                    self.skip_value()?;
+                    self.skip_whitespace();
+                    self.expect(b',')?;
+                }
+                self.find(rest)
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.bytes.get(self.pos) == Some(&byte)).then(|| self.pos += 1)
+    }
+
+    /// A string, unescaped.
+    fn string(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.expect(b'"')?;
+        loop {
+            match self.bytes.get(self.pos)? {
+                b'\\' => self.pos += 2,
+                b'"' => break,
+                _ => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).ok()?;
+        serde_json::from_str(text).ok()
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos)? {
+            b'"' => self.string().map(|_| ()),
+            open @ (b'{' | b'[') => {
+                let close = if *open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos)? {
+                        b if *b == close => {
+                            self.pos += 1;
+                            return Some(());
+                        }
+                        b',' | b':' => self.pos += 1,
+                        _ => self.skip_value()?,
+                    }
+                }
+            }
+            _ => {
+                while self
+                    .bytes
+                    .get(self.pos)
+                    .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+                {
+                    self.pos += 1;
+                }
+                Some(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest;
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    const TYPO: &str = r#"{
+  "name": "arm",
+  "description": "Arm",
+  "version": "v1",
+  "functions": [
+    {"tag": 1, "name": "home", "desc": "Home", "return": null, "params": []},
+    {"tag": 2, "name": "setServo", "desc": "Move", "return": null,
+     "paramss": [{"name": "angle", "type": "i16"}], "params": []}
+  ]
+}"#;
+
+    #[test]
+    fn test_strict_parse_reports_unknown_field_position() {
+        let manifest: Manifest = parse(TYPO, false, "arm.json").unwrap();
+        assert!(manifest.functions[1].params.is_empty());
+
+        let err = parse::<Manifest>(TYPO, true, "arm.json").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse arm.json: unknown field `paramss` in /functions/1 at line 8 column 6"
+        );
+    }
+
+    /// Property names at each level of the schema match what the manifest
+    /// types accept, by way of a manifest using every field.
+    #[test]
+    fn test_schema_matches_manifest_types() {
+        let full = serde_json::json!({
+            "name": "arm", "description": "Arm", "version": "v1",
+            "functions": [{"tag": 1, "name": "drive", "desc": "Drive", "return": "i16",
+                "params": [{"name": "mm", "type": "i16", "minimum": 0, "maximum": 9, "unit": "mm"}],
+                "ack": "complete", "mutates": false, "debounce_ms": 300}],
+            "safe_state": "drive", "on_connect": "drive", "on_disconnect": "drive", "busy": "drive",
+            "watchdog": {"function": "drive", "interval_ms": 100},
+            "battery": {"function": "drive", "interval_ms": 100, "low": 7, "critical": 6},
+            "odometry": {"pose": "drive", "drive": {"drive": "mm"}},
+            "board": {"tool": "avrdude", "mcu": "atmega328p", "programmer": "arduino",
+                "baud": 115200, "address": "0x10000"},
+            "composites": [{"name": "twice", "desc": "Twice", "params": [],
+                "steps": [{"repeat": 2, "steps": [{"call": "drive", "arguments": {}, "delay_ms": 5}]}]}],
+            "self_test": [{"call": "drive", "arguments": {"mm": 5}, "desc": "Drive", "min": 0,
+                "max": 9, "delay_ms": 5}]
+        });
+        parse::<Manifest>(&full.to_string(), true, "full manifest").unwrap();
+
+        let schema: Value = serde_json::from_str(SCHEMA).unwrap();
+        let keys = |value: &Value| -> BTreeSet<String> {
+            value.as_object().unwrap().keys().cloned().collect()
+        };
+        let defs = &schema["$defs"];
+        let steps = &defs["steps"]["items"]["oneOf"];
+        for (properties, example) in [
+            (&schema["properties"], &full),
+            (&defs["function"]["properties"], &full["functions"][0]),
+            (
+                &defs["parameter"]["properties"],
+                &full["functions"][0]["params"][0],
+            ),
+            (
+                &schema["properties"]["watchdog"]["properties"],
+                &full["watchdog"],
+            ),
+            (
+                &schema["properties"]["battery"]["properties"],
+                &full["battery"],
+            ),
+            (
+                &schema["properties"]["odometry"]["properties"],
+                &full["odometry"],
+            ),
+            (&schema["properties"]["board"]["properties"], &full["board"]),
+            (&defs["composite"]["properties"], &full["composites"][0]),
+            (&steps[1]["properties"], &full["composites"][0]["steps"][0]),
+            (
+                &steps[0]["properties"],
+                &full["composites"][0]["steps"][0]["steps"][0],
+            ),
+            (&defs["self_test_step"]["properties"], &full["self_test"][0]),
+        ] {
+            assert_eq!(keys(properties), keys(example));
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! A small MCP client for the adapter's streamable HTTP endpoint: sessions,
+//! `initialize`, `tools/list`, `tools/call` and the notification stream.
+//! Used for remote adapters, `call --url` and the end-to-end tests.
+//!
+//! Also holds the device-side response encoding the simulator and the
+//! adapter's loopback device share.
+
+use anyhow::{anyhow, Context, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Shared with the adapter's and the simulator's tests
+#[doc(hidden)]
+pub mod conformance;
+pub mod hex;
+pub mod responses;
+
+/// Header carrying the session ID handed out by `initialize`.
+pub const SESSION_HEADER: &str = "mcp-session-id";
+
+/// Protocol version sent in `initialize`.
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// An error the server answered with, as opposed to failing to reach it.
+/// Returned inside `anyhow::Error`; downcast to tell the two apart.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    /// `data.kind` of a failed tool call, e.g. `"timeout"` or `"validation"`.
+    pub fn kind(&self) -> Option<&str> {
+        self.data.as_ref()?.get("kind")?.as_str()
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+pub struct McpClient {
+    /// Base URL, e.g. `http://pi-2.local:8080`
+    url: String,
+    token: Option<String>,
+    /// Limit for everything but `tools/call`
+    timeout: Duration,
+    /// Limit for `tools/call`, which may run scripts or sequences
+    call_timeout: Duration,
+    client: Client<HttpConnector, Full<Bytes>>,
+    /// From `initialize`, sent with every later request
+    session: Mutex<Option<String>>,
+    next_id: AtomicU64,
+}
+
+impl McpClient {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token: None,
+            timeout: Duration::from_secs(10),
+            call_timeout: Duration::from_secs(120),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            session: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Bearer token for adapters started with `--auth-token`.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeout: Duration, call_timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.call_timeout = call_timeout;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn session(&self) -> Option<String> {
+        self.session.lock().unwrap().clone()
+    }
+
+    /// Forget the session, e.g. after the server went away, so the next
+    /// `initialize` starts a new one.
+    pub fn reset_session(&self) {
+        *self.session.lock().unwrap() = None;
+    }
+
+    /// Start a session as `client_name`, returning the server's
+    /// `initialize` result.
+    pub async fn initialize(&self, client_name: &str) -> Result<Value> {
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {"name": client_name, "version": env!("CARGO_PKG_VERSION")}
+        });
+        self.request("initialize", Some(params)).await
+    }
+
+    /// The server's tools, as `tools/list` describes them.
+    pub async fn list_tools(&self) -> Result<Vec<Value>> {
+        let mut result = self.request("tools/list", None).await?;
+        match result["tools"].take() {
+            Value::Array(tools) => Ok(tools),
+            _ => Err(anyhow!("Invalid tools/list result")),
+        }
+    }
+
+    /// Call a tool, returning the `tools/call` result with its `content`.
+    pub async fn call_tool(&self, name: &str, arguments: &Value) -> Result<Value> {
+        let params = json!({"name": name, "arguments": arguments});
+        self.request("tools/call", Some(params)).await
+    }
+
+    /// One JSON-RPC request, returning its `result`. An `error` reply comes
+    /// back as [`RpcError`].
+    pub async fn request(&self, method: &str, params: Option<Value>) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut body = json!({"jsonrpc": "2.0", "id": id, "method": method});
+        if let Some(params) = params {
+            body["params"] = params;
+        }
+        let timeout = if method == "tools/call" {
+            self.call_timeout
+        } else {
+            self.timeout
+        };
+        let mut reply = tokio::time::timeout(timeout, self.post(body.to_string()))
+            .await
+            .map_err(|_| anyhow!("No answer to {} within {:?}", method, timeout))?
+            .with_context(|| format!("{} failed", method))?;
+
+        if let Some(error) = reply.get("error").filter(|e| !e.is_null()) {
+            let error: RpcError = serde_json::from_value(error.clone())
+                .with_context(|| format!("Invalid {} error", method))?;
+            return Err(error.into());
+        }
+        if reply["id"] != id {
+            return Err(anyhow!("{} answered with id {}", method, reply["id"]));
+        }
+        Ok(reply["result"].take())
+    }
+
+    /// Send a notification, which gets no reply.
+    pub async fn notify(&self, method: &str, params: Option<Value>) -> Result<()> {
+        let mut body = json!({"jsonrpc": "2.0", "method": method});
+        if let Some(params) = params {
+            body["params"] = params;
+        }
+        let request = self
+            .request_builder(Method::POST, "/mcp")?
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(body.to_string())))?;
+        let response = self.send(request).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("{} answered HTTP {}", method, response.status()));
+        }
+        Ok(())
+    }
+
+    /// POST `body` as is to `/mcp` and return the whole JSON-RPC reply,
+    /// errors included. For requests [`request`](Self::request) can't
+    /// build, such as malformed JSON.
+    pub async fn post(&self, body: String) -> Result<Value> {
+        let request = self
+            .request_builder(Method::POST, "/mcp")?
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(body)))?;
+        let response = self.send(request).await?;
+        let status = response.status();
+        let bytes = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(anyhow!("HTTP {}", status));
+        }
+        serde_json::from_slice(&bytes).context("Invalid JSON-RPC reply")
+    }
+
+    /// GET a JSON endpoint such as `/status`.
+    pub async fn get_json(&self, path: &str) -> Result<Value> {
+        let request = self
+            .request_builder(Method::GET, path)?
+            .body(Full::new(Bytes::new()))?;
+        let response = tokio::time::timeout(self.timeout, self.send(request))
+            .await
+            .map_err(|_| anyhow!("No answer from {} within {:?}", path, self.timeout))??;
+        if !response.status().is_success() {
+            return Err(anyhow!("{} answered HTTP {}", path, response.status()));
+        }
+        let bytes = response.into_body().collect().await?.to_bytes();
+        serde_json::from_slice(&bytes).with_context(|| format!("Invalid {} reply", path))
+    }
+
+    /// Open the standalone SSE stream of server notifications.
+    pub async fn notifications(&self) -> Result<Notifications> {
+        let request = self
+            .request_builder(Method::GET, "/mcp")?
+            .header("Accept", "text/event-stream")
+            .body(Full::new(Bytes::new()))?;
+        let response = tokio::time::timeout(self.timeout, self.send(request))
+            .await
+            .map_err(|_| anyhow!("No notification stream within {:?}", self.timeout))??;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Notification stream answered HTTP {}",
+                response.status()
+            ));
+        }
+        Ok(Notifications {
+            body: response.into_body(),
+            buffer: String::new(),
+        })
+    }
+
+    async fn send(&self, request: Request<Full<Bytes>>) -> Result<hyper::Response<Incoming>> {
+        let response = self.client.request(request).await?;
+        if let Some(session) = response.headers().get(SESSION_HEADER) {
+            *self.session.lock().unwrap() = Some(session.to_str()?.to_string());
+        }
+        Ok(response)
+    }
+
+    fn request_builder(&self, method: Method, path: &str) -> Result<hyper::http::request::Builder> {
+        let uri: hyper::Uri = format!("{}{}", self.url, path)
+            .parse()
+            .with_context(|| format!("Invalid adapter URL '{}'", self.url))?;
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        if let Some(session) = self.session.lock().unwrap().as_deref() {
+            request = request.header(SESSION_HEADER, session);
+        }
+        Ok(request)
+    }
+}
+
+/// Notifications from the server's SSE stream, in the order sent.
+pub struct Notifications {
+    body: Incoming,
+    buffer: String,
+}
+
+impl Notifications {
+    /// The next notification, or `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Result<Option<Value>> {
+        loop {
+            while let Some(end) = self.buffer.find("\n\n") {
+                let event: String = self.buffer.drain(..end + 2).collect();
+                if let Some(message) = parse_event(&event)? {
+                    return Ok(Some(message));
+                }
+            }
+            match self.body.frame().await {
+                None => return Ok(None),
+                Some(frame) => {
+                    if let Ok(data) = frame?.into_data() {
+                        self.buffer.push_str(&String::from_utf8_lossy(&data));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The JSON message in one SSE event, or `None` for a comment such as the
+/// server's keep-alive ping.
+fn parse_event(event: &str) -> Result<Option<Value>> {
+    let data: Vec<&str> = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect();
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let message = serde_json::from_str(&data.join("\n")).context("Invalid notification")?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event_skips_pings() {
+        assert!(parse_event(": ping\n\n").unwrap().is_none());
+        let message = parse_event(
+            "event: message\ndata: {\"method\": \"notifications/tools/list_changed\"}\n\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(message["method"], "notifications/tools/list_changed");
+        assert!(parse_event("data: {oops\n\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_json_refuses_error_status() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            // A JSON body, which must not be taken for the answer
+            let body = r#"{"error": "Not found"}"#;
+            let reply = format!(
+                "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(reply.as_bytes()).await.unwrap();
+        });
+
+        let err = McpClient::new(&url).get_json("/status").await.unwrap_err();
+        assert_eq!(err.to_string(), "/status answered HTTP 404 Not Found");
+    }
+}
@@ -0,0 +1,238 @@
+//! Robots found over mDNS, such as ESP32 boards on Wi-Fi advertising
+//! `_hackpack._tcp`, given as an `mdns://_hackpack._tcp` line with an
+//! optional `/<instance>` to pick one of several. Once found, a robot is
+//! reached over TCP like a `tcp://` line.
+
+use anyhow::{anyhow, Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::tcp;
+use crate::transport::{Connector, Transport};
+
+/// Robots currently advertising, and where the open connection went.
+#[derive(Debug, Default)]
+struct Services {
+    /// Address by instance name
+    addresses: BTreeMap<String, SocketAddr>,
+    /// Instance first connected to when the line names none; later
+    /// attempts only go to it
+    chosen: Option<String>,
+    connected: Option<SocketAddr>,
+}
+
+impl Services {
+    /// `instance`, else the one connected to before, else the first robot.
+    fn instance<'a>(&'a self, instance: Option<&'a str>) -> Option<&'a str> {
+        instance
+            .or(self.chosen.as_deref())
+            .or_else(|| self.addresses.keys().next().map(String::as_str))
+    }
+
+    fn address(&self, instance: Option<&str>) -> Option<SocketAddr> {
+        let instance = self.instance(instance)?;
+        self.addresses.get(instance).copied()
+    }
+
+    /// Whether the robot is still advertised where it was reached. A robot
+    /// that roamed to another address counts as gone once, so the stale
+    /// connection is dropped and the next attempt goes to the new address.
+    fn is_present(&mut self, instance: Option<&str>) -> bool {
+        match (self.address(instance), self.connected) {
+            (None, _) => false,
+            (Some(current), Some(connected)) if current != connected => {
+                info!("Robot moved from {} to {}", connected, current);
+                self.connected = None;
+                false
+            }
+            (Some(_), _) => true,
+        }
+    }
+}
+
+pub struct MdnsConnector {
+    /// The line as configured, with its scheme
+    name: String,
+    instance: Option<String>,
+    services: Arc<Mutex<Services>>,
+    daemon: ServiceDaemon,
+}
+
+impl MdnsConnector {
+    /// Start browsing for `spec`: a service type such as `_hackpack._tcp`,
+    /// optionally followed by `/<instance>`.
+    pub fn new(spec: &str) -> Result<Self> {
+        let (service, instance) = match spec.split_once('/') {
+            Some((service, instance)) => (service, Some(instance.to_string())),
+            None => (spec, None),
+        };
+        let service = service.trim_end_matches('.');
+        let service_type = format!(
+            "{}.local.",
+            service.strip_suffix(".local").unwrap_or(service)
+        );
+
+        let daemon = ServiceDaemon::new().context("Failed to start mDNS")?;
+        let events = daemon
+            .browse(&service_type)
+            .with_context(|| format!("Failed to browse for {}", service_type))?;
+        let services = Arc::new(Mutex::new(Services::default()));
+        let browsed = Arc::clone(&services);
+        std::thread::Builder::new()
+            .name("mdns-browse".to_string())
+            .spawn(move || {
+                // Ends when the daemon shuts down
+                while let Ok(event) = events.recv() {
+                    match event {
+                        ServiceEvent::ServiceResolved(info) => {
+                            let instance = instance_name(info.get_fullname(), &service_type);
+                            // IPv4 first, as ESP32 bridges rarely listen on IPv6
+                            let Some(ip) =
+                                info.get_addresses().iter().min_by_key(|ip| ip.is_ipv6())
+                            else {
+                                continue;
+                            };
+                            let address = SocketAddr::new(*ip, info.get_port());
+                            debug!("mDNS: {} at {}", instance, address);
+                            browsed
+                                .lock()
+                                .unwrap()
+                                .addresses
+                                .insert(instance.to_string(), address);
+                        }
+                        ServiceEvent::ServiceRemoved(_, fullname) => {
+                            let instance = instance_name(&fullname, &service_type);
+                            debug!("mDNS: {} left", instance);
+                            browsed.lock().unwrap().addresses.remove(instance);
+                        }
+                        _ => {}
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            name: format!("mdns://{}", spec),
+            instance,
+            services,
+            daemon,
+        })
+    }
+}
+
+/// `arm` for `arm._hackpack._tcp.local.`
+fn instance_name<'a>(fullname: &'a str, service_type: &str) -> &'a str {
+    fullname
+        .strip_suffix(service_type)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(fullname)
+}
+
+impl Connector for MdnsConnector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_present(&self) -> bool {
+        self.services
+            .lock()
+            .unwrap()
+            .is_present(self.instance.as_deref())
+    }
+
+    fn open(&self) -> Result<Box<dyn Transport>> {
+        let (instance, address) = {
+            let services = self.services.lock().unwrap();
+            if self.instance.is_none() && services.chosen.is_none() && services.addresses.len() > 1
+            {
+                let names: Vec<&str> = services.addresses.keys().map(String::as_str).collect();
+                warn!(
+                    "Several robots advertise {}: {}; using the first. Add /<instance> to the line to pick one.",
+                    self.name,
+                    names.join(", ")
+                );
+            }
+            let not_found = || anyhow!("No robot advertising {} found", self.name);
+            let instance = services
+                .instance(self.instance.as_deref())
+                .ok_or_else(not_found)?;
+            let address = services.addresses.get(instance).ok_or_else(not_found)?;
+            (instance.to_string(), *address)
+        };
+        let transport = tcp::connect(&address)
+            .with_context(|| format!("Connection to {} at {} failed", self.name, address))?;
+        info!("Connected to {} ('{}') at {}", self.name, instance, address);
+        let mut services = self.services.lock().unwrap();
+        services.chosen = Some(instance);
+        services.connected = Some(address);
+        Ok(transport)
+    }
+
+    /// Nothing resets the board when a socket opens.
+    fn boot_timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+impl Drop for MdnsConnector {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roaming_robot_is_reconnected() {
+        assert_eq!(
+            instance_name("arm._hackpack._tcp.local.", "_hackpack._tcp.local."),
+            "arm"
+        );
+
+        let mut services = Services::default();
+        assert!(!services.is_present(Some("arm")));
+        let first: SocketAddr = "192.168.1.20:3333".parse().unwrap();
+        services.addresses.insert("arm".to_string(), first);
+        services
+            .addresses
+            .insert("rover".to_string(), "192.168.1.30:3333".parse().unwrap());
+        assert!(services.is_present(Some("arm")));
+        assert_eq!(services.address(None), Some(first));
+
+        services.connected = Some(first);
+        assert!(services.is_present(Some("arm")));
+
+        // Roamed to another access point and got a new address
+        let second: SocketAddr = "192.168.2.7:3333".parse().unwrap();
+        services.addresses.insert("arm".to_string(), second);
+        assert!(!services.is_present(Some("arm")));
+        assert!(services.is_present(Some("arm")));
+        assert_eq!(services.address(Some("arm")), Some(second));
+
+        services.addresses.remove("arm");
+        assert!(!services.is_present(Some("arm")));
+    }
+
+    #[test]
+    fn test_unnamed_line_sticks_to_the_robot_it_reached() {
+        let mut services = Services::default();
+        services
+            .addresses
+            .insert("rover".to_string(), "192.168.1.30:3333".parse().unwrap());
+        services.chosen = Some("rover".to_string());
+        // A robot that sorts first starts advertising
+        services
+            .addresses
+            .insert("arm".to_string(), "192.168.1.20:3333".parse().unwrap());
+        assert_eq!(services.instance(None), Some("rover"));
+
+        services.addresses.remove("rover");
+        assert!(!services.is_present(None));
+        assert_eq!(services.address(None), None);
+    }
+}
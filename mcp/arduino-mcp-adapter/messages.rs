@@ -0,0 +1,198 @@
+//! Catalogs of the messages tool call errors carry, in the language chosen
+//! with `--lang`. Only the adapter's own wording is translated; details from
+//! the device, the manifest or the OS are passed on as they are, and
+//! `data.kind` stays the same in every language.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, OnceLock};
+
+use crate::robot_state::RobotState;
+
+/// Built-in catalogs, English first. Each is a flat TOML table of message
+/// keys.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("resources/messages/en.toml")),
+    ("de", include_str!("resources/messages/de.toml")),
+    ("es", include_str!("resources/messages/es.toml")),
+];
+
+/// Messages in one language. Keys a catalog lacks fall back to English.
+#[derive(Clone)]
+pub struct Messages {
+    lang: &'static str,
+    texts: Arc<HashMap<String, String>>,
+}
+
+impl Messages {
+    /// The catalog for `lang`, such as `de` or `de-AT`.
+    pub fn new(lang: &str) -> Result<Self> {
+        let base = lang.split(['-', '_']).next().unwrap_or_default();
+        let (lang, source) = CATALOGS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(base))
+            .ok_or_else(|| {
+                let names: Vec<&str> = CATALOGS.iter().map(|(name, _)| *name).collect();
+                anyhow!(
+                    "No messages for language '{}' (available: {})",
+                    lang,
+                    names.join(", ")
+                )
+            })?;
+        let texts = toml::from_str(source).expect("built-in message catalogs are valid");
+        Ok(Self {
+            lang,
+            texts: Arc::new(texts),
+        })
+    }
+
+    pub fn lang(&self) -> &str {
+        self.lang
+    }
+
+    /// The message for `key`, with each `{name}` placeholder replaced by
+    /// its value in `args`. Values are inserted as they are, even if they
+    /// hold something that looks like a placeholder.
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut rest = self.text(key);
+        let mut text = String::with_capacity(rest.len());
+        while let Some(start) = rest.find('{') {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = rest.find('}').and_then(|end| {
+                let (_, value) = args.iter().find(|(name, _)| **name == rest[1..end])?;
+                Some((end, value))
+            });
+            match value {
+                Some((end, value)) => {
+                    text.push_str(&value.to_string());
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    text.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        text.push_str(rest);
+        text
+    }
+
+    pub fn get(&self, key: &str) -> String {
+        self.format(key, &[])
+    }
+
+    /// Why the robot in `state` can or can't take calls.
+    pub fn state(&self, state: &RobotState) -> String {
+        match state {
+            RobotState::Disconnected => self.get("state_disconnected"),
+            RobotState::Connecting => self.get("state_connecting"),
+            RobotState::Connected => self.get("state_connected"),
+            RobotState::Initializing => self.get("state_initializing"),
+            RobotState::Ready(_) => self.get("state_ready"),
+            RobotState::Error(error) => self.format("state_error", &[("error", error)]),
+            RobotState::VersionMismatch {
+                device_id,
+                firmware,
+                manifest,
+            } => self.format(
+                "state_version_mismatch",
+                &[
+                    ("device_id", device_id),
+                    ("firmware", firmware),
+                    ("manifest", manifest),
+                ],
+            ),
+            RobotState::Fault { device_id, fault } => {
+                self.format("state_fault", &[("device_id", device_id), ("fault", fault)])
+            }
+        }
+    }
+
+    fn text<'a>(&'a self, key: &'a str) -> &'a str {
+        match self.texts.get(key) {
+            Some(text) => text,
+            None if self.lang != "en" => english().text(key),
+            None => key,
+        }
+    }
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        english().clone()
+    }
+}
+
+/// The English catalog, for logs and the default.
+pub fn english() -> &'static Messages {
+    static ENGLISH: OnceLock<Messages> = OnceLock::new();
+    ENGLISH.get_or_init(|| Messages::new("en").unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// The `{name}` placeholders in `text`.
+    fn placeholders(text: &str) -> BTreeSet<&str> {
+        text.split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+            .collect()
+    }
+
+    #[test]
+    fn test_catalogs_translate_every_message() {
+        let english = english();
+        for (lang, _) in CATALOGS {
+            let messages = Messages::new(lang).unwrap();
+            let mut keys: Vec<&String> = messages.texts.keys().collect();
+            keys.sort();
+            let mut expected: Vec<&String> = english.texts.keys().collect();
+            expected.sort();
+            assert_eq!(keys, expected, "{}", lang);
+            for (key, text) in messages.texts.iter() {
+                assert_eq!(
+                    placeholders(text),
+                    placeholders(&english.texts[key]),
+                    "{}.{}",
+                    lang,
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_formats_messages_in_the_chosen_language() {
+        let german = Messages::new("de-AT").unwrap();
+        assert_eq!(german.lang(), "de");
+        assert_eq!(
+            german.format("function_not_found", &[("name", &"fly")]),
+            "Funktion nicht gefunden: fly"
+        );
+        assert_eq!(
+            german.state(&RobotState::Error("timeout".to_string())),
+            "Roboterfehler: timeout"
+        );
+        assert_eq!(
+            english().state(&RobotState::Disconnected),
+            "Robot not connected - check USB connection"
+        );
+        // A value isn't searched for further placeholders
+        assert_eq!(
+            english().state(&RobotState::VersionMismatch {
+                device_id: "{firmware}".to_string(),
+                firmware: "2".to_string(),
+                manifest: "3".to_string(),
+            }),
+            "Firmware on {firmware} is version 2 but its manifest is version 3 - \
+             flash the firmware built from the current manifest"
+        );
+        let err = Messages::new("xx").err().unwrap().to_string();
+        assert!(err.contains("available: en, de, es"), "{}", err);
+    }
+}
@@ -0,0 +1,53 @@
+//! Compiled-in policies around tool calls. A [`Middleware`] sees every call
+//! made through `McpServer::call_tool`, whatever surface it came from, and
+//! may rewrite it, refuse it or rewrite its result. Servers take it through
+//! `McpServer::with_middleware`.
+
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::server::McpError;
+
+/// A tool call on its way to the robot.
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    pub tool: String,
+    pub arguments: Value,
+}
+
+pub trait Middleware: Send + Sync {
+    /// Inspect or rewrite `call` before it runs. An error refuses the call
+    /// and is returned to the client as is.
+    fn before_call(&self, _call: &mut ToolCall) -> Result<(), McpError> {
+        Ok(())
+    }
+
+    /// Inspect or rewrite the outcome of `call`, in reverse order of
+    /// registration. A refused call only passes back through the middleware
+    /// that saw it, including the one that refused it.
+    fn after_call(&self, _call: &ToolCall, _result: &mut Result<Value, McpError>) {}
+}
+
+/// Run `call` through `middleware` around `execute`.
+pub async fn run<F, Fut>(
+    middleware: &[Arc<dyn Middleware>],
+    mut call: ToolCall,
+    execute: F,
+) -> (ToolCall, Result<Value, McpError>)
+where
+    F: FnOnce(ToolCall) -> Fut,
+    Fut: std::future::Future<Output = Result<Value, McpError>>,
+{
+    let refused = middleware
+        .iter()
+        .enumerate()
+        .find_map(|(index, m)| m.before_call(&mut call).err().map(|e| (index, e)));
+    let (mut result, ran) = match refused {
+        Some((index, error)) => (Err(error), index + 1),
+        None => (execute(call.clone()).await, middleware.len()),
+    };
+    for m in middleware[..ran].iter().rev() {
+        m.after_call(&call, &mut result);
+    }
+    (call, result)
+}
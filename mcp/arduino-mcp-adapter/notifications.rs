@@ -0,0 +1,34 @@
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// Fan-out of server-initiated JSON-RPC notifications to every open SSE stream.
+pub struct Notifier {
+    sender: broadcast::Sender<Value>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        Self { sender }
+    }
+
+    /// Queue a notification for all currently connected sessions.
+    pub fn notify(&self, method: &str, params: Option<Value>) {
+        let mut message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": method
+        });
+        if let Some(params) = params {
+            message["params"] = params;
+        }
+
+        // An error only means nobody is listening right now
+        let receivers = self.sender.send(message).unwrap_or(0);
+        debug!("Notification {} sent to {} session(s)", method, receivers);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Value> {
+        self.sender.subscribe()
+    }
+}
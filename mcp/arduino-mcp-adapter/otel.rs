@@ -0,0 +1,72 @@
+//! Span export over OTLP/HTTP for `--otlp-endpoint`, so the time a tool call
+//! spends in HTTP handling, validation, queueing and on the serial line
+//! shows up in Jaeger or any other OpenTelemetry collector. Exporting needs
+//! the `otel` build feature.
+
+use tracing_subscriber::{Layer, Registry};
+
+/// Spans are reported as coming from this service.
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+const SERVICE_NAME: &str = "arduino-mcp-adapter";
+
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+pub use exporter::layer;
+
+#[cfg(feature = "otel")]
+mod exporter {
+    use anyhow::{Context, Result};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing::level_filters::LevelFilter;
+    use tracing_subscriber::Layer;
+
+    use super::{BoxedLayer, SERVICE_NAME};
+
+    /// Flushes the spans still buffered when dropped.
+    pub struct Guard(SdkTracerProvider);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            if let Err(e) = self.0.shutdown() {
+                eprintln!("Failed to flush spans: {}", e);
+            }
+        }
+    }
+
+    /// A layer exporting spans to `endpoint`, e.g.
+    /// `http://localhost:4318/v1/traces`.
+    pub fn layer(endpoint: &str) -> Result<(BoxedLayer, Guard)> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to set up OTLP span export")?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build();
+        let layer = tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(SERVICE_NAME))
+            // Debug events would turn every frame into a span event
+            .with_filter(LevelFilter::INFO);
+        Ok((Box::new(layer), Guard(provider)))
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod exporter {
+    use anyhow::{anyhow, Result};
+
+    use super::BoxedLayer;
+
+    pub enum Guard {}
+
+    pub fn layer(_endpoint: &str) -> Result<(BoxedLayer, Guard)> {
+        Err(anyhow!(
+            "This adapter was built without span export; rebuild it with `--features otel`"
+        ))
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::frame_log::Direction;
+
+/// LINKTYPE_USER0; point Wireshark's "DLT User" table at a SLIP dissector.
+const LINKTYPE_USER0: u16 = 147;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Writes every byte read from or written to the serial port as a pcapng
+/// capture, one packet per read/write call, timestamped in microseconds.
+pub struct PcapWriter {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl PcapWriter {
+    /// Create (or truncate) `path` and write the section and interface headers.
+    pub fn create(path: &Path, interface_name: &str) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Failed to create pcap file {}: {}", path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+
+        // Byte-order magic, version 1.0, unknown section length
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, BLOCK_SECTION_HEADER, &shb)?;
+
+        // Link type, reserved, no snap length limit
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut idb, OPT_IF_NAME, interface_name.as_bytes());
+        push_option(&mut idb, OPT_END, &[]);
+        write_block(&mut writer, BLOCK_INTERFACE_DESCRIPTION, &idb)?;
+
+        writer.flush()?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Append one packet. Failures are logged rather than returned so a full
+    /// disk never interrupts robot traffic.
+    pub fn record(&self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+
+        // epb_flags bits 0-1: 1 = inbound, 2 = outbound
+        let flags: u32 = match direction {
+            Direction::Rx => 1,
+            Direction::Tx => 2,
+        };
+
+        let mut epb = Vec::with_capacity(bytes.len() + 40);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        epb.extend_from_slice(bytes);
+        pad_to_word(&mut epb);
+        push_option(&mut epb, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut epb, OPT_END, &[]);
+
+        let mut writer = self.writer.lock().unwrap();
+        let result = write_block(&mut *writer, BLOCK_ENHANCED_PACKET, &epb);
+        if let Err(e) = result.and_then(|_| Ok(writer.flush()?)) {
+            warn!("Failed to write pcap packet: {}", e);
+        }
+    }
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> Result<()> {
+    let total_length = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_length.to_le_bytes())?;
+    Ok(())
+}
+
+fn push_option(buffer: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buffer.extend_from_slice(&code.to_le_bytes());
+    buffer.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(value);
+    pad_to_word(buffer);
+}
+
+fn pad_to_word(buffer: &mut Vec<u8>) {
+    while !buffer.len().is_multiple_of(4) {
+        buffer.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_are_well_formed() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let pcap = PcapWriter::create(file.path(), "/dev/ttyUSB0").unwrap();
+        pcap.record(Direction::Tx, &[0xC0, 0x00, 0x00, 0xC0]);
+        pcap.record(Direction::Rx, &[0xC0, 0x41, 0x42]);
+        drop(pcap);
+
+        let bytes = std::fs::read(file.path()).unwrap();
+        let u32_at = |pos: usize| u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+
+        // Walk the blocks: leading and trailing lengths must agree
+        let mut pos = 0;
+        let mut block_types = Vec::new();
+        while pos < bytes.len() {
+            let length = u32_at(pos + 4) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32_at(pos + length - 4) as usize, length);
+            block_types.push(u32_at(pos));
+            pos += length;
+        }
+
+        assert_eq!(pos, bytes.len());
+        assert_eq!(
+            block_types,
+            vec![
+                BLOCK_SECTION_HEADER,
+                BLOCK_INTERFACE_DESCRIPTION,
+                BLOCK_ENHANCED_PACKET,
+                BLOCK_ENHANCED_PACKET
+            ]
+        );
+    }
+}
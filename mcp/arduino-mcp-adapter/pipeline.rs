@@ -0,0 +1,331 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{oneshot, Semaphore, SemaphorePermit};
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::chunks::Reassembler;
+use crate::errors::AdapterError;
+use crate::events::DeviceEvents;
+use crate::frame_log::{Direction, FrameLog};
+use crate::pcap::PcapWriter;
+use crate::protocol::{crc8, device_event};
+use crate::slip::SlipDecoder;
+use crate::transport::Transport;
+
+/// How long a pipelined command waits for a free slot, and for its response
+/// until the function has a deadline of its own.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Never given to a command, so that a frame starting with it can only be
+/// an event.
+const EVENT_SEQ: u8 = 0xFF;
+
+/// Sequence-numbered command pipeline.
+///
+/// Up to `depth` commands may be outstanding on the wire at once. Each command
+/// frame is prefixed with a sequence byte that the firmware echoes as the first
+/// byte of its response, so a background reader can hand every response to
+/// the caller waiting for it regardless of completion order.
+pub struct Pipeline {
+    depth: usize,
+    slots: Semaphore,
+    state: Mutex<PipelineState>,
+    reader_stop: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+#[derive(Default)]
+struct PipelineState {
+    next_seq: u8,
+    /// Reply channel per outstanding sequence number, taken once answered
+    pending: HashMap<u8, Option<oneshot::Sender<Result<Vec<u8>>>>>,
+}
+
+/// A reserved sequence number; dropping it frees the pipeline slot.
+pub struct Ticket<'a> {
+    pipeline: &'a Pipeline,
+    pub seq: u8,
+    receiver: oneshot::Receiver<Result<Vec<u8>>>,
+    _slot: SemaphorePermit<'a>,
+}
+
+impl Pipeline {
+    pub fn new(depth: usize) -> Self {
+        let depth = depth.clamp(1, 255);
+        Self {
+            depth,
+            slots: Semaphore::new(depth),
+            state: Mutex::new(PipelineState::default()),
+            reader_stop: Mutex::new(None),
+        }
+    }
+
+    /// Whether commands use sequence-numbered framing.
+    pub fn is_enabled(&self) -> bool {
+        self.depth > 1
+    }
+
+    /// Wait for a free slot and reserve the next unused sequence number.
+    pub async fn begin(&self) -> Result<Ticket<'_>> {
+        let slot = time::timeout(RESPONSE_TIMEOUT, self.slots.acquire())
+            .await
+            .map_err(|_| {
+                AdapterError::Timeout("Timed out waiting for a free pipeline slot".to_string())
+            })?
+            .expect("pipeline slots are never closed");
+        let mut state = self.state.lock().unwrap();
+
+        let mut seq = state.next_seq;
+        while seq == EVENT_SEQ || state.pending.contains_key(&seq) {
+            seq = seq.wrapping_add(1);
+        }
+        state.next_seq = seq.wrapping_add(1);
+
+        let (sender, receiver) = oneshot::channel();
+        state.pending.insert(seq, Some(sender));
+
+        Ok(Ticket {
+            pipeline: self,
+            seq,
+            receiver,
+            _slot: slot,
+        })
+    }
+
+    /// Start matching responses read from `port` to outstanding tickets,
+    /// reassembling them first if they are `chunked`, and handing event
+    /// frames to `events` if the device sends them. Replaces any reader
+    /// left over from a previous connection.
+    pub fn start_reader(
+        self: &Arc<Self>,
+        mut port: Box<dyn Transport>,
+        frame_log: Arc<FrameLog>,
+        capture: Option<Arc<PcapWriter>>,
+        chunked: bool,
+        events: Option<Arc<DeviceEvents>>,
+    ) {
+        let stop = Arc::new(AtomicBool::new(false));
+        if let Some(previous) = self.reader_stop.lock().unwrap().replace(Arc::clone(&stop)) {
+            previous.store(true, Ordering::Relaxed);
+        }
+
+        let pipeline = Arc::clone(self);
+        std::thread::spawn(move || {
+            let mut buffer = [0; 256];
+            let mut decoder = SlipDecoder::counted(frame_log.slip()).with_resync();
+            let mut partial = chunked.then(HashMap::new);
+
+            while !stop.load(Ordering::Relaxed) {
+                match port.read(&mut buffer) {
+                    Ok(bytes_read) => {
+                        if let Some(capture) = &capture {
+                            capture.record(Direction::Rx, &buffer[..bytes_read]);
+                        }
+                        for &byte in &buffer[..bytes_read] {
+                            match decoder.process_byte(byte) {
+                                Ok(Some(frame)) => {
+                                    frame_log.record(Direction::Rx, &frame, true);
+                                    if let Some(events) = &events {
+                                        if let Some(event) = device_event(&frame) {
+                                            events.publish(event);
+                                            continue;
+                                        }
+                                    }
+                                    match &mut partial {
+                                        Some(partial) => pipeline.dispatch_chunk(partial, &frame),
+                                        None => pipeline.dispatch(&frame),
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(e) => warn!("Discarding malformed pipelined frame: {}", e),
+                            }
+                        }
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                    Err(e) => {
+                        if !stop.load(Ordering::Relaxed) {
+                            pipeline.fail_all(&format!("Serial read error: {}", e));
+                        }
+                        break;
+                    }
+                }
+            }
+            debug!("Pipeline reader stopped");
+        });
+    }
+
+    /// Stop the reader and fail every outstanding command.
+    pub fn stop_reader(&self) {
+        if let Some(stop) = self.reader_stop.lock().unwrap().take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.fail_all("Serial port closed");
+    }
+
+    /// Route a `[seq] [data...] [crc]` frame to the ticket that sent `seq`.
+    /// A frame that fails its CRC check fails that ticket rather than
+    /// handing it damaged data.
+    fn dispatch(&self, frame: &[u8]) {
+        if frame.len() < 2 {
+            warn!("Pipelined response too short ({} bytes)", frame.len());
+            return;
+        }
+
+        let (body, crc) = frame.split_at(frame.len() - 1);
+        let seq = body[0];
+        if crc8(body) != crc[0] {
+            warn!("Pipelined response seq={} failed its CRC check", seq);
+            self.deliver(
+                seq,
+                Err(AdapterError::CrcMismatch("Response failed its CRC check".to_string()).into()),
+            );
+            return;
+        }
+        let data = body[1..].to_vec();
+        debug!("Pipelined response seq={} ({} data bytes)", seq, data.len());
+        self.deliver(seq, Ok(data));
+    }
+
+    /// Add a `[seq] [index] [total] [payload...] [crc]` chunk to the response
+    /// for `seq`, routing the response once its last chunk is in.
+    fn dispatch_chunk(&self, partial: &mut HashMap<u8, Reassembler>, frame: &[u8]) {
+        if frame.len() < 2 {
+            warn!("Pipelined chunk too short ({} bytes)", frame.len());
+            return;
+        }
+
+        let (body, crc) = frame.split_at(frame.len() - 1);
+        let crc_ok = crc8(body) == crc[0];
+        let seq = body[0];
+        if let Some(result) = partial.entry(seq).or_default().push(&body[1..], crc_ok) {
+            partial.remove(&seq);
+            debug!("Pipelined response seq={} reassembled", seq);
+            self.deliver(seq, result);
+        }
+    }
+
+    fn deliver(&self, seq: u8, result: Result<Vec<u8>>) {
+        match self.state.lock().unwrap().pending.get_mut(&seq) {
+            // A send error only means the caller already gave up
+            Some(slot) => match slot.take() {
+                Some(sender) => {
+                    let _ = sender.send(result);
+                }
+                None => warn!("Dropping duplicate response for sequence number {}", seq),
+            },
+            None => warn!("Dropping response with unknown sequence number {}", seq),
+        }
+    }
+
+    fn fail_all(&self, message: &str) {
+        for slot in self.state.lock().unwrap().pending.values_mut() {
+            if let Some(sender) = slot.take() {
+                let _ = sender.send(Err(AdapterError::SerialIo(message.to_string()).into()));
+            }
+        }
+    }
+}
+
+impl Ticket<'_> {
+    /// Wait up to `timeout` for the response to this sequence number.
+    pub async fn wait(mut self, timeout: Duration) -> Result<Vec<u8>> {
+        match time::timeout(timeout, &mut self.receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(AdapterError::SerialIo("Serial port closed".to_string()).into()),
+            Err(_) => Err(AdapterError::Timeout(format!(
+                "Timed out waiting for response to seq {}",
+                self.seq
+            ))
+            .into()),
+        }
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.pipeline
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .remove(&self.seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::Kind;
+
+    fn seal(body: &[u8]) -> Vec<u8> {
+        let mut frame = body.to_vec();
+        frame.push(crc8(body));
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_responses_matched_out_of_order() {
+        let pipeline = Pipeline::new(4);
+        let first = pipeline.begin().await.unwrap();
+        let second = pipeline.begin().await.unwrap();
+        assert_ne!(first.seq, second.seq);
+
+        pipeline.dispatch(&seal(&[second.seq, 0x2A, 0x00]));
+        pipeline.dispatch(&seal(&[first.seq, 0x07, 0x00]));
+
+        assert_eq!(
+            second.wait(RESPONSE_TIMEOUT).await.unwrap(),
+            vec![0x2A, 0x00]
+        );
+        assert_eq!(
+            first.wait(RESPONSE_TIMEOUT).await.unwrap(),
+            vec![0x07, 0x00]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_damaged_response_fails_its_ticket() {
+        let pipeline = Pipeline::new(4);
+        let ticket = pipeline.begin().await.unwrap();
+
+        let mut frame = seal(&[ticket.seq, 0x2A, 0x00]);
+        *frame.last_mut().unwrap() ^= 0x01;
+        pipeline.dispatch(&frame);
+
+        let err = ticket.wait(RESPONSE_TIMEOUT).await.unwrap_err();
+        assert_eq!(Kind::of(&err), Kind::CrcMismatch);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_reassembled_per_sequence_number() {
+        let pipeline = Pipeline::new(4);
+        let first = pipeline.begin().await.unwrap();
+        let second = pipeline.begin().await.unwrap();
+
+        // Chunks of the two responses interleaved
+        let mut partial = HashMap::new();
+        pipeline.dispatch_chunk(&mut partial, &seal(&[first.seq, 0, 2, 1, 2]));
+        pipeline.dispatch_chunk(&mut partial, &seal(&[second.seq, 0, 1, 9]));
+        pipeline.dispatch_chunk(&mut partial, &seal(&[first.seq, 1, 2, 3]));
+
+        assert_eq!(first.wait(RESPONSE_TIMEOUT).await.unwrap(), vec![1, 2, 3]);
+        assert_eq!(second.wait(RESPONSE_TIMEOUT).await.unwrap(), vec![9]);
+        assert!(partial.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sequence_numbers_freed_on_drop() {
+        let pipeline = Pipeline::new(2);
+        let first = pipeline.begin().await.unwrap();
+        let second = pipeline.begin().await.unwrap();
+        drop(first);
+
+        assert_eq!(pipeline.state.lock().unwrap().pending.len(), 1);
+        let third = pipeline.begin().await.unwrap();
+        assert_ne!(third.seq, second.seq);
+        assert_eq!(pipeline.state.lock().unwrap().pending.len(), 2);
+    }
+}
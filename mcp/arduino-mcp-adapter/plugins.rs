@@ -0,0 +1,515 @@
+//! WASM plugins: `.wasm` modules from `--plugin-dir` that add MCP tools and
+//! call robot functions to carry them out. Running plugins needs the
+//! `plugins` build feature.
+//!
+//! A plugin exports `memory`, `alloc(len) -> ptr`, `tools() -> packed` and
+//! `call(name_ptr, name_len, args_ptr, args_len) -> packed`, and may import
+//! `robot.call` (same signature as its own `call`) and
+//! `robot.log(ptr, len)`. Strings are UTF-8 in the plugin's memory, and a
+//! packed string is `ptr << 32 | len`. `tools` returns a JSON array of
+//! `{name, description, inputSchema}`; both `call`s take JSON arguments and
+//! return `{"result": text}` or `{"error": message}`. `robot.call` runs any
+//! tool but a plugin's, as a client call would.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Weak;
+use tracing::info;
+
+use crate::manifest::Tool;
+use crate::rest;
+use crate::server::McpServer;
+
+const UNSUPPORTED: &str =
+    "This adapter was built without plugin support; rebuild it with `--features plugins`";
+
+/// The plugins loaded from a directory.
+pub struct Plugins {
+    plugins: Vec<host::Plugin>,
+}
+
+impl Plugins {
+    /// Load every `.wasm` file in `dir`, in name order. Robot calls from the
+    /// plugins are tool calls on `server`.
+    pub async fn load(dir: &Path, server: Weak<McpServer>) -> Result<Self> {
+        if cfg!(not(feature = "plugins")) {
+            return Err(anyhow!(UNSUPPORTED));
+        }
+        let mut paths = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read plugin directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut plugins = Vec::new();
+        let mut names = HashSet::new();
+        for path in paths {
+            let plugin = host::Plugin::load(&path, Weak::clone(&server))
+                .await
+                .with_context(|| format!("Failed to load plugin {}", path.display()))?;
+            for tool in plugin.tools() {
+                if !names.insert(tool.name.clone()) {
+                    return Err(anyhow!(
+                        "Plugin {} defines tool '{}' again",
+                        path.display(),
+                        tool.name
+                    ));
+                }
+            }
+            info!(
+                "Plugin {}: {}",
+                path.display(),
+                plugin
+                    .tools()
+                    .iter()
+                    .map(|tool| tool.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            plugins.push(plugin);
+        }
+        Ok(Self { plugins })
+    }
+
+    pub fn tools(&self) -> impl Iterator<Item = &Tool> {
+        self.plugins.iter().flat_map(|plugin| plugin.tools())
+    }
+
+    /// Run `tool` if a plugin defines it.
+    pub async fn call(&self, tool: &str, arguments: &Value) -> Option<Result<String>> {
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|plugin| plugin.tools().iter().any(|t| t.name == tool))?;
+        Some(plugin.call(tool, arguments).await)
+    }
+}
+
+/// What either side's `call` returns.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+enum Outcome {
+    Result(String),
+    Error(String),
+}
+
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+fn parse_tools(json: &str) -> Result<Vec<Tool>> {
+    let tools: Vec<Tool> =
+        serde_json::from_str(json).context("tools() must return a JSON array of tools")?;
+    if let Some(tool) = tools.iter().find(|tool| tool.name.is_empty()) {
+        return Err(anyhow!("Tool without a name: {}", tool.description));
+    }
+    Ok(tools)
+}
+
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+fn parse_outcome(json: &str) -> Result<String> {
+    match serde_json::from_str(json) {
+        Ok(Outcome::Result(text)) => Ok(text),
+        Ok(Outcome::Error(message)) => Err(anyhow!(message)),
+        Err(_) => Err(anyhow!(
+            "Plugin returned neither {{\"result\"}} nor {{\"error\"}}: {}",
+            json
+        )),
+    }
+}
+
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+fn outcome(result: Result<String>) -> String {
+    match result {
+        Ok(text) => serde_json::json!({ "result": text }),
+        Err(e) => serde_json::json!({ "error": format!("{:#}", e) }),
+    }
+    .to_string()
+}
+
+/// A tool called by a plugin, dispatched like a client call so it gets the
+/// same readiness checks, validation, middleware and statistics.
+#[cfg_attr(not(feature = "plugins"), allow(dead_code))]
+async fn robot_call(server: &Weak<McpServer>, function: &str, arguments: &str) -> Result<String> {
+    let arguments: Value = serde_json::from_str(arguments).context("Invalid arguments JSON")?;
+    let server = server
+        .upgrade()
+        .ok_or_else(|| anyhow!("The adapter is shutting down"))?;
+    let result = server
+        .call_tool(function, &arguments)
+        .await
+        .map_err(|e| anyhow!(e.message))?;
+    Ok(rest::result_text(&result))
+}
+
+#[cfg(feature = "plugins")]
+mod host {
+    use anyhow::{anyhow, Result};
+    use serde_json::Value;
+    use std::path::Path;
+    use std::sync::{OnceLock, Weak};
+    use std::time::{Duration, Instant};
+    use tokio::sync::Mutex;
+    use tracing::info;
+    use wasmtime::{
+        AsContext, Caller, Config, Engine, Linker, Memory, Module, Store, TypedFunc, UpdateDeadline,
+    };
+
+    use super::{outcome, parse_outcome, parse_tools, robot_call};
+    use crate::manifest::Tool;
+    use crate::server::McpServer;
+
+    /// How long one tool call may run, robot calls included.
+    const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+    /// How often running plugins yield and check their deadline.
+    const TICK: Duration = Duration::from_millis(10);
+
+    struct Host {
+        name: String,
+        server: Weak<McpServer>,
+        /// When the running call is cut off
+        deadline: Instant,
+    }
+
+    pub struct Plugin {
+        tools: Vec<Tool>,
+        timeout: Duration,
+        /// A plugin runs one call at a time
+        instance: Mutex<Instance>,
+    }
+
+    struct Instance {
+        store: Store<Host>,
+        memory: Memory,
+        alloc: TypedFunc<u32, u32>,
+        call: TypedFunc<(u32, u32, u32, u32), i64>,
+    }
+
+    /// The engine shared by all plugins, with a thread ticking its epoch.
+    fn engine() -> &'static Engine {
+        static ENGINE: OnceLock<Engine> = OnceLock::new();
+        ENGINE.get_or_init(|| {
+            let mut config = Config::new();
+            config.async_support(true).epoch_interruption(true);
+            let engine = Engine::new(&config).expect("valid wasmtime configuration");
+            let ticking = engine.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(TICK);
+                ticking.increment_epoch();
+            });
+            engine
+        })
+    }
+
+    impl Plugin {
+        pub async fn load(path: &Path, server: Weak<McpServer>) -> Result<Self> {
+            let engine = engine();
+            let module = Module::from_file(engine, path)?;
+            let mut linker = Linker::new(engine);
+            linker.func_wrap_async(
+                "robot",
+                "call",
+                |mut caller: Caller<'_, Host>, (name_ptr, name_len, args_ptr, args_len)| {
+                    Box::new(async move {
+                        let memory = memory(&mut caller)?;
+                        let function = read(&memory, &caller, name_ptr, name_len)?;
+                        let arguments = read(&memory, &caller, args_ptr, args_len)?;
+                        let server = Weak::clone(&caller.data().server);
+                        let result = robot_call(&server, &function, &arguments).await;
+                        let alloc = caller
+                            .get_export("alloc")
+                            .and_then(|export| export.into_func())
+                            .ok_or_else(|| anyhow!("Plugin exports no alloc"))?
+                            .typed::<u32, u32>(&caller)?;
+                        write(&mut caller, &memory, &alloc, &outcome(result)).await
+                    })
+                },
+            )?;
+            linker.func_wrap(
+                "robot",
+                "log",
+                |mut caller: Caller<'_, Host>, ptr: u32, len: u32| -> Result<()> {
+                    let memory = memory(&mut caller)?;
+                    let message = read(&memory, &caller, ptr, len)?;
+                    info!("Plugin {}: {}", caller.data().name, message);
+                    Ok(())
+                },
+            )?;
+
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut store = Store::new(
+                engine,
+                Host {
+                    name,
+                    server,
+                    deadline: Instant::now() + CALL_TIMEOUT,
+                },
+            );
+            store.epoch_deadline_callback(|store| {
+                if Instant::now() >= store.data().deadline {
+                    Ok(UpdateDeadline::Interrupt)
+                } else {
+                    Ok(UpdateDeadline::Yield(1))
+                }
+            });
+            store.set_epoch_deadline(1);
+
+            let instance = linker.instantiate_async(&mut store, &module).await?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| anyhow!("Plugin exports no memory"))?;
+            let alloc = instance.get_typed_func(&mut store, "alloc")?;
+            let call = instance.get_typed_func(&mut store, "call")?;
+            let tools = instance.get_typed_func::<(), i64>(&mut store, "tools")?;
+            let packed = tools.call_async(&mut store, ()).await?;
+            let (ptr, len) = unpack(packed);
+            let tools = parse_tools(&read(&memory, &store, ptr, len)?)?;
+
+            Ok(Self {
+                tools,
+                timeout: CALL_TIMEOUT,
+                instance: Mutex::new(Instance {
+                    store,
+                    memory,
+                    alloc,
+                    call,
+                }),
+            })
+        }
+
+        pub fn tools(&self) -> &[Tool] {
+            &self.tools
+        }
+
+        /// Run `tool`, cut off after the timeout whether the plugin is
+        /// computing or waiting for a robot call.
+        pub async fn call(&self, tool: &str, arguments: &Value) -> Result<String> {
+            let mut instance = self.instance.lock().await;
+            tokio::time::timeout(self.timeout, self.run(&mut instance, tool, arguments))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("Plugin call timed out after {:?}", self.timeout)))
+        }
+
+        async fn run(
+            &self,
+            instance: &mut Instance,
+            tool: &str,
+            arguments: &Value,
+        ) -> Result<String> {
+            let Instance {
+                store,
+                memory,
+                alloc,
+                call,
+            } = instance;
+            store.data_mut().deadline = Instant::now() + self.timeout;
+            store.set_epoch_deadline(1);
+
+            let (name_ptr, name_len) = unpack(write(&mut *store, memory, alloc, tool).await?);
+            let (args_ptr, args_len) =
+                unpack(write(&mut *store, memory, alloc, &arguments.to_string()).await?);
+            let packed = call
+                .call_async(&mut *store, (name_ptr, name_len, args_ptr, args_len))
+                .await
+                .map_err(|e| match e.downcast_ref::<wasmtime::Trap>() {
+                    Some(wasmtime::Trap::Interrupt) => {
+                        anyhow!("Plugin call timed out after {:?}", self.timeout)
+                    }
+                    _ => anyhow!("Plugin trapped: {:#}", e),
+                })?;
+            let (ptr, len) = unpack(packed);
+            parse_outcome(&read(memory, &*store, ptr, len)?)
+        }
+    }
+
+    fn memory(caller: &mut Caller<'_, Host>) -> Result<Memory> {
+        caller
+            .get_export("memory")
+            .and_then(|export| export.into_memory())
+            .ok_or_else(|| anyhow!("Plugin exports no memory"))
+    }
+
+    fn read(memory: &Memory, store: impl AsContext, ptr: u32, len: u32) -> Result<String> {
+        let start = ptr as usize;
+        let bytes = memory
+            .data(&store)
+            .get(start..start + len as usize)
+            .ok_or_else(|| anyhow!("Plugin string out of bounds"))?;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+
+    /// Copy `text` into memory the plugin allocates for it.
+    async fn write(
+        mut store: impl wasmtime::AsContextMut<Data = Host>,
+        memory: &Memory,
+        alloc: &TypedFunc<u32, u32>,
+        text: &str,
+    ) -> Result<i64> {
+        let len = text.len() as u32;
+        let ptr = alloc.call_async(&mut store, len).await?;
+        memory.write(&mut store, ptr as usize, text.as_bytes())?;
+        Ok(pack(ptr, len))
+    }
+
+    fn pack(ptr: u32, len: u32) -> i64 {
+        ((u64::from(ptr) << 32) | u64::from(len)) as i64
+    }
+
+    fn unpack(packed: i64) -> (u32, u32) {
+        ((packed as u64 >> 32) as u32, packed as u32)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::connection::ConnectionManager;
+        use crate::fixtures::{self, function};
+        use crate::loopback::LoopbackConnector;
+        use crate::manifest::ManifestManager;
+        use serde_json::json;
+        use std::sync::Arc;
+
+        fn functions() -> serde_json::Value {
+            json!([function(1, "getDistance", Some("i16"), &[])])
+        }
+
+        /// Its one tool hands back what `getDistance` answers.
+        const RANGER: &str = r#"(module
+            (import "robot" "call" (func $robot (param i32 i32 i32 i32) (result i64)))
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 1024))
+            (data (i32.const 0) "[{\"name\":\"range\",\"description\":\"Range\",\"inputSchema\":{\"type\":\"object\"}}]")
+            (data (i32.const 512) "getDistance{}")
+            (func (export "alloc") (param $len i32) (result i32)
+                (global.get $next)
+                (global.set $next (i32.add (global.get $next) (local.get $len))))
+            (func (export "tools") (result i64) (i64.const 72))
+            (func (export "call") (param i32 i32 i32 i32) (result i64)
+                (call $robot (i32.const 512) (i32.const 11) (i32.const 523) (i32.const 2))))"#;
+
+        struct Loaded {
+            plugin: Plugin,
+            connector: LoopbackConnector,
+            // Robot calls go to the server while it lives
+            _server: Arc<McpServer>,
+            _dir: tempfile::TempDir,
+        }
+
+        async fn load(wat: &str) -> Loaded {
+            let dir = fixtures::manifest_dir(&fixtures::manifest_json(functions(), json!({})));
+            let path = dir.path().join("plugin.wat");
+            std::fs::write(&path, wat).unwrap();
+            let device = fixtures::device(fixtures::manifest(functions()))
+                .respond("getDistance", 42i16.to_le_bytes().to_vec());
+            let connector = LoopbackConnector::new(device);
+            let manifest_manager = Arc::new(ManifestManager::new(dir.path().to_path_buf()));
+            let connection_manager = Arc::new(
+                ConnectionManager::with_connector(Box::new(connector.clone()))
+                    .with_manifest_manager(Arc::clone(&manifest_manager)),
+            );
+            connection_manager
+                .check_and_update_connection()
+                .await
+                .unwrap();
+            let server = Arc::new(McpServer::new(
+                connection_manager,
+                manifest_manager,
+                Arc::new(crate::config::Config::default()),
+            ));
+            let plugin = Plugin::load(&path, Arc::downgrade(&server)).await.unwrap();
+            Loaded {
+                plugin,
+                connector,
+                _server: server,
+                _dir: dir,
+            }
+        }
+
+        #[tokio::test]
+        async fn test_plugin_tool_calls_robot() {
+            let loaded = load(RANGER).await;
+            assert_eq!(loaded.plugin.tools()[0].name, "range");
+            assert_eq!(loaded.plugin.call("range", &json!({})).await.unwrap(), "42");
+        }
+
+        #[tokio::test]
+        async fn test_runaway_plugin_is_interrupted() {
+            let spinner = RANGER.replace(
+                "(call $robot (i32.const 512) (i32.const 11) (i32.const 523) (i32.const 2))",
+                "(loop $forever (br $forever)) (unreachable)",
+            );
+            let mut loaded = load(&spinner).await;
+            loaded.plugin.timeout = Duration::from_millis(100);
+            let err = loaded.plugin.call("range", &json!({})).await.unwrap_err();
+            assert!(err.to_string().contains("timed out"), "{}", err);
+        }
+
+        #[tokio::test]
+        async fn test_robot_calls_count_against_the_deadline() {
+            let mut loaded = load(RANGER).await;
+            loaded.connector.silence("getDistance");
+            loaded.plugin.timeout = Duration::from_millis(100);
+            let started = Instant::now();
+            let err = loaded.plugin.call("range", &json!({})).await.unwrap_err();
+            assert!(err.to_string().contains("timed out"), "{}", err);
+            assert!(started.elapsed() < Duration::from_secs(2));
+        }
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+mod host {
+    use anyhow::{anyhow, Result};
+    use serde_json::Value;
+    use std::path::Path;
+    use std::sync::Weak;
+
+    use super::UNSUPPORTED;
+    use crate::manifest::Tool;
+    use crate::server::McpServer;
+
+    pub enum Plugin {}
+
+    impl Plugin {
+        pub async fn load(_path: &Path, _server: Weak<McpServer>) -> Result<Self> {
+            Err(anyhow!(UNSUPPORTED))
+        }
+
+        pub fn tools(&self) -> &[Tool] {
+            match *self {}
+        }
+
+        pub async fn call(&self, _tool: &str, _arguments: &Value) -> Result<String> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abi_json() {
+        let tools = parse_tools(
+            r#"[{"name": "patrol", "description": "Patrol", "inputSchema": {"type": "object"}}]"#,
+        )
+        .unwrap();
+        assert_eq!(tools[0].name, "patrol");
+        assert!(parse_tools(r#"[{"name": "", "description": "x", "inputSchema": {}}]"#).is_err());
+        assert!(parse_tools(r#"{"name": "patrol"}"#).is_err());
+
+        assert_eq!(parse_outcome(r#"{"result": "ok"}"#).unwrap(), "ok");
+        let err = parse_outcome(r#"{"error": "blocked"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "blocked");
+        assert!(parse_outcome("42").is_err());
+
+        assert_eq!(parse_outcome(&outcome(Ok("7".to_string()))).unwrap(), "7");
+        assert!(parse_outcome(&outcome(Err(anyhow!("no")))).is_err());
+    }
+}
@@ -0,0 +1,769 @@
+//! The thread that owns an open port. Commands reach it over a channel and
+//! their responses come back on a `oneshot`, so callers never hold a lock
+//! across serial I/O and a slow device can't stall unrelated tasks.
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::{debug, info_span, warn, Span};
+
+use crate::bootloader::{self, BootloaderDetected, NOISE_LIMIT};
+use crate::chunks::Reassembler;
+use crate::errors::AdapterError;
+use crate::events::DeviceEvents;
+use crate::frame_log::{Direction, FrameLog};
+use crate::pcap::PcapWriter;
+use crate::protocol::{crc8, device_event, DeviceEvent};
+use crate::reliable::{self, LinkStats};
+use crate::slip::{slip_encode_into, SlipDecoder, SLIP_CLEAR_SEQUENCE, SLIP_END, SLIP_ESC};
+use crate::transport::Transport;
+
+/// Room for a full command frame with every byte escaped, and ESC CLEAR.
+const MAX_WIRE_FRAME: usize = 2 * 256 + 4;
+/// How often an idle port is checked for event frames.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How often the port is checked for input while a reliable-mode command
+/// waits, so its timers aren't stretched by the port's read timeout.
+const RELIABLE_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Which lane of the port's queue a command waits in. Urgent commands go
+/// out before every normal one still waiting, though never in the middle
+/// of the command being sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    /// Safety commands such as the watchdog keep-alive and emergency stop
+    Urgent,
+}
+
+enum Request {
+    Transact {
+        seq: Option<u8>,
+        priority: Priority,
+        tag: u8,
+        args: Bytes,
+        /// When an unsequenced command stops waiting for its response
+        deadline: Instant,
+        reply: oneshot::Sender<Result<Vec<u8>>>,
+        /// The caller's span, for the serial I/O spans
+        span: Span,
+    },
+    /// A plain command that gives up on its response at `timeout`
+    Poll {
+        tag: u8,
+        timeout: Duration,
+        reply: oneshot::Sender<Result<Vec<u8>>>,
+    },
+    Probe {
+        reply: oneshot::Sender<bool>,
+    },
+    Discard {
+        reply: oneshot::Sender<()>,
+    },
+    /// ESC CLEAR on its own
+    Clear,
+}
+
+/// Requests taken off the channel, waiting their turn by priority.
+#[derive(Default)]
+struct Lanes {
+    urgent: VecDeque<Request>,
+    normal: VecDeque<Request>,
+}
+
+impl Lanes {
+    fn push(&mut self, request: Request) {
+        match request {
+            Request::Transact {
+                priority: Priority::Urgent,
+                ..
+            } => self.urgent.push_back(request),
+            _ => self.normal.push_back(request),
+        }
+    }
+
+    /// Take everything already sent, so an urgent request sent behind
+    /// normal ones is seen before they are served.
+    fn fill(&mut self, requests: &mpsc::Receiver<Request>) {
+        while let Ok(request) = requests.try_recv() {
+            self.push(request);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Request> {
+        self.urgent.pop_front().or_else(|| self.normal.pop_front())
+    }
+}
+
+/// Handle to the port's thread. The thread exits, closing the port, once
+/// every handle is gone or [`PortActor::close`] is called.
+pub struct PortActor {
+    requests: mpsc::Sender<Request>,
+    flags: Arc<Flags>,
+}
+
+#[derive(Default)]
+struct Flags {
+    closed: AtomicBool,
+    /// Set when a read from the port fails
+    failed: AtomicBool,
+    /// Responses arrive in chunks, as negotiated in the handshake
+    chunked: AtomicBool,
+    /// Event frames may arrive between and before responses
+    events: AtomicBool,
+    /// Unsequenced commands go through the reliable link layer
+    reliable: AtomicBool,
+}
+
+struct Worker {
+    port: Box<dyn Transport>,
+    flags: Arc<Flags>,
+    frame_log: Arc<FrameLog>,
+    capture: Option<Arc<PcapWriter>>,
+    events: Arc<DeviceEvents>,
+    link: Arc<LinkStats>,
+    /// Sequence number of the next reliable-mode command
+    next_seq: u8,
+    /// Bytes read after the last response, such as an event sent right
+    /// behind it
+    leftover: Vec<u8>,
+    /// Decoder for plain responses and events, kept so its buffer is too
+    decoder: SlipDecoder,
+    /// The command frame being sent, and its SLIP encoding. Reused for every
+    /// command, as telemetry polling sends dozens a second.
+    command: BytesMut,
+    wire: BytesMut,
+}
+
+impl PortActor {
+    pub fn spawn(
+        port: Box<dyn Transport>,
+        frame_log: Arc<FrameLog>,
+        capture: Option<Arc<PcapWriter>>,
+        events: Arc<DeviceEvents>,
+        link: Arc<LinkStats>,
+    ) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let flags = Arc::new(Flags::default());
+        let mut worker = Worker {
+            port,
+            flags: Arc::clone(&flags),
+            capture,
+            events,
+            link,
+            next_seq: 0,
+            leftover: Vec::new(),
+            decoder: SlipDecoder::counted(frame_log.slip()).with_resync(),
+            command: BytesMut::with_capacity(MAX_WIRE_FRAME),
+            wire: BytesMut::with_capacity(MAX_WIRE_FRAME),
+            frame_log,
+        };
+        std::thread::spawn(move || worker.run(receiver));
+        Self { requests, flags }
+    }
+
+    /// Send one command frame and return the response data, giving up on it
+    /// after `timeout`. With a sequence number only the write happens here
+    /// and the pipeline reader delivers the response.
+    pub async fn transact(
+        &self,
+        seq: Option<u8>,
+        priority: Priority,
+        tag: u8,
+        args: Bytes,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Transact {
+            seq,
+            priority,
+            tag,
+            args,
+            deadline: Instant::now() + timeout,
+            reply,
+            span: Span::current(),
+        })?;
+        response.await.map_err(|_| port_closed())?
+    }
+
+    /// Send a plain command without arguments and wait up to `timeout` for
+    /// its response, dropping unread input first. For asking a device that
+    /// may still be booting, which loses commands, whether it is up.
+    pub async fn poll(&self, tag: u8, timeout: Duration) -> Result<Vec<u8>> {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Poll {
+            tag,
+            timeout,
+            reply,
+        })?;
+        response.await.map_err(|_| port_closed())?
+    }
+
+    /// Whether the port still accepts writes.
+    pub async fn probe(&self) -> bool {
+        let (reply, response) = oneshot::channel();
+        self.send(Request::Probe { reply }).is_ok() && response.await.unwrap_or(false)
+    }
+
+    /// Drop whatever the device sent that no command has read yet, such as
+    /// replies meant for commands sent before it restarted.
+    pub async fn discard_input(&self) {
+        let (reply, done) = oneshot::channel();
+        if self.send(Request::Discard { reply }).is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    /// Send ESC CLEAR, so the device drops whatever partial frame it holds
+    /// and the next frame starts clean at both ends.
+    pub fn clear(&self) {
+        let _ = self.send(Request::Clear);
+    }
+
+    /// Read responses as chunks from now on.
+    pub fn set_chunked(&self, chunked: bool) {
+        self.flags.chunked.store(chunked, Ordering::Relaxed);
+    }
+
+    /// Watch for event frames from now on, also while no command is
+    /// waiting.
+    pub fn set_events(&self, events: bool) {
+        self.flags.events.store(events, Ordering::Relaxed);
+    }
+
+    /// Send unsequenced commands through the reliable link layer from now on.
+    pub fn set_reliable(&self, reliable: bool) {
+        self.flags.reliable.store(reliable, Ordering::Relaxed);
+    }
+
+    /// Whether a read from the port has failed.
+    pub fn has_failed(&self) -> bool {
+        self.flags.failed.load(Ordering::Relaxed)
+    }
+
+    /// Fail queued and waiting commands; the port closes once the current
+    /// one finishes.
+    pub fn close(&self) {
+        self.flags.closed.store(true, Ordering::Relaxed);
+    }
+
+    fn send(&self, request: Request) -> Result<()> {
+        if self.flags.closed.load(Ordering::Relaxed) {
+            return Err(port_closed());
+        }
+        self.requests.send(request).map_err(|_| port_closed())
+    }
+}
+
+impl Drop for PortActor {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Worker {
+    fn run(&mut self, requests: mpsc::Receiver<Request>) {
+        // Whatever reached the device before the port was opened
+        self.write_clear();
+        let mut lanes = Lanes::default();
+        loop {
+            lanes.fill(&requests);
+            if let Some(request) = lanes.pop() {
+                self.serve(request);
+                if self.flags.closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                continue;
+            }
+            let request = match self.flags.events.load(Ordering::Relaxed) {
+                true => match requests.recv_timeout(EVENT_POLL_INTERVAL) {
+                    Ok(request) => request,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if self.flags.closed.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        self.read_events();
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                },
+                false => match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                },
+            };
+            lanes.push(request);
+        }
+        debug!("Port actor stopped");
+    }
+
+    fn serve(&mut self, request: Request) {
+        let closed = self.flags.closed.load(Ordering::Relaxed);
+        match request {
+            Request::Transact { reply, .. } | Request::Poll { reply, .. } if closed => {
+                let _ = reply.send(Err(port_closed()));
+            }
+            Request::Transact {
+                seq,
+                tag,
+                args,
+                reply,
+                span,
+                ..
+            } if seq.is_none() && self.flags.reliable.load(Ordering::Relaxed) => {
+                let result = info_span!(parent: &span, "serial_reliable")
+                    .in_scope(|| self.transact_reliable(tag, &args, &|| reply.is_closed()));
+                let _ = reply.send(result);
+            }
+            Request::Transact {
+                seq,
+                tag,
+                args,
+                deadline,
+                reply,
+                span,
+                ..
+            } => {
+                let result = info_span!(parent: &span, "serial_write")
+                    .in_scope(|| self.write_command(seq, tag, &args, true))
+                    .and_then(|()| match seq {
+                        Some(_) => Ok(Vec::new()),
+                        None => info_span!(parent: &span, "serial_read")
+                            .in_scope(|| self.read_response(Some(deadline))),
+                    });
+                // The caller may have given up waiting
+                let _ = reply.send(result);
+            }
+            Request::Poll {
+                tag,
+                timeout,
+                reply,
+            } => {
+                self.discard_input();
+                let deadline = Instant::now() + timeout;
+                let result = self
+                    .write_command(None, tag, &[], true)
+                    .and_then(|()| self.read_response(Some(deadline)));
+                if result.is_err() {
+                    self.write_clear();
+                }
+                let _ = reply.send(result);
+            }
+            Request::Probe { reply } => {
+                let _ = reply.send(!closed && self.port.write(&[]).is_ok());
+            }
+            Request::Discard { reply } => {
+                self.discard_input();
+                let _ = reply.send(());
+            }
+            Request::Clear if closed => {}
+            Request::Clear => self.write_clear(),
+        }
+    }
+
+    /// Send one command frame, after ESC CLEAR if `clear`, so noise that
+    /// reached the device before it, or a frame cut short, can't run into it.
+    fn write_command(
+        &mut self,
+        seq: Option<u8>,
+        tag: u8,
+        args_data: &[u8],
+        clear: bool,
+    ) -> Result<()> {
+        debug!(
+            "Sending SLIP command with tag: {} and {} arg bytes",
+            tag,
+            args_data.len()
+        );
+
+        self.command.clear();
+        if let Some(seq) = seq {
+            self.command.put_u8(seq);
+        }
+        self.command.put_u8(tag);
+        self.command.put_slice(args_data);
+
+        let crc = crc8(&self.command);
+        self.command.put_u8(crc);
+        self.frame_log
+            .record(Direction::Tx, &self.command, seq.is_some());
+
+        self.wire.clear();
+        if clear {
+            self.wire.put_slice(&SLIP_CLEAR_SEQUENCE);
+        }
+        slip_encode_into(&self.command, &mut self.wire);
+        self.port
+            .write_all(&self.wire)
+            .and_then(|_| self.port.flush())
+            .map_err(|e| AdapterError::SerialIo(format!("Serial write error: {}", e)))?;
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Tx, &self.wire);
+        }
+        debug!("SLIP command sent and flushed ({} bytes)", self.wire.len());
+        Ok(())
+    }
+
+    fn write_clear(&mut self) {
+        let written = self
+            .port
+            .write_all(&SLIP_CLEAR_SEQUENCE)
+            .and_then(|_| self.port.flush());
+        match written {
+            Ok(()) => {
+                if let Some(capture) = &self.capture {
+                    capture.record(Direction::Tx, &SLIP_CLEAR_SEQUENCE);
+                }
+                debug!("SLIP clear sequence sent");
+            }
+            Err(e) => debug!("Failed to send the SLIP clear sequence: {}", e),
+        }
+    }
+
+    /// Read the response to the command just sent, giving up at `deadline`
+    /// once a read finds nothing.
+    fn read_response(&mut self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        debug!("Beginning to read SLIP response from serial port");
+        let mut buffer = [0; 256];
+        self.decoder.reset();
+        // Bytes outside any frame, checked for a bootloader's replies
+        let mut noise = Vec::new();
+        let mut chunks = self
+            .flags
+            .chunked
+            .load(Ordering::Relaxed)
+            .then(Reassembler::default);
+
+        // Read until we get a complete SLIP frame, or the last chunk
+        loop {
+            match self.read_some(&mut buffer) {
+                Ok(bytes_read) if bytes_read > 0 => {
+                    debug!("Read {} bytes from serial", bytes_read);
+
+                    // Process each byte through SLIP decoder
+                    for (index, &byte) in buffer[..bytes_read].iter().enumerate() {
+                        if self.decoder.is_idle() && byte != SLIP_END && byte != SLIP_ESC {
+                            if noise.len() == NOISE_LIMIT {
+                                noise.remove(0);
+                            }
+                            noise.push(byte);
+                            if bootloader::is_handshake(&noise) {
+                                return Err(BootloaderDetected.into());
+                            }
+                        }
+                        if let Some(frame) = self.decoder.process_byte(byte)? {
+                            debug!("Received SLIP frame: {} bytes", frame.len());
+                            self.frame_log.record(Direction::Rx, &frame, false);
+
+                            if let Some(result) = self.take_frame(frame, &mut chunks) {
+                                self.keep_leftover(&buffer[index + 1..bytes_read]);
+                                return result;
+                            }
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    debug!("Serial read timeout");
+                    if self.flags.closed.load(Ordering::Relaxed) {
+                        return Err(port_closed());
+                    }
+                }
+                Err(e) => {
+                    self.flags.failed.store(true, Ordering::Relaxed);
+                    return Err(AdapterError::SerialIo(format!("Serial read error: {}", e)).into());
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(AdapterError::Timeout("No response".to_string()).into());
+            }
+        }
+    }
+
+    /// Send a command through the reliable link layer and return its
+    /// response data. It is sent again while unacknowledged, when the
+    /// firmware reports it damaged, and when its response arrives damaged
+    /// or not at all, until the caller stops waiting (`gave_up`) or
+    /// [`reliable::MAX_SENDS`] requests for a late response went unanswered.
+    fn transact_reliable(
+        &mut self,
+        tag: u8,
+        args: &[u8],
+        gave_up: &dyn Fn() -> bool,
+    ) -> Result<Vec<u8>> {
+        let seq = self.next_seq;
+        self.next_seq = reliable::next_seq(seq);
+        self.write_command(Some(seq), tag, args, true)?;
+
+        let mut decoder = SlipDecoder::counted(self.frame_log.slip()).with_resync();
+        let chunked = self.flags.chunked.load(Ordering::Relaxed);
+        let mut chunks = Reassembler::default();
+        // Sends that went unacknowledged or were damaged on the way
+        let mut attempts = 1;
+        // Sends after the acknowledgement that got no response in time
+        let mut late = 0;
+        let mut acked = false;
+        // A frame arrived damaged since the last send
+        let mut damaged = false;
+        let mut wait = reliable::ACK_TIMEOUT;
+        let mut deadline = Instant::now() + wait;
+
+        loop {
+            let frame = self.read_frame_until(&mut decoder, deadline, gave_up)?;
+            // Whether the send was lost or damaged, as opposed to a response
+            // that is merely late
+            let failed = match frame {
+                None if self.flags.closed.load(Ordering::Relaxed) => return Err(port_closed()),
+                None if gave_up() => {
+                    return Err(AdapterError::Timeout(
+                        "Gave up waiting for the response".to_string(),
+                    )
+                    .into())
+                }
+                None if acked && !damaged => {
+                    // Firmware that acknowledges and then never answers
+                    // would otherwise be asked forever
+                    late += 1;
+                    if late == reliable::MAX_SENDS {
+                        self.link.record_failure();
+                        return Err(AdapterError::Timeout(format!(
+                            "No response to an acknowledged command after asking {} times",
+                            late
+                        ))
+                        .into());
+                    }
+                    wait = (wait * 2).min(reliable::MAX_RESEND_INTERVAL);
+                    false
+                }
+                None => true,
+                Some(frame) => {
+                    if self.publish_event(&frame) {
+                        continue;
+                    }
+                    if let Some(acked_seq) = reliable::acknowledged(&frame) {
+                        if acked_seq == seq && !acked {
+                            acked = true;
+                            wait = reliable::RESEND_INTERVAL;
+                            deadline = Instant::now() + wait;
+                        } else {
+                            self.link.record_duplicate();
+                        }
+                        continue;
+                    }
+                    if reliable::is_nak(&frame) {
+                        self.link.record_nak();
+                        true
+                    } else if !crc_ok(&frame) {
+                        // Perhaps the acknowledgement, with the response
+                        // right behind it; a lost chunk fails the response
+                        // once the last one is in. Otherwise ask again soon.
+                        self.link.record_corrupted();
+                        damaged = true;
+                        deadline = deadline.min(Instant::now() + reliable::ACK_TIMEOUT);
+                        continue;
+                    } else if frame[0] != seq {
+                        self.link.record_duplicate();
+                        continue;
+                    } else if !chunked {
+                        // Strip the sequence byte and CRC in place
+                        let mut data = frame;
+                        data.pop();
+                        data.remove(0);
+                        return Ok(data);
+                    } else {
+                        match chunks.push(&frame[1..frame.len() - 1], true) {
+                            None => continue,
+                            Some(Ok(data)) => return Ok(data),
+                            Some(Err(e)) => {
+                                debug!("Response to seq {} incomplete: {}", seq, e);
+                                true
+                            }
+                        }
+                    }
+                }
+            };
+
+            if failed {
+                if attempts == reliable::MAX_SENDS {
+                    self.link.record_failure();
+                    return Err(AdapterError::SerialIo(format!(
+                        "Command not delivered after {} sends over the reliable link",
+                        attempts
+                    ))
+                    .into());
+                }
+                attempts += 1;
+            }
+            debug!("Sending seq {} again", seq);
+            self.link.record_retransmit();
+            chunks = Reassembler::default();
+            damaged = false;
+            self.write_command(Some(seq), tag, args, false)?;
+            deadline = Instant::now() + wait;
+        }
+    }
+
+    /// Read until `decoder` completes a frame, or `deadline` passes or the
+    /// caller gives up with none. Bytes after the frame are kept for the
+    /// next read.
+    fn read_frame_until(
+        &mut self,
+        decoder: &mut SlipDecoder,
+        deadline: Instant,
+        gave_up: &dyn Fn() -> bool,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut buffer = [0; 256];
+        loop {
+            if self.leftover.is_empty() && self.port.pending_bytes() == 0 {
+                let closed = self.flags.closed.load(Ordering::Relaxed);
+                if Instant::now() >= deadline || closed || gave_up() {
+                    return Ok(None);
+                }
+                std::thread::sleep(RELIABLE_POLL_INTERVAL);
+                continue;
+            }
+            let bytes_read = match self.read_some(&mut buffer) {
+                Ok(bytes_read) => bytes_read,
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    self.flags.failed.store(true, Ordering::Relaxed);
+                    return Err(AdapterError::SerialIo(format!("Serial read error: {}", e)).into());
+                }
+            };
+            for (index, &byte) in buffer[..bytes_read].iter().enumerate() {
+                match decoder.process_byte(byte) {
+                    Ok(Some(frame)) => {
+                        self.frame_log.record(Direction::Rx, &frame, true);
+                        self.keep_leftover(&buffer[index + 1..bytes_read]);
+                        return Ok(Some(frame));
+                    }
+                    Ok(None) => {}
+                    // A damaged frame; its command is sent again on timeout
+                    Err(e) => debug!("Discarding malformed frame: {}", e),
+                }
+            }
+        }
+    }
+
+    /// The response `frame` completes, if any. Event frames are handed on
+    /// and reading goes on.
+    fn take_frame(
+        &self,
+        mut frame: Vec<u8>,
+        chunks: &mut Option<Reassembler>,
+    ) -> Option<Result<Vec<u8>>> {
+        if self.publish_event(&frame) {
+            return None;
+        }
+        // Strip CRC (last byte); a void function sends only the CRC
+        let Some(crc) = frame.pop() else {
+            return Some(Err(
+                AdapterError::Protocol("Frame too short".to_string()).into()
+            ));
+        };
+        if let Some(chunks) = chunks {
+            return chunks.push(&frame, crc8(&frame) == crc);
+        }
+        if crc8(&frame) != crc {
+            return Some(Err(AdapterError::CrcMismatch(
+                "Response failed its CRC check".to_string(),
+            )
+            .into()));
+        }
+        Some(Ok(frame))
+    }
+
+    /// Read the event frames sent while no command was waiting. Anything
+    /// else is unexpected here and dropped.
+    fn read_events(&mut self) {
+        let mut buffer = [0; 256];
+        self.decoder.reset();
+        while !self.decoder.is_idle() || !self.leftover.is_empty() || self.port.pending_bytes() > 0
+        {
+            let bytes_read = match self.read_some(&mut buffer) {
+                Ok(bytes_read) => bytes_read,
+                Err(_) => return,
+            };
+            for &byte in &buffer[..bytes_read] {
+                let Ok(Some(frame)) = self.decoder.process_byte(byte) else {
+                    continue;
+                };
+                self.frame_log.record(Direction::Rx, &frame, false);
+                if !self.publish_event(&frame) {
+                    warn!("Dropping unexpected {}-byte frame", frame.len());
+                }
+            }
+        }
+    }
+
+    fn discard_input(&mut self) {
+        let mut buffer = [0; 256];
+        let mut discarded = std::mem::take(&mut self.leftover).len();
+        while self.port.pending_bytes() > 0 {
+            match self.read_some(&mut buffer) {
+                Ok(bytes_read) => discarded += bytes_read,
+                Err(_) => break,
+            }
+        }
+        if discarded > 0 {
+            debug!("Discarded {} unread bytes", discarded);
+        }
+    }
+
+    /// Whether `frame` is an event frame, publishing it if so.
+    fn publish_event(&self, frame: &[u8]) -> bool {
+        let Some(event) = device_event(frame) else {
+            return false;
+        };
+        if self.flags.events.load(Ordering::Relaxed) {
+            self.events.publish(event);
+            return true;
+        }
+        // Sent as the firmware starts, so it may arrive before the handshake
+        // reply; the handshake is about to resync anyway
+        if event == DeviceEvent::Reset {
+            debug!("Ignoring reset event sent before the handshake");
+            return true;
+        }
+        false
+    }
+
+    /// Keep `bytes` read past a frame for the next read, in the buffer the
+    /// last ones were kept in.
+    fn keep_leftover(&mut self, bytes: &[u8]) {
+        self.leftover.clear();
+        self.leftover.extend_from_slice(bytes);
+    }
+
+    /// Bytes left over from the last response first, then the port's.
+    fn read_some(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if !self.leftover.is_empty() {
+            let count = buffer.len().min(self.leftover.len());
+            buffer[..count].copy_from_slice(&self.leftover[..count]);
+            self.leftover.drain(..count);
+            return Ok(count);
+        }
+        let bytes_read = self.port.read(buffer)?;
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Rx, &buffer[..bytes_read]);
+        }
+        Ok(bytes_read)
+    }
+}
+
+/// Whether the last byte of `frame` is the CRC of the rest.
+fn crc_ok(frame: &[u8]) -> bool {
+    match frame.split_last() {
+        Some((&crc, body)) => !body.is_empty() && crc8(body) == crc,
+        None => false,
+    }
+}
+
+fn port_closed() -> anyhow::Error {
+    AdapterError::SerialIo("Serial port closed".to_string()).into()
+}
@@ -0,0 +1,156 @@
+//! `list-ports` and `probe` subcommands for finding the robot among the
+//! serial devices on a machine.
+
+use anyhow::Result;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::path::{Path, PathBuf};
+
+use crate::call;
+use crate::config::Config;
+use crate::connection::ConnectionManager;
+use crate::manifest::{ManifestManager, MODULE_SEPARATOR};
+use crate::usb_devices::UsbDeviceMap;
+
+/// Stable udev names for USB serial devices.
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+/// Print every serial port with its USB details and `/dev/serial/by-id`
+/// alias, and the device ID `usb_devices` maps it to.
+pub fn list(usb_devices: Option<&UsbDeviceMap>) -> Result<()> {
+    let mut ports = serialport::available_ports()?;
+    if ports.is_empty() {
+        println!("No serial ports found");
+        return Ok(());
+    }
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+
+    for port in &ports {
+        let device_id = match (&port.port_type, usb_devices) {
+            (SerialPortType::UsbPort(usb), Some(map)) => map.lookup(usb),
+            _ => None,
+        };
+        match device_id {
+            Some(id) => println!("{} -> {}", describe(port), id),
+            None => println!("{}", describe(port)),
+        }
+        for alias in aliases(Path::new(BY_ID_DIR), Path::new(&port.port_name)) {
+            println!("    {}", alias.display());
+        }
+    }
+    Ok(())
+}
+
+/// One line per port: name, then VID:PID, manufacturer, product and serial
+/// number for USB devices.
+fn describe(port: &SerialPortInfo) -> String {
+    let details = match &port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut details = format!("{:04x}:{:04x}", usb.vid, usb.pid);
+            for text in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                details.push(' ');
+                details.push_str(text);
+            }
+            if let Some(serial) = &usb.serial_number {
+                details.push_str(&format!(" (serial {})", serial));
+            }
+            details
+        }
+        SerialPortType::PciPort => "PCI".to_string(),
+        SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+        SerialPortType::Unknown => "built-in".to_string(),
+    };
+    format!("{:<16} {}", port.port_name, details)
+}
+
+/// Symlinks in `dir` that resolve to `port`.
+fn aliases(dir: &Path, port: &Path) -> Vec<PathBuf> {
+    let Ok(port) = port.canonicalize() else {
+        return Vec::new();
+    };
+    let Ok(entries) = dir.read_dir() else {
+        return Vec::new();
+    };
+
+    let mut aliases: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.canonicalize().is_ok_and(|target| target == port))
+        .collect();
+    aliases.sort();
+    aliases
+}
+
+/// Identify the device on `line` and report which manifest would serve it.
+pub async fn probe(line: &str, config: &Config) -> Result<()> {
+    let connection_manager =
+        ConnectionManager::new(line, config.baud)?.with_pipeline_depth(config.pipeline_depth()?);
+    let device_id = call::connect(&connection_manager).await?;
+    println!("Port:      {}", line);
+    println!("Device ID: {}", device_id);
+
+    let Some(manifest_dir) = &config.manifest_dir else {
+        println!("Manifest:  no manifest directory configured");
+        return Ok(());
+    };
+    let manifest_manager = ManifestManager::new(manifest_dir.clone())
+        .with_device_manifests(config.device_manifests())
+        .with_strict_manifests(config.strict_manifests);
+    let path = manifest_manager.manifest_path(&device_id);
+
+    match manifest_manager.get_manifest(&device_id) {
+        Ok(manifest) if !path.exists() => println!(
+            "Manifest:  modules {} ({} functions)",
+            device_id
+                .split(MODULE_SEPARATOR)
+                .collect::<Vec<_>>()
+                .join(", "),
+            manifest.functions.len()
+        ),
+        Ok(manifest) => println!(
+            "Manifest:  {} ({} {}, {} functions)",
+            path.display(),
+            manifest.name,
+            manifest.version,
+            manifest.functions.len()
+        ),
+        Err(e) if path.exists() => println!("Manifest:  {} is invalid: {}", path.display(), e),
+        Err(_) => println!("Manifest:  none (expected {})", path.display()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    #[test]
+    fn test_describe_usb_port() {
+        let port = SerialPortInfo {
+            port_name: "/dev/ttyACM0".to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x2341,
+                pid: 0x43,
+                serial_number: Some("7543".to_string()),
+                manufacturer: Some("Arduino".to_string()),
+                product: None,
+            }),
+        };
+        assert_eq!(
+            describe(&port),
+            "/dev/ttyACM0     2341:0043 Arduino (serial 7543)"
+        );
+    }
+
+    #[test]
+    fn test_aliases_resolve_symlinks_to_port() {
+        let dir = tempfile::tempdir().unwrap();
+        let port = dir.path().join("ttyUSB0");
+        std::fs::write(&port, "").unwrap();
+        let by_id = dir.path().join("by-id");
+        std::fs::create_dir(&by_id).unwrap();
+        std::os::unix::fs::symlink(&port, by_id.join("usb-Arduino-if00")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("other"), by_id.join("usb-Other")).unwrap();
+
+        assert_eq!(aliases(&by_id, &port), vec![by_id.join("usb-Arduino-if00")]);
+    }
+}
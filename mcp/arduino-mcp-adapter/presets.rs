@@ -0,0 +1,133 @@
+//! Named argument values from the config's `[presets]` tables. A client
+//! writes `"@slow"` wherever a value goes and the adapter puts in the value
+//! configured for `slow`, so instructors can tune safe values without
+//! changing prompts or manifests.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use tracing::debug;
+
+use crate::middleware::{Middleware, ToolCall};
+use crate::server::McpError;
+
+/// `[presets.<group>]` tables of named values. Groups only organise the
+/// file; names are shared by all of them.
+pub type PresetGroups = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// Check that every preset name is usable and appears in one group only.
+pub fn check(groups: &PresetGroups) -> Result<()> {
+    let mut seen: HashMap<&str, &str> = HashMap::new();
+    for (group, presets) in groups {
+        for name in presets.keys() {
+            if !is_valid_name(name) {
+                return Err(anyhow!(
+                    "Preset '{}.{}' may only contain letters, digits, '_' and '-'",
+                    group,
+                    name
+                ));
+            }
+            if let Some(other) = seen.insert(name, group) {
+                return Err(anyhow!(
+                    "Preset '{}' is defined in both [presets.{}] and [presets.{}]",
+                    name,
+                    other,
+                    group
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Replaces `"@name"` strings in tool arguments with the preset's value,
+/// before the call is validated.
+pub struct Presets {
+    values: HashMap<String, Value>,
+}
+
+impl Presets {
+    pub fn new(groups: &PresetGroups) -> Self {
+        let values = groups
+            .values()
+            .flat_map(|presets| presets.iter())
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Self { values }
+    }
+
+    /// Resolve presets anywhere in `value`, including the steps of a
+    /// `callSequence`. Strings naming no preset are left alone.
+    fn resolve(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => {
+                let preset = text
+                    .strip_prefix('@')
+                    .and_then(|name| self.values.get(name));
+                if let Some(preset) = preset {
+                    debug!("Resolved preset {} to {}", text, preset);
+                    *value = preset.clone();
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.resolve(item)),
+            Value::Object(fields) => fields.values_mut().for_each(|field| self.resolve(field)),
+            _ => {}
+        }
+    }
+}
+
+impl Middleware for Presets {
+    fn before_call(&self, call: &mut ToolCall) -> Result<(), McpError> {
+        self.resolve(&mut call.arguments);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolves_presets_and_rejects_clashing_names() {
+        let groups: PresetGroups = toml::from_str(
+            "[speeds]\nslow = 80\nfast = 200\n[angles]\nhome = 90\n[words]\ngreeting = \"hi\"",
+        )
+        .unwrap();
+        check(&groups).unwrap();
+        let presets = Presets::new(&groups);
+
+        let mut arguments = json!({
+            "speed": "@slow",
+            "text": "@greeting",
+            "steps": [{"name": "setServo", "arguments": {"angle": "@home"}}],
+            "note": "@nobody",
+            "plain": "slow",
+        });
+        presets.resolve(&mut arguments);
+        assert_eq!(
+            arguments,
+            json!({
+                "speed": 80,
+                "text": "hi",
+                "steps": [{"name": "setServo", "arguments": {"angle": 90}}],
+                "note": "@nobody",
+                "plain": "slow",
+            })
+        );
+
+        let clashing: PresetGroups =
+            toml::from_str("[speeds]\nslow = 80\n[servo]\nslow = 10").unwrap();
+        let err = check(&clashing).unwrap_err().to_string();
+        assert!(err.contains("[presets.servo]"), "{}", err);
+        let bad: PresetGroups = toml::from_str("[speeds]\n\"very slow\" = 10").unwrap();
+        assert!(check(&bad).is_err());
+    }
+}
@@ -1,6 +1,285 @@
 use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::Serialize;
+use std::fmt;
+use std::ops::RangeInclusive;
 use tracing::debug;
 
+/// Tag of `deviceId()`, the one function every firmware has.
+pub const DEVICE_ID_TAG: u8 = 0;
+
+/// Tags kept for commands the adapter itself sends, such as the two below;
+/// manifest functions may not use them.
+pub const RESERVED_TAGS: RangeInclusive<u8> = 0xF0..=0xFF;
+
+/// Reserved tag answered with the firmware's protocol version and
+/// capabilities, sent right after connecting.
+pub const PROTOCOL_VERSION_TAG: u8 = 0xFE;
+
+/// Reserved tag answered with the manifest `version` the firmware's bindings
+/// were generated from.
+pub const MANIFEST_VERSION_TAG: u8 = 0xFD;
+
+/// Reserved tag answered with the signature of the function at an index,
+/// by firmware built with `MCP_INTROSPECTION`; see [`crate::introspect`].
+pub const DESCRIBE_FUNCTION_TAG: u8 = 0xFC;
+
+/// Newest protocol version this adapter speaks.
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// Frame buffer size of firmware that doesn't report one.
+const DEFAULT_MAX_FRAME_SIZE: usize = 256;
+
+/// `getProtocolVersion` flag: commands carry a sequence byte.
+const FLAG_SEQUENCE_NUMBERS: u8 = 0x01;
+/// `getProtocolVersion` flag: the device can send unsolicited event frames.
+const FLAG_EVENTS: u8 = 0x02;
+/// `getProtocolVersion` flag: responses are sent as chunks (protocol v3).
+const FLAG_CHUNKED: u8 = 0x04;
+/// `getProtocolVersion` flag: commands are acknowledged and may be sent
+/// again, see [`crate::reliable`].
+const FLAG_RELIABLE: u8 = 0x08;
+
+/// CRC-8 with polynomial 0x07 and initial value 0x00.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if crc & 0x80 != 0 {
+                crc = (crc << 1) ^ 0x07;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// What the connected firmware's wire format looks like.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProtocolInfo {
+    /// 1 for firmware that predates `getProtocolVersion`
+    pub version: u8,
+    /// Whether the version came from the handshake rather than being assumed
+    pub negotiated: bool,
+    pub crc: &'static str,
+    pub sequence_numbers: bool,
+    /// Largest frame the device accepts, CRC and sequence byte included
+    pub max_frame_size: usize,
+    pub events: bool,
+    /// Responses arrive as `[index] [total] [payload...]` chunks
+    pub chunked: bool,
+    /// Frames go through the reliable link layer
+    pub reliable: bool,
+}
+
+impl ProtocolInfo {
+    /// Firmware without the handshake; sequence numbers can only be taken
+    /// on trust from the configuration.
+    pub fn legacy(sequence_numbers: bool) -> Self {
+        Self {
+            version: 1,
+            negotiated: false,
+            crc: "crc8",
+            sequence_numbers,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            events: false,
+            chunked: false,
+            reliable: false,
+        }
+    }
+
+    /// Parse a `getProtocolVersion` response:
+    /// `[version] [flags] [max frame size: u16]`.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let &version = data
+            .first()
+            .ok_or_else(|| anyhow!("Empty getProtocolVersion response"))?;
+        if version > PROTOCOL_VERSION {
+            return Err(anyhow!(
+                "Firmware speaks protocol v{}, but this adapter only supports up to v{} - update the adapter",
+                version,
+                PROTOCOL_VERSION
+            ));
+        }
+        let [_, flags, low, high] = data else {
+            return Err(anyhow!(
+                "Invalid getProtocolVersion response ({} bytes)",
+                data.len()
+            ));
+        };
+        if version < 2 {
+            return Err(anyhow!("Invalid protocol version {}", version));
+        }
+
+        Ok(Self {
+            version,
+            negotiated: true,
+            crc: "crc8",
+            sequence_numbers: flags & FLAG_SEQUENCE_NUMBERS != 0,
+            max_frame_size: u16::from_le_bytes([*low, *high]) as usize,
+            events: flags & FLAG_EVENTS != 0,
+            chunked: flags & FLAG_CHUNKED != 0,
+            reliable: flags & FLAG_RELIABLE != 0,
+        })
+    }
+}
+
+/// Something the firmware reports on its own, in an `[0xFF] [0xFE] [code]`
+/// frame, when it set the events flag in the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// A motion started by an immediately acknowledged function has ended
+    MotionDone,
+    /// The firmware started, e.g. after a brownout or watchdog reset, and
+    /// has lost whatever state the adapter set up
+    Reset,
+    /// The firmware cut its motors on a fault, which the adapter latches
+    /// until a client calls `clearFault`
+    Fault(Fault),
+    Unknown(u8),
+}
+
+impl DeviceEvent {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => Self::MotionDone,
+            0x02 => Self::Reset,
+            0x10 => Self::Fault(Fault::Stall),
+            0x11 => Self::Fault(Fault::Overcurrent),
+            0x12 => Self::Fault(Fault::Thermal),
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Why the firmware stopped its motors, from event codes 0x10 and up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// A motor is blocked
+    Stall,
+    /// A motor or driver drew more current than it may
+    Overcurrent,
+    /// A motor or driver got too hot
+    Thermal,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stall => write!(f, "stall"),
+            Self::Overcurrent => write!(f, "overcurrent"),
+            Self::Thermal => write!(f, "thermal"),
+        }
+    }
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MotionDone => write!(f, "motion done"),
+            Self::Reset => write!(f, "reset"),
+            Self::Fault(fault) => write!(f, "{} fault", fault),
+            Self::Unknown(code) => write!(f, "event 0x{:02X}", code),
+        }
+    }
+}
+
+/// The event `frame` carries, if it is an event frame with a good CRC.
+/// Three bytes starting `0xFF 0xFE` can't be a response: no return type
+/// has that length unless it is a string ending in the 0 that event codes
+/// never are, and no chunk index or sequence number is 0xFF.
+pub fn device_event(frame: &[u8]) -> Option<DeviceEvent> {
+    match *frame {
+        [0xFF, 0xFE, code, crc] if code != 0 && crc8(&frame[..3]) == crc => {
+            Some(DeviceEvent::from_code(code))
+        }
+        _ => None,
+    }
+}
+
+/// Error reported by the firmware in an `[0xFF] [code]` frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The command frame failed the firmware's CRC check
+    Crc,
+    /// Unknown tag, wrong argument length or a result that didn't fit
+    Dispatch,
+    /// The firmware can't take the command right now
+    Busy,
+    /// The function reported a hardware problem
+    HardwareFault,
+    Unknown(u8),
+}
+
+impl DeviceError {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => Self::Crc,
+            0x02 => Self::Dispatch,
+            0x03 => Self::Busy,
+            0x04 => Self::HardwareFault,
+            code => Self::Unknown(code),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Crc => 0x01,
+            Self::Dispatch => 0x02,
+            Self::Busy => 0x03,
+            Self::HardwareFault => 0x04,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    /// Stable name for MCP error data.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Crc => "crc_error",
+            Self::Dispatch => "dispatch_error",
+            Self::Busy => "robot_busy",
+            Self::HardwareFault => "hardware_fault",
+            Self::Unknown(_) => "device_error",
+        }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::Crc => "CRC error",
+            Self::Dispatch => "dispatch error",
+            Self::Busy => "busy",
+            Self::HardwareFault => "hardware fault",
+            Self::Unknown(_) => "unknown error",
+        };
+        write!(f, "Device reported {} (code {})", description, self.code())
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+/// The error in `data` if it is an `[0xFF] [code]` error frame rather than
+/// a value of `return_type`. An `i16` result can't be told apart from one.
+pub fn device_error(return_type: Option<&str>, data: &[u8]) -> Option<DeviceError> {
+    let [0xFF, code] = *data else {
+        return None;
+    };
+    let is_error = match return_type {
+        Some("i16") => false,
+        // Too short for their length prefix or value
+        Some("i32" | "blob" | "image") | None => true,
+        // "\xFF" as a string is [0xFF, 0x00]
+        Some(_) => code != 0,
+    };
+    is_error.then(|| DeviceError::from_code(code))
+}
+
 pub struct ResponseDecoder<'a> {
     data: &'a [u8],
     pos: usize,
@@ -11,6 +290,15 @@ impl<'a> ResponseDecoder<'a> {
         Self { data, pos: 0 }
     }
 
+    pub fn read_u8(&mut self) -> Result<u8> {
+        let value = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Not enough data for u8"))?;
+        self.pos += 1;
+        Ok(value)
+    }
+
     pub fn read_i16(&mut self) -> Result<i16> {
         if self.pos + 2 > self.data.len() {
             return Err(anyhow!("Not enough data for i16"));
@@ -43,7 +331,7 @@ impl<'a> ResponseDecoder<'a> {
             .position(|&b| b == 0)
             .unwrap_or(remaining.len());
 
-        if end_pos == 0 && remaining.len() > 0 && remaining[0] == 0 {
+        if end_pos == 0 && !remaining.is_empty() && remaining[0] == 0 {
             // Empty string with null terminator
             self.pos += 1;
             return Ok(String::new());
@@ -61,15 +349,59 @@ impl<'a> ResponseDecoder<'a> {
         debug!("Decoded C string: '{}'", result);
         Ok(result)
     }
+
+    /// `[length: u16 LE] [bytes...]`, as sent for `blob` and `image`.
+    pub fn read_blob(&mut self) -> Result<Vec<u8>> {
+        if self.pos + 2 > self.data.len() {
+            return Err(anyhow!("Not enough data for blob length"));
+        }
+        let len = u16::from_le_bytes([self.data[self.pos], self.data[self.pos + 1]]) as usize;
+        let start = self.pos + 2;
+        if start + len > self.data.len() {
+            return Err(anyhow!(
+                "Blob of {} bytes but only {} received",
+                len,
+                self.data.len() - start
+            ));
+        }
+        self.pos = start + len;
+        Ok(self.data[start..self.pos].to_vec())
+    }
+}
+
+/// MIME type of an image, recognised from its first bytes.
+pub fn image_mime_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+/// Binary results travel as `data:` URLs so they stay plain strings.
+pub fn data_url(mime_type: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", mime_type, BASE64.encode(data))
 }
 
+/// Split a base64 `data:` URL into its MIME type and base64 payload.
+pub fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("data:")?.split_once(";base64,")
+}
+
+/// Builds a command's argument bytes, handed on to the port as `Bytes` so
+/// resends and the port's thread share them rather than copy them.
 pub struct CommandEncoder {
-    data: Vec<u8>,
+    data: BytesMut,
 }
 
 impl CommandEncoder {
     pub fn new() -> Self {
-        Self { data: Vec::new() }
+        Self {
+            data: BytesMut::with_capacity(32),
+        }
     }
 
     pub fn write_i16(&mut self, value: i16) {
@@ -82,15 +414,19 @@ impl CommandEncoder {
 
     pub fn write_cstring(&mut self, value: &str) {
         self.data.extend_from_slice(value.as_bytes());
-        self.data.push(0); // Null terminator
+        self.data.put_u8(0); // Null terminator
     }
 
-    pub fn finish(self) -> Vec<u8> {
-        self.data
+    pub fn finish(self) -> Bytes {
+        self.data.freeze()
     }
 }
 
 pub fn decode_response_by_type(data: &[u8], return_type: &str) -> Result<String> {
+    if let Some(error) = device_error(Some(return_type), data) {
+        return Err(error.into());
+    }
+
     // Handle void functions (no data)
     if data.is_empty() {
         return Ok("Command executed successfully".to_string());
@@ -108,6 +444,204 @@ pub fn decode_response_by_type(data: &[u8], return_type: &str) -> Result<String>
             let value = decoder.read_i32()?;
             Ok(value.to_string())
         }
+        "blob" => Ok(data_url("application/octet-stream", &decoder.read_blob()?)),
+        "image" => {
+            let image = decoder.read_blob()?;
+            let mime_type = image_mime_type(&image)
+                .ok_or_else(|| anyhow!("Image is not JPEG, PNG, GIF, WebP or BMP data"))?;
+            Ok(data_url(mime_type, &image))
+        }
         _ => decoder.read_cstring(), // Default to string
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slip::{slip_encode, SlipDecoder};
+    use mcp_client::conformance::{self, hex, vectors};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_protocol_info_decode() {
+        let info = ProtocolInfo::decode(&[2, 0x03, 0x00, 0x02]).unwrap();
+        assert!(info.negotiated && info.sequence_numbers && info.events);
+        assert_eq!(info.max_frame_size, 512);
+
+        let info = ProtocolInfo::decode(&[3, 0x04, 0x00, 0x01]).unwrap();
+        assert!(info.chunked && !info.sequence_numbers);
+        let info = ProtocolInfo::decode(&[3, 0x0C, 0x00, 0x01]).unwrap();
+        assert!(info.chunked && info.reliable);
+
+        let err = ProtocolInfo::decode(&[4, 0, 0, 1]).unwrap_err();
+        assert!(err.to_string().contains("update the adapter"));
+        assert!(ProtocolInfo::decode(&[2, 0]).is_err());
+        assert!(ProtocolInfo::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn test_conformance_framing() {
+        let decode = |wire: &[u8]| -> Vec<Vec<u8>> {
+            let mut decoder = SlipDecoder::new();
+            wire.iter()
+                .filter_map(|&byte| decoder.process_byte(byte).unwrap())
+                .collect()
+        };
+        conformance::check_framing(crc8, slip_encode, decode);
+    }
+
+    #[test]
+    fn test_conformance_commands() {
+        for v in vectors("commands") {
+            let mut encoder = CommandEncoder::new();
+            for argument in v["arguments"].as_array().unwrap() {
+                let value = &argument["value"];
+                match argument["type"].as_str().unwrap() {
+                    "i16" => encoder.write_i16(value.as_i64().unwrap() as i16),
+                    "i32" => encoder.write_i32(value.as_i64().unwrap() as i32),
+                    _ => encoder.write_cstring(value.as_str().unwrap()),
+                }
+            }
+            let args = encoder.finish();
+            assert_eq!(args, hex(&v["args"]), "{}", v["name"]);
+            let mut frame = vec![v["tag"].as_u64().unwrap() as u8];
+            frame.extend(&args);
+            frame.push(crc8(&frame));
+            assert_eq!(frame, hex(&v["frame"]), "{}", v["name"]);
+        }
+        for v in vectors("bad_commands") {
+            let frame = hex(&v["frame"]);
+            let (crc, body) = frame.split_last().unwrap();
+            assert_ne!(crc8(body), *crc, "{}", v["name"]);
+        }
+    }
+
+    #[test]
+    fn test_conformance_responses() {
+        let body = |v: &serde_json::Value| {
+            let frame = hex(&v["frame"]);
+            let (&crc, body) = frame.split_last().unwrap();
+            assert_eq!(crc8(body), crc, "{}", v);
+            body.to_vec()
+        };
+        for v in vectors("responses") {
+            let return_type = v["return"].as_str().unwrap_or("void");
+            let text = decode_response_by_type(&body(&v), return_type).unwrap();
+            assert_eq!(text, v["text"], "{}", v["name"]);
+        }
+        for v in vectors("errors") {
+            let error = device_error(Some("CStr"), &body(&v)).unwrap();
+            assert_eq!(error.name(), v["name"]);
+            assert_eq!(u64::from(error.code()), v["code"]);
+        }
+        for v in vectors("events") {
+            let event = device_event(&hex(&v["frame"])).unwrap();
+            assert_eq!(event.to_string(), v["event"]);
+        }
+        for v in vectors("handshake") {
+            let info = ProtocolInfo::decode(&body(&v)).unwrap();
+            let flags = u8::from(info.sequence_numbers)
+                | u8::from(info.events) << 1
+                | u8::from(info.chunked) << 2
+                | u8::from(info.reliable) << 3;
+            assert_eq!(u64::from(info.version), v["version"]);
+            assert_eq!(vec![flags], hex(&v["flags"]));
+            assert_eq!(info.max_frame_size as u64, v["max_frame_size"]);
+        }
+    }
+
+    #[test]
+    fn test_error_frames_decoded() {
+        let err = decode_response_by_type(&[0xFF, 0x04], "i32").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DeviceError>(),
+            Some(&DeviceError::HardwareFault)
+        );
+        assert_eq!(device_error(None, &[0xFF, 0x03]), Some(DeviceError::Busy));
+        assert_eq!(
+            device_error(Some("CStr"), &[0xFF, 0x09]),
+            Some(DeviceError::Unknown(9))
+        );
+
+        // Valid values that look like error frames
+        assert_eq!(
+            decode_response_by_type(&[0xFF, 0x02], "i16").unwrap(),
+            "767"
+        );
+        assert_eq!(device_error(Some("CStr"), &[0xFF, 0x00]), None);
+
+        let seal = |data: &[u8]| [data, &[crc8(data)]].concat();
+        assert_eq!(
+            device_event(&seal(&[0xFF, 0xFE, 0x01])),
+            Some(DeviceEvent::MotionDone)
+        );
+        assert_eq!(
+            device_event(&seal(&[0xFF, 0xFE, 0x02])),
+            Some(DeviceEvent::Reset)
+        );
+        assert_eq!(
+            device_event(&seal(&[0xFF, 0xFE, 0x12])),
+            Some(DeviceEvent::Fault(Fault::Thermal))
+        );
+        // "\xFF\xFE" as a string, an i32, and a damaged event
+        assert_eq!(device_event(&seal(&[0xFF, 0xFE, 0x00])), None);
+        assert_eq!(device_event(&seal(&[0xFF, 0xFE, 0x01, 0x00])), None);
+        assert_eq!(device_event(&[0xFF, 0xFE, 0x01, 0x00]), None);
+    }
+
+    #[test]
+    fn test_decode_image_as_data_url() {
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A];
+        let mut data = vec![png.len() as u8, 0];
+        data.extend_from_slice(&png);
+
+        let url = decode_response_by_type(&data, "image").unwrap();
+        let (mime_type, payload) = parse_data_url(&url).unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(BASE64.decode(payload).unwrap(), png);
+
+        let url = decode_response_by_type(&[2, 0, 1, 2], "blob").unwrap();
+        assert_eq!(url, "data:application/octet-stream;base64,AQI=");
+        assert!(decode_response_by_type(&[2, 0, 1, 2], "image").is_err());
+        assert!(decode_response_by_type(&[9, 0, 1], "blob").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_encoded_values_decode_in_order(
+            a in any::<i16>(),
+            b in any::<i32>(),
+            s in "[^\0]{0,32}",
+        ) {
+            let mut encoder = CommandEncoder::new();
+            encoder.write_i16(a);
+            encoder.write_cstring(&s);
+            encoder.write_i32(b);
+            let data = encoder.finish();
+
+            let mut decoder = ResponseDecoder::new(&data);
+            prop_assert_eq!(decoder.read_i16().unwrap(), a);
+            prop_assert_eq!(decoder.read_cstring().unwrap(), s);
+            prop_assert_eq!(decoder.read_i32().unwrap(), b);
+            prop_assert!(decoder.read_i16().is_err());
+        }
+
+        #[test]
+        fn prop_response_decoder_never_panics(
+            data in prop::collection::vec(any::<u8>(), 0..64),
+            reads in prop::collection::vec(0..3u8, 0..16),
+        ) {
+            let mut decoder = ResponseDecoder::new(&data);
+            for read in reads {
+                let _ = match read {
+                    0 => decoder.read_i16().map(|v| v.to_string()),
+                    1 => decoder.read_i32().map(|v| v.to_string()),
+                    _ => decoder.read_cstring(),
+                };
+            }
+            for return_type in ["CStr", "i16", "i32", "bool", "blob", "image"] {
+                let _ = decode_response_by_type(&data, return_type);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! The Python environment `runPythonScript` runs in: the system `python3`,
+//! or a virtualenv the adapter creates and installs requirements into.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time;
+use tracing::info;
+
+/// Requirements already installed, one per line, kept inside the venv.
+const INSTALLED_FILE: &str = "arduino-mcp-requirements.txt";
+
+/// Upper bound for creating the venv or one `pip install`.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub struct PythonEnv {
+    venv: Option<PathBuf>,
+    installed: Mutex<HashSet<String>>,
+}
+
+impl PythonEnv {
+    pub fn new(venv: Option<PathBuf>) -> Self {
+        Self {
+            venv,
+            installed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn venv(&self) -> Option<&Path> {
+        self.venv.as_deref()
+    }
+
+    /// The interpreter scripts are run with.
+    pub fn interpreter(&self) -> PathBuf {
+        match &self.venv {
+            Some(venv) => venv.join("bin").join("python"),
+            None => PathBuf::from("python3"),
+        }
+    }
+
+    /// Create the venv if it doesn't exist yet and load the list of
+    /// requirements installed by earlier runs.
+    pub async fn prepare(&self) -> Result<()> {
+        let Some(venv) = &self.venv else {
+            return Ok(());
+        };
+
+        if !self.interpreter().exists() {
+            info!("Creating Python virtualenv at {}", venv.display());
+            run_setup(Command::new("python3").arg("-m").arg("venv").arg(venv))
+                .await
+                .with_context(|| format!("Failed to create virtualenv {}", venv.display()))?;
+        }
+
+        if let Ok(content) = std::fs::read_to_string(venv.join(INSTALLED_FILE)) {
+            let mut installed = self.installed.lock().await;
+            installed.extend(content.lines().map(str::to_string));
+        }
+        Ok(())
+    }
+
+    /// `pip install` whichever of `requirements` haven't been installed yet.
+    pub async fn install(&self, requirements: &[String]) -> Result<()> {
+        let Some(venv) = &self.venv else {
+            return Err(anyhow!(
+                "requirements need the adapter to run with --python-venv"
+            ));
+        };
+        for requirement in requirements {
+            check_requirement(requirement)?;
+        }
+
+        // Held during pip so concurrent scripts don't install the same thing twice
+        let mut installed = self.installed.lock().await;
+        let missing: Vec<&String> = requirements
+            .iter()
+            .filter(|r| !installed.contains(r.trim()))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        info!("Installing Python requirements: {:?}", missing);
+        run_setup(
+            Command::new(self.interpreter())
+                .args([
+                    "-m",
+                    "pip",
+                    "install",
+                    "--quiet",
+                    "--disable-pip-version-check",
+                ])
+                .args(missing.iter().map(|r| r.trim())),
+        )
+        .await
+        .context("pip install failed")?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(venv.join(INSTALLED_FILE))?;
+        for requirement in missing {
+            writeln!(file, "{}", requirement.trim())?;
+            installed.insert(requirement.trim().to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Requirements are passed to pip as arguments, so keep them from being
+/// read as options.
+pub fn check_requirement(requirement: &str) -> Result<()> {
+    let requirement = requirement.trim();
+    if requirement.is_empty() || requirement.starts_with('-') || requirement.contains('\n') {
+        return Err(anyhow!("Invalid requirement '{}'", requirement));
+    }
+    Ok(())
+}
+
+async fn run_setup(command: &mut Command) -> Result<()> {
+    let output = time::timeout(SETUP_TIMEOUT, command.kill_on_drop(true).output())
+        .await
+        .map_err(|_| anyhow!("timed out after {} seconds", SETUP_TIMEOUT.as_secs()))??;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpreter_path() {
+        assert_eq!(PythonEnv::new(None).interpreter(), PathBuf::from("python3"));
+        assert_eq!(
+            PythonEnv::new(Some(PathBuf::from("/opt/venv"))).interpreter(),
+            PathBuf::from("/opt/venv/bin/python")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_installed_requirements_are_not_reinstalled() {
+        let dir = tempfile::tempdir().unwrap();
+        // An interpreter that fails if pip is ever run
+        std::fs::create_dir(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin/python"), "").unwrap();
+        std::fs::write(dir.path().join(INSTALLED_FILE), "numpy\nscipy>=1.10\n").unwrap();
+
+        let env = PythonEnv::new(Some(dir.path().to_path_buf()));
+        env.prepare().await.unwrap();
+        env.install(&["numpy".to_string(), " scipy>=1.10".to_string()])
+            .await
+            .unwrap();
+        assert!(env.install(&["pandas".to_string()]).await.is_err());
+
+        let err = env
+            .install(&["-r/etc/passwd".to_string()])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid requirement"));
+        assert!(PythonEnv::new(None)
+            .install(&["numpy".to_string()])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Warm `python3` interpreters for `runPythonScript`, so a call doesn't pay
+//! interpreter start-up and import time.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tempfile::Builder;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time;
+use tracing::{debug, warn};
+
+/// Scripts a worker runs before it is replaced, bounding leaked state.
+const MAX_RUNS_PER_WORKER: usize = 50;
+
+const WORKER_SOURCE: &str = include_str!("resources/python_worker.py");
+
+/// Console output and exit status of one script.
+#[derive(Debug, Deserialize)]
+pub struct ScriptOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the interpreter was killed by a signal
+    pub status: Option<i32>,
+}
+
+/// Keeps up to `size` idle workers. With size 0 every script gets a fresh
+/// `python3` process.
+pub struct PythonPool {
+    size: usize,
+    interpreter: PathBuf,
+    idle: Mutex<Vec<Worker>>,
+}
+
+impl PythonPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            interpreter: PathBuf::from("python3"),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run scripts with `interpreter` instead of `python3` from PATH.
+    pub fn with_interpreter(mut self, interpreter: PathBuf) -> Self {
+        self.interpreter = interpreter;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Spawn workers until `size` are idle.
+    pub fn fill(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        while idle.len() < self.size {
+            match Worker::spawn(&self.interpreter) {
+                Ok(worker) => idle.push(worker),
+                Err(e) => {
+                    warn!("Could not start Python worker: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    pub async fn run(&self, script: &str, timeout: Duration) -> Result<ScriptOutput> {
+        if self.size == 0 {
+            return run_once(&self.interpreter, script, timeout).await;
+        }
+
+        let idle = self.idle.lock().unwrap().pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => Worker::spawn(&self.interpreter)?,
+        };
+
+        let output = match time::timeout(timeout, worker.run(script)).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                self.fill();
+                return Err(e);
+            }
+            Err(_) => {
+                // Dropping the worker kills it
+                drop(worker);
+                self.fill();
+                return Err(timed_out(timeout));
+            }
+        };
+
+        worker.runs += 1;
+        let reusable = output.status == Some(0) && worker.runs < MAX_RUNS_PER_WORKER;
+        let mut idle = self.idle.lock().unwrap();
+        if reusable && idle.len() < self.size {
+            idle.push(worker);
+        } else {
+            debug!("Retiring Python worker after {} run(s)", worker.runs);
+            drop(idle);
+            drop(worker);
+            self.fill();
+        }
+        Ok(output)
+    }
+}
+
+struct Worker {
+    // Held so the process is killed when the worker is dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    runs: usize,
+}
+
+impl Worker {
+    fn spawn(interpreter: &Path) -> Result<Self> {
+        let mut child = Command::new(interpreter)
+            .arg("-c")
+            .arg(WORKER_SOURCE)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| spawn_error(interpreter))?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Self {
+            _child: child,
+            stdin,
+            stdout,
+            runs: 0,
+        })
+    }
+
+    async fn run(&mut self, script: &str) -> Result<ScriptOutput> {
+        let mut request = format!("{}\n", script.len()).into_bytes();
+        request.extend_from_slice(script.as_bytes());
+        self.stdin
+            .write_all(&request)
+            .await
+            .context("Failed to send script to Python worker")?;
+        self.stdin.flush().await?;
+
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("Python worker exited unexpectedly"));
+        }
+        serde_json::from_str(&line).context("Invalid response from Python worker")
+    }
+}
+
+/// Run `script` in a new interpreter process.
+async fn run_once(interpreter: &Path, script: &str, timeout: Duration) -> Result<ScriptOutput> {
+    let mut temp_file = Builder::new()
+        .prefix("arduino-mcp-script-")
+        .suffix(".py")
+        .tempfile()
+        .context("Failed to create temporary Python file")?;
+    temp_file
+        .write_all(script.as_bytes())
+        .context("Failed to write temporary Python script")?;
+    let temp_path = temp_file.into_temp_path();
+
+    let child = Command::new(interpreter)
+        .arg(&temp_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| spawn_error(interpreter))?;
+
+    let output = match time::timeout(timeout, child.wait_with_output()).await {
+        Ok(result) => result.context("Failed to collect python3 output")?,
+        Err(_) => return Err(timed_out(timeout)),
+    };
+
+    Ok(ScriptOutput {
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        status: output.status.code(),
+    })
+}
+
+fn spawn_error(interpreter: &Path) -> String {
+    format!(
+        "Failed to spawn {0} process. Ensure {0} is installed and on PATH.",
+        interpreter.display()
+    )
+}
+
+fn timed_out(timeout: Duration) -> anyhow::Error {
+    anyhow!(
+        "Python script timed out after {} seconds",
+        timeout.as_secs()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python_runner::python_version;
+
+    const PID: &str = "import os\nprint(os.getpid())";
+
+    async fn pid(pool: &PythonPool) -> String {
+        pool.run(PID, Duration::from_secs(10)).await.unwrap().stdout
+    }
+
+    #[tokio::test]
+    async fn test_workers_are_reused_with_fresh_globals() {
+        if python_version(Path::new("python3")).await.is_none() {
+            return;
+        }
+        let pool = PythonPool::new(1);
+        pool.fill();
+
+        let first = pid(&pool).await;
+        pool.run("x = 1", Duration::from_secs(10)).await.unwrap();
+        let output = pool
+            .run("print('x' in globals())", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "False\n");
+        assert_eq!(pid(&pool).await, first);
+    }
+
+    #[tokio::test]
+    async fn test_failed_script_retires_worker() {
+        if python_version(Path::new("python3")).await.is_none() {
+            return;
+        }
+        let pool = PythonPool::new(1);
+        let first = pid(&pool).await;
+
+        let output = pool
+            .run("import os, sys\nos.write(1, b'raw\\n')\nprint('bye', file=sys.stderr)\nsys.exit(3)", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(output.status, Some(3));
+        assert_eq!(output.stdout, "raw\n");
+        assert_eq!(output.stderr, "bye\n");
+
+        assert_ne!(pid(&pool).await, first);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_worker() {
+        if python_version(Path::new("python3")).await.is_none() {
+            return;
+        }
+        let pool = PythonPool::new(1);
+        let err = pool
+            .run("while True:\n    pass", Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(!pid(&pool).await.is_empty());
+    }
+}
@@ -1,71 +1,57 @@
-use anyhow::{anyhow, Context, Result};
-use std::io::Write;
-use std::path::PathBuf;
-use std::process::Stdio;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::path::Path;
 use std::time::Duration;
-use tempfile::Builder;
 use tokio::process::Command;
 use tokio::time;
 
-/// Execute the provided Python script with a prelude that exposes MCP tools.
+use crate::manifest::Function;
+use crate::python_pool::PythonPool;
+
+/// Report the version of `interpreter`, or `None` if it can't be run.
+pub async fn python_version(interpreter: &Path) -> Option<String> {
+    let output = time::timeout(
+        Duration::from_secs(2),
+        Command::new(interpreter)
+            .arg("--version")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Execute the provided Python script with a prelude that exposes MCP tools,
+/// called through the adapter's tool socket at `tool_socket`, and binds `args` (a JSON object) to the `ARGS` dict.
 pub async fn run_python_script(
+    pool: &PythonPool,
     script: &str,
+    args: &Value,
     timeout: Duration,
-    tool_names: &[String],
-    endpoint: &str,
+    functions: &[Function],
+    tool_socket: &Path,
 ) -> Result<String> {
     if script.trim().is_empty() {
         return Err(anyhow!("Python script must not be empty"));
     }
 
-    let mut full_script = build_prelude(tool_names, endpoint);
+    let mut full_script = build_prelude(functions, args, tool_socket);
     full_script.push_str("\n# --- User script starts here ---\n");
     full_script.push_str(script);
     if !script.ends_with('\n') {
         full_script.push('\n');
     }
 
-    let mut temp_file = Builder::new()
-        .prefix("arduino-mcp-script-")
-        .suffix(".py")
-        .tempfile()
-        .context("Failed to create temporary Python file")?;
-    temp_file
-        .write_all(full_script.as_bytes())
-        .context("Failed to write temporary Python script")?;
-
-    let temp_path = temp_file.into_temp_path();
-    let script_path: PathBuf = temp_path.to_path_buf();
-
-    let mut command = Command::new("python3");
-    command.arg(&script_path);
-    command.stdout(Stdio::piped());
-    command.stderr(Stdio::piped());
-    command.kill_on_drop(true);
-
-    let child = command
-        .spawn()
-        .context("Failed to spawn python3 process. Ensure python3 is installed and on PATH.")?;
-
-    let timeout_secs = timeout.as_secs();
-    let output = match time::timeout(timeout, child.wait_with_output()).await {
-        Ok(result) => result.context("Failed to collect python3 output")?,
-        Err(_) => {
-            return Err(anyhow!(
-                "Python script timed out after {} seconds",
-                timeout_secs
-            ));
-        }
-    };
-
-    // Drop the temp path to ensure the file is removed after execution
-    drop(temp_path);
-
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    let output = pool.run(&full_script, timeout).await?;
 
-    if !output.status.success() {
-        let status_str = match output.status.code() {
+    if output.status != Some(0) {
+        let status_str = match output.status {
             Some(code) => format!("exit code {}", code),
             None => "terminated by signal".to_string(),
         };
@@ -73,12 +59,12 @@ pub async fn run_python_script(
         return Err(anyhow!(
             "Python script failed with {}.\nSTDOUT:\n{}\nSTDERR:\n{}",
             status_str,
-            stdout,
-            stderr
+            output.stdout,
+            output.stderr
         ));
     }
 
-    Ok(format_console_output(stdout, stderr))
+    Ok(format_console_output(output.stdout, output.stderr))
 }
 
 fn format_console_output(stdout: String, stderr: String) -> String {
@@ -96,25 +82,107 @@ fn format_console_output(stdout: String, stderr: String) -> String {
     }
 }
 
-fn build_prelude(tool_names: &[String], endpoint: &str) -> String {
+/// Typed wrappers for the manifest functions, plus a plain keyword-argument
+/// trampoline for `runPythonScript` itself.
+fn build_prelude(functions: &[Function], args: &Value, tool_socket: &Path) -> String {
     const TEMPLATE: &str = include_str!("resources/python_prelude.py.tmpl");
 
-    let endpoint_literal = serde_json::to_string(endpoint).unwrap();
-    let trampolines = tool_names
-        .iter()
-        .enumerate()
-        .map(|(index, name)| {
-            let name_literal = serde_json::to_string(name).unwrap();
-            format!(
-                "_tool_fn_{idx} = _wrap_tool({name_literal})\nsetattr(tools, {name_literal}, _tool_fn_{idx})",
-                idx = index,
-                name_literal = name_literal
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
+    let socket_literal = serde_json::to_string(&tool_socket.to_string_lossy()).unwrap();
+    // JSON text inside string literals, so null/true/false need no translation
+    let specs_literal = serde_json::to_string(&serde_json::to_string(functions).unwrap()).unwrap();
+    let args_literal = serde_json::to_string(&args.to_string()).unwrap();
+    let trampolines = if functions.iter().any(|f| f.name == "runPythonScript") {
+        String::new()
+    } else {
+        "setattr(tools, \"runPythonScript\", _wrap_tool(\"runPythonScript\"))".to_string()
+    };
 
     TEMPLATE
-        .replace("__MCP_ENDPOINT__", &endpoint_literal)
+        .replace("__TOOL_SOCKET__", &socket_literal)
+        .replace("__TOOL_SPECS__", &specs_literal)
+        .replace("__SCRIPT_ARGS__", &args_literal)
         .replace("__TOOL_TRAMPOLINES__", &trampolines)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+tools._call = lambda name, **kwargs: f"{name} {sorted(kwargs.items())}"
+print(tools.setServo(90, speed="0x10"))
+print(tools.setServo(angle=-5.0, speed=1))
+print(tools.setLabel(label=42))
+for call in (
+    lambda: tools.setServo(40000, 1),
+    lambda: tools.setServo(1.5, 1),
+    lambda: tools.setServo(True, 1),
+    lambda: tools.setServo(1),
+    lambda: tools.setLabel("a\0b"),
+):
+    try:
+        call()
+    except (TypeError, ValueError) as exc:
+        print(type(exc).__name__, exc)
+print(tools.setServo.__doc__.splitlines()[0])
+"#;
+
+    #[tokio::test]
+    async fn test_prelude_wrappers_convert_and_check_arguments() {
+        if python_version(Path::new("python3")).await.is_none() {
+            return;
+        }
+        let functions: Vec<Function> = serde_json::from_value(serde_json::json!([
+            {"tag": 1, "name": "setServo", "desc": "Move the \"arm\" servo", "return": null,
+             "params": [{"name": "angle", "type": "i16"}, {"name": "speed", "type": "i32"}]},
+            {"tag": 2, "name": "setLabel", "desc": "Show text", "return": "CStr",
+             "params": [{"name": "label", "type": "CStr"}]}
+        ]))
+        .unwrap();
+
+        let output = run_python_script(
+            &PythonPool::new(0),
+            SCRIPT,
+            &serde_json::json!({}),
+            Duration::from_secs(10),
+            &functions,
+            Path::new("/nonexistent/tools.sock"),
+        )
+        .await
+        .unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], "setServo [('angle', 90), ('speed', 16)]");
+        assert_eq!(lines[1], "setServo [('angle', -5), ('speed', 1)]");
+        assert_eq!(lines[2], "setLabel [('label', '42')]");
+        assert!(lines[3].starts_with("ValueError setServo(angle=40000): out of range for i16"));
+        assert!(lines[4].starts_with("TypeError setServo(angle=1.5): expected i16"));
+        assert!(lines[5].starts_with("TypeError setServo(angle=True): expected i16"));
+        assert_eq!(
+            lines[6],
+            "TypeError setServo(angle, speed): missing a required argument: 'speed'"
+        );
+        assert!(lines[7].starts_with("ValueError setLabel(label='a\\x00b')"));
+        assert_eq!(lines[8], "Move the \"arm\" servo");
+    }
+
+    #[tokio::test]
+    async fn test_args_are_bound_to_a_dict() {
+        if python_version(Path::new("python3")).await.is_none() {
+            return;
+        }
+        let args = serde_json::json!({"path": [[0, 1], [2, 3]], "label": "it's \"quoted\"", "dry": true, "n": null});
+
+        let output = run_python_script(
+            &PythonPool::new(0),
+            "print(ARGS['path'][1][0], ARGS['label'], ARGS['dry'], ARGS['n'])",
+            &args,
+            Duration::from_secs(10),
+            &[],
+            Path::new("/nonexistent/tools.sock"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output, "2 it's \"quoted\" True None");
+    }
+}
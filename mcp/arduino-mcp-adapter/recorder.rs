@@ -0,0 +1,350 @@
+//! Session recording: every tool call clients make, written out as it
+//! happens, as a Python script for `runPythonScript` or a macro for
+//! `runMacro`. A session that worked interactively becomes a routine that
+//! can be run again.
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::macros::{self, Macro, MAX_RECORDED_GAP};
+use crate::sequence::{StepSpec, MAX_STEPS};
+
+/// Pauses shorter than this are left out of scripts.
+const MIN_SCRIPT_SLEEP_MS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Python,
+    Macro,
+}
+
+#[derive(Default)]
+struct State {
+    /// Manifest `name` of the robot the first call went to
+    robot: Option<String>,
+    /// The macro so far; scripts are written out as they go
+    steps: Vec<StepSpec>,
+    last_call: Option<Instant>,
+}
+
+pub struct SessionRecorder {
+    path: PathBuf,
+    format: Format,
+    state: Mutex<State>,
+}
+
+impl SessionRecorder {
+    /// Record to `path`, replacing it: a script if it ends in `.py`, a macro
+    /// named after the file if it ends in `.json`.
+    pub fn create(path: &Path) -> Result<Self> {
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("py") => Format::Python,
+            Some("json") => Format::Macro,
+            _ => {
+                return Err(anyhow!(
+                    "Session recording {} must end in .py or .json",
+                    path.display()
+                ))
+            }
+        };
+        if format == Format::Macro {
+            macros::check_name("macro", &macro_name(path))?;
+        }
+        let recorder = Self {
+            path: path.to_path_buf(),
+            format,
+            state: Mutex::new(State::default()),
+        };
+        match format {
+            Format::Python => {
+                let mut file = File::create(path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                writeln!(
+                    file,
+                    "# Recorded by arduino-mcp-adapter; run it with the runPythonScript tool\n\
+                     import time\n"
+                )?;
+            }
+            Format::Macro => recorder.save_macro(&State::default())?,
+        }
+        info!("Recording the session's calls to {}", path.display());
+        Ok(recorder)
+    }
+
+    /// Add a successful call on `robot`.
+    pub fn record(&self, robot: &str, function: &str, arguments: &Value) -> Result<()> {
+        let step = StepSpec {
+            name: function.to_string(),
+            arguments: arguments.clone(),
+            delay_ms: 0,
+        };
+        self.record_steps(robot, Instant::now(), vec![step])
+    }
+
+    /// Add the function calls a tool such as `callSequence` made on `robot`,
+    /// the first at `started`, each followed by its `delay_ms`.
+    pub fn record_steps(&self, robot: &str, started: Instant, steps: Vec<StepSpec>) -> Result<()> {
+        let Some(first) = steps.first() else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.robot.get_or_insert_with(|| robot.to_string()) != robot {
+            warn!(
+                "Not recording '{}': the session recording is for another robot",
+                first.name
+            );
+            return Ok(());
+        }
+        let gap_ms = state.last_call.map(|last_call| {
+            started
+                .saturating_duration_since(last_call)
+                .min(MAX_RECORDED_GAP)
+                .as_millis() as u64
+        });
+        state.last_call = Some(Instant::now());
+
+        match self.format {
+            Format::Python => {
+                let mut lines = String::new();
+                let pauses = std::iter::once(gap_ms.unwrap_or(0))
+                    .chain(steps.iter().map(|step| step.delay_ms));
+                for (pause_ms, step) in pauses.zip(&steps) {
+                    if pause_ms >= MIN_SCRIPT_SLEEP_MS {
+                        lines.push_str(&format!("time.sleep({})\n", pause_ms as f64 / 1000.0));
+                    }
+                    lines.push_str(&python_call(&step.name, &step.arguments));
+                    lines.push('\n');
+                }
+                self.append(&lines)
+            }
+            Format::Macro => {
+                let room = MAX_STEPS - state.steps.len();
+                if steps.len() > room {
+                    warn!(
+                        "Not recording {} call(s) from '{}' on: a macro holds at most {} steps",
+                        steps.len() - room,
+                        steps[room].name,
+                        MAX_STEPS
+                    );
+                }
+                if room == 0 {
+                    return Ok(());
+                }
+                if let (Some(previous), Some(gap_ms)) = (state.steps.last_mut(), gap_ms) {
+                    previous.delay_ms = gap_ms;
+                }
+                state.steps.extend(steps.into_iter().take(room));
+                if let Some(last) = state.steps.last_mut() {
+                    // The gap to the next call replaces it
+                    last.delay_ms = 0;
+                }
+                self.save_macro(&state)
+            }
+        }
+    }
+
+    /// Add a successful call of a built-in tool such as `scheduleTool`,
+    /// which only a script can make again.
+    pub fn record_tool(&self, robot: &str, tool: &str, arguments: &Value) -> Result<()> {
+        if self.format == Format::Macro {
+            warn!("Not recording '{}': a macro can't call it", tool);
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.robot.get_or_insert_with(|| robot.to_string()) != robot {
+            warn!(
+                "Not recording '{}': the session recording is for another robot",
+                tool
+            );
+            return Ok(());
+        }
+        let now = Instant::now();
+        let gap_ms = state
+            .last_call
+            .map(|last_call| (now - last_call).min(MAX_RECORDED_GAP).as_millis() as u64);
+        state.last_call = Some(now);
+
+        let mut lines = String::new();
+        if let Some(gap_ms) = gap_ms.filter(|ms| *ms >= MIN_SCRIPT_SLEEP_MS) {
+            lines.push_str(&format!("time.sleep({})\n", gap_ms as f64 / 1000.0));
+        }
+        lines.push_str(&python_tool_call(tool, arguments));
+        lines.push('\n');
+        self.append(&lines)
+    }
+
+    fn append(&self, lines: &str) -> Result<()> {
+        OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    fn save_macro(&self, state: &State) -> Result<()> {
+        let recorded = Macro {
+            name: macro_name(&self.path),
+            robot: state.robot.clone().unwrap_or_default(),
+            steps: state.steps.clone(),
+        };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&recorded)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+fn macro_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `tools.name(arg=value, ...)` with the arguments as Python literals.
+fn python_call(function: &str, arguments: &Value) -> String {
+    format!("tools.{}({})", function, python_keywords(arguments))
+}
+
+/// `tools._call("name", arg=value, ...)`, for tools without a wrapper.
+fn python_tool_call(tool: &str, arguments: &Value) -> String {
+    let keywords = python_keywords(arguments);
+    let separator = if keywords.is_empty() { "" } else { ", " };
+    format!(
+        "tools._call({}{}{})",
+        Value::from(tool),
+        separator,
+        keywords
+    )
+}
+
+fn python_keywords(arguments: &Value) -> String {
+    match arguments {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, python_literal(value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => String::new(),
+    }
+}
+
+fn python_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "None".to_string(),
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        // JSON numbers and strings read the same in Python
+        Value::Number(_) | Value::String(_) => value.to_string(),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(python_literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| {
+                    format!("{}: {}", Value::from(key.as_str()), python_literal(value))
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_records_calls_as_script_and_macro() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("wave.py");
+        let macro_path = dir.path().join("wave.json");
+        let recorders = [
+            SessionRecorder::create(&script).unwrap(),
+            SessionRecorder::create(&macro_path).unwrap(),
+        ];
+        for recorder in &recorders {
+            recorder
+                .record("arm", "setServo", &json!({"angle": 90}))
+                .unwrap();
+            recorder
+                .record("arm", "say", &json!({"text": "hi \"there\"", "loud": true}))
+                .unwrap();
+            // Another robot was plugged in
+            recorder.record("rover", "drive", &json!({})).unwrap();
+        }
+
+        let script = std::fs::read_to_string(&script).unwrap();
+        assert!(script.contains("import time\n"), "{}", script);
+        assert!(
+            script.ends_with(
+                "tools.setServo(angle=90)\ntools.say(loud=True, text=\"hi \\\"there\\\"\")\n"
+            ),
+            "{}",
+            script
+        );
+        let saved: Macro =
+            serde_json::from_str(&std::fs::read_to_string(&macro_path).unwrap()).unwrap();
+        assert_eq!((saved.name.as_str(), saved.robot.as_str()), ("wave", "arm"));
+        let names: Vec<&str> = saved.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["setServo", "say"]);
+
+        assert!(SessionRecorder::create(&dir.path().join("wave.txt")).is_err());
+        assert!(SessionRecorder::create(&dir.path().join("bad name.json")).is_err());
+    }
+
+    #[test]
+    fn test_records_steps_with_pauses_and_tools() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("patrol.py");
+        let macro_path = dir.path().join("patrol.json");
+        let recorders = [
+            SessionRecorder::create(&script).unwrap(),
+            SessionRecorder::create(&macro_path).unwrap(),
+        ];
+        let steps = vec![
+            StepSpec {
+                name: "drive".to_string(),
+                arguments: json!({"speed": 50}),
+                delay_ms: 500,
+            },
+            StepSpec {
+                name: "stop".to_string(),
+                arguments: json!({}),
+                delay_ms: 0,
+            },
+        ];
+        for recorder in &recorders {
+            recorder
+                .record_steps("rover", Instant::now(), steps.clone())
+                .unwrap();
+            recorder
+                .record_tool("rover", "scheduleTool", &json!({"cancel": "blink"}))
+                .unwrap();
+        }
+
+        let script = std::fs::read_to_string(&script).unwrap();
+        assert!(
+            script.ends_with(
+                "tools.drive(speed=50)\ntime.sleep(0.5)\ntools.stop()\n\
+                 tools._call(\"scheduleTool\", cancel=\"blink\")\n"
+            ),
+            "{}",
+            script
+        );
+        let saved: Macro =
+            serde_json::from_str(&std::fs::read_to_string(&macro_path).unwrap()).unwrap();
+        let recorded: Vec<(&str, u64)> = saved
+            .steps
+            .iter()
+            .map(|s| (s.name.as_str(), s.delay_ms))
+            .collect();
+        assert_eq!(recorded, [("drive", 500), ("stop", 0)]);
+    }
+}
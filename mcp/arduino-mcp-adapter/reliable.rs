@@ -0,0 +1,123 @@
+//! Reliable link layer for noisy links, such as long cables or Bluetooth,
+//! used with firmware that reports the reliable flag in the handshake.
+//! One command is outstanding at a time, under a sequence byte. The
+//! firmware acknowledges each command that passes its CRC check and keeps
+//! its last response, so a command sent again after a lost acknowledgement
+//! or a damaged response is answered again rather than run twice.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::protocol::crc8;
+
+/// Second byte of `[0xFF] [0xAC] [seq] [CRC-8]`, the acknowledgement frame.
+const ACK: u8 = 0xAC;
+
+/// How long to wait for an acknowledgement before sending again.
+pub const ACK_TIMEOUT: Duration = Duration::from_millis(200);
+/// Sends of a command the firmware never acknowledges before giving up.
+pub const MAX_SENDS: u32 = 5;
+/// How long to wait for a response to an acknowledged command before
+/// asking for it again; doubled each time up to [`MAX_RESEND_INTERVAL`].
+pub const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_RESEND_INTERVAL: Duration = Duration::from_secs(16);
+
+/// Sequence number of the acknowledgement `frame`, if it is one with a
+/// good CRC. Sequence number 0xFF is never used, so no response starts
+/// with 0xFF.
+pub fn acknowledged(frame: &[u8]) -> Option<u8> {
+    match *frame {
+        [0xFF, ACK, seq, crc] if crc8(&frame[..3]) == crc => Some(seq),
+        _ => None,
+    }
+}
+
+/// Whether `frame` is the firmware's CRC error frame, which in reliable mode
+/// asks for the command to be sent again.
+pub fn is_nak(frame: &[u8]) -> bool {
+    frame == [0xFF, 0x01, crc8(&[0xFF, 0x01])]
+}
+
+/// The sequence number after `seq`, skipping 0xFF.
+pub fn next_seq(seq: u8) -> u8 {
+    match seq.wrapping_add(1) {
+        0xFF => 0,
+        next => next,
+    }
+}
+
+/// Link-layer counters for `/status`.
+#[derive(Default)]
+pub struct LinkStats {
+    retransmits: AtomicU64,
+    naks: AtomicU64,
+    corrupted: AtomicU64,
+    duplicates: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LinkSnapshot {
+    /// Commands sent again, for any reason
+    pub retransmits: u64,
+    /// Commands the firmware reported damaged
+    pub naks: u64,
+    /// Responses that arrived damaged
+    pub corrupted: u64,
+    /// Repeated acknowledgements and responses dropped
+    pub duplicates: u64,
+    /// Commands given up on
+    pub failures: u64,
+}
+
+impl LinkStats {
+    pub fn record_retransmit(&self) {
+        self.retransmits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_nak(&self) {
+        self.naks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_corrupted(&self) {
+        self.corrupted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LinkSnapshot {
+        LinkSnapshot {
+            retransmits: self.retransmits.load(Ordering::Relaxed),
+            naks: self.naks.load(Ordering::Relaxed),
+            corrupted: self.corrupted.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_frames_and_sequence_numbers() {
+        let seal = |data: &[u8]| [data, &[crc8(data)]].concat();
+        assert_eq!(acknowledged(&seal(&[0xFF, 0xAC, 7])), Some(7));
+        assert_eq!(acknowledged(&[0xFF, 0xAC, 7, 0]), None);
+        // A response to sequence number 7
+        assert_eq!(acknowledged(&seal(&[7, 0xAC, 7])), None);
+        assert!(is_nak(&seal(&[0xFF, 0x01])));
+        assert!(!is_nak(&seal(&[0xFF, 0x02])));
+
+        assert_eq!(next_seq(0), 1);
+        assert_eq!(next_seq(0xFE), 0);
+    }
+}
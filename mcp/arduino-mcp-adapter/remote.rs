@@ -0,0 +1,273 @@
+//! Tools mounted from other adapters' HTTP endpoints, e.g. robots attached to
+//! another Raspberry Pi, for re-export from a fleet's unified `/mcp`.
+
+use anyhow::Context;
+use mcp_client::{McpClient, RpcError};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::config::RemoteSpec;
+use crate::manifest::Tool;
+use crate::notifications::Notifier;
+use crate::server::McpError;
+
+/// How often a remote's tools and status are fetched again.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Limit for `initialize`, `tools/list` and `/status` requests.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Limit for forwarded tool calls, which may run scripts or sequences.
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What was learned from the remote on the last poll.
+#[derive(Default)]
+struct Snapshot {
+    tools: Vec<Tool>,
+    status: Option<Value>,
+    /// Why the last poll failed; cleared by a successful one
+    error: Option<String>,
+    last_seen: Option<Instant>,
+}
+
+pub struct RemoteAdapter {
+    pub name: String,
+    /// Base URL, e.g. `http://pi-2.local:8080`
+    pub url: String,
+    /// Its session is dropped when the remote stops answering
+    client: McpClient,
+    snapshot: Mutex<Snapshot>,
+}
+
+impl RemoteAdapter {
+    pub fn new(spec: &RemoteSpec) -> Self {
+        Self {
+            name: spec.name.clone(),
+            url: spec.url.trim_end_matches('/').to_string(),
+            client: McpClient::new(&spec.url)
+                .with_token(spec.token.clone())
+                .with_timeouts(REQUEST_TIMEOUT, CALL_TIMEOUT),
+            snapshot: Mutex::new(Snapshot::default()),
+        }
+    }
+
+    /// Poll the remote until the runtime stops, telling `notifier` whenever
+    /// its tool list changes, including when it goes away or comes back.
+    pub fn spawn_poller(self: &Arc<Self>, notifier: Arc<Notifier>) {
+        let remote = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if remote.refresh().await {
+                    notifier.notify("notifications/tools/list_changed", None);
+                }
+            }
+        });
+    }
+
+    /// Fetch the remote's tools and status. Returns whether the tool list
+    /// changed.
+    pub async fn refresh(&self) -> bool {
+        let result = async {
+            if self.client.session().is_none() {
+                self.client.initialize("arduino-mcp-adapter").await?;
+            }
+            let tools = self.client.list_tools().await?;
+            let tools: Vec<Tool> =
+                serde_json::from_value(Value::Array(tools)).context("Invalid tools/list result")?;
+            let status = self.client.get_json("/status").await?;
+            Ok::<_, anyhow::Error>((tools, status))
+        }
+        .await;
+
+        let mut snapshot = self.snapshot.lock().unwrap();
+        let old_names: Vec<String> = snapshot.tools.iter().map(|t| t.name.clone()).collect();
+        match result {
+            Ok((tools, status)) => {
+                if snapshot.error.take().is_some() || snapshot.last_seen.is_none() {
+                    info!("Remote adapter '{}' reachable at {}", self.name, self.url);
+                }
+                snapshot.tools = tools;
+                snapshot.status = Some(status);
+                snapshot.last_seen = Some(Instant::now());
+            }
+            Err(e) => {
+                let message = format!("{:#}", e);
+                if snapshot.error.as_ref() != Some(&message) {
+                    warn!("Remote adapter '{}' unreachable: {}", self.name, message);
+                }
+                self.client.reset_session();
+                snapshot.tools.clear();
+                snapshot.error = Some(message);
+            }
+        }
+        let names: Vec<String> = snapshot.tools.iter().map(|t| t.name.clone()).collect();
+        names != old_names
+    }
+
+    /// Tools from the last successful poll.
+    pub fn tools(&self) -> Vec<Tool> {
+        self.snapshot.lock().unwrap().tools.clone()
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        let snapshot = self.snapshot.lock().unwrap();
+        snapshot.last_seen.is_some() && snapshot.error.is_none()
+    }
+
+    /// Reachability and the remote's own `/status`, as of the last poll.
+    pub fn status(&self) -> Value {
+        let snapshot = self.snapshot.lock().unwrap();
+        serde_json::json!({
+            "name": self.name,
+            "url": self.url,
+            "reachable": snapshot.last_seen.is_some() && snapshot.error.is_none(),
+            "error": snapshot.error,
+            "last_seen_secs": snapshot.last_seen.map(|t| t.elapsed().as_secs()),
+            "tools": snapshot.tools.len(),
+            "status": snapshot.status
+        })
+    }
+
+    /// Forward a `tools/call` by the remote's own tool name. Errors from the
+    /// remote are passed through unchanged.
+    pub async fn call_tool(&self, name: &str, arguments: &Value) -> Result<Value, McpError> {
+        match self.client.call_tool(name, arguments).await {
+            Ok(result) => Ok(result),
+            Err(e) => match e.downcast::<RpcError>() {
+                Ok(error) => {
+                    debug!(
+                        "Remote '{}' answered tools/call with {}",
+                        self.name, error.message
+                    );
+                    Err(McpError {
+                        code: error.code,
+                        message: error.message,
+                        data: error.data,
+                    })
+                }
+                Err(e) => Err(McpError {
+                    code: -32603,
+                    message: format!("Remote adapter '{}' unreachable: {:#}", self.name, e),
+                    data: Some(serde_json::json!({
+                        "remote": self.name,
+                        "url": self.url,
+                        "suggestion": "Check that the remote adapter is running and reachable"
+                    })),
+                }),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::SESSION_HEADER;
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Bytes;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+    use tokio::net::TcpListener;
+
+    /// A remote adapter with one tool, `getDistance`, returning 42.
+    async fn fake_remote() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<hyper::body::Incoming>| async move {
+                        let path = req.uri().path().to_string();
+                        let body = req.collect().await?.to_bytes();
+                        let request: Value = serde_json::from_slice(&body).unwrap_or_default();
+                        let mut result = match (path.as_str(), request["method"].as_str()) {
+                            ("/status", _) => serde_json::json!({"ready": true}),
+                            (_, Some("tools/list")) => serde_json::json!({"result": {"tools": [
+                                {"name": "getDistance", "description": "Distance",
+                                 "inputSchema": {"type": "object"}}
+                            ]}}),
+                            (_, Some("tools/call"))
+                                if request["params"]["name"] == "getDistance" =>
+                            {
+                                serde_json::json!({"result": {"content": [{"type": "text", "text": "42"}]}})
+                            }
+                            (_, Some("tools/call")) => serde_json::json!({
+                                "error": {"code": -32602, "message": "Function not found"}
+                            }),
+                            _ => serde_json::json!({"result": {}}),
+                        };
+                        if path == "/mcp" {
+                            result["id"] = request["id"].clone();
+                        }
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .header(SESSION_HEADER, "abc")
+                                .body(Full::new(Bytes::from(result.to_string())))
+                                .unwrap(),
+                        )
+                    });
+                    let io = hyper_util::rt::TokioIo::new(stream);
+                    let _ = http1::Builder::new().serve_connection(io, service).await;
+                });
+            }
+        });
+        url
+    }
+
+    fn remote(url: &str) -> RemoteAdapter {
+        RemoteAdapter::new(&RemoteSpec {
+            name: "pi-2".to_string(),
+            url: url.to_string(),
+            token: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_mounts_and_calls_remote_tools() {
+        let remote = remote(&fake_remote().await);
+        assert!(remote.refresh().await);
+        assert!(remote.is_reachable());
+        assert_eq!(remote.tools()[0].name, "getDistance");
+        assert_eq!(remote.client.session().as_deref(), Some("abc"));
+        assert_eq!(remote.status()["status"]["ready"], true);
+
+        let result = remote
+            .call_tool("getDistance", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result["content"][0]["text"], "42");
+        // The remote's own error comes back as it was
+        let error = remote
+            .call_tool("fly", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, -32602);
+
+        // Unchanged tools on the next poll
+        assert!(!remote.refresh().await);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_remote_has_no_tools() {
+        // Nothing listens on a port just released
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let remote = remote(&url);
+        assert!(!remote.refresh().await);
+        assert!(!remote.is_reachable());
+        assert!(remote.tools().is_empty());
+        assert!(remote.status()["error"].is_string());
+
+        let error = remote
+            .call_tool("getDistance", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("unreachable"), "{}", error.message);
+    }
+}
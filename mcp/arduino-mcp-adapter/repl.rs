@@ -0,0 +1,294 @@
+//! `repl` subcommand: an interactive prompt for calling device functions
+//! during firmware bring-up, with tab completion from the manifest.
+
+use anyhow::{anyhow, Result};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::{Map, Value};
+
+use crate::call;
+use crate::connection::ConnectionManager;
+use crate::manifest::{type_to_json_type, Function, ManifestManager};
+
+const BUILTINS: [&str; 4] = ["help", "list", "quit", "exit"];
+
+/// Completes function names and `param=` for the function being typed.
+struct ReplHelper {
+    functions: Vec<Function>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, words) = completions(&self.functions, &line[..pos]);
+        let pairs = words
+            .into_iter()
+            .map(|word| Pair {
+                display: word.clone(),
+                replacement: word,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Completion candidates for the word ending at the end of `line`, and the
+/// position where that word starts.
+fn completions(functions: &[Function], line: &str) -> (usize, Vec<String>) {
+    let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &line[start..];
+
+    let mut words = line[..start].split_whitespace();
+    let candidates: Vec<String> = match words.next() {
+        None => BUILTINS
+            .iter()
+            .map(|b| b.to_string())
+            .chain(functions.iter().map(|f| f.name.clone()))
+            .collect(),
+        Some(name) => {
+            let given: Vec<&str> = words.filter_map(|w| w.split('=').next()).collect();
+            functions
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| {
+                    f.params
+                        .iter()
+                        .filter(|p| !given.contains(&p.name.as_str()))
+                        .map(|p| format!("{}=", p.name))
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+    };
+
+    let matches = candidates
+        .into_iter()
+        .filter(|c| c.starts_with(word))
+        .collect();
+    (start, matches)
+}
+
+/// Parse `name arg=value ...`, `name value ...` (positional) or
+/// `name {"arg": value}` into a function and its JSON arguments.
+fn parse_call<'a>(functions: &'a [Function], line: &str) -> Result<(&'a Function, Value)> {
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    let func = functions
+        .iter()
+        .find(|f| f.name == name)
+        .ok_or_else(|| anyhow!("Unknown function '{}' - type 'list' to see them", name))?;
+
+    if rest.starts_with('{') {
+        return Ok((func, serde_json::from_str(rest)?));
+    }
+
+    let mut arguments = Map::new();
+    for (index, word) in rest.split_whitespace().enumerate() {
+        let (param, text) = match word.split_once('=') {
+            Some((key, text)) => (
+                func.params
+                    .iter()
+                    .find(|p| p.name == key)
+                    .ok_or_else(|| anyhow!("'{}' has no parameter '{}'", func.name, key))?,
+                text,
+            ),
+            None => (
+                func.params.get(index).ok_or_else(|| {
+                    anyhow!("'{}' takes {} argument(s)", func.name, func.params.len())
+                })?,
+                word,
+            ),
+        };
+
+        let value = if type_to_json_type(&param.param_type) == "string" {
+            serde_json::from_str::<String>(text)
+                .unwrap_or_else(|_| text.to_string())
+                .into()
+        } else {
+            // Leave unparseable values as strings for validation to report
+            serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+        };
+        arguments.insert(param.name.clone(), value);
+    }
+
+    Ok((func, Value::Object(arguments)))
+}
+
+fn signature(func: &Function) -> String {
+    let params: Vec<String> = func
+        .params
+        .iter()
+        .map(|p| format!("{}: {}", p.name, p.param_type))
+        .collect();
+    match &func.return_type {
+        Some(ret) => format!("{}({}) -> {}", func.name, params.join(", "), ret),
+        None => format!("{}({})", func.name, params.join(", ")),
+    }
+}
+
+/// Run the prompt until `quit`, ^D or ^C.
+pub async fn run(
+    connection_manager: &ConnectionManager,
+    manifest_manager: &ManifestManager,
+) -> Result<()> {
+    let device_id = call::connect(connection_manager).await?;
+    let manifest = manifest_manager.get_manifest(&device_id)?;
+    println!(
+        "Connected to {} ({} functions). Type 'help' for usage.",
+        device_id,
+        manifest.functions.len()
+    );
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper {
+        functions: manifest.functions,
+    }));
+
+    loop {
+        let line = match editor.readline(&format!("{}> ", device_id)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        match line {
+            "quit" | "exit" => break,
+            "help" => {
+                println!("  <function> arg=value ...   call with named arguments");
+                println!("  <function> value ...       call with positional arguments");
+                println!("  <function> {{\"arg\": value}}  call with a JSON object");
+                println!("  list                       show the device's functions");
+                println!("  quit                       leave (also ^D)");
+                continue;
+            }
+            _ => {}
+        }
+
+        // Reconnects after a reset and picks up a reflashed manifest
+        let manifest = match call::connect(connection_manager).await {
+            Ok(device_id) => manifest_manager.get_manifest(&device_id),
+            Err(e) => Err(e),
+        };
+        match manifest {
+            Ok(manifest) => {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.functions = manifest.functions;
+                }
+            }
+            Err(e) => {
+                println!("error: {}", e);
+                continue;
+            }
+        }
+        let functions = &editor.helper().expect("helper is set").functions;
+
+        if line == "list" {
+            for func in functions {
+                println!("  [{}] {} - {}", func.tag, signature(func), func.desc);
+            }
+            continue;
+        }
+
+        let call = parse_call(functions, line).and_then(|(func, arguments)| {
+            manifest_manager
+                .validate_function_arguments(func, &arguments)
+                .map_err(|e| anyhow!("Invalid arguments: {}", e))?;
+            Ok((func, arguments))
+        });
+        let result = match call {
+            Ok((func, arguments)) => connection_manager.execute_function(func, &arguments).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(text) => println!("{}", text),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn functions() -> Vec<Function> {
+        serde_json::from_value(json!([
+            {"tag": 1, "name": "setServo", "desc": "Move", "return": null,
+             "params": [{"name": "angle", "type": "i16"}, {"name": "speed", "type": "i16"}]},
+            {"tag": 2, "name": "setLabel", "desc": "Label", "return": null,
+             "params": [{"name": "text", "type": "CStr"}]},
+            {"tag": 3, "name": "getStatus", "desc": "Status", "return": "CStr", "params": []}
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_completes_function_names_then_unused_params() {
+        let functions = functions();
+        assert_eq!(
+            completions(&functions, "set"),
+            (0, vec!["setServo".to_string(), "setLabel".to_string()])
+        );
+        assert_eq!(
+            completions(&functions, "setServo "),
+            (9, vec!["angle=".to_string(), "speed=".to_string()])
+        );
+        assert_eq!(
+            completions(&functions, "setServo angle=90 s"),
+            (18, vec!["speed=".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parses_named_positional_and_json_arguments() {
+        let functions = functions();
+        for line in [
+            "setServo angle=90 speed=-5",
+            "setServo 90 -5",
+            r#"setServo {"angle": 90, "speed": -5}"#,
+        ] {
+            let (func, arguments) = parse_call(&functions, line).unwrap();
+            assert_eq!(func.name, "setServo");
+            assert_eq!(arguments, json!({"angle": 90, "speed": -5}));
+        }
+
+        let (_, arguments) = parse_call(&functions, "setLabel 42").unwrap();
+        assert_eq!(arguments, json!({"text": "42"}));
+        let (_, arguments) = parse_call(&functions, "setServo angle=far").unwrap();
+        assert_eq!(arguments, json!({"angle": "far"}));
+
+        assert!(parse_call(&functions, "fly").is_err());
+        assert!(parse_call(&functions, "getStatus 1").is_err());
+        assert!(parse_call(&functions, "setServo height=3").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+"""Warm interpreter for the adapter's Python pool.
+
+Reads scripts from stdin as `<byte length>\n<utf-8 source>` and answers each
+with one JSON line `{"stdout", "stderr", "status"}`. File descriptors 1 and 2
+point at per-run temp files while a script runs, so output from subprocesses
+is captured too; results go out on a private copy of the original stdout.
+"""
+
+import importlib
+import os
+import sys
+import tempfile
+import traceback
+
+# Modules the tools prelude imports, loaded once up front
+import inspect  # noqa: F401
+import json
+import socket  # noqa: F401
+import threading  # noqa: F401
+
+# Keep the protocol streams private so scripts can't read or corrupt them
+_requests = os.fdopen(os.dup(0), "rb")
+_results = os.fdopen(os.dup(1), "w", encoding="utf-8")
+_devnull = os.open(os.devnull, os.O_RDWR)
+os.dup2(_devnull, 0)
+os.dup2(_devnull, 1)
+os.dup2(_devnull, 2)
+
+
+def _run(source):
+    # Pick up packages installed into the venv since the last run
+    importlib.invalidate_caches()
+    with tempfile.TemporaryFile() as out, tempfile.TemporaryFile() as err:
+        os.dup2(out.fileno(), 1)
+        os.dup2(err.fileno(), 2)
+        status = 0
+        try:
+            exec(compile(source, "<script>", "exec"), {"__name__": "__main__"})
+        except SystemExit as exc:
+            if isinstance(exc.code, int):
+                status = exc.code
+            elif exc.code is not None:
+                print(exc.code, file=sys.stderr)
+                status = 1
+        except BaseException:
+            traceback.print_exc()
+            status = 1
+        finally:
+            sys.stdout.flush()
+            sys.stderr.flush()
+            os.dup2(_devnull, 1)
+            os.dup2(_devnull, 2)
+
+        out.seek(0)
+        err.seek(0)
+        return {
+            "stdout": out.read().decode("utf-8", errors="replace"),
+            "stderr": err.read().decode("utf-8", errors="replace"),
+            "status": status,
+        }
+
+
+def main():
+    while True:
+        header = _requests.readline()
+        if not header:
+            return
+        source = _requests.read(int(header)).decode("utf-8")
+        _results.write(json.dumps(_run(source)) + "\n")
+        _results.flush()
+
+
+main()
@@ -0,0 +1,231 @@
+//! Device-side responses: encoding return values, splitting them into
+//! chunks and sealing the frames they go out in. Shared by the simulator and
+//! the adapter's loopback device.
+
+use anyhow::{anyhow, Result};
+use tracing::debug;
+
+/// CRC-8 with polynomial 0x07 and initial value 0x00, as the firmware seals
+/// its frames.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if crc & 0x80 != 0 {
+                crc = (crc << 1) ^ 0x07;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Response data types
+pub enum ResponseData {
+    Void,
+    I16(i16),
+    I32(i32),
+    CStr(String),
+    /// Binary data sent as `[length: u16 LE] [bytes...]`, for `blob` and `image`
+    Blob(Vec<u8>),
+    /// Already-encoded data, e.g. the `getProtocolVersion` reply
+    Raw(Vec<u8>),
+}
+
+/// Encode a response frame: [data...] [crc]
+pub fn encode_response(response_data: &ResponseData) -> Result<Vec<u8>> {
+    let mut frame = Vec::new();
+
+    match response_data {
+        ResponseData::Void => {
+            // Empty response, just CRC
+        }
+        ResponseData::I16(value) => {
+            frame.extend_from_slice(&value.to_le_bytes());
+        }
+        ResponseData::I32(value) => {
+            frame.extend_from_slice(&value.to_le_bytes());
+        }
+        ResponseData::CStr(s) => {
+            frame.extend_from_slice(s.as_bytes());
+            frame.push(0); // Null terminator
+        }
+        ResponseData::Blob(data) => {
+            let len = u16::try_from(data.len()).map_err(|_| anyhow!("Blob too large"))?;
+            frame.extend_from_slice(&len.to_le_bytes());
+            frame.extend_from_slice(data);
+        }
+        ResponseData::Raw(data) => {
+            frame.extend_from_slice(data);
+        }
+    }
+
+    // Calculate and append CRC
+    let crc = crc8(&frame);
+    frame.push(crc);
+
+    debug!(
+        "Response encoded: {} bytes (CRC: 0x{:02X})",
+        frame.len(),
+        crc
+    );
+
+    Ok(frame)
+}
+
+/// Split response data into `[index] [total] [payload...]` chunk bodies,
+/// CRC not included. Empty data is still sent as one chunk.
+pub fn split_chunks(data: &[u8], max_payload: usize) -> Vec<Vec<u8>> {
+    let payloads: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(max_payload).collect()
+    };
+    debug_assert!(payloads.len() <= 255, "too many chunks");
+    let total = payloads.len() as u8;
+    payloads
+        .iter()
+        .enumerate()
+        .map(|(index, payload)| {
+            let mut chunk = vec![index as u8, total];
+            chunk.extend_from_slice(payload);
+            chunk
+        })
+        .collect()
+}
+
+/// `[seq] [data...] [crc]`, without the sequence byte if `seq` is `None`.
+pub fn seal(seq: Option<u8>, data: &[u8]) -> Vec<u8> {
+    let mut frame: Vec<u8> = seq.into_iter().collect();
+    frame.extend_from_slice(data);
+    frame.push(crc8(&frame));
+    frame
+}
+
+/// Turn a `[data...] [crc]` response into the frames to send, split into
+/// chunks that fit `max_frame_size` if `chunked`.
+pub fn package(
+    seq: Option<u8>,
+    response: &[u8],
+    chunked: bool,
+    max_frame_size: usize,
+) -> Vec<Vec<u8>> {
+    let data = &response[..response.len() - 1];
+    if !chunked {
+        return vec![seal(seq, data)];
+    }
+    // Index, total and CRC, plus the sequence byte if any
+    let max_payload = max_frame_size - 3 - usize::from(seq.is_some());
+    split_chunks(data, max_payload)
+        .iter()
+        .map(|chunk| seal(seq, chunk))
+        .collect()
+}
+
+/// Error frame: [seq] [0xFF] [error_code] [CRC]
+pub fn error_frame(seq: Option<u8>, error_code: u8) -> Vec<u8> {
+    seal(seq, &[0xFF, error_code])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::{hex, vectors};
+
+    #[test]
+    fn test_conformance_crc8() {
+        for v in vectors("crc8") {
+            assert_eq!(vec![crc8(&hex(&v["data"]))], hex(&v["crc"]), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_encode_void() {
+        let response = encode_response(&ResponseData::Void).unwrap();
+        assert_eq!(response.len(), 1); // Just CRC
+    }
+
+    #[test]
+    fn test_encode_i16() {
+        let response = encode_response(&ResponseData::I16(42)).unwrap();
+        assert_eq!(response.len(), 3); // 2 bytes + CRC
+        assert_eq!(response[0], 42); // Little-endian low byte
+        assert_eq!(response[1], 0); // Little-endian high byte
+    }
+
+    #[test]
+    fn test_encode_i32() {
+        let response = encode_response(&ResponseData::I32(1000)).unwrap();
+        assert_eq!(response.len(), 5); // 4 bytes + CRC
+        assert_eq!(response[0], 0xE8); // Little-endian: 1000 = 0x03E8
+        assert_eq!(response[1], 0x03);
+        assert_eq!(response[2], 0x00);
+        assert_eq!(response[3], 0x00);
+    }
+
+    #[test]
+    fn test_encode_cstr() {
+        let response = encode_response(&ResponseData::CStr("hello".to_string())).unwrap();
+        assert_eq!(response.len(), 7); // "hello" + null + CRC
+        assert_eq!(&response[0..5], b"hello");
+        assert_eq!(response[5], 0); // Null terminator
+    }
+
+    #[test]
+    fn test_encode_blob() {
+        let response = encode_response(&ResponseData::Blob(vec![0xAA, 0xBB, 0xCC])).unwrap();
+        assert_eq!(response.len(), 6); // length + 3 bytes + CRC
+        assert_eq!(&response[0..2], &[3, 0]);
+        assert_eq!(&response[2..5], &[0xAA, 0xBB, 0xCC]);
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let chunks = split_chunks(&[1, 2, 3, 4, 5], 2);
+        assert_eq!(
+            chunks,
+            vec![vec![0, 3, 1, 2], vec![1, 3, 3, 4], vec![2, 3, 5]]
+        );
+        assert_eq!(split_chunks(&[], 2), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn test_package_seals_each_chunk() {
+        let response = encode_response(&ResponseData::Raw(vec![1, 2, 3, 4, 5])).unwrap();
+        // Three bytes of payload fit beside the sequence byte
+        let frames = package(Some(9), &response, true, 7);
+        assert_eq!(
+            frames,
+            [
+                seal(Some(9), &[0, 2, 1, 2, 3]),
+                seal(Some(9), &[1, 2, 4, 5])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conformance_responses() {
+        for v in vectors("responses") {
+            let value = &v["value"];
+            let response = match v["return"].as_str() {
+                None => ResponseData::Void,
+                Some("i16") => ResponseData::I16(value.as_i64().unwrap() as i16),
+                Some("i32") => ResponseData::I32(value.as_i64().unwrap() as i32),
+                Some("blob") => ResponseData::Blob(hex(value)),
+                Some(_) => ResponseData::CStr(value.as_str().unwrap().to_string()),
+            };
+            assert_eq!(
+                encode_response(&response).unwrap(),
+                hex(&v["frame"]),
+                "{}",
+                v["name"]
+            );
+        }
+        for v in vectors("errors") {
+            let code = v["code"].as_u64().unwrap() as u8;
+            assert_eq!(error_frame(None, code), hex(&v["frame"]), "{}", v["name"]);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use serde_json::{Map, Value};
+
+use crate::manifest::{Manifest, Tool};
+
+/// Build an OpenAPI 3 document describing the REST facade for the given tools.
+pub fn openapi_document(manifest: Option<&Manifest>, tools: &[Tool]) -> Value {
+    let (title, version, description) = match manifest {
+        Some(m) => (
+            format!("{} (arduino-mcp-adapter)", m.name),
+            m.version.clone(),
+            m.description.clone(),
+        ),
+        None => (
+            "arduino-mcp-adapter".to_string(),
+            "0.1.0".to_string(),
+            "No robot identified yet - tool list is incomplete".to_string(),
+        ),
+    };
+
+    let mut paths = Map::new();
+    for tool in tools {
+        let query_parameters: Vec<Value> = tool.input_schema["properties"]
+            .as_object()
+            .map(|props| {
+                props
+                    .iter()
+                    .map(|(name, schema)| {
+                        serde_json::json!({
+                            "name": name,
+                            "in": "query",
+                            "required": is_required(&tool.input_schema, name),
+                            "schema": schema
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        paths.insert(
+            format!("/api/tools/{}", tool.name),
+            serde_json::json!({
+                "get": {
+                    "operationId": format!("{}Get", tool.name),
+                    "summary": tool.description,
+                    "parameters": query_parameters,
+                    "responses": standard_responses()
+                },
+                "post": {
+                    "operationId": tool.name,
+                    "summary": tool.description,
+                    "requestBody": {
+                        "required": false,
+                        "content": {
+                            "application/json": {
+                                "schema": tool.input_schema
+                            }
+                        }
+                    },
+                    "responses": standard_responses()
+                }
+            }),
+        );
+    }
+
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": title,
+            "version": version,
+            "description": description
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "ToolResult": {
+                    "type": "object",
+                    "properties": {
+                        "tool": {"type": "string"},
+                        "result": {"type": "string"}
+                    }
+                },
+                "ToolError": {
+                    "type": "object",
+                    "properties": {
+                        "error": {
+                            "type": "object",
+                            "properties": {
+                                "code": {"type": "integer"},
+                                "message": {"type": "string"},
+                                "data": {}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn standard_responses() -> Value {
+    let error = serde_json::json!({
+        "content": {
+            "application/json": {"schema": {"$ref": "#/components/schemas/ToolError"}}
+        }
+    });
+    serde_json::json!({
+        "200": {
+            "description": "Tool executed",
+            "content": {
+                "application/json": {"schema": {"$ref": "#/components/schemas/ToolResult"}}
+            }
+        },
+        "400": merge_description(&error, "Invalid arguments"),
+        "404": merge_description(&error, "Unknown tool"),
+        "500": merge_description(&error, "Execution error"),
+        "503": merge_description(&error, "Robot not ready")
+    })
+}
+
+fn merge_description(base: &Value, description: &str) -> Value {
+    let mut value = base.clone();
+    value["description"] = Value::String(description.to_string());
+    value
+}
+
+fn is_required(schema: &Value, name: &str) -> bool {
+    schema["required"]
+        .as_array()
+        .is_some_and(|required| required.iter().any(|r| r == name))
+}
+
+/// Flatten the text blocks of a `tools/call` result into a single string.
+/// Image blocks become `data:` URLs.
+pub fn result_text(result: &Value) -> String {
+    result["content"]
+        .as_array()
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter_map(|block| match block["type"].as_str() {
+                    Some("image") => Some(format!(
+                        "data:{};base64,{}",
+                        block["mimeType"].as_str()?,
+                        block["data"].as_str()?
+                    )),
+                    _ => block["text"].as_str().map(str::to_string),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default()
+}
+
+/// Convert a URL query string into a tool arguments object, using the tool's
+/// input schema to turn numeric and boolean parameters into JSON values.
+pub fn query_to_arguments(query: Option<&str>, tool: &Tool) -> Value {
+    let mut arguments = Map::new();
+
+    for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+        let (raw_key, raw_value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = percent_decode(raw_key, true);
+        let text = percent_decode(raw_value, true);
+
+        let value = match tool.input_schema["properties"][&key]["type"].as_str() {
+            Some("integer") => text
+                .parse::<i64>()
+                .map(Value::from)
+                .unwrap_or(Value::String(text)),
+            Some("number") => text
+                .parse::<f64>()
+                .ok()
+                .and_then(|n| serde_json::Number::from_f64(n).map(Value::Number))
+                .unwrap_or(Value::String(text)),
+            Some("boolean") => match text.as_str() {
+                "true" | "1" => Value::Bool(true),
+                "false" | "0" => Value::Bool(false),
+                _ => Value::String(text),
+            },
+            _ => Value::String(text),
+        };
+
+        arguments.insert(key, value);
+    }
+
+    Value::Object(arguments)
+}
+
+/// The tool name in a `/api/tools/{name}` path, which clients may encode.
+pub fn tool_name(path: &str) -> String {
+    percent_decode(&path["/api/tools/".len()..], false)
+}
+
+/// `input` with `%XX` escapes decoded, and `+` read as a space in a query.
+fn percent_decode(input: &str, plus_is_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_is_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let high = (bytes[i + 1] as char).to_digit(16).unwrap_or(0);
+                let low = (bytes[i + 2] as char).to_digit(16).unwrap_or(0);
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servo_tool() -> Tool {
+        Tool {
+            name: "setServo".to_string(),
+            description: "Move the servo".to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "angle": {"type": "integer"},
+                    "label": {"type": "string"}
+                },
+                "required": ["angle", "label"]
+            }),
+        }
+    }
+
+    #[test]
+    fn test_query_to_arguments_uses_schema_types() {
+        let args = query_to_arguments(Some("angle=90&label=hello%20world"), &servo_tool());
+        assert_eq!(
+            args,
+            serde_json::json!({"angle": 90, "label": "hello world"})
+        );
+    }
+
+    #[test]
+    fn test_tool_name_is_decoded() {
+        assert_eq!(tool_name("/api/tools/set%53ervo"), "setServo");
+        assert_eq!(tool_name("/api/tools/a+b"), "a+b");
+    }
+
+    #[test]
+    fn test_query_to_arguments_keeps_invalid_numbers_as_strings() {
+        let args = query_to_arguments(Some("angle=ninety"), &servo_tool());
+        assert_eq!(args, serde_json::json!({"angle": "ninety"}));
+    }
+
+    #[test]
+    fn test_openapi_document_lists_tool_paths() {
+        let doc = openapi_document(None, &[servo_tool()]);
+        let post = &doc["paths"]["/api/tools/setServo"]["post"];
+        assert_eq!(post["operationId"], "setServo");
+        assert_eq!(
+            post["requestBody"]["content"]["application/json"]["schema"]["required"],
+            serde_json::json!(["angle", "label"])
+        );
+    }
+}
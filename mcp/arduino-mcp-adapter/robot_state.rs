@@ -0,0 +1,344 @@
+//! The robot's connection state and what moves it on. [`RobotState::on`]
+//! is a pure function of the state and an [`Event`], so every transition
+//! can be tested without a device; the connection manager reports events
+//! and does the I/O.
+
+use serde::{Serialize, Serializer};
+
+use crate::messages;
+use crate::protocol::Fault;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RobotState {
+    Disconnected,  // No serial device found
+    Connecting,    // Found device, trying to connect
+    Connected,     // Connected but not identified
+    Initializing,  // Getting device ID
+    Ready(String), // Ready with device ID
+    Error(String), // Error state with description
+    /// Firmware was built from a different manifest version than the loaded one
+    VersionMismatch {
+        device_id: String,
+        firmware: String,
+        manifest: String,
+    },
+    /// The firmware stopped on a fault; calls that would move the robot are
+    /// refused until a client clears it
+    Fault {
+        device_id: String,
+        fault: Fault,
+    },
+}
+
+/// How a state is sent to clients: the same fields for every state, so
+/// they can be read without parsing a Rust `Debug` string.
+#[derive(Serialize)]
+struct Fields<'a> {
+    state: &'static str,
+    device_id: Option<&'a str>,
+    error: Option<&'a str>,
+    fault: Option<Fault>,
+    /// Manifest versions, for `version_mismatch`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    firmware: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest: Option<&'a str>,
+}
+
+impl Serialize for RobotState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut fields = Fields {
+            state: self.name(),
+            device_id: None,
+            error: None,
+            fault: None,
+            firmware: None,
+            manifest: None,
+        };
+        match self {
+            RobotState::Ready(device_id) => fields.device_id = Some(device_id),
+            RobotState::Error(error) => fields.error = Some(error),
+            RobotState::VersionMismatch {
+                device_id,
+                firmware,
+                manifest,
+            } => {
+                fields.device_id = Some(device_id);
+                fields.firmware = Some(firmware);
+                fields.manifest = Some(manifest);
+            }
+            RobotState::Fault { device_id, fault } => {
+                fields.device_id = Some(device_id);
+                fields.fault = Some(*fault);
+            }
+            _ => {}
+        }
+        fields.serialize(serializer)
+    }
+}
+
+/// Something that happened to the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The device is attached while there is no connection, or a failed
+    /// one, so a connection is attempted
+    DeviceAppeared,
+    /// The device is no longer attached
+    DeviceGone,
+    /// The port stopped taking writes, or was closed on shutdown
+    Closed,
+    PortOpened,
+    OpenFailed(String),
+    /// The handshake and identification began
+    HandshakeStarted,
+    /// `deviceId()` answered and the firmware matches the manifest
+    IdOk(String),
+    /// `deviceId()` answered but the firmware was built from another
+    /// manifest version
+    IdMismatch {
+        device_id: String,
+        firmware: String,
+        manifest: String,
+    },
+    /// The handshake or `deviceId()` failed
+    InitFailed(String),
+    /// The identified device restarted; `reopen` starts over on a fresh
+    /// connection rather than the open port
+    Reset {
+        reopen: bool,
+    },
+    /// A read from the port failed
+    IoError(String),
+    /// A call was answered by the bootloader, so the device reset
+    BootloaderReply(String),
+    /// The device reported a fault event
+    Fault(Fault),
+    /// A client called `clearFault`
+    FaultCleared,
+}
+
+impl RobotState {
+    pub fn is_ready(&self) -> bool {
+        matches!(self, RobotState::Ready(_))
+    }
+
+    /// Whether the robot takes calls; while faulted, only those that don't
+    /// move it are let through.
+    pub fn accepts_calls(&self) -> bool {
+        matches!(self, RobotState::Ready(_) | RobotState::Fault { .. })
+    }
+
+    /// The state's name in `snake_case`, without its details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RobotState::Disconnected => "disconnected",
+            RobotState::Connecting => "connecting",
+            RobotState::Connected => "connected",
+            RobotState::Initializing => "initializing",
+            RobotState::Ready(_) => "ready",
+            RobotState::Error(_) => "error",
+            RobotState::VersionMismatch { .. } => "version_mismatch",
+            RobotState::Fault { .. } => "fault",
+        }
+    }
+
+    pub fn device_id(&self) -> Option<&str> {
+        match self {
+            RobotState::Ready(id) | RobotState::Fault { device_id: id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Why the robot can or can't take calls, in English.
+    pub fn error_message(&self) -> String {
+        messages::english().state(self)
+    }
+
+    /// The state `event` leads to, or `None` if it doesn't apply here, such
+    /// as the answer to a handshake that a shutdown overtook.
+    pub fn on(&self, event: &Event) -> Option<RobotState> {
+        use RobotState::*;
+        let identified = matches!(
+            self,
+            Ready(_) | VersionMismatch { .. } | RobotState::Fault { .. }
+        );
+        let next = match event {
+            Event::DeviceAppeared if matches!(self, Disconnected | Error(_)) => Connecting,
+            Event::DeviceGone | Event::Closed if *self != Disconnected => Disconnected,
+            Event::PortOpened if *self == Connecting => Connected,
+            Event::OpenFailed(msg) if *self == Connecting => Error(msg.clone()),
+            Event::HandshakeStarted if matches!(self, Connected | Initializing) => Initializing,
+            // Checking the ID again doesn't clear a fault
+            Event::IdOk(id) if matches!(self, RobotState::Fault { device_id, .. } if device_id == id) => {
+                self.clone()
+            }
+            Event::IdOk(id) if *self == Initializing || identified => Ready(id.clone()),
+            Event::IdMismatch {
+                device_id,
+                firmware,
+                manifest,
+            } if *self == Initializing || identified => VersionMismatch {
+                device_id: device_id.clone(),
+                firmware: firmware.clone(),
+                manifest: manifest.clone(),
+            },
+            Event::InitFailed(msg) if *self == Initializing => Error(msg.clone()),
+            Event::Reset { reopen: true } if identified => Connecting,
+            Event::Reset { reopen: false } if identified => Initializing,
+            Event::IoError(msg) if *self != Disconnected => Error(msg.clone()),
+            Event::BootloaderReply(msg) if self.accepts_calls() => Error(msg.clone()),
+            Event::Fault(fault) => match self {
+                Ready(id) => RobotState::Fault {
+                    device_id: id.clone(),
+                    fault: *fault,
+                },
+                _ => return None,
+            },
+            Event::FaultCleared => match self {
+                RobotState::Fault { device_id, .. } => Ready(device_id.clone()),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states() -> [RobotState; 8] {
+        [
+            RobotState::Disconnected,
+            RobotState::Connecting,
+            RobotState::Connected,
+            RobotState::Initializing,
+            RobotState::Ready("arm".to_string()),
+            RobotState::Error("old".to_string()),
+            RobotState::VersionMismatch {
+                device_id: "arm".to_string(),
+                firmware: "v1".to_string(),
+                manifest: "v2".to_string(),
+            },
+            RobotState::Fault {
+                device_id: "arm".to_string(),
+                fault: Fault::Stall,
+            },
+        ]
+    }
+
+    /// One letter per state, in the order of [`states`].
+    fn letter(state: &RobotState) -> char {
+        match state {
+            RobotState::Disconnected => 'D',
+            RobotState::Connecting => 'C',
+            RobotState::Connected => 'O',
+            RobotState::Initializing => 'I',
+            RobotState::Ready(_) => 'R',
+            RobotState::Error(_) => 'E',
+            RobotState::VersionMismatch { .. } => 'V',
+            RobotState::Fault { .. } => 'F',
+        }
+    }
+
+    #[test]
+    fn test_fault_matrix() {
+        let mismatch = Event::IdMismatch {
+            device_id: "arm".to_string(),
+            firmware: "v1".to_string(),
+            manifest: "v3".to_string(),
+        };
+        // Where each event leads from D, C, O, I, R, E, V and F; `.` where
+        // it doesn't apply
+        let matrix = [
+            (Event::DeviceAppeared, "C....C.."),
+            (Event::DeviceGone, ".DDDDDDD"),
+            (Event::Closed, ".DDDDDDD"),
+            (Event::PortOpened, ".O......"),
+            (Event::OpenFailed("busy".to_string()), ".E......"),
+            (Event::HandshakeStarted, "..II...."),
+            (Event::IdOk("arm".to_string()), "...RR.RF"),
+            (Event::IdOk("rover".to_string()), "...RR.RR"),
+            (mismatch, "...VV.VV"),
+            (Event::InitFailed("timeout".to_string()), "...E...."),
+            (Event::Reset { reopen: true }, "....C.CC"),
+            (Event::Reset { reopen: false }, "....I.II"),
+            (Event::IoError("unplugged".to_string()), ".EEEEEEE"),
+            (Event::BootloaderReply("reset".to_string()), "....E..E"),
+            (Event::Fault(Fault::Thermal), "....F..."),
+            (Event::FaultCleared, ".......R"),
+        ];
+        for (event, row) in &matrix {
+            let outcome: String = states()
+                .iter()
+                .map(|state| state.on(event).as_ref().map_or('.', letter))
+                .collect();
+            assert_eq!(&outcome, row, "{:?}", event);
+        }
+    }
+
+    #[test]
+    fn test_serialized_as_fields() {
+        let fault = serde_json::to_value(&states()[7]).unwrap();
+        assert_eq!(
+            fault,
+            serde_json::json!({
+                "state": "fault",
+                "device_id": "arm",
+                "error": null,
+                "fault": "stall"
+            })
+        );
+        let mismatch = serde_json::to_value(&states()[6]).unwrap();
+        assert_eq!(mismatch["state"], "version_mismatch");
+        assert_eq!(
+            (&mismatch["firmware"], &mismatch["manifest"]),
+            (&"v1".into(), &"v2".into())
+        );
+        assert_eq!(serde_json::to_value(&states()[5]).unwrap()["error"], "old");
+    }
+
+    #[test]
+    fn test_events_carry_their_details() {
+        let connecting = RobotState::Connecting;
+        assert_eq!(
+            connecting.on(&Event::OpenFailed("busy".to_string())),
+            Some(RobotState::Error("busy".to_string()))
+        );
+        let ready = RobotState::Initializing.on(&Event::IdOk("rover".to_string()));
+        assert_eq!(ready, Some(RobotState::Ready("rover".to_string())));
+        assert_eq!(ready.unwrap().device_id(), Some("rover"));
+
+        // A fault keeps the first one reported, and the device ID
+        let fault = RobotState::Ready("arm".to_string())
+            .on(&Event::Fault(Fault::Overcurrent))
+            .unwrap();
+        assert_eq!(fault.on(&Event::Fault(Fault::Thermal)), None);
+        assert!(fault.accepts_calls() && !fault.is_ready());
+        assert_eq!(fault.device_id(), Some("arm"));
+        assert!(matches!(
+            fault,
+            RobotState::Fault {
+                fault: Fault::Overcurrent,
+                ..
+            }
+        ));
+
+        // A connection's life, start to finish
+        let mut state = RobotState::Disconnected;
+        for event in [
+            Event::DeviceAppeared,
+            Event::PortOpened,
+            Event::HandshakeStarted,
+            Event::IdOk("arm".to_string()),
+            Event::Reset { reopen: false },
+            Event::HandshakeStarted,
+            Event::IdOk("arm".to_string()),
+            Event::DeviceGone,
+        ] {
+            state = state.on(&event).unwrap();
+        }
+        assert_eq!(state, RobotState::Disconnected);
+    }
+}
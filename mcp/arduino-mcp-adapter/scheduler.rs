@@ -0,0 +1,266 @@
+//! Recurring tool calls: run a manifest function on a fixed interval and keep
+//! the results in the telemetry cache.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+use crate::connection::ConnectionManager;
+use crate::macros;
+use crate::manifest::ManifestManager;
+use crate::sequence;
+use crate::telemetry::{Sample, TelemetryCache};
+
+/// Shortest interval allowed, so schedules can't crowd out client calls.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Schedules allowed at once.
+pub const MAX_SCHEDULES: usize = 32;
+
+/// A schedule as written in `[[schedules]]` or passed to `scheduleTool`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleSpec {
+    pub name: String,
+    /// Manifest function to call
+    pub function: String,
+    #[serde(default = "sequence::empty_arguments")]
+    pub arguments: Value,
+    /// Interval such as `30s`, `5m` or `1h`
+    pub every: String,
+}
+
+/// A schedule and its recent results, as reported by `GET /telemetry`.
+#[derive(Debug, Serialize)]
+pub struct ScheduleReport {
+    #[serde(flatten)]
+    pub spec: ScheduleSpec,
+    pub samples: Vec<Sample>,
+}
+
+struct Running {
+    spec: ScheduleSpec,
+    task: JoinHandle<()>,
+}
+
+pub struct Scheduler {
+    connection_manager: Arc<ConnectionManager>,
+    manifest_manager: Arc<ManifestManager>,
+    telemetry: Arc<TelemetryCache>,
+    schedules: Mutex<BTreeMap<String, Running>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        connection_manager: Arc<ConnectionManager>,
+        manifest_manager: Arc<ManifestManager>,
+    ) -> Self {
+        Self {
+            connection_manager,
+            manifest_manager,
+            telemetry: Arc::new(TelemetryCache::new()),
+            schedules: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Start `spec`, replacing any schedule with the same name. Must be called
+    /// from within the Tokio runtime.
+    pub fn add(&self, spec: ScheduleSpec) -> Result<()> {
+        macros::check_name("schedule", &spec.name)?;
+        let interval = parse_interval(&spec.every)?;
+        if !spec.arguments.is_object() {
+            return Err(anyhow!("Schedule arguments must be an object"));
+        }
+
+        let mut schedules = self.schedules.lock().unwrap();
+        if schedules.len() >= MAX_SCHEDULES && !schedules.contains_key(&spec.name) {
+            return Err(anyhow!(
+                "At most {} schedules can run at once",
+                MAX_SCHEDULES
+            ));
+        }
+
+        info!(
+            "Scheduling '{}': {} every {}",
+            spec.name, spec.function, spec.every
+        );
+        let task = tokio::spawn(run(
+            spec.clone(),
+            interval,
+            Arc::clone(&self.connection_manager),
+            Arc::clone(&self.manifest_manager),
+            Arc::clone(&self.telemetry),
+        ));
+        // A replaced schedule's results stay, as the new one's history
+        if let Some(previous) = schedules.insert(spec.name.clone(), Running { spec, task }) {
+            previous.task.abort();
+        }
+        Ok(())
+    }
+
+    /// Returns whether the schedule existed.
+    pub fn remove(&self, name: &str) -> bool {
+        let Some(running) = self.schedules.lock().unwrap().remove(name) else {
+            return false;
+        };
+        running.task.abort();
+        self.telemetry.remove(name);
+        info!("Cancelled schedule '{}'", name);
+        true
+    }
+
+    /// Every schedule with its recent results, sorted by name.
+    pub fn report(&self) -> Vec<ScheduleReport> {
+        self.schedules
+            .lock()
+            .unwrap()
+            .values()
+            .map(|running| ScheduleReport {
+                spec: running.spec.clone(),
+                samples: self.telemetry.samples(&running.spec.name),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<ScheduleReport> {
+        let spec = self.schedules.lock().unwrap().get(name)?.spec.clone();
+        Some(ScheduleReport {
+            samples: self.telemetry.samples(name),
+            spec,
+        })
+    }
+
+    pub fn stop(&self) {
+        for (_, running) in std::mem::take(&mut *self.schedules.lock().unwrap()) {
+            running.task.abort();
+        }
+    }
+}
+
+async fn run(
+    spec: ScheduleSpec,
+    interval: Duration,
+    connection_manager: Arc<ConnectionManager>,
+    manifest_manager: Arc<ManifestManager>,
+    telemetry: Arc<TelemetryCache>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // A slow robot delays the next call instead of firing a burst after it
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let state = connection_manager.get_state();
+        let Some(device_id) = state.device_id() else {
+            debug!("Schedule '{}' skipped: robot not ready", spec.name);
+            continue;
+        };
+
+        let result = call(&spec, device_id, &connection_manager, &manifest_manager).await;
+        if let Err(e) = &result {
+            warn!("Schedule '{}' failed: {:#}", spec.name, e);
+        }
+        telemetry.record(&spec.name, result.map_err(|e| format!("{:#}", e)));
+    }
+}
+
+async fn call(
+    spec: &ScheduleSpec,
+    device_id: &str,
+    connection_manager: &ConnectionManager,
+    manifest_manager: &ManifestManager,
+) -> Result<String> {
+    // Looked up every time so a swapped robot or edited manifest is picked up
+    let manifest = manifest_manager.get_manifest(device_id)?;
+    let func = manifest
+        .functions
+        .iter()
+        .find(|f| f.name == spec.function)
+        .ok_or_else(|| anyhow!("Function not found: {}", spec.function))?;
+    manifest_manager.validate_function_arguments(func, &spec.arguments)?;
+    connection_manager
+        .execute_function(func, &spec.arguments)
+        .await
+}
+
+/// Parse `30s`, `5m` or `1h`.
+pub fn parse_interval(every: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid interval '{}' (use e.g. 30s, 5m or 1h)", every);
+    let every = every.trim();
+    let split = every
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (count, unit) = every.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => count,
+        "m" => count.saturating_mul(60),
+        "h" => count.saturating_mul(3600),
+        _ => return Err(invalid()),
+    };
+
+    let interval = Duration::from_secs(seconds);
+    if interval < MIN_INTERVAL {
+        return Err(anyhow!(
+            "Interval '{}' is shorter than {}s",
+            every,
+            MIN_INTERVAL.as_secs()
+        ));
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_interval("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("30").is_err());
+        assert!(parse_interval("s").is_err());
+        assert!(parse_interval("2d").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replacing_keeps_results() {
+        let scheduler = Scheduler::new(
+            Arc::new(ConnectionManager::new("/dev/ttyNONE", 115200).unwrap()),
+            Arc::new(ManifestManager::new(std::env::temp_dir())),
+        );
+        let spec = |every: &str| ScheduleSpec {
+            name: "battery".to_string(),
+            function: "getBatteryVoltage".to_string(),
+            arguments: sequence::empty_arguments(),
+            every: every.to_string(),
+        };
+        scheduler.add(spec("1m")).unwrap();
+        scheduler
+            .telemetry
+            .record("battery", Ok("7400".to_string()));
+
+        scheduler.add(spec("5m")).unwrap();
+        let report = scheduler.get("battery").unwrap();
+        assert_eq!(report.spec.every, "5m");
+        assert_eq!(report.samples.len(), 1);
+        let bad_name = ScheduleSpec {
+            name: "bad name".to_string(),
+            ..spec("1m")
+        };
+        let err = scheduler.add(bad_name).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Invalid schedule name"),
+            "{}",
+            err
+        );
+        scheduler.stop();
+    }
+}
@@ -0,0 +1,296 @@
+//! `selfTest` tool and `--self-test` mode: the diagnostic steps a manifest
+//! lists under `self_test`, such as reading the battery, wiggling a servo
+//! and checking a sensor, each reported as passed or failed.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::time::Duration;
+use tracing::info;
+
+use crate::connection::ConnectionManager;
+use crate::manifest::{Manifest, ManifestManager};
+use crate::sequence;
+
+/// ```json
+/// {"call": "readBattery", "desc": "Battery charged", "min": 6.5}
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SelfTestStep {
+    pub call: String,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub arguments: Map<String, Value>,
+    /// What the step checks, for the report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    /// Lowest result that passes; the result must then be a number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Highest result that passes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Pause after the call, e.g. for a servo to get there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<u64>,
+}
+
+/// Outcome of one step.
+#[derive(Debug, Serialize)]
+pub struct StepReport {
+    pub call: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub passed: usize,
+    pub failed: usize,
+    pub steps: Vec<StepReport>,
+}
+
+impl Report {
+    pub fn ok(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// One line per step, then the totals.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            let verdict = if step.passed { "PASS" } else { "FAIL" };
+            write!(
+                f,
+                "{} {}",
+                verdict,
+                step.desc.as_ref().unwrap_or(&step.call)
+            )?;
+            if let Some(result) = &step.result {
+                write!(f, ": {}", result)?;
+            }
+            if let Some(error) = &step.error {
+                write!(f, " ({})", error)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{} of {} steps passed", self.passed, self.steps.len())
+    }
+}
+
+/// Check the self-test of `manifest` when it is loaded, so a typo fails the
+/// manifest rather than the test before a class.
+pub fn check(manifest: &Manifest, manifest_manager: &ManifestManager) -> Result<()> {
+    for (index, step) in manifest.self_test.iter().enumerate() {
+        let Some(func) = manifest.functions.iter().find(|f| f.name == step.call) else {
+            return Err(anyhow!(
+                "self_test step {}: function '{}' is not in the manifest",
+                index + 1,
+                step.call
+            ));
+        };
+        manifest_manager
+            .validate_function_arguments(func, &Value::Object(step.arguments.clone()))
+            .map_err(|e| anyhow!("self_test step {}: {}", index + 1, e))?;
+        if let (Some(min), Some(max)) = (step.min, step.max) {
+            if min > max {
+                return Err(anyhow!(
+                    "self_test step {}: min {} is above max {}",
+                    index + 1,
+                    min,
+                    max
+                ));
+            }
+        }
+    }
+    let limit = sequence::MAX_TOTAL_DELAY.as_millis() as u64;
+    let total_delay = manifest
+        .self_test
+        .iter()
+        .filter_map(|s| s.delay_ms)
+        .try_fold(0u64, u64::checked_add);
+    match total_delay {
+        Some(total_delay) if total_delay <= limit => {}
+        Some(total_delay) => {
+            return Err(anyhow!(
+                "self_test delays add up to {} ms, over the {} ms limit",
+                total_delay,
+                limit
+            ))
+        }
+        None => {
+            return Err(anyhow!(
+                "self_test delays add up to more than the {} ms limit",
+                limit
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Whether every step calls a function `manifest` has, such as one cut down
+/// to its read-only functions.
+pub fn fits(steps: &[SelfTestStep], manifest: &Manifest) -> bool {
+    steps
+        .iter()
+        .all(|step| manifest.functions.iter().any(|f| f.name == step.call))
+}
+
+/// Run every step of the manifest's self-test, carrying on past failures so
+/// the report covers them all.
+pub async fn run(
+    connection_manager: &ConnectionManager,
+    manifest: &Manifest,
+    manifest_manager: &ManifestManager,
+) -> Report {
+    let mut steps = Vec::new();
+    for step in &manifest.self_test {
+        let outcome = run_step(connection_manager, manifest, manifest_manager, step).await;
+        let (result, error) = match outcome {
+            Ok(result) => {
+                let error = verdict(step, &result).err().map(|e| e.to_string());
+                (Some(result), error)
+            }
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        steps.push(StepReport {
+            call: step.call.clone(),
+            desc: step.desc.clone(),
+            passed: error.is_none(),
+            result,
+            error,
+        });
+        if let Some(ms) = step.delay_ms {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+        }
+    }
+    let passed = steps.iter().filter(|s| s.passed).count();
+    let report = Report {
+        passed,
+        failed: steps.len() - passed,
+        steps,
+    };
+    info!(
+        "Self-test of {}: {} of {} steps passed",
+        manifest.name,
+        report.passed,
+        report.steps.len()
+    );
+    report
+}
+
+async fn run_step(
+    connection_manager: &ConnectionManager,
+    manifest: &Manifest,
+    manifest_manager: &ManifestManager,
+    step: &SelfTestStep,
+) -> Result<String> {
+    let func = manifest
+        .functions
+        .iter()
+        .find(|f| f.name == step.call)
+        .ok_or_else(|| anyhow!("Function not found: {}", step.call))?;
+    let arguments = Value::Object(step.arguments.clone());
+    manifest_manager
+        .validate_function_arguments(func, &arguments)
+        .map_err(|e| anyhow!("Invalid arguments: {}", e))?;
+    connection_manager.execute_function(func, &arguments).await
+}
+
+/// Whether `result` is within the step's bounds, if it has any.
+fn verdict(step: &SelfTestStep, result: &str) -> Result<()> {
+    if step.min.is_none() && step.max.is_none() {
+        return Ok(());
+    }
+    let value: f64 = result
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("expected a number"))?;
+    if let Some(min) = step.min.filter(|&min| value < min) {
+        return Err(anyhow!("below the minimum of {}", min));
+    }
+    if let Some(max) = step.max.filter(|&max| value > max) {
+        return Err(anyhow!("above the maximum of {}", max));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, function};
+    use crate::loopback::LoopbackConnector;
+    use serde_json::json;
+
+    fn manifest(self_test: Value) -> Manifest {
+        fixtures::manifest_with(
+            json!([
+                function(1, "readBattery", Some("i16"), &[]),
+                function(2, "setServo", None, &[("angle", "i16")])
+            ]),
+            json!({"self_test": self_test}),
+        )
+    }
+
+    #[test]
+    fn test_check_rejects_bad_steps() {
+        let manager = ManifestManager::new(std::env::temp_dir());
+        let check = |self_test| check(&manifest(self_test), &manager);
+        check(json!([{"call": "setServo", "arguments": {"angle": 90}}])).unwrap();
+        let err = check(json!([{"call": "readSonar"}])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "self_test step 1: function 'readSonar' is not in the manifest"
+        );
+        assert!(check(json!([{"call": "readBattery", "min": 5, "max": 1}])).is_err());
+        assert!(check(json!([{"call": "readBattery", "delay_ms": 60_001}])).is_err());
+        let err = check(json!([
+            {"call": "readBattery", "delay_ms": u64::MAX},
+            {"call": "readBattery", "delay_ms": 1}
+        ]))
+        .unwrap_err();
+        assert!(err.to_string().contains("more than the"), "{}", err);
+        let err = check(json!([{"call": "setServo", "arguments": {"angle": "up"}}])).unwrap_err();
+        assert!(err.to_string().starts_with("self_test step 1: "), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_reports_every_step() {
+        let manifest = manifest(json!([
+            {"call": "readBattery", "desc": "Battery charged", "min": 650, "max": 900},
+            {"call": "readBattery", "min": 800},
+            {"call": "setServo", "arguments": {"angle": "up"}},
+            {"call": "setServo", "arguments": {"angle": 90}, "delay_ms": 10}
+        ]));
+        let device = fixtures::device(manifest.clone())
+            .respond("readBattery", 740i16.to_le_bytes().to_vec());
+        let connector = LoopbackConnector::new(device);
+        let connection_manager = ConnectionManager::with_connector(Box::new(connector.clone()));
+        crate::call::connect(&connection_manager).await.unwrap();
+        let manager = ManifestManager::new(std::env::temp_dir());
+
+        // A failing step doesn't stop the ones after it
+        let report = run(&connection_manager, &manifest, &manager).await;
+        assert_eq!((report.passed, report.failed), (2, 2));
+        assert!(!report.ok());
+        let passed: Vec<bool> = report.steps.iter().map(|s| s.passed).collect();
+        assert_eq!(passed, [true, false, false, true]);
+        assert_eq!(
+            report.steps[1].error.as_deref(),
+            Some("below the minimum of 800")
+        );
+        assert!(report.steps[2].result.is_none());
+        assert_eq!(connector.calls().last().unwrap().0, "setServo");
+
+        let text = report.to_string();
+        assert!(text.starts_with("PASS Battery charged: 740\n"), "{}", text);
+        assert!(text.ends_with("2 of 4 steps passed"), "{}", text);
+    }
+}
@@ -0,0 +1,211 @@
+//! `callSequence` tool: several device calls sent back to back, with no
+//! other command on the wire between them. A `wait` step only pauses.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::manifest::{Function, Manifest, ManifestManager};
+
+/// Steps allowed in one sequence.
+pub const MAX_STEPS: usize = 100;
+
+/// Upper bound for the sum of all `delay_ms`, since the serial queue is held
+/// for the whole sequence.
+pub const MAX_TOTAL_DELAY: Duration = Duration::from_secs(60);
+
+/// Built-in tool and step that pauses without sending anything.
+pub const WAIT: &str = "wait";
+/// Longest single `wait`.
+pub const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// A step as clients send it, and as macros store it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StepSpec {
+    pub name: String,
+    #[serde(default = "empty_arguments")]
+    pub arguments: Value,
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// Default for an `arguments` field left out.
+pub fn empty_arguments() -> Value {
+    Value::Object(Default::default())
+}
+
+/// One validated step.
+#[derive(Debug)]
+pub struct Step {
+    /// `None` for a `wait` step
+    pub func: Option<Function>,
+    pub arguments: Value,
+    /// Pause after this step before the next one
+    pub delay: Duration,
+}
+
+/// Outcome of one step, as returned to the client.
+#[derive(Debug, Serialize)]
+pub struct StepResult {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Parse and validate every step before anything is sent, so a typo in the
+/// last step doesn't leave the robot halfway through a move.
+pub fn parse_steps(
+    arguments: &Value,
+    manifest: &Manifest,
+    manifest_manager: &ManifestManager,
+) -> Result<Vec<Step>> {
+    let specs: Vec<StepSpec> = match arguments.get("steps") {
+        Some(steps) => serde_json::from_value(steps.clone()).map_err(|e| {
+            anyhow!(
+                "Parameter 'steps' must be an array of {{name, arguments, delay_ms}} objects: {}",
+                e
+            )
+        })?,
+        None => return Err(anyhow!("Missing required parameter 'steps'")),
+    };
+    if specs.is_empty() || specs.len() > MAX_STEPS {
+        return Err(anyhow!(
+            "Parameter 'steps' must hold between 1 and {} steps",
+            MAX_STEPS
+        ));
+    }
+
+    let steps = validate_steps(specs, manifest, manifest_manager)?;
+    let total_delay: Duration = steps.iter().map(|s| s.delay).sum();
+    if total_delay > MAX_TOTAL_DELAY {
+        return Err(anyhow!(
+            "Step delays and waits add up to {} ms, over the {} ms limit",
+            total_delay.as_millis(),
+            MAX_TOTAL_DELAY.as_millis()
+        ));
+    }
+    Ok(steps)
+}
+
+/// The `ms` argument of a `wait`.
+pub fn wait_duration(arguments: &Value) -> Result<Duration> {
+    let ms = arguments
+        .get("ms")
+        .ok_or_else(|| anyhow!("Missing required parameter 'ms'"))?;
+    match ms.as_u64() {
+        Some(ms) if ms <= MAX_WAIT.as_millis() as u64 => Ok(Duration::from_millis(ms)),
+        _ => Err(anyhow!(
+            "Parameter 'ms' must be a whole number of milliseconds up to {}",
+            MAX_WAIT.as_millis()
+        )),
+    }
+}
+
+/// Resolve each step's function and check its arguments. A `wait` step
+/// pauses for its `ms` plus its own `delay_ms`.
+pub fn validate_steps(
+    specs: Vec<StepSpec>,
+    manifest: &Manifest,
+    manifest_manager: &ManifestManager,
+) -> Result<Vec<Step>> {
+    specs
+        .into_iter()
+        .enumerate()
+        .map(|(index, step)| {
+            if step.name == WAIT {
+                let wait = wait_duration(&step.arguments)
+                    .map_err(|e| anyhow!("Step {} (wait): {}", index + 1, e))?;
+                return Ok(Step {
+                    func: None,
+                    arguments: step.arguments,
+                    delay: wait + Duration::from_millis(step.delay_ms),
+                });
+            }
+            let func = manifest
+                .functions
+                .iter()
+                .find(|f| f.name == step.name)
+                .ok_or_else(|| anyhow!("Step {}: function not found: {}", index + 1, step.name))?;
+            manifest_manager
+                .validate_function_arguments(func, &step.arguments)
+                .map_err(|e| {
+                    anyhow!(
+                        "Step {} ({}): invalid arguments: {}",
+                        index + 1,
+                        step.name,
+                        e
+                    )
+                })?;
+            Ok(Step {
+                func: Some(func.clone()),
+                arguments: step.arguments,
+                delay: Duration::from_millis(step.delay_ms),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, function};
+
+    fn parse(steps: Value) -> Result<Vec<Step>> {
+        let manifest = fixtures::manifest(serde_json::json!([function(
+            1,
+            "setServo",
+            None,
+            &[("angle", "i16")]
+        )]));
+        let manager = ManifestManager::new(std::env::temp_dir());
+        parse_steps(&serde_json::json!({ "steps": steps }), &manifest, &manager)
+    }
+
+    #[test]
+    fn test_parse_steps_validates_every_step_up_front() {
+        let steps = parse(serde_json::json!([
+            {"name": "setServo", "arguments": {"angle": 10}, "delay_ms": 250},
+            {"name": "setServo", "arguments": {"angle": 20}}
+        ]))
+        .unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].delay, Duration::from_millis(250));
+        assert!(steps[1].delay.is_zero());
+
+        let steps = parse(serde_json::json!([
+            {"name": "setServo", "arguments": {"angle": 10}},
+            {"name": "wait", "arguments": {"ms": 500}, "delay_ms": 100}
+        ]))
+        .unwrap();
+        assert!(steps[1].func.is_none());
+        assert_eq!(steps[1].delay, Duration::from_millis(600));
+        let err =
+            parse(serde_json::json!([{"name": "wait", "arguments": {"ms": -1}}])).unwrap_err();
+        assert!(err.to_string().starts_with("Step 1 (wait)"), "{}", err);
+
+        let err = parse(serde_json::json!([
+            {"name": "setServo", "arguments": {"angle": 10}},
+            {"name": "setServo", "arguments": {"angle": "up"}}
+        ]))
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Step 2 (setServo)"), "{}", err);
+
+        assert!(parse(serde_json::json!([{"name": "fly"}])).is_err());
+        assert!(parse(serde_json::json!([])).is_err());
+        assert!(parse(serde_json::json!([{"name": "setServo", "delay": 5}])).is_err());
+        for steps in [
+            serde_json::json!([{"name": "setServo", "arguments": {"angle": 1}, "delay_ms": 60_001}]),
+            serde_json::json!([
+                {"name": "wait", "arguments": {"ms": 60_000}},
+                {"name": "wait", "arguments": {"ms": 1}}
+            ]),
+        ] {
+            let err = parse(steps).unwrap_err();
+            assert!(err.to_string().contains("limit"), "{}", err);
+        }
+    }
+}
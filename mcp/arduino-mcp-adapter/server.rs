@@ -1,18 +1,56 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
-use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode};
+use mcp_client::hex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, OnceLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
-use tracing::{debug, error, info};
+use tokio::sync::{broadcast, Notify};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
+use crate::battery::Level;
+use crate::composite::Composite;
+use crate::config::Config;
 use crate::connection::ConnectionManager;
-use crate::manifest::{Manifest, ManifestManager, Tool};
+use crate::debounce::Debouncer;
+use crate::errors::{AdapterError, Kind};
+use crate::gamepad::{self, GamepadMapping};
+use crate::geofence::GeofenceViolation;
+use crate::http_server;
+use crate::macros::MacroStore;
+use crate::manifest::{
+    self, Ack, ArgumentError, Function, Manifest, ManifestManager, ManifestNotFound, Tool,
+};
+use crate::manifest_schema;
+use crate::messages::Messages;
+use crate::middleware::{self, Middleware, ToolCall};
+use crate::notifications::Notifier;
+use crate::plugins::Plugins;
+use crate::presets::Presets;
+use crate::protocol::{self, DeviceError};
+use crate::python_env::{self, PythonEnv};
+use crate::python_pool::PythonPool;
 use crate::python_runner;
+use crate::recorder::SessionRecorder;
+use crate::rest;
+use crate::robot_state::RobotState;
+use crate::scheduler::{ScheduleSpec, Scheduler};
+use crate::self_test;
+use crate::sequence::{self, Step, StepResult, StepSpec};
+use crate::sessions::{SessionInfo, Sessions, UnknownSession, SESSION_HEADER};
+use crate::stats::ToolStats;
+use crate::tool_bridge::ToolBridge;
+use crate::transcript::{Entry as TranscriptEntry, Transcript, TRANSCRIPT_CAPACITY};
+
+/// How long shutdown waits for running tool calls before closing the port.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct McpRequest {
@@ -22,6 +60,39 @@ pub struct McpRequest {
     pub params: Option<Value>,
 }
 
+/// `params` of a `tools/call` request.
+#[derive(Debug, Deserialize)]
+pub struct ToolCallParams {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+impl ToolCallParams {
+    /// Errors are `-32602` with a JSON pointer to the offending member,
+    /// e.g. `/params/name: invalid type: integer `3`, expected a string`.
+    pub fn parse(params: Option<&Value>) -> Result<Self, McpError> {
+        let params = params.cloned().unwrap_or_else(|| Value::Object(Map::new()));
+        serde_path_to_error::deserialize(params).map_err(|e| {
+            let mut pointer = "/params".to_string();
+            for segment in e.path().iter() {
+                if let serde_path_to_error::Segment::Map { key } = segment {
+                    pointer.push('/');
+                    pointer.push_str(&manifest::pointer_token(key));
+                }
+            }
+            McpError::invalid_params(pointer, e.into_inner())
+        })
+    }
+}
+
+/// Body of `POST /macros/record`.
+#[derive(Deserialize)]
+struct RecordRequest {
+    name: String,
+    count: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct McpResponse {
     pub jsonrpc: String,
@@ -30,95 +101,582 @@ pub struct McpResponse {
     pub error: Option<McpError>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct McpError {
     pub code: i32,
     pub message: String,
     pub data: Option<Value>,
 }
 
+impl McpResponse {
+    pub fn from_result(id: Option<Value>, result: Result<Value, McpError>) -> Self {
+        match result {
+            Ok(result) => Self {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => Self {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+impl McpError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// `-32602` for the member of the request at `pointer`, which is named
+    /// in the message and in `data.pointer`.
+    pub fn invalid_params(pointer: String, message: impl std::fmt::Display) -> Self {
+        Self {
+            code: -32602,
+            message: format!("{}: {}", pointer, message),
+            data: Some(serde_json::json!({ "pointer": pointer })),
+        }
+    }
+
+    /// `-32602` for arguments rejected by the manifest, pointing into
+    /// `/params/arguments` when the failing argument is known.
+    pub fn invalid_arguments(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<ArgumentError>() {
+            Some(e) => Self::invalid_params(format!("/params/arguments{}", e.pointer), &e.message),
+            None => Self::new(-32602, format!("Invalid arguments: {}", error)),
+        }
+    }
+
+    /// Set `data.kind`, keeping the rest of `data`.
+    pub fn with_kind(mut self, kind: Kind) -> Self {
+        let data = self.data.get_or_insert_with(|| serde_json::json!({}));
+        data["kind"] = kind.name().into();
+        self
+    }
+
+    /// The kind named in `data.kind`, if any.
+    pub fn kind(&self) -> Option<Kind> {
+        let kind = self.data.as_ref()?.get("kind")?;
+        serde_json::from_value(kind.clone()).ok()
+    }
+}
+
+/// Counts tool calls currently executing so shutdown can wait for them.
+struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+struct InFlightGuard<'a>(&'a InFlight);
+
+impl InFlight {
+    fn new() -> Self {
+        Self {
+            count: AtomicUsize::new(0),
+            idle: Notify::new(),
+        }
+    }
+
+    fn enter(&self) -> InFlightGuard<'_> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self)
+    }
+
+    /// Wait until no calls are running; returns `false` on timeout.
+    async fn wait_idle(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.count.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
 pub struct McpServer {
     connection_manager: Arc<ConnectionManager>,
     manifest_manager: Arc<ManifestManager>,
+    config: Arc<Config>,
+    /// Set once the tool bridge is listening
+    tool_socket: OnceLock<PathBuf>,
+    notifier: Arc<Notifier>,
+    in_flight: InFlight,
+    shutting_down: AtomicBool,
+    python_env: PythonEnv,
+    python_pool: PythonPool,
+    /// `python3 --version`, asked once for `/health`
+    python_version: tokio::sync::OnceCell<Option<String>>,
+    macros: MacroStore,
+    session_recorder: Option<SessionRecorder>,
+    scheduler: Scheduler,
+    sessions: Sessions,
+    tool_stats: ToolStats,
+    middleware: Vec<Arc<dyn Middleware>>,
+    /// What error messages clients see are worded in
+    messages: Messages,
+    /// Set once the plugins are loaded
+    plugins: OnceLock<Plugins>,
+    transcript: Arc<Transcript>,
+    /// Repeats of calls to functions with `debounce_ms`
+    debouncer: Debouncer<Result<Value, McpError>>,
 }
 
+/// Compare in time that depends only on the lengths, so how quickly a
+/// guess is refused doesn't tell how much of it was right.
+fn tokens_match(provided: &[u8], token: &[u8]) -> bool {
+    let mut diff = provided.len() ^ token.len();
+    for (i, &byte) in token.iter().enumerate() {
+        diff |= usize::from(byte ^ provided.get(i).copied().unwrap_or(!byte));
+    }
+    diff == 0
+}
+
+tokio::task_local! {
+    /// Set while a plugin tool runs: its robot calls can't run plugin tools,
+    /// which could wait on the plugin itself.
+    static IN_PLUGIN: ();
+}
+
+/// MCP resource with the `/stats` document.
+pub(crate) const STATS_URI: &str = "robot://stats";
+
 impl McpServer {
     pub fn new(
         connection_manager: Arc<ConnectionManager>,
         manifest_manager: Arc<ManifestManager>,
+        config: Arc<Config>,
     ) -> Self {
-        Self {
+        let python_env = PythonEnv::new(config.python_venv.clone());
+        let python_pool =
+            PythonPool::new(config.python_pool_size).with_interpreter(python_env.interpreter());
+        let macros = MacroStore::new(config.macro_dir());
+        let scheduler = Scheduler::new(
+            Arc::clone(&connection_manager),
+            Arc::clone(&manifest_manager),
+        );
+        let sessions = Sessions::new(config.max_clients, config.exclusive_control);
+        let presets = (!config.presets.is_empty()).then(|| Presets::new(&config.presets));
+        let server = Self {
             connection_manager,
             manifest_manager,
+            config,
+            tool_socket: OnceLock::new(),
+            notifier: Arc::new(Notifier::new()),
+            in_flight: InFlight::new(),
+            shutting_down: AtomicBool::new(false),
+            python_env,
+            python_pool,
+            python_version: tokio::sync::OnceCell::new(),
+            macros,
+            session_recorder: None,
+            scheduler,
+            sessions,
+            tool_stats: ToolStats::default(),
+            middleware: Vec::new(),
+            messages: Messages::default(),
+            plugins: OnceLock::new(),
+            transcript: Arc::new(Transcript::new(TRANSCRIPT_CAPACITY)),
+            debouncer: Debouncer::new(),
+        };
+        match presets {
+            // First, so other middleware and validation see the values
+            Some(presets) => server.with_middleware(Arc::new(presets)),
+            None => server,
         }
     }
 
-    pub async fn start(&self, port: u16) -> Result<()> {
-        let addr = format!("0.0.0.0:{}", port);
-        let base_url = Arc::new(format!("http://127.0.0.1:{}/mcp", port));
+    /// Run every tool call through `middleware`, after the middleware added
+    /// before it.
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Word error messages for clients from `messages`.
+    pub fn with_messages(mut self, messages: Messages) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Write every robot function call clients make to `recorder`.
+    pub fn with_session_recorder(mut self, recorder: SessionRecorder) -> Self {
+        self.session_recorder = Some(recorder);
+        self
+    }
+
+    /// Serve HTTP until `shutdown` completes, then drain in-flight tool calls,
+    /// put the robot into its safe state and close the serial port.
+    pub async fn start(self: Arc<Self>, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let addr = format!("0.0.0.0:{}", self.config.port);
         let listener = TcpListener::bind(&addr).await?;
         info!("MCP HTTP server listening on {}", addr);
 
-        // Start connection monitoring in background
-        let connection_manager = Arc::clone(&self.connection_manager);
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
-            loop {
-                interval.tick().await;
-                if let Err(e) = connection_manager.check_and_update_connection() {
-                    error!("Connection check error: {}", e);
-                }
-            }
-        });
+        self.start_background().await?;
+        self.python_version().await;
+
+        tokio::pin!(shutdown);
 
         loop {
-            let (stream, _) = listener.accept().await?;
-            let connection_manager = Arc::clone(&self.connection_manager);
-            let manifest_manager = Arc::clone(&self.manifest_manager);
-            let base_url = Arc::clone(&base_url);
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => break,
+            };
+            let server = Arc::clone(&self);
 
             tokio::spawn(async move {
                 let io = hyper_util::rt::TokioIo::new(stream);
-                if let Err(err) = http1::Builder::new()
+                let service_server = Arc::clone(&server);
+                if let Err(err) = http_server::builder(&server.config.http)
                     .serve_connection(
                         io,
                         service_fn(move |req| {
-                            Self::handle_request(
-                                req,
-                                Arc::clone(&connection_manager),
-                                Arc::clone(&manifest_manager),
-                                Arc::clone(&base_url),
-                            )
+                            let server = Arc::clone(&service_server);
+                            async move { server.handle_request(req, peer).await }
                         }),
                     )
                     .await
                 {
                     error!("Connection error: {}", err);
                 }
+                server.connection_closed(peer);
             });
         }
+
+        drop(listener);
+        self.shutdown().await;
+        Ok(())
+    }
+
+    /// Start connection monitoring, the watchers, the tool bridge, configured
+    /// schedules, the gamepad, plugins and the Python pool.
+    pub(crate) async fn start_background(self: &Arc<Self>) -> Result<()> {
+        // Start connection monitoring in background
+        let connection_manager = Arc::clone(&self.connection_manager);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = connection_manager.recovery_requested() => {}
+                }
+                if let Err(e) = connection_manager.check_and_update_connection().await {
+                    error!("Connection check error: {}", e);
+                }
+            }
+        });
+
+        tokio::spawn(Arc::clone(&self.connection_manager).run_watchdog());
+        tokio::spawn(Arc::clone(&self.connection_manager).run_battery_monitor());
+
+        self.spawn_device_watcher();
+        self.spawn_reset_watcher();
+        self.spawn_battery_watcher();
+        self.spawn_manifest_watcher();
+        self.spawn_transcript_recorder();
+        self.start_tool_bridge()?;
+
+        for spec in &self.config.schedules {
+            self.scheduler
+                .add(spec.clone())
+                .with_context(|| format!("Invalid schedule '{}' in config", spec.name))?;
+        }
+
+        if let Some(path) = &self.config.gamepad {
+            gamepad::spawn(Arc::clone(self), GamepadMapping::load(path)?)?;
+            info!("Gamepad teleoperation mapped by {}", path.display());
+        }
+
+        if let Some(dir) = &self.config.plugin_dir {
+            let plugins = Plugins::load(dir, Arc::downgrade(self)).await?;
+            let _ = self.plugins.set(plugins);
+        }
+
+        self.python_env.prepare().await?;
+        if self.python_pool.size() > 0 {
+            self.python_pool.fill();
+            info!(
+                "Python pool: {} warm interpreter(s)",
+                self.python_pool.size()
+            );
+        }
+        Ok(())
+    }
+
+    /// End the session tied to a closed HTTP connection, if any.
+    pub(crate) fn connection_closed(&self, peer: SocketAddr) {
+        self.sessions.close_connection(peer);
+    }
+
+    pub(crate) fn notifier(&self) -> &Arc<Notifier> {
+        &self.notifier
+    }
+
+    /// Listen on the Unix socket `runPythonScript` children call tools through.
+    fn start_tool_bridge(self: &Arc<Self>) -> Result<()> {
+        let bridge = ToolBridge::bind()?;
+        debug!("Tool socket at {}", bridge.path().display());
+        let _ = self.tool_socket.set(bridge.path());
+        tokio::spawn(bridge.serve(Arc::clone(self)));
+        Ok(())
+    }
+
+    pub(crate) async fn shutdown(&self) {
+        info!("Shutting down: stopped accepting HTTP connections");
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.scheduler.stop();
+
+        if !self.in_flight.wait_idle(SHUTDOWN_DRAIN_TIMEOUT).await {
+            warn!(
+                "{} tool call(s) still running after {:?}, continuing shutdown",
+                self.in_flight.count.load(Ordering::SeqCst),
+                SHUTDOWN_DRAIN_TIMEOUT
+            );
+        }
+
+        self.connection_manager.shutdown().await;
+        info!("Shutdown complete");
+    }
+
+    /// Follow robot state transitions: tell clients about each one, so UIs
+    /// needn't poll `/status`, and have a swapped or re-flashed robot's
+    /// manifest reloaded and the tool list re-fetched.
+    fn spawn_device_watcher(&self) {
+        let mut states = self.connection_manager.subscribe();
+        let manifest_manager = Arc::clone(&self.manifest_manager);
+        let notifier = Arc::clone(&self.notifier);
+        let messages = self.messages.clone();
+        let mut previous = self.connection_manager.get_state();
+        let mut announced = previous.device_id().map(str::to_string);
+
+        tokio::spawn(async move {
+            loop {
+                let state = match states.recv().await {
+                    Ok(state) => state,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Device watcher missed {} state transitions", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                notifier.notify(
+                    "notifications/robot_state_changed",
+                    Some(serde_json::json!({
+                        "state": state,
+                        "previous": previous,
+                        "ready": state.is_ready(),
+                        "message": messages.state(&state),
+                    })),
+                );
+                if matches!(state, RobotState::Fault { .. }) {
+                    // Clients that ignore state changes still learn why
+                    // their calls are refused
+                    notifier.notify(
+                        "notifications/message",
+                        Some(serde_json::json!({
+                            "level": "error",
+                            "logger": "robot",
+                            "data": messages.state(&state),
+                        })),
+                    );
+                }
+                previous = state.clone();
+
+                let ready_id = state.device_id().map(str::to_string);
+                if ready_id == announced {
+                    continue;
+                }
+
+                match &ready_id {
+                    Some(device_id) => {
+                        // The same ID may come back with new firmware, so never
+                        // trust a manifest cached before the disconnect
+                        manifest_manager.invalidate(device_id);
+                        info!("Device '{}' ready, tool list changed", device_id);
+                    }
+                    None => info!("Device no longer ready, tool list changed"),
+                }
+
+                announced = ready_id;
+                notifier.notify("notifications/tools/list_changed", None);
+            }
+        });
+    }
+
+    /// Keep the serial frames and notifications for `/transcript`.
+    fn spawn_transcript_recorder(&self) {
+        self.transcript.spawn_recorder(
+            self.connection_manager.frame_log().subscribe(),
+            self.notifier.subscribe(),
+        );
+    }
+
+    /// Warn clients when the robot restarts mid-session, since whatever
+    /// they had it doing stopped and its settings are back to defaults.
+    fn spawn_reset_watcher(&self) {
+        let mut resets = self.connection_manager.subscribe_resets();
+        let notifier = Arc::clone(&self.notifier);
+
+        tokio::spawn(async move {
+            loop {
+                let reason = match resets.recv().await {
+                    Ok(reason) => reason,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                notifier.notify(
+                    "notifications/message",
+                    Some(serde_json::json!({
+                        "level": "warning",
+                        "logger": "robot",
+                        "data": format!(
+                            "{}; the robot was reinitialized, so any motion or setting from before is lost",
+                            reason
+                        )
+                    })),
+                );
+            }
+        });
+    }
+
+    /// Tell clients when the battery runs low or critical, and when it's
+    /// charged again.
+    fn spawn_battery_watcher(&self) {
+        let mut changes = self.connection_manager.subscribe_battery();
+        let notifier = Arc::clone(&self.notifier);
+        let lockout = self.config.battery_lockout;
+
+        tokio::spawn(async move {
+            loop {
+                let status = match changes.recv().await {
+                    Ok(status) => status,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let (level, text) = match status.level {
+                    Level::Ok => ("info", "Battery charged again"),
+                    Level::Low => ("warning", "Battery low; charge it soon"),
+                    Level::Critical if lockout => (
+                        "error",
+                        "Battery critical; calls that change the robot are refused until it is charged",
+                    ),
+                    Level::Critical => ("error", "Battery critical; charge it now"),
+                };
+                notifier.notify(
+                    "notifications/message",
+                    Some(serde_json::json!({
+                        "level": level,
+                        "logger": "battery",
+                        "data": format!("{} (reading {})", text, status.voltage)
+                    })),
+                );
+            }
+        });
+    }
+
+    /// Poll the active device's manifest file so edits on disk reach clients
+    /// without reconnecting the robot.
+    fn spawn_manifest_watcher(&self) {
+        let connection_manager = Arc::clone(&self.connection_manager);
+        let manifest_manager = Arc::clone(&self.manifest_manager);
+        let notifier = Arc::clone(&self.notifier);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(2));
+            // Device served generic tools for want of a manifest file
+            let mut missing: Option<String> = None;
+            loop {
+                interval.tick().await;
+                if let Some(device_id) = connection_manager.get_state().device_id() {
+                    let found = manifest_manager.manifest_path(device_id).exists();
+                    let added = found && missing.as_deref() == Some(device_id);
+                    missing = (!found).then(|| device_id.to_string());
+                    if manifest_manager.refresh_if_changed(device_id) || added {
+                        notifier.notify("notifications/tools/list_changed", None);
+                    }
+                }
+            }
+        });
     }
 
-    async fn handle_request(
+    pub(crate) async fn handle_request(
+        &self,
         req: Request<hyper::body::Incoming>,
-        connection_manager: Arc<ConnectionManager>,
-        manifest_manager: Arc<ManifestManager>,
-        base_url: Arc<String>,
+        peer: SocketAddr,
+    ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
+        let span = info_span!("http_request", method = %req.method(), path = req.uri().path());
+        self.route_request(req, peer).instrument(span).await
+    }
+
+    async fn route_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        peer: SocketAddr,
     ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
-        let response = match req.method() {
-            &Method::POST => match req.uri().path() {
-                "/mcp" => {
-                    Self::handle_mcp_post(req, connection_manager, manifest_manager, base_url).await
+        let authorized = match &self.config.auth.token {
+            Some(token) => Self::is_authorized(&req, token),
+            None => true,
+        };
+        let exempt = req.method() == Method::OPTIONS || req.uri().path() == "/health";
+        if !exempt && !authorized {
+            return Ok(Self::unauthorized_response());
+        }
+
+        let response = match *req.method() {
+            Method::POST => match req.uri().path() {
+                "/mcp" => self.handle_mcp_post(req, peer).await,
+                "/status" => self.handle_status().await,
+                "/macros/record" => self.handle_macro_record(req).await,
+                path if path.starts_with("/api/tools/") => self.handle_rest_tool(req, peer).await,
+                _ => Ok(Self::not_found_response()),
+            },
+            Method::GET => match req.uri().path() {
+                // Standalone SSE stream for server-initiated notifications
+                "/mcp" => Ok(self.sse_stream_response(StatusCode::OK)),
+                "/status" => self.handle_status().await,
+                "/health" => Ok(self.handle_health(authorized).await),
+                "/openapi.json" => Ok(self.handle_openapi()),
+                "/manifest.schema.json" => {
+                    Ok(Self::json_response(manifest_schema::SCHEMA.to_string()))
                 }
-                "/status" => Self::handle_status(connection_manager).await,
+                "/debug/frames" => Ok(self.handle_debug_frames(req.uri().query())),
+                "/transcript" => Ok(self.handle_transcript(req.headers())),
+                "/api/tools" => Ok(self.handle_rest_tools_list()),
+                "/macros" => Ok(self.handle_macros_list()),
+                "/telemetry" => Ok(self.handle_telemetry()),
+                "/stats" => Ok(Self::json_response(self.tool_stats().to_string())),
+                path if path.starts_with("/api/tools/") => self.handle_rest_tool(req, peer).await,
+                path if path.starts_with("/macros/") => Ok(self.handle_macro_get(path)),
                 _ => Ok(Self::not_found_response()),
             },
-            &Method::GET => match req.uri().path() {
-                "/status" => Self::handle_status(connection_manager).await,
-                "/health" => Ok(Self::health_response()),
+            Method::DELETE => match req.uri().path() {
+                "/mcp" => Ok(self.handle_session_delete(&req)),
+                "/macros/record" => Ok(self.handle_macro_cancel()),
+                path if path.starts_with("/macros/") => Ok(self.handle_macro_delete(path)),
                 _ => Ok(Self::not_found_response()),
             },
-            &Method::OPTIONS => Ok(Self::cors_response()),
+            Method::OPTIONS => Ok(Self::cors_response()),
             _ => Ok(Self::not_found_response()),
         };
 
@@ -126,10 +684,9 @@ impl McpServer {
     }
 
     async fn handle_mcp_post(
+        &self,
         req: Request<hyper::body::Incoming>,
-        connection_manager: Arc<ConnectionManager>,
-        manifest_manager: Arc<ManifestManager>,
-        base_url: Arc<String>,
+        peer: SocketAddr,
     ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
         let headers = req.headers().clone();
         let body_bytes = req.collect().await?.to_bytes();
@@ -149,6 +706,43 @@ impl McpServer {
             }
         };
 
+        let session = match request.method.as_str() {
+            "initialize" => {
+                let client = request
+                    .params
+                    .as_ref()
+                    .and_then(|params| params["clientInfo"]["name"].as_str())
+                    .map(str::to_string);
+                self.sessions.open(peer, client)
+            }
+            _ => {
+                let header = headers
+                    .get(SESSION_HEADER)
+                    .and_then(|value| value.to_str().ok());
+                self.sessions.resolve(header, peer)
+            }
+        };
+        let session = match session {
+            Ok(session) => session,
+            Err(e) if e.is::<UnknownSession>() => {
+                debug!("Refused unknown session from {}", peer);
+                let mut response = Self::error_response(-32000, &e.to_string());
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return Ok(response);
+            }
+            Err(e) => {
+                warn!("Refused client {}: {}", peer, e);
+                return Ok(Self::error_response(-32000, &e.to_string()));
+            }
+        };
+
+        if let Ok(message) = serde_json::from_str(&body_str) {
+            self.transcript.record(TranscriptEntry::Request {
+                session: session.clone(),
+                message,
+            });
+        }
+
         let response = match request.method.as_str() {
             "initialize" => Self::handle_initialize(&request).await,
             "notifications/initialized" => {
@@ -157,14 +751,27 @@ impl McpServer {
                 info!("Request headers: {:?}", headers);
 
                 // Return SSE stream that stays open
-                return Ok(Self::sse_stream_response());
+                return Ok(self.sse_stream_response(StatusCode::ACCEPTED));
             }
-            "tools/list" => {
-                Self::handle_tools_list(&request, &connection_manager, &manifest_manager).await
+            "tools/list" => self.handle_tools_list(&request).await,
+            "tools/call" => self.handle_tools_call(&request, &session).await,
+            "resources/list" => McpResponse::from_result(
+                request.id.clone(),
+                Ok(serde_json::json!({ "resources": [Self::stats_resource(STATS_URI)] })),
+            ),
+            "resources/read" => {
+                let result = match request.params.as_ref().map(|params| &params["uri"]) {
+                    Some(Value::String(uri)) if uri == STATS_URI => {
+                        Ok(Self::resource_contents(uri, &self.tool_stats()))
+                    }
+                    _ => Err(McpError::new(-32602, "Unknown resource")),
+                };
+                McpResponse::from_result(request.id.clone(), result)
             }
-            "tools/call" => {
-                Self::handle_tools_call(&request, &connection_manager, &manifest_manager, &base_url)
-                    .await
+            // Log messages (resets, faults, battery) are all warnings or
+            // errors and always sent, so the level isn't kept
+            "logging/setLevel" => {
+                McpResponse::from_result(request.id.clone(), Ok(serde_json::json!({})))
             }
             _ => McpResponse {
                 jsonrpc: "2.0".to_string(),
@@ -180,34 +787,105 @@ impl McpServer {
 
         let response_json = serde_json::to_string(&response).unwrap();
         debug!("Sending MCP response: {}", response_json);
+        self.transcript.record(TranscriptEntry::Response {
+            session: session.clone(),
+            message: serde_json::to_value(&response).unwrap(),
+        });
+
+        let mut http_response = Self::json_response(response_json);
+        if request.method == "initialize" {
+            info!("Client session {} started from {}", session, peer);
+            http_response
+                .headers_mut()
+                .insert(SESSION_HEADER, session.parse().unwrap());
+        }
+        Ok(http_response)
+    }
 
-        Ok(Self::json_response(response_json))
+    /// `GET /transcript`: the history of the caller's own session, named by
+    /// its `Mcp-Session-Id` header, as a file to download.
+    pub(crate) fn handle_transcript(
+        &self,
+        headers: &hyper::HeaderMap,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let Some(transcript) = headers
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|session| self.sessions.is_open(session))
+            .and_then(|session| self.transcript.export(session))
+        else {
+            return Self::not_found_response();
+        };
+        let mut response = Self::json_response(transcript.to_string());
+        let filename = format!(
+            "attachment; filename=\"transcript-{}.json\"",
+            transcript["session"].as_str().unwrap_or_default()
+        );
+        if let Ok(value) = filename.parse() {
+            response
+                .headers_mut()
+                .insert(hyper::header::CONTENT_DISPOSITION, value);
+        }
+        response
+    }
+
+    /// `DELETE /mcp`: the client is done with its session.
+    fn handle_session_delete(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let closed = req
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|id| self.sessions.close(id));
+        if !closed {
+            return Self::not_found_response();
+        }
+        let mut response = Self::json_response("{}".to_string());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        response
     }
 
     async fn handle_status(
-        connection_manager: Arc<ConnectionManager>,
+        &self,
     ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
-        let state = connection_manager.get_state();
+        Ok(Self::json_response(self.status().to_string()))
+    }
+
+    /// The `/status` document.
+    pub(crate) fn status(&self) -> Value {
+        let state = self.connection_manager.get_state();
 
-        let status = serde_json::json!({
+        serde_json::json!({
             "state": format!("{:?}", state),
             "message": state.error_message(),
             "device_id": state.device_id(),
-            "ready": state.is_ready()
-        });
-
-        Ok(Self::json_response(serde_json::to_string(&status).unwrap()))
+            "ready": state.is_ready(),
+            "protocol": self.connection_manager.protocol(),
+            "adapter_version": env!("CARGO_PKG_VERSION"),
+            "stats": self.connection_manager.stats(),
+            "latency": self.connection_manager.latency(),
+            "link": self.connection_manager.link(),
+            "pose": self.connection_manager.pose(),
+            "battery": self.connection_manager.battery(),
+            "sessions": self.sessions.list()
+        })
     }
 
-    async fn handle_initialize(_request: &McpRequest) -> McpResponse {
+    pub(crate) async fn handle_initialize(_request: &McpRequest) -> McpResponse {
         let result = serde_json::json!({
             "protocolVersion": "2024-11-05",
             "capabilities": {
-                "tools": {}
+                "tools": {
+                    "listChanged": true
+                },
+                "resources": {},
+                "logging": {}
             },
             "serverInfo": {
                 "name": "arduino-mcp-adapter",
-                "version": "0.1.0"
+                "version": env!("CARGO_PKG_VERSION")
             }
         });
 
@@ -219,22 +897,24 @@ impl McpServer {
         }
     }
 
-    async fn handle_tools_list(
-        _request: &McpRequest,
-        connection_manager: &Arc<ConnectionManager>,
-        manifest_manager: &Arc<ManifestManager>,
-    ) -> McpResponse {
-        let state = connection_manager.get_state();
+    async fn handle_tools_list(&self, _request: &McpRequest) -> McpResponse {
+        let state = self.connection_manager.get_state();
 
         match state.device_id() {
-            Some(device_id) => match manifest_manager.get_manifest(device_id) {
+            Some(device_id) => match self.client_manifest(device_id) {
                 Ok(manifest) => {
-                    let mut tools = manifest_manager.create_tools_list(&manifest);
-                    tools.push(Self::python_runner_tool());
+                    let mut tools = self.tools_for(&manifest);
+                    if self.sessions.exclusive() {
+                        tools.push(Self::take_control_tool());
+                        tools.push(Self::release_control_tool());
+                    }
 
-                    let result = serde_json::json!({
+                    let mut result = serde_json::json!({
                         "tools": tools
                     });
+                    if manifest.fallback {
+                        result["_status"] = self.missing_manifest_status(device_id);
+                    }
 
                     McpResponse {
                         jsonrpc: "2.0".to_string(),
@@ -249,7 +929,9 @@ impl McpServer {
                     result: None,
                     error: Some(McpError {
                         code: -32603,
-                        message: format!("Failed to load manifest: {}", e),
+                        message: self
+                            .messages
+                            .format("manifest_load_failed", &[("error", &e)]),
                         data: None,
                     }),
                 },
@@ -259,8 +941,8 @@ impl McpServer {
                 let result = serde_json::json!({
                     "tools": [],
                     "_status": {
-                        "robot_state": format!("{:?}", state),
-                        "message": state.error_message()
+                        "robot_state": state,
+                        "message": self.messages.state(&state)
                     }
                 });
 
@@ -274,377 +956,3338 @@ impl McpServer {
         }
     }
 
-    async fn handle_tools_call(
-        request: &McpRequest,
-        connection_manager: &Arc<ConnectionManager>,
-        manifest_manager: &Arc<ManifestManager>,
-        base_url: &Arc<String>,
-    ) -> McpResponse {
-        let params = match request.params.as_ref() {
-            Some(p) => p,
-            None => {
-                return McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32602,
-                        message: "Missing params".to_string(),
-                        data: None,
-                    }),
-                };
-            }
-        };
-
-        let tool_name = match params["name"].as_str() {
-            Some(name) => name,
-            None => {
-                return McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32602,
-                        message: "Missing tool name".to_string(),
-                        data: None,
-                    }),
-                };
-            }
+    async fn handle_tools_call(&self, request: &McpRequest, session: &str) -> McpResponse {
+        let params = match ToolCallParams::parse(request.params.as_ref()) {
+            Ok(params) => params,
+            Err(e) => return McpResponse::from_result(request.id.clone(), Err(e)),
         };
+        let tool_name = params.name.as_str();
 
-        let empty_args = serde_json::json!({});
-        let arguments = params.get("arguments").unwrap_or(&empty_args);
-
-        // Check robot state first
-        let state = connection_manager.get_state();
-        if !state.is_ready() {
-            return McpResponse {
-                jsonrpc: "2.0".to_string(),
-                id: request.id.clone(),
-                result: None,
-                error: Some(McpError {
-                    code: -32603,
-                    message: format!("Robot not ready: {}", state.error_message()),
-                    data: Some(serde_json::json!({
-                        "robot_state": format!("{:?}", state),
-                        "suggestion": "Check robot connection and try again"
-                    })),
-                }),
-            };
+        if let Some(result) = self.control_gate(tool_name, session) {
+            return McpResponse::from_result(request.id.clone(), result);
         }
 
-        let device_id = state.device_id().unwrap(); // Safe because state.is_ready()
+        let arguments = Value::Object(params.arguments);
+        let result = self.call_tool(tool_name, &arguments).await;
 
-        // Get manifest and find function
-        let manifest = match manifest_manager.get_manifest(device_id) {
-            Ok(m) => m,
-            Err(e) => {
-                return McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32603,
-                        message: format!("Failed to load manifest: {}", e),
-                        data: None,
-                    }),
-                };
-            }
-        };
+        McpResponse::from_result(request.id.clone(), result)
+    }
 
-        if tool_name == "runPythonScript" {
-            return Self::handle_run_python_script(request, arguments, &manifest, base_url).await;
+    /// With exclusive control, answer the control tools and refuse calls
+    /// from sessions other than the controller; `None` lets the call through.
+    fn control_gate(&self, tool_name: &str, session: &str) -> Option<Result<Value, McpError>> {
+        if !self.sessions.exclusive() {
+            return None;
         }
+        match tool_name {
+            "takeControl" => Some(Ok(self.take_control(session))),
+            "releaseControl" => Some(Ok(self.release_control(session))),
+            // Anyone may stop the robot
+            "emergencyStop" => None,
+            _ => self
+                .sessions
+                .check_control(session)
+                .err()
+                .map(|controller| Err(self.controlled_elsewhere(controller))),
+        }
+    }
 
-        let func = match manifest.functions.iter().find(|f| f.name == tool_name) {
-            Some(f) => f,
-            None => {
-                return McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32602,
-                        message: format!("Function not found: {}", tool_name),
-                        data: None,
-                    }),
-                };
-            }
+    /// A tool call arriving through the REST facade or a fleet's unified
+    /// `/mcp`, in the per-connection session of `peer`, so `--max-clients` and exclusive
+    /// control apply as on the robot's own endpoint.
+    pub(crate) async fn call_tool_from(
+        &self,
+        peer: SocketAddr,
+        tool_name: &str,
+        arguments: &Value,
+    ) -> Result<Value, McpError> {
+        let session = self.sessions.resolve(None, peer).map_err(|e| {
+            warn!("Refused client {}: {}", peer, e);
+            McpError::new(-32000, e.to_string())
+        })?;
+        if let Some(result) = self.control_gate(tool_name, &session) {
+            return result;
+        }
+        self.call_tool(tool_name, arguments).await
+    }
+
+    /// Execute a tool by name and return the MCP `tools/call` result object.
+    ///
+    /// Shared by the JSON-RPC endpoint and the REST facade so both surfaces
+    /// apply the same readiness checks, validation, and error codes.
+    pub(crate) async fn call_tool(
+        &self,
+        tool_name: &str,
+        arguments: &Value,
+    ) -> Result<Value, McpError> {
+        let started = Instant::now();
+        let call = ToolCall {
+            tool: tool_name.to_string(),
+            arguments: arguments.clone(),
         };
+        let (call, result) = middleware::run(&self.middleware, call, |call| async move {
+            self.dispatch_tool(&call.tool, &call.arguments).await
+        })
+        .instrument(info_span!("tool_call", tool = tool_name))
+        .await;
+        self.tool_stats
+            .record(&call.tool, started.elapsed(), result.is_ok());
+        result.map_err(|error| self.error_details(error, &call.tool, started.elapsed()))
+    }
 
-        // Validate arguments
-        if let Err(e) = manifest_manager.validate_function_arguments(func, arguments) {
-            return McpResponse {
-                jsonrpc: "2.0".to_string(),
-                id: request.id.clone(),
-                result: None,
-                error: Some(McpError {
-                    code: -32602,
-                    message: format!("Invalid arguments: {}", e),
-                    data: None,
-                }),
+    /// Complete a tool call error's `data` with its kind, whether a retry
+    /// may help, the robot's state, the function's tag and how long the
+    /// call took, and give it the code `[error_codes]` sets for its kind.
+    fn error_details(&self, mut error: McpError, tool_name: &str, latency: Duration) -> McpError {
+        let kind = error.kind().unwrap_or_else(|| Kind::from_code(error.code));
+        if let Some(&code) = self.config.error_codes.get(&kind) {
+            error.code = code;
+        }
+        let state = self.connection_manager.get_state();
+        let tag = state
+            .device_id()
+            .and_then(|id| self.manifest_manager.get_manifest(id).ok())
+            .and_then(|manifest| {
+                let func = manifest.functions.iter().find(|f| f.name == tool_name)?;
+                Some(func.tag)
+            });
+        let data = error.data.get_or_insert_with(|| serde_json::json!({}));
+        data["kind"] = kind.name().into();
+        data["retryable"] = kind.retryable().into();
+        if data.get("robot_state").is_none() {
+            data["robot_state"] = serde_json::json!(state);
+        }
+        data["tag"] = tag.into();
+        data["latency_ms"] = (latency.as_millis() as u64).into();
+        error
+    }
+
+    async fn dispatch_tool(&self, tool_name: &str, arguments: &Value) -> Result<Value, McpError> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(
+                McpError::new(-32603, self.messages.get("shutting_down")).with_kind(Kind::NotReady)
+            );
+        }
+        if tool_name == sequence::WAIT {
+            // Needs no robot, and shouldn't hold up shutdown
+            return Self::handle_wait(arguments).await;
+        }
+        let _in_flight = self.in_flight.enter();
+
+        // Check robot state first
+        let state = self.connection_manager.get_state();
+        if !state.accepts_calls() {
+            return Err(McpError {
+                code: Kind::NotReady.code(),
+                message: self.not_ready_message(&state),
+                data: Some(serde_json::json!({
+                    "kind": Kind::NotReady.name(),
+                    "robot_state": state,
+                    "suggestion": self.messages.get("suggest_check_connection")
+                })),
+            });
+        }
+
+        let device_id = state.device_id().unwrap(); // Safe because state.accepts_calls()
+
+        // Get manifest and find function
+        let manifest = self.client_manifest(device_id).map_err(|e| {
+            McpError::new(
+                -32603,
+                self.messages
+                    .format("manifest_load_failed", &[("error", &e)]),
+            )
+        })?;
+
+        if tool_name == "emergencyStop" && self.offers_emergency_stop(&manifest) {
+            // Never refused for a full queue, as it goes ahead of it
+            return match self.connection_manager.emergency_stop().await {
+                Ok(response_text) => Ok(Self::text_content(response_text)),
+                Err(e) => Err(self.execution_error(e)),
             };
         }
+        if tool_name == "clearFault" && self.offers_clear_fault() {
+            // Sends nothing, so a full queue doesn't matter
+            return self.handle_clear_fault();
+        }
+        if let Some(error) = self.queue_saturated() {
+            return Err(error);
+        }
 
-        // Execute the function
-        match connection_manager.execute_function(func, arguments) {
-            Ok(response_text) => {
-                let result = serde_json::json!({
-                    "content": [
-                        {
-                            "type": "text",
-                            "text": response_text
-                        }
-                    ]
-                });
+        if tool_name == "runPythonScript" {
+            return self.handle_run_python_script(arguments, &manifest).await;
+        }
+        if tool_name == "callSequence" {
+            return self.handle_call_sequence(arguments, &manifest).await;
+        }
+        if tool_name == "runMacro" {
+            return self.handle_run_macro(arguments, &manifest).await;
+        }
+        if tool_name == "awaitMotionComplete" && Self::has_motion(&manifest) {
+            return self.handle_await_motion(arguments).await;
+        }
+        if tool_name == "scheduleTool" {
+            return self.handle_schedule_tool(arguments, &manifest);
+        }
+        if tool_name == "rawCommand" && self.offers_raw_command() {
+            return self.handle_raw_command(arguments).await;
+        }
+        if tool_name == "selfTest" && !manifest.self_test.is_empty() {
+            return self.handle_self_test(&manifest).await;
+        }
 
-                McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: Some(result),
-                    error: None,
+        if let Some(composite) = manifest.composites.iter().find(|c| c.name == tool_name) {
+            return self.handle_composite(composite, arguments, &manifest).await;
+        }
+
+        let Some(func) = manifest.functions.iter().find(|f| f.name == tool_name) else {
+            if self.config.read_only {
+                return Err(McpError::new(
+                    -32602,
+                    self.messages
+                        .format("function_not_found_read_only", &[("name", &tool_name)]),
+                ));
+            }
+            return self.handle_plugin_tool(tool_name, arguments).await;
+        };
+
+        match func.debounce_ms {
+            Some(window) => {
+                let window = Duration::from_millis(window);
+                self.debouncer
+                    .run(&func.name, arguments, window, || {
+                        self.call_function(&manifest, func, arguments)
+                    })
+                    .await
+            }
+            None => self.call_function(&manifest, func, arguments).await,
+        }
+    }
+
+    /// Check the arguments for a manifest function, and send it.
+    async fn call_function(
+        &self,
+        manifest: &Manifest,
+        func: &Function,
+        arguments: &Value,
+    ) -> Result<Value, McpError> {
+        info_span!("validate")
+            .in_scope(|| {
+                self.manifest_manager
+                    .validate_function_arguments(func, arguments)
+            })
+            .map_err(McpError::invalid_arguments)?;
+
+        match self
+            .connection_manager
+            .execute_function(func, arguments)
+            .await
+        {
+            Ok(response_text) => {
+                self.record_call(manifest, &func.name, arguments);
+                match func.return_type.as_deref() {
+                    Some("image") => Ok(Self::image_content(response_text)),
+                    _ => Ok(Self::text_content(response_text)),
                 }
             }
-            Err(e) => McpResponse {
-                jsonrpc: "2.0".to_string(),
-                id: request.id.clone(),
-                result: None,
-                error: Some(McpError {
-                    code: -32603,
-                    message: format!("Execution error: {}", e),
-                    data: Some(serde_json::json!({
-                        "robot_state": format!("{:?}", connection_manager.get_state()),
-                        "suggestion": "Check robot connection and try again"
-                    })),
-                }),
-            },
+            Err(e) => Err(self.execution_error(e)),
         }
     }
 
     async fn handle_run_python_script(
-        request: &McpRequest,
+        &self,
         arguments: &Value,
         manifest: &Manifest,
-        base_url: &Arc<String>,
-    ) -> McpResponse {
-        let script_value = match arguments.get("script") {
-            Some(value) => value,
-            None => {
-                return McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32602,
-                        message: "Missing required parameter 'script' for runPythonScript"
-                            .to_string(),
-                        data: None,
-                    }),
-                };
-            }
-        };
+    ) -> Result<Value, McpError> {
+        let script_value = arguments
+            .get("script")
+            .ok_or_else(|| self.missing_parameter("script", "runPythonScript"))?;
 
-        let script = match script_value.as_str() {
-            Some(value) => value,
-            None => {
-                return McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32602,
-                        message: "Parameter 'script' must be a string".to_string(),
-                        data: None,
-                    }),
-                };
-            }
-        };
+        let script = script_value
+            .as_str()
+            .ok_or_else(|| McpError::new(-32602, self.messages.get("script_not_string")))?;
 
-        let timeout_secs = match arguments.get("timeout") {
-            Some(value) => {
-                if let Some(num) = value.as_u64() {
-                    if num == 0 {
-                        return McpResponse {
-                            jsonrpc: "2.0".to_string(),
-                            id: request.id.clone(),
-                            result: None,
-                            error: Some(McpError {
-                                code: -32602,
-                                message: "Parameter 'timeout' must be greater than 0 seconds"
-                                    .to_string(),
-                                data: None,
-                            }),
-                        };
-                    }
-                    if num > 300 {
-                        return McpResponse {
-                            jsonrpc: "2.0".to_string(),
-                            id: request.id.clone(),
-                            result: None,
-                            error: Some(McpError {
-                                code: -32602,
-                                message: "Parameter 'timeout' cannot exceed 300 seconds"
-                                    .to_string(),
-                                data: None,
-                            }),
-                        };
-                    }
-                    num
-                } else {
-                    return McpResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: request.id.clone(),
-                        result: None,
-                        error: Some(McpError {
-                            code: -32602,
-                            message: "Parameter 'timeout' must be an integer number of seconds"
-                                .to_string(),
-                            data: None,
-                        }),
-                    };
-                }
+        let timeout_duration = self.timeout_argument(arguments, 60)?;
+
+        let script_args = match arguments.get("args") {
+            Some(value @ Value::Object(_)) => value.clone(),
+            Some(_) => {
+                return Err(McpError::new(
+                    -32602,
+                    self.messages.get("script_args_not_object"),
+                ))
             }
-            None => 60,
+            None => Value::Object(Default::default()),
         };
 
-        let mut tool_names: Vec<String> =
-            manifest.functions.iter().map(|f| f.name.clone()).collect();
-        if !tool_names.iter().any(|name| name == "runPythonScript") {
-            tool_names.push("runPythonScript".to_string());
+        let requirements: Vec<String> = match arguments.get("requirements") {
+            Some(value) => serde_json::from_value(value.clone()).map_err(|_| {
+                McpError::new(-32602, self.messages.get("requirements_not_strings"))
+            })?,
+            None => Vec::new(),
+        };
+        if !requirements.is_empty() {
+            if self.python_env.venv().is_none() {
+                return Err(McpError::new(
+                    -32602,
+                    self.messages.get("requirements_need_venv"),
+                ));
+            }
+            for requirement in &requirements {
+                python_env::check_requirement(requirement)
+                    .map_err(|e| McpError::new(-32602, e.to_string()))?;
+            }
+            if let Err(err) = self.python_env.install(&requirements).await {
+                error!("runPythonScript requirements failed: {}", err);
+                return Err(McpError::new(
+                    -32603,
+                    self.messages
+                        .format("requirements_failed", &[("error", &format!("{:#}", err))]),
+                ));
+            }
         }
 
-        let timeout_duration = Duration::from_secs(timeout_secs);
+        let tool_socket = self
+            .tool_socket
+            .get()
+            .ok_or_else(|| McpError::new(-32603, self.messages.get("tool_socket_down")))?;
 
         match python_runner::run_python_script(
+            &self.python_pool,
             script,
+            &script_args,
             timeout_duration,
-            &tool_names,
-            base_url.as_str(),
+            &manifest.functions,
+            tool_socket,
         )
         .await
         {
-            Ok(output) => {
-                let result = serde_json::json!({
-                    "content": [
-                        {
-                            "type": "text",
-                            "text": output
-                        }
-                    ]
-                });
-
-                McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: Some(result),
-                    error: None,
-                }
-            }
+            Ok(output) => Ok(Self::text_content(output)),
             Err(err) => {
                 error!("runPythonScript failed: {}", err);
-                McpResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: request.id.clone(),
-                    result: None,
-                    error: Some(McpError {
-                        code: -32603,
-                        message: format!("Failed to execute Python script: {}", err),
-                        data: None,
-                    }),
-                }
+                Err(McpError::new(
+                    -32603,
+                    self.messages.format("script_failed", &[("error", &err)]),
+                ))
             }
         }
     }
 
-    fn python_runner_tool() -> Tool {
-        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
-        TOOL_CACHE
-            .get_or_init(|| {
-                serde_json::from_str(include_str!("resources/runPythonScript.json"))
-                    .expect("runPythonScript.json must deserialize to Tool")
-            })
-            .clone()
+    async fn handle_await_motion(&self, arguments: &Value) -> Result<Value, McpError> {
+        let timeout = self.timeout_argument(arguments, 30)?;
+        match self.connection_manager.await_motion(timeout).await {
+            Ok(waited) => Ok(Self::text_content(format!(
+                "Motion complete after {:.1}s",
+                waited.as_secs_f32()
+            ))),
+            Err(e) => Err(self.execution_error(e)),
+        }
     }
 
-    fn json_response(body: String) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
-        Response::builder()
-            .header("Content-Type", "application/json")
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
-            .header("Access-Control-Allow-Headers", "Content-Type")
-            .body(BoxBody::new(Full::new(body.into()).map_err(|e| match e {})))
-            .unwrap()
+    async fn handle_wait(arguments: &Value) -> Result<Value, McpError> {
+        let duration =
+            sequence::wait_duration(arguments).map_err(|e| McpError::new(-32602, e.to_string()))?;
+        tokio::time::sleep(duration).await;
+        Ok(Self::text_content(format!(
+            "Waited {} ms",
+            duration.as_millis()
+        )))
     }
 
-    fn cors_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
-        Response::builder()
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
-            .header("Access-Control-Allow-Headers", "Content-Type")
-            .body(BoxBody::new(Full::new("".into()).map_err(|e| match e {})))
-            .unwrap()
+    /// The optional `timeout` argument in whole seconds, 1 to 300.
+    fn timeout_argument(&self, arguments: &Value, default_secs: u64) -> Result<Duration, McpError> {
+        const MAX_SECS: u64 = 300;
+        let Some(value) = arguments.get("timeout") else {
+            return Ok(Duration::from_secs(default_secs));
+        };
+        match value.as_u64() {
+            Some(0) => Err(McpError::new(-32602, self.messages.get("timeout_zero"))),
+            Some(secs) if secs > MAX_SECS => Err(McpError::new(
+                -32602,
+                self.messages
+                    .format("timeout_too_long", &[("max", &MAX_SECS)]),
+            )),
+            Some(secs) => Ok(Duration::from_secs(secs)),
+            None => Err(McpError::new(
+                -32602,
+                self.messages.get("timeout_not_integer"),
+            )),
+        }
     }
 
-    fn not_found_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
-        Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(BoxBody::new(
-                Full::new("Not Found".into()).map_err(|e| match e {}),
-            ))
-            .unwrap()
+    async fn handle_call_sequence(
+        &self,
+        arguments: &Value,
+        manifest: &Manifest,
+    ) -> Result<Value, McpError> {
+        let steps = sequence::parse_steps(arguments, manifest, &self.manifest_manager)
+            .map_err(|e| McpError::new(-32602, e.to_string()))?;
+
+        let started = Instant::now();
+        let results = self.connection_manager.execute_sequence(&steps).await;
+        self.record_steps(manifest, started, &steps, &results);
+        Ok(Self::steps_content("callSequence", &results, steps.len()))
     }
 
-    fn health_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
-        let health = serde_json::json!({
-            "status": "ok",
-            "service": "arduino-mcp-adapter",
-            "version": "0.1.0"
-        });
-        Self::json_response(serde_json::to_string(&health).unwrap())
+    async fn handle_run_macro(
+        &self,
+        arguments: &Value,
+        manifest: &Manifest,
+    ) -> Result<Value, McpError> {
+        let name = arguments
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| self.missing_parameter("name", "runMacro"))?;
+        let saved = self
+            .macros
+            .load(name)
+            .map_err(|e| McpError::new(-32602, format!("{:#}", e)))?
+            .ok_or_else(|| self.macro_not_found(name))?;
+        if saved.robot != manifest.name {
+            return Err(McpError::new(
+                -32602,
+                self.messages.format(
+                    "macro_other_robot",
+                    &[
+                        ("name", &name),
+                        ("recorded", &saved.robot),
+                        ("robot", &manifest.name),
+                    ],
+                ),
+            ));
+        }
+        let steps = sequence::validate_steps(saved.steps, manifest, &self.manifest_manager)
+            .map_err(|e| {
+                McpError::new(
+                    -32602,
+                    self.messages
+                        .format("macro_outdated", &[("name", &name), ("error", &e)]),
+                )
+            })?;
+
+        info!("Running macro '{}' ({} steps)", name, steps.len());
+        let started = Instant::now();
+        let results = self.connection_manager.execute_steps(&steps).await;
+        self.record_steps(manifest, started, &steps, &results);
+        Ok(Self::steps_content("runMacro", &results, steps.len()))
     }
 
-    fn error_response(
-        code: i32,
-        message: &str,
-    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
-        let error = McpResponse {
-            jsonrpc: "2.0".to_string(),
-            id: None,
-            result: None,
-            error: Some(McpError {
-                code,
-                message: message.to_string(),
-                data: None,
-            }),
+    async fn handle_composite(
+        &self,
+        composite: &Composite,
+        arguments: &Value,
+        manifest: &Manifest,
+    ) -> Result<Value, McpError> {
+        let steps = composite
+            .expand(arguments, manifest, &self.manifest_manager)
+            .map_err(McpError::invalid_arguments)?;
+
+        debug!(
+            "Running composite '{}' ({} steps)",
+            composite.name,
+            steps.len()
+        );
+        let started = Instant::now();
+        let results = self.connection_manager.execute_steps(&steps).await;
+        self.record_steps(manifest, started, &steps, &results);
+        Ok(Self::steps_content(&composite.name, &results, steps.len()))
+    }
+
+    fn handle_clear_fault(&self) -> Result<Value, McpError> {
+        match self.connection_manager.clear_fault() {
+            Ok(fault) => Ok(Self::text_content(format!(
+                "Cleared the {} fault; the robot may move again",
+                fault
+            ))),
+            Err(e) => Err(McpError::new(-32602, e.to_string()).with_kind(Kind::Validation)),
+        }
+    }
+
+    async fn handle_self_test(&self, manifest: &Manifest) -> Result<Value, McpError> {
+        let report =
+            self_test::run(&self.connection_manager, manifest, &self.manifest_manager).await;
+        Ok(Self::text_content(serde_json::to_string(&report).unwrap()))
+    }
+
+    fn handle_schedule_tool(
+        &self,
+        arguments: &Value,
+        manifest: &Manifest,
+    ) -> Result<Value, McpError> {
+        let name = arguments
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| self.missing_parameter("name", "scheduleTool"))?;
+
+        if arguments.get("cancel").and_then(Value::as_bool) == Some(true) {
+            if !self.scheduler.remove(name) {
+                return Err(self.schedule_not_found(name));
+            }
+            self.record_tool(manifest, "scheduleTool", arguments);
+            return Ok(Self::text_content(format!("Cancelled schedule '{}'", name)));
+        }
+
+        if arguments.get("function").is_some() {
+            let mut fields = arguments.clone();
+            if let Some(fields) = fields.as_object_mut() {
+                fields.remove("cancel");
+            }
+            let spec: ScheduleSpec = serde_json::from_value(fields).map_err(|e| {
+                McpError::new(
+                    -32602,
+                    self.messages.format("invalid_schedule", &[("error", &e)]),
+                )
+            })?;
+            let func = manifest
+                .functions
+                .iter()
+                .find(|f| f.name == spec.function)
+                .ok_or_else(|| self.function_not_found(&spec.function))?;
+            self.manifest_manager
+                .validate_function_arguments(func, &spec.arguments)
+                .map_err(|e| self.invalid_arguments(e))?;
+            self.scheduler
+                .add(spec)
+                .map_err(|e| McpError::new(-32602, e.to_string()))?;
+            self.record_tool(manifest, "scheduleTool", arguments);
+        }
+
+        let report = self
+            .scheduler
+            .get(name)
+            .ok_or_else(|| self.schedule_not_found(name))?;
+        Ok(Self::text_content(serde_json::to_string(&report).unwrap()))
+    }
+
+    async fn handle_raw_command(&self, arguments: &Value) -> Result<Value, McpError> {
+        let tag = arguments
+            .get("tag")
+            .and_then(Value::as_u64)
+            .and_then(|tag| u8::try_from(tag).ok())
+            .ok_or_else(|| McpError::new(-32602, self.messages.get("raw_tag_range")))?;
+        let args = match arguments.get("args") {
+            None => Vec::new(),
+            Some(args) => args
+                .as_str()
+                .ok_or_else(|| McpError::new(-32602, self.messages.get("raw_args_not_hex")))
+                .and_then(|args| hex::parse_hex(args).map_err(|e| self.invalid_arguments(e)))?,
         };
 
-        let body = serde_json::to_string(&error).unwrap();
-        Self::json_response(body)
+        info!("rawCommand: tag {} with {} argument bytes", tag, args.len());
+        match self.connection_manager.execute_raw(tag, &args).await {
+            Ok(data) if data.is_empty() => Ok(Self::text_content("(no data)".to_string())),
+            Ok(data) => Ok(Self::text_content(hex::to_hex(&data))),
+            Err(e) => Err(self.execution_error(e)),
+        }
     }
 
-    fn sse_stream_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
-        use tokio_stream::wrappers::ReceiverStream;
+    fn take_control(&self, session: &str) -> Value {
+        let text = match self.sessions.take_control(session) {
+            Some(previous) => {
+                info!(
+                    "Session {} took control from {}",
+                    session,
+                    Self::client_name(&previous)
+                );
+                format!(
+                    "You now control the robot, taken over from {}",
+                    Self::client_name(&previous)
+                )
+            }
+            None => "You now control the robot".to_string(),
+        };
+        Self::text_content(text)
+    }
 
-        // Create a channel and spawn a task to keep the sender alive indefinitely
-        let (tx, rx) = tokio::sync::mpsc::channel::<
-            Result<hyper::body::Frame<hyper::body::Bytes>, hyper::Error>,
-        >(1);
+    fn release_control(&self, session: &str) -> Value {
+        let text = match self.sessions.release_control(session) {
+            true => "Released control of the robot",
+            false => "You did not have control of the robot",
+        };
+        Self::text_content(text.to_string())
+    }
 
-        // Spawn a task that holds the sender forever, keeping the stream alive
-        tokio::spawn(async move {
-            let _tx = tx; // Keep sender alive
-                          // Sleep forever - this keeps the connection open
-            std::future::pending::<()>().await;
+    /// The error for a tool call from a session without control.
+    fn controlled_elsewhere(&self, controller: SessionInfo) -> McpError {
+        McpError {
+            code: Kind::Control.code(),
+            message: self.messages.format(
+                "controlled_elsewhere",
+                &[("client", &Self::client_name(&controller))],
+            ),
+            data: Some(serde_json::json!({
+                "kind": Kind::Control.name(),
+                "controller": controller,
+                "suggestion": self.messages.get("suggest_controlled_elsewhere")
+            })),
+        }
+    }
+
+    /// The error for a call while the robot's command queue is over
+    /// `--max-queue-depth` or `--max-queue-age-ms`. Refusing it keeps a
+    /// client retrying in a loop from building an ever longer backlog.
+    fn queue_saturated(&self) -> Option<McpError> {
+        let (depth, oldest) = self.connection_manager.queue();
+        let oldest = oldest.unwrap_or_default();
+        let max_age = Duration::from_millis(self.config.max_queue_age_ms);
+        let too_deep = self.config.max_queue_depth > 0 && depth >= self.config.max_queue_depth;
+        let too_old = !max_age.is_zero() && depth > 0 && oldest >= max_age;
+        if !too_deep && !too_old {
+            return None;
+        }
+        debug!(
+            "Refusing a call with {} commands queued, the oldest for {:?}",
+            depth, oldest
+        );
+        Some(McpError {
+            code: Kind::QueueFull.code(),
+            message: self.messages.format(
+                "queue_full",
+                &[("depth", &depth), ("age_ms", &oldest.as_millis())],
+            ),
+            data: Some(serde_json::json!({
+                "kind": Kind::QueueFull.name(),
+                "queue_depth": depth,
+                "oldest_queued_ms": oldest.as_millis() as u64,
+                "suggestion": self.messages.get("suggest_queue_full")
+            })),
+        })
+    }
+
+    fn not_ready_message(&self, state: &RobotState) -> String {
+        let reason = self.messages.state(state);
+        self.messages.format("not_ready", &[("reason", &reason)])
+    }
+
+    fn function_not_found(&self, name: &str) -> McpError {
+        McpError::new(
+            -32602,
+            self.messages
+                .format("function_not_found", &[("name", &name)]),
+        )
+    }
+
+    fn missing_parameter(&self, name: &str, tool: &str) -> McpError {
+        McpError::new(
+            -32602,
+            self.messages
+                .format("missing_parameter", &[("name", &name), ("tool", &tool)]),
+        )
+    }
+
+    /// The error for a tool a Python script may not call back into.
+    pub(crate) fn refused_in_script(&self, name: &str) -> McpError {
+        McpError::new(
+            -32602,
+            self.messages
+                .format("script_calls_tool", &[("name", &name)]),
+        )
+    }
+
+    fn macro_not_found(&self, name: &str) -> McpError {
+        McpError::new(
+            -32602,
+            self.messages.format("macro_not_found", &[("name", &name)]),
+        )
+    }
+
+    fn schedule_not_found(&self, name: &str) -> McpError {
+        McpError::new(
+            -32602,
+            self.messages
+                .format("schedule_not_found", &[("name", &name)]),
+        )
+    }
+
+    fn invalid_arguments(&self, error: impl std::fmt::Display) -> McpError {
+        McpError::new(
+            -32602,
+            self.messages
+                .format("invalid_arguments", &[("error", &error)]),
+        )
+    }
+
+    fn client_name(session: &SessionInfo) -> String {
+        match &session.client {
+            Some(client) => format!("{} at {}", client, session.peer),
+            None => session.peer.clone(),
+        }
+    }
+
+    /// A failed robot call, with the firmware's error code in `data` when it
+    /// sent one.
+    fn execution_error(&self, e: anyhow::Error) -> McpError {
+        let kind = Kind::of(&e);
+        if let Some(violation) = e.downcast_ref::<GeofenceViolation>() {
+            return McpError {
+                code: kind.code(),
+                message: self
+                    .messages
+                    .format("geofence", &[("violation", violation)]),
+                data: Some(serde_json::json!({
+                    "kind": kind.name(),
+                    "geofence": violation,
+                    "suggestion": self.messages.get("suggest_geofence")
+                })),
+            };
+        }
+        let state = self.connection_manager.get_state();
+        let mut data = serde_json::json!({
+            "kind": kind.name(),
+            "robot_state": state,
+            "suggestion": self.messages.get("suggest_check_connection")
         });
+        if let RobotState::Fault { fault, .. } = state {
+            data["fault"] = serde_json::json!(fault);
+        }
+        if let Some(AdapterError::Fault(fault, name)) = e.downcast_ref::<AdapterError>() {
+            data["suggestion"] = self.messages.get("suggest_fault").into();
+            return McpError {
+                code: kind.code(),
+                message: self
+                    .messages
+                    .format("fault", &[("name", name), ("fault", fault)]),
+                data: Some(data),
+            };
+        }
+        if let Some(error) = e.downcast_ref::<DeviceError>() {
+            data["device_error"] = serde_json::json!({"code": error.code(), "name": error.name()});
+            data["suggestion"] = self
+                .messages
+                .get(&format!("suggest_{}", error.name()))
+                .into();
+        }
+        McpError {
+            code: kind.code(),
+            message: self.messages.format("execution_error", &[("error", &e)]),
+            data: Some(data),
+        }
+    }
 
-        let stream = ReceiverStream::new(rx);
+    /// `{"completed": n, "steps": [...]}` as text content.
+    fn steps_content(tool_name: &str, results: &[StepResult], total: usize) -> Value {
+        let completed = results.iter().filter(|r| r.error.is_none()).count();
+        if completed < total {
+            warn!(
+                "{} stopped after {} of {} steps",
+                tool_name, completed, total
+            );
+        }
+        let output = serde_json::json!({ "completed": completed, "steps": results });
+        Self::text_content(output.to_string())
+    }
 
-        Response::builder()
-            .status(StatusCode::ACCEPTED)
-            .header("Content-Type", "text/event-stream")
-            .header("Cache-Control", "no-cache")
-            .header("Access-Control-Allow-Origin", "*")
-            .body(BoxBody::new(http_body_util::StreamBody::new(stream)))
-            .unwrap()
+    /// Add a successful function call to the macro and session recordings.
+    fn record_call(&self, manifest: &Manifest, tool_name: &str, arguments: &Value) {
+        match self.macros.record(&manifest.name, tool_name, arguments) {
+            // runMacro's list of names changed
+            Ok(Some(_)) => self
+                .notifier
+                .notify("notifications/tools/list_changed", None),
+            Ok(None) => {}
+            Err(e) => error!("Failed to save macro: {:#}", e),
+        }
+        if let Some(recorder) = &self.session_recorder {
+            if let Err(e) = recorder.record(&manifest.name, tool_name, arguments) {
+                error!("Failed to record the call: {:#}", e);
+            }
+        }
+    }
+
+    /// Add the steps a multi-step tool completed to the session recording,
+    /// with its `wait` steps as pauses.
+    fn record_steps(
+        &self,
+        manifest: &Manifest,
+        mut started: Instant,
+        steps: &[Step],
+        results: &[StepResult],
+    ) {
+        let Some(recorder) = &self.session_recorder else {
+            return;
+        };
+        let mut specs: Vec<StepSpec> = Vec::new();
+        let completed = steps
+            .iter()
+            .zip(results)
+            .take_while(|(_, result)| result.error.is_none());
+        for (step, _) in completed {
+            let delay_ms = step.delay.as_millis() as u64;
+            match (&step.func, specs.last_mut()) {
+                (Some(func), _) => specs.push(StepSpec {
+                    name: func.name.clone(),
+                    arguments: step.arguments.clone(),
+                    delay_ms,
+                }),
+                (None, Some(previous)) => previous.delay_ms += delay_ms,
+                (None, None) => started += step.delay,
+            }
+        }
+        if let Err(e) = recorder.record_steps(&manifest.name, started, specs) {
+            error!("Failed to record the call: {:#}", e);
+        }
+    }
+
+    /// Add a successful call of a built-in tool to the session recording.
+    fn record_tool(&self, manifest: &Manifest, tool_name: &str, arguments: &Value) {
+        if let Some(recorder) = &self.session_recorder {
+            if let Err(e) = recorder.record_tool(&manifest.name, tool_name, arguments) {
+                error!("Failed to record the call: {:#}", e);
+            }
+        }
+    }
+
+    fn run_macro_tool(&self, manifest: &Manifest) -> Option<Tool> {
+        let names: Vec<String> = match self.macros.list() {
+            Ok(macros) => macros
+                .into_iter()
+                .filter(|m| m.robot == manifest.name)
+                .map(|m| m.name)
+                .collect(),
+            Err(e) => {
+                warn!("Failed to list macros: {:#}", e);
+                return None;
+            }
+        };
+        if names.is_empty() {
+            return None;
+        }
+
+        Some(Tool {
+            name: "runMacro".to_string(),
+            description: "Replay a recorded macro: a saved series of robot function calls, run in order with the pauses they were recorded with. Execution stops at the first failing step. Returns JSON with the number of completed steps and each step's result or error.".to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "enum": names,
+                        "description": "Macro to run."
+                    }
+                },
+                "required": ["name"]
+            }),
+        })
+    }
+
+    pub(crate) fn text_content(text: String) -> Value {
+        serde_json::json!({
+            "content": [
+                {
+                    "type": "text",
+                    "text": text
+                }
+            ]
+        })
+    }
+
+    /// An `image` result, decoded as a `data:` URL, as an MCP image block.
+    fn image_content(data_url: String) -> Value {
+        match protocol::parse_data_url(&data_url) {
+            Some((mime_type, data)) => serde_json::json!({
+                "content": [
+                    {
+                        "type": "image",
+                        "data": data,
+                        "mimeType": mime_type
+                    }
+                ]
+            }),
+            None => Self::text_content(data_url),
+        }
+    }
+
+    /// Run a tool defined by a plugin.
+    async fn handle_plugin_tool(
+        &self,
+        tool_name: &str,
+        arguments: &Value,
+    ) -> Result<Value, McpError> {
+        if IN_PLUGIN.try_with(|_| ()).is_ok() {
+            return Err(McpError::new(
+                -32602,
+                self.messages
+                    .format("plugin_calls_plugin", &[("name", &tool_name)]),
+            ));
+        }
+        let outcome = match self.plugins.get() {
+            Some(plugins) => {
+                IN_PLUGIN
+                    .scope((), plugins.call(tool_name, arguments))
+                    .await
+            }
+            None => None,
+        };
+        match outcome {
+            Some(Ok(text)) => Ok(Self::text_content(text)),
+            Some(Err(e)) => Err(McpError::new(
+                -32603,
+                self.messages
+                    .format("plugin_error", &[("error", &format!("{:#}", e))]),
+            )),
+            None => Err(self.function_not_found(tool_name)),
+        }
+    }
+
+    /// Manifest functions plus the built-in and plugin tools; `runMacro`
+    /// only once a macro has been recorded for this robot.
+    fn tools_for(&self, manifest: &Manifest) -> Vec<Tool> {
+        let mut tools = self.manifest_manager.create_tools_list(manifest);
+        if manifest.fallback {
+            if self.offers_raw_command() {
+                tools.push(Self::raw_command_tool());
+            }
+            return tools;
+        }
+        tools.push(Self::python_runner_tool());
+        tools.push(Self::call_sequence_tool());
+        tools.push(Self::wait_tool());
+        tools.push(Self::schedule_tool());
+        if self.offers_raw_command() {
+            tools.push(Self::raw_command_tool());
+        }
+        if Self::has_motion(manifest) {
+            tools.push(Self::await_motion_tool());
+        }
+        if self.offers_emergency_stop(manifest) {
+            tools.push(Self::emergency_stop_tool());
+        }
+        if !manifest.self_test.is_empty() {
+            tools.push(Self::self_test_tool());
+        }
+        if self.offers_clear_fault() {
+            tools.push(Self::clear_fault_tool());
+        }
+        if let Some(tool) = self.run_macro_tool(manifest) {
+            tools.push(tool);
+        }
+        // Plugins call robot functions without going through the manifest
+        if let Some(plugins) = self.plugins.get().filter(|_| !self.config.read_only) {
+            // Manifest functions and built-in tools win over plugin tools of the same name
+            let plugin_tools = plugins
+                .tools()
+                .filter(|tool| !tools.iter().any(|t| t.name == tool.name))
+                .cloned()
+                .collect::<Vec<_>>();
+            tools.extend(plugin_tools);
+        }
+        tools
+    }
+
+    /// The device's manifest as clients see it: with `--read-only`, only
+    /// the functions and composites that don't change the robot.
+    /// Without a manifest file, the generic tools of [`manifest::fallback`].
+    fn client_manifest(&self, device_id: &str) -> anyhow::Result<Manifest> {
+        let manifest = match self.manifest_manager.get_manifest(device_id) {
+            Err(e) if e.is::<ManifestNotFound>() => {
+                let version = self.connection_manager.firmware_manifest_version();
+                manifest::fallback(device_id, version.is_some())
+            }
+            manifest => manifest?,
+        };
+        Ok(match self.config.read_only {
+            true => manifest::read_only(manifest),
+            false => manifest,
+        })
+    }
+
+    /// `rawCommand` can send anything, so it takes `--enable-raw`, and
+    /// read-only mode leaves it out.
+    fn offers_raw_command(&self) -> bool {
+        self.config.enable_raw && !self.config.read_only
+    }
+
+    /// `tools/list`'s hint for a device served with generic tools: the
+    /// file its manifest was looked for in, and the manifests there are.
+    fn missing_manifest_status(&self, device_id: &str) -> Value {
+        let path = self.manifest_manager.manifest_path(device_id);
+        let available = self
+            .manifest_manager
+            .list_available_manifests()
+            .unwrap_or_default();
+        let mut status = serde_json::json!({
+            "robot_state": self.connection_manager.get_state(),
+            "message": self.messages.format(
+                "no_manifest",
+                &[("device_id", &device_id), ("path", &path.display())]
+            ),
+            "available_manifests": available,
+            "firmware_manifest_version": self.connection_manager.firmware_manifest_version()
+        });
+        if !self.config.enable_raw && !self.config.read_only {
+            status["suggestion"] = self.messages.get("suggest_enable_raw").into();
+        }
+        status
+    }
+
+    /// Faults arrive as events, so only firmware that sends them can latch
+    /// one.
+    fn offers_clear_fault(&self) -> bool {
+        self.connection_manager.protocol().is_some_and(|p| p.events)
+    }
+
+    fn offers_emergency_stop(&self, manifest: &Manifest) -> bool {
+        manifest.safe_state.is_some() && !self.config.read_only
+    }
+
+    /// Tools currently exposed for the identified device, if any.
+    pub(crate) fn current_tools(&self) -> (Option<Manifest>, Vec<Tool>) {
+        let state = self.connection_manager.get_state();
+        match state
+            .device_id()
+            .and_then(|id| self.client_manifest(id).ok())
+        {
+            Some(manifest) => {
+                let tools = self.tools_for(&manifest);
+                (Some(manifest), tools)
+            }
+            None => (None, Vec::new()),
+        }
+    }
+
+    fn handle_openapi(&self) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let (manifest, tools) = self.current_tools();
+        let document = rest::openapi_document(manifest.as_ref(), &tools);
+        Self::json_response(serde_json::to_string(&document).unwrap())
+    }
+
+    fn handle_debug_frames(
+        &self,
+        query: Option<&str>,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let limit = query
+            .unwrap_or("")
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("limit="))
+            .and_then(|value| value.parse().ok());
+
+        let frame_log = self.connection_manager.frame_log();
+        let body = serde_json::json!({
+            "capacity": frame_log.capacity(),
+            "frames": frame_log.recent(limit)
+        });
+        Self::json_response(serde_json::to_string(&body).unwrap())
+    }
+
+    /// The `/stats` document: calls, error rate and latency percentiles of
+    /// each tool called so far.
+    pub(crate) fn tool_stats(&self) -> Value {
+        serde_json::json!({ "tools": self.tool_stats.snapshot() })
+    }
+
+    pub(crate) fn stats_resource(uri: &str) -> Value {
+        serde_json::json!({
+            "uri": uri,
+            "name": "stats",
+            "description": "Per-tool call counts, error rates and latency percentiles",
+            "mimeType": "application/json"
+        })
+    }
+
+    /// A `resources/read` result holding `document`.
+    pub(crate) fn resource_contents(uri: &str, document: &Value) -> Value {
+        serde_json::json!({
+            "contents": [{
+                "uri": uri,
+                "mimeType": "application/json",
+                "text": serde_json::to_string_pretty(document).unwrap()
+            }]
+        })
+    }
+
+    fn handle_telemetry(&self) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let body = serde_json::json!({ "schedules": self.scheduler.report() });
+        Self::json_response(serde_json::to_string(&body).unwrap())
+    }
+
+    fn handle_macros_list(&self) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        match self.macros.list() {
+            Ok(macros) => {
+                let macros: Vec<Value> = macros
+                    .iter()
+                    .map(|m| {
+                        serde_json::json!({
+                            "name": m.name,
+                            "robot": m.robot,
+                            "steps": m.steps.len()
+                        })
+                    })
+                    .collect();
+                let body = serde_json::json!({
+                    "macros": macros,
+                    "recording": self.macros.recording()
+                });
+                Self::json_response(serde_json::to_string(&body).unwrap())
+            }
+            Err(e) => Self::rest_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                McpError::new(-32603, format!("{:#}", e)),
+            ),
+        }
+    }
+
+    async fn handle_macro_record(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
+        let body_bytes = req.collect().await?.to_bytes();
+        let request: RecordRequest = match serde_json::from_slice(&body_bytes) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = McpError::new(
+                    -32602,
+                    format!("Expected {{\"name\": ..., \"count\": ...}}: {}", e),
+                );
+                return Ok(Self::rest_error_response(StatusCode::BAD_REQUEST, error));
+            }
+        };
+
+        match self.macros.start_recording(&request.name, request.count) {
+            Ok(status) => {
+                let body = serde_json::json!({ "recording": status });
+                Ok(Self::json_response(serde_json::to_string(&body).unwrap()))
+            }
+            Err(e) => Ok(Self::rest_error_response(
+                StatusCode::BAD_REQUEST,
+                McpError::new(-32602, e.to_string()),
+            )),
+        }
+    }
+
+    fn handle_macro_cancel(&self) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        match self.macros.cancel_recording() {
+            Some(name) => {
+                let body = serde_json::json!({ "cancelled": name });
+                Self::json_response(serde_json::to_string(&body).unwrap())
+            }
+            None => Self::rest_error_response(
+                StatusCode::NOT_FOUND,
+                McpError::new(-32602, self.messages.get("no_macro_recording")),
+            ),
+        }
+    }
+
+    fn handle_macro_get(&self, path: &str) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let name = &path["/macros/".len()..];
+        match self.macros.load(name) {
+            Ok(Some(saved)) => Self::json_response(serde_json::to_string(&saved).unwrap()),
+            Ok(None) => {
+                Self::rest_error_response(StatusCode::NOT_FOUND, self.macro_not_found(name))
+            }
+            Err(e) => Self::rest_error_response(
+                StatusCode::BAD_REQUEST,
+                McpError::new(-32602, format!("{:#}", e)),
+            ),
+        }
+    }
+
+    fn handle_macro_delete(
+        &self,
+        path: &str,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let name = &path["/macros/".len()..];
+        match self.macros.delete(name) {
+            Ok(true) => {
+                self.notifier
+                    .notify("notifications/tools/list_changed", None);
+                let body = serde_json::json!({ "deleted": name });
+                Self::json_response(serde_json::to_string(&body).unwrap())
+            }
+            Ok(false) => {
+                Self::rest_error_response(StatusCode::NOT_FOUND, self.macro_not_found(name))
+            }
+            Err(e) => Self::rest_error_response(
+                StatusCode::BAD_REQUEST,
+                McpError::new(-32602, format!("{:#}", e)),
+            ),
+        }
+    }
+
+    fn handle_rest_tools_list(&self) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let (_, tools) = self.current_tools();
+        let body = serde_json::json!({ "tools": tools });
+        Self::json_response(serde_json::to_string(&body).unwrap())
+    }
+
+    /// Each HTTP connection is a session of its own, as on a fleet's `/mcp`,
+    /// so `--max-clients` and exclusive control apply to REST calls too.
+    async fn handle_rest_tool(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        peer: SocketAddr,
+    ) -> Result<Response<BoxBody<hyper::body::Bytes, hyper::Error>>, hyper::Error> {
+        let tool_name = rest::tool_name(req.uri().path());
+
+        let state = self.connection_manager.get_state();
+        if !state.accepts_calls() {
+            let error = McpError {
+                code: Kind::NotReady.code(),
+                message: self.not_ready_message(&state),
+                data: Some(serde_json::json!({
+                    "kind": Kind::NotReady.name(),
+                    "robot_state": state
+                })),
+            };
+            return Ok(Self::rest_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                error,
+            ));
+        }
+
+        let (_, tools) = self.current_tools();
+        let tool = match tools.into_iter().find(|t| t.name == tool_name) {
+            Some(tool) => tool,
+            None => {
+                let error = self.function_not_found(&tool_name);
+                return Ok(Self::rest_error_response(StatusCode::NOT_FOUND, error));
+            }
+        };
+
+        let arguments = if req.method() == Method::GET {
+            rest::query_to_arguments(req.uri().query(), &tool)
+        } else {
+            let body_bytes = req.collect().await?.to_bytes();
+            if body_bytes.iter().all(|b| b.is_ascii_whitespace()) {
+                serde_json::json!({})
+            } else {
+                match serde_json::from_slice::<Value>(&body_bytes) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        let error = McpError::new(-32700, format!("JSON parse error: {}", e));
+                        return Ok(Self::rest_error_response(StatusCode::BAD_REQUEST, error));
+                    }
+                }
+            }
+        };
+
+        debug!("REST call {} with arguments {}", tool_name, arguments);
+
+        match self.call_tool_from(peer, &tool_name, &arguments).await {
+            Ok(result) => {
+                let body = serde_json::json!({
+                    "tool": tool_name,
+                    "result": rest::result_text(&result)
+                });
+                Ok(Self::json_response(serde_json::to_string(&body).unwrap()))
+            }
+            Err(error) => {
+                // By kind, as `[error_codes]` may change the code
+                let status = match error.kind() {
+                    Some(Kind::Validation) => StatusCode::BAD_REQUEST,
+                    Some(Kind::QueueFull) => StatusCode::TOO_MANY_REQUESTS,
+                    Some(Kind::NotReady) => StatusCode::SERVICE_UNAVAILABLE,
+                    Some(Kind::Control) => StatusCode::CONFLICT,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                Ok(Self::rest_error_response(status, error))
+            }
+        }
+    }
+
+    fn rest_error_response(
+        status: StatusCode,
+        error: McpError,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let body = serde_json::json!({ "error": error });
+        let mut response = Self::json_response(serde_json::to_string(&body).unwrap());
+        *response.status_mut() = status;
+        response
+    }
+
+    fn python_runner_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/runPythonScript.json"))
+                    .expect("runPythonScript.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn call_sequence_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/callSequence.json"))
+                    .expect("callSequence.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn wait_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/wait.json"))
+                    .expect("wait.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn await_motion_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/awaitMotionComplete.json"))
+                    .expect("awaitMotionComplete.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    /// Whether some function returns before its motion ends, so
+    /// `awaitMotionComplete` has something to wait for.
+    fn has_motion(manifest: &Manifest) -> bool {
+        manifest.functions.iter().any(|f| f.ack == Ack::Immediate)
+    }
+
+    fn emergency_stop_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/emergencyStop.json"))
+                    .expect("emergencyStop.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn clear_fault_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/clearFault.json"))
+                    .expect("clearFault.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn self_test_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/selfTest.json"))
+                    .expect("selfTest.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn schedule_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/scheduleTool.json"))
+                    .expect("scheduleTool.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn take_control_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/takeControl.json"))
+                    .expect("takeControl.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn release_control_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/releaseControl.json"))
+                    .expect("releaseControl.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    fn raw_command_tool() -> Tool {
+        static TOOL_CACHE: OnceLock<Tool> = OnceLock::new();
+        TOOL_CACHE
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("resources/rawCommand.json"))
+                    .expect("rawCommand.json must deserialize to Tool")
+            })
+            .clone()
+    }
+
+    pub(crate) fn json_response(
+        body: String,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Expose-Headers", "Mcp-Session-Id")
+            .header("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS")
+            .header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, Authorization, Mcp-Session-Id",
+            )
+            .body(BoxBody::new(Full::new(body.into()).map_err(|e| match e {})))
+            .unwrap()
+    }
+
+    pub(crate) fn cors_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS")
+            .header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, Authorization, Mcp-Session-Id",
+            )
+            .body(BoxBody::new(Full::new("".into()).map_err(|e| match e {})))
+            .unwrap()
+    }
+
+    pub(crate) fn is_authorized(req: &Request<hyper::body::Incoming>, token: &str) -> bool {
+        req.headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|provided| tokens_match(provided.as_bytes(), token.as_bytes()))
+    }
+
+    pub(crate) fn unauthorized_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", "Bearer")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(BoxBody::new(
+                Full::new("Unauthorized".into()).map_err(|e| match e {}),
+            ))
+            .unwrap()
+    }
+
+    pub(crate) fn not_found_response() -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(BoxBody::new(
+                Full::new("Not Found".into()).map_err(|e| match e {}),
+            ))
+            .unwrap()
+    }
+
+    /// The interpreter's version, or `None` without one; asked only once.
+    async fn python_version(&self) -> Option<String> {
+        self.python_version
+            .get_or_init(|| async {
+                python_runner::python_version(&self.python_env.interpreter()).await
+            })
+            .await
+            .clone()
+    }
+
+    /// Deep health check; 503 unless the robot is ready so container
+    /// healthchecks can gate on it. Callers without the auth token only
+    /// get the status.
+    async fn handle_health(
+        &self,
+        authorized: bool,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let state = self.connection_manager.get_state();
+        let status = if state.accepts_calls() {
+            "ok"
+        } else {
+            "unavailable"
+        };
+        let health = match authorized {
+            true => self.health_checks(&state, status).await,
+            false => serde_json::json!({ "status": status }),
+        };
+
+        let mut response = Self::json_response(serde_json::to_string(&health).unwrap());
+        if !state.accepts_calls() {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        response
+    }
+
+    async fn health_checks(&self, state: &RobotState, status: &str) -> Value {
+        let manifest = match state.device_id() {
+            Some(device_id) => match self.manifest_manager.get_manifest(device_id) {
+                Ok(manifest) => serde_json::json!({
+                    "loaded": true,
+                    "name": manifest.name,
+                    "version": manifest.version,
+                    "functions": manifest.functions.len()
+                }),
+                Err(e) => serde_json::json!({ "loaded": false, "error": e.to_string() }),
+            },
+            None => serde_json::json!({ "loaded": false }),
+        };
+
+        let python = self.python_version().await;
+
+        serde_json::json!({
+            "status": status,
+            "service": "arduino-mcp-adapter",
+            "version": env!("CARGO_PKG_VERSION"),
+            "checks": {
+                "serial": {
+                    "connected": self.connection_manager.is_port_open(),
+                    "state": format!("{:?}", state),
+                    "message": state.error_message()
+                },
+                "device": {
+                    "ready": state.is_ready(),
+                    "device_id": state.device_id(),
+                    "last_response_ms": self
+                        .connection_manager
+                        .last_response_age()
+                        .map(|age| age.as_millis() as u64)
+                },
+                "manifest": manifest,
+                "python": {
+                    "available": python.is_some(),
+                    "version": python
+                }
+            }
+        })
+    }
+
+    pub(crate) fn error_response(
+        code: i32,
+        message: &str,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        let error = McpResponse {
+            jsonrpc: "2.0".to_string(),
+            id: None,
+            result: None,
+            error: Some(McpError {
+                code,
+                message: message.to_string(),
+                data: None,
+            }),
+        };
+
+        let body = serde_json::to_string(&error).unwrap();
+        Self::json_response(body)
+    }
+
+    fn sse_stream_response(
+        &self,
+        status: StatusCode,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        Self::sse_response(&self.notifier, status, self.config.http.ping_interval())
+    }
+
+    /// SSE stream of everything sent through `notifier` from now on, with a
+    /// comment every `ping` so a vanished client is noticed.
+    pub(crate) fn sse_response(
+        notifier: &Notifier,
+        status: StatusCode,
+        ping: Option<Duration>,
+    ) -> Response<BoxBody<hyper::body::Bytes, hyper::Error>> {
+        use tokio_stream::wrappers::ReceiverStream;
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<
+            Result<hyper::body::Frame<hyper::body::Bytes>, hyper::Error>,
+        >(1);
+
+        // Forward notifications as SSE events until the client goes away
+        let mut notifications = notifier.subscribe();
+        tokio::spawn(async move {
+            let mut pings = ping
+                .map(|every| tokio::time::interval_at(tokio::time::Instant::now() + every, every));
+            loop {
+                let ping = async {
+                    match pings.as_mut() {
+                        Some(pings) => pings.tick().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let event = tokio::select! {
+                    received = notifications.recv() => match received {
+                        Ok(message) => format!("event: message\ndata: {}\n\n", message),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("SSE session missed {} notifications", skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    // A comment line, which clients ignore
+                    _ = ping => ": ping\n\n".to_string(),
+                };
+                let frame = hyper::body::Frame::data(hyper::body::Bytes::from(event));
+                if tx.send(Ok(frame)).await.is_err() {
+                    debug!("SSE session closed");
+                    break;
+                }
+            }
+        });
+
+        let stream = ReceiverStream::new(rx);
+
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(BoxBody::new(http_body_util::StreamBody::new(stream)))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::loopback::{LoopbackConnector, LoopbackDevice};
+    use crate::robot_state::RobotState;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+
+    fn manifest_json() -> Value {
+        fixtures::manifest_json(
+            serde_json::json!([
+                {"tag": 1, "name": "blinkLED", "desc": "Blink", "return": null,
+                 "params": [{"name": "n", "type": "i16"}]},
+                {"tag": 2, "name": "getSensorValue", "desc": "Read sensor", "return": "i32",
+                 "params": [{"name": "sensorId", "type": "i16"}]},
+                {"tag": 3, "name": "getStatus", "desc": "Status", "return": "CStr",
+                 "params": []},
+                {"tag": 4, "name": "snapshot", "desc": "Camera", "return": "image",
+                 "params": []},
+                {"tag": 5, "name": "moveTo", "desc": "Move", "return": null, "ack": "immediate",
+                 "params": [{"name": "angle", "type": "i16"}]},
+                {"tag": 6, "name": "stop", "desc": "Stop", "return": null, "params": []},
+                {"tag": 7, "name": "readBattery", "desc": "Battery in mV", "return": "i16",
+                 "params": []}
+            ]),
+            serde_json::json!({
+                "safe_state": "stop",
+                "battery": {"function": "readBattery", "low": 6800, "critical": 6200},
+                "self_test": [
+                    {"call": "getSensorValue", "arguments": {"sensorId": 1}, "desc": "Sensor 1",
+                     "min": 1},
+                    {"call": "blinkLED", "arguments": {"n": 1}}
+                ]
+            }),
+        )
+    }
+
+    /// Server wired to an in-process device; the tempdir holds the manifest.
+    async fn loopback_server(
+        device: LoopbackDevice,
+        pipeline_depth: usize,
+    ) -> (McpServer, LoopbackConnector, tempfile::TempDir) {
+        loopback_server_with(device, pipeline_depth, Config::default()).await
+    }
+
+    async fn loopback_server_with(
+        device: LoopbackDevice,
+        pipeline_depth: usize,
+        config: Config,
+    ) -> (McpServer, LoopbackConnector, tempfile::TempDir) {
+        let dir = fixtures::manifest_dir(&manifest_json());
+        let manifest_manager = Arc::new(ManifestManager::new(dir.path().to_path_buf()));
+
+        let connector = LoopbackConnector::new(device);
+        let connection_manager = Arc::new(
+            ConnectionManager::with_connector(Box::new(connector.clone()))
+                .with_pipeline_depth(pipeline_depth)
+                .with_busy_wait(Duration::from_millis(config.busy_wait_ms))
+                .with_dry_run(config.dry_run)
+                .with_battery_lockout(config.battery_lockout)
+                .with_manifest_manager(Arc::clone(&manifest_manager)),
+        );
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+
+        let config = Config {
+            macro_dir: Some(dir.path().join("macros")),
+            ..config
+        };
+        let server = McpServer::new(connection_manager, manifest_manager, Arc::new(config));
+        (server, connector, dir)
+    }
+
+    fn device() -> LoopbackDevice {
+        fixtures::device(serde_json::from_value(manifest_json()).unwrap())
+    }
+
+    fn text(result: &Value) -> &str {
+        result["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_device_reset_resyncs_and_warns_clients() {
+        let (server, connector, _dir) = loopback_server(device().events(), 1).await;
+        let connection_manager = Arc::clone(&server.connection_manager);
+        let mut notifications = server.notifier().subscribe();
+        server.spawn_reset_watcher();
+
+        connector.restart();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            connection_manager.recovery_requested(),
+        )
+        .await
+        .unwrap();
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert!(connection_manager.get_state().is_ready());
+        let identified = connector
+            .calls()
+            .iter()
+            .filter(|(name, _)| name == "deviceId")
+            .count();
+        assert_eq!(identified, 2);
+        assert_eq!(connection_manager.stats().resets, 1);
+
+        let message = tokio::time::timeout(Duration::from_secs(5), notifications.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message["method"], "notifications/message");
+        assert_eq!(message["params"]["level"], "warning");
+    }
+
+    #[tokio::test]
+    async fn test_critical_battery_warns_and_locks_out_motion() {
+        let device = device().respond("readBattery", 6100i16.to_le_bytes().to_vec());
+        let config = Config {
+            battery_lockout: true,
+            ..Config::default()
+        };
+        let (server, _connector, _dir) = loopback_server_with(device, 1, config).await;
+        let mut notifications = server.notifier().subscribe();
+        server.spawn_battery_watcher();
+        tokio::spawn(Arc::clone(&server.connection_manager).run_battery_monitor());
+
+        let message = tokio::time::timeout(Duration::from_secs(5), notifications.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message["params"]["logger"], "battery");
+        assert_eq!(message["params"]["level"], "error");
+        let battery = &server.status()["battery"];
+        assert_eq!(battery["voltage"], 6100.0);
+        assert_eq!(battery["level"], "critical");
+
+        let error = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), Some(Kind::BatteryCritical));
+        assert!(error.message.contains("charged"), "{}", error.message);
+        // Stopping and reading are still allowed
+        for tool in ["stop", "getStatus", "emergencyStop"] {
+            assert!(server.call_tool(tool, &serde_json::json!({})).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fault_refuses_motion_until_cleared() {
+        let (server, connector, _dir) = loopback_server(device().events(), 1).await;
+        let connection_manager = Arc::clone(&server.connection_manager);
+        let mut notifications = server.notifier().subscribe();
+        server.spawn_device_watcher();
+        let (_, tools) = server.current_tools();
+        assert!(tools.iter().any(|t| t.name == "clearFault"));
+
+        connector.report_fault(0x10);
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            connection_manager.recovery_requested(),
+        )
+        .await
+        .unwrap();
+        connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert_eq!(server.status()["device_id"], "test-robot");
+        let error = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), Some(Kind::Fault));
+        assert_eq!(
+            error.message,
+            "'blinkLED' refused: the robot stopped on a stall fault; call clearFault once it is safe to move again"
+        );
+        assert_eq!(error.data.as_ref().unwrap()["fault"], "stall");
+        // Stopping and reading are still allowed
+        for tool in ["stop", "getStatus"] {
+            assert!(server.call_tool(tool, &serde_json::json!({})).await.is_ok());
+        }
+        // After the state change itself
+        let message = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let message = notifications.recv().await.unwrap();
+                if message["method"] == "notifications/message" {
+                    return message;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(message["params"]["level"], "error");
+
+        let cleared = server
+            .call_tool("clearFault", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(text(&cleared).contains("stall"));
+        assert!(server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .is_ok());
+        assert!(server
+            .call_tool("clearFault", &serde_json::json!({}))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fault_during_handshake_latched_once_ready() {
+        let (server, connector, _dir) = loopback_server(device().events(), 1).await;
+        let connection_manager = Arc::clone(&server.connection_manager);
+        let monitor = tokio::spawn({
+            let connection_manager = Arc::clone(&connection_manager);
+            async move {
+                loop {
+                    connection_manager.recovery_requested().await;
+                    let _ = connection_manager.check_and_update_connection().await;
+                }
+            }
+        });
+        let reach = |state: fn(&RobotState) -> bool| {
+            let connection_manager = Arc::clone(&connection_manager);
+            tokio::time::timeout(Duration::from_secs(5), async move {
+                while !state(&connection_manager.get_state()) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+
+        // The restarted device doesn't answer deviceId until woken
+        connector.silence("deviceId");
+        connector.restart();
+        reach(|state| *state == RobotState::Initializing)
+            .await
+            .unwrap();
+        connector.report_fault(0x11);
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            connection_manager.recovery_requested(),
+        )
+        .await
+        .unwrap();
+        // Taken while it can't be latched
+        assert!(connection_manager.execute_raw(0x01, &[]).await.is_err());
+
+        connector.wake("deviceId");
+        reach(|state| matches!(state, RobotState::Fault { .. }))
+            .await
+            .unwrap();
+        let error = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), Some(Kind::Fault));
+        assert_eq!(server.status()["ready"], false);
+        monitor.abort();
+    }
+
+    #[tokio::test]
+    async fn test_state_changes_are_pushed_to_clients() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+        let mut notifications = server.notifier().subscribe();
+        server.spawn_device_watcher();
+
+        connector.unplug();
+        let _ = server
+            .connection_manager
+            .check_and_update_connection()
+            .await;
+        let message = tokio::time::timeout(Duration::from_secs(5), notifications.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message["method"], "notifications/robot_state_changed");
+        let params = &message["params"];
+        assert_eq!(
+            params["previous"],
+            serde_json::json!({
+                "state": "ready",
+                "device_id": "test-robot",
+                "error": null,
+                "fault": null
+            })
+        );
+        assert_eq!(params["state"]["state"], "disconnected");
+        assert_eq!(params["ready"], false);
+        assert!(params["message"].as_str().unwrap().starts_with("Robot"));
+    }
+
+    #[tokio::test]
+    async fn test_reliable_link_resends_through_noise() {
+        let device = device()
+            .reliable()
+            .respond("getSensorValue", 1234i32.to_le_bytes().to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+        assert!(server.connection_manager.protocol().unwrap().reliable);
+
+        connector.add_noise(2);
+        let result = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 3}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "1234");
+        // Answered again from the last response rather than run twice
+        let runs = connector
+            .calls()
+            .iter()
+            .filter(|(name, _)| name == "getSensorValue")
+            .count();
+        assert_eq!(runs, 1);
+        let link = &server.status()["link"];
+        assert_eq!(link["retransmits"], 2);
+        assert_eq!(link["corrupted"], 2);
+        assert_eq!(link["failures"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_clear_sequence_sent_on_open_and_after_garbled_responses() {
+        let device = device().respond("getSensorValue", 1234i32.to_le_bytes().to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+        // One on opening, then one before each of the handshake's three
+        // commands
+        assert_eq!(connector.clears(), 4);
+        assert_eq!(connector.lone_clears(), 1);
+
+        let before = connector.clears();
+        let arguments = serde_json::json!({"sensorId": 3});
+        server
+            .call_tool("getSensorValue", &arguments)
+            .await
+            .unwrap();
+        assert_eq!(connector.clears(), before + 1);
+        assert_eq!(connector.lone_clears(), 1);
+
+        // The garbled response is followed by a clear of its own, not just
+        // the one before the next command
+        connector.add_noise(1);
+        let _ = server.call_tool("getSensorValue", &arguments).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while connector.lone_clears() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(connector.lone_clears(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_plain_response_with_bad_crc_fails() {
+        let device = device().respond("getSensorValue", 1234i32.to_le_bytes().to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+
+        connector.add_noise(1);
+        let error = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 3}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.data.unwrap()["kind"], "crc_mismatch");
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_round_trips_through_device() {
+        let device = device()
+            .respond("getSensorValue", 1234i32.to_le_bytes().to_vec())
+            .respond("getStatus", b"ok\0".to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+
+        let result = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 3}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "1234");
+
+        let result = server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "ok");
+
+        assert_eq!(
+            connector.calls(),
+            vec![
+                ("deviceId".to_string(), vec![]),
+                ("getSensorValue".to_string(), vec![3, 0]),
+                ("getStatus".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_rejects_unknown_function_and_bad_arguments() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+
+        let err = server
+            .call_tool("fly", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1.5}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+        assert!(
+            err.message
+                .starts_with("/params/arguments/n: expected integer"),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.data.unwrap()["pointer"], "/params/arguments/n");
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": "three"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+        assert_eq!(err.data.unwrap()["pointer"], "/params/arguments/n");
+
+        let err = ToolCallParams::parse(Some(&serde_json::json!({"name": 7}))).unwrap_err();
+        assert!(
+            err.message.starts_with("/params/name: invalid type"),
+            "{}",
+            err.message
+        );
+        let err = ToolCallParams::parse(Some(
+            &serde_json::json!({"name": "blinkLED", "arguments": [1]}),
+        ))
+        .unwrap_err();
+        assert!(
+            err.message.starts_with("/params/arguments: "),
+            "{}",
+            err.message
+        );
+        let err = ToolCallParams::parse(None).unwrap_err();
+        assert_eq!(err.message, "/params: missing field `name`");
+        // Keys are escaped as in RFC 6901
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 3, "a/b~": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.data.unwrap()["pointer"], "/params/arguments/a~1b~0");
+
+        // Requirements can only be installed into a configured venv
+        let err = server
+            .call_tool(
+                "runPythonScript",
+                &serde_json::json!({"script": "print(1)", "requirements": ["numpy"]}),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+        assert!(err.message.contains("--python-venv"));
+
+        assert_eq!(connector.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_rewrites_refuses_and_annotates() {
+        /// Takes centimetres, sends millimetres; `reset` is off limits.
+        struct Policy;
+        impl Middleware for Policy {
+            fn before_call(&self, call: &mut ToolCall) -> Result<(), McpError> {
+                if call.tool == "reset" {
+                    return Err(McpError::new(-32603, "reset is disabled here"));
+                }
+                if let Some(cm) = call.arguments["sensorId"].as_i64() {
+                    call.arguments["sensorId"] = (cm * 10).into();
+                }
+                Ok(())
+            }
+
+            fn after_call(&self, call: &ToolCall, result: &mut Result<Value, McpError>) {
+                if let Ok(result) = result {
+                    result["content"][0]["text"] = format!("{} (via policy)", call.tool).into();
+                }
+            }
+        }
+
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+        let server = server.with_middleware(Arc::new(Policy));
+
+        let result = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 3}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "getSensorValue (via policy)");
+        assert_eq!(connector.calls()[1].1, vec![30, 0]);
+
+        let err = server
+            .call_tool("reset", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "reset is disabled here");
+    }
+
+    #[tokio::test]
+    async fn test_presets_resolve_before_validation() {
+        let config = Config::parse("[presets.sensors]\nleft = 3").unwrap();
+        let (server, connector, _dir) = loopback_server_with(device(), 1, config).await;
+
+        server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": "@left"}))
+            .await
+            .unwrap();
+        assert_eq!(connector.calls()[1].1, vec![3, 0]);
+
+        let err = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": "@right"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+    }
+
+    #[tokio::test]
+    async fn test_tool_stats_count_calls_and_errors() {
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        for _ in 0..2 {
+            server
+                .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+                .await
+                .unwrap();
+        }
+        let _ = server.call_tool("blinkLED", &serde_json::json!({})).await;
+
+        let read = McpServer::resource_contents(STATS_URI, &server.tool_stats());
+        let stats: Value =
+            serde_json::from_str(read["contents"][0]["text"].as_str().unwrap()).unwrap();
+        let blink = &stats["tools"]["blinkLED"];
+        assert_eq!(
+            (blink["calls"].as_u64(), blink["errors"].as_u64()),
+            (Some(3), Some(1))
+        );
+        assert!(blink["latency_ms"]["max"].as_f64().unwrap() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_fails_after_device_unplugged() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+
+        connector.unplug();
+        server
+            .connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32603);
+        assert!(err.message.starts_with("Robot not ready"));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_handshake() {
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        let protocol = server.connection_manager.protocol().unwrap();
+        assert!(protocol.negotiated);
+        assert_eq!((protocol.version, protocol.max_frame_size), (2, 256));
+
+        // Commands too big for the device's frame buffer are refused
+        let (server, connector, _dir) = loopback_server(device().max_frame_size(3), 1).await;
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("3-byte limit"), "{}", err.message);
+        assert_eq!(connector.calls().len(), 1);
+
+        // Older firmware answers with an error frame and is assumed to be v1
+        let (server, _connector, _dir) = loopback_server(device().legacy(), 1).await;
+        assert!(server.connection_manager.get_state().is_ready());
+        assert!(!server.connection_manager.protocol().unwrap().negotiated);
+
+        // Firmware framing that doesn't match --pipeline-depth is reported
+        let connection_manager =
+            ConnectionManager::with_connector(Box::new(LoopbackConnector::new(device())))
+                .with_pipeline_depth(4);
+        assert!(connection_manager
+            .check_and_update_connection()
+            .await
+            .is_err());
+        let message = connection_manager.get_state().error_message();
+        assert!(message.contains("--pipeline-depth 1"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_image_result_is_image_content() {
+        let png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        let mut data = vec![png.len() as u8, 0];
+        data.extend_from_slice(&png);
+        let (server, _connector, _dir) =
+            loopback_server(device().respond("snapshot", data), 1).await;
+
+        let result = server
+            .call_tool("snapshot", &serde_json::json!({}))
+            .await
+            .unwrap();
+        let block = &result["content"][0];
+        assert_eq!(block["type"], "image");
+        assert_eq!(block["mimeType"], "image/png");
+        assert_eq!(block["data"], "iVBORw0KGgo=");
+        assert_eq!(
+            rest::result_text(&result),
+            "data:image/png;base64,iVBORw0KGgo="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_large_image_arrives_in_chunks() {
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        png.extend((0..600).map(|i| i as u8));
+        let mut data = (png.len() as u16).to_le_bytes().to_vec();
+        data.extend_from_slice(&png);
+
+        for depth in [1, 2] {
+            let device = device()
+                .chunked()
+                .max_frame_size(64)
+                .respond("snapshot", data.clone());
+            let device = if depth > 1 {
+                device.sequenced()
+            } else {
+                device
+            };
+            let (server, _connector, _dir) = loopback_server(device, depth).await;
+
+            let result = server
+                .call_tool("snapshot", &serde_json::json!({}))
+                .await
+                .unwrap();
+            let block = &result["content"][0];
+            assert_eq!(block["mimeType"], "image/png");
+            assert_eq!(BASE64.decode(block["data"].as_str().unwrap()).unwrap(), png);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_command_only_when_enabled() {
+        let sensor = || device().respond("getSensorValue", vec![0x2A, 0, 0, 0]);
+        let (server, _connector, _dir) = loopback_server(sensor(), 1).await;
+        let (_, tools) = server.current_tools();
+        assert!(!tools.iter().any(|t| t.name == "rawCommand"));
+        let raw = serde_json::json!({"tag": 2, "args": "0300"});
+        assert!(server.call_tool("rawCommand", &raw).await.is_err());
+
+        let config = Config {
+            enable_raw: true,
+            ..Config::default()
+        };
+        let (server, connector, _dir) = loopback_server_with(sensor(), 1, config).await;
+        let (_, tools) = server.current_tools();
+        assert!(tools.iter().any(|t| t.name == "rawCommand"));
+
+        let result = server.call_tool("rawCommand", &raw).await.unwrap();
+        assert_eq!(text(&result), "2A 00 00 00");
+        let last = connector.calls().pop().unwrap();
+        assert_eq!(last, ("getSensorValue".to_string(), vec![3, 0]));
+
+        // Unknown tags come back as the firmware's error frame
+        let result = server
+            .call_tool("rawCommand", &serde_json::json!({"tag": 99}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "FF 02");
+        let err = server
+            .call_tool("rawCommand", &serde_json::json!({"tag": 2, "args": "xyz"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+    }
+
+    #[tokio::test]
+    async fn test_device_error_in_error_data() {
+        let device = device().respond("getSensorValue", vec![0xFF, 0x04]);
+        let (server, _connector, _dir) = loopback_server(device, 1).await;
+
+        let err = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32603);
+        assert!(err.message.contains("hardware fault"), "{}", err.message);
+        let data = err.data.unwrap();
+        assert_eq!(
+            data["device_error"],
+            serde_json::json!({"code": 4, "name": "hardware_fault"})
+        );
+        assert_eq!(data["kind"], "hardware_fault");
+    }
+
+    #[tokio::test]
+    async fn test_error_data_and_configured_codes() {
+        let device = device().respond("getSensorValue", vec![0xFF, 0x02]);
+        let config = Config {
+            error_codes: [(Kind::Dispatch, -32010)].into_iter().collect(),
+            ..Config::default()
+        };
+        let (server, _connector, _dir) = loopback_server_with(device, 1, config).await;
+        let tag = server
+            .current_tools()
+            .0
+            .unwrap()
+            .functions
+            .iter()
+            .find(|f| f.name == "getSensorValue")
+            .unwrap()
+            .tag;
+
+        let err = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32010);
+        let data = err.data.unwrap();
+        assert_eq!(data["kind"], "dispatch");
+        assert_eq!(data["retryable"], false);
+        assert_eq!(data["tag"], tag);
+        assert!(data["latency_ms"].is_u64());
+        assert_eq!(data["robot_state"]["state"], "ready");
+
+        // Built-in tools have no tag
+        let err = server
+            .call_tool("wait", &serde_json::json!({"ms": -1}))
+            .await
+            .unwrap_err();
+        let data = err.data.unwrap();
+        assert_eq!(
+            (err.code, data["kind"].as_str()),
+            (-32602, Some("validation"))
+        );
+        assert_eq!(data["tag"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_tool_socket_refuses_runpythonscript() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        let server = Arc::new(server);
+        server.start_tool_bridge().unwrap();
+        let stream = tokio::net::UnixStream::connect(server.tool_socket.get().unwrap())
+            .await
+            .unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        let mut replies = Vec::new();
+        for request in [
+            serde_json::json!({"name": "runPythonScript", "arguments": {"script": "pass"}}),
+            serde_json::json!({"name": "getStatus"}),
+        ] {
+            writer
+                .write_all(format!("{}\n", request).as_bytes())
+                .await
+                .unwrap();
+            let reply = lines.next_line().await.unwrap().unwrap();
+            replies.push(serde_json::from_str::<Value>(&reply).unwrap());
+        }
+        assert_eq!(
+            replies[0]["error"]["message"],
+            "Python scripts can't call runPythonScript"
+        );
+        assert!(replies[1]["result"].is_object(), "{}", replies[1]);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_tool_errors_in_the_chosen_language() {
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        let server = server.with_messages(Messages::new("es").unwrap());
+
+        let err = server
+            .call_tool(
+                "scheduleTool",
+                &serde_json::json!({"name": "nope", "cancel": true}),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Programación no encontrada: nope");
+        let err = server
+            .call_tool(
+                "runPythonScript",
+                &serde_json::json!({"script": "pass", "timeout": 301}),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "El parámetro 'timeout' no puede superar los 300 segundos"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_messages_in_the_chosen_language() {
+        let device = device().respond("getSensorValue", vec![0xFF, 0x04]);
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+        let server = server.with_messages(Messages::new("de").unwrap());
+
+        let err = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+            .await
+            .unwrap_err();
+        assert!(
+            err.message.starts_with("Fehler bei der Ausführung: "),
+            "{}",
+            err.message
+        );
+        let data = err.data.unwrap();
+        assert_eq!(data["kind"], "hardware_fault");
+        assert_eq!(
+            data["suggestion"],
+            "Prüfe die Verkabelung und Stromversorgung des Roboters"
+        );
+
+        connector.unplug();
+        server
+            .connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Roboter nicht bereit: Roboter nicht verbunden - USB-Verbindung prüfen"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors_carry_their_kind() {
+        let device = device().respond("getSensorValue", vec![0xFF, 0x02]);
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+        let kind = |err: McpError| (err.code, err.data.unwrap()["kind"].clone());
+
+        let err = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), (-32603, "dispatch".into()));
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": "many"}))
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), (-32602, "validation".into()));
+        let err = server
+            .call_tool("fly", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), (-32602, "validation".into()));
+
+        connector.unplug();
+        server
+            .connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(kind(err), (-32603, "not_ready".into()));
+    }
+
+    #[tokio::test]
+    async fn test_busy_device_retried_until_free() {
+        let device = device()
+            .respond("getSensorValue", 7i32.to_le_bytes().to_vec())
+            .busy("getSensorValue", 2);
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+
+        let result = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "7");
+        let attempts = connector
+            .calls()
+            .iter()
+            .filter(|(name, _)| name == "getSensorValue")
+            .count();
+        assert_eq!(attempts, 3);
+        assert_eq!(server.connection_manager.stats().errors.dispatch, 0);
+    }
+
+    #[tokio::test]
+    async fn test_busy_error_after_bounded_wait() {
+        let device = device().busy("getSensorValue", usize::MAX);
+        let config = Config {
+            busy_wait_ms: 200,
+            ..Config::default()
+        };
+        let (server, _connector, _dir) = loopback_server_with(device, 1, config).await;
+
+        let started = std::time::Instant::now();
+        let err = server
+            .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(err.message.contains("still busy"), "{}", err.message);
+        assert_eq!(err.data.unwrap()["device_error"]["name"], "robot_busy");
+    }
+
+    #[tokio::test]
+    async fn test_await_motion_complete_on_event() {
+        for (device, depth) in [(device().events(), 1), (device().events().sequenced(), 4)] {
+            let (server, _connector, _dir) = loopback_server(device, depth).await;
+            let result = server
+                .call_tool("moveTo", &serde_json::json!({"angle": 90}))
+                .await
+                .unwrap();
+            assert_eq!(text(&result), "Action started; it may still be running");
+
+            let result = server
+                .call_tool("awaitMotionComplete", &serde_json::json!({"timeout": 5}))
+                .await
+                .unwrap();
+            assert!(text(&result).starts_with("Motion complete after"));
+        }
+
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        let err = server
+            .call_tool("awaitMotionComplete", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("no motion events"), "{}", err.message);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_logs_frames_instead_of_sending() {
+        let config = Config {
+            dry_run: true,
+            ..Config::default()
+        };
+        let (server, connector, _dir) = loopback_server_with(device(), 1, config).await;
+        let before = connector.calls().len();
+
+        let result = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 3}))
+            .await
+            .unwrap();
+        let frame = format!("01 03 00 {:02X}", protocol::crc8(&[0x01, 0x03, 0x00]));
+        assert_eq!(
+            text(&result),
+            format!("Dry run: not sent; the frame would be {}", frame)
+        );
+        // Still validated
+        let error = server
+            .call_tool("blinkLED", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, -32602);
+        server
+            .call_tool("moveTo", &serde_json::json!({"angle": 90}))
+            .await
+            .unwrap();
+        server
+            .call_tool("awaitMotionComplete", &serde_json::json!({"timeout": 1}))
+            .await
+            .unwrap();
+        assert_eq!(connector.calls().len(), before);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_offers_only_functions_that_read() {
+        let config = Config {
+            read_only: true,
+            enable_raw: true,
+            ..Config::default()
+        };
+        let (server, connector, _dir) = loopback_server_with(device(), 1, config).await;
+
+        let (_, tools) = server.current_tools();
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        for name in ["getStatus", "snapshot", "callSequence"] {
+            assert!(names.contains(&name), "{} missing from {:?}", name, names);
+        }
+        for name in [
+            "blinkLED",
+            "moveTo",
+            "stop",
+            "rawCommand",
+            "emergencyStop",
+            "selfTest",
+        ] {
+            assert!(!names.contains(&name), "{} offered in {:?}", name, names);
+        }
+
+        let error = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, -32602);
+        assert!(error.message.contains("read-only"), "{}", error.message);
+        let steps = serde_json::json!({"steps": [{"name": "getStatus"}, {"name": "moveTo",
+            "arguments": {"angle": 90}}]});
+        assert!(server.call_tool("callSequence", &steps).await.is_err());
+        assert!(server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .is_ok());
+        assert_eq!(connector.calls().last().unwrap().0, "getStatus");
+        assert!(!connector.calls().iter().any(|(name, _)| name == "moveTo"));
+    }
+
+    #[tokio::test]
+    async fn test_self_test_reports_each_step() {
+        let device = device().respond("getSensorValue", 0i32.to_le_bytes().to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+        let (_, tools) = server.current_tools();
+        assert!(tools.iter().any(|t| t.name == "selfTest"));
+
+        let result = server
+            .call_tool("selfTest", &serde_json::json!({}))
+            .await
+            .unwrap();
+        let report: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(report["passed"], 1);
+        assert_eq!(report["failed"], 1);
+        assert_eq!(report["steps"][0]["desc"], "Sensor 1");
+        assert_eq!(report["steps"][0]["passed"], false);
+        assert_eq!(report["steps"][0]["error"], "below the minimum of 1");
+        assert_eq!(report["steps"][1]["passed"], true);
+        assert_eq!(connector.calls().last().unwrap().0, "blinkLED");
+    }
+
+    #[tokio::test]
+    async fn test_unpipelined_timeouts_counted() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+        // Enough round trips for getStatus to earn a deadline
+        for _ in 0..8 {
+            server
+                .call_tool("getStatus", &serde_json::json!({}))
+                .await
+                .unwrap();
+        }
+        connector.silence("getStatus");
+
+        let error = server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.data.unwrap()["kind"], "timeout");
+        assert_eq!(server.status()["stats"]["errors"]["timeout"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_silent_device_times_out_and_frees_the_port() {
+        // blinkLED has no round trips yet, so it gets the fixed deadline
+        let (server, _connector, _dir) = loopback_server(device().silent("blinkLED"), 1).await;
+
+        let error = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.data.unwrap()["kind"], "timeout");
+        server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tokens_match_only_exactly() {
+        assert!(tokens_match(b"s3cret", b"s3cret"));
+        assert!(!tokens_match(b"s3creT", b"s3cret"));
+        assert!(!tokens_match(b"s3cre", b"s3cret"));
+        assert!(!tokens_match(b"s3crets", b"s3cret"));
+        assert!(!tokens_match(b"", b"s3cret"));
+    }
+
+    #[tokio::test]
+    async fn test_health_details_only_for_authorized_callers() {
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        let health = |authorized: bool| {
+            let server = &server;
+            async move {
+                let response = server.handle_health(authorized).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        let details = health(true).await;
+        assert_eq!(details["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(details["checks"]["device"]["device_id"], "test-robot");
+        assert_eq!(health(false).await, serde_json::json!({ "status": "ok" }));
+    }
+
+    #[tokio::test]
+    async fn test_transcript_only_for_the_callers_own_session() {
+        let (server, _connector, _dir) = loopback_server(device(), 1).await;
+        let open = |port: u16| {
+            server
+                .sessions
+                .open(([127, 0, 0, 1], port).into(), None)
+                .unwrap()
+        };
+        let (alice, bob) = (open(1), open(2));
+        for session in [&alice, &bob] {
+            server.transcript.record(TranscriptEntry::Request {
+                session: session.clone(),
+                message: serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+            });
+        }
+        let fetch = |session: Option<&str>| {
+            let mut headers = hyper::HeaderMap::new();
+            if let Some(session) = session {
+                headers.insert(SESSION_HEADER, session.parse().unwrap());
+            }
+            server.handle_transcript(&headers)
+        };
+
+        let response = fetch(Some(&alice));
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let transcript: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(transcript["session"], alice.as_str());
+        assert_eq!(transcript["entries"].as_array().unwrap().len(), 1);
+
+        // No header, or one naming a session that is no longer open
+        assert_eq!(fetch(None).status(), StatusCode::NOT_FOUND);
+        server.sessions.close(&bob);
+        assert_eq!(fetch(Some(&bob)).status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_exclusive_control_takeover() {
+        let config = Config {
+            exclusive_control: true,
+            ..Config::default()
+        };
+        let (server, _connector, _dir) = loopback_server_with(device(), 1, config).await;
+        let alice = server
+            .sessions
+            .open(([127, 0, 0, 1], 1).into(), Some("alice".to_string()))
+            .unwrap();
+        let bob = server
+            .sessions
+            .resolve(None, ([127, 0, 0, 1], 2).into())
+            .unwrap();
+        let call = |tool: &str| McpRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(serde_json::json!(1)),
+            method: "tools/call".to_string(),
+            params: Some(serde_json::json!({"name": tool, "arguments": {}})),
+        };
+
+        let response = server.handle_tools_call(&call("getStatus"), &alice).await;
+        assert!(response.error.is_none());
+        let response = server.handle_tools_call(&call("getStatus"), &bob).await;
+        let error = response.error.unwrap();
+        assert_eq!(error.code, -32001);
+        assert!(error.message.contains("alice"), "{}", error.message);
+
+        let response = server.handle_tools_call(&call("takeControl"), &bob).await;
+        assert!(text(&response.result.unwrap()).contains("taken over from alice"));
+        let response = server.handle_tools_call(&call("getStatus"), &bob).await;
+        assert!(response.error.is_none());
+        let response = server.handle_tools_call(&call("getStatus"), &alice).await;
+        assert_eq!(response.error.unwrap().code, -32001);
+    }
+
+    /// Serve `server` over HTTP on a local port, as `start` does.
+    async fn serve_http(server: Arc<McpServer>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = listener.accept().await.unwrap();
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let service_server = Arc::clone(&server);
+                    let _ = http_server::builder(&server.config.http)
+                        .serve_connection(
+                            hyper_util::rt::TokioIo::new(stream),
+                            service_fn(move |req| {
+                                let server = Arc::clone(&service_server);
+                                async move { server.handle_request(req, peer).await }
+                            }),
+                        )
+                        .await;
+                    server.connection_closed(peer);
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_rest_calls_respect_exclusive_control() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let config = Config {
+            exclusive_control: true,
+            ..Config::default()
+        };
+        let (server, _connector, _dir) = loopback_server_with(device(), 1, config).await;
+        let server = Arc::new(server);
+        let addr = serve_http(Arc::clone(&server)).await;
+        let rest_call = || async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(
+                    b"POST /api/tools/getStatus HTTP/1.1\r\nHost: test\r\n\
+                      Content-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // With nobody in control, the REST call's connection gets it
+        assert!(rest_call().await.starts_with("HTTP/1.1 200"));
+
+        let alice = server
+            .sessions
+            .open(([127, 0, 0, 1], 1).into(), Some("alice".to_string()))
+            .unwrap();
+        server.take_control(&alice);
+
+        let response = rest_call().await;
+        assert!(response.starts_with("HTTP/1.1 409"), "{}", response);
+        assert!(response.contains("-32001"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_status_counts_calls_and_errors() {
+        let device = device().respond("blinkLED", vec![0xFF, 0x02]);
+        let (server, _connector, _dir) = loopback_server(device, 1).await;
+        server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .unwrap();
+
+        let stats = server.connection_manager.stats();
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.errors.dispatch, 1);
+        assert_eq!(stats.errors.crc + stats.errors.timeout, 0);
+        assert_eq!(stats.queue_depth, 0);
+        assert!(stats.last_reconnect.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_manifest_version_mismatch_until_manifest_updated() {
+        let (server, _connector, dir) = loopback_server(device().firmware_version("v2"), 1).await;
+        assert_eq!(
+            server.connection_manager.get_state(),
+            RobotState::VersionMismatch {
+                device_id: "test-robot".to_string(),
+                firmware: "v2".to_string(),
+                manifest: "v1".to_string(),
+            }
+        );
+        let err = server
+            .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+            .await
+            .unwrap_err();
+        assert!(
+            err.message.contains("flash the firmware"),
+            "{}",
+            err.message
+        );
+
+        let mut manifest = manifest_json();
+        manifest["version"] = "v2".into();
+        std::fs::write(dir.path().join("test-robot.json"), manifest.to_string()).unwrap();
+        server
+            .connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert!(server.connection_manager.get_state().is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_missing_manifest_offers_generic_tools() {
+        let manifest = serde_json::from_value(manifest_json()).unwrap();
+        let device = LoopbackDevice::new("mystery-bot", manifest);
+        let (server, _connector, _dir) = loopback_server(device, 1).await;
+        assert!(server.connection_manager.get_state().is_ready());
+
+        let request: McpRequest = serde_json::from_value(
+            serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+        )
+        .unwrap();
+        let result = server.handle_tools_list(&request).await.result.unwrap();
+        let names: Vec<&str> = result["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["deviceId", "getManifestVersion"]);
+        let suggestion = result["_status"]["suggestion"].as_str().unwrap();
+        assert!(suggestion.contains("--enable-raw"), "{}", suggestion);
+        assert_eq!(result["_status"]["robot_state"]["device_id"], "mystery-bot");
+        assert_eq!(
+            result["_status"]["available_manifests"],
+            serde_json::json!(["test-robot"])
+        );
+        assert_eq!(result["_status"]["firmware_manifest_version"], "v1");
+        let message = result["_status"]["message"].as_str().unwrap();
+        assert!(message.contains("mystery-bot.json"), "{}", message);
+
+        let id = server
+            .call_tool("deviceId", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(text(&id), "mystery-bot");
+        let raw = serde_json::json!({"tag": 2, "args": "01 00"});
+        assert!(server.call_tool("rawCommand", &raw).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_debounced_function_sends_repeats_once() {
+        let (server, connector, dir) = loopback_server(device(), 1).await;
+        let mut manifest = manifest_json();
+        manifest["functions"][0]["debounce_ms"] = 5000.into();
+        std::fs::write(dir.path().join("test-robot.json"), manifest.to_string()).unwrap();
+        server.manifest_manager.invalidate("test-robot");
+
+        let once = serde_json::json!({"n": 1});
+        let (first, second) = tokio::join!(
+            server.call_tool("blinkLED", &once),
+            server.call_tool("blinkLED", &once)
+        );
+        assert_eq!(first.unwrap(), second.unwrap());
+        server
+            .call_tool("blinkLED", &serde_json::json!({"n": 2}))
+            .await
+            .unwrap();
+        let blinks = connector
+            .calls()
+            .iter()
+            .filter(|(name, _)| name == "blinkLED")
+            .count();
+        assert_eq!(blinks, 2);
+    }
+
+    #[tokio::test]
+    async fn test_python_script_calls_tools_through_socket() {
+        if python_runner::python_version(std::path::Path::new("python3"))
+            .await
+            .is_none()
+        {
+            return;
+        }
+        let device = device().respond("getSensorValue", 1234i32.to_le_bytes().to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+        let server = Arc::new(server);
+        server.start_tool_bridge().unwrap();
+
+        let script = "print(tools.getSensorValue(sensorId=7))\n\
+                      try:\n    tools.blinkLED(n=40000)\n\
+                      except ValueError as exc:\n    print('rejected')";
+        let result = server
+            .call_tool("runPythonScript", &serde_json::json!({"script": script}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "1234\nrejected");
+
+        // The out-of-range call never reached the device
+        assert_eq!(
+            connector.calls(),
+            vec![
+                ("deviceId".to_string(), vec![]),
+                ("getSensorValue".to_string(), vec![7, 0]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_calls_match_responses_by_sequence() {
+        let device = device()
+            .sequenced()
+            .respond("getSensorValue", (-7i32).to_le_bytes().to_vec());
+        let (server, _connector, _dir) = loopback_server(device, 4).await;
+        let server = Arc::new(server);
+
+        let calls = (0..8).map(|_| {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                server
+                    .call_tool("getSensorValue", &serde_json::json!({"sensorId": 1}))
+                    .await
+            })
+        });
+        for call in calls.collect::<Vec<_>>() {
+            let result = call.await.unwrap().unwrap();
+            assert_eq!(text(&result), "-7");
+        }
+    }
+
+    fn spawn_calls(server: &Arc<McpServer>, count: i64) -> Vec<tokio::task::JoinHandle<Value>> {
+        (0..count)
+            .map(|n| {
+                let server = Arc::clone(server);
+                tokio::spawn(async move {
+                    match server
+                        .call_tool("getSensorValue", &serde_json::json!({"sensorId": n}))
+                        .await
+                    {
+                        Ok(result) => result,
+                        Err(e) => serde_json::json!({ "code": e.code }),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_calls_alongside_connection_checks() {
+        for (device, depth) in [(device(), 1), (device().sequenced(), 4)] {
+            let device = device.respond("getSensorValue", 5i32.to_le_bytes().to_vec());
+            let (server, connector, _dir) = loopback_server(device, depth).await;
+            let server = Arc::new(server);
+
+            let checker = {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        server
+                            .connection_manager
+                            .check_and_update_connection()
+                            .await
+                            .unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            };
+            let calls = spawn_calls(&server, 20);
+
+            tokio::time::timeout(Duration::from_secs(10), async {
+                for call in calls {
+                    assert_eq!(text(&call.await.unwrap()), "5");
+                }
+                checker.await.unwrap();
+            })
+            .await
+            .expect("calls and connection checks deadlocked");
+
+            assert!(server.connection_manager.get_state().is_ready());
+            assert_eq!(connector.calls().len(), 21);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_call_sequence_is_not_interleaved() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+        let server = Arc::new(server);
+
+        let steps = serde_json::json!({"steps": [
+            {"name": "blinkLED", "arguments": {"n": 1}, "delay_ms": 20},
+            {"name": "blinkLED", "arguments": {"n": 2}, "delay_ms": 20},
+            {"name": "getStatus"}
+        ]});
+        let sequence = {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move { server.call_tool("callSequence", &steps).await })
+        };
+        for call in spawn_calls(&server, 10) {
+            call.await.unwrap();
+        }
+        let result = sequence.await.unwrap().unwrap();
+        let output: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(output["completed"], 3);
+
+        let names: Vec<String> = connector
+            .calls()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let start = names.iter().position(|n| n == "blinkLED").unwrap();
+        assert_eq!(
+            names[start..start + 3],
+            ["blinkLED", "blinkLED", "getStatus"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recorded_macro_replays_calls() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+        let tool_names = |server: &McpServer| -> Vec<String> {
+            server
+                .current_tools()
+                .1
+                .into_iter()
+                .map(|t| t.name)
+                .collect()
+        };
+        assert!(!tool_names(&server).contains(&"runMacro".to_string()));
+
+        server.macros.start_recording("demo", 2).unwrap();
+        server
+            .call_tool("blinkLED", &serde_json::json!({"n": 3}))
+            .await
+            .unwrap();
+        // Rejected calls are not recorded
+        assert!(server
+            .call_tool("fly", &serde_json::json!({}))
+            .await
+            .is_err());
+        server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(server.macros.recording().is_none());
+        assert!(tool_names(&server).contains(&"runMacro".to_string()));
+
+        let result = server
+            .call_tool("runMacro", &serde_json::json!({"name": "demo"}))
+            .await
+            .unwrap();
+        let output: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(output["completed"], 2);
+        let calls = connector.calls();
+        assert_eq!(calls[3..], calls[1..3]);
+
+        let err = server
+            .call_tool("runMacro", &serde_json::json!({"name": "missing"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+    }
+
+    #[tokio::test]
+    async fn test_call_sequence_stops_at_failed_step() {
+        // blinkLED's frame doesn't fit, getStatus's does
+        let (server, connector, _dir) = loopback_server(device().max_frame_size(3), 1).await;
+
+        let steps = serde_json::json!({"steps": [
+            {"name": "getStatus"},
+            {"name": "blinkLED", "arguments": {"n": 1}},
+            {"name": "getStatus"}
+        ]});
+        let result = server.call_tool("callSequence", &steps).await.unwrap();
+        let output: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(output["completed"], 1);
+        assert_eq!(output["steps"].as_array().unwrap().len(), 2);
+        assert!(output["steps"][1]["error"].is_string());
+        assert_eq!(connector.calls().len(), 2);
+
+        // Nothing is sent when any step is invalid
+        let steps = serde_json::json!({"steps": [
+            {"name": "getStatus"},
+            {"name": "blinkLED", "arguments": {}}
+        ]});
+        let err = server.call_tool("callSequence", &steps).await.unwrap_err();
+        assert_eq!(err.code, -32602);
+        assert_eq!(connector.calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wait_pauses_without_sending() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+
+        let started = Instant::now();
+        let result = server
+            .call_tool("wait", &serde_json::json!({"ms": 50}))
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "Waited 50 ms");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        let err = server
+            .call_tool("wait", &serde_json::json!({"ms": 60_001}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+
+        let steps = serde_json::json!({"steps": [
+            {"name": "getStatus"},
+            {"name": "wait", "arguments": {"ms": 50}},
+            {"name": "getStatus"}
+        ]});
+        let started = Instant::now();
+        let result = server.call_tool("callSequence", &steps).await.unwrap();
+        let output: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(output["completed"], 3);
+        assert_eq!(output["steps"][1]["result"], "Waited 50 ms");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        // The handshake and two getStatus calls
+        assert_eq!(connector.calls().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_tool_records_results() {
+        let device = device().respond("getSensorValue", 7i32.to_le_bytes().to_vec());
+        let (server, connector, _dir) = loopback_server(device, 1).await;
+
+        let err = server
+            .call_tool(
+                "scheduleTool",
+                &serde_json::json!({"name": "sensor", "function": "fly", "every": "1s"}),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, -32602);
+
+        let schedule = serde_json::json!({
+            "name": "sensor",
+            "function": "getSensorValue",
+            "arguments": {"sensorId": 1},
+            "every": "1s"
+        });
+        server.call_tool("scheduleTool", &schedule).await.unwrap();
+
+        // The first call runs right away
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.scheduler.get("sensor").unwrap().samples.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("scheduled call never ran");
+        let result = server
+            .call_tool("scheduleTool", &serde_json::json!({"name": "sensor"}))
+            .await
+            .unwrap();
+        let report: Value = serde_json::from_str(text(&result)).unwrap();
+        assert_eq!(report["function"], "getSensorValue");
+        assert_eq!(report["samples"][0]["result"], "7");
+        assert_eq!(connector.calls()[1].0, "getSensorValue");
+
+        server
+            .call_tool(
+                "scheduleTool",
+                &serde_json::json!({"name": "sensor", "cancel": true}),
+            )
+            .await
+            .unwrap();
+        assert!(server.scheduler.report().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_saturated_queue_refuses_calls() {
+        let config = Config {
+            max_queue_depth: 1,
+            ..Config::default()
+        };
+        let (server, connector, _dir) =
+            loopback_server_with(device().silent("blinkLED"), 1, config).await;
+        let server = Arc::new(server);
+
+        let stuck = {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                server
+                    .call_tool("blinkLED", &serde_json::json!({"n": 1}))
+                    .await
+            })
+        };
+        while server.connection_manager.queue().0 == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let error = server
+            .call_tool("getStatus", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, -32003);
+        let data = error.data.unwrap();
+        assert_eq!(data["queue_depth"], 1);
+        assert_eq!(data["kind"], "queue_full");
+
+        connector.unplug();
+        server
+            .connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+        assert!(stuck.await.unwrap().is_err());
+        assert_eq!(server.connection_manager.queue().0, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_emergency_stop_jumps_the_queue() {
+        let (server, connector, _dir) = loopback_server(device().silent("blinkLED"), 1).await;
+        let server = Arc::new(server);
+        let call = |tool: &'static str, arguments: Value| {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move { server.call_tool(tool, &arguments).await })
+        };
+        let queued = |depth: usize| {
+            let connection_manager = Arc::clone(&server.connection_manager);
+            async move {
+                while connection_manager.queue().0 < depth {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                // Past the count, into the port's queue
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        let stuck = call("blinkLED", serde_json::json!({"n": 1}));
+        queued(1).await;
+        let statuses: Vec<_> = (0..3)
+            .map(|_| call("getStatus", serde_json::json!({})))
+            .collect();
+        queued(4).await;
+        let stop = call("emergencyStop", serde_json::json!({}));
+        queued(5).await;
+
+        connector.wake("blinkLED");
+        assert!(stop.await.unwrap().is_ok());
+        assert!(stuck.await.unwrap().is_ok());
+        for status in statuses {
+            assert!(status.await.unwrap().is_ok());
+        }
+        let calls: Vec<_> = connector
+            .calls()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            calls[calls.len() - 5..],
+            ["blinkLED", "stop", "getStatus", "getStatus", "getStatus"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_unplug_fails_queued_calls_without_hanging() {
+        let (server, connector, _dir) = loopback_server(device(), 1).await;
+        let server = Arc::new(server);
+
+        let calls = spawn_calls(&server, 20);
+        connector.unplug();
+        server
+            .connection_manager
+            .check_and_update_connection()
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            for call in calls {
+                let result = call.await.unwrap();
+                // Calls that beat the unplug succeed; the rest fail cleanly
+                assert!(result.get("content").is_some() || result["code"] == -32603);
+            }
+        })
+        .await
+        .expect("calls hung after the port closed");
+        assert!(!server.connection_manager.is_port_open());
     }
 }
@@ -0,0 +1,307 @@
+//! MCP client sessions: which clients are connected and, with
+//! `exclusive_control`, which one may call tools.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub use mcp_client::SESSION_HEADER;
+
+/// Sessions not heard from for this long are dropped.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// One connected client as shown in `/status`, without its ID: whoever
+/// knows that can act as the session.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    /// `clientInfo.name` from `initialize`
+    pub client: Option<String>,
+    pub peer: String,
+    pub connected_secs: u64,
+    pub idle_secs: u64,
+    /// Allowed to call tools while `exclusive_control` is on
+    pub controlling: bool,
+}
+
+struct Session {
+    client: Option<String>,
+    peer: SocketAddr,
+    /// Keyed by connection rather than by header, so it ends with it
+    per_connection: bool,
+    started: Instant,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    sessions: HashMap<String, Session>,
+    controller: Option<String>,
+}
+
+pub struct Sessions {
+    max_clients: Option<usize>,
+    exclusive: bool,
+    inner: Mutex<Inner>,
+}
+
+impl Sessions {
+    pub fn new(max_clients: Option<usize>, exclusive: bool) -> Self {
+        Self {
+            max_clients,
+            exclusive,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Start a session for an `initialize` request and return its ID.
+    pub fn open(&self, peer: SocketAddr, client: Option<String>) -> Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.insert(id.clone(), peer, client, false)?;
+        Ok(id)
+    }
+
+    /// The session a request belongs to: the one named by its header, or
+    /// else one per HTTP connection, created on first use so clients that
+    /// skip `initialize` keep working. A header naming no session, e.g. one
+    /// from before a restart, is refused with [`UnknownSession`].
+    pub fn resolve(&self, header: Option<&str>, peer: SocketAddr) -> Result<String> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::expire(&mut inner);
+        let id = match header {
+            Some(id) => {
+                let session = inner.sessions.get_mut(id).ok_or(UnknownSession)?;
+                session.last_seen = Instant::now();
+                return Ok(id.to_string());
+            }
+            None => format!("conn-{}", peer),
+        };
+        if let Some(session) = inner.sessions.get_mut(&id) {
+            session.last_seen = Instant::now();
+            return Ok(id);
+        }
+        drop(inner);
+        self.insert(id.clone(), peer, None, true)?;
+        Ok(id)
+    }
+
+    fn insert(
+        &self,
+        id: String,
+        peer: SocketAddr,
+        client: Option<String>,
+        per_connection: bool,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::expire(&mut inner);
+        if let Some(max) = self.max_clients {
+            if inner.sessions.len() >= max {
+                return Err(anyhow!(
+                    "Too many clients: {} of {} sessions in use",
+                    inner.sessions.len(),
+                    max
+                ));
+            }
+        }
+        let now = Instant::now();
+        inner.sessions.insert(
+            id,
+            Session {
+                client,
+                peer,
+                per_connection,
+                started: now,
+                last_seen: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// Whether `id` names a session that is still open.
+    pub fn is_open(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        Self::expire(&mut inner);
+        inner.sessions.contains_key(id)
+    }
+
+    /// End a session, e.g. on `DELETE /mcp`, giving up control if it had it.
+    pub fn close(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.controller.as_deref() == Some(id) {
+            inner.controller = None;
+        }
+        inner.sessions.remove(id).is_some()
+    }
+
+    /// End the per-connection session of a closed HTTP connection.
+    pub fn close_connection(&self, peer: SocketAddr) {
+        let id = format!("conn-{}", peer);
+        let per_connection = {
+            let inner = self.inner.lock().unwrap();
+            inner.sessions.get(&id).is_some_and(|s| s.per_connection)
+        };
+        if per_connection {
+            self.close(&id);
+        }
+    }
+
+    /// Whether `id` may call tools. With exclusive control, the first
+    /// session to call one becomes the controller.
+    pub fn check_control(&self, id: &str) -> std::result::Result<(), SessionInfo> {
+        if !self.exclusive {
+            return Ok(());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        Self::expire(&mut inner);
+        if let Some(controller) = inner.controller.as_deref().filter(|c| *c != id) {
+            if let Some(session) = inner.sessions.get(controller) {
+                return Err(Self::info(session, true));
+            }
+        }
+        inner.controller = Some(id.to_string());
+        Ok(())
+    }
+
+    /// Make `id` the controller, returning the session it took over from.
+    pub fn take_control(&self, id: &str) -> Option<SessionInfo> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::expire(&mut inner);
+        let previous = inner
+            .controller
+            .replace(id.to_string())
+            .filter(|previous| previous != id)?;
+        let session = inner.sessions.get(&previous)?;
+        Some(Self::info(session, false))
+    }
+
+    /// Give up control if `id` has it.
+    pub fn release_control(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.controller.as_deref() != Some(id) {
+            return false;
+        }
+        inner.controller = None;
+        true
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::expire(&mut inner);
+        let mut list: Vec<SessionInfo> = inner
+            .sessions
+            .iter()
+            .map(|(id, session)| {
+                let controlling = inner.controller.as_deref() == Some(id.as_str());
+                Self::info(session, controlling)
+            })
+            .collect();
+        list.sort_by_key(|info| std::cmp::Reverse(info.connected_secs));
+        list
+    }
+
+    fn info(session: &Session, controlling: bool) -> SessionInfo {
+        SessionInfo {
+            client: session.client.clone(),
+            peer: session.peer.to_string(),
+            connected_secs: session.started.elapsed().as_secs(),
+            idle_secs: session.last_seen.elapsed().as_secs(),
+            controlling,
+        }
+    }
+
+    fn expire(inner: &mut Inner) {
+        inner
+            .sessions
+            .retain(|_, session| session.last_seen.elapsed() < SESSION_IDLE_TIMEOUT);
+        if let Some(controller) = &inner.controller {
+            if !inner.sessions.contains_key(controller) {
+                inner.controller = None;
+            }
+        }
+    }
+}
+
+/// An `Mcp-Session-Id` header naming no open session, answered with 404 so
+/// the client sends `initialize` again.
+#[derive(Debug)]
+pub struct UnknownSession;
+
+impl fmt::Display for UnknownSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown or expired session; send initialize to start a new one"
+        )
+    }
+}
+
+impl std::error::Error for UnknownSession {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let sessions = Sessions::new(Some(2), false);
+        let first = sessions
+            .open(peer(1), Some("inspector".to_string()))
+            .unwrap();
+        sessions.resolve(None, peer(2)).unwrap();
+        assert!(sessions.open(peer(3), None).is_err());
+
+        // Known sessions still get through, and closing one makes room
+        assert_eq!(sessions.resolve(Some(&first), peer(1)).unwrap(), first);
+        sessions.close_connection(peer(2));
+        assert!(sessions.open(peer(3), None).is_ok());
+        assert_eq!(sessions.list().len(), 2);
+    }
+
+    #[test]
+    fn test_unknown_session_ids_are_refused() {
+        let sessions = Sessions::new(None, true);
+        let alice = sessions.open(peer(1), None).unwrap();
+        assert!(sessions.check_control(&alice).is_ok());
+
+        let err = sessions.resolve(Some("made-up"), peer(2)).unwrap_err();
+        assert!(err.is::<UnknownSession>());
+        assert_eq!(sessions.list().len(), 1);
+        // Nor can a closed session be picked up again
+        sessions.close(&alice);
+        assert!(sessions.resolve(Some(&alice), peer(1)).is_err());
+    }
+
+    #[test]
+    fn test_exclusive_control_and_takeover() {
+        let sessions = Sessions::new(None, true);
+        let alice = sessions.open(peer(1), Some("alice".to_string())).unwrap();
+        let bob = sessions.open(peer(2), Some("bob".to_string())).unwrap();
+
+        // First caller takes control
+        assert!(sessions.check_control(&alice).is_ok());
+        let controller = sessions.check_control(&bob).unwrap_err();
+        assert_eq!(controller.client.as_deref(), Some("alice"));
+
+        let previous = sessions.take_control(&bob).unwrap();
+        assert_eq!(previous.client.as_deref(), Some("alice"));
+        assert!(sessions.check_control(&bob).is_ok());
+        assert!(sessions.check_control(&alice).is_err());
+
+        // Closing the controlling session frees the robot
+        sessions.close(&bob);
+        assert!(sessions.check_control(&alice).is_ok());
+        assert!(sessions.release_control(&alice));
+        assert!(!sessions.list()[0].controlling);
+    }
+}
@@ -1,23 +1,80 @@
 use anyhow::{anyhow, Result};
+use bytes::BufMut;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tracing::{debug, warn};
 
 // SLIP protocol constants
-const SLIP_END: u8 = 0xC0;
-const SLIP_ESC: u8 = 0xDB;
+pub const SLIP_END: u8 = 0xC0;
+pub const SLIP_ESC: u8 = 0xDB;
 const SLIP_ESC_END: u8 = 0xDC;
 const SLIP_ESC_ESC: u8 = 0xDD;
 const SLIP_CLEAR: u8 = 0xDE;
 
+/// ESC CLEAR: drops any partial frame at the other end, so the next END
+/// starts a frame whatever noise came before.
+pub const SLIP_CLEAR_SEQUENCE: [u8; 2] = [SLIP_ESC, SLIP_CLEAR];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SlipDecodeState {
     Idle,
     Receiving,
     Escaped,
+    /// ESC between frames, usually the start of ESC CLEAR
+    EscapedIdle,
+}
+
+/// Trouble the decoders of one direction ran into since startup. Usually
+/// the first sign of a baud rate mismatch or a bad cable, well before CRC
+/// errors pile up.
+#[derive(Debug, Default)]
+pub struct SlipCounters {
+    invalid_escapes: AtomicU64,
+    oversized_frames: AtomicU64,
+    empty_frames: AtomicU64,
+    resets: AtomicU64,
+    garbage_bytes: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SlipSnapshot {
+    /// ESC followed by anything but ESC_END, ESC_ESC or CLEAR
+    pub invalid_escapes: u64,
+    /// Frames dropped for growing past 1024 bytes
+    pub oversized_frames: u64,
+    /// END right after END
+    pub empty_frames: u64,
+    /// Partial frames thrown away by an ESC CLEAR sequence
+    pub resets: u64,
+    /// Bytes between frames, such as a board's boot messages, skipped by
+    /// decoders in resync mode
+    pub garbage_bytes: u64,
+}
+
+impl SlipCounters {
+    pub fn snapshot(&self) -> SlipSnapshot {
+        SlipSnapshot {
+            invalid_escapes: self.invalid_escapes.load(Ordering::Relaxed),
+            oversized_frames: self.oversized_frames.load(Ordering::Relaxed),
+            empty_frames: self.empty_frames.load(Ordering::Relaxed),
+            resets: self.resets.load(Ordering::Relaxed),
+            garbage_bytes: self.garbage_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn count(&self, counter: fn(&Self) -> &AtomicU64) {
+        counter(self).fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 pub struct SlipDecoder {
     state: SlipDecodeState,
     buffer: Vec<u8>,
+    counters: Option<Arc<SlipCounters>>,
+    /// Skip anything between frames rather than failing on a stray ESC
+    resync: bool,
 }
 
 impl SlipDecoder {
@@ -25,6 +82,36 @@ impl SlipDecoder {
         Self {
             state: SlipDecodeState::Idle,
             buffer: Vec::with_capacity(256),
+            counters: None,
+            resync: false,
+        }
+    }
+
+    /// A decoder that adds what goes wrong to `counters`.
+    pub fn counted(counters: Arc<SlipCounters>) -> Self {
+        Self {
+            counters: Some(counters),
+            ..Self::new()
+        }
+    }
+
+    /// Resync mode: bytes between frames, as a board spews while it boots,
+    /// are counted and skipped, escape sequences included, rather than
+    /// failing the read.
+    pub fn with_resync(mut self) -> Self {
+        self.resync = true;
+        self
+    }
+
+    fn count(&self, counter: fn(&SlipCounters) -> &AtomicU64) {
+        if let Some(counters) = &self.counters {
+            counters.count(counter);
+        }
+    }
+
+    fn count_garbage(&self, bytes: u64) {
+        if let Some(counters) = &self.counters {
+            counters.garbage_bytes.fetch_add(bytes, Ordering::Relaxed);
         }
     }
 
@@ -33,16 +120,18 @@ impl SlipDecoder {
         self.buffer.clear();
     }
 
+    /// Between frames, where bytes other than END and ESC are ignored.
+    pub fn is_idle(&self) -> bool {
+        self.state == SlipDecodeState::Idle
+    }
+
     /// Process a single byte, returning Some(frame) when a complete frame is decoded
     pub fn process_byte(&mut self, byte: u8) -> Result<Option<Vec<u8>>> {
-        let char_display = if byte >= 32 && byte <= 126 {
-            format!("'{}'", byte as char)
-        } else {
-            format!("0x{:02X}", byte)
-        };
         debug!(
             "SLIP State: {:?}, Byte: {} ({})",
-            self.state, byte, char_display
+            self.state,
+            byte,
+            ShownByte(byte)
         );
         match self.state {
             SlipDecodeState::Idle => {
@@ -53,11 +142,35 @@ impl SlipDecoder {
                 } else if byte == SLIP_ESC {
                     // ESC in idle state - could be clear sequence
                     debug!("SLIP Escape in idle, waiting for next byte");
-                    self.state = SlipDecodeState::Escaped;
+                    self.state = SlipDecodeState::EscapedIdle;
+                } else if self.resync {
+                    self.count_garbage(1);
                 }
                 // Ignore other bytes when idle
                 Ok(None)
             }
+            SlipDecodeState::EscapedIdle if self.resync => {
+                match byte {
+                    SLIP_CLEAR => {
+                        debug!("SLIP Clear sequence between frames");
+                        self.state = SlipDecodeState::Idle;
+                    }
+                    SLIP_END => {
+                        debug!("SLIP Stray ESC before a frame start, skipping it");
+                        self.count_garbage(1);
+                        self.state = SlipDecodeState::Receiving;
+                        self.buffer.clear();
+                    }
+                    // Perhaps the start of ESC CLEAR
+                    SLIP_ESC => self.count_garbage(1),
+                    _ => {
+                        debug!("SLIP Skipping ESC 0x{:02X} between frames", byte);
+                        self.count_garbage(2);
+                        self.state = SlipDecodeState::Idle;
+                    }
+                }
+                Ok(None)
+            }
             SlipDecodeState::Receiving => {
                 if byte == SLIP_END {
                     // End of frame
@@ -68,6 +181,7 @@ impl SlipDecoder {
                         Ok(Some(frame))
                     } else {
                         debug!("SLIP Empty frame, ignoring");
+                        self.count(|c| &c.empty_frames);
                         // Empty frame, continue receiving
                         Ok(None)
                     }
@@ -86,17 +200,25 @@ impl SlipDecoder {
                         );
                     } else {
                         warn!("SLIP Frame too large, resetting");
+                        self.count(|c| &c.oversized_frames);
                         self.reset();
                         return Err(anyhow!("SLIP frame too large"));
                     }
                     Ok(None)
                 }
             }
-            SlipDecodeState::Escaped => {
+            SlipDecodeState::Escaped | SlipDecodeState::EscapedIdle => {
                 match byte {
                     SLIP_CLEAR => {
                         debug!("SLIP Clear sequence detected (ESC+CLEAR), resetting decoder");
+                        // The firmware sends one before every frame, so only
+                        // a partial frame makes it count
+                        if !self.buffer.is_empty() {
+                            self.count(|c| &c.resets);
+                        }
+                        // Between frames, waiting for the next END
                         self.reset();
+                        return Ok(None);
                     }
                     SLIP_ESC_END => {
                         debug!("SLIP Escaped END byte, adding 0xC0 to buffer");
@@ -104,6 +226,7 @@ impl SlipDecoder {
                             self.buffer.push(SLIP_END);
                         } else {
                             warn!("SLIP Frame too large during escape, resetting");
+                            self.count(|c| &c.oversized_frames);
                             self.reset();
                             return Err(anyhow!("SLIP frame too large"));
                         }
@@ -114,59 +237,69 @@ impl SlipDecoder {
                             self.buffer.push(SLIP_ESC);
                         } else {
                             warn!("SLIP Frame too large during escape, resetting");
+                            self.count(|c| &c.oversized_frames);
                             self.reset();
                             return Err(anyhow!("SLIP frame too large"));
                         }
                     }
                     _ => {
                         warn!("SLIP Invalid escape sequence: 0x{:02X}, resetting", byte);
-                        // Invalid escape sequence
+                        self.count(|c| &c.invalid_escapes);
                         self.reset();
                         return Err(anyhow!("Invalid SLIP escape sequence: 0x{:02X}", byte));
                     }
                 }
-                if self.state != SlipDecodeState::Receiving {
-                    self.state = SlipDecodeState::Receiving;
-                }
+                self.state = SlipDecodeState::Receiving;
                 Ok(None)
             }
         }
     }
 }
 
+/// A byte as a character when printable, in hex otherwise. Formatted only
+/// when the debug log is on, as the decoder sees every byte read.
+struct ShownByte(u8);
+
+impl fmt::Display for ShownByte {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if (32..=126).contains(&self.0) {
+            write!(f, "'{}'", self.0 as char)
+        } else {
+            write!(f, "0x{:02X}", self.0)
+        }
+    }
+}
+
 /// Encode data into SLIP format
 pub fn slip_encode(data: &[u8]) -> Vec<u8> {
     let mut encoded = Vec::with_capacity(data.len() + 10); // Extra space for escaping
+    slip_encode_into(data, &mut encoded);
+    encoded
+}
 
+/// Append the SLIP encoding of `data` to `out`, so a buffer kept between
+/// frames can take it without allocating.
+pub fn slip_encode_into(data: &[u8], out: &mut impl BufMut) {
     // Start frame marker
-    encoded.push(SLIP_END);
+    out.put_u8(SLIP_END);
 
     // Encode data with escaping
     for &byte in data {
         match byte {
-            SLIP_END => {
-                encoded.push(SLIP_ESC);
-                encoded.push(SLIP_ESC_END);
-            }
-            SLIP_ESC => {
-                encoded.push(SLIP_ESC);
-                encoded.push(SLIP_ESC_ESC);
-            }
-            _ => {
-                encoded.push(byte);
-            }
+            SLIP_END => out.put_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => out.put_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _ => out.put_u8(byte),
         }
     }
 
     // End frame marker
-    encoded.push(SLIP_END);
-
-    encoded
+    out.put_u8(SLIP_END);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_slip_encode_simple() {
@@ -193,6 +326,32 @@ mod tests {
         assert_eq!(encoded, expected);
     }
 
+    #[test]
+    fn test_slip_encode_into_appends_to_a_reused_buffer() {
+        let mut wire = bytes::BytesMut::new();
+        wire.put_slice(&SLIP_CLEAR_SEQUENCE);
+        slip_encode_into(&[0x01, SLIP_END], &mut wire);
+        let capacity = wire.capacity();
+        assert_eq!(
+            &wire[..],
+            [
+                SLIP_ESC,
+                SLIP_CLEAR,
+                SLIP_END,
+                0x01,
+                SLIP_ESC,
+                SLIP_ESC_END,
+                SLIP_END
+            ]
+        );
+
+        // The next frame fits where the last one was
+        wire.clear();
+        slip_encode_into(&[0x02], &mut wire);
+        assert_eq!(&wire[..], slip_encode(&[0x02]));
+        assert_eq!(wire.capacity(), capacity);
+    }
+
     #[test]
     fn test_slip_decode_simple() {
         let mut decoder = SlipDecoder::new();
@@ -249,4 +408,112 @@ mod tests {
         assert_eq!(frames.len(), 1);
         assert_eq!(frames[0], original);
     }
+
+    fn decode_all(decoder: &mut SlipDecoder, input: &[u8]) -> Vec<Vec<u8>> {
+        input
+            .iter()
+            .filter_map(|&byte| decoder.process_byte(byte).ok().flatten())
+            .collect()
+    }
+
+    #[test]
+    fn test_slip_escape_at_frame_end_is_rejected() {
+        let mut decoder = SlipDecoder::new();
+        for &byte in &[SLIP_END, 0x01, SLIP_ESC] {
+            assert!(decoder.process_byte(byte).unwrap().is_none());
+        }
+        assert!(decoder.process_byte(SLIP_END).is_err());
+
+        // The decoder recovers on the next frame
+        let frames = decode_all(&mut decoder, &slip_encode(&[0x02, 0x03]));
+        assert_eq!(frames, vec![vec![0x02, 0x03]]);
+    }
+
+    #[test]
+    fn test_slip_clear_mid_frame_discards_partial_data() {
+        let mut decoder = SlipDecoder::new();
+        let mut input = vec![SLIP_END, 0x01, 0x02, SLIP_ESC, SLIP_CLEAR];
+        input.extend(slip_encode(&[0x03]));
+
+        assert_eq!(decode_all(&mut decoder, &input), vec![vec![0x03]]);
+    }
+
+    #[test]
+    fn test_counts_decoder_trouble() {
+        let counters = Arc::new(SlipCounters::default());
+        let mut decoder = SlipDecoder::counted(Arc::clone(&counters));
+        // What the firmware sends: ESC CLEAR before each frame
+        let mut input = vec![SLIP_ESC, SLIP_CLEAR];
+        input.extend(slip_encode(&[0x01]));
+        input.extend([SLIP_END, SLIP_END, 0x02, SLIP_END]);
+        input.extend([SLIP_END, 0x03, SLIP_ESC, SLIP_CLEAR]);
+        input.extend([SLIP_END, 0x04, SLIP_ESC, 0x42]);
+        input.push(SLIP_END);
+        input.extend(std::iter::repeat_n(0x05, 1025));
+        let frames = decode_all(&mut decoder, &input);
+
+        assert_eq!(frames, vec![vec![0x01], vec![0x02]]);
+        assert_eq!(
+            counters.snapshot(),
+            SlipSnapshot {
+                invalid_escapes: 1,
+                oversized_frames: 1,
+                empty_frames: 1,
+                resets: 1,
+                garbage_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resync_skips_boot_noise() {
+        let counters = Arc::new(SlipCounters::default());
+        let mut decoder = SlipDecoder::counted(Arc::clone(&counters)).with_resync();
+        let mut input = b"boot v1.2".to_vec();
+        input.extend([SLIP_ESC, 0x42, SLIP_ESC, SLIP_CLEAR]);
+        input.extend(slip_encode(&[0x01]));
+        input.extend([b'!', SLIP_ESC]);
+        input.extend(slip_encode(&[SLIP_END, 0x02]));
+        let frames = decode_all(&mut decoder, &input);
+
+        assert_eq!(frames, vec![vec![0x01], vec![SLIP_END, 0x02]]);
+        let counts = counters.snapshot();
+        assert_eq!(counts.garbage_bytes, 9 + 2 + 2);
+        assert_eq!(counts.invalid_escapes, 0);
+
+        // Without it the stray escape fails the read
+        let mut plain = SlipDecoder::new();
+        assert!(input.iter().any(|&byte| plain.process_byte(byte).is_err()));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_slip_roundtrip(data in prop::collection::vec(any::<u8>(), 1..512)) {
+            let mut decoder = SlipDecoder::new();
+            let frames = decode_all(&mut decoder, &slip_encode(&data));
+            prop_assert_eq!(frames, vec![data]);
+        }
+
+        #[test]
+        fn prop_slip_roundtrip_after_garbage(
+            garbage in prop::collection::vec(any::<u8>(), 0..64),
+            data in prop::collection::vec(any::<u8>(), 1..64),
+        ) {
+            // Whatever precedes it, ESC CLEAR plus a frame always decodes
+            let mut decoder = SlipDecoder::new();
+            decode_all(&mut decoder, &garbage);
+            let mut input = vec![SLIP_ESC, SLIP_CLEAR];
+            input.extend(slip_encode(&data));
+            let frames = decode_all(&mut decoder, &input);
+            prop_assert_eq!(frames.last(), Some(&data));
+        }
+
+        #[test]
+        fn prop_slip_decoder_never_panics(input in prop::collection::vec(any::<u8>(), 0..4096)) {
+            let mut decoder = SlipDecoder::new();
+            for frame in decode_all(&mut decoder, &input) {
+                prop_assert!(!frame.is_empty() && frame.len() <= 1024);
+            }
+        }
+    }
 }
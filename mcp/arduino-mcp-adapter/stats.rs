@@ -0,0 +1,275 @@
+//! Counters reported by `/status`, and per-tool statistics reported by
+//! `/stats` and the `robot://stats` resource.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::slip::SlipSnapshot;
+
+/// Latest calls per tool that latency percentiles are taken over.
+const LATENCY_WINDOW: usize = 1000;
+/// Distinct tool names tracked, so calls to made-up names can't grow the
+/// table without bound.
+const MAX_TOOLS: usize = 256;
+
+/// Kinds of failed command counted separately in `/status`.
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorKind {
+    /// No response in time
+    Timeout,
+    /// The firmware answered with an `[0xFF] [code]` error frame
+    Dispatch,
+    /// Anything else, e.g. a closed port or an undecodable response
+    Other,
+}
+
+pub struct Stats {
+    started: Instant,
+    calls: AtomicU64,
+    timeouts: AtomicU64,
+    dispatch_errors: AtomicU64,
+    other_errors: AtomicU64,
+    /// When each queued command was queued, by a running count
+    queued: Mutex<BTreeMap<u64, Instant>>,
+    next_queued: AtomicU64,
+    /// Unix milliseconds of the last successful port open; 0 if none yet
+    last_reconnect_ms: AtomicU64,
+    resets: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub uptime_secs: u64,
+    pub calls: u64,
+    pub errors: ErrorCounts,
+    /// Commands waiting for or awaiting a response from the device
+    pub queue_depth: usize,
+    /// How long the oldest of them has been queued
+    pub oldest_queued_ms: Option<u64>,
+    /// Unix milliseconds of the last successful port open
+    pub last_reconnect: Option<u64>,
+    /// Times the device restarted mid-session and was resynced
+    pub resets: u64,
+    pub slip: SlipDirections,
+}
+
+/// SLIP decoder trouble by direction. Frames to the device are decoded by
+/// the firmware, so only the frames received are counted here.
+#[derive(Debug, Serialize)]
+pub struct SlipDirections {
+    pub rx: SlipSnapshot,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorCounts {
+    pub crc: u64,
+    pub timeout: u64,
+    pub dispatch: u64,
+    pub other: u64,
+}
+
+/// Counts a command as queued until dropped.
+pub struct Queued<'a> {
+    queued: &'a Mutex<BTreeMap<u64, Instant>>,
+    id: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            calls: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            dispatch_errors: AtomicU64::new(0),
+            other_errors: AtomicU64::new(0),
+            queued: Mutex::new(BTreeMap::new()),
+            next_queued: AtomicU64::new(0),
+            last_reconnect_ms: AtomicU64::new(0),
+            resets: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_call(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self, kind: ErrorKind) {
+        let counter = match kind {
+            ErrorKind::Timeout => &self.timeouts,
+            ErrorKind::Dispatch => &self.dispatch_errors,
+            ErrorKind::Other => &self.other_errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.last_reconnect_ms.store(now, Ordering::Relaxed);
+    }
+
+    pub fn record_reset(&self) {
+        self.resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn enqueue(&self) -> Queued<'_> {
+        let id = self.next_queued.fetch_add(1, Ordering::Relaxed);
+        self.queued.lock().unwrap().insert(id, Instant::now());
+        Queued {
+            queued: &self.queued,
+            id,
+        }
+    }
+
+    /// Commands queued, and how long the oldest has been waiting.
+    pub fn queue(&self) -> (usize, Option<Duration>) {
+        let queued = self.queued.lock().unwrap();
+        let oldest = queued.values().next().map(Instant::elapsed);
+        (queued.len(), oldest)
+    }
+
+    /// CRC failures and SLIP decoder trouble are counted by the frame log,
+    /// which sees every frame received.
+    pub fn snapshot(&self, crc_errors: u64, slip_rx: SlipSnapshot) -> StatsSnapshot {
+        let last_reconnect = self.last_reconnect_ms.load(Ordering::Relaxed);
+        let (queue_depth, oldest_queued) = self.queue();
+        StatsSnapshot {
+            uptime_secs: self.started.elapsed().as_secs(),
+            calls: self.calls.load(Ordering::Relaxed),
+            errors: ErrorCounts {
+                crc: crc_errors,
+                timeout: self.timeouts.load(Ordering::Relaxed),
+                dispatch: self.dispatch_errors.load(Ordering::Relaxed),
+                other: self.other_errors.load(Ordering::Relaxed),
+            },
+            queue_depth,
+            oldest_queued_ms: oldest_queued.map(|age| age.as_millis() as u64),
+            last_reconnect: (last_reconnect > 0).then_some(last_reconnect),
+            resets: self.resets.load(Ordering::Relaxed),
+            slip: SlipDirections { rx: slip_rx },
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queued.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Call counts, errors and latencies of each tool, as clients see them.
+#[derive(Default)]
+pub struct ToolStats {
+    tools: Mutex<BTreeMap<String, ToolRecord>>,
+}
+
+#[derive(Default)]
+struct ToolRecord {
+    calls: u64,
+    errors: u64,
+    latencies: VecDeque<Duration>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolSnapshot {
+    pub calls: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    /// Over the last [`LATENCY_WINDOW`] calls
+    pub latency_ms: Percentiles,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl ToolStats {
+    pub fn record(&self, tool: &str, elapsed: Duration, ok: bool) {
+        let mut tools = self.tools.lock().unwrap();
+        if !tools.contains_key(tool) && tools.len() >= MAX_TOOLS {
+            return;
+        }
+        let record = tools.entry(tool.to_string()).or_default();
+        record.calls += 1;
+        record.errors += u64::from(!ok);
+        if record.latencies.len() == LATENCY_WINDOW {
+            record.latencies.pop_front();
+        }
+        record.latencies.push_back(elapsed);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ToolSnapshot> {
+        let tools = self.tools.lock().unwrap();
+        tools
+            .iter()
+            .map(|(name, record)| {
+                let mut latencies: Vec<Duration> = record.latencies.iter().copied().collect();
+                latencies.sort();
+                let snapshot = ToolSnapshot {
+                    calls: record.calls,
+                    errors: record.errors,
+                    error_rate: record.errors as f64 / record.calls as f64,
+                    latency_ms: Percentiles {
+                        p50: percentile(&latencies, 0.50),
+                        p90: percentile(&latencies, 0.90),
+                        p99: percentile(&latencies, 0.99),
+                        max: percentile(&latencies, 1.0),
+                    },
+                };
+                (name.clone(), snapshot)
+            })
+            .collect()
+    }
+}
+
+/// Nearest-rank percentile of `sorted`, in milliseconds.
+fn percentile(sorted: &[Duration], fraction: f64) -> f64 {
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted
+        .get(rank.saturating_sub(1))
+        .map_or(0.0, |latency| latency.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_stats_percentiles_and_error_rate() {
+        let stats = ToolStats::default();
+        for ms in 1..=100 {
+            stats.record("moveTo", Duration::from_millis(ms), ms % 10 != 0);
+        }
+        stats.record("getStatus", Duration::from_millis(3), true);
+
+        let snapshot = stats.snapshot();
+        let move_to = &snapshot["moveTo"];
+        assert_eq!((move_to.calls, move_to.errors), (100, 10));
+        assert_eq!(move_to.error_rate, 0.1);
+        assert_eq!(
+            move_to.latency_ms,
+            Percentiles {
+                p50: 50.0,
+                p90: 90.0,
+                p99: 99.0,
+                max: 100.0
+            }
+        );
+        assert_eq!(snapshot["getStatus"].latency_ms.p99, 3.0);
+    }
+}
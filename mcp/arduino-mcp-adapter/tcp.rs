@@ -0,0 +1,132 @@
+//! Robots reached over TCP instead of USB, e.g. through an ESP32 serial
+//! bridge, given as a `tcp://host:port` line.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::transport::{Connector, Transport};
+
+/// Line prefix selecting [`TcpConnector`].
+pub const TCP_SCHEME: &str = "tcp://";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Same as the serial port's read timeout.
+const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub struct TcpConnector {
+    /// The line as configured, with its scheme
+    name: String,
+    address: String,
+}
+
+impl TcpConnector {
+    pub fn new(address: &str) -> Self {
+        Self {
+            name: format!("{}{}", TCP_SCHEME, address),
+            address: address.to_string(),
+        }
+    }
+}
+
+impl Connector for TcpConnector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Only known by connecting; a failed connect is retried like a serial
+    /// port that won't open.
+    fn is_present(&self) -> bool {
+        true
+    }
+
+    fn open(&self) -> Result<Box<dyn Transport>> {
+        let address = self
+            .address
+            .to_socket_addrs()
+            .with_context(|| format!("Invalid address '{}'", self.address))?
+            .next()
+            .ok_or_else(|| anyhow!("'{}' did not resolve to an address", self.address))?;
+        connect(&address).with_context(|| format!("Connection to {} failed", self.name))
+    }
+
+    /// Nothing resets the board when a socket opens.
+    fn boot_timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Connect to a robot's bridge, with the usual timeouts.
+pub fn connect(address: &SocketAddr) -> Result<Box<dyn Transport>> {
+    let stream = TcpStream::connect_timeout(address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(Box::new(TcpTransport(stream)))
+}
+
+struct TcpTransport(TcpStream);
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            // Read timeouts surface as WouldBlock on Unix
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            // The bridge closed the connection
+            Ok(0) if !buf.is_empty() => Err(io::ErrorKind::ConnectionAborted.into()),
+            result => result,
+        }
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn try_clone_transport(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpTransport(self.0.try_clone()?)))
+    }
+
+    fn pending_bytes(&self) -> usize {
+        let mut byte = [0; 1];
+        if self.0.set_nonblocking(true).is_err() {
+            return 0;
+        }
+        let pending = self.0.peek(&mut byte).unwrap_or(0);
+        let _ = self.0.set_nonblocking(false);
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_reads_time_out_and_report_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let connector = TcpConnector::new(&address);
+        assert_eq!(connector.name(), format!("tcp://{}", address));
+
+        let mut transport = connector.open().unwrap();
+        let (mut bridge, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        let err = transport.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        bridge.write_all(&[0xC0, 0x01]).unwrap();
+        assert_eq!(transport.read(&mut buf).unwrap(), 2);
+        drop(bridge);
+        let err = transport.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
@@ -0,0 +1,62 @@
+//! Recent results of scheduled calls, kept in memory for `/telemetry`.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Samples kept per series; older ones are dropped.
+pub const TELEMETRY_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    /// Milliseconds since the Unix epoch
+    pub at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct TelemetryCache {
+    series: Mutex<HashMap<String, VecDeque<Sample>>>,
+}
+
+impl TelemetryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, series: &str, result: Result<String, String>) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        let mut all = self.series.lock().unwrap();
+        let samples = all.entry(series.to_string()).or_default();
+        if samples.len() == TELEMETRY_HISTORY {
+            samples.pop_front();
+        }
+        samples.push_back(Sample { at, result, error });
+    }
+
+    /// Samples of `series`, oldest first.
+    pub fn samples(&self, series: &str) -> Vec<Sample> {
+        self.series
+            .lock()
+            .unwrap()
+            .get(series)
+            .map(|samples| samples.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn remove(&self, series: &str) {
+        self.series.lock().unwrap().remove(series);
+    }
+}
@@ -0,0 +1,101 @@
+//! Unix socket that `runPythonScript` children use to call tools, so they
+//! don't go back through the HTTP endpoint.
+//!
+//! Each line sent is `{"name": ..., "arguments": {...}}`; each reply is one
+//! line shaped like a JSON-RPC response with `result` or `error`.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, error};
+
+use crate::sequence;
+use crate::server::{McpError, McpResponse, McpServer};
+
+const SOCKET_NAME: &str = "tools.sock";
+/// Tools a script can't call: one that starts scripts could tie up the
+/// Python pool with scripts that each start another.
+const REFUSED_TOOLS: &[&str] = &["runPythonScript"];
+/// Pause after a failed accept, such as when the process is out of file
+/// descriptors, rather than retrying in a busy loop.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Deserialize)]
+struct BridgeRequest {
+    name: String,
+    #[serde(default = "sequence::empty_arguments")]
+    arguments: Value,
+}
+
+pub struct ToolBridge {
+    // The socket lives in a private directory so only this user can reach it
+    dir: TempDir,
+    listener: UnixListener,
+}
+
+impl ToolBridge {
+    pub fn bind() -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("arduino-mcp-")
+            .tempdir()
+            .context("Failed to create tool socket directory")?;
+        let listener = UnixListener::bind(dir.path().join(SOCKET_NAME))
+            .context("Failed to bind tool socket")?;
+        Ok(Self { dir, listener })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join(SOCKET_NAME)
+    }
+
+    /// Accept connections until the runtime shuts down; the socket directory
+    /// is removed when this future is dropped.
+    pub async fn serve(self, server: Arc<McpServer>) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, Arc::clone(&server)));
+                }
+                Err(e) => {
+                    error!("Tool socket accept error: {}", e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+            }
+        }
+    }
+}
+
+/// Calls on one connection run in order; scripts open one connection per
+/// thread to run calls concurrently.
+async fn handle_connection(stream: UnixStream, server: Arc<McpServer>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let result = match serde_json::from_str::<BridgeRequest>(&line) {
+            Ok(request) if REFUSED_TOOLS.contains(&request.name.as_str()) => {
+                Err(server.refused_in_script(&request.name))
+            }
+            Ok(request) => {
+                debug!(
+                    "Python call {} with arguments {}",
+                    request.name, request.arguments
+                );
+                server.call_tool(&request.name, &request.arguments).await
+            }
+            Err(e) => Err(McpError::new(-32700, format!("JSON parse error: {}", e))),
+        };
+
+        let mut reply = serde_json::to_vec(&McpResponse::from_result(None, result)).unwrap();
+        reply.push(b'\n');
+        if writer.write_all(&reply).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! `/transcript`: one client session's history as a single JSON document,
+//! with its MCP requests and responses interleaved with the notifications
+//! sent and the serial frames exchanged meanwhile, for bug reports and for
+//! grading a student's session.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::frame_log::FrameRecord;
+
+/// Number of entries kept across all sessions; the oldest go first.
+pub const TRANSCRIPT_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    /// A JSON-RPC message a client posted to `/mcp`
+    Request {
+        session: String,
+        message: Value,
+    },
+    Response {
+        session: String,
+        message: Value,
+    },
+    /// Sent to every session
+    Notification {
+        message: Value,
+    },
+    Frame {
+        frame: FrameRecord,
+    },
+    /// Frames or notifications lost because the recorder fell behind
+    Gap {
+        missed: u64,
+    },
+}
+
+impl Entry {
+    /// Whether the entry belongs in `session`'s transcript.
+    fn concerns(&self, session: &str) -> bool {
+        match self {
+            Self::Request { session: id, .. } | Self::Response { session: id, .. } => id == session,
+            _ => true,
+        }
+    }
+
+    /// Order within a millisecond: a request before the frames it caused,
+    /// and its response after them.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Request { .. } => 0,
+            Self::Frame { .. } | Self::Gap { .. } => 1,
+            Self::Notification { .. } => 2,
+            Self::Response { .. } => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    /// Milliseconds since the Unix epoch
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+pub struct Transcript {
+    records: Mutex<VecDeque<Record>>,
+    capacity: usize,
+}
+
+impl Transcript {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, entry: Entry) {
+        let at_ms = match &entry {
+            // Stamped when it crossed the wire, not when it got here
+            Entry::Frame { frame } => frame.timestamp_ms,
+            _ => now_ms(),
+        };
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(Record { at_ms, entry });
+    }
+
+    /// Everything from `session`'s first request on that concerns it, in
+    /// time order, or `None` if no request of it is still kept. Frames are
+    /// only kept while one of the session's requests awaits its response, so
+    /// other clients' traffic stays out unless it ran meanwhile.
+    pub fn export(&self, session: &str) -> Option<Value> {
+        let records = self.records.lock().unwrap();
+        let start = records.iter().position(
+            |r| matches!(&r.entry, Entry::Request { session: id, .. } if id == session),
+        )?;
+        let mut entries: Vec<&Record> = records
+            .iter()
+            .skip(start)
+            .filter(|r| r.entry.concerns(session))
+            .collect();
+        // Frames arrive through a channel, so may be recorded a little late
+        entries.sort_by_key(|r| (r.at_ms, r.entry.rank()));
+        let mut awaiting = 0usize;
+        entries.retain(|r| match &r.entry {
+            // Notifications from the client get no response
+            Entry::Request { message, .. } if !message["id"].is_null() => {
+                awaiting += 1;
+                true
+            }
+            Entry::Response { .. } => {
+                awaiting = awaiting.saturating_sub(1);
+                true
+            }
+            Entry::Frame { .. } => awaiting > 0,
+            _ => true,
+        });
+        Some(serde_json::json!({
+            "session": session,
+            "exported_at_ms": now_ms(),
+            "entries": entries
+        }))
+    }
+
+    /// Record frames and notifications as they are sent until both
+    /// channels close.
+    pub fn spawn_recorder(
+        self: &Arc<Self>,
+        mut frames: broadcast::Receiver<FrameRecord>,
+        mut notifications: broadcast::Receiver<Value>,
+    ) {
+        let transcript = Arc::clone(self);
+        tokio::spawn(async move {
+            let (mut frames_open, mut notifications_open) = (true, true);
+            while frames_open || notifications_open {
+                tokio::select! {
+                    frame = frames.recv(), if frames_open => {
+                        frames_open = transcript.take(frame.map(|frame| Entry::Frame { frame }));
+                    }
+                    message = notifications.recv(), if notifications_open => {
+                        notifications_open = transcript
+                            .take(message.map(|message| Entry::Notification { message }));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Record what a channel gave, returning whether it is still open.
+    fn take(&self, received: Result<Entry, broadcast::error::RecvError>) -> bool {
+        match received {
+            Ok(entry) => self.record(entry),
+            Err(broadcast::error::RecvError::Lagged(missed)) => self.record(Entry::Gap { missed }),
+            Err(broadcast::error::RecvError::Closed) => return false,
+        }
+        true
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_log::{Direction, FrameLog, FRAME_LOG_CAPACITY};
+    use crate::notifications::Notifier;
+    use serde_json::json;
+
+    #[test]
+    fn test_export_keeps_one_session_and_shared_entries() {
+        let transcript = Transcript::new(TRANSCRIPT_CAPACITY);
+        let request = |session: &str, id: u64| Entry::Request {
+            session: session.to_string(),
+            message: json!({"jsonrpc": "2.0", "id": id, "method": "tools/list"}),
+        };
+        transcript.record(Entry::Notification {
+            message: json!({"method": "notifications/tools/list_changed"}),
+        });
+        transcript.record(request("a", 1));
+        transcript.record(request("b", 1));
+        transcript.record(Entry::Frame {
+            frame: FrameRecord::parse(Direction::Tx, &[0x01, 0x07], false),
+        });
+        transcript.record(Entry::Response {
+            session: "a".to_string(),
+            message: json!({"jsonrpc": "2.0", "id": 1, "result": {}}),
+        });
+        // Sent for session "b" after "a" had its answer
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        transcript.record(Entry::Frame {
+            frame: FrameRecord::parse(Direction::Tx, &[0x02, 0x07], false),
+        });
+
+        let exported = transcript.export("a").unwrap();
+        let types: Vec<&str> = exported["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["type"].as_str().unwrap())
+            .collect();
+        // The notification came before the session's first request
+        assert_eq!(types, ["request", "frame", "response"]);
+        assert_eq!(exported["entries"][1]["frame"]["tag"], 1);
+        assert!(transcript.export("c").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_recorder_follows_frames_and_notifications() {
+        let transcript = Arc::new(Transcript::new(TRANSCRIPT_CAPACITY));
+        let frame_log = FrameLog::new(FRAME_LOG_CAPACITY);
+        let notifier = Notifier::new();
+        transcript.spawn_recorder(frame_log.subscribe(), notifier.subscribe());
+
+        transcript.record(Entry::Request {
+            session: "a".to_string(),
+            message: json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call"}),
+        });
+        frame_log.record(Direction::Tx, &[0x01, 0x07], false);
+        notifier.notify("notifications/message", None);
+        let recorded = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let exported = transcript.export("a").unwrap();
+                if exported["entries"].as_array().unwrap().len() == 3 {
+                    return exported;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        let mut types: Vec<&str> = recorded["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["type"].as_str().unwrap())
+            .collect();
+        types.sort();
+        assert_eq!(types, ["frame", "notification", "request"]);
+    }
+}
@@ -0,0 +1,318 @@
+//! How the adapter reaches a device: a [`Transport`] byte stream, opened by
+//! a [`Connector`] chosen from the line's scheme (`serial://`, `tcp://`,
+//! `mdns://`, `ble://`, `pty://` or `loop://`).
+
+use anyhow::{anyhow, Context, Result};
+use nix::sys::termios::{self, SetArg, SpecialCharacterIndices};
+use serialport::SerialPort;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::ble::BleConnector;
+use crate::loopback::LoopbackConnector;
+use crate::mdns::MdnsConnector;
+use crate::tcp::TcpConnector;
+
+/// The connector for `line`: a URI such as `tcp://host:port`, or a plain
+/// serial device path.
+pub fn connector_for(line: &str, baud_rate: u32) -> Result<Box<dyn Connector>> {
+    Ok(match line.split_once("://") {
+        None => Box::new(SerialConnector::new(line.to_string(), baud_rate)),
+        Some(("serial", path)) => Box::new(SerialConnector::new(path.to_string(), baud_rate)),
+        Some(("tcp", address)) => Box::new(TcpConnector::new(address)),
+        Some(("mdns", spec)) => Box::new(MdnsConnector::new(spec)?),
+        Some(("ble", device)) => Box::new(BleConnector::new(device)?),
+        Some(("pty", path)) => Box::new(PtyConnector::new(path)),
+        Some(("loop", spec)) => Box::new(LoopbackConnector::from_spec(spec)?),
+        Some((scheme, _)) => {
+            return Err(anyhow!(
+            "Unknown line scheme '{}://' in '{}' (expected serial, tcp, mdns, ble, pty or loop)",
+            scheme,
+            line
+        ))
+        }
+    })
+}
+
+/// The serial device `line` names, if it names one, for looking up its
+/// USB descriptors.
+pub fn serial_path(line: &str) -> Option<&str> {
+    match line.split_once("://") {
+        None => Some(line),
+        Some(("serial", path)) => Some(path),
+        Some(_) => None,
+    }
+}
+
+/// Byte stream to a device. Reads should time out (`ErrorKind::TimedOut`)
+/// rather than block forever when no data arrives.
+pub trait Transport: Read + Write + Send {
+    /// A second handle to the same stream, for reading on another thread.
+    fn try_clone_transport(&self) -> Result<Box<dyn Transport>>;
+
+    /// Bytes that a read would return without waiting, so an idle port
+    /// can be checked for event frames. Transports that can't tell report
+    /// 0, and their events are read along with the next response.
+    fn pending_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn try_clone_transport(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn pending_bytes(&self) -> usize {
+        self.bytes_to_read().unwrap_or(0) as usize
+    }
+}
+
+/// Finds and opens the transport for one device.
+pub trait Connector: Send + Sync {
+    /// Human-readable location, e.g. the serial line path.
+    fn name(&self) -> &str;
+
+    /// Whether the device is attached right now.
+    fn is_present(&self) -> bool;
+
+    fn open(&self) -> Result<Box<dyn Transport>>;
+
+    /// How long the device may take to answer commands after opening; zero
+    /// if it answers at once.
+    fn boot_timeout(&self) -> Duration {
+        // Arduino resets when DTR is asserted on open, and some boards take
+        // several seconds to start
+        Duration::from_secs(10)
+    }
+
+    /// How long a bootloader waits for an upload before starting the
+    /// firmware.
+    fn bootloader_window(&self) -> Duration {
+        // Optiboot's 1s watchdog timeout, with margin
+        Duration::from_millis(1500)
+    }
+}
+
+/// USB/UART serial line, e.g. `/dev/ttyUSB0`.
+pub struct SerialConnector {
+    line_path: String,
+    baud_rate: u32,
+}
+
+impl SerialConnector {
+    pub fn new(line_path: String, baud_rate: u32) -> Self {
+        Self {
+            line_path,
+            baud_rate,
+        }
+    }
+}
+
+impl Connector for SerialConnector {
+    fn name(&self) -> &str {
+        &self.line_path
+    }
+
+    fn is_present(&self) -> bool {
+        Path::new(&self.line_path).exists()
+    }
+
+    fn open(&self) -> Result<Box<dyn Transport>> {
+        match serialport::new(&self.line_path, self.baud_rate)
+            .timeout(Duration::from_millis(1000))
+            .open()
+        {
+            Ok(port) => Ok(Box::new(port)),
+            Err(e) => {
+                let error_msg = match e.kind() {
+                    serialport::ErrorKind::NoDevice => "Device not found".to_string(),
+                    serialport::ErrorKind::InvalidInput => "Invalid device path".to_string(),
+                    serialport::ErrorKind::Unknown => {
+                        if e.to_string().contains("busy") || e.to_string().contains("in use") {
+                            "Serial port is busy - close other applications using this port"
+                                .to_string()
+                        } else {
+                            format!("Connection failed: {}", e)
+                        }
+                    }
+                    _ => format!("Serial port error: {}", e),
+                };
+                Err(anyhow!(error_msg))
+            }
+        }
+    }
+}
+
+/// Same as the serial port's read timeout, in the deciseconds `VTIME`
+/// counts.
+const PTY_READ_TIMEOUT_DS: u8 = 10;
+
+nix::ioctl_read_bad!(bytes_available, nix::libc::FIONREAD, nix::libc::c_int);
+
+/// Pseudo-terminal or other character device opened as a plain file, such
+/// as the simulator's PTY: raw mode, and no baud rate or DTR to set.
+pub struct PtyConnector {
+    /// The line as configured, with its scheme
+    name: String,
+    path: String,
+}
+
+impl PtyConnector {
+    pub fn new(path: &str) -> Self {
+        Self {
+            name: format!("pty://{}", path),
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Connector for PtyConnector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_present(&self) -> bool {
+        Path::new(&self.path).exists()
+    }
+
+    fn open(&self) -> Result<Box<dyn Transport>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NOCTTY)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path))?;
+        let mut settings = termios::tcgetattr(&file)
+            .with_context(|| format!("{} is not a terminal", self.path))?;
+        termios::cfmakeraw(&mut settings);
+        // Reads return what arrived, or nothing once the timeout passes
+        settings.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
+        settings.control_chars[SpecialCharacterIndices::VTIME as usize] = PTY_READ_TIMEOUT_DS;
+        termios::tcsetattr(&file, SetArg::TCSANOW, &settings)?;
+        Ok(Box::new(PtyTransport(file)))
+    }
+
+    /// Nothing resets a simulated device when its PTY opens.
+    fn boot_timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+struct PtyTransport(File);
+
+impl Read for PtyTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            // VTIME passed with nothing to read
+            Ok(0) if !buf.is_empty() => Err(io::ErrorKind::TimedOut.into()),
+            result => result,
+        }
+    }
+}
+
+impl Write for PtyTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for PtyTransport {
+    fn try_clone_transport(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(PtyTransport(self.0.try_clone()?)))
+    }
+
+    fn pending_bytes(&self) -> usize {
+        let mut count: nix::libc::c_int = 0;
+        // SAFETY: FIONREAD writes one c_int to `count`
+        match unsafe { bytes_available(self.0.as_raw_fd(), &mut count) } {
+            Ok(_) => count.max(0) as usize,
+            Err(_) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionManager;
+    use nix::fcntl::OFlag;
+    use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt};
+
+    #[test]
+    fn test_line_schemes_select_connectors() {
+        assert_eq!(
+            connector_for("/dev/ttyUSB0", 115200).unwrap().name(),
+            "/dev/ttyUSB0"
+        );
+        assert_eq!(
+            connector_for("serial:///dev/ttyACM0", 115200)
+                .unwrap()
+                .name(),
+            "/dev/ttyACM0"
+        );
+        assert_eq!(
+            connector_for("tcp://10.0.0.2:3333", 115200).unwrap().name(),
+            "tcp://10.0.0.2:3333"
+        );
+        assert_eq!(
+            connector_for("pty:///tmp/robot", 115200).unwrap().name(),
+            "pty:///tmp/robot"
+        );
+        let err = connector_for("usb://arm", 115200).err().unwrap();
+        assert!(err.to_string().contains("Unknown line scheme 'usb://'"));
+
+        assert_eq!(serial_path("serial:///dev/ttyACM0"), Some("/dev/ttyACM0"));
+        assert_eq!(serial_path("tcp://10.0.0.2:3333"), None);
+    }
+
+    #[tokio::test]
+    async fn test_loop_line_serves_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("arm.json");
+        std::fs::write(
+            &path,
+            r#"{"name": "arm", "description": "Arm", "version": "v1", "functions": []}"#,
+        )
+        .unwrap();
+
+        let line = format!("loop://{}?chunked&events", path.display());
+        let connection_manager = ConnectionManager::new(&line, 115200).unwrap();
+        assert_eq!(
+            crate::call::connect(&connection_manager).await.unwrap(),
+            "arm"
+        );
+
+        let line = format!("loop://{}?fast", path.display());
+        let err = connector_for(&line, 115200).err().unwrap();
+        assert!(err.to_string().contains("Unknown loop:// option 'fast'"));
+    }
+
+    #[test]
+    fn test_pty_reads_time_out() {
+        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY).unwrap();
+        grantpt(&master).unwrap();
+        unlockpt(&master).unwrap();
+        let path = ptsname_r(&master).unwrap();
+
+        let mut transport = PtyConnector::new(&path).open().unwrap();
+        let mut buf = [0u8; 4];
+        let err = transport.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        (&master).write_all(&[0xC0, 0x0A]).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(transport.pending_bytes(), 2);
+        // Raw mode: no newline translation
+        assert_eq!(transport.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], &[0xC0, 0x0A]);
+    }
+}
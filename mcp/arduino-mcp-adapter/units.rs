@@ -0,0 +1,170 @@
+//! Units of integer parameters, e.g. a servo angle in `deg`. Clients may
+//! give such a value in another unit of the same kind, as a string like
+//! `"1.57rad"` or together with a `unit` argument, and it is converted
+//! before the call is encoded.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Name of the argument giving the unit of a call's plain numbers.
+pub const UNIT_ARGUMENT: &str = "unit";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    Deg,
+    Rad,
+    Mm,
+    Cm,
+    Ms,
+    S,
+}
+
+const ALL: [Unit; 6] = [Unit::Deg, Unit::Rad, Unit::Mm, Unit::Cm, Unit::Ms, Unit::S];
+
+impl Unit {
+    pub fn parse(name: &str) -> Option<Unit> {
+        ALL.into_iter().find(|unit| unit.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Deg => "deg",
+            Unit::Rad => "rad",
+            Unit::Mm => "mm",
+            Unit::Cm => "cm",
+            Unit::Ms => "ms",
+            Unit::S => "s",
+        }
+    }
+
+    /// Angle, length or time.
+    fn kind(self) -> &'static str {
+        match self {
+            Unit::Deg | Unit::Rad => "angle",
+            Unit::Mm | Unit::Cm => "length",
+            Unit::Ms | Unit::S => "time",
+        }
+    }
+
+    /// Size in the first unit of its kind.
+    fn scale(self) -> f64 {
+        match self {
+            Unit::Deg | Unit::Mm | Unit::Ms => 1.0,
+            Unit::Rad => 180.0 / PI,
+            Unit::Cm => 10.0,
+            Unit::S => 1000.0,
+        }
+    }
+
+    pub fn converts_to(self, other: Unit) -> bool {
+        self.kind() == other.kind()
+    }
+
+    /// Units a value of this unit may be given in.
+    pub fn compatible(self) -> impl Iterator<Item = Unit> {
+        ALL.into_iter().filter(move |unit| unit.converts_to(self))
+    }
+
+    /// For the input schema: the value as a client might send it in the
+    /// other unit of its kind.
+    pub fn example(self) -> &'static str {
+        match self {
+            Unit::Deg => "1.57rad",
+            Unit::Rad => "90deg",
+            Unit::Mm => "2.5cm",
+            Unit::Cm => "25mm",
+            Unit::Ms => "1.5s",
+            Unit::S => "1500ms",
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `value` in `from`, converted to `to` and rounded to a whole number.
+pub fn convert(value: f64, from: Unit, to: Unit) -> Result<i64> {
+    if !from.converts_to(to) {
+        return Err(anyhow!("{} can't be converted to {}", from, to));
+    }
+    let converted = (value * from.scale() / to.scale()).round();
+    if !converted.is_finite() || converted.abs() >= i64::MAX as f64 {
+        return Err(anyhow!("{}{} is out of range", value, from));
+    }
+    Ok(converted as i64)
+}
+
+/// The value for a parameter in `unit`, when given with a unit: either as a
+/// string like `"90deg"` or `"1.57 rad"`, or as a number while the call's
+/// `unit` argument is `given`. `None` when the value carries no unit.
+pub fn resolve(unit: Unit, value: &Value, given: Option<Unit>) -> Result<Option<i64>> {
+    if let Some(text) = value.as_str() {
+        let text = text.trim();
+        let split = text
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(text.len());
+        let (number, suffix) = text.split_at(split);
+        if suffix.is_empty() {
+            return Ok(None);
+        }
+        let from = Unit::parse(suffix)
+            .ok_or_else(|| anyhow!("unknown unit '{}', expected one of {}", suffix, names(unit)))?;
+        let number: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("expected a number before '{}', got \"{}\"", suffix, text))?;
+        return convert(number, from, unit).map(Some);
+    }
+    match (given, value.as_f64()) {
+        (Some(from), Some(number)) if from.converts_to(unit) => {
+            convert(number, from, unit).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// `deg or rad`, for error messages.
+fn names(unit: Unit) -> String {
+    unit.compatible()
+        .map(Unit::name)
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve_converts_compatible_units() {
+        let resolve = |unit, value: Value, given| resolve(unit, &value, given);
+        assert_eq!(
+            resolve(Unit::Deg, json!("1.5708rad"), None).unwrap(),
+            Some(90)
+        );
+        assert_eq!(resolve(Unit::Mm, json!(" 2.5 cm"), None).unwrap(), Some(25));
+        assert_eq!(
+            resolve(Unit::Ms, json!(1.5), Some(Unit::S)).unwrap(),
+            Some(1500)
+        );
+        assert_eq!(resolve(Unit::Ms, json!("250"), None).unwrap(), None);
+        // A unit argument of another kind is left for other parameters
+        assert_eq!(resolve(Unit::Ms, json!(90), Some(Unit::Deg)).unwrap(), None);
+
+        let err = resolve(Unit::Deg, json!("3cm"), None).unwrap_err();
+        assert_eq!(err.to_string(), "cm can't be converted to deg");
+        let err = resolve(Unit::Deg, json!("90ft"), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown unit 'ft', expected one of deg or rad"
+        );
+    }
+}
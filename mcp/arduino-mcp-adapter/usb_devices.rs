@@ -0,0 +1,146 @@
+//! `devices.toml`: which device a serial port holds, judged by its USB
+//! descriptors, so the port and manifest can be picked before the serial
+//! handshake finishes.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serialport::{SerialPortType, UsbPortInfo};
+use std::fs;
+use std::path::Path;
+
+/// ```toml
+/// [[device]]
+/// id = "arm"
+/// vid = 0x2341
+/// pid = 0x0043
+/// serial = "75439333535351F0A1C2"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsbDeviceMap {
+    #[serde(default, rename = "device")]
+    devices: Vec<UsbDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsbDevice {
+    /// Device ID as `deviceId()` returns it
+    id: String,
+    vid: u16,
+    pid: u16,
+    /// Tells apart boards with the same VID:PID; any serial matches when
+    /// left out
+    serial: Option<String>,
+}
+
+impl UsbDeviceMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read USB device map {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse USB device map {}", path.display()))
+    }
+
+    /// The device ID for a port's USB descriptors. An entry with a matching
+    /// serial number wins over one for any board with that VID:PID.
+    pub fn lookup(&self, usb: &UsbPortInfo) -> Option<&str> {
+        let matching = |device: &&UsbDevice| device.vid == usb.vid && device.pid == usb.pid;
+        self.devices
+            .iter()
+            .filter(matching)
+            .find(|device| {
+                device.serial.is_some() && device.serial.as_deref() == usb.serial_number.as_deref()
+            })
+            .or_else(|| {
+                self.devices
+                    .iter()
+                    .filter(matching)
+                    .find(|device| device.serial.is_none())
+            })
+            .map(|device| device.id.as_str())
+    }
+
+    /// The device ID for the port at `line`, which may be a symlink such as
+    /// a `/dev/serial/by-id` alias.
+    pub fn identify(&self, line: &str) -> Option<&str> {
+        let line = Path::new(line).canonicalize().ok()?;
+        let ports = serialport::available_ports().ok()?;
+        ports
+            .iter()
+            .filter(|port| Path::new(&port.port_name).canonicalize().ok() == Some(line.clone()))
+            .find_map(|port| match &port.port_type {
+                SerialPortType::UsbPort(usb) => self.lookup(usb),
+                _ => None,
+            })
+    }
+
+    /// The one attached port holding a mapped device, with its device ID,
+    /// for running without `--line`.
+    pub fn find_port(&self) -> Result<Option<(String, String)>> {
+        let mut found: Vec<(String, String)> = serialport::available_ports()?
+            .into_iter()
+            .filter_map(|port| match &port.port_type {
+                SerialPortType::UsbPort(usb) => self
+                    .lookup(usb)
+                    .map(|id| (port.port_name.clone(), id.to_string())),
+                _ => None,
+            })
+            .collect();
+        match found.len() {
+            0 | 1 => Ok(found.pop()),
+            _ => {
+                let ports: Vec<String> = found
+                    .iter()
+                    .map(|(port, id)| format!("{} ({})", port, id))
+                    .collect();
+                Err(anyhow!(
+                    "Several mapped devices attached: {}. Pass --line to pick one.",
+                    ports.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb(vid: u16, pid: u16, serial: Option<&str>) -> UsbPortInfo {
+        UsbPortInfo {
+            vid,
+            pid,
+            serial_number: serial.map(str::to_string),
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    #[test]
+    fn test_serial_number_match_wins() {
+        let map: UsbDeviceMap = toml::from_str(
+            r#"
+            [[device]]
+            id = "any-uno"
+            vid = 0x2341
+            pid = 0x0043
+
+            [[device]]
+            id = "arm"
+            vid = 0x2341
+            pid = 0x0043
+            serial = "7543"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(map.lookup(&usb(0x2341, 0x43, Some("7543"))), Some("arm"));
+        assert_eq!(
+            map.lookup(&usb(0x2341, 0x43, Some("9999"))),
+            Some("any-uno")
+        );
+        assert_eq!(map.lookup(&usb(0x2341, 0x43, None)), Some("any-uno"));
+        assert_eq!(map.lookup(&usb(0x1a86, 0x7523, None)), None);
+    }
+}
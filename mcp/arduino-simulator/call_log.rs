@@ -0,0 +1,105 @@
+//! `--call-log`: every call the simulator decodes, written as one JSON line
+//! each, so integration tests can check exactly which commands the adapter
+//! sent.
+
+use anyhow::{Context, Result};
+use mcp_client::responses::ResponseData;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Shared by all simulated devices; lines are written whole.
+pub struct CallLog {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl CallLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create call log: {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+
+    /// ```json
+    /// {"device": "test-robot", "function": "setServo", "arguments": {"angle": 90}, "response": null}
+    /// ```
+    /// with `"error"` instead of `"response"` when the call was refused.
+    pub fn record(
+        &self,
+        device: &str,
+        function: &str,
+        arguments: Map<String, Value>,
+        result: &Result<ResponseData>,
+    ) {
+        let mut entry = json!({
+            "device": device,
+            "function": function,
+            "arguments": arguments,
+        });
+        match result {
+            Ok(response) => entry["response"] = response_json(response),
+            Err(e) => entry["error"] = e.to_string().into(),
+        }
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", entry) {
+            warn!("Failed to write call log: {}", e);
+        }
+    }
+}
+
+/// Blobs are logged by size rather than content.
+fn response_json(response: &ResponseData) -> Value {
+    match response {
+        ResponseData::Void => Value::Null,
+        ResponseData::I16(n) => (*n).into(),
+        ResponseData::I32(n) => (*n).into(),
+        ResponseData::CStr(s) => s.as_str().into(),
+        ResponseData::Blob(data) | ResponseData::Raw(data) => json!({ "bytes": data.len() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_records_one_line_per_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("calls.jsonl");
+        let log = CallLog::create(&path).unwrap();
+
+        let mut arguments = Map::new();
+        arguments.insert("angle".to_string(), 90.into());
+        log.record("arm", "setServo", arguments, &Ok(ResponseData::Void));
+        log.record(
+            "arm",
+            "getImage",
+            Map::new(),
+            &Ok(ResponseData::Blob(vec![0; 3])),
+        );
+        log.record(
+            "arm",
+            "move",
+            Map::new(),
+            &Err(anyhow!("Function 'move' is busy")),
+        );
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines[0],
+            json!({"device": "arm", "function": "setServo", "arguments": {"angle": 90}, "response": null})
+        );
+        assert_eq!(lines[1]["response"], json!({"bytes": 3}));
+        assert_eq!(lines[2]["error"], "Function 'move' is busy");
+    }
+}
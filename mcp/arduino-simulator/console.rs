@@ -0,0 +1,363 @@
+use crate::kinematics::Kinematics;
+use crate::slip::SlipCounts;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Runtime knobs changed from the control console and read by the simulator.
+#[derive(Debug, Default)]
+pub struct Control {
+    /// Return value overrides, by function name
+    values: HashMap<String, String>,
+    /// Scenario response sequences and the index of the next value
+    sequences: HashMap<String, (Vec<String>, usize)>,
+    /// Calls received per function name, including `deviceId`
+    calls: BTreeMap<String, u64>,
+    /// Calls still to be answered with a busy error, by function name
+    busy: HashMap<String, u64>,
+    /// Physical model answering sensor functions, from `--kinematics`
+    pub kinematics: Option<Kinematics>,
+    /// Symlink removed and incoming frames dropped while set
+    pub disconnected: bool,
+    /// Decoder trouble with the frames received so far
+    pub slip: SlipCounts,
+}
+
+impl Control {
+    /// The value to return for `function`: a console override if set, then
+    /// the kinematics model's reading, otherwise the next scenario response,
+    /// cycling.
+    pub fn next_value(&mut self, function: &str) -> Option<String> {
+        if let Some(value) = self.values.get(function) {
+            return Some(value.clone());
+        }
+        let now = Instant::now();
+        if let Some(value) = self
+            .kinematics
+            .as_mut()
+            .and_then(|kinematics| kinematics.read(function, now))
+        {
+            return Some(value);
+        }
+
+        let (values, next) = self.sequences.get_mut(function)?;
+        let value = values[*next % values.len()].clone();
+        *next = (*next + 1) % values.len();
+        Some(value)
+    }
+
+    pub fn set_sequences(&mut self, sequences: HashMap<String, Vec<String>>) {
+        self.sequences = sequences
+            .into_iter()
+            .map(|(function, values)| (function, (values, 0)))
+            .collect();
+    }
+
+    pub fn record_call(&mut self, function: &str) {
+        *self.calls.entry(function.to_string()).or_default() += 1;
+    }
+
+    /// Whether this call of `function` should be answered with a busy error.
+    pub fn take_busy(&mut self, function: &str) -> bool {
+        let Some(remaining) = self.busy.get_mut(function) else {
+            return false;
+        };
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.busy.remove(function);
+        }
+        true
+    }
+}
+
+/// One simulated device the console can address.
+pub struct ConsoleTarget {
+    pub device_id: String,
+    pub control: Arc<Mutex<Control>>,
+    /// Manifest return type per function name, so `set` values can be
+    /// checked before they are sent to the adapter
+    pub return_types: HashMap<String, Option<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Set { function: String, value: String },
+    Unset { function: String },
+    Busy { function: String, count: u64 },
+    Disconnect,
+    Connect,
+    Counts,
+    State,
+    Reset,
+    Help,
+}
+
+const HELP: &str = "Commands:
+  set <function> <value>  return <value> from <function> (e.g. set getBattery 10)
+  unset <function>        go back to the default stub value
+  busy <function> <count> answer the next <count> calls with a busy error
+  disconnect              remove the PTY symlink and ignore frames
+  connect                 restore the symlink
+  counts                  show calls received per function and SLIP errors
+  state                   show the kinematics model's pose, wheels and battery
+  reset                   clear call counts
+  help                    show this help
+Prefix a command with @<device-id> to address one device when simulating
+several; otherwise it applies to all of them.";
+
+pub fn parse_command(line: &str) -> Result<Command> {
+    let mut words = line.split_whitespace();
+    let command = match (words.next(), words.next()) {
+        (Some("set"), Some(function)) => {
+            let value = words.collect::<Vec<_>>().join(" ");
+            if value.is_empty() {
+                return Err(anyhow!("Usage: set <function> <value>"));
+            }
+            return Ok(Command::Set {
+                function: function.to_string(),
+                value,
+            });
+        }
+        (Some("unset"), Some(function)) => Command::Unset {
+            function: function.to_string(),
+        },
+        (Some("busy"), Some(function)) => Command::Busy {
+            function: function.to_string(),
+            count: words
+                .next()
+                .and_then(|count| count.parse().ok())
+                .filter(|&count| count > 0)
+                .ok_or_else(|| anyhow!("Usage: busy <function> <count>"))?,
+        },
+        (Some("disconnect"), None) => Command::Disconnect,
+        (Some("connect"), None) => Command::Connect,
+        (Some("counts"), None) => Command::Counts,
+        (Some("state"), None) => Command::State,
+        (Some("reset"), None) => Command::Reset,
+        (Some("help"), None) => Command::Help,
+        _ => return Err(anyhow!("Unknown command '{}' (try 'help')", line.trim())),
+    };
+
+    if words.next().is_some() {
+        return Err(anyhow!("Too many arguments (try 'help')"));
+    }
+    Ok(command)
+}
+
+/// Read commands from stdin on a background thread. Piping a script into the
+/// simulator, e.g. `(sleep 5; echo "set getBattery 10") | arduino-simulator ...`,
+/// replays a scenario.
+pub fn spawn_stdin_console(targets: Vec<ConsoleTarget>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (device, line) = split_device(&line);
+            let selected: Vec<&ConsoleTarget> = targets
+                .iter()
+                .filter(|t| device.is_none_or(|id| t.device_id == id))
+                .collect();
+            if selected.is_empty() {
+                warn!("Unknown device '{}'", device.unwrap_or_default());
+                continue;
+            }
+
+            match parse_command(line) {
+                Ok(command) => {
+                    for target in selected {
+                        // Same span as the device's own log lines
+                        let _span = (targets.len() > 1).then(|| {
+                            tracing::info_span!("device", id = %target.device_id).entered()
+                        });
+                        if let Err(e) = apply(&target.control, &target.return_types, &command) {
+                            warn!("{}", e);
+                        }
+                    }
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+    });
+}
+
+/// Split an optional leading `@device-id` selector from a console line.
+fn split_device(line: &str) -> (Option<&str>, &str) {
+    match line.trim_start().strip_prefix('@') {
+        Some(rest) => {
+            let (device, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            (Some(device), command)
+        }
+        None => (None, line),
+    }
+}
+
+fn apply(
+    control: &Mutex<Control>,
+    return_types: &HashMap<String, Option<String>>,
+    command: &Command,
+) -> Result<()> {
+    let mut control = control.lock().unwrap();
+
+    match command {
+        Command::Set { function, value } => {
+            let return_type = return_types
+                .get(function)
+                .ok_or_else(|| anyhow!("Unknown function '{}'", function))?;
+            check_value(return_type.as_deref(), value)?;
+            info!("{} now returns {}", function, value);
+            control.values.insert(function.clone(), value.clone());
+        }
+        Command::Unset { function } => {
+            control.values.remove(function);
+            info!("{} returns its default value", function);
+        }
+        Command::Busy { function, count } => {
+            if !return_types.contains_key(function) {
+                return Err(anyhow!("Unknown function '{}'", function));
+            }
+            info!("{} answers busy to the next {} calls", function, count);
+            control.busy.insert(function.clone(), *count);
+        }
+        Command::Disconnect => {
+            control.disconnected = true;
+            info!("Simulating disconnect");
+        }
+        Command::Connect => {
+            control.disconnected = false;
+            info!("Simulating reconnect");
+        }
+        Command::Counts => {
+            if control.calls.is_empty() {
+                info!("No calls received yet");
+            }
+            for (function, count) in &control.calls {
+                info!("  {}: {}", function, count);
+            }
+            info!("SLIP: {}", control.slip);
+        }
+        Command::State => match &control.kinematics {
+            Some(kinematics) => info!("{}", kinematics.describe()),
+            None => info!("No kinematics model (start with --kinematics)"),
+        },
+        Command::Reset => {
+            control.calls.clear();
+            info!("Call counts cleared");
+        }
+        Command::Help => {
+            for line in HELP.lines() {
+                info!("{}", line);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_value(return_type: Option<&str>, value: &str) -> Result<()> {
+    let valid = match return_type {
+        None => return Err(anyhow!("Function has no return value")),
+        Some("i16") => value.parse::<i16>().is_ok(),
+        Some("i32") => value.parse::<i32>().is_ok(),
+        // The value names a file whose bytes are returned
+        Some("blob" | "image") if !std::path::Path::new(value).is_file() => {
+            return Err(anyhow!("'{}' is not a file", value))
+        }
+        Some(_) => true,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "'{}' is not a valid {}",
+            value,
+            return_type.unwrap_or_default()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse_command("set getStatus low battery").unwrap(),
+            Command::Set {
+                function: "getStatus".to_string(),
+                value: "low battery".to_string()
+            }
+        );
+        assert_eq!(parse_command("disconnect").unwrap(), Command::Disconnect);
+        assert!(parse_command("set getBattery").is_err());
+        assert!(parse_command("counts now").is_err());
+        assert_eq!(
+            parse_command("busy moveTo 3").unwrap(),
+            Command::Busy {
+                function: "moveTo".to_string(),
+                count: 3
+            }
+        );
+        assert!(parse_command("busy moveTo 0").is_err());
+    }
+
+    #[test]
+    fn test_scenario_sequence_cycles_until_overridden() {
+        let mut control = Control::default();
+        control.set_sequences(HashMap::from([(
+            "getDistance".to_string(),
+            vec!["100".to_string(), "80".to_string()],
+        )]));
+
+        let mut next = || control.next_value("getDistance");
+        assert_eq!(next().as_deref(), Some("100"));
+        assert_eq!(next().as_deref(), Some("80"));
+        assert_eq!(next().as_deref(), Some("100"));
+
+        control
+            .values
+            .insert("getDistance".to_string(), "5".to_string());
+        assert_eq!(control.next_value("getDistance").as_deref(), Some("5"));
+        assert_eq!(control.next_value("getStatus"), None);
+    }
+
+    #[test]
+    fn test_split_device_selector() {
+        assert_eq!(split_device("@arm set x 1"), (Some("arm"), "set x 1"));
+        assert_eq!(split_device("counts"), (None, "counts"));
+    }
+
+    #[test]
+    fn test_set_checks_return_type() {
+        let control = Mutex::new(Control::default());
+        let return_types = HashMap::from([
+            ("getBattery".to_string(), Some("i16".to_string())),
+            ("blinkLED".to_string(), None),
+        ]);
+        let set = |function: &str, value: &str| {
+            apply(
+                &control,
+                &return_types,
+                &Command::Set {
+                    function: function.to_string(),
+                    value: value.to_string(),
+                },
+            )
+        };
+
+        assert!(set("getBattery", "10").is_ok());
+        assert!(set("getBattery", "40000").is_err());
+        assert!(set("blinkLED", "1").is_err());
+        assert_eq!(
+            control.lock().unwrap().next_value("getBattery").as_deref(),
+            Some("10")
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! `--expect`: the calls the simulator should receive, in order. It stops
+//! at the first call that deviates from the script, and exits non-zero if
+//! a call deviated or is still missing when it shuts down.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{error, info};
+
+/// ```json
+/// {
+///   "calls": [
+///     {"function": "blinkLED", "arguments": {"n": 3}},
+///     {"function": "getStatus"}
+///   ]
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Script {
+    calls: Vec<ExpectedCall>,
+    /// Functions left out of the check, called whenever the adapter connects
+    #[serde(default = "default_ignore")]
+    ignore: Vec<String>,
+}
+
+fn default_ignore() -> Vec<String> {
+    vec!["deviceId".to_string()]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpectedCall {
+    /// Required with `--devices`; order is checked per device
+    device: Option<String>,
+    function: String,
+    /// Must match exactly when given; any arguments are accepted otherwise
+    arguments: Option<Map<String, Value>>,
+}
+
+impl fmt::Display for ExpectedCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.arguments {
+            Some(arguments) => write!(f, "{}({})", self.function, Value::from(arguments.clone())),
+            None => write!(f, "{}(...)", self.function),
+        }
+    }
+}
+
+pub struct Expectations {
+    ignore: Vec<String>,
+    /// Calls still to come, per device ID
+    pending: Mutex<HashMap<String, VecDeque<ExpectedCall>>>,
+    deviation: Mutex<Option<String>>,
+}
+
+impl Expectations {
+    /// Load the script for the simulated `devices`.
+    pub fn load(path: &Path, devices: &[String]) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read expected calls: {}", path.display()))?;
+        let script: Script = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse expected calls: {}", path.display()))?;
+        Self::new(script, devices)
+    }
+
+    fn new(script: Script, devices: &[String]) -> Result<Self> {
+        let mut pending: HashMap<String, VecDeque<ExpectedCall>> = devices
+            .iter()
+            .map(|device| (device.clone(), VecDeque::new()))
+            .collect();
+        for call in script.calls {
+            let device = match (&call.device, devices) {
+                (Some(device), _) => device.clone(),
+                (None, [device]) => device.clone(),
+                (None, _) => {
+                    return Err(anyhow!(
+                        "Expected call {} must name its device when simulating several",
+                        call
+                    ))
+                }
+            };
+            pending
+                .get_mut(&device)
+                .ok_or_else(|| {
+                    anyhow!("Expected call {} is for unknown device '{}'", call, device)
+                })?
+                .push_back(call);
+        }
+        Ok(Self {
+            ignore: script.ignore,
+            pending: Mutex::new(pending),
+            deviation: Mutex::new(None),
+        })
+    }
+
+    /// Check a call `device` received against the next one expected.
+    pub fn check(&self, device: &str, function: &str, arguments: &Map<String, Value>) {
+        if self.ignore.iter().any(|ignored| ignored == function) {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        let received = format!("{}({})", function, Value::from(arguments.clone()));
+        let deviation = match pending.get_mut(device).and_then(VecDeque::pop_front) {
+            None => format!("Unexpected extra call {}", received),
+            Some(expected)
+                if expected.function != function
+                    || expected.arguments.as_ref().is_some_and(|a| a != arguments) =>
+            {
+                format!("Received {}, expected {}", received, expected)
+            }
+            Some(_) => {
+                info!("Expected call {} received", received);
+                return;
+            }
+        };
+        error!("{}", deviation);
+        self.deviation.lock().unwrap().get_or_insert(deviation);
+    }
+
+    /// Whether a call has deviated, so the simulator should stop.
+    pub fn deviated(&self) -> bool {
+        self.deviation.lock().unwrap().is_some()
+    }
+
+    /// The verdict at shutdown.
+    pub fn finish(&self) -> Result<()> {
+        if let Some(deviation) = self.deviation.lock().unwrap().as_ref() {
+            return Err(anyhow!("{}", deviation));
+        }
+        let pending = self.pending.lock().unwrap();
+        let missing: Vec<String> = pending
+            .values()
+            .flatten()
+            .map(ExpectedCall::to_string)
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Expected calls not received: {}",
+                missing.join(", ")
+            ));
+        }
+        info!("All expected calls received");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expectations() -> Expectations {
+        let script = serde_json::from_value(json!({
+            "calls": [
+                {"function": "blinkLED", "arguments": {"n": 3}},
+                {"function": "getStatus"}
+            ]
+        }))
+        .unwrap();
+        Expectations::new(script, &["test-robot".to_string()]).unwrap()
+    }
+
+    fn arguments(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_calls_in_order_pass() {
+        let expect = expectations();
+        expect.check("test-robot", "deviceId", &Map::new());
+        expect.check("test-robot", "blinkLED", &arguments(json!({"n": 3})));
+        assert!(expect.finish().is_err());
+        expect.check("test-robot", "getStatus", &Map::new());
+        assert!(!expect.deviated());
+        expect.finish().unwrap();
+
+        expect.check("test-robot", "getStatus", &Map::new());
+        assert!(expect.deviated());
+    }
+
+    #[test]
+    fn test_wrong_arguments_deviate() {
+        let expect = expectations();
+        expect.check("test-robot", "blinkLED", &arguments(json!({"n": 4})));
+        assert!(expect.deviated());
+        let err = expect.finish().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Received blinkLED({"n":4}), expected blinkLED({"n":3})"#
+        );
+    }
+}
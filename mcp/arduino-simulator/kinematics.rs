@@ -0,0 +1,347 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use tracing::info;
+
+/// A simple physical model loaded from `--kinematics`, so sensor functions
+/// answer consistently with the commands sent before them.
+///
+/// ```json
+/// {
+///   "track_width": 120,
+///   "drive": { "setMotors": { "left": "left", "right": "right" } },
+///   "move": { "driveDistance": "mm" },
+///   "turn": { "turnBy": "degrees" },
+///   "servos": { "setServo": "angle" },
+///   "battery": { "drain_per_sec": 0.01, "drain_per_unit": 0.001 },
+///   "sensors": { "getPosition": "pose", "getBattery": "battery", "getArm": "servo:setServo" }
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KinematicsSpec {
+    /// Distance between the wheels, in distance units
+    #[serde(default = "default_track_width")]
+    pub track_width: f64,
+    /// Distance units per second for one unit of wheel speed
+    #[serde(default = "default_speed_scale")]
+    pub speed_scale: f64,
+    /// Functions setting the two wheel speeds, with their parameter names
+    #[serde(default)]
+    pub drive: HashMap<String, WheelParams>,
+    /// Functions moving straight by a distance parameter, done at once
+    #[serde(default, rename = "move")]
+    pub moves: HashMap<String, String>,
+    /// Functions turning on the spot by a degrees parameter, done at once
+    #[serde(default)]
+    pub turn: HashMap<String, String>,
+    /// Functions setting a servo, with the parameter holding its position
+    #[serde(default)]
+    pub servos: HashMap<String, String>,
+    #[serde(default)]
+    pub battery: BatterySpec,
+    /// What each getter returns: `pose`, `x`, `y`, `heading`, `battery` or
+    /// `servo:<function>`
+    #[serde(default)]
+    pub sensors: HashMap<String, String>,
+}
+
+fn default_track_width() -> f64 {
+    100.0
+}
+
+fn default_speed_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WheelParams {
+    pub left: String,
+    pub right: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatterySpec {
+    /// Charge in percent at start
+    pub start: f64,
+    /// Percent lost per second, moving or not
+    pub drain_per_sec: f64,
+    /// Percent lost per distance unit travelled
+    pub drain_per_unit: f64,
+}
+
+impl Default for BatterySpec {
+    fn default() -> Self {
+        Self {
+            start: 100.0,
+            drain_per_sec: 0.0,
+            drain_per_unit: 0.0,
+        }
+    }
+}
+
+impl KinematicsSpec {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read kinematics file: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse kinematics file: {}", path.display()))
+    }
+
+    /// Check the spec against a device's functions and their parameter
+    /// names. Functions the device lacks are skipped, as with scenarios.
+    pub fn check(&self, params: &HashMap<String, Vec<String>>) -> Result<()> {
+        let uses = self
+            .drive
+            .iter()
+            .flat_map(|(f, wheels)| [(f, &wheels.left), (f, &wheels.right)])
+            .chain(&self.moves)
+            .chain(&self.turn)
+            .chain(&self.servos);
+        for (function, param) in uses {
+            if let Some(names) = params.get(function) {
+                if !names.contains(param) {
+                    return Err(anyhow!(
+                        "Kinematics: '{}' has no parameter '{}'",
+                        function,
+                        param
+                    ));
+                }
+            }
+        }
+        for (function, quantity) in &self.sensors {
+            let valid = match quantity.strip_prefix("servo:") {
+                Some(servo) => self.servos.contains_key(servo),
+                None => matches!(
+                    quantity.as_str(),
+                    "pose" | "x" | "y" | "heading" | "battery"
+                ),
+            };
+            if !valid {
+                return Err(anyhow!(
+                    "Kinematics: unknown sensor '{}' for '{}'",
+                    quantity,
+                    function
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The simulated robot's state.
+#[derive(Debug)]
+pub struct Kinematics {
+    spec: KinematicsSpec,
+    x: f64,
+    y: f64,
+    /// Radians counter-clockwise from the x axis
+    heading: f64,
+    /// Wheel speeds last set, in distance units per second
+    left: f64,
+    right: f64,
+    battery: f64,
+    servos: HashMap<String, f64>,
+    updated: Instant,
+}
+
+impl Kinematics {
+    pub fn new(spec: KinematicsSpec, now: Instant) -> Self {
+        Self {
+            battery: spec.battery.start,
+            spec,
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            left: 0.0,
+            right: 0.0,
+            servos: HashMap::new(),
+            updated: now,
+        }
+    }
+
+    /// Apply a call of `function` with its numeric `arguments`.
+    pub fn apply(&mut self, function: &str, arguments: &HashMap<&str, f64>, now: Instant) {
+        self.advance(now);
+        let arg = |name: &String| arguments.get(name.as_str()).copied().unwrap_or(0.0);
+
+        if let Some(wheels) = self.spec.drive.get(function) {
+            let scale = self.spec.speed_scale;
+            (self.left, self.right) = (arg(&wheels.left) * scale, arg(&wheels.right) * scale);
+            if self.battery <= 0.0 {
+                (self.left, self.right) = (0.0, 0.0);
+            }
+        } else if let Some(param) = self.spec.moves.get(function) {
+            let distance = arg(param);
+            self.x += distance * self.heading.cos();
+            self.y += distance * self.heading.sin();
+            self.drain(distance.abs());
+        } else if let Some(param) = self.spec.turn.get(function) {
+            self.heading = (self.heading + arg(param).to_radians()).rem_euclid(TAU);
+        } else if let Some(param) = self.spec.servos.get(function) {
+            self.servos.insert(function.to_string(), arg(param));
+        } else {
+            return;
+        }
+        info!("Kinematics: {}", self.describe());
+    }
+
+    /// What a sensor function reads now, if it is modelled.
+    pub fn read(&mut self, function: &str, now: Instant) -> Option<String> {
+        let quantity = self.spec.sensors.get(function)?.clone();
+        self.advance(now);
+        let heading = whole(self.heading.to_degrees().rem_euclid(360.0)) % 360;
+        let value = match quantity.as_str() {
+            "pose" => format!("{},{},{}", whole(self.x), whole(self.y), heading),
+            "x" => whole(self.x).to_string(),
+            "y" => whole(self.y).to_string(),
+            "heading" => heading.to_string(),
+            "battery" => whole(self.battery).to_string(),
+            servo => {
+                let servo = servo.strip_prefix("servo:")?;
+                whole(self.servos.get(servo).copied().unwrap_or(0.0)).to_string()
+            }
+        };
+        Some(value)
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "pose ({:.0}, {:.0}) heading {:.0}°, wheels {}/{}, battery {:.1}%",
+            self.x,
+            self.y,
+            self.heading.to_degrees(),
+            self.left,
+            self.right,
+            self.battery
+        )
+    }
+
+    /// Integrate the wheel speeds up to `now`.
+    fn advance(&mut self, now: Instant) {
+        let dt = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        if dt == 0.0 {
+            return;
+        }
+        self.battery -= self.spec.battery.drain_per_sec * dt;
+
+        let speed = (self.left + self.right) / 2.0;
+        let rate = (self.right - self.left) / self.spec.track_width;
+        if rate.abs() < 1e-9 {
+            self.x += speed * dt * self.heading.cos();
+            self.y += speed * dt * self.heading.sin();
+        } else {
+            // Along an arc of radius speed / rate
+            let heading = self.heading + rate * dt;
+            self.x += speed / rate * (heading.sin() - self.heading.sin());
+            self.y += speed / rate * (self.heading.cos() - heading.cos());
+            self.heading = heading.rem_euclid(TAU);
+        }
+        self.drain(speed.abs() * dt);
+    }
+
+    fn drain(&mut self, distance: f64) {
+        self.battery = (self.battery - self.spec.battery.drain_per_unit * distance).max(0.0);
+        if self.battery == 0.0 {
+            (self.left, self.right) = (0.0, 0.0);
+        }
+    }
+}
+
+/// Readings go out as whole numbers, which every return type can carry.
+fn whole(value: f64) -> i64 {
+    value.round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn spec() -> KinematicsSpec {
+        serde_json::from_str(
+            r#"{
+                "track_width": 100,
+                "drive": {"setMotors": {"left": "left", "right": "right"}},
+                "move": {"driveDistance": "mm"},
+                "turn": {"turnBy": "degrees"},
+                "servos": {"setServo": "angle"},
+                "battery": {"drain_per_sec": 1, "drain_per_unit": 0.01},
+                "sensors": {"getPosition": "pose", "getBattery": "battery",
+                            "getArm": "servo:setServo"}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn args<'a>(pairs: &[(&'a str, f64)]) -> HashMap<&'a str, f64> {
+        pairs.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_moves_turns_and_servos() {
+        let now = Instant::now();
+        let mut robot = Kinematics::new(spec(), now);
+        robot.apply("driveDistance", &args(&[("mm", 200.0)]), now);
+        robot.apply("turnBy", &args(&[("degrees", 90.0)]), now);
+        robot.apply("driveDistance", &args(&[("mm", 50.0)]), now);
+        robot.apply("setServo", &args(&[("angle", 45.0)]), now);
+
+        assert_eq!(robot.read("getPosition", now).unwrap(), "200,50,90");
+        assert_eq!(robot.read("getArm", now).unwrap(), "45");
+        // 250 units at 0.01% each
+        assert_eq!(robot.read("getBattery", now).unwrap(), "98");
+        assert_eq!(robot.read("getStatus", now), None);
+    }
+
+    #[test]
+    fn test_differential_drive_integrates_over_time() {
+        let start = Instant::now();
+        let mut robot = Kinematics::new(spec(), start);
+        robot.apply(
+            "setMotors",
+            &args(&[("left", 100.0), ("right", 100.0)]),
+            start,
+        );
+        let later = start + Duration::from_secs(2);
+        assert_eq!(robot.read("getPosition", later).unwrap(), "200,0,0");
+
+        // Spinning on the spot: a quarter turn at 100 / 100 rad/s
+        robot.apply(
+            "setMotors",
+            &args(&[("left", -50.0), ("right", 50.0)]),
+            later,
+        );
+        let turned = later + Duration::from_secs_f64(std::f64::consts::FRAC_PI_2);
+        assert_eq!(robot.read("getPosition", turned).unwrap(), "200,0,90");
+    }
+
+    #[test]
+    fn test_check_against_device_functions() {
+        let params = HashMap::from([
+            (
+                "setMotors".to_string(),
+                vec!["left".to_string(), "right".to_string()],
+            ),
+            ("driveDistance".to_string(), vec!["distance".to_string()]),
+        ]);
+        let err = spec().check(&params).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("'driveDistance' has no parameter 'mm'"));
+
+        let mut spec = spec();
+        spec.moves.clear();
+        assert!(spec.check(&params).is_ok());
+        spec.sensors
+            .insert("getSpeed".to_string(), "speed".to_string());
+        assert!(spec.check(&params).is_err());
+    }
+}
@@ -1,24 +1,51 @@
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
+use mcp_client::responses::{encode_response, error_frame, package, ResponseData};
 use nix::fcntl::OFlag;
 use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt, PtyMaster};
 use nix::unistd::read;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
 use std::fs;
+use std::io::IsTerminal;
 use std::os::unix::fs as unix_fs;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
+mod call_log;
+mod console;
+mod expect;
+mod kinematics;
 // Re-use SLIP protocol constants and logic
 mod protocol;
+mod scenario;
 mod slip;
 
-use protocol::{crc8, decode_command, encode_response, ResponseData};
+use call_log::CallLog;
+use console::{spawn_stdin_console, ConsoleTarget, Control};
+use expect::Expectations;
+use kinematics::{Kinematics, KinematicsSpec};
+use protocol::{
+    crc8, decode_command, CommandError, ACK, FLAG_CHUNKED, FLAG_RELIABLE, MANIFEST_VERSION_TAG,
+    MAX_FRAME_SIZE, PROTOCOL_VERSION, PROTOCOL_VERSION_TAG,
+};
+use scenario::Scenario;
 use slip::{slip_encode, SlipDecoder};
 
+/// 1x1 grey PNG returned by `image` functions with no value set.
+const PLACEHOLDER_PNG: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3A, 0x7E, 0x9B,
+    0x55, 0x00, 0x00, 0x00, 0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x68, 0x00, 0x00, 0x00,
+    0x82, 0x00, 0x81, 0x77, 0xCD, 0x72, 0xB6, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE,
+    0x42, 0x60, 0x82,
+];
+
 #[derive(Parser, Debug)]
 #[command(name = "arduino-simulator")]
 #[command(about = "Arduino simulator for testing MCP communication")]
@@ -26,11 +53,116 @@ use slip::{slip_encode, SlipDecoder};
     long_about = "Simulates an Arduino device by creating a PTY and implementing the MCP serial protocol"
 )]
 struct Args {
-    #[arg(short, long, help = "Path to symlink for the PTY (e.g., /tmp/mytty)")]
+    #[arg(
+        short,
+        long,
+        help = "Path to symlink for the PTY (e.g., /tmp/mytty); with --devices, a directory holding one symlink per device"
+    )]
     line: PathBuf,
 
-    #[arg(short, long, help = "Path to JSON manifest file")]
-    manifest: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "Path to JSON manifest file",
+        required_unless_present = "devices",
+        conflicts_with = "devices"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Simulate several devices from one process (comma-separated manifest files)"
+    )]
+    devices: Option<Vec<PathBuf>>,
+
+    #[arg(
+        long,
+        help = "Expect a sequence byte before each command tag and echo it in responses (for --pipeline-depth > 1)"
+    )]
+    sequence_numbers: bool,
+
+    #[arg(
+        long,
+        help = "Send each response in a single frame, as protocol v2 firmware does, instead of in chunks"
+    )]
+    single_frame: bool,
+
+    #[arg(
+        long,
+        help = "Speak the reliable link layer: acknowledge each command, and answer a repeated one from the last response"
+    )]
+    reliable: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "Damage this percentage of the frames sent, as on a noisy link"
+    )]
+    noise: u8,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Delay every response by this many milliseconds (firmware processing time)"
+    )]
+    latency_ms: u64,
+
+    #[arg(
+        long,
+        help = "Pace response bytes as on a real link at this baud rate (8N1, 10 bits per byte)"
+    )]
+    throttle_baud: Option<u32>,
+
+    #[arg(
+        long,
+        help = "JSON file with canned responses per function, returned in order and repeated"
+    )]
+    scenario: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "JSON file describing a kinematics model (drive, servos, battery) that answers sensor functions"
+    )]
+    kinematics: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Report this manifest version instead of the manifest's own (to test version mismatch detection)"
+    )]
+    firmware_version: Option<String>,
+
+    #[arg(
+        long,
+        help = "Write each decoded call (function, arguments, response) to this file as JSON lines"
+    )]
+    call_log: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "JSON file of the calls to expect, in order; exit non-zero if the calls received deviate"
+    )]
+    expect: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Read control commands from stdin even when it isn't a terminal, such as a pipe"
+    )]
+    console: bool,
+}
+
+/// Per-device behaviour shared by every simulated device.
+#[derive(Debug, Clone, Copy)]
+struct SimulatorOptions {
+    sequence_numbers: bool,
+    /// Protocol v3 chunked responses
+    chunked: bool,
+    reliable: bool,
+    /// Percentage of frames sent damaged
+    noise: u8,
+    latency: Duration,
+    throttle_baud: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -60,12 +192,13 @@ struct Parameter {
 
 struct PtySymlink {
     symlink_path: PathBuf,
+    target_path: PathBuf,
 }
 
 impl PtySymlink {
     fn new(symlink_path: PathBuf, target_path: &Path) -> Result<Self> {
-        // Remove existing symlink if it exists
-        if symlink_path.exists() {
+        // Remove existing symlink if it exists (dangling links included)
+        if symlink_path.symlink_metadata().is_ok() {
             info!("Removing existing symlink at {}", symlink_path.display());
             fs::remove_file(&symlink_path).with_context(|| {
                 format!(
@@ -84,13 +217,27 @@ impl PtySymlink {
         unix_fs::symlink(target_path, &symlink_path)
             .with_context(|| format!("Failed to create symlink: {}", symlink_path.display()))?;
 
-        Ok(Self { symlink_path })
+        Ok(Self {
+            symlink_path,
+            target_path: target_path.to_path_buf(),
+        })
+    }
+
+    /// Remove the symlink so the adapter sees the device disappear.
+    fn remove(&self) -> Result<()> {
+        fs::remove_file(&self.symlink_path)
+            .with_context(|| format!("Failed to remove symlink: {}", self.symlink_path.display()))
+    }
+
+    fn restore(&self) -> Result<()> {
+        unix_fs::symlink(&self.target_path, &self.symlink_path)
+            .with_context(|| format!("Failed to create symlink: {}", self.symlink_path.display()))
     }
 }
 
 impl Drop for PtySymlink {
     fn drop(&mut self) {
-        if self.symlink_path.exists() {
+        if self.symlink_path.symlink_metadata().is_ok() {
             info!("Cleaning up symlink at {}", self.symlink_path.display());
             if let Err(e) = fs::remove_file(&self.symlink_path) {
                 error!("Failed to remove symlink: {}", e);
@@ -103,28 +250,32 @@ struct Simulator {
     manifest: Manifest,
     device_id: String,
     pty_master: PtyMaster,
-    _symlink: PtySymlink,
+    symlink: PtySymlink,
     slip_decoder: SlipDecoder,
+    options: SimulatorOptions,
+    control: Arc<Mutex<Control>>,
+    /// Whether a console `disconnect` is currently in effect
+    simulating_disconnect: bool,
+    call_log: Option<Arc<CallLog>>,
+    expectations: Option<Arc<Expectations>>,
+    /// Sequence number and response frames of the last reliable command
+    last_reply: Option<(u8, Vec<Vec<u8>>)>,
+    /// xorshift state deciding which frames `--noise` damages
+    noise_state: u64,
 }
 
 impl Simulator {
-    fn new(args: Args) -> Result<Self> {
+    fn new(line: &Path, manifest_path: &Path, options: SimulatorOptions) -> Result<Self> {
         // Load manifest
-        let manifest_content = fs::read_to_string(&args.manifest).with_context(|| {
-            format!("Failed to read manifest file: {}", args.manifest.display())
+        let manifest_content = fs::read_to_string(manifest_path).with_context(|| {
+            format!("Failed to read manifest file: {}", manifest_path.display())
         })?;
 
         let manifest: Manifest = serde_json::from_str(&manifest_content).with_context(|| {
-            format!("Failed to parse manifest file: {}", args.manifest.display())
+            format!("Failed to parse manifest file: {}", manifest_path.display())
         })?;
 
-        // Derive device ID from manifest filename (without .json extension)
-        let device_id = args
-            .manifest
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow!("Invalid manifest filename"))?
-            .to_string();
+        let device_id = device_id_for(manifest_path)?;
 
         info!(
             "Loaded manifest: {} ({})",
@@ -168,18 +319,96 @@ impl Simulator {
         info!("PTY slave: {}", slave_name);
 
         // Create symlink
-        let symlink = PtySymlink::new(args.line.clone(), Path::new(&slave_name))?;
-        info!("Symlink created at: {}", args.line.display());
+        let symlink = PtySymlink::new(line.to_path_buf(), Path::new(&slave_name))?;
+        info!("Symlink created at: {}", line.display());
 
         Ok(Self {
             manifest,
             device_id,
             pty_master,
-            _symlink: symlink,
+            symlink,
             slip_decoder: SlipDecoder::new(),
+            options,
+            control: Arc::new(Mutex::new(Control::default())),
+            simulating_disconnect: false,
+            call_log: None,
+            expectations: None,
+            last_reply: None,
+            noise_state: noise_seed(),
         })
     }
 
+    fn return_types(&self) -> HashMap<String, Option<String>> {
+        self.manifest
+            .functions
+            .iter()
+            .map(|f| (f.name.clone(), f.return_type.clone()))
+            .collect()
+    }
+
+    /// What the stdin control console needs to drive this device.
+    fn console_target(&self) -> ConsoleTarget {
+        ConsoleTarget {
+            device_id: self.device_id.clone(),
+            control: Arc::clone(&self.control),
+            return_types: self.return_types(),
+        }
+    }
+
+    /// Return the scenario's canned responses for this device's functions.
+    fn load_scenario(&self, scenario: &Scenario) -> Result<()> {
+        let sequences = scenario.sequences_for(&self.return_types())?;
+        for (function, values) in &sequences {
+            info!("Scenario: {} returns {:?}", function, values);
+        }
+        self.control.lock().unwrap().set_sequences(sequences);
+        Ok(())
+    }
+
+    /// Model this device with `spec`, checked against its functions.
+    fn load_kinematics(&self, spec: &KinematicsSpec) -> Result<()> {
+        let params = self
+            .manifest
+            .functions
+            .iter()
+            .map(|f| {
+                (
+                    f.name.clone(),
+                    f.params.iter().map(|p| p.name.clone()).collect(),
+                )
+            })
+            .collect();
+        spec.check(&params)?;
+        self.control.lock().unwrap().kinematics =
+            Some(Kinematics::new(spec.clone(), Instant::now()));
+        Ok(())
+    }
+
+    /// Apply a console `disconnect`/`connect` by removing or restoring the symlink.
+    fn sync_simulated_disconnect(&mut self) {
+        let disconnected = self.control.lock().unwrap().disconnected;
+        if disconnected == self.simulating_disconnect {
+            return;
+        }
+
+        let result = if disconnected {
+            self.symlink.remove()
+        } else {
+            self.symlink.restore()
+        };
+        match result {
+            Ok(()) => {
+                self.simulating_disconnect = disconnected;
+                self.slip_decoder.reset();
+            }
+            Err(e) => {
+                error!("{}", e);
+                // Don't retry every loop iteration
+                self.control.lock().unwrap().disconnected = self.simulating_disconnect;
+            }
+        }
+    }
+
     fn handle_command(&self, frame: &[u8]) -> Result<Vec<u8>> {
         // Decode command frame (tag + args + CRC)
         let (tag, args) = decode_command(frame)?;
@@ -192,9 +421,34 @@ impl Simulator {
 
         // Handle tag 0 (deviceId) specially
         if tag == 0 {
+            self.control.lock().unwrap().record_call("deviceId");
             info!("[deviceId()] -> \"{}\"", self.device_id);
-            let response = encode_response(&ResponseData::CStr(self.device_id.clone()))?;
-            return Ok(response);
+            let response = Ok(ResponseData::CStr(self.device_id.clone()));
+            self.note_call("deviceId", Map::new(), &response);
+            return encode_response(&response?);
+        }
+
+        if tag == MANIFEST_VERSION_TAG {
+            info!("[getManifestVersion()] -> \"{}\"", self.manifest.version);
+            return encode_response(&ResponseData::CStr(self.manifest.version.clone()));
+        }
+
+        if tag == PROTOCOL_VERSION_TAG {
+            // [version] [flags: bit 0 = sequence numbers, bit 2 = chunked,
+            // bit 3 = reliable] [max frame size: u16]
+            let (version, chunked) = match self.options.chunked {
+                true => (PROTOCOL_VERSION, FLAG_CHUNKED),
+                false => (2, 0),
+            };
+            let reliable = match self.options.reliable {
+                true => FLAG_RELIABLE,
+                false => 0,
+            };
+            let flags = u8::from(self.options.sequence_numbers) | chunked | reliable;
+            let mut data = vec![version, flags];
+            data.extend_from_slice(&(MAX_FRAME_SIZE as u16).to_le_bytes());
+            debug!("[getProtocolVersion()] -> v{}", version);
+            return encode_response(&ResponseData::Raw(data));
         }
 
         // Find function in manifest
@@ -205,7 +459,7 @@ impl Simulator {
             .find(|f| f.tag == tag)
             .ok_or_else(|| {
                 warn!("Unknown function tag: {}", tag);
-                anyhow!("Unknown function tag: {}", tag)
+                CommandError::UnknownTag(tag)
             })?;
 
         // Parse arguments
@@ -224,35 +478,118 @@ impl Simulator {
             args_str.join(", ")
         };
 
-        // Generate stub response based on return type
+        let response = self.respond(func, &parsed_args, &args_display);
+        let arguments = func
+            .params
+            .iter()
+            .map(|p| p.name.clone())
+            .zip(parsed_args)
+            .collect();
+        self.note_call(&func.name, arguments, &response);
+        encode_response(&response?)
+    }
+
+    /// Whether a call deviated from `--expect`, which stops every device.
+    fn deviated(&self) -> bool {
+        self.expectations.as_ref().is_some_and(|e| e.deviated())
+    }
+
+    /// Check a decoded call against `--expect` and write it to `--call-log`.
+    fn note_call(
+        &self,
+        function: &str,
+        arguments: Map<String, Value>,
+        response: &Result<ResponseData>,
+    ) {
+        if let Some(expectations) = &self.expectations {
+            expectations.check(&self.device_id, function, &arguments);
+        }
+        if let Some(call_log) = &self.call_log {
+            call_log.record(&self.device_id, function, arguments, response);
+        }
+    }
+
+    fn respond(
+        &self,
+        func: &Function,
+        parsed_args: &[Value],
+        args_display: &str,
+    ) -> Result<ResponseData> {
+        // Generate stub response based on return type, unless the console
+        // set a value for this function
+        let value = {
+            let mut control = self.control.lock().unwrap();
+            control.record_call(&func.name);
+            if control.take_busy(&func.name) {
+                info!("[{}({})] -> busy", func.name, args_display);
+                return Err(CommandError::Busy(func.name.clone()).into());
+            }
+            if let Some(kinematics) = control.kinematics.as_mut() {
+                let arguments = func
+                    .params
+                    .iter()
+                    .zip(parsed_args.iter())
+                    .filter_map(|(p, v)| Some((p.name.as_str(), v.as_f64()?)))
+                    .collect();
+                kinematics.apply(&func.name, &arguments, Instant::now());
+            }
+            control.next_value(&func.name)
+        };
         let response_data = match func.return_type.as_deref() {
             None => {
                 info!("[{}({})] -> void", func.name, args_display);
                 ResponseData::Void
             }
             Some("i16") => {
-                info!("[{}({})] -> 0 (i16)", func.name, args_display);
-                ResponseData::I16(0)
+                let n = value.and_then(|v| v.parse().ok()).unwrap_or(0);
+                info!("[{}({})] -> {} (i16)", func.name, args_display, n);
+                ResponseData::I16(n)
             }
             Some("i32") => {
-                info!("[{}({})] -> 0 (i32)", func.name, args_display);
-                ResponseData::I32(0)
+                let n = value.and_then(|v| v.parse().ok()).unwrap_or(0);
+                info!("[{}({})] -> {} (i32)", func.name, args_display, n);
+                ResponseData::I32(n)
             }
             Some("CStr") => {
-                info!("[{}({})] -> \"\" (CStr)", func.name, args_display);
-                ResponseData::CStr(String::new())
+                let s = value.unwrap_or_default();
+                info!("[{}({})] -> \"{}\" (CStr)", func.name, args_display, s);
+                ResponseData::CStr(s)
+            }
+            Some(kind @ ("blob" | "image")) => {
+                // A value set from the console or a scenario names a file to send
+                let data = match value {
+                    Some(path) => fs::read(&path)
+                        .with_context(|| format!("Failed to read {} for {}", path, func.name))?,
+                    None if kind == "image" => PLACEHOLDER_PNG.to_vec(),
+                    None => Vec::new(),
+                };
+                // Refused by the firmware when too big for a frame, or for
+                // 255 chunks
+                let max_len = match self.options.chunked {
+                    true => 255 * (MAX_FRAME_SIZE - 4),
+                    false => MAX_FRAME_SIZE - 1,
+                };
+                if data.len() + 2 > max_len {
+                    return Err(CommandError::TooLarge(data.len()).into());
+                }
+                info!(
+                    "[{}({})] -> {} bytes ({})",
+                    func.name,
+                    args_display,
+                    data.len(),
+                    kind
+                );
+                ResponseData::Blob(data)
             }
             Some(other) => {
                 warn!("Unknown return type: {}, returning empty string", other);
                 ResponseData::CStr(String::new())
             }
         };
-
-        let response = encode_response(&response_data)?;
-        Ok(response)
+        Ok(response_data)
     }
 
-    fn parse_arguments(&self, params: &[Parameter], args: &[u8]) -> Result<Vec<String>> {
+    fn parse_arguments(&self, params: &[Parameter], args: &[u8]) -> Result<Vec<Value>> {
         let mut result = Vec::new();
         let mut offset = 0;
 
@@ -260,15 +597,21 @@ impl Simulator {
             match param.param_type.as_str() {
                 "i16" => {
                     if offset + 2 > args.len() {
-                        return Err(anyhow!("Not enough data for i16 parameter"));
+                        return Err(CommandError::BadArguments(
+                            "Not enough data for i16 parameter".to_string(),
+                        )
+                        .into());
                     }
                     let value = i16::from_le_bytes([args[offset], args[offset + 1]]);
-                    result.push(value.to_string());
+                    result.push(value.into());
                     offset += 2;
                 }
                 "i32" => {
                     if offset + 4 > args.len() {
-                        return Err(anyhow!("Not enough data for i32 parameter"));
+                        return Err(CommandError::BadArguments(
+                            "Not enough data for i32 parameter".to_string(),
+                        )
+                        .into());
                     }
                     let value = i32::from_le_bytes([
                         args[offset],
@@ -276,7 +619,7 @@ impl Simulator {
                         args[offset + 2],
                         args[offset + 3],
                     ]);
-                    result.push(value.to_string());
+                    result.push(value.into());
                     offset += 4;
                 }
                 "CStr" => {
@@ -286,7 +629,7 @@ impl Simulator {
                         .map(|p| offset + p)
                         .unwrap_or(args.len());
                     let s = String::from_utf8_lossy(&args[offset..end]).to_string();
-                    result.push(format!("\"{}\"", s));
+                    result.push(s.into());
                     offset = end + 1; // Skip null terminator
                 }
                 _ => {
@@ -298,21 +641,125 @@ impl Simulator {
         Ok(result)
     }
 
-    fn send_error_response(&mut self, error_code: u8) -> Result<()> {
-        // Error frame: [0xFF] [error_code] [CRC]
-        let mut frame = vec![0xFF, error_code];
-        let crc = crc8(&frame);
-        frame.push(crc);
+    /// Split a `[seq] [tag] [args...] [crc]` frame when sequence numbers are
+    /// enabled; the CRC covers the sequence byte too.
+    fn split_sequence(&self, frame: &[u8]) -> Result<(Option<u8>, Vec<u8>)> {
+        // The adapter's getProtocolVersion handshake is never sequenced
+        let handshake = frame.len() == 2 && frame[0] == PROTOCOL_VERSION_TAG;
+        let sequenced = self.options.sequence_numbers || self.options.reliable;
+        if !sequenced || handshake {
+            return Ok((None, frame.to_vec()));
+        }
+        if frame.len() < 3 {
+            return Err(anyhow!("Sequenced command frame too short"));
+        }
+        if crc8(&frame[..frame.len() - 1]) != frame[frame.len() - 1] {
+            return Err(CommandError::CrcMismatch.into());
+        }
 
-        let encoded = slip_encode(&frame);
-        self.write_to_pty(&encoded)?;
+        // Re-seal the unsequenced part so decode_command can validate it
+        let mut unsequenced = frame[1..frame.len() - 1].to_vec();
+        unsequenced.push(crc8(&unsequenced));
+        Ok((Some(frame[0]), unsequenced))
+    }
 
-        Ok(())
+    /// The frames answering a decoded command: its response, or an error.
+    fn answer(&self, seq: Option<u8>, frame: &[u8]) -> Vec<Vec<u8>> {
+        match self.handle_command(frame) {
+            Ok(response) => {
+                // The handshake reply is never chunked
+                let chunked = self.options.chunked && frame[0] != PROTOCOL_VERSION_TAG;
+                package(seq, &response, chunked, MAX_FRAME_SIZE)
+            }
+            Err(e) => {
+                // Anything else, such as a frame too short to hold a
+                // command, is answered as damaged
+                let error = e.downcast_ref::<CommandError>();
+                match error {
+                    Some(CommandError::Busy(_)) => {}
+                    Some(CommandError::CrcMismatch) | None => {
+                        error!("CRC or protocol error: {}", e)
+                    }
+                    Some(_) => error!("Dispatch error: {}", e),
+                }
+                vec![error_frame(seq, error.map_or(0x01, CommandError::code))]
+            }
+        }
+    }
+
+    /// Acknowledge a reliable command, then answer it, or answer it again
+    /// from the last response if it is a repeat.
+    fn answer_reliably(&mut self, seq: u8, frame: &[u8]) -> Vec<Vec<u8>> {
+        let mut ack = vec![0xFF, ACK, seq];
+        ack.push(crc8(&ack));
+        let reply = match &self.last_reply {
+            Some((last, reply)) if *last == seq => {
+                debug!("Command {} repeated, answering from the last response", seq);
+                reply.clone()
+            }
+            _ => {
+                let reply = self.answer(Some(seq), frame);
+                self.last_reply = Some((seq, reply.clone()));
+                reply
+            }
+        };
+        [vec![ack], reply].concat()
+    }
+
+    fn send_error_response(&mut self, seq: Option<u8>, error_code: u8) -> Result<()> {
+        self.send_frames(vec![error_frame(seq, error_code)])
+    }
+
+    /// SLIP-encode and send `frames`, damaging some if `--noise` is set.
+    fn send_frames(&mut self, frames: Vec<Vec<u8>>) -> Result<()> {
+        let mut encoded = Vec::new();
+        for mut frame in frames {
+            if self.noisy() {
+                debug!("Damaging a {}-byte frame", frame.len());
+                if let Some(crc) = frame.last_mut() {
+                    *crc ^= 0xFF;
+                }
+            }
+            encoded.extend(slip_encode(&frame));
+        }
+        debug!("Sending response: {} bytes", encoded.len());
+        self.write_to_pty(&encoded)
+    }
+
+    /// Whether `--noise` damages the next frame.
+    fn noisy(&mut self) -> bool {
+        if self.options.noise == 0 {
+            return false;
+        }
+        // xorshift64
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 7;
+        self.noise_state ^= self.noise_state << 17;
+        self.noise_state % 100 < u64::from(self.options.noise)
     }
 
     fn write_to_pty(&mut self, data: &[u8]) -> Result<()> {
+        if !self.options.latency.is_zero() {
+            std::thread::sleep(self.options.latency);
+        }
+
         let fd = self.pty_master.as_raw_fd();
-        nix::unistd::write(fd, data).context("Failed to write to PTY")?;
+        let Some(baud) = self.options.throttle_baud else {
+            nix::unistd::write(fd, data).context("Failed to write to PTY")?;
+            return Ok(());
+        };
+
+        // One start bit, 8 data bits, one stop bit per byte; sleep against an
+        // absolute schedule so timer slack doesn't accumulate
+        let byte_time = Duration::from_secs_f64(10.0 / baud as f64);
+        let start = Instant::now();
+        for (i, byte) in data.iter().enumerate() {
+            nix::unistd::write(fd, std::slice::from_ref(byte)).context("Failed to write to PTY")?;
+            let due = start + byte_time * (i as u32 + 1);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
         Ok(())
     }
 
@@ -323,8 +770,13 @@ impl Simulator {
         let mut buffer = [0u8; 256];
         let mut connected = false;
 
-        while running.load(Ordering::Relaxed) {
+        while running.load(Ordering::Relaxed) && !self.deviated() {
+            self.sync_simulated_disconnect();
+
             match read(fd, &mut buffer) {
+                Ok(n) if n > 0 && self.simulating_disconnect => {
+                    debug!("Dropping {} bytes while simulating disconnect", n);
+                }
                 Ok(0) => {
                     // EOF - shouldn't normally happen for PTY, but handle it
                     if connected {
@@ -332,7 +784,7 @@ impl Simulator {
                         connected = false;
                         self.slip_decoder.reset();
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    std::thread::sleep(Duration::from_millis(100));
                 }
                 Ok(n) => {
                     if !connected {
@@ -349,31 +801,37 @@ impl Simulator {
                             Ok(Some(frame)) => {
                                 debug!("SLIP frame complete: {} bytes", frame.len());
 
+                                let (seq, frame) = match self.split_sequence(&frame) {
+                                    Ok(split) => split,
+                                    Err(e) => {
+                                        error!("CRC or protocol error: {}", e);
+                                        // Unsequenced in reliable mode, where
+                                        // it asks for the command again
+                                        let seq = frame
+                                            .first()
+                                            .copied()
+                                            .filter(|_| !self.options.reliable);
+                                        let _ = self.send_error_response(seq, 0x01);
+                                        continue;
+                                    }
+                                };
+
                                 // Process the command
-                                match self.handle_command(&frame) {
-                                    Ok(response) => {
-                                        let encoded = slip_encode(&response);
-                                        debug!("Sending response: {} bytes", encoded.len());
-                                        if let Err(e) = self.write_to_pty(&encoded) {
-                                            error!("Failed to send response: {}", e);
-                                            // Write failure likely means disconnect
-                                            if connected {
-                                                info!("Client disconnected (write error)");
-                                                connected = false;
-                                                self.slip_decoder.reset();
-                                            }
-                                        }
+                                let frames = match seq.filter(|_| self.options.reliable) {
+                                    Some(seq) => self.answer_reliably(seq, &frame),
+                                    None => {
+                                        // A handshake starts a new session
+                                        self.last_reply = None;
+                                        self.answer(seq, &frame)
                                     }
-                                    Err(e) => {
-                                        if e.to_string().contains("Unknown function tag") {
-                                            error!("Dispatch error: {}", e);
-                                            let _ = self.send_error_response(0x02);
-                                        // Dispatch error
-                                        } else {
-                                            error!("CRC or protocol error: {}", e);
-                                            let _ = self.send_error_response(0x01);
-                                            // CRC mismatch
-                                        }
+                                };
+                                if let Err(e) = self.send_frames(frames) {
+                                    error!("Failed to send response: {}", e);
+                                    // Write failure likely means disconnect
+                                    if connected {
+                                        info!("Client disconnected (write error)");
+                                        connected = false;
+                                        self.slip_decoder.reset();
                                     }
                                 }
                             }
@@ -382,14 +840,15 @@ impl Simulator {
                             }
                             Err(e) => {
                                 error!("SLIP decode error: {}", e);
-                                let _ = self.send_error_response(0x01);
+                                let _ = self.send_error_response(None, 0x01);
                             }
                         }
                     }
+                    self.control.lock().unwrap().slip = self.slip_decoder.counts();
                 }
                 Err(nix::errno::Errno::EAGAIN) => {
                     // No data available, sleep briefly
-                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    std::thread::sleep(Duration::from_millis(10));
                 }
                 Err(nix::errno::Errno::EIO) => {
                     // I/O error - typically means client disconnected
@@ -398,7 +857,7 @@ impl Simulator {
                         connected = false;
                         self.slip_decoder.reset();
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    std::thread::sleep(Duration::from_millis(100));
                 }
                 Err(e) => {
                     // Other errors - log and continue
@@ -408,7 +867,7 @@ impl Simulator {
                         connected = false;
                         self.slip_decoder.reset();
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    std::thread::sleep(Duration::from_millis(100));
                 }
             }
         }
@@ -418,6 +877,24 @@ impl Simulator {
     }
 }
 
+/// Nonzero xorshift seed from the clock.
+fn noise_seed() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    nanos | 1
+}
+
+/// Device ID is the manifest filename without its .json extension.
+fn device_id_for(manifest_path: &Path) -> Result<String> {
+    manifest_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Invalid manifest filename: {}", manifest_path.display()))
+}
+
 fn main() -> Result<()> {
     // Initialize logging
     tracing_subscriber::fmt()
@@ -429,17 +906,101 @@ fn main() -> Result<()> {
 
     info!("Arduino Simulator starting...");
     info!("Line: {}", args.line.display());
-    info!("Manifest: {}", args.manifest.display());
-
-    // Validate arguments
-    if !args.manifest.exists() {
-        return Err(anyhow!(
-            "Manifest file does not exist: {}",
-            args.manifest.display()
-        ));
+
+    // One (symlink, manifest) pair per simulated device
+    let instances = match (&args.devices, &args.manifest) {
+        (Some(manifests), _) => {
+            fs::create_dir_all(&args.line).with_context(|| {
+                format!(
+                    "Failed to create symlink directory: {}",
+                    args.line.display()
+                )
+            })?;
+            manifests
+                .iter()
+                .map(|manifest| Ok((args.line.join(device_id_for(manifest)?), manifest.clone())))
+                .collect::<Result<Vec<_>>>()?
+        }
+        (None, Some(manifest)) => vec![(args.line.clone(), manifest.clone())],
+        (None, None) => return Err(anyhow!("Either --manifest or --devices is required")),
+    };
+
+    let options = SimulatorOptions {
+        sequence_numbers: args.sequence_numbers,
+        chunked: !args.single_frame,
+        reliable: args.reliable,
+        noise: args.noise,
+        latency: Duration::from_millis(args.latency_ms),
+        throttle_baud: args.throttle_baud.filter(|&baud| baud > 0),
+    };
+    if let Some(baud) = options.throttle_baud {
+        info!("Throttling responses to {} baud", baud);
+    }
+    if !options.latency.is_zero() {
+        info!("Delaying responses by {:?}", options.latency);
+    }
+    if options.noise > 0 {
+        info!("Damaging {}% of the frames sent", options.noise);
     }
 
-    let mut simulator = Simulator::new(args)?;
+    let scenario = args.scenario.as_deref().map(Scenario::load).transpose()?;
+    let kinematics = args
+        .kinematics
+        .as_deref()
+        .map(KinematicsSpec::load)
+        .transpose()?;
+    let call_log = match &args.call_log {
+        Some(path) => {
+            info!("Logging calls to {}", path.display());
+            Some(Arc::new(CallLog::create(path)?))
+        }
+        None => None,
+    };
+
+    let expectations = match &args.expect {
+        Some(path) => {
+            let devices = instances
+                .iter()
+                .map(|(_, manifest)| device_id_for(manifest))
+                .collect::<Result<Vec<_>>>()?;
+            Some(Arc::new(Expectations::load(path, &devices)?))
+        }
+        None => None,
+    };
+
+    let mut simulators = Vec::new();
+    for (line, manifest) in &instances {
+        info!("Manifest: {}", manifest.display());
+
+        // Validate arguments
+        if !manifest.exists() {
+            return Err(anyhow!(
+                "Manifest file does not exist: {}",
+                manifest.display()
+            ));
+        }
+
+        let mut simulator = Simulator::new(line, manifest, options)?;
+        if let Some(version) = &args.firmware_version {
+            info!("Reporting manifest version {}", version);
+            simulator.manifest.version = version.clone();
+        }
+        if let Some(scenario) = &scenario {
+            simulator.load_scenario(scenario)?;
+        }
+        if let Some(kinematics) = &kinematics {
+            simulator.load_kinematics(kinematics)?;
+        }
+        simulator.call_log = call_log.clone();
+        simulator.expectations = expectations.clone();
+        simulators.push(simulator);
+    }
+
+    // Left alone, stdin may belong to whatever started the simulator
+    if args.console || std::io::stdin().is_terminal() {
+        spawn_stdin_console(simulators.iter().map(Simulator::console_target).collect());
+        info!("Control console on stdin - type 'help' for commands");
+    }
 
     // Set up Ctrl+C handler
     let running = Arc::new(AtomicBool::new(true));
@@ -451,8 +1012,28 @@ fn main() -> Result<()> {
     })
     .context("Failed to set Ctrl+C handler")?;
 
-    // Run simulator
-    simulator.run(running)?;
+    // Run simulators, one thread each, logging under a per-device span
+    let multiple = simulators.len() > 1;
+    let handles: Vec<_> = simulators
+        .into_iter()
+        .map(|mut simulator| {
+            let running = Arc::clone(&running);
+            std::thread::spawn(move || {
+                let _span = multiple
+                    .then(|| tracing::info_span!("device", id = %simulator.device_id).entered());
+                simulator.run(running)
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle
+            .join()
+            .map_err(|_| anyhow!("Simulator thread panicked"))??;
+    }
+    if let Some(expectations) = &expectations {
+        expectations.finish()?;
+    }
 
     Ok(())
 }
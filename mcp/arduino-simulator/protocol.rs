@@ -13,7 +13,7 @@ pub fn crc8(data: &[u8]) -> u8 {
             if (crc & 0x80) != 0 {
                 crc = (crc << 1) ^ 0x07;
             } else {
-                crc = crc << 1;
+                crc <<= 1;
             }
         }
     }
@@ -21,12 +21,52 @@ pub fn crc8(data: &[u8]) -> u8 {
     crc
 }
 
-/// Response data types
-pub enum ResponseData {
-    Void,
-    I16(i16),
-    I32(i32),
-    CStr(String),
+/// Reserved tag the adapter sends right after connecting.
+pub const PROTOCOL_VERSION_TAG: u8 = 0xFE;
+
+/// Reserved tag answered with the manifest `version` the firmware was built from.
+pub const MANIFEST_VERSION_TAG: u8 = 0xFD;
+
+/// Protocol version reported, matching `MCP_PROTOCOL_VERSION` in the firmware.
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// `getProtocolVersion` flag: responses are sent as chunks.
+pub const FLAG_CHUNKED: u8 = 0x04;
+
+/// `getProtocolVersion` flag: commands are acknowledged, and a repeated one is
+/// answered from the last response.
+pub const FLAG_RELIABLE: u8 = 0x08;
+
+/// Second byte of the `[0xFF] [0xAC] [seq] [CRC]` acknowledgement frame.
+pub const ACK: u8 = 0xAC;
+
+/// Frame size reported, matching the firmware's `MAX_FRAME_SIZE`.
+pub const MAX_FRAME_SIZE: usize = 256;
+
+/// A command the firmware answers with an `[0xFF] [code]` error frame.
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("CRC mismatch")]
+    CrcMismatch,
+    #[error("Unknown function tag: {0}")]
+    UnknownTag(u8),
+    #[error("{0}")]
+    BadArguments(String),
+    #[error("Result of {0} bytes does not fit in a frame")]
+    TooLarge(usize),
+    #[error("Function '{0}' is busy")]
+    Busy(String),
+}
+
+impl CommandError {
+    /// The `MCP_ERROR_*` code from `mcp.hpp`.
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::CrcMismatch => 0x01,
+            Self::UnknownTag(_) | Self::BadArguments(_) | Self::TooLarge(_) => 0x02,
+            Self::Busy(_) => 0x03,
+        }
+    }
 }
 
 /// Decode a command frame: [tag] [args...] [crc]
@@ -51,7 +91,7 @@ pub fn decode_command(frame: &[u8]) -> Result<(u8, &[u8])> {
             "CRC mismatch: calculated=0x{:02X}, received=0x{:02X}",
             calculated_crc, received_crc
         );
-        return Err(anyhow!("CRC mismatch"));
+        return Err(CommandError::CrcMismatch.into());
     }
 
     debug!("CRC valid: 0x{:02X}", received_crc);
@@ -63,42 +103,12 @@ pub fn decode_command(frame: &[u8]) -> Result<(u8, &[u8])> {
     Ok((tag, args))
 }
 
-/// Encode a response frame: [data...] [crc]
-pub fn encode_response(response_data: &ResponseData) -> Result<Vec<u8>> {
-    let mut frame = Vec::new();
-
-    match response_data {
-        ResponseData::Void => {
-            // Empty response, just CRC
-        }
-        ResponseData::I16(value) => {
-            frame.extend_from_slice(&value.to_le_bytes());
-        }
-        ResponseData::I32(value) => {
-            frame.extend_from_slice(&value.to_le_bytes());
-        }
-        ResponseData::CStr(s) => {
-            frame.extend_from_slice(s.as_bytes());
-            frame.push(0); // Null terminator
-        }
-    }
-
-    // Calculate and append CRC
-    let crc = crc8(&frame);
-    frame.push(crc);
-
-    debug!(
-        "Response encoded: {} bytes (CRC: 0x{:02X})",
-        frame.len(),
-        crc
-    );
-
-    Ok(frame)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::slip::{slip_encode, SlipDecoder};
+    use mcp_client::conformance::{self, hex, vectors};
+    use proptest::prelude::*;
 
     #[test]
     fn test_crc8() {
@@ -111,35 +121,29 @@ mod tests {
     }
 
     #[test]
-    fn test_encode_void() {
-        let response = encode_response(&ResponseData::Void).unwrap();
-        assert_eq!(response.len(), 1); // Just CRC
+    fn test_conformance_framing() {
+        let decode = |wire: &[u8]| -> Vec<Vec<u8>> {
+            let mut decoder = SlipDecoder::new();
+            wire.iter()
+                .filter_map(|&byte| decoder.process_byte(byte).unwrap())
+                .collect()
+        };
+        conformance::check_framing(crc8, slip_encode, decode);
     }
 
     #[test]
-    fn test_encode_i16() {
-        let response = encode_response(&ResponseData::I16(42)).unwrap();
-        assert_eq!(response.len(), 3); // 2 bytes + CRC
-        assert_eq!(response[0], 42); // Little-endian low byte
-        assert_eq!(response[1], 0); // Little-endian high byte
-    }
-
-    #[test]
-    fn test_encode_i32() {
-        let response = encode_response(&ResponseData::I32(1000)).unwrap();
-        assert_eq!(response.len(), 5); // 4 bytes + CRC
-        assert_eq!(response[0], 0xE8); // Little-endian: 1000 = 0x03E8
-        assert_eq!(response[1], 0x03);
-        assert_eq!(response[2], 0x00);
-        assert_eq!(response[3], 0x00);
-    }
-
-    #[test]
-    fn test_encode_cstr() {
-        let response = encode_response(&ResponseData::CStr("hello".to_string())).unwrap();
-        assert_eq!(response.len(), 7); // "hello" + null + CRC
-        assert_eq!(&response[0..5], b"hello");
-        assert_eq!(response[5], 0); // Null terminator
+    fn test_conformance_commands() {
+        for v in vectors("commands") {
+            let frame = hex(&v["frame"]);
+            let (tag, args) = decode_command(&frame).unwrap();
+            assert_eq!(u64::from(tag), v["tag"], "{}", v["name"]);
+            assert_eq!(args, hex(&v["args"]), "{}", v["name"]);
+        }
+        for v in vectors("bad_commands") {
+            let err = decode_command(&hex(&v["frame"])).unwrap_err();
+            let code = err.downcast_ref::<CommandError>().unwrap().code();
+            assert_eq!(u64::from(code), v["error"], "{}", v["name"]);
+        }
     }
 
     #[test]
@@ -170,6 +174,46 @@ mod tests {
         let frame = vec![5, 0xFF]; // Wrong CRC
         let result = decode_command(&frame);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("CRC"));
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("CRC"));
+        let error = err.downcast_ref::<CommandError>().unwrap();
+        assert!(matches!(error, CommandError::CrcMismatch));
+        assert_eq!(error.code(), 0x01);
+        assert_eq!(CommandError::UnknownTag(9).code(), 0x02);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_decode_command_roundtrip(
+            tag in any::<u8>(),
+            args in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let mut frame = vec![tag];
+            frame.extend_from_slice(&args);
+            frame.push(crc8(&frame));
+
+            let (decoded_tag, decoded_args) = decode_command(&frame).unwrap();
+            prop_assert_eq!(decoded_tag, tag);
+            prop_assert_eq!(decoded_args, &args[..]);
+        }
+
+        #[test]
+        fn prop_decode_command_rejects_corruption(
+            data in prop::collection::vec(any::<u8>(), 1..64),
+            index in any::<prop::sample::Index>(),
+            flip in 1..=255u8,
+        ) {
+            // CRC-8 detects every single-byte error
+            let mut frame = data;
+            frame.push(crc8(&frame));
+            let index = index.index(frame.len());
+            frame[index] ^= flip;
+            prop_assert!(decode_command(&frame).is_err());
+        }
+
+        #[test]
+        fn prop_decode_command_never_panics(frame in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = decode_command(&frame);
+        }
     }
 }
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::console::check_value;
+
+/// Canned responses loaded from `--scenario`.
+///
+/// ```json
+/// { "responses": { "getDistance": [100, 80, 60], "getStatus": ["ok"] } }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Values returned in order per function name, repeating from the start
+    /// once exhausted
+    #[serde(default)]
+    pub responses: HashMap<String, Vec<Value>>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scenario file: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse scenario file: {}", path.display()))
+    }
+
+    /// Response sequences for the functions a device actually has, checked
+    /// against their return types. Functions the device lacks are skipped so
+    /// one scenario can drive several devices.
+    pub fn sequences_for(
+        &self,
+        return_types: &HashMap<String, Option<String>>,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let mut sequences = HashMap::new();
+
+        for (function, values) in &self.responses {
+            let Some(return_type) = return_types.get(function) else {
+                continue;
+            };
+            if values.is_empty() {
+                return Err(anyhow!("Scenario responses for '{}' are empty", function));
+            }
+
+            let values = values
+                .iter()
+                .map(|value| {
+                    let text = match value {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    check_value(return_type.as_deref(), &text)
+                        .map(|_| text)
+                        .with_context(|| format!("Invalid scenario response for '{}'", function))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            sequences.insert(function.clone(), values);
+        }
+
+        Ok(sequences)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn return_types() -> HashMap<String, Option<String>> {
+        HashMap::from([
+            ("getDistance".to_string(), Some("i16".to_string())),
+            ("getStatus".to_string(), Some("CStr".to_string())),
+        ])
+    }
+
+    #[test]
+    fn test_sequences_skip_unknown_functions() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{"responses": {"getDistance": [100, 80], "getStatus": ["ok"], "getBattery": [5]}}"#,
+        )
+        .unwrap();
+
+        let sequences = scenario.sequences_for(&return_types()).unwrap();
+        assert_eq!(sequences["getDistance"], vec!["100", "80"]);
+        assert_eq!(sequences["getStatus"], vec!["ok"]);
+        assert!(!sequences.contains_key("getBattery"));
+    }
+
+    #[test]
+    fn test_sequences_reject_out_of_range_values() {
+        let scenario: Scenario =
+            serde_json::from_str(r#"{"responses": {"getDistance": [100000]}}"#).unwrap();
+        assert!(scenario.sequences_for(&return_types()).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::{anyhow, Result};
+use std::fmt;
 use tracing::{debug, warn};
 
 // SLIP protocol constants
@@ -15,9 +16,31 @@ pub enum SlipDecodeState {
     Escaped,
 }
 
+/// Trouble decoding the frames the adapter sent, for the console's
+/// `counts`. The adapter counts the other direction in `/status`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SlipCounts {
+    pub invalid_escapes: u64,
+    pub oversized_frames: u64,
+    pub empty_frames: u64,
+    /// Partial frames thrown away by an ESC CLEAR sequence
+    pub resets: u64,
+}
+
+impl fmt::Display for SlipCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} invalid escapes, {} oversized frames, {} empty frames, {} resets",
+            self.invalid_escapes, self.oversized_frames, self.empty_frames, self.resets
+        )
+    }
+}
+
 pub struct SlipDecoder {
     state: SlipDecodeState,
     buffer: Vec<u8>,
+    counts: SlipCounts,
 }
 
 impl SlipDecoder {
@@ -25,9 +48,14 @@ impl SlipDecoder {
         Self {
             state: SlipDecodeState::Idle,
             buffer: Vec::with_capacity(256),
+            counts: SlipCounts::default(),
         }
     }
 
+    pub fn counts(&self) -> SlipCounts {
+        self.counts
+    }
+
     pub fn reset(&mut self) {
         self.state = SlipDecodeState::Idle;
         self.buffer.clear();
@@ -57,6 +85,7 @@ impl SlipDecoder {
                         Ok(Some(frame))
                     } else {
                         debug!("SLIP: Empty frame");
+                        self.counts.empty_frames += 1;
                         Ok(None)
                     }
                 } else if byte == SLIP_ESC {
@@ -69,6 +98,7 @@ impl SlipDecoder {
                         self.buffer.push(byte);
                     } else {
                         warn!("SLIP: Frame too large, resetting");
+                        self.counts.oversized_frames += 1;
                         self.reset();
                         return Err(anyhow!("SLIP frame too large"));
                     }
@@ -79,7 +109,12 @@ impl SlipDecoder {
                 match byte {
                     SLIP_CLEAR => {
                         debug!("SLIP: Clear sequence");
+                        if !self.buffer.is_empty() {
+                            self.counts.resets += 1;
+                        }
+                        // Between frames, waiting for the next END
                         self.reset();
+                        return Ok(None);
                     }
                     SLIP_ESC_END => {
                         debug!("SLIP: Escaped END");
@@ -87,6 +122,7 @@ impl SlipDecoder {
                             self.buffer.push(SLIP_END);
                         } else {
                             warn!("SLIP: Frame too large during escape");
+                            self.counts.oversized_frames += 1;
                             self.reset();
                             return Err(anyhow!("SLIP frame too large"));
                         }
@@ -97,12 +133,14 @@ impl SlipDecoder {
                             self.buffer.push(SLIP_ESC);
                         } else {
                             warn!("SLIP: Frame too large during escape");
+                            self.counts.oversized_frames += 1;
                             self.reset();
                             return Err(anyhow!("SLIP frame too large"));
                         }
                     }
                     _ => {
                         warn!("SLIP: Invalid escape sequence: 0x{:02X}", byte);
+                        self.counts.invalid_escapes += 1;
                         self.reset();
                         return Err(anyhow!("Invalid SLIP escape sequence: 0x{:02X}", byte));
                     }
@@ -143,3 +181,57 @@ pub fn slip_encode(data: &[u8]) -> Vec<u8> {
 
     encoded
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode_all(decoder: &mut SlipDecoder, input: &[u8]) -> Vec<Vec<u8>> {
+        input
+            .iter()
+            .filter_map(|&byte| decoder.process_byte(byte).ok().flatten())
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_decoder_trouble() {
+        let mut decoder = SlipDecoder::new();
+        let mut input = vec![SLIP_ESC, SLIP_CLEAR];
+        input.extend(slip_encode(&[0x01]));
+        input.extend([SLIP_END, SLIP_END, 0x02, SLIP_END]);
+        input.extend([SLIP_END, 0x03, SLIP_ESC, SLIP_CLEAR]);
+        input.extend([SLIP_END, 0x04, SLIP_ESC, 0x42]);
+        input.push(SLIP_END);
+        input.extend(std::iter::repeat_n(0x05, 1025));
+
+        assert_eq!(
+            decode_all(&mut decoder, &input),
+            vec![vec![0x01], vec![0x02]]
+        );
+        assert_eq!(
+            decoder.counts(),
+            SlipCounts {
+                invalid_escapes: 1,
+                oversized_frames: 1,
+                empty_frames: 1,
+                resets: 1,
+            }
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_slip_roundtrip(frames in prop::collection::vec(prop::collection::vec(any::<u8>(), 1..128), 1..8)) {
+            let input: Vec<u8> = frames.iter().flat_map(|frame| slip_encode(frame)).collect();
+            let mut decoder = SlipDecoder::new();
+            prop_assert_eq!(decode_all(&mut decoder, &input), frames);
+        }
+
+        #[test]
+        fn prop_slip_decoder_never_panics(input in prop::collection::vec(any::<u8>(), 0..4096)) {
+            let mut decoder = SlipDecoder::new();
+            decode_all(&mut decoder, &input);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Throughput of the frame codec: SLIP encoding and decoding, CRC-8 and
+//! response decoding, on frames the size of a typical call and of a full
+//! one. Run with `cargo bench --bench codec`.
+
+// The adapter is a binary crate, so pull the codec source in directly
+#[path = "../arduino-mcp-adapter/slip.rs"]
+#[allow(dead_code)]
+mod slip;
+
+// Its tests are compiled in but not run, as `cargo bench` builds with cfg(test)
+#[path = "../arduino-mcp-adapter/protocol.rs"]
+#[allow(dead_code, unused_imports)]
+mod protocol;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use protocol::{crc8, decode_response_by_type, ResponseDecoder};
+use slip::{slip_encode, SlipDecoder};
+
+/// A short call's frame and a full one, with every byte value so some need
+/// escaping.
+fn frames() -> Vec<Vec<u8>> {
+    [8, 256]
+        .into_iter()
+        .map(|len| (0..len).map(|i| (i * 37) as u8).collect())
+        .collect()
+}
+
+fn slip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slip");
+    for frame in frames() {
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("encode", frame.len()),
+            &frame,
+            |b, frame| b.iter(|| slip_encode(black_box(frame))),
+        );
+
+        let wire = slip_encode(&frame);
+        group.bench_with_input(BenchmarkId::new("decode", frame.len()), &wire, |b, wire| {
+            let mut decoder = SlipDecoder::new();
+            b.iter(|| {
+                let mut decoded = None;
+                for &byte in black_box(wire) {
+                    if let Some(frame) = decoder.process_byte(byte).unwrap() {
+                        decoded = Some(frame);
+                    }
+                }
+                decoded.unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc8");
+    for frame in frames() {
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(frame.len()),
+            &frame,
+            |b, frame| b.iter(|| crc8(black_box(frame))),
+        );
+    }
+    group.finish();
+}
+
+fn responses(c: &mut Criterion) {
+    let mut group = c.benchmark_group("response");
+    let i32_data = 100_000i32.to_le_bytes();
+    group.bench_function("i32", |b| {
+        b.iter(|| decode_response_by_type(black_box(&i32_data), "i32").unwrap())
+    });
+    let text = b"temperature 21.5C, humidity 40%\0";
+    group.bench_function("CStr", |b| {
+        b.iter(|| decode_response_by_type(black_box(text), "CStr").unwrap())
+    });
+    // A telemetry struct read one field at a time
+    let telemetry: Vec<u8> = [1i16, -2, 3, -4]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .chain(123_456i32.to_le_bytes())
+        .collect();
+    group.bench_function("fields", |b| {
+        b.iter(|| {
+            let mut decoder = ResponseDecoder::new(black_box(&telemetry));
+            let sum: i32 = (0..4).map(|_| i32::from(decoder.read_i16().unwrap())).sum();
+            sum + decoder.read_i32().unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, slip, crc, responses);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+//! Tool calls per second through the whole adapter: HTTP, the MCP server,
+//! the connection and the frame codec, against an in-process `loop://`
+//! device so no serial link limits the rate. Run with
+//! `cargo bench --bench round_trips`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mcp_client::McpClient;
+use serde_json::json;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+/// Calls in flight at once for the pipelined run.
+const CONCURRENT_CALLS: usize = 8;
+
+/// An adapter on a free port with a `loop://` device for `test-robot.json`,
+/// stopped on drop.
+struct Adapter {
+    url: String,
+    process: Child,
+    _dir: TempDir,
+}
+
+impl Adapter {
+    fn start(options: &str, pipeline_depth: usize) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("test-robot.json");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test-robot.json"),
+            &manifest,
+        )
+        .unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let process = Command::new(env!("CARGO_BIN_EXE_arduino-mcp-adapter"))
+            .arg("--line")
+            .arg(format!("loop://{}{}", manifest.display(), options))
+            .arg("--manifest-dir")
+            .arg(dir.path())
+            .arg("--port")
+            .arg(port.to_string())
+            .arg("--pipeline-depth")
+            .arg(pipeline_depth.to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Self {
+            url: format!("http://127.0.0.1:{}", port),
+            process,
+            _dir: dir,
+        }
+    }
+
+    /// An initialized client, once the adapter reports the robot ready.
+    async fn client(&self) -> McpClient {
+        let client = McpClient::new(&self.url);
+        let deadline = Instant::now() + Duration::from_secs(15);
+        loop {
+            if let Ok(health) = client.get_json("/health").await {
+                if health["status"] == "ok" {
+                    break;
+                }
+            }
+            assert!(Instant::now() < deadline, "adapter never became ready");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        client.initialize("bench").await.unwrap();
+        client
+    }
+}
+
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn round_trips(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let arguments = json!({"sensorId": 3});
+    let mut group = c.benchmark_group("tool_call");
+
+    let adapter = Adapter::start("", 1);
+    let client = runtime.block_on(adapter.client());
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("sequential", |b| {
+        b.to_async(&runtime).iter(|| async {
+            client
+                .call_tool("getSensorValue", &arguments)
+                .await
+                .unwrap()
+        })
+    });
+    drop(adapter);
+
+    let adapter = Adapter::start("?sequenced", CONCURRENT_CALLS);
+    let client = Arc::new(runtime.block_on(adapter.client()));
+    group.throughput(Throughput::Elements(CONCURRENT_CALLS as u64));
+    group.bench_function("pipelined", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut calls = tokio::task::JoinSet::new();
+            for _ in 0..CONCURRENT_CALLS {
+                let (client, arguments) = (Arc::clone(&client), arguments.clone());
+                calls.spawn(async move { client.call_tool("getSensorValue", &arguments).await });
+            }
+            while let Some(result) = calls.join_next().await {
+                result.unwrap().unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, round_trips);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+#![no_main]
+
+#[path = "../../arduino-mcp-adapter/protocol.rs"]
+#[allow(dead_code)]
+mod protocol;
+
+#[path = "../../arduino-simulator/protocol.rs"]
+#[allow(dead_code)]
+mod device_protocol;
+
+use libfuzzer_sys::fuzz_target;
+use protocol::{decode_response_by_type, ResponseDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    // Simulator side: command frames from the adapter
+    if let Ok((tag, args)) = device_protocol::decode_command(data) {
+        assert_eq!(tag, data[0]);
+        assert_eq!(args.len(), data.len() - 2);
+    }
+
+    // Adapter side: response payloads, read as every supported type and as
+    // an arbitrary mix driven by the first byte
+    for return_type in ["CStr", "i16", "i32"] {
+        let _ = decode_response_by_type(data, return_type);
+    }
+    if let Some((&reads, rest)) = data.split_first() {
+        let mut decoder = ResponseDecoder::new(rest);
+        for shift in (0..8).step_by(2) {
+            let _ = match (reads >> shift) & 0b11 {
+                0 => decoder.read_i16().map(|v| v.to_string()),
+                1 => decoder.read_i32().map(|v| v.to_string()),
+                _ => decoder.read_cstring(),
+            };
+        }
+    }
+});
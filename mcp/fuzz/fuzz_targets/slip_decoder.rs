@@ -0,0 +1,29 @@
+#![no_main]
+
+// The adapter is a binary crate, so pull the decoder source in directly
+#[path = "../../arduino-mcp-adapter/slip.rs"]
+#[allow(dead_code)]
+mod slip;
+
+use libfuzzer_sys::fuzz_target;
+use slip::{slip_encode, SlipDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    // Arbitrary byte streams must never panic or yield oversized frames
+    let mut decoder = SlipDecoder::new();
+    for &byte in data {
+        if let Ok(Some(frame)) = decoder.process_byte(byte) {
+            assert!(!frame.is_empty() && frame.len() <= 1024);
+        }
+    }
+
+    // Any payload survives an encode/decode roundtrip on a fresh decoder
+    if !data.is_empty() && data.len() <= 1024 {
+        let mut decoder = SlipDecoder::new();
+        let frames: Vec<Vec<u8>> = slip_encode(data)
+            .into_iter()
+            .filter_map(|byte| decoder.process_byte(byte).unwrap())
+            .collect();
+        assert_eq!(frames, vec![data.to_vec()]);
+    }
+});
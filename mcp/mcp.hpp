@@ -6,6 +6,12 @@
 #define MCP_TOOL(documentation)
 #define MCP_DESCRIPTION(desc) struct __mcp_desc_sentinel { } __attribute__((annotate("MCP_DESCRIPTION:" desc)));
 
+// Stream the protocol runs over. Define MCP_STREAM before including this
+// header to use another one, such as a Bluetooth LE UART on an ESP32.
+#ifndef MCP_STREAM
+#define MCP_STREAM Serial
+#endif
+
 // SLIP protocol constants
 #define SLIP_END     0xC0    // Frame marker
 #define SLIP_ESC     0xDB    // Escape character
@@ -13,6 +19,83 @@
 #define SLIP_ESC_ESC 0xDD    // Escaped ESC
 #define SLIP_CLEAR   0xDE    // Clear sequence
 
+// Reserved getProtocolVersion tag, answered before dispatch with
+// [version] [flags] [max frame size: u16 LE]
+#define MCP_PROTOCOL_VERSION_TAG 0xFE
+#define MCP_PROTOCOL_VERSION     3
+
+// getProtocolVersion flag: the firmware sends [0xFF] [0xFE] [code] [crc]
+// event frames on its own. Reported when the sketch defines MCP_EVENTS
+// before including this header.
+#define MCP_FLAG_EVENTS 0x02
+
+// getProtocolVersion flag: responses are sent as
+// [index] [total] [payload...] [crc] chunks
+#define MCP_FLAG_CHUNKED 0x04
+
+// getProtocolVersion flag: commands come as [seq] [tag] [args...] [crc]; each
+// is acknowledged with [0xFF] [0xAC] [seq] [crc] and answered under its
+// sequence byte, and a repeat of the last one is answered again without
+// running it. Reported when the sketch defines MCP_RELIABLE before including
+// this header, for noisy links such as long cables or Bluetooth.
+#define MCP_FLAG_RELIABLE 0x08
+#define MCP_ACK           0xAC
+
+// Event codes
+#define MCP_EVENT_MOTION_DONE 0x01 // A motion started by an "ack": "immediate" function ended
+#define MCP_EVENT_RESET       0x02 // The firmware just started, e.g. after a brownout
+// Faults: cut the motors first, then report why. The adapter refuses motion
+// until a client calls clearFault.
+#define MCP_EVENT_FAULT_STALL       0x10 // A motor is blocked
+#define MCP_EVENT_FAULT_OVERCURRENT 0x11 // A motor or driver drew too much current
+#define MCP_EVENT_FAULT_THERMAL     0x12 // A motor or driver got too hot
+
+// Reserved getManifestVersion tag, answered by the generated bindings with
+// the manifest `version` they were built from
+#define MCP_MANIFEST_VERSION_TAG 0xFD
+
+// Reserved describeFunction tag, answered by the generated bindings when the
+// sketch defines MCP_INTROSPECTION before including this header (the
+// descriptions take RAM). [0xFC] [index] gets [count] [tag] [return type]
+// [param count] name\0 desc\0 and [type] name\0 per parameter, or
+// [count] name\0 description\0 once index reaches count. Type codes: 0 none,
+// 1 i16, 2 i32, 3 CStr, 4 blob, 5 image. Read by `arduino-mcp-adapter
+// dump-manifest`.
+#define MCP_DESCRIBE_FUNCTION_TAG 0xFC
+
+// Error codes sent as [0xFF] [code]
+#define MCP_ERROR_CRC      0x01 // Command failed the CRC check
+#define MCP_ERROR_DISPATCH 0x02 // Unknown tag, bad arguments or oversized result
+#define MCP_ERROR_BUSY     0x03 // Can't take the command now; the host may retry
+#define MCP_ERROR_HARDWARE 0x04 // The function hit a hardware problem
+
+// Call from a tool function to answer with an error frame instead of its
+// return value, e.g. mcp_fail(MCP_ERROR_HARDWARE) when a sensor doesn't respond.
+inline uint8_t& mcp_pending_error() {
+    static uint8_t code = 0;
+    return code;
+}
+inline void mcp_fail(uint8_t code) { mcp_pending_error() = code; }
+
+// Call from a tool function that can't act yet, e.g. while a motor move is
+// still running, before it has changed anything. The host waits a little and
+// sends the same command again.
+inline void mcp_busy() { mcp_fail(MCP_ERROR_BUSY); }
+
+// Binary return values, sent as [length: u16 LE] [bytes...]. Return MCPImage
+// for JPEG, PNG, GIF, WebP or BMP data shown to the client as an image, and
+// MCPBlob for anything else. `data` must stay valid after the function
+// returns, e.g. point into a static buffer.
+struct MCPBlob {
+    const uint8_t* data;
+    uint16_t len;
+};
+
+struct MCPImage {
+    const uint8_t* data;
+    uint16_t len;
+};
+
 // MCP protocol state machine
 enum MCPState {
     MCP_IDLE,
@@ -27,6 +110,13 @@ private:
     int frame_pos;
     MCPState state;
     uint8_t response_buffer[MAX_FRAME_SIZE];
+#ifdef MCP_RELIABLE
+    // Sequence byte, result length and error code of the last command, kept
+    // in case its response goes astray; -1 before the first
+    int last_seq;
+    int last_response_len;
+    uint8_t last_error;
+#endif
     
     // Simple CRC-8 implementation
     uint8_t crc8(const uint8_t* data, int len) {
@@ -51,42 +141,80 @@ private:
     
     void send_slip_frame(const uint8_t* data, int len) {
         // Clear any garbage with ESC CLEAR sequence
-        Serial.write(SLIP_ESC);
-        Serial.write(SLIP_CLEAR);
+        MCP_STREAM.write(SLIP_ESC);
+        MCP_STREAM.write(SLIP_CLEAR);
         
         // Send frame start marker
-        Serial.write(SLIP_END);
+        MCP_STREAM.write(SLIP_END);
         
         // Send data with escaping
         for (int i = 0; i < len; i++) {
             if (data[i] == SLIP_END) {
-                Serial.write(SLIP_ESC);
-                Serial.write(SLIP_ESC_END);
+                MCP_STREAM.write(SLIP_ESC);
+                MCP_STREAM.write(SLIP_ESC_END);
             } else if (data[i] == SLIP_ESC) {
-                Serial.write(SLIP_ESC);
-                Serial.write(SLIP_ESC_ESC);
+                MCP_STREAM.write(SLIP_ESC);
+                MCP_STREAM.write(SLIP_ESC_ESC);
             } else {
-                Serial.write(data[i]);
+                MCP_STREAM.write(data[i]);
             }
         }
         
         // Send frame end marker
-        Serial.write(SLIP_END);
+        MCP_STREAM.write(SLIP_END);
+    }
+
+    // Send head followed by tail as chunks, building each one in
+    // frame_buffer as the command in it has been handled, each after the
+    // sequence byte unless seq is -1. Returns false if the response would
+    // need more than 255 chunks.
+    bool send_chunks(int seq, const uint8_t* head, int head_len, const uint8_t* tail, long tail_len) {
+        const int payload_max = MAX_FRAME_SIZE - 3 - (seq < 0 ? 0 : 1); // [seq] index, total and CRC
+        long total_len = head_len + tail_len;
+        long total = total_len == 0 ? 1 : (total_len + payload_max - 1) / payload_max;
+        if (total > 255) return false;
+
+        long pos = 0;
+        for (int index = 0; index < total; index++) {
+            int len = 0;
+            if (seq >= 0) frame_buffer[len++] = seq;
+            frame_buffer[len++] = index;
+            frame_buffer[len++] = total;
+            while (len < MAX_FRAME_SIZE - 1 && pos < total_len) {
+                frame_buffer[len++] = pos < head_len ? head[pos] : tail[pos - head_len];
+                pos++;
+            }
+            frame_buffer[len] = crc8(frame_buffer, len);
+            send_slip_frame(frame_buffer, len + 1);
+        }
+        return true;
     }
     
 public:
+#ifdef MCP_RELIABLE
+    MCPHandler() : frame_pos(0), state(MCP_IDLE), last_seq(-1), last_response_len(0), last_error(0) {}
+#else
     MCPHandler() : frame_pos(0), state(MCP_IDLE) {}
+#endif
+
+    // Send an event frame, e.g. send_event(MCP_EVENT_MOTION_DONE) from
+    // loop() once a move has finished.
+    void send_event(uint8_t code) {
+        uint8_t event[4] = {0xFF, 0xFE, code};
+        event[3] = crc8(event, 3);
+        send_slip_frame(event, 4);
+    }
     
     void process_serial() {
-        while (Serial.available() > 0) {
-            uint8_t byte = Serial.read();
-            Serial.write('R'); // Debug: byte received
+        while (MCP_STREAM.available() > 0) {
+            uint8_t byte = MCP_STREAM.read();
+            MCP_STREAM.write('R'); // Debug: byte received
             
             switch (state) {
                 case MCP_IDLE:
                 {
                     if (byte == SLIP_END) {
-                        Serial.write('S'); // Debug: frame start
+                        MCP_STREAM.write('S'); // Debug: frame start
                         state = MCP_RECEIVING;
                         frame_pos = 0;
                     }
@@ -97,24 +225,24 @@ public:
                 case MCP_RECEIVING:
                 {
                     if (byte == SLIP_END) {
-                        Serial.write('E'); // Debug: frame end
+                        MCP_STREAM.write('E'); // Debug: frame end
                         // End of frame - process if we have data
                         if (frame_pos > 1) { // At least 1 byte data + 1 byte CRC
-                            Serial.write('P'); // Debug: processing frame
+                            MCP_STREAM.write('P'); // Debug: processing frame
                             process_frame();
                         }
                         reset_frame();
                     } else if (byte == SLIP_ESC) {
-                        Serial.write('\\'); // Debug: escape character
+                        MCP_STREAM.write('\\'); // Debug: escape character
                         state = MCP_ESCAPED;
                     } else {
                         // Regular data byte
                         if (frame_pos < MAX_FRAME_SIZE) {
-                            Serial.write('D'); // Debug: data byte added
+                            MCP_STREAM.write('D'); // Debug: data byte added
                             frame_buffer[frame_pos++] = byte;
                         } else {
                             // Frame too large - reset
-                            Serial.write('X'); // Debug: frame too large
+                            MCP_STREAM.write('X'); // Debug: frame too large
                             reset_frame();
                         }
                     }
@@ -124,24 +252,29 @@ public:
                 case MCP_ESCAPED:
                 {
                     if (byte == SLIP_ESC_END) {
-                        Serial.write('e'); // Debug: escaped END
+                        MCP_STREAM.write('e'); // Debug: escaped END
                         if (frame_pos < MAX_FRAME_SIZE) {
                             frame_buffer[frame_pos++] = SLIP_END;
                         } else {
-                            Serial.write('X'); // Debug: frame too large
+                            MCP_STREAM.write('X'); // Debug: frame too large
                             reset_frame();
                         }
                     } else if (byte == SLIP_ESC_ESC) {
-                        Serial.write('s'); // Debug: escaped ESC
+                        MCP_STREAM.write('s'); // Debug: escaped ESC
                         if (frame_pos < MAX_FRAME_SIZE) {
                             frame_buffer[frame_pos++] = SLIP_ESC;
                         } else {
-                            Serial.write('X'); // Debug: frame too large
+                            MCP_STREAM.write('X'); // Debug: frame too large
                             reset_frame();
                         }
+                    } else if (byte == SLIP_CLEAR) {
+                        // The adapter sends ESC CLEAR before commands; wait
+                        // for the END that starts the next frame
+                        reset_frame();
+                        break;
                     } else {
                         // Invalid escape sequence - reset frame
-                        Serial.write('!'); // Debug: invalid escape
+                        MCP_STREAM.write('!'); // Debug: invalid escape
                         reset_frame();
                     }
                     state = MCP_RECEIVING;
@@ -152,10 +285,36 @@ public:
     }
     
 private:
+    // [seq] [0xFF] [code] [crc], without the sequence byte if seq is -1
+    void send_error(int seq, uint8_t code) {
+        uint8_t error_response[4];
+        int len = 0;
+        if (seq >= 0) error_response[len++] = seq;
+        error_response[len++] = 0xFF;
+        error_response[len++] = code;
+        error_response[len] = crc8(error_response, len);
+        send_slip_frame(error_response, len + 1);
+    }
+
     void process_frame(); // Implementation moved to after bindings include
 };
 
 // Global MCP handler instance
 extern MCPHandler mcp_handler;
 
+// Call once a motion started by an "ack": "immediate" function has ended.
+inline void mcp_motion_done() { mcp_handler.send_event(MCP_EVENT_MOTION_DONE); }
+
+// Call after cutting the motors on a fault, e.g.
+// mcp_fault(MCP_EVENT_FAULT_STALL).
+inline void mcp_fault(uint8_t code) { mcp_handler.send_event(code); }
+
+// Call at the end of setup(), so an adapter still connected from before a
+// brownout or watchdog reset knows to set the robot up again.
+inline void mcp_started() {
+#ifdef MCP_EVENTS
+    mcp_handler.send_event(MCP_EVENT_RESET);
+#endif
+}
+
 #endif // MCP_HPP
\ No newline at end of file
@@ -14,29 +14,72 @@ inline void MCPHandler::process_frame() {
     uint8_t calculated_crc = crc8(frame_buffer, data_len);
 
     if (received_crc != calculated_crc) {
-        // CRC mismatch - send error response
-        uint8_t error_response[2] = {0xFF, 0x01}; // Error code 1: CRC error
-        uint8_t error_crc = crc8(error_response, 1);
-        error_response[1] = error_crc;
-        send_slip_frame(error_response, 2);
+        // CRC mismatch - send error response, which under MCP_RELIABLE asks
+        // for the command again
+        send_error(-1, MCP_ERROR_CRC);
         return;
     }
 
+    // The handshake is never sequenced
+    if (frame_buffer[0] == MCP_PROTOCOL_VERSION_TAG && data_len == 1) {
+        // Chunked responses, no sequence numbers
+        uint8_t flags = MCP_FLAG_CHUNKED;
+#ifdef MCP_EVENTS
+        flags |= MCP_FLAG_EVENTS;
+#endif
+#ifdef MCP_RELIABLE
+        flags |= MCP_FLAG_RELIABLE;
+        last_seq = -1; // A new session
+#endif
+        uint8_t version_response[5] = {
+            MCP_PROTOCOL_VERSION, flags,
+            (uint8_t)(MAX_FRAME_SIZE & 0xFF), (uint8_t)(MAX_FRAME_SIZE >> 8)
+        };
+        version_response[4] = crc8(version_response, 4);
+        send_slip_frame(version_response, 5);
+        return;
+    }
+
+    int seq = -1;
+    const uint8_t* command = frame_buffer;
+#ifdef MCP_RELIABLE
+    seq = frame_buffer[0];
+    command++;
+    data_len--;
+    uint8_t ack[4] = {0xFF, MCP_ACK, (uint8_t)seq};
+    ack[3] = crc8(ack, 3);
+    send_slip_frame(ack, 4);
+    // A repeat of the last command: its response went astray, so send it
+    // again rather than run the command twice
+    if (seq == last_seq) {
+        bool sent = last_error == 0 &&
+                    send_chunks(seq, response_buffer, last_response_len,
+                                MCPBindings::stream_data, MCPBindings::stream_len);
+        if (!sent) send_error(seq, last_error ? last_error : MCP_ERROR_DISPATCH);
+        return;
+    }
+#endif
+
     // CRC valid - dispatch the command
     int response_len;
-    int result = MCPBindings::dispatch(frame_buffer, data_len, response_buffer, MAX_FRAME_SIZE - 1, &response_len);
-
-    if (result == 0) {
-        // Success - send response with CRC
-        uint8_t response_crc = crc8(response_buffer, response_len);
-        response_buffer[response_len] = response_crc;
-        send_slip_frame(response_buffer, response_len + 1);
-    } else {
+    mcp_pending_error() = 0;
+    int result = MCPBindings::dispatch(command, data_len, response_buffer, MAX_FRAME_SIZE - 1, &response_len);
+
+    // The function called mcp_fail()
+    uint8_t error_code = result == 0 ? mcp_pending_error() : MCP_ERROR_DISPATCH;
+#ifdef MCP_RELIABLE
+    last_seq = seq;
+    last_response_len = response_len;
+    last_error = error_code;
+#endif
+
+    // Success - send the response, then any binary result streamed after it
+    bool sent = error_code == 0 &&
+                send_chunks(seq, response_buffer, response_len,
+                            MCPBindings::stream_data, MCPBindings::stream_len);
+    if (!sent) {
         // Error - send error response
-        uint8_t error_response[2] = {0xFF, 0x02}; // Error code 2: Dispatch error
-        uint8_t error_crc = crc8(error_response, 1);
-        error_response[1] = error_crc;
-        send_slip_frame(error_response, 2);
+        send_error(seq, error_code ? error_code : MCP_ERROR_DISPATCH);
     }
 }
 
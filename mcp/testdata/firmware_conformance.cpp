@@ -0,0 +1,194 @@
+// Host-compiled check of mcp.hpp against the golden frames in
+// protocol-vectors.json, through the header generate_vectors makes of them.
+// Run with `make test-firmware`.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <vector>
+
+// Stands in for Serial: bytes to read in, bytes written out
+struct TestStream {
+    std::vector<uint8_t> in;
+    size_t in_pos = 0;
+    std::vector<uint8_t> out;
+
+    int available() { return (int)(in.size() - in_pos); }
+    int read() { return in[in_pos++]; }
+    size_t write(uint8_t byte) { out.push_back(byte); return 1; }
+};
+static TestStream test_stream;
+
+#define MCP_STREAM test_stream
+#define MCP_EVENTS
+// crc8 and send_slip_frame are private; the vectors check them directly
+#define private public
+#include "../mcp.hpp"
+#undef private
+
+#include "protocol_vectors.h"
+
+// Bindings that record each command and answer with a preset result
+namespace MCPBindings {
+    static const uint8_t* stream_data = nullptr;
+    static uint16_t stream_len = 0;
+
+    static std::vector<uint8_t> last_command;
+    static std::vector<uint8_t> result;
+    static uint8_t fail_code = 0;
+
+    int dispatch(const uint8_t* data, int len, uint8_t* out, int out_max_len, int* out_len) {
+        stream_data = nullptr;
+        stream_len = 0;
+        last_command.assign(data, data + len);
+        if (fail_code) mcp_fail(fail_code);
+        if ((int)result.size() > out_max_len) return -1;
+        memcpy(out, result.data(), result.size());
+        *out_len = (int)result.size();
+        return 0;
+    }
+}
+
+#include "../mcp_process_frame.hpp"
+
+MCPHandler mcp_handler;
+
+static int failures = 0;
+
+static std::vector<uint8_t> bytes(const MCPVectorBytes& v) {
+    return std::vector<uint8_t>(v.data, v.data + v.len);
+}
+
+static void expect(bool ok, const char* what, const char* name) {
+    if (!ok) {
+        printf("FAIL %s: %s\n", what, name);
+        failures++;
+    }
+}
+
+// Frames in what the firmware wrote, skipping the debug characters between
+// them: ESC CLEAR starts over and END ends a frame
+static std::vector<std::vector<uint8_t>> written_frames() {
+    std::vector<std::vector<uint8_t>> frames;
+    std::vector<uint8_t> frame;
+    const std::vector<uint8_t>& out = test_stream.out;
+    for (size_t i = 0; i < out.size(); i++) {
+        if (out[i] == SLIP_ESC && i + 1 < out.size()) {
+            uint8_t next = out[++i];
+            if (next == SLIP_CLEAR) frame.clear();
+            else frame.push_back(next == SLIP_ESC_END ? SLIP_END : next == SLIP_ESC_ESC ? SLIP_ESC : next);
+        } else if (out[i] == SLIP_END) {
+            if (!frame.empty()) frames.push_back(frame);
+            frame.clear();
+        } else {
+            frame.push_back(out[i]);
+        }
+    }
+    return frames;
+}
+
+// Feed a frame to process_serial as the adapter sends it
+static std::vector<std::vector<uint8_t>> send(const std::vector<uint8_t>& frame) {
+    test_stream.in.clear();
+    test_stream.in_pos = 0;
+    test_stream.out.clear();
+    std::vector<uint8_t>& in = test_stream.in;
+    in.push_back(SLIP_ESC);
+    in.push_back(SLIP_CLEAR);
+    in.push_back(SLIP_END);
+    for (uint8_t byte : frame) {
+        if (byte == SLIP_END) { in.push_back(SLIP_ESC); in.push_back(SLIP_ESC_END); }
+        else if (byte == SLIP_ESC) { in.push_back(SLIP_ESC); in.push_back(SLIP_ESC_ESC); }
+        else in.push_back(byte);
+    }
+    in.push_back(SLIP_END);
+    mcp_handler.process_serial();
+    return written_frames();
+}
+
+static std::vector<uint8_t> with_crc(std::vector<uint8_t> frame) {
+    frame.push_back(mcp_handler.crc8(frame.data(), (int)frame.size()));
+    return frame;
+}
+
+int main() {
+    for (int i = 0; i < mcp_crc8_vectors_count; i++) {
+        const MCPCrc8Vector& v = mcp_crc8_vectors[i];
+        expect(mcp_handler.crc8((const uint8_t*)v.data.data, v.data.len) == v.crc, "crc8", v.data.data);
+    }
+
+    for (int i = 0; i < mcp_slip_vectors_count; i++) {
+        const MCPSlipVector& v = mcp_slip_vectors[i];
+        test_stream.out.clear();
+        mcp_handler.send_slip_frame((const uint8_t*)v.frame.data, v.frame.len);
+        std::vector<uint8_t> wire = {SLIP_ESC, SLIP_CLEAR};
+        std::vector<uint8_t> expected = bytes(v.wire);
+        wire.insert(wire.end(), expected.begin(), expected.end());
+        expect(test_stream.out == wire, "slip encoding", v.name);
+    }
+
+    for (int i = 0; i < mcp_handshake_vectors_count; i++) {
+        const MCPResponseVector& v = mcp_handshake_vectors[i];
+        std::vector<std::vector<uint8_t>> frames = send(with_crc({MCP_PROTOCOL_VERSION_TAG}));
+        expect(frames.size() == 1 && frames[0] == bytes(v.frame), "handshake", v.name);
+    }
+
+    // Each command reaches dispatch intact, and its result comes back as
+    // one [0] [1] [payload] [crc] chunk
+    MCPBindings::result = {0x2A, 0x00};
+    for (int i = 0; i < mcp_command_vectors_count; i++) {
+        const MCPCommandVector& v = mcp_command_vectors[i];
+        if (v.tag == MCP_PROTOCOL_VERSION_TAG) continue; // Answered as the handshake
+        MCPBindings::last_command.clear();
+        std::vector<std::vector<uint8_t>> frames = send(bytes(v.frame));
+        std::vector<uint8_t> command = {v.tag};
+        std::vector<uint8_t> args = bytes(v.args);
+        command.insert(command.end(), args.begin(), args.end());
+        expect(MCPBindings::last_command == command, "command dispatch", v.name);
+        expect(frames.size() == 1 && frames[0] == with_crc({0, 1, 0x2A, 0x00}), "command reply", v.name);
+    }
+
+    for (int i = 0; i < mcp_response_vectors_count; i++) {
+        const MCPResponseVector& v = mcp_response_vectors[i];
+        std::vector<uint8_t> frame = bytes(v.frame);
+        MCPBindings::result.assign(frame.begin(), frame.end() - 1);
+        std::vector<uint8_t> chunk = {0, 1};
+        chunk.insert(chunk.end(), MCPBindings::result.begin(), MCPBindings::result.end());
+        std::vector<std::vector<uint8_t>> frames = send(with_crc({5}));
+        expect(frames.size() == 1 && frames[0] == with_crc(chunk), "response", v.name);
+    }
+
+    for (int i = 0; i < mcp_bad_command_vectors_count; i++) {
+        const MCPBadCommandVector& v = mcp_bad_command_vectors[i];
+        MCPBindings::last_command.clear();
+        std::vector<std::vector<uint8_t>> frames = send(bytes(v.frame));
+        expect(MCPBindings::last_command.empty(), "bad command not dispatched", v.name);
+        expect(frames.size() == 1 && frames[0] == with_crc({0xFF, v.error}), "bad command error", v.name);
+    }
+
+    // Every error code goes out as the listed frame, CRC errors by way of the
+    // bad commands above and the rest through mcp_fail
+    for (int i = 0; i < mcp_error_vectors_count; i++) {
+        const MCPErrorVector& v = mcp_error_vectors[i];
+        if (v.code == MCP_ERROR_CRC) continue;
+        MCPBindings::fail_code = v.code;
+        std::vector<std::vector<uint8_t>> frames = send(with_crc({5}));
+        MCPBindings::fail_code = 0;
+        expect(frames.size() == 1 && frames[0] == bytes(v.frame), "error", v.name);
+    }
+
+    for (int i = 0; i < mcp_event_vectors_count; i++) {
+        const MCPEventVector& v = mcp_event_vectors[i];
+        test_stream.out.clear();
+        mcp_handler.send_event(v.frame.data[2]);
+        std::vector<std::vector<uint8_t>> frames = written_frames();
+        expect(frames.size() == 1 && frames[0] == bytes(v.frame), "event", v.name);
+    }
+
+    if (failures) {
+        printf("%d firmware conformance checks failed\n", failures);
+        return 1;
+    }
+    printf("Firmware matches the protocol vectors\n");
+    return 0;
+}
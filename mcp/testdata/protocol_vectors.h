@@ -0,0 +1,85 @@
+// Generated by generate_vectors from testdata/protocol-vectors.json; do not edit.
+#ifndef MCP_PROTOCOL_VECTORS_H
+#define MCP_PROTOCOL_VECTORS_H
+
+#include <stdint.h>
+
+// Bytes with their length, as they may contain NUL
+struct MCPVectorBytes {
+    const char* data;
+    uint16_t len;
+};
+
+struct MCPCrc8Vector { MCPVectorBytes data; uint8_t crc; };
+struct MCPSlipVector { const char* name; MCPVectorBytes frame; MCPVectorBytes wire; };
+struct MCPCommandVector { const char* name; uint8_t tag; MCPVectorBytes args; MCPVectorBytes frame; };
+struct MCPBadCommandVector { const char* name; MCPVectorBytes frame; uint8_t error; };
+struct MCPResponseVector { const char* name; MCPVectorBytes frame; };
+struct MCPErrorVector { const char* name; uint8_t code; MCPVectorBytes frame; };
+struct MCPEventVector { const char* name; MCPVectorBytes frame; };
+
+#define MCP_VECTORS_PROTOCOL_VERSION 3
+
+static const MCPCrc8Vector mcp_crc8_vectors[] = {
+    {{"", 0}, 0x00},
+    {{"\x01", 1}, 0x07},
+    {{"\x31\x32\x33\x34\x35\x36\x37\x38\x39", 9}, 0xf4},
+    {{"\xff\xfe\x01", 3}, 0xee},
+};
+static const int mcp_crc8_vectors_count = sizeof(mcp_crc8_vectors) / sizeof(mcp_crc8_vectors[0]);
+
+static const MCPSlipVector mcp_slip_vectors[] = {
+    {"plain frame", {"\x01\x02\x03", 3}, {"\xc0\x01\x02\x03\xc0", 5}},
+    {"escaped END", {"\x01\xc0\x03", 3}, {"\xc0\x01\xdb\xdc\x03\xc0", 6}},
+    {"escaped ESC", {"\xdb", 1}, {"\xc0\xdb\xdd\xc0", 4}},
+    {"END and ESC together", {"\xc0\xdb\xdc\xde", 4}, {"\xc0\xdb\xdc\xdb\xdd\xdc\xde\xc0", 8}},
+};
+static const int mcp_slip_vectors_count = sizeof(mcp_slip_vectors) / sizeof(mcp_slip_vectors[0]);
+
+static const MCPCommandVector mcp_command_vectors[] = {
+    {"deviceId()", 0, {"", 0}, {"\x00\x00", 2}},
+    {"getProtocolVersion()", 254, {"", 0}, {"\xfe\xf4", 2}},
+    {"setServo(90)", 5, {"\x5a\x00", 2}, {"\x05\x5a\x00\x4e", 4}},
+    {"drive(-300, 100000)", 6, {"\xd4\xfe\xa0\x86\x01\x00", 6}, {"\x06\xd4\xfe\xa0\x86\x01\x00\xab", 8}},
+    {"say(\"hi\")", 7, {"\x68\x69\x00", 3}, {"\x07\x68\x69\x00\xbe", 5}},
+    {"bytes needing escapes", 192, {"\xdb\x00", 2}, {"\xc0\xdb\x00\xa0", 4}},
+};
+static const int mcp_command_vectors_count = sizeof(mcp_command_vectors) / sizeof(mcp_command_vectors[0]);
+
+static const MCPBadCommandVector mcp_bad_command_vectors[] = {
+    {"damaged CRC", {"\x05\x5a\x00\xb1", 4}, 1},
+};
+static const int mcp_bad_command_vectors_count = sizeof(mcp_bad_command_vectors) / sizeof(mcp_bad_command_vectors[0]);
+
+static const MCPResponseVector mcp_response_vectors[] = {
+    {"void", {"\x00", 1}},
+    {"i16", {"\x2e\xfb\x97", 3}},
+    {"i32", {"\xa0\x86\x01\x00\x9c", 5}},
+    {"CStr", {"\x6f\x6b\x00\xe0", 4}},
+    {"blob", {"\x03\x00\x01\x02\x03\xee", 6}},
+};
+static const int mcp_response_vectors_count = sizeof(mcp_response_vectors) / sizeof(mcp_response_vectors[0]);
+
+static const MCPErrorVector mcp_error_vectors[] = {
+    {"crc_error", 1, {"\xff\x01\xd0", 3}},
+    {"dispatch_error", 2, {"\xff\x02\xd9", 3}},
+    {"robot_busy", 3, {"\xff\x03\xde", 3}},
+    {"hardware_fault", 4, {"\xff\x04\xcb", 3}},
+};
+static const int mcp_error_vectors_count = sizeof(mcp_error_vectors) / sizeof(mcp_error_vectors[0]);
+
+static const MCPEventVector mcp_event_vectors[] = {
+    {"motion done", {"\xff\xfe\x01\xee", 4}},
+    {"reset", {"\xff\xfe\x02\xe7", 4}},
+    {"stall fault", {"\xff\xfe\x10\x99", 4}},
+    {"overcurrent fault", {"\xff\xfe\x11\x9e", 4}},
+    {"thermal fault", {"\xff\xfe\x12\x97", 4}},
+};
+static const int mcp_event_vectors_count = sizeof(mcp_event_vectors) / sizeof(mcp_event_vectors[0]);
+
+static const MCPResponseVector mcp_handshake_vectors[] = {
+    {"v3 with events and chunks", {"\x03\x06\x00\x01\x40", 5}},
+};
+static const int mcp_handshake_vectors_count = sizeof(mcp_handshake_vectors) / sizeof(mcp_handshake_vectors[0]);
+
+#endif // MCP_PROTOCOL_VECTORS_H
@@ -0,0 +1,119 @@
+//! Runs the simulator and the adapter as child processes, for end-to-end
+//! tests talking MCP to the adapter over HTTP.
+
+use mcp_client::McpClient;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// How long the adapter gets to find and identify the simulated robot.
+const READY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A simulated robot behind an adapter, both stopped on drop.
+pub struct Harness {
+    pub url: String,
+    dir: TempDir,
+    simulator: Child,
+    adapter: Child,
+}
+
+impl Harness {
+    /// Simulate `test-robot.json` and wait until the adapter reports the
+    /// robot ready.
+    pub async fn start() -> Harness {
+        let dir = tempfile::tempdir().unwrap();
+        let manifests = dir.path().join("manifests");
+        std::fs::create_dir(&manifests).unwrap();
+        let manifest = manifests.join("test-robot.json");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test-robot.json"),
+            &manifest,
+        )
+        .unwrap();
+        let line = dir.path().join("tty");
+
+        let simulator = Command::new(env!("CARGO_BIN_EXE_arduino-simulator"))
+            .arg("--line")
+            .arg(&line)
+            .arg("--manifest")
+            .arg(&manifest)
+            .arg("--call-log")
+            .arg(dir.path().join("calls.jsonl"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        wait_for(|| line.symlink_metadata().is_ok(), "simulator PTY").await;
+
+        let port = free_port();
+        let adapter = Command::new(env!("CARGO_BIN_EXE_arduino-mcp-adapter"))
+            .arg("--line")
+            .arg(&line)
+            .arg("--manifest-dir")
+            .arg(&manifests)
+            .arg("--port")
+            .arg(port.to_string())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let harness = Harness {
+            url: format!("http://127.0.0.1:{}", port),
+            dir,
+            simulator,
+            adapter,
+        };
+        let client = harness.client();
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            if let Ok(health) = client.get_json("/health").await {
+                if health["status"] == "ok" {
+                    break;
+                }
+            }
+            assert!(Instant::now() < deadline, "adapter never became ready");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        harness
+    }
+
+    /// A client for the adapter, not yet initialized.
+    pub fn client(&self) -> McpClient {
+        McpClient::new(&self.url)
+    }
+
+    /// The calls the simulator has decoded so far, from its `--call-log`.
+    pub fn calls(&self) -> Vec<Value> {
+        let path: PathBuf = self.dir.path().join("calls.jsonl");
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for child in [&mut self.adapter, &mut self.simulator] {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// A port nothing is listening on right now.
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+async fn wait_for(ready: impl Fn() -> bool, what: &str) {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    while !ready() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
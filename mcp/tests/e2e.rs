@@ -0,0 +1,105 @@
+//! The adapter binary serving a simulated robot, driven over HTTP the way an
+//! MCP client would.
+
+mod common;
+
+use common::Harness;
+use mcp_client::RpcError;
+use serde_json::{json, Value};
+
+/// The error a request was answered with.
+fn rpc_error(result: anyhow::Result<Value>) -> RpcError {
+    result.unwrap_err().downcast().unwrap()
+}
+
+#[tokio::test]
+async fn test_initialize_list_and_call() {
+    let harness = Harness::start().await;
+    let client = harness.client();
+
+    let result = client.initialize("e2e-test").await.unwrap();
+    assert_eq!(result["serverInfo"]["name"], "arduino-mcp-adapter");
+    assert!(client.session().is_some());
+
+    let tools = client.list_tools().await.unwrap();
+    let names: Vec<&str> = tools.iter().filter_map(|t| t["name"].as_str()).collect();
+    assert!(names.contains(&"blinkLED"), "{:?}", names);
+    let blink = tools.iter().find(|t| t["name"] == "blinkLED").unwrap();
+    assert_eq!(blink["inputSchema"]["properties"]["n"]["type"], "integer");
+
+    client
+        .call_tool("blinkLED", &json!({"n": 3}))
+        .await
+        .unwrap();
+    let result = client.call_tool("getStatus", &json!({})).await.unwrap();
+    assert_eq!(result["content"][0]["type"], "text");
+
+    // The simulator saw exactly those calls, after identifying itself
+    let calls: Vec<Value> = harness
+        .calls()
+        .into_iter()
+        .filter(|call| call["function"] != "deviceId")
+        .map(|call| json!([call["function"], call["arguments"]]))
+        .collect();
+    assert_eq!(
+        calls,
+        [json!(["blinkLED", {"n": 3}]), json!(["getStatus", {}])]
+    );
+}
+
+#[tokio::test]
+async fn test_error_paths() {
+    let harness = Harness::start().await;
+    let client = harness.client();
+    client.initialize("e2e-test").await.unwrap();
+
+    let response = client.post("{not json".to_string()).await.unwrap();
+    assert_eq!(response["error"]["code"], -32700);
+
+    let error = rpc_error(client.request("tools/fly", Some(json!({}))).await);
+    assert_eq!(error.code, -32601);
+
+    let error = rpc_error(client.call_tool("launchRocket", &json!({})).await);
+    assert_eq!(error.code, -32602);
+
+    let error = rpc_error(client.call_tool("blinkLED", &json!({"n": "three"})).await);
+    assert_eq!(error.code, -32602);
+    assert_eq!(error.data.unwrap()["pointer"], "/params/arguments/n");
+
+    let error = rpc_error(
+        client
+            .request("tools/call", Some(json!({"arguments": {}})))
+            .await,
+    );
+    assert_eq!(error.message, "/params: missing field `name`");
+
+    // None of them reached the robot
+    assert!(harness
+        .calls()
+        .iter()
+        .all(|call| call["function"] == "deviceId"));
+}
+
+#[tokio::test]
+async fn test_call_subcommand_through_running_adapter() {
+    let harness = Harness::start().await;
+    let call = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_arduino-mcp-adapter"))
+            .args(["call", "--url", &harness.url])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = call(&["--tool", "blinkLED", "--args", r#"{"n": 2}"#]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(harness
+        .calls()
+        .iter()
+        .any(|call| call["function"] == "blinkLED" && call["arguments"] == json!({"n": 2})));
+
+    let output = call(&["--tool", "blinkLED", "--args", r#"{"n": "two"}"#]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("/params/arguments/n"), "{}", stderr);
+}